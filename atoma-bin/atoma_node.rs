@@ -1,22 +1,27 @@
-use std::{path::PathBuf, str::FromStr, sync::Arc};
+use std::{path::PathBuf, str::FromStr, sync::Arc, time::Duration};
 
 use anyhow::{Context, Result};
-use atoma_confidential::AtomaConfidentialCompute;
+use arc_swap::ArcSwap;
+use atoma_confidential::{types::ConfidentialComputeStatus, AtomaConfidentialCompute};
 use atoma_daemon::{telemetry, AtomaDaemonConfig, DaemonState};
 use atoma_p2p::{AtomaP2pNode, AtomaP2pNodeConfig};
-use atoma_service::{config::AtomaServiceConfig, server::AppState};
+use atoma_service::{
+    config::AtomaServiceConfig,
+    drain::Drain,
+    models::{fetch_max_context_lengths, initialize_tokenizers, ModelRegistry},
+    readiness::Readiness,
+    replication::{ConcurrentRequests, Replication, StopSignals},
+    server::AppState,
+};
 use atoma_state::{config::AtomaStateManagerConfig, AtomaState, AtomaStateManager};
 use atoma_sui::{client::Client, config::Config, subscriber::Subscriber};
 use atoma_utils::spawn_with_shutdown;
 use clap::Parser;
-use dashmap::{DashMap, DashSet};
-use futures::future::try_join_all;
-use hf_hub::{api::sync::ApiBuilder, Repo, RepoType};
 use sui_keys::keystore::FileBasedKeystore;
 use sui_sdk::{types::base_types::ObjectID, wallet_context::WalletContext};
-use tokenizers::Tokenizer;
 use tokio::{
     net::TcpListener,
+    signal::unix::{signal, Signal, SignalKind},
     sync::{watch, RwLock},
     try_join,
 };
@@ -35,6 +40,10 @@ struct Args {
     /// Path to the configuration file
     #[arg(short, long)]
     config_path: String,
+
+    /// Rebuilds the state by replaying the event log, and exits (the node must be stopped)
+    #[arg(long)]
+    rebuild_state: bool,
 }
 
 /// Configuration for the Atoma node.
@@ -76,76 +85,42 @@ impl NodeConfig {
     }
 }
 
-/// Initializes tokenizers for multiple models by fetching their configurations from HuggingFace.
-///
-/// This function concurrently fetches tokenizer configurations for multiple models from HuggingFace's
-/// repository and initializes them. Each tokenizer is wrapped in an Arc for safe sharing across threads.
-///
-/// # Arguments
-///
-/// * `models` - A slice of model names/paths on HuggingFace (e.g., ["facebook/opt-125m"])
-/// * `revisions` - A slice of revision/branch names corresponding to each model (e.g., ["main"])
-///
-/// # Returns
-///
-/// Returns a `Result` containing a vector of Arc-wrapped tokenizers on success, or an error if:
-/// - Failed to fetch tokenizer configuration from HuggingFace
-/// - Failed to parse the tokenizer JSON
-/// - Any other network or parsing errors occur
-///
-/// # Examples
-///
-/// ```rust,ignore
-/// use anyhow::Result;
-///
-/// #[tokio::main]
-/// async fn example() -> Result<()> {
-///     let models = vec!["facebook/opt-125m".to_string()];
-///     let revisions = vec!["main".to_string()];
-///
-///     let tokenizers = initialize_tokenizers(&models, &revisions).await?;
-///     Ok(())
-/// }
-/// ```
-#[instrument(level = "info", skip(models, revisions))]
-async fn initialize_tokenizers(
-    models: &[String],
-    revisions: &[String],
-    hf_token: String,
-) -> Result<Vec<Arc<Tokenizer>>> {
-    let api = ApiBuilder::new()
-        .with_progress(true)
-        .with_token(Some(hf_token))
-        .build()?;
-    let fetch_futures: Vec<_> = models
-        .iter()
-        .zip(revisions.iter())
-        .map(|(model, revision)| {
-            let api = api.clone();
-            async move {
-                let repo = api.repo(Repo::with_revision(
-                    model.clone(),
-                    RepoType::Model,
-                    revision.clone(),
-                ));
-
-                let tokenizer_filename = repo.get("tokenizer.json").unwrap_or_else(|e| {
-                    panic!("Failed to get tokenizer.json for model {model}, with error: {e}");
-                });
-
-                Tokenizer::from_file(tokenizer_filename)
-                    .map_err(|e| {
-                        anyhow::anyhow!(format!(
-                            "Failed to parse tokenizer for model {}, with error: {}",
-                            model, e
-                        ))
-                    })
-                    .map(Arc::new)
-            }
-        })
-        .collect();
-
-    try_join_all(fetch_futures).await
+/// Rebuilds the state of the node by replaying the events recorded in its event log.
+async fn rebuild_state(config_path: &str, database_url: &str) -> Result<()> {
+    info!(
+        target = "atoma-node-service",
+        event = "state_rebuild",
+        database_url,
+        "Rebuilding the state from the event log"
+    );
+    let client = Arc::new(RwLock::new(
+        Client::new_from_config(config_path.to_string()).await?,
+    ));
+    // NOTE: No events are received while rebuilding the state, so the senders are dropped.
+    let (_, event_subscriber_receiver) = flume::unbounded();
+    let (_, state_manager_receiver) = flume::unbounded();
+    let (_, p2p_event_receiver) = flume::unbounded();
+    let state_manager = AtomaStateManager::new_from_url(
+        database_url,
+        client,
+        event_subscriber_receiver,
+        state_manager_receiver,
+        p2p_event_receiver,
+    )
+    .await?;
+    let (num_replayed_events, num_failed_events) = state_manager
+        .rebuild_from_event_log()
+        .await
+        .context("Failed to rebuild the state")?;
+    info!(
+        target = "atoma-node-service",
+        event = "state_rebuild",
+        num_replayed_events,
+        num_failed_events,
+        "State rebuilt from the event log"
+    );
+    telemetry::shutdown();
+    Ok(())
 }
 
 #[tokio::main]
@@ -159,9 +134,20 @@ async fn main() -> Result<()> {
     let args = Args::parse();
     let config = NodeConfig::load(&args.config_path);
 
+    if args.rebuild_state {
+        return rebuild_state(&args.config_path, &config.state.database_url).await;
+    }
+
     info!("Starting Atoma node service");
 
+    // NOTE: The node shuts down in phases. The services are stopped first, once their in-flight
+    // requests are drained. Then the Sui subscriber (which persists its cursor), the P2P node and
+    // the confidential compute service are stopped, and the state manager is stopped last, so
+    // that it flushes the events sent by all the other tasks. Any task failing stops the services,
+    // which starts the shutdown.
     let (shutdown_sender, mut shutdown_receiver) = watch::channel(false);
+    let (node_shutdown_sender, node_shutdown_receiver) = watch::channel(false);
+    let (state_manager_shutdown_sender, state_manager_shutdown_receiver) = watch::channel(false);
     let (event_subscriber_sender, event_subscriber_receiver) = flume::unbounded();
     let (state_manager_sender, state_manager_receiver) = flume::unbounded();
     let (p2p_event_sender, p2p_event_receiver) = flume::unbounded();
@@ -193,11 +179,11 @@ async fn main() -> Result<()> {
         event = "p2p_node_spawn",
         "Spawning Atoma's p2p node service"
     );
-    let p2p_node_service_shutdown_receiver = shutdown_receiver.clone();
+    let p2p_node_service_shutdown_receiver = node_shutdown_receiver.clone();
+    let p2p_node = AtomaP2pNode::start(config.p2p, Arc::new(keystore), p2p_event_sender, false)?;
+    let p2p_num_peers = p2p_node.num_peers();
     let p2p_node_service_handle = spawn_with_shutdown(
         async move {
-            let p2p_node =
-                AtomaP2pNode::start(config.p2p, Arc::new(keystore), p2p_event_sender, false)?;
             let pinned_future = Box::pin(p2p_node.run(p2p_node_service_shutdown_receiver));
             pinned_future.await
         },
@@ -212,10 +198,11 @@ async fn main() -> Result<()> {
     );
 
     let client = Arc::new(RwLock::new(
-        Client::new_from_config(args.config_path).await?,
+        Client::new_from_config(args.config_path.clone()).await?,
     ));
-    let state_manager_shutdown_receiver = shutdown_receiver.clone();
     let database_url = config.state.database_url.clone();
+    let dead_letter_config = config.state.dead_letter.clone();
+    let retention_config = config.state.retention.clone();
     let client_clone = client.clone();
     let state_manager_handle = spawn_with_shutdown(
         async move {
@@ -226,7 +213,9 @@ async fn main() -> Result<()> {
                 state_manager_receiver,
                 p2p_event_receiver,
             )
-            .await?;
+            .await?
+            .with_dead_letter_config(dead_letter_config)
+            .with_retention_config(retention_config);
             state_manager.run(state_manager_shutdown_receiver).await
         },
         shutdown_sender.clone(),
@@ -247,6 +236,8 @@ async fn main() -> Result<()> {
 
     let (compute_shared_secret_sender, compute_shared_secret_receiver) =
         tokio::sync::mpsc::unbounded_channel();
+    let (confidential_compute_status_sender, confidential_compute_status_receiver) =
+        watch::channel(ConfidentialComputeStatus::default());
 
     let confidential_compute_service_handle = spawn_with_shutdown(
         AtomaConfidentialCompute::start_confidential_compute_service(
@@ -255,7 +246,8 @@ async fn main() -> Result<()> {
             app_state_decryption_receiver,
             app_state_encryption_receiver,
             compute_shared_secret_receiver,
-            shutdown_receiver.clone(),
+            node_shutdown_receiver.clone(),
+            confidential_compute_status_sender,
         ),
         shutdown_sender.clone(),
     );
@@ -275,8 +267,9 @@ async fn main() -> Result<()> {
         event_subscriber_sender,
         stack_retrieve_receiver,
         subscriber_confidential_compute_sender,
-        shutdown_receiver.clone(),
+        node_shutdown_receiver,
     );
+    let subscriber_status = subscriber.status();
 
     info!(
         target = "atoma-node-service",
@@ -306,23 +299,66 @@ async fn main() -> Result<()> {
 
     let hf_token =
         std::env::var(HF_TOKEN).context(format!("Variable {HF_TOKEN} not set in the .env file"))?;
-    let tokenizers =
-        initialize_tokenizers(&config.service.models, &config.service.revisions, hf_token).await?;
+    let tokenizers = initialize_tokenizers(
+        &config.service.models,
+        &config.service.revisions,
+        hf_token.clone(),
+    )
+    .await?;
+    let max_context_lengths = fetch_max_context_lengths(
+        &config.service.models,
+        &config.service.revisions,
+        hf_token.clone(),
+    )
+    .await?;
+    let model_registry = ModelRegistry::new(
+        config.service.models,
+        tokenizers,
+        config.service.chat_completions_service_urls,
+    )
+    .context("Invalid models configuration")?
+    .with_max_context_lengths(max_context_lengths)
+    .with_context_length_config(config.service.context_length);
 
     let keystore = FileBasedKeystore::new(&config.sui.sui_keystore_path().into())
         .context("Failed to initialize keystore")?;
 
+    let filters = atoma_service::filters::FilterPipeline::from_config(&config.service.filters)
+        .context("Failed to configure request filters")?;
+
+    let atoma_state = AtomaState::new_from_url(&config.state.database_url).await?;
+    let replication = if config.service.replication.enabled {
+        Some(
+            Replication::new(&config.service.replication, atoma_state.clone())
+                .context("Failed to configure replication")?,
+        )
+    } else {
+        None
+    };
+    let (concurrent_requests_per_stack, client_dropped_streamer_connections) =
+        replication.as_ref().map_or_else(
+            || {
+                (
+                    Arc::new(ConcurrentRequests::default()),
+                    Arc::new(StopSignals::default()),
+                )
+            },
+            |replication| {
+                (
+                    replication.concurrent_requests(),
+                    replication.stop_signals(),
+                )
+            },
+        );
     let app_state = AppState {
-        concurrent_requests_per_stack: Arc::new(DashMap::new()),
-        client_dropped_streamer_connections: Arc::new(DashSet::new()),
+        concurrent_requests_per_stack,
+        client_dropped_streamer_connections,
         state_manager_sender,
         stack_retrieve_sender,
         decryption_sender: app_state_decryption_sender,
         encryption_sender: app_state_encryption_sender,
         compute_shared_secret_sender,
-        tokenizers: Arc::new(tokenizers),
-        models: Arc::new(config.service.models),
-        chat_completions_service_urls: config.service.chat_completions_service_urls,
+        model_registry: Arc::new(ArcSwap::from_pointee(model_registry)),
         embeddings_service_url: config
             .service
             .embeddings_service_url
@@ -333,10 +369,26 @@ async fn main() -> Result<()> {
             .context("Image generations service URL not configured")?,
         keystore: Arc::new(keystore),
         address_index,
+        filters: Arc::new(filters),
+        resumable_streams: Arc::new(atoma_service::resumable_streams::ResumableStreams::new(
+            config.service.resumable_streams,
+        )),
+        image_generations: Arc::new(config.service.image_generations),
+        drain: Drain::default(),
+        readiness: Arc::new(
+            Readiness::new(config.service.readiness)
+                .with_database(atoma_state.clone())
+                .with_sui_client(client.clone())
+                .with_subscriber_status(subscriber_status)
+                .with_confidential_compute_status(confidential_compute_status_receiver)
+                .with_p2p_num_peers(p2p_num_peers),
+        ),
     };
+    let drain = app_state.drain.clone();
+    let drain_timeout = Duration::from_millis(config.service.drain.timeout_ms);
 
     let daemon_app_state = DaemonState {
-        atoma_state: AtomaState::new_from_url(&config.state.database_url).await?,
+        atoma_state,
         client,
         node_badges: config
             .daemon
@@ -357,11 +409,87 @@ async fn main() -> Result<()> {
         target = "atoma-node-service",
         event = "atoma_node_service_spawn",
         bind_address = config.service.service_bind_address,
+        tls = config.service.tls.is_some(),
         "Starting Atoma node service"
     );
 
+    atoma_service::batch_processor::restore_in_progress_batches(&app_state)
+        .await
+        .context("Failed to restore in-progress batches")?;
+
+    info!(
+        target = "atoma-node-service",
+        event = "atoma_batch_processor_spawn",
+        "Starting Atoma batch processor"
+    );
+    let batch_processor_handle = spawn_with_shutdown(
+        atoma_service::batch_processor::run_batch_processor(
+            app_state.clone(),
+            config.service.batch_processor,
+            shutdown_receiver.clone(),
+        ),
+        shutdown_sender.clone(),
+    );
+
+    info!(
+        target = "atoma-node-service",
+        event = "atoma_model_sync_spawn",
+        "Starting Atoma model synchronizer"
+    );
+    #[allow(clippy::cast_possible_wrap)]
+    let node_small_ids = config
+        .sui
+        .node_small_ids()
+        .map(|ids| ids.into_iter().map(|id| id as i64).collect());
+    let model_sync_handle = tokio::task::spawn(atoma_service::model_sync::run_model_sync(
+        app_state.clone(),
+        config.service.model_sync,
+        hf_token.clone(),
+        node_small_ids,
+        shutdown_receiver.clone(),
+    ));
+
+    info!(
+        target = "atoma-node-service",
+        event = "atoma_node_reload_handler_spawn",
+        "Reloading models configuration on SIGHUP"
+    );
+    let hangup_signal =
+        signal(SignalKind::hangup()).context("Failed to register SIGHUP handler")?;
+    let reload_handle = tokio::task::spawn(handle_reload_signals(
+        args.config_path,
+        hf_token,
+        app_state.model_registry.clone(),
+        hangup_signal,
+        shutdown_receiver.clone(),
+    ));
+
+    let replication_shutdown_receiver = shutdown_receiver.clone();
+    let replication_handle = spawn_with_shutdown(
+        async move {
+            match replication {
+                Some(replication) => {
+                    info!(
+                        target = "atoma-node-service",
+                        event = "atoma_replication_spawn",
+                        replica_id = replication.replica_id(),
+                        "Sharing in-flight requests with the other service replicas"
+                    );
+                    replication.run(replication_shutdown_receiver).await
+                }
+                None => Ok(()),
+            }
+        },
+        shutdown_sender.clone(),
+    );
+
     let service_handle = spawn_with_shutdown(
-        atoma_service::server::run_server(app_state, tcp_listener, shutdown_receiver.clone()),
+        atoma_service::server::run_server(
+            app_state,
+            tcp_listener,
+            config.service.tls,
+            shutdown_receiver.clone(),
+        ),
         shutdown_sender.clone(),
     );
 
@@ -386,8 +514,9 @@ async fn main() -> Result<()> {
                 info!(
                     target = "atoma-node-service",
                     event = "atoma-node-stop",
-                    "ctrl-c received, sending shutdown signal"
+                    "ctrl-c received, draining in-flight requests before shutting down"
                 );
+                drain_in_flight_requests(&drain, drain_timeout).await;
                 shutdown_sender
                     .send(true)
                     .context("Failed to send shutdown signal")?;
@@ -400,23 +529,36 @@ async fn main() -> Result<()> {
     });
 
     // Wait for shutdown signal and handle cleanup
-    let (
-        subscriber_result,
-        state_manager_result,
-        server_result,
-        daemon_result,
-        p2p_node_service_result,
-        confidential_compute_service_result,
-        _,
-    ) = try_join!(
-        subscriber_handle,
-        state_manager_handle,
+    let (server_result, daemon_result, batch_processor_result, replication_result, (), (), _) = try_join!(
         service_handle,
         daemon_handle,
-        p2p_node_service_handle,
-        confidential_compute_service_handle,
+        batch_processor_handle,
+        replication_handle,
+        model_sync_handle,
+        reload_handle,
         ctrl_c
     )?;
+
+    info!(
+        target = "atoma-node-service",
+        event = "atoma_node_services_stopped",
+        "Services stopped, shutting down the Sui subscriber, P2P node and confidential compute service"
+    );
+    node_shutdown_sender.send_replace(true);
+    let (subscriber_result, p2p_node_service_result, confidential_compute_service_result) = try_join!(
+        subscriber_handle,
+        p2p_node_service_handle,
+        confidential_compute_service_handle
+    )?;
+
+    info!(
+        target = "atoma-node-service",
+        event = "atoma_node_state_manager_flush",
+        "Flushing pending state manager events"
+    );
+    state_manager_shutdown_sender.send_replace(true);
+    let state_manager_result = state_manager_handle.await?;
+
     handle_tasks_results(
         subscriber_result,
         state_manager_result,
@@ -424,6 +566,8 @@ async fn main() -> Result<()> {
         daemon_result,
         p2p_node_service_result,
         confidential_compute_service_result,
+        batch_processor_result,
+        replication_result,
     )?;
 
     info!(
@@ -437,6 +581,118 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Drains the Atoma service: new requests are rejected from now on (and the health endpoint
+/// reports the service as unavailable), and the in-flight requests are waited for, for at
+/// most `timeout`.
+async fn drain_in_flight_requests(drain: &Drain, timeout: Duration) {
+    drain.start();
+    info!(
+        target = "atoma-node-service",
+        event = "atoma_node_drain",
+        in_flight_requests = drain.in_flight_requests(),
+        timeout_ms = timeout.as_millis(),
+        "Draining in-flight requests"
+    );
+    if drain.wait_idle(timeout).await {
+        info!(
+            target = "atoma-node-service",
+            event = "atoma_node_drain",
+            "All in-flight requests finished"
+        );
+    } else {
+        warn!(
+            target = "atoma-node-service",
+            event = "atoma_node_drain",
+            in_flight_requests = drain.in_flight_requests(),
+            "Drain deadline elapsed, shutting down with requests still in flight"
+        );
+    }
+}
+
+/// Reloads the models served by the node whenever a SIGHUP signal is received, until the
+/// node shuts down.
+///
+/// On each signal, the service configuration is read again, the tokenizers of the configured
+/// models are fetched and the resulting model registry is validated, before atomically
+/// replacing the current one. Requests in flight keep using the registry they started with.
+/// If any of these steps fails, the current registry is kept.
+///
+/// The changes made at runtime by the model synchronizer (models onboarded from, or disabled
+/// following, the node's on-chain subscriptions) are carried over to the reloaded registry.
+///
+/// NOTE: Only the models, their tokenizers and the chat completions service URLs are
+/// reloaded, other settings (e.g. bind addresses) still require a restart.
+async fn handle_reload_signals(
+    config_path: String,
+    hf_token: String,
+    model_registry: Arc<ArcSwap<ModelRegistry>>,
+    mut hangup_signal: Signal,
+    mut shutdown_receiver: watch::Receiver<bool>,
+) {
+    loop {
+        tokio::select! {
+            received = hangup_signal.recv() => {
+                if received.is_none() {
+                    break;
+                }
+                info!(
+                    target = "atoma-node-service",
+                    event = "atoma_node_reload",
+                    "SIGHUP received, reloading models configuration"
+                );
+                match load_model_registry(&config_path, &hf_token).await {
+                    Ok(new_model_registry) => {
+                        model_registry.rcu(|current| {
+                            new_model_registry.with_runtime_changes_from(current)
+                        });
+                        info!(
+                            target = "atoma-node-service",
+                            event = "atoma_node_reload",
+                            models = ?model_registry.load().models(),
+                            "Models configuration reloaded"
+                        );
+                    }
+                    Err(e) => {
+                        error!(
+                            target = "atoma-node-service",
+                            event = "atoma_node_reload",
+                            error = ?e,
+                            "Failed to reload models configuration, keeping the current one"
+                        );
+                    }
+                }
+            }
+            _ = shutdown_receiver.changed() => {
+                break;
+            }
+        }
+    }
+}
+
+/// Loads the models configuration from the service configuration file, fetches the
+/// tokenizers of the configured models, and validates the resulting model registry.
+///
+/// # Errors
+///
+/// Returns an error if the configuration file cannot be read, if a tokenizer cannot be
+/// fetched, or if the models configuration is invalid.
+async fn load_model_registry(config_path: &str, hf_token: &str) -> Result<ModelRegistry> {
+    let config = AtomaServiceConfig::try_from_file_path(config_path)
+        .context("Failed to read service configuration")?;
+    let tokenizers =
+        initialize_tokenizers(&config.models, &config.revisions, hf_token.to_string()).await?;
+    let max_context_lengths =
+        fetch_max_context_lengths(&config.models, &config.revisions, hf_token.to_string()).await?;
+    Ok(ModelRegistry::new(
+        config.models,
+        tokenizers,
+        config.chat_completions_service_urls,
+    )
+    .context("Invalid models configuration")?
+    .with_max_context_lengths(max_context_lengths)
+    .with_context_length_config(config.context_length))
+}
+
 /// Handles the results of various tasks (subscriber, state manager, and server).
 ///
 /// This function checks the results of the subscriber, state manager, and server tasks.
@@ -457,6 +713,7 @@ async fn main() -> Result<()> {
     level = "info",
     skip(subscriber_result, state_manager_result, server_result)
 )]
+#[allow(clippy::too_many_arguments)]
 fn handle_tasks_results(
     subscriber_result: Result<()>,
     state_manager_result: Result<()>,
@@ -464,6 +721,8 @@ fn handle_tasks_results(
     daemon_result: Result<()>,
     p2p_node_service_result: Result<()>,
     confidential_compute_service_result: Result<()>,
+    batch_processor_result: Result<()>,
+    replication_result: Result<()>,
 ) -> Result<()> {
     let result_handler = |result: Result<()>, message: &str| {
         if let Err(e) = result {
@@ -489,5 +748,10 @@ fn handle_tasks_results(
         confidential_compute_service_result,
         "Confidential compute service terminated abruptly",
     )?;
+    result_handler(
+        batch_processor_result,
+        "Batch processor terminated abruptly",
+    )?;
+    result_handler(replication_result, "Replication terminated abruptly")?;
     Ok(())
 }
@@ -0,0 +1,309 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use arc_swap::ArcSwap;
+use atoma_daemon::telemetry;
+use atoma_service::{
+    config::AtomaServiceConfig,
+    drain::Drain,
+    models::{fetch_max_context_lengths, initialize_tokenizers, ModelRegistry},
+    readiness::Readiness,
+    replication::Replication,
+    server::AppState,
+};
+use atoma_state::{config::AtomaStateManagerConfig, AtomaState, AtomaStateManager};
+use atoma_sui::{client::Client, config::Config, subscriber::run_stack_retriever};
+use atoma_utils::spawn_with_shutdown;
+use clap::Parser;
+use sui_keys::keystore::FileBasedKeystore;
+use sui_sdk::wallet_context::WalletContext;
+use tokio::{
+    net::TcpListener,
+    sync::{watch, RwLock},
+    try_join,
+};
+use tracing::{error, info, warn};
+
+/// The name of the environment variable for the Hugging Face token
+const HF_TOKEN: &str = "HF_TOKEN";
+
+/// Command line arguments for an Atoma service replica
+#[derive(Parser)]
+struct Args {
+    /// Index of the address to use from the keystore
+    #[arg(short, long)]
+    address_index: Option<usize>,
+
+    /// Path to the configuration file
+    #[arg(short, long)]
+    config_path: String,
+}
+
+/// Runs an Atoma service replica, serving requests next to the Atoma node (and other
+/// replicas) that share the same database.
+///
+/// A replica only runs the Atoma service, along with a state manager for the events of the
+/// requests it serves, and the retrieval of the stacks bought for these requests. The Sui
+/// subscriber, the P2P node, the confidential compute service and the batch processor only run
+/// on the Atoma node, so confidential requests must be routed to the node, and the batches
+/// accepted by a replica are processed by the node.
+#[tokio::main]
+#[allow(clippy::too_many_lines)]
+#[allow(clippy::redundant_pub_crate)]
+async fn main() -> Result<()> {
+    let _log_guards = telemetry::setup_logging().context("Failed to setup logging")?;
+
+    dotenvy::dotenv().ok();
+
+    let args = Args::parse();
+    let sui_config = Config::from_file_path(&args.config_path);
+    let service_config = AtomaServiceConfig::from_file_path(&args.config_path);
+    let state_config = AtomaStateManagerConfig::from_file_path(&args.config_path);
+
+    anyhow::ensure!(
+        service_config.replication.enabled,
+        "Replication must be enabled to run a service replica"
+    );
+
+    let (shutdown_sender, mut shutdown_receiver) = watch::channel(false);
+    let (stack_retriever_shutdown_sender, stack_retriever_shutdown_receiver) =
+        watch::channel(false);
+    let (state_manager_shutdown_sender, state_manager_shutdown_receiver) = watch::channel(false);
+    let (event_subscriber_sender, event_subscriber_receiver) = flume::unbounded();
+    let (state_manager_sender, state_manager_receiver) = flume::unbounded();
+    // NOTE: Replicas do not run a P2P node, the sender is kept alive so that the state manager
+    // keeps running.
+    let (_p2p_event_sender, p2p_event_receiver) = flume::unbounded();
+
+    let mut wallet_ctx = WalletContext::new(
+        &PathBuf::from(sui_config.sui_config_path()),
+        sui_config.request_timeout(),
+        sui_config.max_concurrent_requests(),
+    )?;
+    let address = wallet_ctx.active_address()?;
+    let address_index = args.address_index.unwrap_or_else(|| {
+        wallet_ctx
+            .get_addresses()
+            .iter()
+            .position(|a| a == &address)
+            .unwrap()
+    });
+    let keystore = FileBasedKeystore::new(&sui_config.sui_keystore_path().into())
+        .context("Failed to initialize keystore")?;
+
+    info!(
+        target = "atoma-service-replica",
+        event = "state_manager_service_spawn",
+        database_url = state_config.database_url,
+        "Spawning state manager service"
+    );
+    let client = Arc::new(RwLock::new(
+        Client::new_from_config(args.config_path.clone()).await?,
+    ));
+    let database_url = state_config.database_url.clone();
+    let dead_letter_config = state_config.dead_letter.clone();
+    let retention_config = state_config.retention.clone();
+    let client_clone = client.clone();
+    let state_manager_handle = spawn_with_shutdown(
+        async move {
+            let state_manager = AtomaStateManager::new_from_url(
+                &database_url,
+                client_clone,
+                event_subscriber_receiver,
+                state_manager_receiver,
+                p2p_event_receiver,
+            )
+            .await?
+            .with_dead_letter_config(dead_letter_config)
+            .with_retention_config(retention_config);
+            state_manager.run(state_manager_shutdown_receiver).await
+        },
+        shutdown_sender.clone(),
+    );
+
+    let (stack_retrieve_sender, stack_retrieve_receiver) = tokio::sync::mpsc::unbounded_channel();
+    let stack_retriever_event_sender = event_subscriber_sender.clone();
+    let stack_retriever_handle = spawn_with_shutdown(
+        async move {
+            run_stack_retriever(
+                &sui_config,
+                stack_retriever_event_sender,
+                stack_retrieve_receiver,
+                stack_retriever_shutdown_receiver,
+            )
+            .await
+        },
+        shutdown_sender.clone(),
+    );
+
+    let hf_token =
+        std::env::var(HF_TOKEN).context(format!("Variable {HF_TOKEN} not set in the .env file"))?;
+    let tokenizers = initialize_tokenizers(
+        &service_config.models,
+        &service_config.revisions,
+        hf_token.clone(),
+    )
+    .await?;
+    let max_context_lengths = fetch_max_context_lengths(
+        &service_config.models,
+        &service_config.revisions,
+        hf_token.clone(),
+    )
+    .await?;
+    let model_registry = ModelRegistry::new(
+        service_config.models,
+        tokenizers,
+        service_config.chat_completions_service_urls,
+    )
+    .context("Invalid models configuration")?
+    .with_max_context_lengths(max_context_lengths)
+    .with_context_length_config(service_config.context_length);
+
+    let filters = atoma_service::filters::FilterPipeline::from_config(&service_config.filters)
+        .context("Failed to configure request filters")?;
+
+    // NOTE: Confidential compute only runs on the Atoma node, so the confidential requests
+    // received by a replica fail, as there is no service to receive them.
+    let (decryption_sender, _) = tokio::sync::mpsc::unbounded_channel();
+    let (encryption_sender, _) = tokio::sync::mpsc::unbounded_channel();
+    let (compute_shared_secret_sender, _) = tokio::sync::mpsc::unbounded_channel();
+
+    let atoma_state = AtomaState::new_from_url(&state_config.database_url).await?;
+    let replication = Replication::new(&service_config.replication, atoma_state.clone())
+        .context("Failed to configure replication")?;
+    let app_state = AppState {
+        concurrent_requests_per_stack: replication.concurrent_requests(),
+        client_dropped_streamer_connections: replication.stop_signals(),
+        state_manager_sender,
+        stack_retrieve_sender,
+        decryption_sender,
+        encryption_sender,
+        compute_shared_secret_sender,
+        model_registry: Arc::new(ArcSwap::from_pointee(model_registry)),
+        embeddings_service_url: service_config
+            .embeddings_service_url
+            .context("Embeddings service URL not configured")?,
+        image_generations_service_url: service_config
+            .image_generations_service_url
+            .context("Image generations service URL not configured")?,
+        keystore: Arc::new(keystore),
+        address_index,
+        filters: Arc::new(filters),
+        resumable_streams: Arc::new(atoma_service::resumable_streams::ResumableStreams::new(
+            service_config.resumable_streams,
+        )),
+        image_generations: Arc::new(service_config.image_generations),
+        drain: Drain::default(),
+        readiness: Arc::new(
+            Readiness::new(service_config.readiness)
+                .with_database(atoma_state)
+                .with_sui_client(client),
+        ),
+    };
+    let drain = app_state.drain.clone();
+    let drain_timeout = Duration::from_millis(service_config.drain.timeout_ms);
+
+    info!(
+        target = "atoma-service-replica",
+        event = "atoma_replication_spawn",
+        replica_id = replication.replica_id(),
+        "Sharing in-flight requests with the other service replicas"
+    );
+    let replication_handle = spawn_with_shutdown(
+        replication.run(shutdown_receiver.clone()),
+        shutdown_sender.clone(),
+    );
+
+    let tcp_listener = TcpListener::bind(&service_config.service_bind_address)
+        .await
+        .context("Failed to bind TCP listener")?;
+    info!(
+        target = "atoma-service-replica",
+        event = "atoma_service_spawn",
+        bind_address = service_config.service_bind_address,
+        tls = service_config.tls.is_some(),
+        "Starting Atoma service replica"
+    );
+    let service_handle = spawn_with_shutdown(
+        atoma_service::server::run_server(
+            app_state,
+            tcp_listener,
+            service_config.tls,
+            shutdown_receiver.clone(),
+        ),
+        shutdown_sender.clone(),
+    );
+
+    let ctrl_c = tokio::task::spawn(async move {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {
+                info!(
+                    target = "atoma-service-replica",
+                    event = "atoma-service-stop",
+                    "ctrl-c received, draining in-flight requests before shutting down"
+                );
+                drain.start();
+                if !drain.wait_idle(drain_timeout).await {
+                    warn!(
+                        target = "atoma-service-replica",
+                        event = "atoma_service_drain",
+                        in_flight_requests = drain.in_flight_requests(),
+                        "Drain deadline elapsed, shutting down with requests still in flight"
+                    );
+                }
+                shutdown_sender
+                    .send(true)
+                    .context("Failed to send shutdown signal")?;
+                Ok::<(), anyhow::Error>(())
+            }
+            _ = shutdown_receiver.changed() => {
+                Ok::<(), anyhow::Error>(())
+            }
+        }
+    });
+
+    let (server_result, replication_result, _) =
+        try_join!(service_handle, replication_handle, ctrl_c)?;
+
+    stack_retriever_shutdown_sender.send_replace(true);
+    let stack_retriever_result = stack_retriever_handle.await?;
+
+    info!(
+        target = "atoma-service-replica",
+        event = "atoma_service_state_manager_flush",
+        "Flushing pending state manager events"
+    );
+    state_manager_shutdown_sender.send_replace(true);
+    let state_manager_result = state_manager_handle.await?;
+    drop(event_subscriber_sender);
+
+    for (result, message) in [
+        (server_result, "Server terminated abruptly"),
+        (replication_result, "Replication terminated abruptly"),
+        (
+            stack_retriever_result,
+            "Stack retriever terminated abruptly",
+        ),
+        (state_manager_result, "State manager terminated abruptly"),
+    ] {
+        if let Err(e) = result {
+            error!(
+                target = "atoma-service-replica",
+                event = "atoma_service_shutdown",
+                error = ?e,
+                "{message}"
+            );
+            return Err(e);
+        }
+    }
+
+    info!(
+        target = "atoma-service-replica",
+        event = "atoma_service_shutdown",
+        "Atoma service replica shut down successfully"
+    );
+
+    telemetry::shutdown();
+
+    Ok(())
+}
@@ -1,4 +1,10 @@
-use atoma_utils::encryption::{decrypt_ciphertext, encrypt_plaintext, Error, NONCE_BYTE_SIZE};
+use atoma_utils::{
+    encryption::{
+        decrypt_ciphertext_with_associated_data, encrypt_plaintext, AeadAlgorithm, Error,
+        SymmetricSecret, NONCE_BYTE_SIZE,
+    },
+    hpke::{self, ENCAPSULATED_KEY_SIZE},
+};
 use thiserror::Error;
 use x25519_dalek::{PublicKey, SharedSecret, StaticSecret};
 
@@ -97,6 +103,7 @@ impl X25519KeyPairManager {
     /// * `ciphertext` - The encrypted data to be decrypted
     /// * `salt` - Salt value used in the encryption process
     /// * `nonce` - Unique nonce (number used once) for the encryption
+    /// * `associated_data` - Data authenticated along the ciphertext (empty if none)
     ///
     /// # Returns
     /// * `Ok(Vec<u8>)` - The decrypted plaintext as a byte vector
@@ -105,7 +112,7 @@ impl X25519KeyPairManager {
     /// Returns a `KeyManagementError` if:
     /// - Decryption fails due to invalid key material
     /// - The ciphertext is malformed
-    /// - The authentication tag is invalid
+    /// - The authentication tag is invalid, including when the associated data does not match
     ///
     /// # Example
     /// ```rust,ignore
@@ -116,7 +123,7 @@ impl X25519KeyPairManager {
     /// let salt = vec![/* salt bytes */];
     /// let nonce = vec![/* nonce bytes */];
     ///
-    /// let plaintext = manager.decrypt_ciphertext(public_key, &ciphertext, &salt, &nonce)?;
+    /// let plaintext = manager.decrypt_ciphertext(public_key, &ciphertext, &salt, &nonce, &[])?;
     /// ```
     pub fn decrypt_ciphertext(
         &self,
@@ -124,10 +131,57 @@ impl X25519KeyPairManager {
         ciphertext: &[u8],
         salt: &[u8],
         nonce: &[u8],
+        associated_data: &[u8],
     ) -> Result<Vec<u8>> {
         let public_key = PublicKey::from(public_key);
         let shared_secret = self.compute_shared_secret(&public_key);
-        Ok(decrypt_ciphertext(&shared_secret, ciphertext, salt, nonce)?)
+        Ok(decrypt_ciphertext_with_associated_data(
+            &shared_secret,
+            ciphertext,
+            salt,
+            nonce,
+            associated_data,
+        )?)
+    }
+
+    /// Opens an HPKE (RFC 9180) base mode envelope sealed to the current public key.
+    ///
+    /// This method:
+    /// 1. Sets up the HPKE recipient context from the sender encapsulated key
+    /// 2. Opens the ciphertext, authenticating the provided associated data
+    /// 3. Exports the secret the response to the request must be encrypted with
+    ///
+    /// # Arguments
+    /// * `encapsulated_key` - The sender HPKE encapsulated key as a 32-byte array
+    /// * `ciphertext` - The sealed data
+    /// * `associated_data` - Data authenticated along the ciphertext
+    /// * `aead` - The HPKE AEAD algorithm the envelope was sealed with
+    ///
+    /// # Returns
+    /// * `Ok((Vec<u8>, SymmetricSecret))` - A tuple containing:
+    ///   - The decrypted plaintext as a byte vector
+    ///   - The response secret exported from the HPKE context
+    ///
+    /// # Errors
+    /// Returns a `KeyManagementError` if:
+    /// - The encapsulated key is invalid
+    /// - The authentication tag is invalid, including when the associated data does not match
+    pub fn open_hpke_envelope(
+        &self,
+        encapsulated_key: [u8; ENCAPSULATED_KEY_SIZE],
+        ciphertext: &[u8],
+        associated_data: &[u8],
+        aead: AeadAlgorithm,
+    ) -> Result<(Vec<u8>, SymmetricSecret)> {
+        let mut context = hpke::setup_base_receiver(
+            &encapsulated_key,
+            &self.secret_key,
+            hpke::REQUEST_INFO,
+            aead,
+        )?;
+        let plaintext = context.open(associated_data, ciphertext)?;
+        let response_secret = hpke::export_response_secret(&context)?;
+        Ok((plaintext, response_secret))
     }
 
     /// Encrypts plaintext using X25519 key exchange and symmetric encryption.
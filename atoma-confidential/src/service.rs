@@ -9,6 +9,7 @@ use crate::{
         ConfidentialComputeDecryptionRequest, ConfidentialComputeDecryptionResponse,
         ConfidentialComputeEncryptionRequest, ConfidentialComputeEncryptionResponse,
         ConfidentialComputeSharedSecretRequest, ConfidentialComputeSharedSecretResponse,
+        ConfidentialComputeStatus,
     },
 };
 use atoma_sui::client::Client;
@@ -21,7 +22,7 @@ use base64::{engine::general_purpose::STANDARD, Engine};
 use remote_attestation::DeviceEvidence;
 use std::sync::Arc;
 use thiserror::Error;
-use tokio::sync::{mpsc::UnboundedReceiver, oneshot, RwLock};
+use tokio::sync::{mpsc::UnboundedReceiver, oneshot, watch, RwLock};
 use tracing::instrument;
 use x25519_dalek::PublicKey;
 
@@ -102,6 +103,9 @@ pub struct AtomaConfidentialCompute {
 
     /// Signal receiver for coordinating graceful shutdown of the service
     shutdown_signal: tokio::sync::watch::Receiver<bool>,
+
+    /// Sender publishing the status of the service, updated on each key rotation
+    status_sender: watch::Sender<ConfidentialComputeStatus>,
 }
 
 impl AtomaConfidentialCompute {
@@ -181,9 +185,34 @@ impl AtomaConfidentialCompute {
             service_encryption_receiver,
             service_shared_secret_receiver,
             shutdown_signal,
+            status_sender: watch::Sender::new(ConfidentialComputeStatus {
+                is_cc_supported,
+                key_rotation_counter,
+            }),
         })
     }
 
+    /// Sets the sender the status of the service is published on, and publishes the current
+    /// status.
+    #[must_use]
+    pub fn with_status_sender(
+        mut self,
+        status_sender: watch::Sender<ConfidentialComputeStatus>,
+    ) -> Self {
+        status_sender.send_replace(self.status());
+        self.status_sender = status_sender;
+        self
+    }
+
+    /// Returns the current status of the service
+    #[must_use]
+    pub const fn status(&self) -> ConfidentialComputeStatus {
+        ConfidentialComputeStatus {
+            is_cc_supported: self.is_cc_supported,
+            key_rotation_counter: self.key_rotation_counter,
+        }
+    }
+
     /// Initializes and starts the confidential compute service.
     ///
     /// This method performs the following steps:
@@ -198,6 +227,7 @@ impl AtomaConfidentialCompute {
     /// * `service_encryption_receiver` - Channel receiver for encryption requests
     /// * `service_shared_secret_receiver` - Channel receiver for shared secret computation requests
     /// * `shutdown_signal` - Watch channel receiver for coordinating service shutdown
+    /// * `status_sender` - Watch channel sender on which the status of the service is published
     ///
     /// # Returns
     /// * `Ok(())` if the service starts and runs successfully
@@ -221,6 +251,7 @@ impl AtomaConfidentialCompute {
         service_encryption_receiver: UnboundedReceiver<ServiceEncryptionRequest>,
         service_shared_secret_receiver: UnboundedReceiver<ServiceSharedSecretRequest>,
         shutdown_signal: tokio::sync::watch::Receiver<bool>,
+        status_sender: watch::Sender<ConfidentialComputeStatus>,
     ) -> Result<()> {
         // NOTE: Submit the first node key rotation attestation, because the node is starting up afresh
         let last_key_rotation_data = {
@@ -236,7 +267,8 @@ impl AtomaConfidentialCompute {
                 service_encryption_receiver,
                 service_shared_secret_receiver,
                 shutdown_signal,
-            )?;
+            )?
+            .with_status_sender(status_sender);
             if service.is_cc_supported {
                 tracing::info!(
                     target = "atoma-confidential-compute-service",
@@ -257,6 +289,7 @@ impl AtomaConfidentialCompute {
                 service_shared_secret_receiver,
                 shutdown_signal,
             )?
+            .with_status_sender(status_sender)
         };
 
         service.run().await?;
@@ -468,12 +501,12 @@ impl AtomaConfidentialCompute {
     ///
     /// This method performs the following steps:
     /// 1. Validates that the provided node public key matches the service's current public key
-    /// 2. If valid, attempts to decrypt the ciphertext using the key manager
+    /// 2. If valid, attempts to decrypt the ciphertext (or to open the HPKE envelope) using the key manager
     /// 3. Sends the decryption result back through the provided sender channel
     ///
     /// # Arguments
     /// * `decryption_request` - A tuple containing:
-    ///   - The decryption request with ciphertext, nonce, salt, and public keys
+    ///   - The decryption request with ciphertext, nonce, salt, public keys and associated data
     ///   - A oneshot sender channel for returning the decryption response
     ///
     /// # Returns
@@ -502,19 +535,35 @@ impl AtomaConfidentialCompute {
             salt,
             client_dh_public_key,
             node_dh_public_key,
+            associated_data,
+            hpke_aead,
         } = decryption_request;
         let result = if PublicKey::from(node_dh_public_key) == self.key_manager.get_public_key() {
-            self.key_manager
-                .decrypt_ciphertext(client_dh_public_key, &ciphertext, &salt, &nonce)
-                .map_err(|e| {
-                    tracing::error!(
-                        target = "atoma-confidential-compute-service",
-                        event = "confidential_compute_service_decryption_error",
-                        "Failed to decrypt cyphertext, with error: {:?}",
-                        e
-                    );
-                    anyhow::anyhow!(e)
-                })
+            match hpke_aead {
+                Some(aead) => self
+                    .key_manager
+                    .open_hpke_envelope(client_dh_public_key, &ciphertext, &associated_data, aead)
+                    .map(|(plaintext, response_secret)| (plaintext, Some(response_secret))),
+                None => self
+                    .key_manager
+                    .decrypt_ciphertext(
+                        client_dh_public_key,
+                        &ciphertext,
+                        &salt,
+                        &nonce,
+                        &associated_data,
+                    )
+                    .map(|plaintext| (plaintext, None)),
+            }
+            .map_err(|e| {
+                tracing::error!(
+                    target = "atoma-confidential-compute-service",
+                    event = "confidential_compute_service_decryption_error",
+                    "Failed to decrypt cyphertext, with error: {:?}",
+                    e
+                );
+                anyhow::anyhow!(e)
+            })
         } else {
             tracing::error!(
                 target = "atoma-confidential-compute-service",
@@ -526,7 +575,12 @@ impl AtomaConfidentialCompute {
             ))
         };
         let message = result
-            .map(|plaintext| ConfidentialComputeDecryptionResponse { plaintext })
+            .map(
+                |(plaintext, response_secret)| ConfidentialComputeDecryptionResponse {
+                    plaintext,
+                    response_secret,
+                },
+            )
             .map_err(|e| anyhow::anyhow!(e));
         sender
             .send(message)
@@ -686,6 +740,7 @@ impl AtomaConfidentialCompute {
                 // for a previous key rotation counter and not for the current one).
                 if self.key_rotation_counter < event.key_rotation_counter {
                     self.key_rotation_counter = event.key_rotation_counter;
+                    self.status_sender.send_replace(self.status());
                     self.key_manager.rotate_keys();
                     self.submit_nvidia_cc_attestation(event.nonce).await?;
                 }
@@ -1,4 +1,7 @@
-use atoma_utils::constants::{NONCE_SIZE, SALT_SIZE};
+use atoma_utils::{
+    constants::{NONCE_SIZE, SALT_SIZE},
+    encryption::{AeadAlgorithm, SymmetricSecret},
+};
 
 /// Size of a Diffie-Hellman public key in bytes
 pub const DH_PUBLIC_KEY_SIZE: usize = 32;
@@ -21,10 +24,19 @@ pub struct ConfidentialComputeDecryptionRequest {
     pub salt: [u8; SALT_SIZE],
 
     /// Public key component for Diffie-Hellman key exchange from the client
+    /// (the HPKE encapsulated key, for HPKE envelopes)
     pub client_dh_public_key: [u8; DH_PUBLIC_KEY_SIZE],
 
     /// Public key component for Diffie-Hellman key exchange from the node
     pub node_dh_public_key: [u8; DH_PUBLIC_KEY_SIZE],
+
+    /// Data authenticated along the ciphertext (e.g. the cleartext request envelope),
+    /// empty for requests that were encrypted without associated data
+    pub associated_data: Vec<u8>,
+
+    /// The HPKE AEAD algorithm, if the ciphertext is an HPKE base mode envelope,
+    /// in which case `nonce` and `salt` are not used for decryption
+    pub hpke_aead: Option<AeadAlgorithm>,
 }
 
 /// Response containing the decrypted data from a confidential computation request
@@ -35,6 +47,10 @@ pub struct ConfidentialComputeDecryptionRequest {
 pub struct ConfidentialComputeDecryptionResponse {
     /// The decrypted data resulting from the confidential computation
     pub plaintext: Vec<u8>,
+
+    /// The secret exported from the HPKE context of the request, to encrypt the response with
+    /// (only set for HPKE envelopes)
+    pub response_secret: Option<SymmetricSecret>,
 }
 
 /// A request for confidential computation that includes plaintext data and key exchange parameters
@@ -81,3 +97,13 @@ pub struct ConfidentialComputeSharedSecretResponse {
     /// Cryptographic nonce used in the encryption process
     pub nonce: [u8; NONCE_SIZE],
 }
+
+/// Status of the confidential compute service, as published to the Atoma service (e.g. for
+/// its readiness endpoint).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConfidentialComputeStatus {
+    /// Whether confidential computing is supported on the node
+    pub is_cc_supported: bool,
+    /// Current key rotation counter
+    pub key_rotation_counter: u64,
+}
@@ -2,15 +2,20 @@ use axum::Router;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
-use crate::handlers::{attestation_disputes, claimed_stacks, nodes, stacks, subscriptions, tasks};
+use crate::handlers::{
+    archived_stacks, attestation_disputes, claimed_stacks, earnings, nodes, stacks, subscriptions,
+    tasks,
+};
 
 pub fn openapi_routes() -> Router {
     /// OpenAPI documentation for the Atoma daemon API.
     #[derive(OpenApi)]
     #[openapi(
         nest(
+            (path = archived_stacks::ARCHIVED_STACKS_PATH, api = archived_stacks::ArchivedStacksOpenApi, tags = ["Archived stacks"]),
             (path = attestation_disputes::ATTESTATION_DISPUTES_PATH, api = attestation_disputes::AttestationDisputesOpenApi, tags = ["Attestation disputes"]),
             (path = claimed_stacks::CLAIMED_STACKS_PATH, api = claimed_stacks::ClaimedStacksOpenApi, tags = ["Claimed stacks"]),
+            (path = earnings::EARNINGS_PATH, api = earnings::EarningsOpenApi, tags = ["Earnings"]),
             (path = nodes::NODES_PATH, api = nodes::NodesOpenApi, tags = ["Nodes"]),
             (path = stacks::STACKS_PATH, api = stacks::StacksOpenApi, tags = ["Stacks"]),
             (path = subscriptions::SUBSCRIPTIONS_PATH, api = subscriptions::SubscriptionsOpenApi, tags = ["Subscriptions"]),
@@ -19,7 +24,9 @@ pub fn openapi_routes() -> Router {
         tags(
             (name = "Almost filled stacks", description = "Almost filled stacks management"),
             (name = "Claimed stacks", description = "Claimed stacks management"),
+            (name = "Archived stacks", description = "Archived stacks management"),
             (name = "Attestation disputes", description = "Attestation disputes management"),
+            (name = "Earnings", description = "Earnings and usage reports"),
             (name = "Nodes", description = "Nodes management"),
             (name = "Stacks", description = "Stacks management"),
             (name = "Subscriptions", description = "Subscriptions management"),
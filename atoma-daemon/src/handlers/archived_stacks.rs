@@ -0,0 +1,55 @@
+use atoma_state::types::{ArchivedStack, Stack, StackAttestationDispute, StackSettlementTicket};
+use axum::{extract::Path, extract::State, http::StatusCode, routing::get, Json, Router};
+use tracing::error;
+use utoipa::OpenApi;
+
+use crate::DaemonState;
+
+pub const ARCHIVED_STACKS_PATH: &str = "/archived-stacks";
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(archived_stacks_nodes_list),
+    components(schemas(ArchivedStack, Stack, StackSettlementTicket, StackAttestationDispute))
+)]
+pub struct ArchivedStacksOpenApi;
+
+pub fn archived_stacks_router() -> Router<DaemonState> {
+    Router::new().route(
+        &format!("{ARCHIVED_STACKS_PATH}/nodes/{{node_id}}"),
+        get(archived_stacks_nodes_list),
+    )
+}
+
+/// List archived stacks
+///
+/// Lists all the stacks of a specific node, identified by its small ID, which were claimed,
+/// settled and archived once past the retention period, together with their settlement
+/// tickets and attestation disputes.
+#[utoipa::path(
+    get,
+    path = "/nodes/{node_id}",
+    params(
+        ("node_id" = i64, Path, description = "Node small ID")
+    ),
+    responses(
+        (status = OK, description = "List of archived stacks of the node", body = Vec<ArchivedStack>),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+pub async fn archived_stacks_nodes_list(
+    State(daemon_state): State<DaemonState>,
+    Path(node_id): Path<i64>,
+) -> Result<Json<Vec<ArchivedStack>>, StatusCode> {
+    let node_ids = vec![node_id];
+
+    daemon_state
+        .atoma_state
+        .get_archived_stacks(&node_ids)
+        .await
+        .map(Json)
+        .map_err(|_| {
+            error!("Failed to get archived stacks");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
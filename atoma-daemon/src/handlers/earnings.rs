@@ -0,0 +1,304 @@
+use atoma_state::types::EarningsReportRow;
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::Deserialize;
+use tracing::error;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::DaemonState;
+
+pub const EARNINGS_PATH: &str = "/earnings";
+
+/// The header of the CSV earnings reports.
+const CSV_HEADER: &str = "day,task_small_id,model_name,owner_address,num_requests,compute_units_consumed,num_claimed_stacks,compute_units_claimed,revenue,refunds";
+
+/// The format of an earnings report.
+#[derive(Clone, Copy, Debug, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum EarningsReportFormat {
+    /// A JSON array of rows
+    #[default]
+    Json,
+    /// A CSV document, with a header line
+    Csv,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct EarningsQuery {
+    /// First day of the report (UTC, `YYYY-MM-DD`), inclusive
+    from: Option<String>,
+    /// Last day of the report (UTC, `YYYY-MM-DD`), inclusive
+    to: Option<String>,
+    /// Format of the report, `json` by default
+    #[serde(default)]
+    format: EarningsReportFormat,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(earnings_list, earnings_nodes_list),
+    components(schemas(EarningsReportRow, EarningsQuery, EarningsReportFormat))
+)]
+pub struct EarningsOpenApi;
+
+pub fn earnings_router() -> Router<DaemonState> {
+    Router::new()
+        .route(EARNINGS_PATH, get(earnings_list))
+        .route(
+            &format!("{EARNINGS_PATH}/nodes/{{node_id}}"),
+            get(earnings_nodes_list),
+        )
+}
+
+/// Get earnings report
+///
+/// Reports the usage and the earnings of all the nodes run by the daemon, per day, task and
+/// stack owner. The usage is counted on the day the requests were served, while the claimed
+/// compute units, the revenue and the refunds are counted on the day the stacks were claimed.
+#[utoipa::path(
+    get,
+    path = "",
+    params(
+        ("from" = Option<String>, Query, description = "First day of the report (UTC, YYYY-MM-DD), inclusive"),
+        ("to" = Option<String>, Query, description = "Last day of the report (UTC, YYYY-MM-DD), inclusive"),
+        ("format" = Option<EarningsReportFormat>, Query, description = "Format of the report, json (default) or csv")
+    ),
+    responses(
+        (status = OK, description = "Earnings report of the nodes", body = Vec<EarningsReportRow>),
+        (status = BAD_REQUEST, description = "Invalid day"),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+pub async fn earnings_list(
+    State(daemon_state): State<DaemonState>,
+    Query(query): Query<EarningsQuery>,
+) -> Result<Response, StatusCode> {
+    let node_ids = daemon_state
+        .node_badges
+        .iter()
+        .filter_map(|(_, id)| i64::try_from(*id).ok())
+        .collect::<Vec<i64>>();
+
+    earnings_report(&daemon_state, &node_ids, &query).await
+}
+
+/// Get node earnings report
+///
+/// Reports the usage and the earnings of a specific node, identified by its small ID, per day,
+/// task and stack owner.
+#[utoipa::path(
+    get,
+    path = "/nodes/{node_id}",
+    params(
+        ("node_id" = i64, Path, description = "Node small ID"),
+        ("from" = Option<String>, Query, description = "First day of the report (UTC, YYYY-MM-DD), inclusive"),
+        ("to" = Option<String>, Query, description = "Last day of the report (UTC, YYYY-MM-DD), inclusive"),
+        ("format" = Option<EarningsReportFormat>, Query, description = "Format of the report, json (default) or csv")
+    ),
+    responses(
+        (status = OK, description = "Earnings report of the node", body = Vec<EarningsReportRow>),
+        (status = BAD_REQUEST, description = "Invalid day"),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+pub async fn earnings_nodes_list(
+    State(daemon_state): State<DaemonState>,
+    Path(node_id): Path<i64>,
+    Query(query): Query<EarningsQuery>,
+) -> Result<Response, StatusCode> {
+    earnings_report(&daemon_state, &[node_id], &query).await
+}
+
+/// Retrieves the earnings report of the given nodes and renders it in the requested format.
+async fn earnings_report(
+    daemon_state: &DaemonState,
+    node_ids: &[i64],
+    query: &EarningsQuery,
+) -> Result<Response, StatusCode> {
+    for day in [&query.from, &query.to].into_iter().flatten() {
+        if !is_valid_day(day) {
+            error!("Invalid day in earnings report query: {day}");
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    let rows = daemon_state
+        .atoma_state
+        .get_earnings_report(node_ids, query.from.as_deref(), query.to.as_deref())
+        .await
+        .map_err(|_| {
+            error!("Failed to get earnings report");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(match query.format {
+        EarningsReportFormat::Json => Json(rows).into_response(),
+        EarningsReportFormat::Csv => (
+            [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"earnings.csv\"",
+                ),
+            ],
+            to_csv(&rows),
+        )
+            .into_response(),
+    })
+}
+
+/// Checks that `day` is a calendar day formatted as `YYYY-MM-DD`.
+fn is_valid_day(day: &str) -> bool {
+    let parts = day.split('-').collect::<Vec<_>>();
+    let [year, month, day] = parts.as_slice() else {
+        return false;
+    };
+    let is_number = |part: &str, len: usize| {
+        part.len() == len && part.bytes().all(|byte| byte.is_ascii_digit())
+    };
+    if !is_number(year, 4) || !is_number(month, 2) || !is_number(day, 2) {
+        return false;
+    }
+    let (Ok(year), Ok(month), Ok(day)) =
+        (year.parse::<u32>(), month.parse::<u8>(), day.parse::<u8>())
+    else {
+        return false;
+    };
+    let is_leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year => 29,
+        2 => 28,
+        _ => return false,
+    };
+    (1..=days_in_month).contains(&day)
+}
+
+/// Renders the rows of an earnings report as a CSV document.
+fn to_csv(rows: &[EarningsReportRow]) -> String {
+    let mut csv = format!("{CSV_HEADER}\n");
+    for row in rows {
+        let fields = [
+            escape_csv_field(row.day.as_deref().unwrap_or_default()),
+            row.task_small_id.to_string(),
+            escape_csv_field(row.model_name.as_deref().unwrap_or_default()),
+            escape_csv_field(&row.owner_address),
+            row.num_requests.to_string(),
+            row.compute_units_consumed.to_string(),
+            row.num_claimed_stacks.to_string(),
+            row.compute_units_claimed.to_string(),
+            row.revenue.to_string(),
+            row.refunds.to_string(),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Quotes a CSV field if it contains a separator, a quote or a line break.
+fn escape_csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(day: Option<&str>, model_name: Option<&str>, owner_address: &str) -> EarningsReportRow {
+        EarningsReportRow {
+            day: day.map(str::to_string),
+            task_small_id: 1,
+            model_name: model_name.map(str::to_string),
+            owner_address: owner_address.to_string(),
+            num_requests: 2,
+            compute_units_consumed: 300,
+            num_claimed_stacks: 1,
+            compute_units_claimed: 250,
+            revenue: 10,
+            refunds: 5,
+        }
+    }
+
+    #[test]
+    fn test_escape_csv_field() {
+        assert_eq!(
+            escape_csv_field("meta-llama/Llama-3.3-70B"),
+            "meta-llama/Llama-3.3-70B"
+        );
+        assert_eq!(escape_csv_field(""), "");
+        assert_eq!(escape_csv_field("a,b"), "\"a,b\"");
+        assert_eq!(escape_csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(escape_csv_field("line\nbreak"), "\"line\nbreak\"");
+        assert_eq!(escape_csv_field("carriage\rreturn"), "\"carriage\rreturn\"");
+    }
+
+    #[test]
+    fn test_to_csv() {
+        assert_eq!(to_csv(&[]), format!("{CSV_HEADER}\n"));
+
+        let rows = [
+            row(Some("2025-05-01"), Some("model"), "0x1"),
+            row(None, Some("model, \"quoted\"\nname"), "0x2"),
+            row(Some("2025-05-02"), None, "0x3"),
+        ];
+        let csv = to_csv(&rows);
+        assert_eq!(
+            csv,
+            format!(
+                "{CSV_HEADER}\n\
+                 2025-05-01,1,model,0x1,2,300,1,250,10,5\n\
+                 ,1,\"model, \"\"quoted\"\"\nname\",0x2,2,300,1,250,10,5\n\
+                 2025-05-02,1,,0x3,2,300,1,250,10,5\n"
+            )
+        );
+        // Every row has as many fields as the header
+        let num_fields = CSV_HEADER.split(',').count();
+        assert!(csv
+            .lines()
+            .filter(|line| line.starts_with("2025"))
+            .all(|line| line.split(',').count() == num_fields));
+    }
+
+    #[test]
+    fn test_is_valid_day() {
+        for day in [
+            "2025-05-01",
+            "2025-12-31",
+            "2024-02-29",
+            "2000-02-29",
+            "0001-01-01",
+        ] {
+            assert!(is_valid_day(day), "{day} should be valid");
+        }
+        for day in [
+            "",
+            "2025-5-1",
+            "2025-05-1",
+            "25-05-01",
+            "2025/05/01",
+            "2025-05-01T00:00:00",
+            "2025-00-01",
+            "2025-13-01",
+            "2025-05-00",
+            "2025-05-32",
+            "2025-04-31",
+            "2025-02-29",
+            "1900-02-29",
+            "2025-+5-01",
+            "２025-05-01",
+        ] {
+            assert!(!is_valid_day(day), "{day} should be invalid");
+        }
+    }
+}
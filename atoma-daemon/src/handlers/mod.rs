@@ -1,5 +1,7 @@
+pub mod archived_stacks;
 pub mod attestation_disputes;
 pub mod claimed_stacks;
+pub mod earnings;
 pub mod nodes;
 pub mod stacks;
 pub mod subscriptions;
@@ -1,4 +1,4 @@
-use atoma_state::types::{Stack, StackSettlementTicket};
+use atoma_state::types::{Stack, StackSettlementTicket, StackStatus, StackStatusTransition};
 use axum::{
     extract::{Path, Query, State},
     http::StatusCode,
@@ -20,16 +20,27 @@ pub struct StackQuery {
 
 #[derive(OpenApi)]
 #[openapi(
-    paths(stacks_nodes_list),
-    components(schemas(Stack, StackSettlementTicket, StackQuery))
+    paths(stacks_nodes_list, stack_status_transitions_list),
+    components(schemas(
+        Stack,
+        StackSettlementTicket,
+        StackQuery,
+        StackStatus,
+        StackStatusTransition
+    ))
 )]
 pub struct StacksOpenApi;
 
 pub fn stacks_router() -> Router<DaemonState> {
-    Router::new().route(
-        &format!("{STACKS_PATH}/nodes/{{node_id}}"),
-        get(stacks_nodes_list),
-    )
+    Router::new()
+        .route(
+            &format!("{STACKS_PATH}/nodes/{{node_id}}"),
+            get(stacks_nodes_list),
+        )
+        .route(
+            &format!("{STACKS_PATH}/{{stack_small_id}}/transitions"),
+            get(stack_status_transitions_list),
+        )
 }
 
 /// List stacks
@@ -71,3 +82,33 @@ pub async fn stacks_nodes_list(
         StatusCode::INTERNAL_SERVER_ERROR
     })
 }
+
+/// List stack status transitions
+///
+/// Lists the lifecycle status transitions of a stack, identified by its small ID, in the order
+/// they happened, with the epoch and the event which triggered each of them.
+#[utoipa::path(
+    get,
+    path = "/{stack_small_id}/transitions",
+    params(
+        ("stack_small_id" = i64, Path, description = "Stack small ID")
+    ),
+    responses(
+        (status = OK, description = "List of the status transitions of the stack", body = Vec<StackStatusTransition>),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+pub async fn stack_status_transitions_list(
+    State(daemon_state): State<DaemonState>,
+    Path(stack_small_id): Path<i64>,
+) -> Result<Json<Vec<StackStatusTransition>>, StatusCode> {
+    daemon_state
+        .atoma_state
+        .get_stack_status_transitions(stack_small_id)
+        .await
+        .map(Json)
+        .map_err(|_| {
+            error!("Failed to get stack status transitions");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
@@ -1,7 +1,15 @@
 use atoma_state::state_manager::AtomaState;
 use atoma_sui::client::Client;
-use axum::{http::StatusCode, routing::get, Router};
-use std::sync::Arc;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{sync::Arc, time::Duration};
 use sui_sdk::types::base_types::ObjectID;
 use tokio::{
     net::TcpListener,
@@ -12,9 +20,9 @@ use tracing::instrument;
 use crate::{
     components::openapi::openapi_routes,
     handlers::{
-        attestation_disputes::attestation_disputes_router, claimed_stacks::claimed_stacks_router,
-        nodes::nodes_router, stacks::stacks_router, subscriptions::subscriptions_router,
-        tasks::tasks_router,
+        archived_stacks::archived_stacks_router, attestation_disputes::attestation_disputes_router,
+        claimed_stacks::claimed_stacks_router, earnings::earnings_router, nodes::nodes_router,
+        stacks::stacks_router, subscriptions::subscriptions_router, tasks::tasks_router,
     },
 };
 
@@ -110,6 +118,8 @@ pub async fn run_server(
 ///
 /// ## Health Check
 /// * `GET /health` - Check service health status
+/// * `GET /ready` - Check that the database and the Sui RPC node are reachable (`?verbose=true`
+///   for the result of each check)
 ///
 /// ## Subscription Management
 /// * `GET /subscriptions` - Get all subscriptions for registered nodes
@@ -129,6 +139,10 @@ pub async fn run_server(
 /// * `GET /almost_filled_stacks/{id}/{fraction}` - Get node's stacks filled above fraction
 /// * `GET /stacks/claimed_stacks` - Get all claimed stacks
 /// * `GET /stacks/claimed_stacks/{id}` - Get claimed stacks for a specific node
+/// * `GET /archived-stacks/nodes/{id}` - Get the archived stacks of a specific node
+/// * `GET /stacks/{id}/transitions` - Get the lifecycle status transitions of a stack
+/// * `GET /earnings` - Get the earnings report of all registered nodes, as JSON or CSV
+/// * `GET /earnings/nodes/{id}` - Get the earnings report of a specific node, as JSON or CSV
 /// * `POST /nodes/try-settle-stacks` - Attempt to settle specified stacks
 /// * `POST /submit_stack_settlement_attestations` - Submit attestations for stack settlement
 /// * `POST /claim_funds` - Claim funds from completed stacks
@@ -160,12 +174,15 @@ pub async fn run_server(
 /// ```
 pub fn create_router(daemon_state: DaemonState) -> Router {
     Router::new()
+        .merge(archived_stacks_router())
         .merge(attestation_disputes_router())
         .merge(claimed_stacks_router())
+        .merge(earnings_router())
         .merge(nodes_router())
         .merge(stacks_router())
         .merge(subscriptions_router())
         .merge(tasks_router())
+        .route("/ready", get(ready))
         .with_state(daemon_state)
         .route("/health", get(health))
         .merge(openapi_routes())
@@ -188,6 +205,88 @@ pub async fn health() -> StatusCode {
     StatusCode::OK
 }
 
+/// Timeout of each readiness check of the daemon
+const READY_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Query parameters of the readiness endpoint.
+#[derive(Debug, Default, Deserialize)]
+pub struct ReadyQuery {
+    /// Whether to include the result of each check in the response
+    #[serde(default)]
+    pub verbose: bool,
+}
+
+/// Readiness check endpoint for the daemon.
+///
+/// Checks that the Postgres database and the Sui RPC node are reachable.
+///
+/// # Returns
+/// * `StatusCode::OK` with `{"status": "ready"}` - If all the checks pass
+/// * `StatusCode::SERVICE_UNAVAILABLE` with `{"status": "not_ready"}` - Otherwise
+///
+/// With `?verbose=true`, the result of each check is included under `checks`.
+#[utoipa::path(
+    get,
+    path = "/ready",
+    tag = "health",
+    params(
+        ("verbose" = Option<bool>, Query, description = "Whether to include the result of each check")
+    ),
+    responses(
+        (status = 200, description = "Daemon is ready"),
+        (status = 503, description = "Daemon is not ready")
+    )
+)]
+#[instrument(level = "trace", skip_all)]
+pub async fn ready(
+    State(daemon_state): State<DaemonState>,
+    Query(query): Query<ReadyQuery>,
+) -> impl IntoResponse {
+    let (database, sui_rpc) = tokio::join!(
+        tokio::time::timeout(READY_CHECK_TIMEOUT, daemon_state.atoma_state.ping()),
+        tokio::time::timeout(READY_CHECK_TIMEOUT, async {
+            daemon_state
+                .client
+                .read()
+                .await
+                .get_latest_checkpoint_sequence_number()
+                .await
+        }),
+    );
+    let database = match database {
+        Ok(Ok(())) => json!({ "ready": true }),
+        Ok(Err(e)) => json!({ "ready": false, "error": format!("Database is unreachable: {e}") }),
+        Err(_) => json!({ "ready": false, "error": "Check timed out" }),
+    };
+    let sui_rpc = match sui_rpc {
+        Ok(Ok(latest_checkpoint)) => {
+            json!({ "ready": true, "details": { "latest_checkpoint": latest_checkpoint } })
+        }
+        Ok(Err(e)) => {
+            json!({ "ready": false, "error": format!("Sui RPC node is unreachable: {e}") })
+        }
+        Err(_) => json!({ "ready": false, "error": "Check timed out" }),
+    };
+    let ready = [&database, &sui_rpc]
+        .iter()
+        .all(|check| check["ready"] == Value::Bool(true));
+    let (status_code, status) = if ready {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not_ready")
+    };
+    if query.verbose {
+        return (
+            status_code,
+            Json(json!({
+                "status": status,
+                "checks": { "database": database, "sui_rpc": sui_rpc },
+            })),
+        );
+    }
+    (status_code, Json(json!({ "status": status })))
+}
+
 /// Retrieves all node badges (ObjectID and small ID pairs) from the daemon state.
 ///
 /// # Arguments
@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use clap::Args;
+
+/// Default number of tokens generated per chat completion, if the request does not set a lower
+/// `max_tokens`
+const DEFAULT_COMPLETION_TOKENS: u64 = 16;
+
+/// Default delay between two generated tokens, in milliseconds
+const DEFAULT_INTER_TOKEN_LATENCY_MS: u64 = 10;
+
+/// Default number of dimensions of the generated embeddings
+const DEFAULT_EMBEDDING_DIMENSIONS: usize = 1024;
+
+/// Default status code of the injected failures
+const DEFAULT_FAILURE_STATUS_CODE: u16 = 500;
+
+/// Configuration of the mock inference backend.
+///
+/// Latencies and failures can be injected, to exercise the timeouts and error handling of the
+/// Atoma Service.
+#[derive(Args, Clone, Debug)]
+pub struct MockInferenceConfig {
+    /// Models served by the backend. If empty, requests for any model are served.
+    #[arg(long = "model")]
+    pub models: Vec<String>,
+
+    /// Number of tokens generated per chat completion, if the request does not set a lower
+    /// `max_tokens`.
+    #[arg(long, default_value_t = DEFAULT_COMPLETION_TOKENS)]
+    pub completion_tokens: u64,
+
+    /// Delay before the first generated token, in milliseconds.
+    #[arg(long, default_value_t = 0)]
+    pub time_to_first_token_ms: u64,
+
+    /// Delay between two generated tokens, in milliseconds.
+    ///
+    /// NOTE: A non-zero delay ensures that chunks are flushed one at a time, as by vLLM.
+    #[arg(long, default_value_t = DEFAULT_INTER_TOKEN_LATENCY_MS)]
+    pub inter_token_latency_ms: u64,
+
+    /// Delay before responding to embeddings and image generations requests, in milliseconds.
+    #[arg(long, default_value_t = 0)]
+    pub latency_ms: u64,
+
+    /// Number of dimensions of the generated embeddings.
+    #[arg(long, default_value_t = DEFAULT_EMBEDDING_DIMENSIONS)]
+    pub embedding_dimensions: usize,
+
+    /// Probability (between 0 and 1) for a request to fail with `failure_status_code`.
+    #[arg(long, default_value_t = 0.0)]
+    pub failure_rate: f64,
+
+    /// Status code of the failed requests.
+    #[arg(long, default_value_t = DEFAULT_FAILURE_STATUS_CODE)]
+    pub failure_status_code: u16,
+
+    /// Probability (between 0 and 1) for a streamed chat completion to be interrupted halfway,
+    /// without its usage chunk.
+    #[arg(long, default_value_t = 0.0)]
+    pub stream_interruption_rate: f64,
+}
+
+impl Default for MockInferenceConfig {
+    fn default() -> Self {
+        Self {
+            models: Vec::new(),
+            completion_tokens: DEFAULT_COMPLETION_TOKENS,
+            time_to_first_token_ms: 0,
+            inter_token_latency_ms: DEFAULT_INTER_TOKEN_LATENCY_MS,
+            latency_ms: 0,
+            embedding_dimensions: DEFAULT_EMBEDDING_DIMENSIONS,
+            failure_rate: 0.0,
+            failure_status_code: DEFAULT_FAILURE_STATUS_CODE,
+            stream_interruption_rate: 0.0,
+        }
+    }
+}
+
+impl MockInferenceConfig {
+    pub(crate) const fn time_to_first_token(&self) -> Duration {
+        Duration::from_millis(self.time_to_first_token_ms)
+    }
+
+    pub(crate) const fn inter_token_latency(&self) -> Duration {
+        Duration::from_millis(self.inter_token_latency_ms)
+    }
+
+    pub(crate) const fn latency(&self) -> Duration {
+        Duration::from_millis(self.latency_ms)
+    }
+
+    /// Whether the backend serves the model
+    pub(crate) fn serves(&self, model: &str) -> bool {
+        self.models.is_empty() || self.models.iter().any(|served| served == model)
+    }
+}
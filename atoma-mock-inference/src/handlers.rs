@@ -0,0 +1,555 @@
+use std::{
+    sync::Arc,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Multipart, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Response,
+    },
+    Json,
+};
+use futures::{channel::mpsc, SinkExt};
+use serde_json::{json, Value};
+use tracing::{info, instrument};
+
+use crate::{config::MockInferenceConfig, metrics::Metrics};
+
+/// Words the generated completions are made of, one token per word
+const WORDS: [&str; 8] = [
+    "Atoma",
+    "is",
+    "a",
+    "decentralized",
+    "network",
+    "for",
+    "verifiable",
+    "inference",
+];
+
+/// A 1x1 PNG image, base64 encoded, returned by the image generations endpoint
+const GENERATED_IMAGE: &str =
+    "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAQAAAC1HAwCAAAAC0lEQVR42mNkYAAAAAYAAjCB0C8AAAAASUVORK5CYII=";
+
+/// The last streamed chunk of a chat completion
+const DONE_CHUNK: &str = "[DONE]";
+
+/// The state shared by the handlers of the mock inference backend
+#[derive(Clone)]
+pub struct MockState {
+    /// The configuration of the backend
+    pub config: Arc<MockInferenceConfig>,
+    /// The metrics exposed on `/metrics`
+    pub metrics: Arc<Metrics>,
+}
+
+impl MockState {
+    /// Rejects the request if the model is not served, or if a failure is injected.
+    fn check_request(&self, model: &str) -> Result<(), Response> {
+        if !self.config.serves(model) {
+            return Err(error_response(
+                StatusCode::NOT_FOUND,
+                &format!("The model `{model}` does not exist."),
+            ));
+        }
+        if self.config.failure_rate > 0.0 && rand::random::<f64>() < self.config.failure_rate {
+            let status = StatusCode::from_u16(self.config.failure_status_code)
+                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            info!(
+                target = "atoma-mock-inference",
+                level = "info",
+                "Injecting failure with status code {status}"
+            );
+            return Err(error_response(status, "Injected failure"));
+        }
+        Ok(())
+    }
+}
+
+/// Builds an OpenAI-style error response.
+fn error_response(status: StatusCode, message: &str) -> Response {
+    let body = json!({
+        "object": "error",
+        "message": message,
+        "type": status.canonical_reason().unwrap_or("Error"),
+        "code": status.as_u16(),
+    });
+    (status, Json(body)).into_response()
+}
+
+/// Returns the model of a request body.
+fn model_of(request: &Value) -> String {
+    request
+        .get("model")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Counts the tokens of a text, one token per whitespace-separated word.
+fn count_tokens(text: &str) -> u64 {
+    text.split_whitespace().count() as u64
+}
+
+/// Returns the current UNIX timestamp, in seconds.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// A chat completion generated by the mock backend
+struct Completion {
+    id: String,
+    model: String,
+    created: u64,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    finish_reason: &'static str,
+}
+
+impl Completion {
+    /// Builds the completion of a chat completions request.
+    fn new(request: &Value, config: &MockInferenceConfig) -> Self {
+        let prompt_tokens = request
+            .get("messages")
+            .and_then(Value::as_array)
+            .map(|messages| {
+                messages
+                    .iter()
+                    .filter_map(|message| message.get("content"))
+                    .map(|content| match content {
+                        Value::String(text) => count_tokens(text),
+                        Value::Array(parts) => parts
+                            .iter()
+                            .filter_map(|part| part.get("text").and_then(Value::as_str))
+                            .map(count_tokens)
+                            .sum(),
+                        _ => 0,
+                    })
+                    .sum()
+            })
+            .unwrap_or_default();
+        let max_tokens = request
+            .get("max_completion_tokens")
+            .or_else(|| request.get("max_tokens"))
+            .and_then(Value::as_u64);
+        let (completion_tokens, finish_reason) = match max_tokens {
+            Some(max_tokens) if max_tokens < config.completion_tokens => (max_tokens, "length"),
+            _ => (config.completion_tokens, "stop"),
+        };
+        Self {
+            id: format!("chatcmpl-{:032x}", rand::random::<u128>()),
+            model: model_of(request),
+            created: now(),
+            prompt_tokens,
+            completion_tokens,
+            finish_reason,
+        }
+    }
+
+    /// Returns the content of a generated token.
+    fn token(index: u64) -> String {
+        #[allow(clippy::cast_possible_truncation)]
+        let word = WORDS[(index % WORDS.len() as u64) as usize];
+        if index == 0 {
+            word.to_string()
+        } else {
+            format!(" {word}")
+        }
+    }
+
+    /// Returns the content of the whole completion.
+    fn content(&self) -> String {
+        (0..self.completion_tokens).map(Self::token).collect()
+    }
+
+    fn usage(&self) -> Value {
+        json!({
+            "prompt_tokens": self.prompt_tokens,
+            "completion_tokens": self.completion_tokens,
+            "total_tokens": self.prompt_tokens + self.completion_tokens,
+        })
+    }
+
+    /// Builds a streamed chunk, with the given choices.
+    fn chunk(&self, choices: &Value) -> Value {
+        json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": self.created,
+            "model": self.model,
+            "choices": choices,
+        })
+    }
+
+    /// Builds the streamed chunk of a generated token.
+    fn token_chunk(&self, index: u64) -> Value {
+        let mut delta = json!({ "content": Self::token(index) });
+        if index == 0 {
+            delta["role"] = json!("assistant");
+        }
+        self.chunk(&json!([{
+            "index": 0,
+            "delta": delta,
+            "logprobs": null,
+            "finish_reason": null,
+        }]))
+    }
+
+    /// Builds the streamed chunk carrying the finish reason.
+    fn finish_chunk(&self) -> Value {
+        self.chunk(&json!([{
+            "index": 0,
+            "delta": { "content": "" },
+            "logprobs": null,
+            "finish_reason": self.finish_reason,
+        }]))
+    }
+
+    /// Builds the last streamed chunk, with no choices and the usage of the completion.
+    fn usage_chunk(&self) -> Value {
+        let mut chunk = self.chunk(&json!([]));
+        chunk["usage"] = self.usage();
+        chunk
+    }
+
+    /// Builds the (non-streamed) response.
+    fn response(&self) -> Value {
+        json!({
+            "id": self.id,
+            "object": "chat.completion",
+            "created": self.created,
+            "model": self.model,
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": self.content(),
+                },
+                "logprobs": null,
+                "finish_reason": self.finish_reason,
+            }],
+            "usage": self.usage(),
+        })
+    }
+}
+
+/// POST /v1/chat/completions
+///
+/// Emulates the vLLM chat completions endpoint. Completions are streamed as Server-Sent Events
+/// if the request sets `stream`, with a final usage chunk if it sets
+/// `stream_options.include_usage`.
+#[instrument(level = "info", skip_all, fields(model))]
+pub async fn chat_completions_handler(
+    State(state): State<MockState>,
+    Json(request): Json<Value>,
+) -> Response {
+    let model = model_of(&request);
+    tracing::Span::current().record("model", model.as_str());
+    if let Err(response) = state.check_request(&model) {
+        return response;
+    }
+    let completion = Completion::new(&request, &state.config);
+    let stream = request
+        .get("stream")
+        .and_then(Value::as_bool)
+        .unwrap_or_default();
+    if stream {
+        let include_usage = request
+            .get("stream_options")
+            .and_then(|options| options.get("include_usage"))
+            .and_then(Value::as_bool)
+            .unwrap_or_default();
+        return stream_completion(state, completion, include_usage);
+    }
+
+    let running_request = state.metrics.start_chat_completion();
+    let start = Instant::now();
+    tokio::time::sleep(state.config.time_to_first_token()).await;
+    state
+        .metrics
+        .time_to_first_token
+        .observe(start.elapsed().as_secs_f64());
+    for _ in 1..completion.completion_tokens {
+        tokio::time::sleep(state.config.inter_token_latency()).await;
+        state
+            .metrics
+            .time_per_output_token
+            .observe(state.config.inter_token_latency().as_secs_f64());
+    }
+    drop(running_request);
+    Json(completion.response()).into_response()
+}
+
+/// Streams a completion, one chunk per token.
+///
+/// If a stream interruption is injected, the stream fails halfway through, without its finish
+/// and usage chunks.
+fn stream_completion(state: MockState, completion: Completion, include_usage: bool) -> Response {
+    let (mut sender, receiver) = mpsc::channel::<Result<Event, std::io::Error>>(1);
+    let interrupted_at = (state.config.stream_interruption_rate > 0.0
+        && rand::random::<f64>() < state.config.stream_interruption_rate)
+        .then_some(completion.completion_tokens / 2);
+    let running_request = state.metrics.start_chat_completion();
+    tokio::spawn(async move {
+        let _running_request = running_request;
+        let start = Instant::now();
+        tokio::time::sleep(state.config.time_to_first_token()).await;
+        state
+            .metrics
+            .time_to_first_token
+            .observe(start.elapsed().as_secs_f64());
+        for index in 0..completion.completion_tokens {
+            if index > 0 {
+                tokio::time::sleep(state.config.inter_token_latency()).await;
+                state
+                    .metrics
+                    .time_per_output_token
+                    .observe(state.config.inter_token_latency().as_secs_f64());
+            }
+            if interrupted_at == Some(index) {
+                info!(
+                    target = "atoma-mock-inference",
+                    level = "info",
+                    "Interrupting stream {} after {index} tokens",
+                    completion.id
+                );
+                let _ = sender
+                    .send(Err(std::io::Error::other("Injected stream interruption")))
+                    .await;
+                return;
+            }
+            let event = Event::default().data(completion.token_chunk(index).to_string());
+            if sender.send(Ok(event)).await.is_err() {
+                // NOTE: The client dropped the connection
+                return;
+            }
+        }
+        let mut chunks = vec![completion.finish_chunk()];
+        if include_usage {
+            chunks.push(completion.usage_chunk());
+        }
+        for chunk in chunks {
+            if sender
+                .send(Ok(Event::default().data(chunk.to_string())))
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+        let _ = sender.send(Ok(Event::default().data(DONE_CHUNK))).await;
+    });
+    Sse::new(receiver).into_response()
+}
+
+/// GET /v1/models
+///
+/// Lists the models served by the backend.
+pub async fn models_handler(State(state): State<MockState>) -> Json<Value> {
+    let created = now();
+    let data = state
+        .config
+        .models
+        .iter()
+        .map(|model| {
+            json!({
+                "id": model,
+                "object": "model",
+                "created": created,
+                "owned_by": "atoma",
+            })
+        })
+        .collect::<Vec<_>>();
+    Json(json!({ "object": "list", "data": data }))
+}
+
+/// POST /v1/embeddings
+///
+/// Emulates the OpenAI-compatible embeddings endpoint of Text Embeddings Inference, with
+/// random (normalized) embeddings.
+#[instrument(level = "info", skip_all, fields(model))]
+pub async fn embeddings_handler(
+    State(state): State<MockState>,
+    Json(request): Json<Value>,
+) -> Response {
+    let model = model_of(&request);
+    tracing::Span::current().record("model", model.as_str());
+    if let Err(response) = state.check_request(&model) {
+        return response;
+    }
+    let inputs = match request.get("input") {
+        Some(Value::String(input)) => vec![input.clone()],
+        Some(Value::Array(inputs)) => inputs
+            .iter()
+            .map(|input| {
+                input
+                    .as_str()
+                    .map_or_else(|| input.to_string(), str::to_string)
+            })
+            .collect(),
+        _ => {
+            return error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "The `input` field must be a string or an array",
+            )
+        }
+    };
+    let start = Instant::now();
+    state.metrics.embeddings_queue_duration.observe(0.0);
+    tokio::time::sleep(state.config.latency()).await;
+    let prompt_tokens = inputs.iter().map(|input| count_tokens(input)).sum::<u64>();
+    #[allow(clippy::cast_precision_loss)]
+    {
+        for input in &inputs {
+            state
+                .metrics
+                .embeddings_input_length
+                .observe(count_tokens(input) as f64);
+        }
+        state
+            .metrics
+            .embeddings_batch_size
+            .observe(inputs.len() as f64);
+        state
+            .metrics
+            .embeddings_batch_tokens
+            .observe(prompt_tokens as f64);
+    }
+    let data = (0..inputs.len())
+        .map(|index| {
+            json!({
+                "object": "embedding",
+                "index": index,
+                "embedding": random_embedding(state.config.embedding_dimensions),
+            })
+        })
+        .collect::<Vec<_>>();
+    state
+        .metrics
+        .embeddings_inference_duration
+        .observe(start.elapsed().as_secs_f64());
+    Json(json!({
+        "object": "list",
+        "data": data,
+        "model": model,
+        "usage": {
+            "prompt_tokens": prompt_tokens,
+            "total_tokens": prompt_tokens,
+        },
+    }))
+    .into_response()
+}
+
+/// Generates a random embedding of unit norm.
+fn random_embedding(dimensions: usize) -> Vec<f32> {
+    let embedding = (0..dimensions)
+        .map(|_| rand::random::<f32>() - 0.5)
+        .collect::<Vec<_>>();
+    let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm == 0.0 {
+        return embedding;
+    }
+    embedding.into_iter().map(|x| x / norm).collect()
+}
+
+/// POST /v1/images/generations
+///
+/// Emulates the OpenAI-compatible image generations endpoint, returning `n` 1x1 PNG images.
+#[instrument(level = "info", skip_all, fields(model))]
+pub async fn image_generations_handler(
+    State(state): State<MockState>,
+    Json(request): Json<Value>,
+) -> Response {
+    generate_images(&state, &request).await
+}
+
+/// POST /v1/images/edits and POST /v1/images/variations
+///
+/// Emulates the OpenAI-compatible image edits and variations endpoints, whose requests are
+/// multipart forms with an `image` file, returning `n` 1x1 PNG images.
+#[instrument(level = "info", skip_all, fields(model))]
+pub async fn image_edits_handler(
+    State(state): State<MockState>,
+    mut multipart: Multipart,
+) -> Response {
+    let mut request = serde_json::Map::new();
+    let mut has_image = false;
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+        };
+        let name = field.name().unwrap_or_default().to_string();
+        if field.file_name().is_some() {
+            has_image |= name.starts_with("image");
+            if let Err(e) = field.bytes().await {
+                return error_response(StatusCode::BAD_REQUEST, &e.to_string());
+            }
+            continue;
+        }
+        match field.text().await {
+            Ok(text) => {
+                let value = text
+                    .parse::<u64>()
+                    .map_or_else(|_| Value::String(text), Value::from);
+                request.insert(name, value);
+            }
+            Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+        }
+    }
+    if !has_image {
+        return error_response(StatusCode::BAD_REQUEST, "The `image` file is required");
+    }
+    generate_images(&state, &Value::Object(request)).await
+}
+
+/// Generates `n` 1x1 PNG images, in the requested response format.
+async fn generate_images(state: &MockState, request: &Value) -> Response {
+    let model = model_of(request);
+    tracing::Span::current().record("model", model.as_str());
+    if let Err(response) = state.check_request(&model) {
+        return response;
+    }
+    let running_request = state.metrics.start_image_generation();
+    let start = Instant::now();
+    tokio::time::sleep(state.config.latency()).await;
+    let n = request.get("n").and_then(Value::as_u64).unwrap_or(1);
+    let as_url = request.get("response_format").and_then(Value::as_str) == Some("url");
+    let data = (0..n)
+        .map(|_| {
+            if as_url {
+                json!({ "url": format!("data:image/png;base64,{GENERATED_IMAGE}") })
+            } else {
+                json!({ "b64_json": GENERATED_IMAGE })
+            }
+        })
+        .collect::<Vec<_>>();
+    state
+        .metrics
+        .image_generation_latency
+        .observe(start.elapsed().as_secs_f64());
+    drop(running_request);
+    Json(json!({ "created": now(), "data": data })).into_response()
+}
+
+/// GET /metrics
+///
+/// Exposes the metrics of the backend, in the Prometheus text format.
+pub async fn metrics_handler(State(state): State<MockState>) -> Response {
+    match state.metrics.encode() {
+        Ok(metrics) => metrics.into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+/// GET /health
+pub async fn health_handler() -> StatusCode {
+    StatusCode::OK
+}
@@ -0,0 +1,282 @@
+//! A mock inference backend, emulating the APIs of the inference services run alongside an
+//! Atoma node:
+//!
+//! - the vLLM OpenAI-compatible chat completions API (streaming and non-streaming, with usage),
+//! - the Text Embeddings Inference (TEI) OpenAI-compatible embeddings API,
+//! - the OpenAI-compatible image generations, edits and variations APIs,
+//! - the Prometheus `/metrics` endpoint, exposing the `vllm:*`, `tei:*` and `mistral:*` series
+//!   queried by the node.
+//!
+//! Latencies and failures can be injected through [`config::MockInferenceConfig`], which makes
+//! the backend suitable to drive end-to-end tests of the Atoma Service, as well as local runs of
+//! a node without GPUs.
+
+use std::{io, net::SocketAddr, sync::Arc};
+
+use axum::{
+    routing::{get, post},
+    Router,
+};
+use tokio::{net::TcpListener, task::JoinHandle};
+use tracing::error;
+
+pub mod config;
+pub mod handlers;
+pub mod metrics;
+
+use config::MockInferenceConfig;
+use handlers::{
+    chat_completions_handler, embeddings_handler, health_handler, image_edits_handler,
+    image_generations_handler, metrics_handler, models_handler, MockState,
+};
+use metrics::Metrics;
+
+/// The chat completions endpoint
+pub const CHAT_COMPLETIONS_PATH: &str = "/v1/chat/completions";
+
+/// The embeddings endpoint
+pub const EMBEDDINGS_PATH: &str = "/v1/embeddings";
+
+/// The image generations endpoint
+pub const IMAGE_GENERATIONS_PATH: &str = "/v1/images/generations";
+
+/// The image edits endpoint
+pub const IMAGE_EDITS_PATH: &str = "/v1/images/edits";
+
+/// The image variations endpoint
+pub const IMAGE_VARIATIONS_PATH: &str = "/v1/images/variations";
+
+/// The models endpoint
+pub const MODELS_PATH: &str = "/v1/models";
+
+/// The Prometheus metrics endpoint
+pub const METRICS_PATH: &str = "/metrics";
+
+/// The health endpoint
+pub const HEALTH_PATH: &str = "/health";
+
+/// Creates the router of the mock inference backend.
+///
+/// # Errors
+///
+/// Returns an error if the Prometheus metrics cannot be registered.
+pub fn create_router(config: MockInferenceConfig) -> Result<Router, prometheus::Error> {
+    let state = MockState {
+        config: Arc::new(config),
+        metrics: Arc::new(Metrics::new()?),
+    };
+    Ok(Router::new()
+        .route(CHAT_COMPLETIONS_PATH, post(chat_completions_handler))
+        .route(EMBEDDINGS_PATH, post(embeddings_handler))
+        .route(IMAGE_GENERATIONS_PATH, post(image_generations_handler))
+        .route(IMAGE_EDITS_PATH, post(image_edits_handler))
+        .route(IMAGE_VARIATIONS_PATH, post(image_edits_handler))
+        .route(MODELS_PATH, get(models_handler))
+        .route(METRICS_PATH, get(metrics_handler))
+        .route(HEALTH_PATH, get(health_handler))
+        .with_state(state))
+}
+
+/// A mock inference backend served in the background, on a random local port.
+///
+/// The server is stopped when dropped.
+pub struct MockInferenceServer {
+    address: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+impl MockInferenceServer {
+    /// Spawns a mock inference backend on a random local port.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the listener cannot be bound, or if the metrics cannot be registered.
+    pub async fn spawn(config: MockInferenceConfig) -> io::Result<Self> {
+        let router = create_router(config).map_err(io::Error::other)?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let handle = tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, router).await {
+                error!(
+                    target = "atoma-mock-inference",
+                    level = "error",
+                    "Mock inference server failed: {e}"
+                );
+            }
+        });
+        Ok(Self { address, handle })
+    }
+
+    /// The address the server listens on
+    pub const fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// The base URL of the server, e.g. `http://127.0.0.1:41234`
+    pub fn url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+}
+
+impl Drop for MockInferenceServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Request, StatusCode},
+    };
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    const MODEL: &str = "meta-llama/Llama-3.1-70B-Instruct";
+
+    fn config() -> MockInferenceConfig {
+        MockInferenceConfig {
+            models: vec![MODEL.to_string()],
+            completion_tokens: 4,
+            inter_token_latency_ms: 1,
+            embedding_dimensions: 8,
+            ..Default::default()
+        }
+    }
+
+    async fn post_json(router: Router, path: &str, body: &Value) -> (StatusCode, String) {
+        let request = Request::post(path)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_chat_completion() {
+        let router = create_router(config()).unwrap();
+        let body = json!({
+            "model": MODEL,
+            "messages": [{ "role": "user", "content": "Hello there, how are you?" }],
+            "max_tokens": 3,
+        });
+        let (status, body) = post_json(router, CHAT_COMPLETIONS_PATH, &body).await;
+        assert_eq!(status, StatusCode::OK);
+        let response: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(response["object"], "chat.completion");
+        assert_eq!(response["choices"][0]["finish_reason"], "length");
+        assert_eq!(response["choices"][0]["message"]["content"], "Atoma is a");
+        assert_eq!(
+            response["usage"],
+            json!({ "prompt_tokens": 5, "completion_tokens": 3, "total_tokens": 8 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_streamed_chat_completion() {
+        let router = create_router(config()).unwrap();
+        let body = json!({
+            "model": MODEL,
+            "messages": [{ "role": "user", "content": "Hello" }],
+            "stream": true,
+            "stream_options": { "include_usage": true },
+        });
+        let (status, body) = post_json(router, CHAT_COMPLETIONS_PATH, &body).await;
+        assert_eq!(status, StatusCode::OK);
+        let events = body
+            .split("\n\n")
+            .filter_map(|event| event.strip_prefix("data: "))
+            .collect::<Vec<_>>();
+        // 4 tokens, the finish chunk, the usage chunk and [DONE]
+        assert_eq!(events.len(), 7);
+        assert_eq!(events[6], "[DONE]");
+        let content = events[..4]
+            .iter()
+            .map(|event| {
+                let chunk: Value = serde_json::from_str(event).unwrap();
+                chunk["choices"][0]["delta"]["content"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect::<String>();
+        assert_eq!(content, "Atoma is a decentralized");
+        let finish: Value = serde_json::from_str(events[4]).unwrap();
+        assert_eq!(finish["choices"][0]["finish_reason"], "stop");
+        let usage: Value = serde_json::from_str(events[5]).unwrap();
+        assert_eq!(usage["choices"], json!([]));
+        assert_eq!(usage["usage"]["completion_tokens"], 4);
+        assert_eq!(usage["usage"]["prompt_tokens"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_failure_injection() {
+        let router = create_router(MockInferenceConfig {
+            failure_rate: 1.0,
+            failure_status_code: 503,
+            ..config()
+        })
+        .unwrap();
+        let body = json!({ "model": MODEL, "input": "Hello" });
+        let (status, _) = post_json(router.clone(), EMBEDDINGS_PATH, &body).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+        let body = json!({ "model": "unknown/model", "input": "Hello" });
+        let (status, _) = post_json(router, EMBEDDINGS_PATH, &body).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_embeddings_and_image_generations() {
+        let router = create_router(config()).unwrap();
+        let body = json!({ "model": MODEL, "input": ["Hello", "Hello world"] });
+        let (status, body) = post_json(router.clone(), EMBEDDINGS_PATH, &body).await;
+        assert_eq!(status, StatusCode::OK);
+        let response: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(response["data"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            response["data"][1]["embedding"].as_array().unwrap().len(),
+            8
+        );
+        assert_eq!(response["usage"]["prompt_tokens"], 3);
+
+        let body = json!({ "model": MODEL, "prompt": "A cat", "n": 2 });
+        let (status, body) = post_json(router, IMAGE_GENERATIONS_PATH, &body).await;
+        assert_eq!(status, StatusCode::OK);
+        let response: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(response["data"].as_array().unwrap().len(), 2);
+        assert!(response["data"][0]["b64_json"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let router = create_router(config()).unwrap();
+        let body = json!({
+            "model": MODEL,
+            "messages": [{ "role": "user", "content": "Hello" }],
+        });
+        post_json(router.clone(), CHAT_COMPLETIONS_PATH, &body).await;
+        let request = Request::get(METRICS_PATH).body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let metrics = String::from_utf8(body.to_vec()).unwrap();
+        for name in [
+            "vllm:time_to_first_token_seconds_count 1",
+            "vllm:time_per_output_token_seconds_count 3",
+            "vllm:request_queue_time_seconds",
+            "vllm:gpu_cache_usage_perc",
+            "vllm:running_requests 0",
+            "tei:embeddings_inference_duration",
+            "mistral:image_generation_latency",
+        ] {
+            assert!(metrics.contains(name), "missing {name} in {metrics}");
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use atoma_mock_inference::{config::MockInferenceConfig, create_router};
+use clap::Parser;
+use tokio::{net::TcpListener, signal};
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+/// Default address the mock inference backend listens on
+const DEFAULT_BIND_ADDRESS: &str = "0.0.0.0:8000";
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Address the mock inference backend listens on
+    #[arg(short, long, default_value = DEFAULT_BIND_ADDRESS)]
+    bind_address: String,
+
+    #[command(flatten)]
+    config: MockInferenceConfig,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .try_init();
+
+    let args = Args::parse();
+    info!(
+        event = "startup",
+        "Starting mock inference backend with configuration: {:?}", args.config
+    );
+    let router = create_router(args.config)?;
+    let listener = TcpListener::bind(&args.bind_address).await?;
+    info!(
+        event = "startup",
+        "Mock inference backend listening on {}",
+        listener.local_addr()?
+    );
+    axum::serve(listener, router)
+        .with_graceful_shutdown(async {
+            let _ = signal::ctrl_c().await;
+            info!(event = "shutdown", "Shutting down mock inference backend");
+        })
+        .await?;
+    Ok(())
+}
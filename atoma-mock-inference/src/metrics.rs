@@ -0,0 +1,167 @@
+//! Prometheus metrics of the mock inference backend, named after the `vllm:*`, `tei:*` and
+//! `mistral:*` series exposed by the emulated inference services (and queried by the Atoma
+//! node).
+
+use prometheus::{Encoder, Gauge, Histogram, HistogramOpts, IntGauge, Opts, Registry, TextEncoder};
+
+/// Number of running requests from which the emulated cache is considered full
+const FULL_CACHE_RUNNING_REQUESTS: f64 = 100.0;
+
+/// The metrics of the mock inference backend
+pub struct Metrics {
+    registry: Registry,
+    pub time_to_first_token: Histogram,
+    pub time_per_output_token: Histogram,
+    pub request_queue_time: Histogram,
+    pub gpu_cache_usage: Gauge,
+    pub cpu_cache_usage: Gauge,
+    pub running_requests: IntGauge,
+    pub waiting_requests: IntGauge,
+    pub embeddings_queue_duration: Histogram,
+    pub embeddings_inference_duration: Histogram,
+    pub embeddings_input_length: Histogram,
+    pub embeddings_batch_size: Histogram,
+    pub embeddings_batch_tokens: Histogram,
+    pub image_generation_latency: Histogram,
+    pub image_generation_running_requests: IntGauge,
+}
+
+impl Metrics {
+    /// Creates the metrics, registered in a dedicated registry.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a metric cannot be registered.
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+        let histogram = |name: &str, help: &str| -> Result<Histogram, prometheus::Error> {
+            let histogram = Histogram::with_opts(HistogramOpts::new(name, help))?;
+            registry.register(Box::new(histogram.clone()))?;
+            Ok(histogram)
+        };
+        let gauge = |name: &str, help: &str| -> Result<Gauge, prometheus::Error> {
+            let gauge = Gauge::with_opts(Opts::new(name, help))?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+        let int_gauge = |name: &str, help: &str| -> Result<IntGauge, prometheus::Error> {
+            let gauge = IntGauge::with_opts(Opts::new(name, help))?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+        Ok(Self {
+            time_to_first_token: histogram(
+                "vllm:time_to_first_token_seconds",
+                "Histogram of time to first token in seconds.",
+            )?,
+            time_per_output_token: histogram(
+                "vllm:time_per_output_token_seconds",
+                "Histogram of time per output token in seconds.",
+            )?,
+            request_queue_time: histogram(
+                "vllm:request_queue_time_seconds",
+                "Histogram of time spent in the waiting queue, in seconds.",
+            )?,
+            gpu_cache_usage: gauge("vllm:gpu_cache_usage_perc", "GPU KV-cache usage.")?,
+            cpu_cache_usage: gauge("vllm:cpu_cache_usage_perc", "CPU KV-cache usage.")?,
+            running_requests: int_gauge(
+                "vllm:running_requests",
+                "Number of requests currently running.",
+            )?,
+            waiting_requests: int_gauge(
+                "vllm:waiting_requests",
+                "Number of requests waiting to be processed.",
+            )?,
+            embeddings_queue_duration: histogram(
+                "tei:embeddings_queue_duration",
+                "Time spent in the embeddings queue.",
+            )?,
+            embeddings_inference_duration: histogram(
+                "tei:embeddings_inference_duration",
+                "Embeddings inference duration.",
+            )?,
+            embeddings_input_length: histogram(
+                "tei:embeddings_input_length",
+                "Embeddings input length, in tokens.",
+            )?,
+            embeddings_batch_size: histogram(
+                "tei:embeddings_batch_size",
+                "Embeddings batch size.",
+            )?,
+            embeddings_batch_tokens: histogram(
+                "tei:embeddings_batch_tokens",
+                "Number of tokens of the embeddings batches.",
+            )?,
+            image_generation_latency: histogram(
+                "mistral:image_generation_latency",
+                "Image generation latency, in seconds.",
+            )?,
+            image_generation_running_requests: int_gauge(
+                "mistral:running_requests",
+                "Number of image generation requests currently running.",
+            )?,
+            registry,
+        })
+    }
+
+    /// Starts a chat completion, which is counted as running until the guard is dropped.
+    pub fn start_chat_completion(&self) -> RunningRequest {
+        self.request_queue_time.observe(0.0);
+        RunningRequest::new(
+            self.running_requests.clone(),
+            Some((self.gpu_cache_usage.clone(), self.cpu_cache_usage.clone())),
+        )
+    }
+
+    /// Starts an image generation, which is counted as running until the guard is dropped.
+    pub fn start_image_generation(&self) -> RunningRequest {
+        RunningRequest::new(self.image_generation_running_requests.clone(), None)
+    }
+
+    /// Encodes the metrics in the Prometheus text format.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metrics cannot be encoded.
+    pub fn encode(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        String::from_utf8(buffer).map_err(|e| prometheus::Error::Msg(e.to_string()))
+    }
+}
+
+/// A running request, counted in the running requests gauge (and the emulated KV-cache usage)
+/// until it is dropped.
+pub struct RunningRequest {
+    running_requests: IntGauge,
+    cache_usage: Option<(Gauge, Gauge)>,
+}
+
+impl RunningRequest {
+    fn new(running_requests: IntGauge, cache_usage: Option<(Gauge, Gauge)>) -> Self {
+        running_requests.inc();
+        let running_request = Self {
+            running_requests,
+            cache_usage,
+        };
+        running_request.update_cache_usage();
+        running_request
+    }
+
+    /// Emulates a KV-cache usage proportional to the number of running requests
+    fn update_cache_usage(&self) {
+        if let Some((gpu_cache_usage, cpu_cache_usage)) = &self.cache_usage {
+            #[allow(clippy::cast_precision_loss)]
+            let usage = (self.running_requests.get() as f64 / FULL_CACHE_RUNNING_REQUESTS).min(1.0);
+            gpu_cache_usage.set(usage);
+            cpu_cache_usage.set(usage / 2.0);
+        }
+    }
+}
+
+impl Drop for RunningRequest {
+    fn drop(&mut self) {
+        self.running_requests.dec();
+        self.update_cache_usage();
+    }
+}
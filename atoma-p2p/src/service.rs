@@ -128,6 +128,10 @@ pub struct AtomaP2pNode {
 
     /// Bootstrap node peer IDs
     bootstrap_node_peer_ids: Option<Vec<String>>,
+
+    /// Sender publishing the number of peers the node is connected to, updated with the
+    /// network metrics
+    num_peers_sender: watch::Sender<usize>,
 }
 
 impl AtomaP2pNode {
@@ -478,9 +482,17 @@ impl AtomaP2pNode {
             network_metrics,
             metrics_registry,
             bootstrap_node_peer_ids: config.bootstrap_node_peer_ids,
+            num_peers_sender: watch::Sender::new(0),
         })
     }
 
+    /// Returns a receiver for the number of peers the node is connected to, updated every
+    /// time the network metrics are.
+    #[must_use]
+    pub fn num_peers(&self) -> watch::Receiver<usize> {
+        self.num_peers_sender.subscribe()
+    }
+
     /// Starts the P2P node's main event loop, handling network events and shutdown signals.
     ///
     /// This method runs an infinite loop that processes:
@@ -577,6 +589,7 @@ impl AtomaP2pNode {
                     self.network_metrics.update_metrics();
 
                     let network_info = self.swarm.network_info();
+                    self.num_peers_sender.send_replace(network_info.num_peers());
 
                     extract_gossipsub_metrics(&self.swarm.behaviour_mut().gossipsub);
 
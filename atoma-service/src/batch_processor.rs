@@ -0,0 +1,369 @@
+//! Background processing of batch inference requests.
+//!
+//! Batches are submitted through the `/v1/batches` endpoint and stored by the state manager.
+//! The batch processor polls for their pending requests and forwards them to the inference
+//! services, in small rounds, and only while the node is not busy with interactive requests.
+//! Once every request of a batch has been processed, the compute units reserved for the batch
+//! and not used are released back to its stack.
+
+use std::time::Duration;
+
+use atoma_state::types::{
+    AtomaAtomaStateManagerEvent, Batch, BatchRequest, BATCH_REQUEST_STATUS_COMPLETED,
+    BATCH_REQUEST_STATUS_FAILED,
+};
+use atoma_utils::hashing::blake2b_hash;
+use futures::future::join_all;
+use reqwest::Client;
+use serde_json::Value;
+use tokio::sync::watch::Receiver;
+use tracing::{error, info, instrument, warn};
+
+use crate::{
+    config::BatchProcessorConfig,
+    error::AtomaServiceError,
+    handlers::{
+        batches::{query_state_manager, BATCHES_PATH},
+        chat_completions::{
+            utils::{extract_total_num_tokens, send_request_to_inference_service},
+            CHAT_COMPLETIONS_PATH,
+        },
+        embeddings::EMBEDDINGS_PATH,
+        handle_status_code_error, sign_response_and_update_stack_hash,
+        structured_output::StructuredOutputValidator,
+        update_stack_num_compute_units,
+    },
+    server::AppState,
+    trace_context::inference_service_headers,
+};
+
+/// Restores the in-flight request counts of the stacks paying for in-progress batches.
+///
+/// Each in-progress batch counts as a single in-flight request for its stack, so that the
+/// stack is not locked for claim while compute units are still reserved for the batch. These
+/// counts are not persisted by the node, and must therefore be restored when it restarts (when
+/// the state is shared between replicas, they replace the batch counts shared by all of them).
+///
+/// # Errors
+///
+/// Returns an error if the in-progress batches cannot be retrieved from the state manager.
+#[instrument(level = "info", skip_all, err)]
+pub async fn restore_in_progress_batches(state: &AppState) -> Result<(), AtomaServiceError> {
+    let batches = query_state_manager(state, BATCHES_PATH, |result_sender| {
+        AtomaAtomaStateManagerEvent::GetInProgressBatches { result_sender }
+    })
+    .await?;
+    state
+        .concurrent_requests_per_stack
+        .restore_batches(batches.iter().map(|batch| batch.stack_small_id));
+    info!(
+        target = "atoma-service",
+        level = "info",
+        "Restored {} in-progress batches",
+        batches.len()
+    );
+    Ok(())
+}
+
+/// Runs the batch processor until a shutdown signal is received.
+///
+/// The processor repeatedly processes rounds of pending batch requests. Whenever there is
+/// nothing to process, either because there are no pending requests or because the node is
+/// busy with interactive requests, it waits for the configured poll interval.
+///
+/// # Arguments
+///
+/// * `state` - The shared application state
+/// * `config` - The batch processor configuration
+/// * `shutdown_receiver` - Receives the shutdown signal of the node
+///
+/// # Errors
+///
+/// This function does not currently fail, errors of individual rounds are logged and the
+/// processor keeps running.
+pub async fn run_batch_processor(
+    state: AppState,
+    config: BatchProcessorConfig,
+    mut shutdown_receiver: Receiver<bool>,
+) -> anyhow::Result<()> {
+    info!(
+        target = "atoma-service",
+        level = "info",
+        "Starting batch processor"
+    );
+    let poll_interval = Duration::from_millis(config.poll_interval_ms);
+    loop {
+        if *shutdown_receiver.borrow() {
+            break;
+        }
+        let num_processed = match process_pending_batch_requests(&state, &config).await {
+            Ok(num_processed) => num_processed,
+            Err(e) => {
+                error!(
+                    target = "atoma-service",
+                    level = "error",
+                    "Failed to process pending batch requests: {e}"
+                );
+                0
+            }
+        };
+        if num_processed == 0 {
+            tokio::select! {
+                () = tokio::time::sleep(poll_interval) => {}
+                _ = shutdown_receiver.changed() => {}
+            }
+        }
+    }
+    info!(
+        target = "atoma-service",
+        level = "info",
+        "Batch processor shut down"
+    );
+    Ok(())
+}
+
+/// Processes a single round of pending batch requests.
+///
+/// Interactive requests take priority over batch requests: no batch request is processed
+/// while the number of in-flight interactive requests is at or above the configured limit.
+/// Otherwise, up to `max_concurrent_requests` pending requests are processed concurrently.
+///
+/// No batch request is processed while the service is draining, on shutdown: the pending
+/// requests are processed once the node restarts.
+///
+/// # Returns
+///
+/// The number of batch requests whose result was recorded in this round.
+async fn process_pending_batch_requests(
+    state: &AppState,
+    config: &BatchProcessorConfig,
+) -> Result<usize, AtomaServiceError> {
+    if state.drain.is_draining() {
+        return Ok(0);
+    }
+    let in_progress_batches = query_state_manager(state, BATCHES_PATH, |result_sender| {
+        AtomaAtomaStateManagerEvent::GetInProgressBatches { result_sender }
+    })
+    .await?;
+    if in_progress_batches.is_empty() {
+        return Ok(0);
+    }
+    // NOTE: Each in-progress batch accounts for one in-flight request of its stack.
+    let in_flight_requests = state.concurrent_requests_per_stack.total();
+    let interactive_requests = in_flight_requests.saturating_sub(in_progress_batches.len() as u64);
+    if interactive_requests >= config.max_interactive_requests {
+        return Ok(0);
+    }
+
+    let requests = query_state_manager(state, BATCHES_PATH, |result_sender| {
+        AtomaAtomaStateManagerEvent::GetPendingBatchRequests {
+            limit: config.max_concurrent_requests as i64,
+            result_sender,
+        }
+    })
+    .await?;
+    let results = join_all(
+        requests
+            .into_iter()
+            .map(|request| process_batch_request(state, &in_progress_batches, request)),
+    )
+    .await;
+    let mut num_processed = 0;
+    for result in results {
+        match result {
+            Ok(true) => num_processed += 1,
+            Ok(false) => {}
+            Err(e) => {
+                error!(
+                    target = "atoma-service",
+                    level = "error",
+                    "Failed to process batch request: {e}"
+                );
+            }
+        }
+    }
+    Ok(num_processed)
+}
+
+/// Processes a single batch request, and records its result.
+///
+/// If the inference service is temporarily unavailable, the request is left pending, to be
+/// retried in a later round. Any other error marks the request as failed. When the request is
+/// the last one of its batch to be processed, the batch is finalized.
+///
+/// The request is counted as in-flight while it is processed, so that the node waits for it
+/// when draining.
+///
+/// # Returns
+///
+/// Whether the result of the request was recorded.
+#[instrument(
+    level = "info",
+    skip_all,
+    fields(batch_id = %request.batch_id, line_index = request.line_index),
+    err
+)]
+async fn process_batch_request(
+    state: &AppState,
+    batches: &[Batch],
+    mut request: BatchRequest,
+) -> Result<bool, AtomaServiceError> {
+    let _in_flight = state.drain.track();
+    let Some(batch) = batches.iter().find(|b| b.batch_id == request.batch_id) else {
+        // NOTE: The batch was completed after the pending requests were fetched
+        return Ok(false);
+    };
+    match send_batch_request(state, batch, &request).await {
+        Ok((response_body, used_compute_units)) => {
+            request.status = BATCH_REQUEST_STATUS_COMPLETED.to_string();
+            request.status_code = Some(i64::from(reqwest::StatusCode::OK.as_u16()));
+            request.response = Some(response_body.to_string());
+            request.used_compute_units = used_compute_units.min(request.estimated_compute_units);
+        }
+        Err(e @ AtomaServiceError::ChatCompletionsServiceUnavailable { .. }) => {
+            warn!(
+                target = "atoma-service",
+                level = "warn",
+                "Inference service unavailable, batch request will be retried: {e}"
+            );
+            return Ok(false);
+        }
+        Err(e) => {
+            request.status = BATCH_REQUEST_STATUS_FAILED.to_string();
+            request.status_code = Some(i64::from(e.status_code().as_u16()));
+            request.error = Some(e.to_string());
+            request.used_compute_units = 0;
+        }
+    }
+
+    let completed_batch = query_state_manager(state, BATCHES_PATH, |result_sender| {
+        AtomaAtomaStateManagerEvent::CompleteBatchRequest {
+            request,
+            result_sender,
+        }
+    })
+    .await?;
+    if let Some(batch) = completed_batch {
+        finalize_batch(state, &batch)?;
+    }
+    Ok(true)
+}
+
+/// Sends a batch request to the inference service targeted by its batch, and signs the
+/// response, exactly as for interactive requests. Batch requests are traced with the request
+/// ID `<batch_id>-<line_index>`.
+///
+/// # Returns
+///
+/// The signed response body, together with the number of compute units used by the request.
+async fn send_batch_request(
+    state: &AppState,
+    batch: &Batch,
+    request: &BatchRequest,
+) -> Result<(Value, i64), AtomaServiceError> {
+    let endpoint = batch.endpoint.as_str();
+    let payload: Value =
+        serde_json::from_str(&request.body).map_err(|e| AtomaServiceError::InvalidBody {
+            message: format!("Failed to parse batch request body: {e}"),
+            endpoint: endpoint.to_string(),
+        })?;
+    let payload_hash: [u8; 32] = blake2b_hash(payload.to_string().as_bytes()).into();
+    let request_id = format!("{}-{}", request.batch_id, request.line_index);
+
+    let (mut response_body, used_compute_units) = if endpoint == CHAT_COMPLETIONS_PATH {
+        let response_body = send_request_to_inference_service(
+            state,
+            &payload,
+            batch.stack_small_id,
+            payload_hash,
+            endpoint,
+            Some(request_id.as_str()),
+        )
+        .await?;
+        let model = payload
+            .get("model")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let used_compute_units = extract_total_num_tokens(&response_body, model);
+        let mut response_body = response_body;
+        if let Some(validator) = StructuredOutputValidator::from_request(&payload, endpoint)? {
+            validator.annotate_response(&mut response_body, model);
+        }
+        (response_body, used_compute_units)
+    } else if endpoint == EMBEDDINGS_PATH {
+        let response = Client::new()
+            .post(format!(
+                "{}{}",
+                state.embeddings_service_url, EMBEDDINGS_PATH
+            ))
+            .headers(inference_service_headers(Some(request_id.as_str())))
+            .json(&payload)
+            .send()
+            .await
+            .map_err(|e| AtomaServiceError::InternalError {
+                message: format!("Error sending request to embeddings service: {}", e),
+                endpoint: endpoint.to_string(),
+            })?;
+        if !response.status().is_success() {
+            let error = response
+                .status()
+                .canonical_reason()
+                .unwrap_or("Unknown error");
+            handle_status_code_error(response.status(), endpoint, error)?;
+        }
+        let response_body =
+            response
+                .json::<Value>()
+                .await
+                .map_err(|e| AtomaServiceError::InternalError {
+                    message: format!("Error reading response body: {}", e),
+                    endpoint: endpoint.to_string(),
+                })?;
+        // NOTE: Embeddings requests are charged their estimated compute units
+        (response_body, request.estimated_compute_units)
+    } else {
+        return Err(AtomaServiceError::InvalidBody {
+            message: format!("Unsupported batch endpoint: {endpoint}"),
+            endpoint: endpoint.to_string(),
+        });
+    };
+
+    sign_response_and_update_stack_hash(
+        &mut response_body,
+        payload_hash,
+        state,
+        batch.stack_small_id,
+        endpoint.to_string(),
+        Some(request_id.as_str()),
+    )
+    .await?;
+    Ok((response_body, used_compute_units))
+}
+
+/// Finalizes a completed batch, releasing the compute units reserved for it and not used,
+/// and the in-flight request slot it was holding on its stack.
+fn finalize_batch(state: &AppState, batch: &Batch) -> Result<(), AtomaServiceError> {
+    let concurrent_requests = state
+        .concurrent_requests_per_stack
+        .decrement_batch(batch.stack_small_id, BATCHES_PATH);
+    update_stack_num_compute_units(
+        &state.state_manager_sender,
+        batch.stack_small_id,
+        batch.reserved_compute_units,
+        batch.used_compute_units,
+        batch.completed_requests,
+        BATCHES_PATH,
+        concurrent_requests,
+        None,
+    )?;
+    info!(
+        target = "atoma-service",
+        level = "info",
+        batch_id = batch.batch_id,
+        stack_small_id = batch.stack_small_id,
+        reserved_compute_units = batch.reserved_compute_units,
+        used_compute_units = batch.used_compute_units,
+        "Batch completed"
+    );
+    Ok(())
+}
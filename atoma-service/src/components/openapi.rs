@@ -2,6 +2,7 @@ use axum::Router;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
+use crate::handlers::batches::{BatchesOpenApi, BATCHES_PATH};
 use crate::handlers::chat_completions::{
     ChatCompletionsOpenApi, ConfidentialChatCompletionsOpenApi, CHAT_COMPLETIONS_PATH,
     CONFIDENTIAL_CHAT_COMPLETIONS_PATH,
@@ -10,23 +11,30 @@ use crate::handlers::embeddings::{
     ConfidentialEmbeddingsOpenApi, EmbeddingsOpenApi, CONFIDENTIAL_EMBEDDINGS_PATH, EMBEDDINGS_PATH,
 };
 use crate::handlers::image_generations::{
-    ConfidentialImageGenerationsOpenApi, ImageGenerationsOpenApi,
-    CONFIDENTIAL_IMAGE_GENERATIONS_PATH, IMAGE_GENERATIONS_PATH,
+    ConfidentialImageGenerationsOpenApi, ImageEditsOpenApi, ImageGenerationsOpenApi,
+    ImageVariationsOpenApi, CONFIDENTIAL_IMAGE_GENERATIONS_PATH, IMAGE_EDITS_PATH,
+    IMAGE_GENERATIONS_PATH, IMAGE_VARIATIONS_PATH,
+};
+use crate::server::{
+    HealthOpenApi, MetricsOpenApi, ReadyOpenApi, HEALTH_PATH, METRICS_PATH, READY_PATH,
 };
-use crate::server::{HealthOpenApi, MetricsOpenApi, HEALTH_PATH, METRICS_PATH};
 
 pub fn openapi_routes() -> Router {
     #[derive(OpenApi)]
     #[openapi(
         nest(
             (path = HEALTH_PATH, api = HealthOpenApi),
+            (path = READY_PATH, api = ReadyOpenApi),
             (path = METRICS_PATH, api = MetricsOpenApi),
             (path = CHAT_COMPLETIONS_PATH, api = ChatCompletionsOpenApi),
             (path = EMBEDDINGS_PATH, api = EmbeddingsOpenApi),
             (path = IMAGE_GENERATIONS_PATH, api = ImageGenerationsOpenApi),
+            (path = IMAGE_EDITS_PATH, api = ImageEditsOpenApi),
+            (path = IMAGE_VARIATIONS_PATH, api = ImageVariationsOpenApi),
             (path = CONFIDENTIAL_IMAGE_GENERATIONS_PATH, api = ConfidentialImageGenerationsOpenApi),
             (path = CONFIDENTIAL_EMBEDDINGS_PATH, api = ConfidentialEmbeddingsOpenApi),
             (path = CONFIDENTIAL_CHAT_COMPLETIONS_PATH, api = ConfidentialChatCompletionsOpenApi),
+            (path = BATCHES_PATH, api = BatchesOpenApi),
         ),
         tags(
             (name = "health", description = "Health check"),
@@ -37,6 +45,7 @@ pub fn openapi_routes() -> Router {
             (name = "confidential-images", description = "Confidential image generations"),
             (name = "confidential-embeddings", description = "Confidential embeddings"),
             (name = "confidential-chat", description = "Confidential chat completions"),
+            (name = "batches", description = "Batch inference"),
         ),
         servers(
             (url = "http://localhost:8080"),
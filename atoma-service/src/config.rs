@@ -1,6 +1,6 @@
 use std::{collections::HashMap, path::Path};
 
-use config::{Config, File};
+use config::{Config, ConfigError, File};
 use serde::Deserialize;
 
 /// Configuration for the Atoma Service.
@@ -44,6 +44,375 @@ pub struct AtomaServiceConfig {
     ///
     /// This field specifies the address and port on which the Atoma Service will bind.
     pub service_bind_address: String,
+
+    /// Configuration for the TLS termination of the Atoma Service.
+    ///
+    /// This is an optional section, if not provided, the service is served over plain HTTP
+    /// (e.g. behind a reverse proxy terminating TLS).
+    pub tls: Option<TlsConfig>,
+
+    /// Configuration for the background processing of asynchronous batches.
+    ///
+    /// This is an optional section, if not provided, default values are used.
+    #[serde(default)]
+    pub batch_processor: BatchProcessorConfig,
+
+    /// Configuration for the built-in request filters.
+    ///
+    /// This is an optional section, if not provided, no filter is applied.
+    #[serde(default)]
+    pub filters: FiltersConfig,
+
+    /// Configuration for the synchronization of the served models with the node's
+    /// on-chain subscriptions.
+    ///
+    /// This is an optional section, if not provided, default values are used.
+    #[serde(default)]
+    pub model_sync: ModelSyncConfig,
+
+    /// Configuration for the enforcement of the models' maximum context lengths.
+    ///
+    /// This is an optional section, if not provided, requests exceeding the maximum context
+    /// length of a model are rejected.
+    #[serde(default)]
+    pub context_length: ContextLengthConfig,
+
+    /// Configuration for resuming streamed chat completions after the client connection drops.
+    ///
+    /// This is an optional section, if not provided, default values are used.
+    #[serde(default)]
+    pub resumable_streams: ResumableStreamsConfig,
+
+    /// Configuration for the validation and pricing of image requests.
+    ///
+    /// This is an optional section, if not provided, default values are used.
+    #[serde(default)]
+    pub image_generations: ImageGenerationsConfig,
+
+    /// Configuration for the draining of in-flight requests when the node shuts down.
+    ///
+    /// This is an optional section, if not provided, default values are used.
+    #[serde(default)]
+    pub drain: DrainConfig,
+
+    /// Configuration for the readiness checks of the node.
+    ///
+    /// This is an optional section, if not provided, default values are used.
+    #[serde(default)]
+    pub readiness: ReadinessConfig,
+
+    /// Configuration for sharing the in-flight requests and stream stop signals with other
+    /// replicas of the Atoma Service.
+    ///
+    /// This is an optional section, if not provided, the state is not shared.
+    #[serde(default)]
+    pub replication: ReplicationConfig,
+}
+
+/// Configuration for the TLS termination of the Atoma Service.
+///
+/// The certificate and private key files are watched, so that renewed certificates are
+/// served without restarting the node.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// Path to the PEM-encoded certificate chain.
+    pub cert_path: String,
+
+    /// Path to the PEM-encoded private key.
+    pub key_path: String,
+
+    /// Whether HTTP/2 is offered to clients, through ALPN (otherwise only HTTP/1.1 is served).
+    pub enable_http2: bool,
+
+    /// Interval, in milliseconds, between two checks for changes of the certificate and
+    /// private key files.
+    pub reload_interval_ms: u64,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            cert_path: String::new(),
+            key_path: String::new(),
+            enable_http2: false,
+            reload_interval_ms: 60_000,
+        }
+    }
+}
+
+/// What to do with chat completions requests whose prompt tokens plus `max_tokens` exceed the
+/// maximum context length of the model.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextLengthPolicy {
+    /// Reject the request.
+    #[default]
+    Reject,
+    /// Lower `max_tokens` to the number of tokens left in the context window.
+    Clamp,
+}
+
+/// Configuration for the enforcement of the models' maximum context lengths.
+///
+/// The maximum context length of a model is read from its HuggingFace configuration
+/// (`max_model_len` or `max_position_embeddings`), next to its tokenizer, unless it is
+/// overridden here. Requests exceeding it are handled before compute units are locked.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ContextLengthConfig {
+    /// Policy applied to models without a policy in `policies`.
+    pub policy: ContextLengthPolicy,
+
+    /// Maximum context length, per model (takes precedence over the HuggingFace configuration).
+    pub max_context_lengths: HashMap<String, u64>,
+
+    /// Policy, per model.
+    pub policies: HashMap<String, ContextLengthPolicy>,
+}
+
+/// Configuration for resumable streamed chat completions.
+///
+/// The most recent chunks of each stream are buffered, so that a client whose connection
+/// drops can reconnect with the `Last-Event-ID` header and receive the chunks it missed.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ResumableStreamsConfig {
+    /// Whether streamed chat completions can be resumed.
+    pub enabled: bool,
+
+    /// Maximum number of chunks buffered per stream.
+    pub buffer_size: usize,
+
+    /// Grace period, in milliseconds, during which a stream whose client disconnected keeps
+    /// being generated, waiting for the client to reconnect.
+    pub grace_period_ms: u64,
+}
+
+impl Default for ResumableStreamsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            buffer_size: 1_024,
+            grace_period_ms: 30_000,
+        }
+    }
+}
+
+/// Configuration for the validation and pricing of image generations, edits and variations
+/// requests.
+///
+/// The compute units of an image request are `n * width * height`, scaled by the number of
+/// diffusion steps of the request relative to `default_steps`. Requests set the number of steps
+/// either directly, with `steps`, or through `quality`. The resolved number of steps is always
+/// forwarded to the image generations service, so that it matches the price of the request.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ImageGenerationsConfig {
+    /// Maximum number of pixels (`width * height`) of a generated image.
+    pub max_pixels: u64,
+
+    /// Sizes (in the `WIDTHxHEIGHT` format) allowed, per model. Models without allowed sizes
+    /// accept any size within `max_pixels`.
+    pub allowed_sizes: HashMap<String, Vec<String>>,
+
+    /// Number of steps of requests setting neither `steps` nor `quality`.
+    pub default_steps: u64,
+
+    /// Maximum number of steps a request can set.
+    pub max_steps: u64,
+
+    /// Number of steps, per `quality`.
+    pub quality_steps: HashMap<String, u64>,
+}
+
+impl Default for ImageGenerationsConfig {
+    fn default() -> Self {
+        Self {
+            max_pixels: 2_048 * 2_048,
+            allowed_sizes: HashMap::new(),
+            default_steps: 25,
+            max_steps: 100,
+            quality_steps: HashMap::from([("standard".to_string(), 25), ("hd".to_string(), 50)]),
+        }
+    }
+}
+
+/// Configuration for the draining of the Atoma Service on shutdown.
+///
+/// When the node is asked to shut down, the service stops accepting new requests (and its
+/// health endpoint reports it as unavailable), and waits for the in-flight requests to finish
+/// before the node stops.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct DrainConfig {
+    /// Maximum time, in milliseconds, to wait for the in-flight requests to finish.
+    pub timeout_ms: u64,
+}
+
+impl Default for DrainConfig {
+    fn default() -> Self {
+        Self { timeout_ms: 60_000 }
+    }
+}
+
+/// Configuration for sharing state between replicas of the Atoma Service.
+///
+/// When enabled, the number of requests in flight for each stack and the stop signals of
+/// streams are shared, through Postgres, with the other replicas running against the same
+/// database, so that several replicas can serve requests behind a load balancer.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ReplicationConfig {
+    /// Whether the state is shared with other replicas.
+    pub enabled: bool,
+
+    /// Unique identifier of the replica. The requests in flight left by a replica that crashed
+    /// are cleared when a replica with the same identifier starts, or once their lease expires.
+    ///
+    /// Defaults to the `HOSTNAME` environment variable, if empty.
+    pub replica_id: String,
+
+    /// Time, in milliseconds, during which stop signals received from other replicas are kept
+    /// for streams that are not served by this replica.
+    pub stop_signal_ttl_ms: u64,
+
+    /// Time, in milliseconds, after which the requests in flight of a replica that stopped
+    /// refreshing them (e.g. because it crashed or was scaled down) are dropped. Replicas
+    /// refresh their requests in flight three times per lease.
+    pub concurrent_requests_lease_ms: u64,
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            replica_id: String::new(),
+            stop_signal_ttl_ms: 60_000,
+            concurrent_requests_lease_ms: 30_000,
+        }
+    }
+}
+
+/// Configuration for the readiness checks of the node, served on the `/ready` endpoint.
+///
+/// The node is ready when its database and Sui RPC node are reachable, its Sui events
+/// subscriber is synced, each of its models has an available inference service, and it is
+/// connected to enough peers.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ReadinessConfig {
+    /// Timeout, in milliseconds, of each readiness check.
+    pub check_timeout_ms: u64,
+
+    /// Maximum time, in milliseconds, since the Sui events subscriber last read all the
+    /// available events.
+    pub max_subscriber_lag_ms: u64,
+
+    /// Minimum number of peers the node must be connected to.
+    pub min_peers: usize,
+
+    /// Whether the node is only ready if confidential computing is supported.
+    pub require_confidential_compute: bool,
+}
+
+impl Default for ReadinessConfig {
+    fn default() -> Self {
+        Self {
+            check_timeout_ms: 2_000,
+            max_subscriber_lag_ms: 60_000,
+            min_peers: 0,
+            require_confidential_compute: false,
+        }
+    }
+}
+
+/// Configuration for the synchronization of the served models with the node's on-chain
+/// subscriptions.
+///
+/// When the node subscribes to a model it does not serve yet, the model is onboarded if one
+/// of the configured chat completions services serves it. When all the tasks of a served
+/// model are deprecated or removed, new requests for the model are rejected.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ModelSyncConfig {
+    /// Whether the served models follow the node's on-chain subscriptions.
+    pub enabled: bool,
+
+    /// Interval, in milliseconds, between two synchronizations.
+    pub poll_interval_ms: u64,
+
+    /// Revision of the tokenizers fetched for onboarded models.
+    pub revision: String,
+
+    /// Timeout, in milliseconds, for the chat completions services to list the models
+    /// they serve.
+    pub request_timeout_ms: u64,
+}
+
+impl Default for ModelSyncConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            poll_interval_ms: 30_000,
+            revision: "main".to_string(),
+            request_timeout_ms: 5_000,
+        }
+    }
+}
+
+/// Configuration for the built-in request filters.
+///
+/// Filters are applied to inference requests after the stack permissions are verified and
+/// before requests are forwarded to the inference services. A filter is only enabled if at
+/// least one of its settings is set.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FiltersConfig {
+    /// If not empty, only these Sui addresses are allowed to use the node.
+    pub allowed_addresses: Vec<String>,
+
+    /// Sui addresses that are not allowed to use the node.
+    pub denied_addresses: Vec<String>,
+
+    /// Models that each Sui address is not allowed to use.
+    pub denied_models: HashMap<String, Vec<String>>,
+
+    /// Maximum number of prompt tokens of chat completions and embeddings requests.
+    pub max_prompt_tokens: Option<u64>,
+
+    /// Maximum number of prompt tokens, per model (takes precedence over `max_prompt_tokens`).
+    pub max_prompt_tokens_per_model: HashMap<String, u64>,
+}
+
+/// Configuration for the background processing of asynchronous batches (`/v1/batches`).
+///
+/// Batch requests are processed with lower priority than interactive traffic: the batch
+/// processor only dispatches new batch requests while the number of interactive requests
+/// in flight is below `max_interactive_requests`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct BatchProcessorConfig {
+    /// Maximum number of batch requests processed concurrently.
+    pub max_concurrent_requests: usize,
+
+    /// Number of interactive requests in flight (across all stacks) from which
+    /// batch processing is paused.
+    pub max_interactive_requests: u64,
+
+    /// Interval, in milliseconds, between polls for pending batch requests, when there
+    /// is no work to do or batch processing is paused.
+    pub poll_interval_ms: u64,
+}
+
+impl Default for BatchProcessorConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_requests: 4,
+            max_interactive_requests: 32,
+            poll_interval_ms: 1_000,
+        }
+    }
 }
 
 impl AtomaServiceConfig {
@@ -66,18 +435,31 @@ impl AtomaServiceConfig {
     /// * The "atoma-service" section is missing from the configuration
     /// * The configuration format doesn't match the expected structure
     pub fn from_file_path<P: AsRef<Path>>(config_file_path: P) -> Self {
-        let builder = Config::builder()
-            .add_source(File::with_name(config_file_path.as_ref().to_str().unwrap()))
+        Self::try_from_file_path(config_file_path)
+            .expect("Failed to generate atoma-service configuration")
+    }
+
+    /// Creates a new `AtomaServiceConfig` instance from a configuration file, without panicking.
+    ///
+    /// This is used to reload the configuration while the node is running, in which case an
+    /// invalid configuration file must not bring the node down.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration file cannot be read or parsed, or if its
+    /// "atoma-service" section is missing or doesn't match the expected structure.
+    pub fn try_from_file_path<P: AsRef<Path>>(config_file_path: P) -> Result<Self, ConfigError> {
+        let config_file_path = config_file_path.as_ref().to_str().ok_or_else(|| {
+            ConfigError::Message("Configuration file path is not valid UTF-8".to_string())
+        })?;
+        Config::builder()
+            .add_source(File::with_name(config_file_path))
             .add_source(
                 config::Environment::with_prefix("ATOMA_SERVICE")
                     .keep_prefix(true)
                     .separator("__"),
-            );
-        let config = builder
-            .build()
-            .expect("Failed to generate atoma-service configuration file");
-        config
+            )
+            .build()?
             .get::<Self>("atoma_service")
-            .expect("Failed to generate configuration instance")
     }
 }
@@ -0,0 +1,180 @@
+//! Draining of the Atoma Service on shutdown.
+//!
+//! When the node is asked to shut down, the service first stops accepting new requests (which
+//! are rejected with a `503 Service Unavailable`, as is the health endpoint), and then waits
+//! for the in-flight requests to finish, so that streams are not cut and the final compute
+//! units and stack hash updates of each request reach the state manager.
+//!
+//! Work outliving the HTTP response of its request is tracked as well: the generations of
+//! resumable streams, which go on while their client is disconnected, and the batch requests
+//! being processed (no new batch request is started while draining).
+
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::body::{Body, Bytes};
+use hyper::body::{Body as HttpBody, Frame, SizeHint};
+use tokio::sync::Notify;
+
+#[derive(Debug, Default)]
+struct DrainInner {
+    /// Whether the service is draining, i.e. no longer accepts new requests
+    draining: AtomicBool,
+    /// Number of requests currently being served
+    in_flight: AtomicUsize,
+    /// Notified when the last in-flight request finishes
+    idle: Notify,
+}
+
+/// Tracks the in-flight requests of the service, and whether it is draining.
+///
+/// Cloning a [`Drain`] returns a handle to the same state.
+#[derive(Clone, Debug, Default)]
+pub struct Drain {
+    inner: Arc<DrainInner>,
+}
+
+impl Drain {
+    /// Starts draining the service: new requests are rejected from now on.
+    pub fn start(&self) {
+        self.inner.draining.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the service is draining
+    #[must_use]
+    pub fn is_draining(&self) -> bool {
+        self.inner.draining.load(Ordering::SeqCst)
+    }
+
+    /// Number of requests currently being served
+    #[must_use]
+    pub fn in_flight_requests(&self) -> usize {
+        self.inner.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Starts tracking a request, which is counted as in-flight until the returned guard is
+    /// dropped.
+    #[must_use]
+    pub fn track(&self) -> InFlightRequest {
+        self.inner.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlightRequest {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+
+    /// Waits for all the in-flight requests to finish, for at most `timeout`.
+    ///
+    /// Returns `true` if there are no more in-flight requests, `false` if the timeout elapsed
+    /// first.
+    pub async fn wait_idle(&self, timeout: Duration) -> bool {
+        tokio::time::timeout(timeout, async {
+            loop {
+                // NOTE: The notification is registered before checking the number of in-flight
+                // requests, so that a request finishing in between is not missed
+                let idle = self.inner.idle.notified();
+                if self.in_flight_requests() == 0 {
+                    return;
+                }
+                idle.await;
+            }
+        })
+        .await
+        .is_ok()
+    }
+}
+
+/// An in-flight request, counted until it is dropped.
+#[derive(Debug)]
+pub struct InFlightRequest {
+    inner: Arc<DrainInner>,
+}
+
+impl InFlightRequest {
+    /// Holds the request in-flight until the response body is fully sent (or the client
+    /// disconnects), so that streamed responses are waited for when draining.
+    #[must_use]
+    pub fn attach(self, body: Body) -> Body {
+        Body::new(InFlightBody {
+            inner: body,
+            _request: self,
+        })
+    }
+}
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        if self.inner.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner.idle.notify_waiters();
+        }
+    }
+}
+
+/// A response body holding its request in-flight until it is dropped
+struct InFlightBody {
+    inner: Body,
+    _request: InFlightRequest,
+}
+
+impl HttpBody for InFlightBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_wait_idle() {
+        let drain = Drain::default();
+        assert!(drain.wait_idle(Duration::from_millis(10)).await);
+
+        let request = drain.track();
+        drain.start();
+        assert!(drain.is_draining());
+        assert_eq!(drain.in_flight_requests(), 1);
+        assert!(!drain.wait_idle(Duration::from_millis(10)).await);
+
+        let waiter = tokio::spawn({
+            let drain = drain.clone();
+            async move { drain.wait_idle(Duration::from_secs(5)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop(request);
+        assert!(waiter.await.unwrap());
+        assert_eq!(drain.in_flight_requests(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_body() {
+        let drain = Drain::default();
+        let body = drain.track().attach(Body::from("Hello"));
+        assert_eq!(body.size_hint().exact(), Some(5));
+        assert_eq!(drain.in_flight_requests(), 1);
+        let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        assert_eq!(bytes, "Hello");
+        assert_eq!(drain.in_flight_requests(), 0);
+    }
+}
@@ -20,13 +20,27 @@ pub struct ErrorResponse {
 ///
 /// This struct contains the specific details of an error that occurred during
 /// API request processing. It is wrapped in [`ErrorResponse`] to maintain a
-/// consistent JSON structure.
+/// consistent JSON structure, and follows the OpenAI error object format, so that
+/// OpenAI SDKs can parse it (and branch their retry logic on `type` and `code`):
+///
+/// ```json
+/// {"error": {"message": "...", "type": "invalid_request_error", "param": "model", "code": "invalid_value", "legacy_code": "INVALID_BODY"}}
+/// ```
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ErrorDetails {
-    /// A machine-readable error code string (e.g., "MISSING_HEADER", "AUTH_ERROR")
-    pub code: String,
     /// A human-readable error message describing what went wrong
     pub message: String,
+    /// The error category, following the OpenAI error taxonomy (e.g. "invalid_request_error",
+    /// "insufficient_quota", "server_error")
+    #[serde(rename = "type")]
+    pub error_type: String,
+    /// The request parameter the error relates to, if any
+    pub param: Option<String>,
+    /// A stable, machine-readable error code string (e.g., "missing_header", "insufficient_quota")
+    pub code: String,
+    /// The machine-readable error code used before the OpenAI error object format was adopted
+    /// (e.g., "MISSING_HEADER", "AUTH_ERROR"), kept for clients that still match on it
+    pub legacy_code: String,
 }
 
 /// Represents all possible errors that can occur within the Atoma service
@@ -63,6 +77,17 @@ pub enum AtomaServiceError {
         endpoint: String,
     },
 
+    /// Error returned when a specific parameter of the request body is missing or invalid
+    #[error("Invalid parameter `{param}`: {message}")]
+    InvalidParameter {
+        /// The name of the invalid parameter
+        param: String,
+        /// Description of why the parameter is invalid
+        message: String,
+        /// The endpoint that the error occurred on
+        endpoint: String,
+    },
+
     /// Error returned when the underlying ML model encounters an error
     #[error("Model error: {model_error}")]
     ModelError {
@@ -116,6 +141,43 @@ pub enum AtomaServiceError {
         /// The endpoint that the error occurred on
         endpoint: String,
     },
+
+    /// Error returned when a request (or its response) is rejected by an operator filter
+    #[error("Request rejected by filter {filter}: {message}")]
+    RequestRejected {
+        /// The name of the filter that rejected the request
+        filter: String,
+        /// Description of why the request was rejected
+        message: String,
+        /// The endpoint that the error occurred on
+        endpoint: String,
+    },
+
+    /// Error returned when a requested resource (e.g. a batch) does not exist
+    #[error("Not found: {message}")]
+    NotFound {
+        /// Description of the resource that was not found
+        message: String,
+        /// The endpoint that the error occurred on
+        endpoint: String,
+    },
+
+    /// Error returned when a request conflicts with a request in progress (e.g. it reuses the
+    /// request ID of a resumable stream)
+    #[error("Conflict: {message}")]
+    Conflict {
+        /// Description of the conflict
+        message: String,
+        /// The endpoint that the error occurred on
+        endpoint: String,
+    },
+
+    /// Error returned when the node is shutting down, and no longer accepts new requests
+    #[error("Service is shutting down")]
+    ShuttingDown {
+        /// The endpoint that the error occurred on
+        endpoint: String,
+    },
 }
 
 impl AtomaServiceError {
@@ -128,17 +190,50 @@ impl AtomaServiceError {
     /// # Returns
     ///
     /// A static string representing the error code, such as:
-    /// - `"MISSING_HEADER"` for missing required headers
-    /// - `"INVALID_HEADER"` for invalid header values
-    /// - `"INVALID_BODY"` for malformed request bodies
-    /// - `"MODEL_ERROR"` for ML model errors
-    /// - `"AUTH_ERROR"` for authentication failures
-    /// - `"INTERNAL_ERROR"` for unexpected server errors
+    /// - `"missing_header"` for missing required headers
+    /// - `"invalid_header"` for invalid header values
+    /// - `"invalid_body"` for malformed request bodies
+    /// - `"invalid_value"` for invalid request body parameters
+    /// - `"model_error"` for ML model errors
+    /// - `"authentication_failed"` for authentication failures
+    /// - `"insufficient_quota"` for stacks without enough compute units left
+    /// - `"internal_error"` for unexpected server errors
+    /// - `"conflict"` for requests conflicting with a request in progress
+    /// - `"service_unavailable"` for requests received while the node is shutting down
     const fn error_code(&self) -> &'static str {
+        match self {
+            Self::MissingHeader { .. } => "missing_header",
+            Self::InvalidHeader { .. } => "invalid_header",
+            Self::InvalidBody { .. } => "invalid_body",
+            Self::InvalidParameter { .. } => "invalid_value",
+            Self::ModelError { .. } => "model_error",
+            Self::AuthError { .. } => "authentication_failed",
+            Self::InternalError { .. } => "internal_error",
+            Self::LockedStackError { .. } => "stack_locked",
+            Self::UnavailableStackError { .. } => "insufficient_quota",
+            Self::ChatCompletionsServiceUnavailable { .. } => "service_overloaded",
+            Self::RequestRejected { .. } => "request_rejected",
+            Self::NotFound { .. } => "not_found",
+            Self::Conflict { .. } => "conflict",
+            Self::ShuttingDown { .. } => "service_unavailable",
+        }
+    }
+
+    /// Returns the error code used before the OpenAI error object format was adopted
+    ///
+    /// Older clients match on these codes, so they are still sent along the new ones, in the
+    /// `legacy_code` field of the error object. Invalid request body parameters used to be
+    /// reported as invalid bodies.
+    ///
+    /// # Returns
+    ///
+    /// A static string representing the legacy error code, such as `"MISSING_HEADER"` or
+    /// `"AUTH_ERROR"`
+    const fn legacy_error_code(&self) -> &'static str {
         match self {
             Self::MissingHeader { .. } => "MISSING_HEADER",
             Self::InvalidHeader { .. } => "INVALID_HEADER",
-            Self::InvalidBody { .. } => "INVALID_BODY",
+            Self::InvalidBody { .. } | Self::InvalidParameter { .. } => "INVALID_BODY",
             Self::ModelError { .. } => "MODEL_ERROR",
             Self::AuthError { .. } => "AUTH_ERROR",
             Self::InternalError { .. } => "INTERNAL_ERROR",
@@ -147,6 +242,56 @@ impl AtomaServiceError {
             Self::ChatCompletionsServiceUnavailable { .. } => {
                 "CHAT_COMPLETIONS_SERVICE_UNAVAILABLE"
             }
+            Self::RequestRejected { .. } => "REQUEST_REJECTED",
+            Self::NotFound { .. } => "NOT_FOUND",
+            Self::Conflict { .. } => "CONFLICT",
+            Self::ShuttingDown { .. } => "SERVICE_UNAVAILABLE",
+        }
+    }
+
+    /// Returns the error type, following the OpenAI error taxonomy
+    ///
+    /// OpenAI SDKs (and their users' retry logic) rely on the error type to decide whether
+    /// a request should be retried:
+    /// - `"invalid_request_error"` for invalid requests, which must not be retried as is
+    /// - `"authentication_error"` for authentication failures
+    /// - `"permission_error"` for requests rejected by the node policies
+    /// - `"not_found_error"` for requests to unknown resources
+    /// - `"insufficient_quota"` for stacks that are exhausted or locked, for which the client
+    ///   needs to buy a new stack (retrying does not help)
+    /// - `"rate_limit_error"` for transient overload of the inference services
+    /// - `"server_error"` for unexpected server errors
+    ///
+    /// # Returns
+    ///
+    /// A static string representing the error type
+    const fn error_type(&self) -> &'static str {
+        match self {
+            Self::MissingHeader { .. }
+            | Self::InvalidHeader { .. }
+            | Self::InvalidBody { .. }
+            | Self::InvalidParameter { .. }
+            | Self::ModelError { .. }
+            | Self::Conflict { .. } => "invalid_request_error",
+            Self::AuthError { .. } => "authentication_error",
+            Self::RequestRejected { .. } => "permission_error",
+            Self::NotFound { .. } => "not_found_error",
+            Self::LockedStackError { .. } | Self::UnavailableStackError { .. } => {
+                "insufficient_quota"
+            }
+            Self::ChatCompletionsServiceUnavailable { .. } => "rate_limit_error",
+            Self::InternalError { .. } | Self::ShuttingDown { .. } => "server_error",
+        }
+    }
+
+    /// Returns the request parameter the error relates to, if any
+    ///
+    /// For missing headers, this is the name of the missing header.
+    fn param(&self) -> Option<String> {
+        match self {
+            Self::InvalidParameter { param, .. } => Some(param.clone()),
+            Self::MissingHeader { header, .. } => Some(header.clone()),
+            _ => None,
         }
     }
 
@@ -162,6 +307,7 @@ impl AtomaServiceError {
     /// - For missing headers: Specifies which header is missing
     /// - For invalid headers: A generic invalid header message
     /// - For invalid body: Includes the specific validation error
+    /// - For invalid parameters: Includes the parameter name and the validation error
     /// - For model errors: Includes the model-specific error message
     /// - For auth errors: A generic authentication failure message
     /// - For internal errors: A generic server error message
@@ -170,6 +316,9 @@ impl AtomaServiceError {
             Self::MissingHeader { header, .. } => format!("Missing required header: {}", header),
             Self::InvalidHeader { .. } => "Invalid header value provided".to_string(),
             Self::InvalidBody { message, .. } => format!("Invalid request body: {}", message),
+            Self::InvalidParameter { param, message, .. } => {
+                format!("Invalid parameter `{}`: {}", param, message)
+            }
             Self::ModelError { model_error, .. } => format!("Model error: {}", model_error),
             Self::AuthError { .. } => "Authentication failed".to_string(),
             Self::InternalError { .. } => "Internal server error occurred".to_string(),
@@ -178,15 +327,39 @@ impl AtomaServiceError {
             Self::ChatCompletionsServiceUnavailable { .. } => {
                 "Chat completions service is unavailable".to_string()
             }
+            Self::RequestRejected {
+                filter, message, ..
+            } => format!("Request rejected by filter {}: {}", filter, message),
+            Self::NotFound { message, .. } => format!("Not found: {}", message),
+            Self::Conflict { message, .. } => format!("Conflict: {}", message),
+            Self::ShuttingDown { .. } => "Service is shutting down".to_string(),
+        }
+    }
+
+    /// Returns the error object sent to clients, following the OpenAI error object format
+    #[must_use]
+    pub fn error_response(&self) -> ErrorResponse {
+        ErrorResponse {
+            error: ErrorDetails {
+                message: self.client_message(),
+                error_type: self.error_type().to_string(),
+                param: self.param(),
+                code: self.error_code().to_string(),
+                legacy_code: self.legacy_error_code().to_string(),
+            },
         }
     }
 
     /// Returns the HTTP status code associated with this error
     ///
     /// Maps each error variant to an appropriate HTTP status code:
-    /// - `400 Bad Request` for invalid inputs (missing/invalid headers, invalid body, model errors)
+    /// - `400 Bad Request` for invalid inputs (missing/invalid headers, invalid body or
+    ///   parameters, model errors)
     /// - `401 Unauthorized` for authentication failures
+    /// - `403 Forbidden` for requests rejected by operator filters
+    /// - `409 Conflict` for requests conflicting with a request in progress
     /// - `500 Internal Server Error` for unexpected server errors
+    /// - `503 Service Unavailable` for requests received while the node is shutting down
     ///
     /// # Returns
     ///
@@ -197,12 +370,17 @@ impl AtomaServiceError {
             Self::MissingHeader { .. }
             | Self::InvalidHeader { .. }
             | Self::InvalidBody { .. }
+            | Self::InvalidParameter { .. }
             | Self::ModelError { .. } => StatusCode::BAD_REQUEST,
             Self::AuthError { .. } => StatusCode::UNAUTHORIZED,
             Self::InternalError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             Self::LockedStackError { .. } => StatusCode::LOCKED,
             Self::UnavailableStackError { .. } => StatusCode::TOO_EARLY,
             Self::ChatCompletionsServiceUnavailable { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::RequestRejected { .. } => StatusCode::FORBIDDEN,
+            Self::NotFound { .. } => StatusCode::NOT_FOUND,
+            Self::Conflict { .. } => StatusCode::CONFLICT,
+            Self::ShuttingDown { .. } => StatusCode::SERVICE_UNAVAILABLE,
         }
     }
 
@@ -220,12 +398,17 @@ impl AtomaServiceError {
             Self::MissingHeader { endpoint, .. }
             | Self::InvalidHeader { endpoint, .. }
             | Self::InvalidBody { endpoint, .. }
+            | Self::InvalidParameter { endpoint, .. }
             | Self::ModelError { endpoint, .. }
             | Self::AuthError { endpoint, .. }
             | Self::InternalError { endpoint, .. }
             | Self::LockedStackError { endpoint, .. }
             | Self::UnavailableStackError { endpoint, .. }
-            | Self::ChatCompletionsServiceUnavailable { endpoint, .. } => endpoint.clone(),
+            | Self::ChatCompletionsServiceUnavailable { endpoint, .. }
+            | Self::RequestRejected { endpoint, .. }
+            | Self::NotFound { endpoint, .. }
+            | Self::Conflict { endpoint, .. }
+            | Self::ShuttingDown { endpoint } => endpoint.clone(),
         }
     }
 
@@ -249,6 +432,9 @@ impl AtomaServiceError {
             Self::MissingHeader { header, .. } => format!("Missing required header: {}", header),
             Self::InvalidHeader { message, .. } => format!("Invalid header value: {}", message),
             Self::InvalidBody { message, .. } => format!("Invalid request body: {}", message),
+            Self::InvalidParameter { param, message, .. } => {
+                format!("Invalid parameter `{}`: {}", param, message)
+            }
             Self::ModelError { model_error, .. } => format!("Model error: {}", model_error),
             Self::AuthError { auth_error, .. } => format!("Authentication error: {}", auth_error),
             Self::InternalError { message, .. } => format!("Internal server error: {}", message),
@@ -259,6 +445,12 @@ impl AtomaServiceError {
             Self::ChatCompletionsServiceUnavailable { message, .. } => {
                 format!("Chat completions service is unavailable: {}", message)
             }
+            Self::RequestRejected {
+                filter, message, ..
+            } => format!("Request rejected by filter {}: {}", filter, message),
+            Self::NotFound { message, .. } => format!("Not found: {}", message),
+            Self::Conflict { message, .. } => format!("Conflict: {}", message),
+            Self::ShuttingDown { .. } => "Service is shutting down".to_string(),
         }
     }
 }
@@ -271,12 +463,250 @@ impl IntoResponse for AtomaServiceError {
             endpoint = self.get_endpoint(""),
             error = %self.message(),
         );
-        let error_response = ErrorResponse {
-            error: ErrorDetails {
-                code: self.error_code().to_string(),
-                message: self.client_message(),
-            },
-        };
-        (self.status_code(), Json(error_response)).into_response()
+        (self.status_code(), Json(self.error_response())).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use sui_sdk::types::digests::TransactionDigest;
+
+    use super::*;
+    use crate::middleware::utils::check_stack_query_result;
+
+    const ENDPOINT: &str = "/v1/chat/completions";
+
+    async fn error_body(error: AtomaServiceError) -> (StatusCode, Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), 1024)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_stack_is_insufficient_quota() {
+        let (status, body) = error_body(AtomaServiceError::UnavailableStackError {
+            message: "Stack 1 has not enough compute units".to_string(),
+            endpoint: ENDPOINT.to_string(),
+        })
+        .await;
+        assert_eq!(status, StatusCode::TOO_EARLY);
+        assert_eq!(
+            body,
+            json!({
+                "error": {
+                    "message": "Stack is unavailable",
+                    "type": "insufficient_quota",
+                    "param": null,
+                    "code": "insufficient_quota",
+                    "legacy_code": "UNAVAILABLE_STACK_ERROR",
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tx_digest_stack_without_enough_compute_units_is_insufficient_quota() {
+        let tx_digest = TransactionDigest::new([1; 32]);
+        let error =
+            check_stack_query_result((Some(1), None), tx_digest, ENDPOINT.to_string()).unwrap_err();
+        let (status, body) = error_body(error).await;
+        assert_eq!(status, StatusCode::TOO_EARLY);
+        assert_eq!(
+            body,
+            json!({
+                "error": {
+                    "message": "Stack is unavailable",
+                    "type": "insufficient_quota",
+                    "param": null,
+                    "code": "insufficient_quota",
+                    "legacy_code": "UNAVAILABLE_STACK_ERROR",
+                }
+            })
+        );
+
+        let error =
+            check_stack_query_result((None, None), tx_digest, ENDPOINT.to_string()).unwrap_err();
+        let (status, body) = error_body(error).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"]["type"], "authentication_error");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_parameter_includes_param() {
+        let (status, body) = error_body(AtomaServiceError::InvalidParameter {
+            param: "model".to_string(),
+            message: "Model not supported".to_string(),
+            endpoint: ENDPOINT.to_string(),
+        })
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body,
+            json!({
+                "error": {
+                    "message": "Invalid parameter `model`: Model not supported",
+                    "type": "invalid_request_error",
+                    "param": "model",
+                    "code": "invalid_value",
+                    "legacy_code": "INVALID_BODY",
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_types_and_codes() {
+        let endpoint = ENDPOINT.to_string();
+        let cases = [
+            (
+                AtomaServiceError::MissingHeader {
+                    header: "X-Signature".to_string(),
+                    endpoint: endpoint.clone(),
+                },
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                Some("X-Signature"),
+                "missing_header",
+                "MISSING_HEADER",
+            ),
+            (
+                AtomaServiceError::InvalidHeader {
+                    message: "Invalid signature".to_string(),
+                    endpoint: endpoint.clone(),
+                },
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                None,
+                "invalid_header",
+                "INVALID_HEADER",
+            ),
+            (
+                AtomaServiceError::InvalidBody {
+                    message: "Failed to parse body as JSON".to_string(),
+                    endpoint: endpoint.clone(),
+                },
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                None,
+                "invalid_body",
+                "INVALID_BODY",
+            ),
+            (
+                AtomaServiceError::ModelError {
+                    model_error: "Model failed".to_string(),
+                    endpoint: endpoint.clone(),
+                },
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                None,
+                "model_error",
+                "MODEL_ERROR",
+            ),
+            (
+                AtomaServiceError::AuthError {
+                    auth_error: "Invalid signature".to_string(),
+                    endpoint: endpoint.clone(),
+                },
+                StatusCode::UNAUTHORIZED,
+                "authentication_error",
+                None,
+                "authentication_failed",
+                "AUTH_ERROR",
+            ),
+            (
+                AtomaServiceError::InternalError {
+                    message: "Database error".to_string(),
+                    endpoint: endpoint.clone(),
+                },
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "server_error",
+                None,
+                "internal_error",
+                "INTERNAL_ERROR",
+            ),
+            (
+                AtomaServiceError::LockedStackError {
+                    message: "Stack 1 is locked".to_string(),
+                    endpoint: endpoint.clone(),
+                },
+                StatusCode::LOCKED,
+                "insufficient_quota",
+                None,
+                "stack_locked",
+                "LOCKED_STACK_ERROR",
+            ),
+            (
+                AtomaServiceError::ChatCompletionsServiceUnavailable {
+                    message: "Too many requests".to_string(),
+                    endpoint: endpoint.clone(),
+                },
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate_limit_error",
+                None,
+                "service_overloaded",
+                "CHAT_COMPLETIONS_SERVICE_UNAVAILABLE",
+            ),
+            (
+                AtomaServiceError::RequestRejected {
+                    filter: "access_list".to_string(),
+                    message: "Address denied".to_string(),
+                    endpoint: endpoint.clone(),
+                },
+                StatusCode::FORBIDDEN,
+                "permission_error",
+                None,
+                "request_rejected",
+                "REQUEST_REJECTED",
+            ),
+            (
+                AtomaServiceError::NotFound {
+                    message: "Batch not found".to_string(),
+                    endpoint: endpoint.clone(),
+                },
+                StatusCode::NOT_FOUND,
+                "not_found_error",
+                None,
+                "not_found",
+                "NOT_FOUND",
+            ),
+            (
+                AtomaServiceError::Conflict {
+                    message: "A resumable stream already exists for request ID 1".to_string(),
+                    endpoint: endpoint.clone(),
+                },
+                StatusCode::CONFLICT,
+                "invalid_request_error",
+                None,
+                "conflict",
+                "CONFLICT",
+            ),
+            (
+                AtomaServiceError::ShuttingDown {
+                    endpoint: endpoint.clone(),
+                },
+                StatusCode::SERVICE_UNAVAILABLE,
+                "server_error",
+                None,
+                "service_unavailable",
+                "SERVICE_UNAVAILABLE",
+            ),
+        ];
+        for (error, expected_status, expected_type, expected_param, expected_code, legacy_code) in
+            cases
+        {
+            let (status, body) = error_body(error).await;
+            assert_eq!(status, expected_status);
+            let error = body["error"].as_object().unwrap();
+            assert_eq!(error.len(), 5);
+            assert_eq!(error["type"], expected_type);
+            assert_eq!(error["param"], json!(expected_param));
+            assert_eq!(error["code"], expected_code);
+            assert_eq!(error["legacy_code"], legacy_code);
+            assert!(error["message"].is_string());
+        }
     }
 }
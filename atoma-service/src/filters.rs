@@ -0,0 +1,509 @@
+//! Operator-defined request and response filters.
+//!
+//! Filters let node operators enforce their own policies (allow/deny lists, prompt size caps,
+//! PII redaction, content moderation, etc.) without modifying the request handlers. Request
+//! filters run after the stack permissions have been verified and before the request is
+//! forwarded to the inference service. They can either reject the request or rewrite its body.
+//! The lines of batch requests go through the same request filters when the batch is created.
+//! Response filters run on the response body (and on each chunk of streamed responses),
+//! before it gets signed by the node.
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+use futures::future::BoxFuture;
+use serde_json::Value;
+use sui_sdk::types::base_types::SuiAddress;
+use thiserror::Error;
+use tracing::info;
+
+use crate::{config::FiltersConfig, error::AtomaServiceError, middleware::RequestType};
+
+/// The context of a request, as seen by request filters
+#[derive(Debug)]
+pub struct RequestFilterContext<'a> {
+    /// The Sui address of the requester
+    pub sui_address: &'a SuiAddress,
+    /// The stack the request is paid with
+    pub stack_small_id: i64,
+    /// The requested model
+    pub model: &'a str,
+    /// The type of request
+    pub request_type: RequestType,
+    /// The number of input tokens of the request, as computed by the node
+    pub num_input_tokens: i64,
+    /// The endpoint of the request
+    pub endpoint: &'a str,
+}
+
+/// The context of a response, as seen by response filters
+#[derive(Debug)]
+pub struct ResponseFilterContext<'a> {
+    /// The stack the request is paid with
+    pub stack_small_id: i64,
+    /// The endpoint of the request
+    pub endpoint: &'a str,
+}
+
+/// The reason why a filter rejected a request or a response
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("{0}")]
+pub struct FilterRejection(pub String);
+
+/// A filter applied to requests before they are forwarded to the inference service.
+///
+/// Request filters are asynchronous, so that they can rely on external services
+/// (e.g. a local content moderation classifier).
+pub trait RequestFilter: Send + Sync {
+    /// The name of the filter, reported to clients when the filter rejects a request
+    fn name(&self) -> &str;
+
+    /// Filters a request, either rejecting it or (optionally) rewriting its body.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`FilterRejection`] if the request must not be processed.
+    fn filter_request<'a>(
+        &'a self,
+        context: &'a RequestFilterContext<'a>,
+        body: &'a mut Value,
+    ) -> BoxFuture<'a, Result<(), FilterRejection>>;
+}
+
+/// A filter applied to response bodies, and to each chunk of streamed responses, before
+/// they are signed by the node.
+///
+/// Response filters are synchronous, as they run for every chunk of streamed responses.
+pub trait ResponseFilter: Send + Sync {
+    /// The name of the filter, reported when the filter rejects a response
+    fn name(&self) -> &str;
+
+    /// Filters a response body (or chunk), either rejecting it or (optionally) rewriting it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`FilterRejection`] if the response must not be returned to the client.
+    fn filter_response(
+        &self,
+        context: &ResponseFilterContext<'_>,
+        body: &mut Value,
+    ) -> Result<(), FilterRejection>;
+}
+
+/// The chain of request and response filters of the node.
+///
+/// Filters are applied in the order they were added, and the first rejection stops the chain.
+#[derive(Default)]
+pub struct FilterPipeline {
+    /// The request filters
+    request_filters: Vec<Box<dyn RequestFilter>>,
+    /// The response filters
+    response_filters: Vec<Box<dyn ResponseFilter>>,
+}
+
+impl FilterPipeline {
+    /// Builds the pipeline of built-in filters enabled in the configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration contains an invalid Sui address.
+    pub fn from_config(config: &FiltersConfig) -> anyhow::Result<Self> {
+        let mut pipeline = Self::default();
+        let access_list = AccessListFilter::from_config(config)?;
+        if !access_list.is_empty() {
+            pipeline = pipeline.with_request_filter(access_list);
+        }
+        let max_prompt_tokens = MaxPromptTokensFilter::from_config(config);
+        if !max_prompt_tokens.is_empty() {
+            pipeline = pipeline.with_request_filter(max_prompt_tokens);
+        }
+        info!(
+            target = "atoma-service",
+            level = "info",
+            "Configured {} request filters and {} response filters",
+            pipeline.request_filters.len(),
+            pipeline.response_filters.len()
+        );
+        Ok(pipeline)
+    }
+
+    /// Appends a request filter to the chain
+    #[must_use]
+    pub fn with_request_filter(mut self, filter: impl RequestFilter + 'static) -> Self {
+        self.request_filters.push(Box::new(filter));
+        self
+    }
+
+    /// Appends a response filter to the chain
+    #[must_use]
+    pub fn with_response_filter(mut self, filter: impl ResponseFilter + 'static) -> Self {
+        self.response_filters.push(Box::new(filter));
+        self
+    }
+
+    /// Whether the pipeline has any request filter
+    #[must_use]
+    pub fn has_request_filters(&self) -> bool {
+        !self.request_filters.is_empty()
+    }
+
+    /// Applies the request filters to a request body.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AtomaServiceError::RequestRejected` if any filter rejects the request.
+    pub async fn filter_request(
+        &self,
+        context: &RequestFilterContext<'_>,
+        body: &mut Value,
+    ) -> Result<(), AtomaServiceError> {
+        for filter in &self.request_filters {
+            filter
+                .filter_request(context, body)
+                .await
+                .map_err(
+                    |FilterRejection(message)| AtomaServiceError::RequestRejected {
+                        filter: filter.name().to_string(),
+                        message,
+                        endpoint: context.endpoint.to_string(),
+                    },
+                )?;
+        }
+        Ok(())
+    }
+
+    /// Applies the response filters to a response body (or chunk).
+    ///
+    /// # Errors
+    ///
+    /// Returns `AtomaServiceError::RequestRejected` if any filter rejects the response.
+    pub fn filter_response(
+        &self,
+        context: &ResponseFilterContext<'_>,
+        body: &mut Value,
+    ) -> Result<(), AtomaServiceError> {
+        for filter in &self.response_filters {
+            filter
+                .filter_response(context, body)
+                .map_err(
+                    |FilterRejection(message)| AtomaServiceError::RequestRejected {
+                        filter: filter.name().to_string(),
+                        message,
+                        endpoint: context.endpoint.to_string(),
+                    },
+                )?;
+        }
+        Ok(())
+    }
+}
+
+/// Built-in filter restricting which addresses can use the node, and which models each
+/// address can use.
+#[derive(Debug, Default)]
+pub struct AccessListFilter {
+    /// If not empty, only these addresses are allowed
+    allowed_addresses: HashSet<SuiAddress>,
+    /// Addresses that are not allowed
+    denied_addresses: HashSet<SuiAddress>,
+    /// Models that each address is not allowed to use
+    denied_models: HashMap<SuiAddress, HashSet<String>>,
+}
+
+impl AccessListFilter {
+    /// Builds the filter from the configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration contains an invalid Sui address.
+    pub fn from_config(config: &FiltersConfig) -> anyhow::Result<Self> {
+        let parse_address = |address: &String| {
+            SuiAddress::from_str(address)
+                .map_err(|e| anyhow::anyhow!("Invalid Sui address {address} in filters: {e}"))
+        };
+        Ok(Self {
+            allowed_addresses: config
+                .allowed_addresses
+                .iter()
+                .map(parse_address)
+                .collect::<anyhow::Result<_>>()?,
+            denied_addresses: config
+                .denied_addresses
+                .iter()
+                .map(parse_address)
+                .collect::<anyhow::Result<_>>()?,
+            denied_models: config
+                .denied_models
+                .iter()
+                .map(|(address, models)| {
+                    Ok((
+                        parse_address(address)?,
+                        models.iter().map(|model| model.to_lowercase()).collect(),
+                    ))
+                })
+                .collect::<anyhow::Result<_>>()?,
+        })
+    }
+
+    /// Whether the filter has no rule, in which case it accepts every request
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.allowed_addresses.is_empty()
+            && self.denied_addresses.is_empty()
+            && self.denied_models.is_empty()
+    }
+
+    fn check(&self, context: &RequestFilterContext<'_>) -> Result<(), FilterRejection> {
+        if self.denied_addresses.contains(context.sui_address)
+            || (!self.allowed_addresses.is_empty()
+                && !self.allowed_addresses.contains(context.sui_address))
+        {
+            return Err(FilterRejection(format!(
+                "Address {} is not allowed to use this node",
+                context.sui_address
+            )));
+        }
+        if self
+            .denied_models
+            .get(context.sui_address)
+            .is_some_and(|models| models.contains(&context.model.to_lowercase()))
+        {
+            return Err(FilterRejection(format!(
+                "Address {} is not allowed to use model {}",
+                context.sui_address, context.model
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl RequestFilter for AccessListFilter {
+    fn name(&self) -> &str {
+        "access_list"
+    }
+
+    fn filter_request<'a>(
+        &'a self,
+        context: &'a RequestFilterContext<'a>,
+        _body: &'a mut Value,
+    ) -> BoxFuture<'a, Result<(), FilterRejection>> {
+        Box::pin(futures::future::ready(self.check(context)))
+    }
+}
+
+/// Built-in filter capping the number of prompt tokens of chat completions and
+/// embeddings requests.
+#[derive(Debug, Default)]
+pub struct MaxPromptTokensFilter {
+    /// The maximum number of prompt tokens, for models without a specific limit
+    max_prompt_tokens: Option<i64>,
+    /// The maximum number of prompt tokens, per model
+    max_prompt_tokens_per_model: HashMap<String, i64>,
+}
+
+impl MaxPromptTokensFilter {
+    /// Builds the filter from the configuration
+    #[must_use]
+    pub fn from_config(config: &FiltersConfig) -> Self {
+        Self {
+            max_prompt_tokens: config.max_prompt_tokens.map(|max| max as i64),
+            max_prompt_tokens_per_model: config
+                .max_prompt_tokens_per_model
+                .iter()
+                .map(|(model, max)| (model.to_lowercase(), *max as i64))
+                .collect(),
+        }
+    }
+
+    /// Whether the filter has no limit, in which case it accepts every request
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.max_prompt_tokens.is_none() && self.max_prompt_tokens_per_model.is_empty()
+    }
+
+    fn check(&self, context: &RequestFilterContext<'_>) -> Result<(), FilterRejection> {
+        if !matches!(
+            context.request_type,
+            RequestType::ChatCompletions | RequestType::Embeddings
+        ) {
+            return Ok(());
+        }
+        let max_prompt_tokens = self
+            .max_prompt_tokens_per_model
+            .get(&context.model.to_lowercase())
+            .copied()
+            .or(self.max_prompt_tokens);
+        match max_prompt_tokens {
+            Some(max_prompt_tokens) if context.num_input_tokens > max_prompt_tokens => {
+                Err(FilterRejection(format!(
+                    "Prompt has {} tokens, which exceeds the maximum of {max_prompt_tokens} tokens for model {}",
+                    context.num_input_tokens, context.model
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl RequestFilter for MaxPromptTokensFilter {
+    fn name(&self) -> &str {
+        "max_prompt_tokens"
+    }
+
+    fn filter_request<'a>(
+        &'a self,
+        context: &'a RequestFilterContext<'a>,
+        _body: &'a mut Value,
+    ) -> BoxFuture<'a, Result<(), FilterRejection>> {
+        Box::pin(futures::future::ready(self.check(context)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const ALICE: &str = "0x0000000000000000000000000000000000000000000000000000000000000a11";
+    const BOB: &str = "0x0000000000000000000000000000000000000000000000000000000000000b0b";
+
+    fn context<'a>(
+        sui_address: &'a SuiAddress,
+        model: &'a str,
+        num_input_tokens: i64,
+    ) -> RequestFilterContext<'a> {
+        RequestFilterContext {
+            sui_address,
+            stack_small_id: 1,
+            model,
+            request_type: RequestType::ChatCompletions,
+            num_input_tokens,
+            endpoint: "/v1/chat/completions",
+        }
+    }
+
+    struct RedactFilter;
+
+    impl ResponseFilter for RedactFilter {
+        fn name(&self) -> &str {
+            "redact"
+        }
+
+        fn filter_response(
+            &self,
+            _context: &ResponseFilterContext<'_>,
+            body: &mut Value,
+        ) -> Result<(), FilterRejection> {
+            if body.get("secret").is_some() {
+                body["secret"] = json!("[REDACTED]");
+            }
+            if body.get("forbidden").is_some() {
+                return Err(FilterRejection("Forbidden content".to_string()));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_access_list_filter() {
+        let alice = SuiAddress::from_str(ALICE).unwrap();
+        let bob = SuiAddress::from_str(BOB).unwrap();
+        let config = FiltersConfig {
+            denied_addresses: vec![BOB.to_string()],
+            denied_models: HashMap::from([(ALICE.to_string(), vec!["Blocked/Model".to_string()])]),
+            ..Default::default()
+        };
+        let pipeline = FilterPipeline::from_config(&config).unwrap();
+        let mut body = json!({});
+
+        assert!(pipeline
+            .filter_request(&context(&alice, "allowed/model", 10), &mut body)
+            .await
+            .is_ok());
+        assert!(matches!(
+            pipeline
+                .filter_request(&context(&alice, "blocked/model", 10), &mut body)
+                .await,
+            Err(AtomaServiceError::RequestRejected { filter, .. }) if filter == "access_list"
+        ));
+        assert!(pipeline
+            .filter_request(&context(&bob, "allowed/model", 10), &mut body)
+            .await
+            .is_err());
+
+        let config = FiltersConfig {
+            allowed_addresses: vec![ALICE.to_string()],
+            ..Default::default()
+        };
+        let pipeline = FilterPipeline::from_config(&config).unwrap();
+        assert!(pipeline
+            .filter_request(&context(&alice, "model", 10), &mut body)
+            .await
+            .is_ok());
+        assert!(pipeline
+            .filter_request(&context(&bob, "model", 10), &mut body)
+            .await
+            .is_err());
+
+        let config = FiltersConfig {
+            allowed_addresses: vec!["not an address".to_string()],
+            ..Default::default()
+        };
+        assert!(FilterPipeline::from_config(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_max_prompt_tokens_filter() {
+        let alice = SuiAddress::from_str(ALICE).unwrap();
+        let config = FiltersConfig {
+            max_prompt_tokens: Some(100),
+            max_prompt_tokens_per_model: HashMap::from([("large/model".to_string(), 1_000)]),
+            ..Default::default()
+        };
+        let pipeline = FilterPipeline::from_config(&config).unwrap();
+        let mut body = json!({});
+
+        assert!(pipeline
+            .filter_request(&context(&alice, "model", 100), &mut body)
+            .await
+            .is_ok());
+        assert!(matches!(
+            pipeline
+                .filter_request(&context(&alice, "model", 101), &mut body)
+                .await,
+            Err(AtomaServiceError::RequestRejected { filter, .. }) if filter == "max_prompt_tokens"
+        ));
+        assert!(pipeline
+            .filter_request(&context(&alice, "large/model", 1_000), &mut body)
+            .await
+            .is_ok());
+
+        let mut image_context = context(&alice, "model", 1_000);
+        image_context.request_type = RequestType::ImageGenerations;
+        assert!(pipeline
+            .filter_request(&image_context, &mut body)
+            .await
+            .is_ok());
+    }
+
+    #[test]
+    fn test_response_filters() {
+        let pipeline = FilterPipeline::default().with_response_filter(RedactFilter);
+        let context = ResponseFilterContext {
+            stack_small_id: 1,
+            endpoint: "/v1/chat/completions",
+        };
+
+        let mut body = json!({"secret": "password"});
+        pipeline.filter_response(&context, &mut body).unwrap();
+        assert_eq!(body, json!({"secret": "[REDACTED]"}));
+
+        let mut body = json!({"forbidden": true});
+        assert!(matches!(
+            pipeline.filter_response(&context, &mut body),
+            Err(AtomaServiceError::RequestRejected { filter, .. }) if filter == "redact"
+        ));
+    }
+}
@@ -0,0 +1,886 @@
+use std::{
+    collections::HashSet,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use atoma_state::{
+    types::{
+        AtomaAtomaStateManagerEvent, Batch, BatchRequest, BATCH_REQUEST_STATUS_COMPLETED,
+        BATCH_REQUEST_STATUS_PENDING, BATCH_STATUS_IN_PROGRESS,
+    },
+    AtomaStateManagerError,
+};
+use atoma_utils::{constants::PAYLOAD_HASH_SIZE, hashing::blake2b_hash, verify_signature};
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, State},
+    http::{header::CONTENT_TYPE, HeaderMap},
+    response::Response,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sui_sdk::types::base_types::SuiAddress;
+use tokio::sync::oneshot;
+use tracing::{info, instrument};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    error::AtomaServiceError,
+    filters::{FilterPipeline, RequestFilterContext},
+    middleware::{utils, RequestType},
+    server::AppState,
+};
+
+use super::{
+    chat_completions::CHAT_COMPLETIONS_PATH, embeddings::EMBEDDINGS_PATH,
+    update_stack_num_compute_units,
+};
+
+/// The path for batches requests
+pub const BATCHES_PATH: &str = "/v1/batches";
+
+/// The path for retrieving a batch
+pub const BATCH_PATH: &str = "/v1/batches/{batch_id}";
+
+/// The path for retrieving the output file of a batch
+pub const BATCH_OUTPUT_PATH: &str = "/v1/batches/{batch_id}/output";
+
+/// Maximum size of a batch input file, in bytes
+pub const MAX_BATCH_INPUT_SIZE: usize = 32 * 1024 * 1024; // 32MB
+
+/// Maximum number of requests in a single batch
+const MAX_BATCH_NUM_REQUESTS: usize = 10_000;
+
+/// The prefix of batch identifiers
+const BATCH_ID_PREFIX: &str = "batch_";
+
+/// The content type of batch output files
+const JSONL_CONTENT_TYPE: &str = "application/jsonl";
+
+/// The only HTTP method supported for batch requests
+const BATCH_REQUEST_METHOD: &str = "POST";
+
+/// The key for the model parameter in the request body
+const MODEL_KEY: &str = "model";
+
+/// The key for the stream parameter in the request body
+const STREAM_KEY: &str = "stream";
+
+/// Maximum difference, in seconds, between the timestamp signed by a batch retrieval request
+/// and the time it is received, so that signed requests cannot be replayed later on
+const MAX_BATCH_QUERY_AGE_SECS: i64 = 300;
+
+/// OpenAPI documentation structure for the batches endpoints.
+///
+/// This struct defines the OpenAPI (Swagger) documentation for the batches API,
+/// including all request and response schemas. It uses the `utoipa` framework to generate
+/// the API documentation.
+#[derive(OpenApi)]
+#[openapi(
+    paths(create_batch_handler, get_batch_handler, get_batch_output_handler),
+    components(schemas(BatchObject, BatchRequestCounts))
+)]
+pub struct BatchesOpenApi;
+
+/// A single line of a batch input file, following the OpenAI batch input format.
+///
+/// ```json
+/// {"custom_id": "request-1", "method": "POST", "url": "/v1/chat/completions", "body": {"model": "...", "messages": [...]}}
+/// ```
+#[derive(Debug, Deserialize)]
+pub(crate) struct BatchInputLine {
+    /// Identifier provided by the user to match requests with their results
+    pub custom_id: String,
+    /// The HTTP method of the request, only `POST` is supported
+    pub method: String,
+    /// The endpoint of the request, either `/v1/chat/completions` or `/v1/embeddings`
+    pub url: String,
+    /// The body of the request
+    pub body: Value,
+}
+
+/// The number of requests of a batch, per status
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BatchRequestCounts {
+    /// Total number of requests in the batch
+    pub total: i64,
+    /// Number of requests that were processed successfully
+    pub completed: i64,
+    /// Number of requests whose processing failed
+    pub failed: i64,
+}
+
+/// A batch, as returned by the batches API
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BatchObject {
+    /// Unique identifier of the batch
+    pub id: String,
+    /// The object type, always `batch`
+    pub object: String,
+    /// The endpoint every request of the batch targets
+    pub endpoint: String,
+    /// Status of the batch, either `in_progress` or `completed`
+    pub status: String,
+    /// The stack the batch is paid with
+    pub stack_small_id: i64,
+    /// Number of compute units reserved on the stack for the batch
+    pub reserved_compute_units: i64,
+    /// Number of compute units used by the requests processed so far
+    pub used_compute_units: i64,
+    /// The number of requests of the batch, per status
+    pub request_counts: BatchRequestCounts,
+    /// Unix timestamp (in seconds) at which the batch was created
+    pub created_at: i64,
+    /// Unix timestamp (in seconds) at which the batch was completed
+    pub completed_at: Option<i64>,
+}
+
+impl From<Batch> for BatchObject {
+    fn from(batch: Batch) -> Self {
+        Self {
+            id: batch.batch_id,
+            object: "batch".to_string(),
+            endpoint: batch.endpoint,
+            status: batch.status,
+            stack_small_id: batch.stack_small_id,
+            reserved_compute_units: batch.reserved_compute_units,
+            used_compute_units: batch.used_compute_units,
+            request_counts: BatchRequestCounts {
+                total: batch.total_requests,
+                completed: batch.completed_requests,
+                failed: batch.failed_requests,
+            },
+            created_at: batch.created_at,
+            completed_at: batch.completed_at,
+        }
+    }
+}
+
+/// Create batch
+///
+/// Accepts a JSONL file of chat completions or embeddings requests, to be processed
+/// asynchronously, with lower priority than interactive requests. Each line follows the
+/// OpenAI batch input format, and all lines must target the same endpoint.
+///
+/// The compute units of every request are estimated and reserved on the stack upfront.
+/// Compute units that are not used are released once the whole batch has been processed.
+///
+/// # Headers
+///
+/// - `X-Signature`: Signature of the Blake2b hash of the raw request body
+/// - `X-Stack-Small-Id`: The stack to pay the batch with
+/// - `X-Tx-Digest` (optional): Digest of the transaction that created the stack, if the
+///   node is not yet aware of it
+///
+/// # Errors
+///
+/// Returns an `AtomaServiceError` if:
+/// - The signature is invalid or the headers are missing
+/// - Any line of the input file is invalid, or targets an unsupported model or endpoint
+/// - Any line of the input file is rejected by the request filters of the node
+/// - The stack does not have enough compute units for the whole batch
+/// - The batch cannot be stored
+#[utoipa::path(
+    post,
+    path = "",
+    tag = "batches",
+    request_body(content = String, content_type = "application/jsonl"),
+    responses(
+        (status = OK, description = "Batch created successfully", body = BatchObject),
+        (status = BAD_REQUEST, description = "Invalid batch input file"),
+        (status = UNAUTHORIZED, description = "Invalid signature or not enough compute units"),
+        (status = FORBIDDEN, description = "A request of the batch was rejected by a filter"),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+#[instrument(level = "info", skip_all, fields(endpoint = BATCHES_PATH), err)]
+pub async fn create_batch_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<BatchObject>, AtomaServiceError> {
+    let endpoint = BATCHES_PATH;
+    let sui_address = verify_request_signature(&headers, &hash_bytes(&body), endpoint)?;
+    let stack_small_id = utils::get_stack_small_id(&headers, endpoint)?;
+    let (request_type, batch_endpoint, lines) = parse_batch_input(&body)?;
+
+    let batch_id = format!(
+        "{BATCH_ID_PREFIX}{}",
+        hex::encode(rand::random::<[u8; 16]>())
+    );
+    let mut requests = Vec::with_capacity(lines.len());
+    let mut reserved_compute_units = 0_i64;
+    for (line_index, mut line) in lines.into_iter().enumerate() {
+        let model = line
+            .body
+            .get(MODEL_KEY)
+            .and_then(Value::as_str)
+            .ok_or_else(|| AtomaServiceError::InvalidBody {
+                message: format!("Request {}: model not found in body", line.custom_id),
+                endpoint: endpoint.to_string(),
+            })?
+            .to_string();
+        let model_registry = state.model_registry.load_full();
+        if !model_registry.contains(&model) {
+            return Err(AtomaServiceError::InvalidBody {
+                message: format!(
+                    "Request {}: model not supported, supported models: {:?}",
+                    line.custom_id,
+                    model_registry.models()
+                ),
+                endpoint: endpoint.to_string(),
+            });
+        }
+        let mut compute_units_estimate =
+            utils::calculate_compute_units(&line.body, request_type, &state, &model, endpoint)?;
+        // NOTE: As for interactive requests, lines exceeding the maximum context length of the
+        // model are rejected (or their `max_tokens` clamped) before reserving compute units.
+        if let (RequestType::ChatCompletions, Some(context_limit)) =
+            (request_type, model_registry.context_limit(&model))
+        {
+            if let Some(clamped_estimate) = utils::enforce_context_length(
+                &mut line.body,
+                &compute_units_estimate,
+                context_limit,
+                endpoint,
+            )
+            .map_err(|e| match e {
+                AtomaServiceError::InvalidParameter {
+                    param,
+                    message,
+                    endpoint,
+                } => AtomaServiceError::InvalidParameter {
+                    param,
+                    message: format!("Request {}: {message}", line.custom_id),
+                    endpoint,
+                },
+                e => e,
+            })? {
+                compute_units_estimate = clamped_estimate;
+            }
+        }
+        // NOTE: Batch lines go through the same request filters as interactive requests,
+        // otherwise a batch would be a way around the operator's policies.
+        filter_batch_line(
+            &state.filters,
+            &RequestFilterContext {
+                sui_address: &sui_address,
+                stack_small_id,
+                model: &model,
+                request_type,
+                num_input_tokens: compute_units_estimate.num_input_compute_units as i64,
+                endpoint: &batch_endpoint,
+            },
+            &line.custom_id,
+            &mut line.body,
+        )
+        .await?;
+        let estimated_compute_units = compute_units_estimate.max_total_compute_units as i64;
+        reserved_compute_units = reserved_compute_units
+            .checked_add(estimated_compute_units)
+            .ok_or_else(|| AtomaServiceError::InvalidBody {
+                message: "Batch requires too many compute units".to_string(),
+                endpoint: endpoint.to_string(),
+            })?;
+        requests.push(BatchRequest {
+            batch_id: batch_id.clone(),
+            line_index: line_index as i64,
+            custom_id: line.custom_id,
+            body: line.body.to_string(),
+            estimated_compute_units,
+            used_compute_units: 0,
+            status: BATCH_REQUEST_STATUS_PENDING.to_string(),
+            status_code: None,
+            response: None,
+            error: None,
+        });
+    }
+
+    utils::lock_compute_units_for_stack(
+        &state,
+        &headers,
+        stack_small_id,
+        &sui_address,
+        reserved_compute_units,
+        endpoint,
+    )
+    .await?;
+
+    let batch = Batch {
+        batch_id,
+        stack_small_id,
+        owner_address: sui_address.to_string(),
+        endpoint: batch_endpoint,
+        status: BATCH_STATUS_IN_PROGRESS.to_string(),
+        reserved_compute_units,
+        used_compute_units: 0,
+        total_requests: requests.len() as i64,
+        completed_requests: 0,
+        failed_requests: 0,
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64),
+        completed_at: None,
+    };
+    if let Err(e) = query_state_manager(&state, endpoint, |result_sender| {
+        AtomaAtomaStateManagerEvent::CreateBatch {
+            batch: batch.clone(),
+            requests,
+            result_sender,
+        }
+    })
+    .await
+    {
+        // NOTE: The batch was not stored, so the compute units locked for it must be released.
+        let concurrent_requests = state.concurrent_requests_per_stack.get(stack_small_id);
+        update_stack_num_compute_units(
+            &state.state_manager_sender,
+            stack_small_id,
+            reserved_compute_units,
+            0,
+            0,
+            endpoint,
+            concurrent_requests,
+            None,
+        )?;
+        return Err(e);
+    }
+    // NOTE: A batch counts as a single in-flight request for its stack until it completes,
+    // so that the stack is not locked for claim while compute units are still reserved for it.
+    state
+        .concurrent_requests_per_stack
+        .increment_batch(stack_small_id);
+
+    info!(
+        target = "atoma-service",
+        level = "info",
+        batch_id = batch.batch_id,
+        stack_small_id,
+        reserved_compute_units,
+        "Created batch with {} requests",
+        batch.total_requests
+    );
+    Ok(Json(BatchObject::from(batch)))
+}
+
+/// Retrieve batch
+///
+/// Returns the status of a batch, together with its request counts and compute units usage.
+///
+/// # Headers
+///
+/// - `X-Signature`: Signature of the Blake2b hash of `{batch_id}:{stack_small_id}:{timestamp}`,
+///   by the owner of the batch's stack
+/// - `X-Stack-Small-Id`: The stack the batch is paid with
+/// - `X-Timestamp`: Unix timestamp (in seconds) at which the request was signed
+///
+/// # Errors
+///
+/// Returns an `AtomaServiceError` if the signature is invalid or stale, or if the batch does
+/// not exist or is not owned by the signer.
+#[utoipa::path(
+    get,
+    path = "/{batch_id}",
+    tag = "batches",
+    params(("batch_id" = String, Path, description = "The batch identifier")),
+    responses(
+        (status = OK, description = "Batch retrieved successfully", body = BatchObject),
+        (status = UNAUTHORIZED, description = "Invalid signature"),
+        (status = NOT_FOUND, description = "Batch not found")
+    )
+)]
+#[instrument(level = "info", skip_all, fields(batch_id = %batch_id), err)]
+pub async fn get_batch_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(batch_id): Path<String>,
+) -> Result<Json<BatchObject>, AtomaServiceError> {
+    let batch = get_authorized_batch(&state, &headers, &batch_id, BATCH_PATH).await?;
+    Ok(Json(BatchObject::from(batch)))
+}
+
+/// Retrieve batch output
+///
+/// Returns a JSONL file with the results of the batch requests processed so far, following
+/// the OpenAI batch output format. Successful responses are signed by the node, exactly as
+/// for interactive requests.
+///
+/// # Headers
+///
+/// - `X-Signature`: Signature of the Blake2b hash of `{batch_id}:{stack_small_id}:{timestamp}`,
+///   by the owner of the batch's stack
+/// - `X-Stack-Small-Id`: The stack the batch is paid with
+/// - `X-Timestamp`: Unix timestamp (in seconds) at which the request was signed
+///
+/// # Errors
+///
+/// Returns an `AtomaServiceError` if the signature is invalid or stale, or if the batch does
+/// not exist or is not owned by the signer.
+#[utoipa::path(
+    get,
+    path = "/{batch_id}/output",
+    tag = "batches",
+    params(("batch_id" = String, Path, description = "The batch identifier")),
+    responses(
+        (status = OK, description = "Batch output retrieved successfully", body = String, content_type = "application/jsonl"),
+        (status = UNAUTHORIZED, description = "Invalid signature"),
+        (status = NOT_FOUND, description = "Batch not found")
+    )
+)]
+#[instrument(level = "info", skip_all, fields(batch_id = %batch_id), err)]
+pub async fn get_batch_output_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(batch_id): Path<String>,
+) -> Result<Response<Body>, AtomaServiceError> {
+    let endpoint = BATCH_OUTPUT_PATH;
+    let batch = get_authorized_batch(&state, &headers, &batch_id, endpoint).await?;
+    let requests = query_state_manager(&state, endpoint, |result_sender| {
+        AtomaAtomaStateManagerEvent::GetBatchRequests {
+            batch_id: batch.batch_id.clone(),
+            result_sender,
+        }
+    })
+    .await?;
+    let output = requests
+        .iter()
+        .filter(|request| request.status != BATCH_REQUEST_STATUS_PENDING)
+        .map(|request| format!("{}\n", batch_output_line(request)))
+        .collect::<String>();
+    Response::builder()
+        .header(CONTENT_TYPE, JSONL_CONTENT_TYPE)
+        .body(Body::from(output))
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Failed to build batch output response: {e}"),
+            endpoint: endpoint.to_string(),
+        })
+}
+
+/// Parses and validates a batch input file.
+///
+/// Empty lines are ignored. Every other line must be a valid [`BatchInputLine`], with a
+/// unique `custom_id`, the `POST` method and a supported, non-streaming, endpoint. All the
+/// lines must target the same endpoint.
+///
+/// # Returns
+///
+/// The request type and endpoint of the batch, together with its parsed lines.
+///
+/// # Errors
+///
+/// Returns `AtomaServiceError::InvalidBody` if the input file is empty, too large, or
+/// contains an invalid line.
+pub(crate) fn parse_batch_input(
+    input: &[u8],
+) -> Result<(RequestType, String, Vec<BatchInputLine>), AtomaServiceError> {
+    let invalid_body = |message: String| AtomaServiceError::InvalidBody {
+        message,
+        endpoint: BATCHES_PATH.to_string(),
+    };
+    let input = std::str::from_utf8(input)
+        .map_err(|e| invalid_body(format!("Batch input file is not valid UTF-8: {e}")))?;
+    let mut lines = Vec::new();
+    let mut custom_ids = HashSet::new();
+    let mut batch_endpoint: Option<String> = None;
+    for (index, line) in input.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_number = index + 1;
+        let line: BatchInputLine = serde_json::from_str(line)
+            .map_err(|e| invalid_body(format!("Line {line_number} is invalid: {e}")))?;
+        if !line.method.eq_ignore_ascii_case(BATCH_REQUEST_METHOD) {
+            return Err(invalid_body(format!(
+                "Line {line_number}: unsupported method {}, only {BATCH_REQUEST_METHOD} is supported",
+                line.method
+            )));
+        }
+        if line.url != CHAT_COMPLETIONS_PATH && line.url != EMBEDDINGS_PATH {
+            return Err(invalid_body(format!(
+                "Line {line_number}: unsupported url {}, supported urls: {CHAT_COMPLETIONS_PATH}, {EMBEDDINGS_PATH}",
+                line.url
+            )));
+        }
+        match &batch_endpoint {
+            Some(endpoint) if *endpoint != line.url => {
+                return Err(invalid_body(format!(
+                    "Line {line_number}: all requests of a batch must target the same url ({endpoint})"
+                )));
+            }
+            Some(_) => {}
+            None => batch_endpoint = Some(line.url.clone()),
+        }
+        if line.body.get(STREAM_KEY).and_then(Value::as_bool) == Some(true) {
+            return Err(invalid_body(format!(
+                "Line {line_number}: streaming is not supported for batch requests"
+            )));
+        }
+        if !custom_ids.insert(line.custom_id.clone()) {
+            return Err(invalid_body(format!(
+                "Line {line_number}: duplicate custom_id {}",
+                line.custom_id
+            )));
+        }
+        lines.push(line);
+        if lines.len() > MAX_BATCH_NUM_REQUESTS {
+            return Err(invalid_body(format!(
+                "Batch exceeds the maximum number of requests ({MAX_BATCH_NUM_REQUESTS})"
+            )));
+        }
+    }
+    let Some(batch_endpoint) = batch_endpoint else {
+        return Err(invalid_body("Batch input file is empty".to_string()));
+    };
+    let request_type = if batch_endpoint == CHAT_COMPLETIONS_PATH {
+        RequestType::ChatCompletions
+    } else {
+        RequestType::Embeddings
+    };
+    Ok((request_type, batch_endpoint, lines))
+}
+
+/// Applies the request filters of the node to a batch line, possibly rewriting its body.
+///
+/// # Errors
+///
+/// Returns `AtomaServiceError::RequestRejected` if any filter rejects the line, so that the
+/// whole batch is rejected.
+pub(crate) async fn filter_batch_line(
+    filters: &FilterPipeline,
+    context: &RequestFilterContext<'_>,
+    custom_id: &str,
+    body: &mut Value,
+) -> Result<(), AtomaServiceError> {
+    filters
+        .filter_request(context, body)
+        .await
+        .map_err(|e| match e {
+            AtomaServiceError::RequestRejected {
+                filter, message, ..
+            } => AtomaServiceError::RequestRejected {
+                filter,
+                message: format!("Request {custom_id}: {message}"),
+                endpoint: BATCHES_PATH.to_string(),
+            },
+            e => e,
+        })
+}
+
+/// Builds the line of the batch output file for a processed batch request, following the
+/// OpenAI batch output format.
+fn batch_output_line(request: &BatchRequest) -> Value {
+    let id = format!("{}-{}", request.batch_id, request.line_index);
+    if request.status == BATCH_REQUEST_STATUS_COMPLETED {
+        let body = request
+            .response
+            .as_deref()
+            .and_then(|response| serde_json::from_str::<Value>(response).ok());
+        json!({
+            "id": id,
+            "custom_id": request.custom_id,
+            "response": {
+                "status_code": request.status_code,
+                "body": body,
+            },
+            "error": null,
+        })
+    } else {
+        json!({
+            "id": id,
+            "custom_id": request.custom_id,
+            "response": null,
+            "error": {
+                "code": request.status_code,
+                "message": request.error,
+            },
+        })
+    }
+}
+
+/// Retrieves a batch, checking that the request is signed by the owner of the batch's stack.
+///
+/// The `X-Signature` header must contain a signature of the Blake2b hash of
+/// `{batch_id}:{stack_small_id}:{timestamp}`, where the stack small id and the timestamp are
+/// the values of the `X-Stack-Small-Id` and `X-Timestamp` headers. Requests whose timestamp
+/// is more than [`MAX_BATCH_QUERY_AGE_SECS`] away from the current time are rejected, so that
+/// a signed request cannot be replayed to read the batch later on.
+///
+/// Batches that are not owned by the signer, or not paid with the given stack, are reported
+/// as not found, so that the existence of other users' batches is not disclosed.
+async fn get_authorized_batch(
+    state: &AppState,
+    headers: &HeaderMap,
+    batch_id: &str,
+    endpoint: &str,
+) -> Result<Batch, AtomaServiceError> {
+    let stack_small_id = utils::get_stack_small_id(headers, endpoint)?;
+    let timestamp = get_request_timestamp(headers, endpoint)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    if (now - timestamp).abs() > MAX_BATCH_QUERY_AGE_SECS {
+        return Err(AtomaServiceError::AuthError {
+            auth_error: format!(
+                "Request timestamp {timestamp} is more than {MAX_BATCH_QUERY_AGE_SECS} seconds away from the current time"
+            ),
+            endpoint: endpoint.to_string(),
+        });
+    }
+    let sui_address = verify_request_signature(
+        headers,
+        &batch_query_hash(batch_id, stack_small_id, timestamp),
+        endpoint,
+    )?;
+    let not_found = || AtomaServiceError::NotFound {
+        message: format!("Batch {batch_id} not found"),
+        endpoint: endpoint.to_string(),
+    };
+    let batch = query_state_manager(state, endpoint, |result_sender| {
+        AtomaAtomaStateManagerEvent::GetBatch {
+            batch_id: batch_id.to_string(),
+            result_sender,
+        }
+    })
+    .await?;
+    if batch.owner_address != sui_address.to_string() || batch.stack_small_id != stack_small_id {
+        return Err(not_found());
+    }
+    // NOTE: Stacks that were archived after being claimed are no longer known to the node,
+    // in which case the batch owner (who owned the stack when the batch was created) is trusted
+    let stack = query_state_manager(state, endpoint, |result_sender| {
+        AtomaAtomaStateManagerEvent::GetStack {
+            stack_small_id,
+            result_sender,
+        }
+    })
+    .await?;
+    if stack.is_some_and(|stack| stack.owner_address != sui_address.to_string()) {
+        return Err(not_found());
+    }
+    Ok(batch)
+}
+
+/// Computes the hash signed by batch retrieval requests.
+fn batch_query_hash(
+    batch_id: &str,
+    stack_small_id: i64,
+    timestamp: i64,
+) -> [u8; PAYLOAD_HASH_SIZE] {
+    hash_bytes(format!("{batch_id}:{stack_small_id}:{timestamp}").as_bytes())
+}
+
+/// Extracts the Unix timestamp (in seconds) of the `X-Timestamp` header.
+fn get_request_timestamp(headers: &HeaderMap, endpoint: &str) -> Result<i64, AtomaServiceError> {
+    headers
+        .get(atoma_utils::constants::TIMESTAMP)
+        .ok_or_else(|| AtomaServiceError::MissingHeader {
+            header: atoma_utils::constants::TIMESTAMP.to_string(),
+            endpoint: endpoint.to_string(),
+        })?
+        .to_str()
+        .ok()
+        .and_then(|timestamp| timestamp.parse::<i64>().ok())
+        .ok_or_else(|| AtomaServiceError::InvalidHeader {
+            message: "Timestamp must be a Unix timestamp, in seconds".to_string(),
+            endpoint: endpoint.to_string(),
+        })
+}
+
+/// Verifies the `X-Signature` header against the given hash, and returns the signer's Sui address.
+fn verify_request_signature(
+    headers: &HeaderMap,
+    hash: &[u8; PAYLOAD_HASH_SIZE],
+    endpoint: &str,
+) -> Result<SuiAddress, AtomaServiceError> {
+    let sui_address = utils::get_sui_address(headers, endpoint)?;
+    let base64_signature = headers
+        .get(atoma_utils::constants::SIGNATURE)
+        .and_then(|signature| signature.to_str().ok())
+        .unwrap_or_default();
+    verify_signature(base64_signature, hash).map_err(|e| AtomaServiceError::AuthError {
+        auth_error: format!("Failed to verify signature, with error: {e}"),
+        endpoint: endpoint.to_string(),
+    })?;
+    Ok(sui_address)
+}
+
+/// Computes the Blake2b hash of the given bytes.
+fn hash_bytes(bytes: &[u8]) -> [u8; PAYLOAD_HASH_SIZE] {
+    blake2b_hash(bytes).into()
+}
+
+/// Sends a query event to the state manager and awaits its result.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the state manager channel
+/// * `endpoint` - The endpoint the query is made for (used for error context)
+/// * `event` - Builds the event to send, from the oneshot channel to receive the result on
+///
+/// # Errors
+///
+/// Returns `AtomaServiceError::NotFound` if the requested batch does not exist, and
+/// `AtomaServiceError::InternalError` if the query fails.
+pub(crate) async fn query_state_manager<T>(
+    state: &AppState,
+    endpoint: &str,
+    event: impl FnOnce(
+        oneshot::Sender<Result<T, AtomaStateManagerError>>,
+    ) -> AtomaAtomaStateManagerEvent,
+) -> Result<T, AtomaServiceError> {
+    let (result_sender, result_receiver) = oneshot::channel();
+    state
+        .state_manager_sender
+        .send(event(result_sender))
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Failed to send event to state manager: {e}"),
+            endpoint: endpoint.to_string(),
+        })?;
+    result_receiver
+        .await
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Failed to receive result from state manager: {e}"),
+            endpoint: endpoint.to_string(),
+        })?
+        .map_err(|e| match e {
+            AtomaStateManagerError::BatchNotFound(batch_id) => AtomaServiceError::NotFound {
+                message: format!("Batch {batch_id} not found"),
+                endpoint: endpoint.to_string(),
+            },
+            e => AtomaServiceError::InternalError {
+                message: format!("State manager query failed: {e}"),
+                endpoint: endpoint.to_string(),
+            },
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(custom_id: &str, url: &str) -> String {
+        json!({
+            "custom_id": custom_id,
+            "method": "POST",
+            "url": url,
+            "body": {"model": "model", "messages": [{"role": "user", "content": "Hello"}]},
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_parse_batch_input() {
+        let input = format!(
+            "{}\n\n{}\n",
+            line("request-1", CHAT_COMPLETIONS_PATH),
+            line("request-2", CHAT_COMPLETIONS_PATH)
+        );
+        let (request_type, endpoint, lines) = parse_batch_input(input.as_bytes()).unwrap();
+        assert_eq!(request_type, RequestType::ChatCompletions);
+        assert_eq!(endpoint, CHAT_COMPLETIONS_PATH);
+        assert_eq!(
+            lines
+                .iter()
+                .map(|l| l.custom_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["request-1", "request-2"]
+        );
+    }
+
+    #[test]
+    fn test_parse_batch_input_rejects_invalid_batches() {
+        let mixed = format!(
+            "{}\n{}",
+            line("request-1", CHAT_COMPLETIONS_PATH),
+            line("request-2", EMBEDDINGS_PATH)
+        );
+        let duplicate = format!(
+            "{}\n{}",
+            line("request-1", EMBEDDINGS_PATH),
+            line("request-1", EMBEDDINGS_PATH)
+        );
+        let unsupported = line("request-1", "/v1/images/generations");
+        let streaming = json!({
+            "custom_id": "request-1",
+            "method": "POST",
+            "url": CHAT_COMPLETIONS_PATH,
+            "body": {"model": "model", "messages": [], "stream": true},
+        })
+        .to_string();
+        for input in [
+            "",
+            "\n\n",
+            "not json",
+            &mixed,
+            &duplicate,
+            &unsupported,
+            &streaming,
+        ] {
+            assert!(matches!(
+                parse_batch_input(input.as_bytes()),
+                Err(AtomaServiceError::InvalidBody { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_batch_query_signature_inputs() {
+        let hash = batch_query_hash("batch_1", 1, 1_700_000_000);
+        assert_ne!(hash, batch_query_hash("batch_2", 1, 1_700_000_000));
+        assert_ne!(hash, batch_query_hash("batch_1", 2, 1_700_000_000));
+        assert_ne!(hash, batch_query_hash("batch_1", 1, 1_700_000_001));
+
+        let mut headers = HeaderMap::new();
+        assert!(matches!(
+            get_request_timestamp(&headers, BATCH_PATH),
+            Err(AtomaServiceError::MissingHeader { .. })
+        ));
+        headers.insert(
+            atoma_utils::constants::TIMESTAMP,
+            "yesterday".parse().unwrap(),
+        );
+        assert!(matches!(
+            get_request_timestamp(&headers, BATCH_PATH),
+            Err(AtomaServiceError::InvalidHeader { .. })
+        ));
+        headers.insert(
+            atoma_utils::constants::TIMESTAMP,
+            "1700000000".parse().unwrap(),
+        );
+        assert_eq!(
+            get_request_timestamp(&headers, BATCH_PATH).unwrap(),
+            1_700_000_000
+        );
+    }
+
+    #[test]
+    fn test_batch_output_line() {
+        let mut request = BatchRequest {
+            batch_id: "batch_1".to_string(),
+            line_index: 0,
+            custom_id: "request-1".to_string(),
+            body: "{}".to_string(),
+            estimated_compute_units: 10,
+            used_compute_units: 5,
+            status: BATCH_REQUEST_STATUS_COMPLETED.to_string(),
+            status_code: Some(200),
+            response: Some(r#"{"choices":[]}"#.to_string()),
+            error: None,
+        };
+        let output = batch_output_line(&request);
+        assert_eq!(output["id"], "batch_1-0");
+        assert_eq!(output["response"]["status_code"], 200);
+        assert_eq!(output["response"]["body"], json!({"choices": []}));
+        assert!(output["error"].is_null());
+
+        request.status = atoma_state::types::BATCH_REQUEST_STATUS_FAILED.to_string();
+        request.status_code = Some(500);
+        request.response = None;
+        request.error = Some("Internal server error".to_string());
+        let output = batch_output_line(&request);
+        assert!(output["response"].is_null());
+        assert_eq!(output["error"]["code"], 500);
+        assert_eq!(output["error"]["message"], "Internal server error");
+    }
+}
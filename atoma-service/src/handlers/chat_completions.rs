@@ -7,9 +7,11 @@ use crate::{
         },
         sign_response_and_update_stack_hash, update_stack_num_compute_units,
     },
-    middleware::EncryptionMetadata,
+    middleware::{utils as middleware_utils, EncryptionMetadata},
+    resumable_streams::{StreamChunk, StreamOwner},
     server::AppState,
-    streamer::{Streamer, StreamingEncryptionMetadata},
+    streamer::{chunk_event, Streamer, StreamingEncryptionMetadata},
+    trace_context::inference_service_headers,
     types::{ConfidentialComputeRequest, ConfidentialComputeResponse},
 };
 use atoma_confidential::types::{
@@ -22,6 +24,7 @@ use axum::{
     response::{IntoResponse, Response, Sse},
     Extension, Json,
 };
+use futures::{Stream, StreamExt};
 use hyper::{HeaderMap, StatusCode};
 use openai_api::{
     completion_choice::{
@@ -47,7 +50,10 @@ use tracing::{debug, info, instrument};
 use utoipa::OpenApi;
 
 use serde::Deserialize;
-use std::time::{Duration, Instant};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use crate::{
     error::AtomaServiceError,
@@ -61,6 +67,7 @@ use crate::{
 use super::{
     handle_confidential_compute_encryption_response, handle_status_code_error,
     request_model::{ComputeUnitsEstimate, RequestModel},
+    structured_output::StructuredOutputValidator,
     vllm_metrics::get_best_available_chat_completions_service_url,
     DEFAULT_MAX_TOKENS,
 };
@@ -212,6 +219,8 @@ pub async fn chat_completions_handler(
         num_input_tokens,
         payload_hash,
         client_encryption_metadata,
+        structured_output_validator,
+        request_id,
         ..
     } = request_metadata;
     info!(
@@ -242,6 +251,8 @@ pub async fn chat_completions_handler(
         num_input_tokens,
         estimated_total_compute_units,
         client_encryption_metadata,
+        structured_output_validator,
+        request_id.as_deref(),
         headers,
     )
     .await
@@ -273,8 +284,10 @@ pub async fn chat_completions_handler(
                 stack_small_id,
                 estimated_total_compute_units,
                 0,
+                0,
                 &endpoint,
                 concurrent_requests,
+                request_id.as_deref(),
             )?;
             return Err(AtomaServiceError::InternalError {
                 message: format!("Error handling chat completions response: {}", e),
@@ -390,6 +403,8 @@ pub async fn confidential_chat_completions_handler(
         estimated_total_compute_units,
         payload_hash,
         client_encryption_metadata,
+        structured_output_validator,
+        request_id,
         ..
     } = request_metadata;
     info!(
@@ -424,6 +439,8 @@ pub async fn confidential_chat_completions_handler(
         num_input_tokens,
         estimated_total_compute_units,
         client_encryption_metadata,
+        structured_output_validator,
+        request_id.as_deref(),
         headers,
     )
     .await
@@ -456,8 +473,10 @@ pub async fn confidential_chat_completions_handler(
                 stack_small_id,
                 estimated_total_compute_units,
                 0,
+                0,
                 &endpoint,
                 concurrent_requests,
+                request_id.as_deref(),
             )?;
             return Err(AtomaServiceError::InternalError {
                 message: format!("Error handling chat completions response: {}", e),
@@ -483,6 +502,7 @@ pub async fn confidential_chat_completions_handler(
 /// * `payload` - The JSON payload containing the chat completion request
 /// * `estimated_total_compute_units` - Estimated compute units for the request
 /// * `client_encryption_metadata` - Optional encryption metadata for confidential compute
+/// * `structured_output_validator` - Optional validator of the output against a strict JSON schema
 ///
 /// # Returns
 ///
@@ -532,6 +552,8 @@ async fn handle_response(
     num_input_tokens: i64,
     estimated_total_compute_units: i64,
     client_encryption_metadata: Option<EncryptionMetadata>,
+    structured_output_validator: Option<Arc<StructuredOutputValidator>>,
+    request_id: Option<&str>,
     headers: HeaderMap,
 ) -> Result<Response<Body>, AtomaServiceError> {
     if is_stream {
@@ -552,6 +574,7 @@ async fn handle_response(
             estimated_total_compute_units,
             payload_hash,
             streaming_encryption_metadata,
+            structured_output_validator,
             endpoint,
             headers,
         )
@@ -564,7 +587,9 @@ async fn handle_response(
             estimated_total_compute_units,
             payload_hash,
             client_encryption_metadata,
+            structured_output_validator,
             endpoint,
+            request_id,
         )
         .await
     }
@@ -574,7 +599,7 @@ async fn handle_response(
 ///
 /// This function performs several key operations in the following order:
 /// 1. Forwards the request to the inference service
-/// 2. Processes and signs the response
+/// 2. Validates the output against the requested JSON schema (if strict), processes and signs the response
 /// 3. Updates token usage tracking
 /// 4. Handles confidential compute encryption (if enabled)
 /// 5. Updates the stack's compute units count (final step)
@@ -591,7 +616,9 @@ async fn handle_response(
 /// * `estimated_total_compute_units` - Estimated compute units count for the request
 /// * `payload_hash` - BLAKE2b hash of the original request payload
 /// * `client_encryption_metadata` - The client encryption metadata for the request
+/// * `structured_output_validator` - Optional validator of the output against a strict JSON schema
 /// * `endpoint` - The endpoint where the request was made
+/// * `request_id` - The request ID, forwarded to the inference service
 ///
 /// # Returns
 ///
@@ -631,6 +658,7 @@ async fn handle_response(
     ),
     err
 )]
+#[allow(clippy::too_many_arguments)]
 async fn handle_non_streaming_response(
     state: &AppState,
     payload: Value,
@@ -638,7 +666,9 @@ async fn handle_non_streaming_response(
     estimated_total_compute_units: i64,
     payload_hash: [u8; PAYLOAD_HASH_SIZE],
     client_encryption_metadata: Option<EncryptionMetadata>,
+    structured_output_validator: Option<Arc<StructuredOutputValidator>>,
     endpoint: String,
+    request_id: Option<&str>,
 ) -> Result<Response<Body>, AtomaServiceError> {
     // Record token metrics and extract the response total number of tokens
     let model = payload
@@ -653,12 +683,13 @@ async fn handle_non_streaming_response(
         level = "debug",
         "Sending non-streaming chat completions request to {endpoint}"
     );
-    let response_body = utils::send_request_to_inference_service(
+    let mut response_body = utils::send_request_to_inference_service(
         state,
         &payload,
         stack_small_id,
         payload_hash,
         &endpoint,
+        request_id,
     )
     .await?;
     debug!(
@@ -668,6 +699,11 @@ async fn handle_non_streaming_response(
     );
     let total_compute_units = utils::extract_total_num_tokens(&response_body, model);
 
+    // NOTE: The validation result is added to the response body before it gets signed
+    if let Some(validator) = structured_output_validator {
+        validator.annotate_response(&mut response_body, model);
+    }
+
     utils::serve_non_streaming_response(
         state,
         response_body,
@@ -679,6 +715,7 @@ async fn handle_non_streaming_response(
         endpoint,
         timer,
         model,
+        request_id,
     )
     .await
 }
@@ -690,6 +727,8 @@ async fn handle_non_streaming_response(
 /// 2. Forwarding the request to the inference service
 /// 3. Establishing an SSE connection with keep-alive functionality
 /// 4. Setting up a Streamer to handle the response chunks and manage token usage
+/// 5. If resumable streams are enabled, buffering the chunks in the background, so that the
+///    client can resume the stream if its connection drops
 ///
 /// # Arguments
 ///
@@ -699,6 +738,7 @@ async fn handle_non_streaming_response(
 /// * `estimated_total_compute_units` - Estimated compute units count for the request
 /// * `payload_hash` - BLAKE2b hash of the original request payload
 /// * `streaming_encryption_metadata` - The client encryption metadata for the streaming request
+/// * `structured_output_validator` - Optional validator of the output against a strict JSON schema
 /// * `endpoint` - The endpoint where the request was made
 ///
 /// # Returns
@@ -711,12 +751,20 @@ async fn handle_non_streaming_response(
 /// - The inference service request fails
 /// - The inference service returns a non-success status code
 ///
+/// Returns `AtomaServiceError::Conflict` if a resumable stream was started for the same request
+/// ID in the meantime.
+///
+/// See [`start_streaming_response`] for the complete list of errors.
+///
 /// # Example Response Stream
 ///
 /// The SSE stream will emit events in the following format:
 /// ```text
+/// id: 0
 /// data: {"choices": [...], "usage": null}
+/// id: 1
 /// data: {"choices": [...], "usage": null}
+/// id: 2
 /// data: {"choices": [...], "usage": {"prompt_tokens": 10, "completion_tokens": 20, "total_tokens": 30}}
 /// ```
 #[instrument(
@@ -734,15 +782,113 @@ async fn handle_non_streaming_response(
 #[allow(clippy::too_many_arguments)]
 async fn handle_streaming_response(
     state: &AppState,
-    mut payload: Value,
+    payload: Value,
     stack_small_id: i64,
     num_input_tokens: i64,
     estimated_total_compute_units: i64,
     payload_hash: [u8; 32],
     streaming_encryption_metadata: Option<StreamingEncryptionMetadata>,
+    structured_output_validator: Option<Arc<StructuredOutputValidator>>,
     endpoint: String,
     headers: HeaderMap,
 ) -> Result<Response<Body>, AtomaServiceError> {
+    // NOTE: Requests resuming the stream must be signed by the same client, for the same payload
+    let stream_owner = if state.resumable_streams.is_enabled() {
+        Some(StreamOwner {
+            stack_small_id,
+            sui_address: middleware_utils::get_sui_address(&headers, &endpoint)?,
+            payload_hash,
+        })
+    } else {
+        None
+    };
+    let streamer = start_streaming_response(
+        state,
+        payload,
+        stack_small_id,
+        num_input_tokens,
+        estimated_total_compute_units,
+        payload_hash,
+        streaming_encryption_metadata,
+        structured_output_validator,
+        endpoint.clone(),
+        &headers,
+    )
+    .await?;
+
+    match stream_owner {
+        Some(stream_owner) => {
+            let request_id = streamer.request_id().to_string();
+            // NOTE: Requests reusing the request ID of a stream are rejected by the
+            // `resume_stream_middleware`, but a concurrent request with the same request ID may
+            // have started its stream since, in which case this stream is dropped, as any
+            // dropped stream.
+            let chunks = state
+                .resumable_streams
+                .start(
+                    request_id,
+                    stream_owner,
+                    streamer.into_events(),
+                    state.drain.track(),
+                )
+                .map_err(|e| AtomaServiceError::Conflict {
+                    message: e.to_string(),
+                    endpoint,
+                })?;
+            Ok(sse_response(chunks))
+        }
+        None => Ok(sse_response(streamer.into_events())),
+    }
+}
+
+/// Creates the Server-Sent Events response streaming chunks to the client, each chunk being
+/// sent as an event with its event ID.
+pub(crate) fn sse_response(
+    chunks: impl Stream<Item = Result<StreamChunk, axum::Error>> + Send + 'static,
+) -> Response<Body> {
+    let events =
+        chunks.map(|chunk| chunk.and_then(|(event_id, chunk)| chunk_event(event_id, &chunk)));
+    Sse::new(events)
+        .keep_alive(
+            axum::response::sse::KeepAlive::new()
+                .interval(Duration::from_millis(STREAM_KEEP_ALIVE_INTERVAL_IN_SECONDS))
+                .text("keep-alive"),
+        )
+        .into_response()
+}
+
+/// Forwards a streaming chat completion request to the inference service, and returns the
+/// [`Streamer`] processing the response chunks (signing, encryption and compute units
+/// accounting), independently of the transport used to send them to the client.
+///
+/// # Arguments
+///
+/// Same as [`handle_streaming_response`].
+///
+/// # Errors
+///
+/// Returns `AtomaServiceError::MissingHeader` or `AtomaServiceError::InvalidHeader` if the
+/// request ID header is missing or invalid.
+///
+/// Returns `AtomaServiceError::ChatCompletionsServiceUnavailable` if no chat completions
+/// service is available for the model.
+///
+/// Returns `AtomaServiceError::InternalError` if:
+/// - The inference service request fails
+/// - The inference service returns a non-success status code
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn start_streaming_response(
+    state: &AppState,
+    mut payload: Value,
+    stack_small_id: i64,
+    num_input_tokens: i64,
+    estimated_total_compute_units: i64,
+    payload_hash: [u8; 32],
+    streaming_encryption_metadata: Option<StreamingEncryptionMetadata>,
+    structured_output_validator: Option<Arc<StructuredOutputValidator>>,
+    endpoint: String,
+    headers: &HeaderMap,
+) -> Result<Streamer, AtomaServiceError> {
     // NOTE: If streaming is requested, add the include_usage option to the payload
     // so that the atoma node state manager can be updated with the total number of tokens
     // that were processed for this request.
@@ -770,9 +916,9 @@ async fn handle_streaming_response(
     CHAT_COMPLETIONS_NUM_REQUESTS.add(1, &[KeyValue::new("model", model.to_owned())]);
     let timer = Instant::now();
 
-    let chat_completions_service_urls = state
-        .chat_completions_service_urls
-        .get(&model.to_lowercase())
+    let model_registry = state.model_registry.load_full();
+    let chat_completions_service_urls = model_registry
+        .chat_completions_service_urls(model)
         .ok_or_else(|| {
             AtomaServiceError::InternalError {
                 message: format!(
@@ -801,6 +947,7 @@ async fn handle_streaming_response(
             "{}{}",
             chat_completions_service_url, CHAT_COMPLETIONS_PATH
         ))
+        .headers(inference_service_headers(Some(request_id.as_str())))
         .json(&payload)
         .send()
         .await
@@ -825,8 +972,7 @@ async fn handle_streaming_response(
     }
 
     let stream = response.bytes_stream();
-    // Create the SSE stream
-    let stream = Sse::new(Streamer::new(
+    Ok(Streamer::new(
         stream,
         state.state_manager_sender.clone(),
         state.concurrent_requests_per_stack.clone(),
@@ -839,17 +985,12 @@ async fn handle_streaming_response(
         state.address_index,
         model.to_string(),
         streaming_encryption_metadata,
+        structured_output_validator,
+        state.filters.clone(),
         endpoint,
         request_id,
         timer,
     ))
-    .keep_alive(
-        axum::response::sse::KeepAlive::new()
-            .interval(Duration::from_millis(STREAM_KEEP_ALIVE_INTERVAL_IN_SECONDS))
-            .text("keep-alive"),
-    );
-
-    Ok(stream.into_response())
 }
 
 /// Represents a chat completion request model following the OpenAI API format
@@ -869,7 +1010,8 @@ impl RequestModel for RequestModelChatCompletions {
         let messages = request
             .get(MESSAGES_KEY)
             .and_then(|m| m.as_array())
-            .ok_or_else(|| AtomaServiceError::InvalidBody {
+            .ok_or_else(|| AtomaServiceError::InvalidParameter {
+                param: MESSAGES_KEY.to_string(),
                 message: "Missing or invalid 'messages' field".to_string(),
                 endpoint: CHAT_COMPLETIONS_PATH.to_string(),
             })?;
@@ -974,7 +1116,10 @@ impl RequestModel for RequestModelChatCompletions {
 pub mod utils {
     use std::time::Instant;
 
-    use atoma_utils::constants::PAYLOAD_HASH_SIZE;
+    use atoma_utils::{
+        constants::{NONCE_SIZE, PAYLOAD_HASH_SIZE},
+        encryption::SymmetricSecret,
+    };
     use hyper::StatusCode;
     use opentelemetry::KeyValue;
 
@@ -983,6 +1128,7 @@ pub mod utils {
         metrics::CHAT_COMPLETIONS_LATENCY_METRICS,
         vllm_metrics::get_best_available_chat_completions_service_url,
     };
+    use crate::trace_context::inference_service_headers;
 
     use super::{
         handle_confidential_compute_encryption_response, info, instrument,
@@ -1044,9 +1190,20 @@ pub mod utils {
         stack_small_id: i64,
         endpoint: &str,
     ) -> Result<Option<StreamingEncryptionMetadata>, AtomaServiceError> {
-        let streaming_encryption_metadata = if let Some(client_encryption_metadata) =
-            client_encryption_metadata
+        let streaming_encryption_metadata = if let Some(EncryptionMetadata {
+            salt,
+            response_secret: Some(response_secret),
+            ..
+        }) = client_encryption_metadata
         {
+            // NOTE: HPKE responses are encrypted with the secret exported from the request
+            // HPKE context, under a fresh stream base nonce
+            Some(StreamingEncryptionMetadata {
+                secret: response_secret,
+                nonce: rand::random::<[u8; NONCE_SIZE]>(),
+                salt,
+            })
+        } else if let Some(client_encryption_metadata) = client_encryption_metadata {
             let (sender, receiver) = tokio::sync::oneshot::channel();
             state
                 .compute_shared_secret_sender
@@ -1082,7 +1239,7 @@ pub mod utils {
                 }
             })?;
             Some(StreamingEncryptionMetadata {
-                shared_secret,
+                secret: SymmetricSecret::from(&shared_secret),
                 nonce,
                 salt: client_encryption_metadata.salt,
             })
@@ -1106,6 +1263,7 @@ pub mod utils {
     /// * `stack_small_id` - Unique identifier for the stack making the request
     /// * `payload_hash` - BLAKE2b hash of the original request payload
     /// * `endpoint` - The API endpoint path where the request was received
+    /// * `request_id` - The request ID, forwarded to the inference service
     ///
     /// # Returns
     ///
@@ -1137,7 +1295,8 @@ pub mod utils {
     ///     }),
     ///     123,
     ///     [0u8; 32],
-    ///     "/v1/chat/completions"
+    ///     "/v1/chat/completions",
+    ///     Some("request-id"),
     /// ).await?;
     /// ```
     #[instrument(
@@ -1152,15 +1311,16 @@ pub mod utils {
         stack_small_id: i64,
         payload_hash: [u8; PAYLOAD_HASH_SIZE],
         endpoint: &str,
+        request_id: Option<&str>,
     ) -> Result<Value, AtomaServiceError> {
         let client = Client::new();
         let model = payload
             .get(MODEL_KEY)
             .and_then(|m| m.as_str())
             .unwrap_or(UNKNOWN_MODEL);
-        let chat_completions_service_url_services = state
-            .chat_completions_service_urls
-            .get(&model.to_lowercase())
+        let model_registry = state.model_registry.load_full();
+        let chat_completions_service_url_services = model_registry
+            .chat_completions_service_urls(model)
             .ok_or_else(|| {
                 AtomaServiceError::InternalError {
                     message: format!(
@@ -1191,6 +1351,7 @@ pub mod utils {
             "{}{}",
             chat_completions_service_url, CHAT_COMPLETIONS_PATH
         ))
+        .headers(inference_service_headers(request_id))
         .json(&payload)
         .send()
         .await
@@ -1319,6 +1480,8 @@ pub mod utils {
     /// * `client_encryption_metadata` - Optional encryption metadata for confidential compute
     /// * `endpoint` - The API endpoint path where the request was received
     /// * `timer` - Prometheus histogram timer for tracking response latency
+    /// * `model` - The model of the request
+    /// * `request_id` - The request ID, recorded on the state manager events
     ///
     /// # Returns
     ///
@@ -1372,6 +1535,7 @@ pub mod utils {
         endpoint: String,
         timer: Instant,
         model: &str,
+        request_id: Option<&str>,
     ) -> Result<Response<Body>, AtomaServiceError> {
         info!(
             target = "atoma-service",
@@ -1390,6 +1554,7 @@ pub mod utils {
             state,
             stack_small_id,
             endpoint.clone(),
+            request_id,
         )
         .await
         {
@@ -1461,8 +1626,10 @@ pub mod utils {
             stack_small_id,
             estimated_total_compute_units,
             total_compute_units,
+            1,
             &endpoint,
             concurrent_requests,
+            request_id,
         )?;
 
         Ok(response_body)
@@ -15,6 +15,7 @@ use crate::{
     },
     middleware::{EncryptionMetadata, RequestMetadata},
     server::AppState,
+    trace_context::inference_service_headers,
     types::{ConfidentialComputeRequest, ConfidentialComputeResponse},
 };
 use axum::{extract::State, Extension, Json};
@@ -104,6 +105,7 @@ pub async fn embeddings_handler(
         payload_hash,
         client_encryption_metadata,
         endpoint_path: endpoint,
+        request_id,
         ..
     } = request_metadata;
 
@@ -118,6 +120,7 @@ pub async fn embeddings_handler(
         payload_hash,
         client_encryption_metadata,
         &endpoint,
+        request_id.as_deref(),
     )
     .await
     {
@@ -147,8 +150,10 @@ pub async fn embeddings_handler(
                 stack_small_id,
                 estimated_total_compute_units,
                 0,
+                0,
                 &endpoint,
                 concurrent_requests,
+                request_id.as_deref(),
             )?;
             Err(e)
         }
@@ -234,6 +239,7 @@ pub async fn confidential_embeddings_handler(
         payload_hash,
         client_encryption_metadata,
         endpoint_path: endpoint,
+        request_id,
         ..
     } = request_metadata;
 
@@ -246,6 +252,7 @@ pub async fn confidential_embeddings_handler(
         payload_hash,
         client_encryption_metadata,
         &endpoint,
+        request_id.as_deref(),
     )
     .await
     {
@@ -274,8 +281,10 @@ pub async fn confidential_embeddings_handler(
                 stack_small_id,
                 estimated_total_compute_units,
                 0,
+                0,
                 &endpoint,
                 concurrent_requests,
+                request_id.as_deref(),
             )?;
             Err(e)
         }
@@ -301,7 +310,7 @@ pub async fn confidential_embeddings_handler(
 /// * `payload_hash` - 32-byte hash of the original request payload
 /// * `client_encryption_metadata` - Optional encryption details for confidential compute
 /// * `endpoint` - The API endpoint path being called
-/// * `timer` - Prometheus histogram timer for tracking request duration
+/// * `request_id` - The request ID, forwarded to the embeddings service
 ///
 /// # Returns
 ///
@@ -345,6 +354,7 @@ async fn handle_embeddings_response(
     payload_hash: [u8; 32],
     client_encryption_metadata: Option<EncryptionMetadata>,
     endpoint: &str,
+    request_id: Option<&str>,
 ) -> Result<Json<Value>, AtomaServiceError> {
     let client = Client::new();
     let response = client
@@ -352,6 +362,7 @@ async fn handle_embeddings_response(
             "{}{}",
             state.embeddings_service_url, EMBEDDINGS_PATH
         ))
+        .headers(inference_service_headers(request_id))
         .json(&payload)
         .send()
         .await
@@ -384,6 +395,7 @@ async fn handle_embeddings_response(
         state,
         stack_small_id,
         endpoint.to_string(),
+        request_id,
     )
     .await
     {
@@ -426,7 +438,8 @@ impl RequestModel for RequestModelEmbeddings {
     fn new(request: &Value) -> Result<Self, AtomaServiceError> {
         let input = request
             .get(INPUT_KEY)
-            .ok_or_else(|| AtomaServiceError::InvalidBody {
+            .ok_or_else(|| AtomaServiceError::InvalidParameter {
+                param: INPUT_KEY.to_string(),
                 message: "Input field is required".to_string(),
                 endpoint: EMBEDDINGS_PATH.to_string(),
             })?;
@@ -452,7 +465,8 @@ impl RequestModel for RequestModelEmbeddings {
         let total_units = match &self.input {
             Value::String(text) => tokenizer
                 .encode(text.as_str(), true)
-                .map_err(|_| AtomaServiceError::InvalidBody {
+                .map_err(|_| AtomaServiceError::InvalidParameter {
+                    param: INPUT_KEY.to_string(),
                     message: "Failed to encode input text".to_string(),
                     endpoint: EMBEDDINGS_PATH.to_string(),
                 })?
@@ -470,7 +484,8 @@ impl RequestModel for RequestModelEmbeddings {
                 })
                 .sum(),
             _ => {
-                return Err(AtomaServiceError::InvalidBody {
+                return Err(AtomaServiceError::InvalidParameter {
+                    param: INPUT_KEY.to_string(),
                     message: "Invalid input format".to_string(),
                     endpoint: EMBEDDINGS_PATH.to_string(),
                 });
@@ -1,6 +1,7 @@
 use std::time::Instant;
 
 use crate::{
+    config::ImageGenerationsConfig,
     error::AtomaServiceError,
     handlers::{
         handle_concurrent_requests_count_decrement,
@@ -11,11 +12,17 @@ use crate::{
         },
         update_stack_num_compute_units,
     },
-    middleware::{EncryptionMetadata, RequestMetadata},
+    middleware::{utils::parse_multipart_fields, EncryptionMetadata, RequestMetadata},
     server::AppState,
+    trace_context::inference_service_headers,
     types::{ConfidentialComputeRequest, ConfidentialComputeResponse},
 };
-use axum::{extract::State, Extension, Json};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header::CONTENT_TYPE, HeaderMap},
+    Extension, Json,
+};
 use opentelemetry::KeyValue;
 use reqwest::Client;
 use serde_json::Value;
@@ -35,6 +42,15 @@ pub const CONFIDENTIAL_IMAGE_GENERATIONS_PATH: &str = "/v1/confidential/images/g
 /// The path for image generations requests
 pub const IMAGE_GENERATIONS_PATH: &str = "/v1/images/generations";
 
+/// The path for image edits requests
+pub const IMAGE_EDITS_PATH: &str = "/v1/images/edits";
+
+/// The path for image variations requests
+pub const IMAGE_VARIATIONS_PATH: &str = "/v1/images/variations";
+
+/// Maximum size of the (multipart) body of image edits and variations requests, in bytes
+pub const MAX_IMAGE_INPUT_SIZE: usize = 16 * 1024 * 1024; // 16MB
+
 /// The key for the model parameter in the request body
 pub const MODEL_KEY: &str = "model";
 
@@ -44,6 +60,24 @@ pub const N_KEY: &str = "n";
 /// The key for the size parameter in the request body
 pub const SIZE_KEY: &str = "size";
 
+/// The key for the quality parameter in the request body
+pub const QUALITY_KEY: &str = "quality";
+
+/// The key for the steps parameter in the request body
+pub const STEPS_KEY: &str = "steps";
+
+/// The key for the response format parameter in the request body
+pub const RESPONSE_FORMAT_KEY: &str = "response_format";
+
+/// The response format returning the generated images as URLs
+pub const URL_RESPONSE_FORMAT: &str = "url";
+
+/// The response format returning the generated images as base64-encoded JSON strings
+pub const B64_JSON_RESPONSE_FORMAT: &str = "b64_json";
+
+/// The prefix of the data URLs of the generated images returned as base64-encoded strings
+const PNG_DATA_URL_PREFIX: &str = "data:image/png;base64,";
+
 /// OpenAPI documentation structure for the image generations endpoint.
 ///
 /// This struct defines the OpenAPI (Swagger) documentation for the image generations API,
@@ -91,9 +125,12 @@ pub struct ImageGenerationsOpenApi;
 pub async fn image_generations_handler(
     Extension(request_metadata): Extension<RequestMetadata>,
     State(state): State<AppState>,
-    Json(payload): Json<Value>,
+    Json(mut payload): Json<Value>,
 ) -> Result<Json<Value>, AtomaServiceError> {
     info!("Received image generations request, with payload: {payload}");
+    if let Some(steps) = resolved_steps(&state.image_generations, &payload) {
+        payload[STEPS_KEY] = Value::from(steps);
+    }
     let model = payload
         .get(MODEL_KEY)
         .and_then(|m| m.as_str())
@@ -108,6 +145,7 @@ pub async fn image_generations_handler(
         payload_hash,
         client_encryption_metadata,
         endpoint_path: endpoint,
+        request_id,
         ..
     } = request_metadata;
 
@@ -118,13 +156,15 @@ pub async fn image_generations_handler(
 
     match handle_image_generations_response(
         &state,
-        payload.clone(),
+        IMAGE_GENERATIONS_PATH,
+        ImagesRequest::Json(payload.clone()),
         payload_hash,
         stack_small_id,
         client_encryption_metadata,
         &endpoint,
         timer,
         model.to_string(),
+        request_id.as_deref(),
     )
     .await
     {
@@ -146,8 +186,10 @@ pub async fn image_generations_handler(
                 stack_small_id,
                 estimated_total_compute_units,
                 0,
+                0,
                 &endpoint,
                 concurrent_requests,
+                request_id.as_deref(),
             )?;
             Err(AtomaServiceError::InternalError {
                 message: format!("Error handling image generations response: {}", e),
@@ -216,9 +258,12 @@ pub struct ConfidentialImageGenerationsOpenApi;
 pub async fn confidential_image_generations_handler(
     Extension(request_metadata): Extension<RequestMetadata>,
     State(state): State<AppState>,
-    Json(payload): Json<Value>,
+    Json(mut payload): Json<Value>,
 ) -> Result<Json<Value>, AtomaServiceError> {
     info!("Received image generations request, with payload: {payload}");
+    if let Some(steps) = resolved_steps(&state.image_generations, &payload) {
+        payload[STEPS_KEY] = Value::from(steps);
+    }
     let model = payload
         .get(MODEL_KEY)
         .and_then(|m| m.as_str())
@@ -235,18 +280,21 @@ pub async fn confidential_image_generations_handler(
         payload_hash,
         client_encryption_metadata,
         endpoint_path: endpoint,
+        request_id,
         ..
     } = request_metadata;
 
     match handle_image_generations_response(
         &state,
-        payload.clone(),
+        IMAGE_GENERATIONS_PATH,
+        ImagesRequest::Json(payload.clone()),
         payload_hash,
         stack_small_id,
         client_encryption_metadata,
         &endpoint,
         timer,
         model.to_string(),
+        request_id.as_deref(),
     )
     .await
     {
@@ -268,8 +316,10 @@ pub async fn confidential_image_generations_handler(
                 stack_small_id,
                 estimated_total_compute_units,
                 0,
+                0,
                 &endpoint,
                 concurrent_requests,
+                request_id.as_deref(),
             )?;
             Err(AtomaServiceError::InternalError {
                 message: format!("Error handling image generations response: {}", e),
@@ -279,24 +329,239 @@ pub async fn confidential_image_generations_handler(
     }
 }
 
+/// OpenAPI documentation structure for the image edits endpoint.
+#[derive(OpenApi)]
+#[openapi(paths(image_edits_handler))]
+pub struct ImageEditsOpenApi;
+
+/// Create image edit
+///
+/// Edits (or extends) an image, given a prompt. The request is a multipart form, with the
+/// `image` (and optional `mask`) files and the same text fields as image generations requests.
+///
+/// # Errors
+///
+/// Returns a `AtomaServiceError::InternalError` if:
+/// - The image generations service request fails
+/// - Response parsing fails
+#[utoipa::path(
+    post,
+    path = "",
+    tag = "images",
+    request_body(content = String, content_type = "multipart/form-data"),
+    responses(
+        (status = OK, description = "Image edited successfully", body = Value),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+#[instrument(
+    level = "info",
+    skip_all,
+    fields(path = request_metadata.endpoint_path),
+    err
+)]
+pub async fn image_edits_handler(
+    Extension(request_metadata): Extension<RequestMetadata>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Value>, AtomaServiceError> {
+    handle_multipart_image_request(request_metadata, state, IMAGE_EDITS_PATH, &headers, body).await
+}
+
+/// OpenAPI documentation structure for the image variations endpoint.
+#[derive(OpenApi)]
+#[openapi(paths(image_variations_handler))]
+pub struct ImageVariationsOpenApi;
+
+/// Create image variation
+///
+/// Generates variations of an image. The request is a multipart form, with the `image` file
+/// and the same text fields as image generations requests (except for the prompt).
+///
+/// # Errors
+///
+/// Returns a `AtomaServiceError::InternalError` if:
+/// - The image generations service request fails
+/// - Response parsing fails
+#[utoipa::path(
+    post,
+    path = "",
+    tag = "images",
+    request_body(content = String, content_type = "multipart/form-data"),
+    responses(
+        (status = OK, description = "Image variations generated successfully", body = Value),
+        (status = INTERNAL_SERVER_ERROR, description = "Internal server error")
+    )
+)]
+#[instrument(
+    level = "info",
+    skip_all,
+    fields(path = request_metadata.endpoint_path),
+    err
+)]
+pub async fn image_variations_handler(
+    Extension(request_metadata): Extension<RequestMetadata>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Value>, AtomaServiceError> {
+    handle_multipart_image_request(
+        request_metadata,
+        state,
+        IMAGE_VARIATIONS_PATH,
+        &headers,
+        body,
+    )
+    .await
+}
+
+/// Forwards a multipart image request (edits or variations) to the image generations service,
+/// releasing the compute units locked for the request if it fails.
+async fn handle_multipart_image_request(
+    request_metadata: RequestMetadata,
+    state: AppState,
+    path: &str,
+    headers: &HeaderMap,
+    body: Bytes,
+) -> Result<Json<Value>, AtomaServiceError> {
+    let RequestMetadata {
+        stack_small_id,
+        estimated_total_compute_units,
+        payload_hash,
+        endpoint_path: endpoint,
+        request_id,
+        ..
+    } = request_metadata;
+    let timer = Instant::now();
+
+    let fields = parse_multipart_fields(headers, body.clone(), &endpoint).await?;
+    let model = fields
+        .get(MODEL_KEY)
+        .and_then(|m| m.as_str())
+        .unwrap_or("unknown")
+        .to_string();
+    info!("Received {path} request, for model: {model}");
+    IMAGE_GEN_NUM_REQUESTS.add(1, &[KeyValue::new("model", model.clone())]);
+
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    // NOTE: The request was priced with the number of steps resolved from its quality, so the
+    // image generations service must run that number of steps too
+    let body = match resolved_steps(&state.image_generations, &fields) {
+        Some(steps) => {
+            append_multipart_text_field(&content_type, &body, STEPS_KEY, &steps.to_string())
+                .ok_or_else(|| AtomaServiceError::InvalidBody {
+                    message: "Failed to add the number of steps to the multipart body".to_string(),
+                    endpoint: endpoint.clone(),
+                })?
+        }
+        None => body,
+    };
+    let request = ImagesRequest::Multipart {
+        content_type,
+        body,
+        response_format: fields
+            .get(RESPONSE_FORMAT_KEY)
+            .and_then(Value::as_str)
+            .map(str::to_string),
+    };
+    match handle_image_generations_response(
+        &state,
+        path,
+        request,
+        payload_hash,
+        stack_small_id,
+        None,
+        &endpoint,
+        timer,
+        model.clone(),
+        request_id.as_deref(),
+    )
+    .await
+    {
+        Ok(response) => {
+            TOTAL_COMPLETED_REQUESTS.add(1, &[KeyValue::new("model", model)]);
+            Ok(response)
+        }
+        Err(e) => {
+            TOTAL_FAILED_REQUESTS.add(1, &[KeyValue::new("model", model.clone())]);
+            TOTAL_FAILED_IMAGE_GENERATION_REQUESTS.add(1, &[KeyValue::new("model", model)]);
+            let concurrent_requests = handle_concurrent_requests_count_decrement(
+                &state.concurrent_requests_per_stack,
+                stack_small_id,
+                "image-generations/handle_multipart_image_request",
+            );
+            update_stack_num_compute_units(
+                &state.state_manager_sender,
+                stack_small_id,
+                estimated_total_compute_units,
+                0,
+                0,
+                &endpoint,
+                concurrent_requests,
+                request_id.as_deref(),
+            )?;
+            Err(AtomaServiceError::InternalError {
+                message: format!("Error handling image generations response: {}", e),
+                endpoint: endpoint.to_string(),
+            })
+        }
+    }
+}
+
+/// The body of an image request, as forwarded to the image generations service
+enum ImagesRequest {
+    /// The JSON body of an image generations request
+    Json(Value),
+    /// The multipart body of an image edits or variations request
+    Multipart {
+        /// The content type of the body, with its multipart boundary
+        content_type: String,
+        /// The raw body
+        body: Bytes,
+        /// The requested response format, if any
+        response_format: Option<String>,
+    },
+}
+
+impl ImagesRequest {
+    /// Returns the response format requested by the client, if any.
+    fn response_format(&self) -> Option<&str> {
+        match self {
+            Self::Json(payload) => payload.get(RESPONSE_FORMAT_KEY).and_then(Value::as_str),
+            Self::Multipart {
+                response_format, ..
+            } => response_format.as_deref(),
+        }
+    }
+}
+
 /// Handles the core logic for processing image generation requests and responses
 ///
 /// This function performs several key operations:
-/// 1. Forwards the image generation request to the image generations service
-/// 2. Signs the response and updates the stack hash
-/// 3. Handles confidential compute encryption if needed
-/// 4. Records timing metrics for the operation
+/// 1. Forwards the image request to the image generations service
+/// 2. Converts the generated images to the requested response format
+/// 3. Signs the response and updates the stack hash
+/// 4. Handles confidential compute encryption if needed
+/// 5. Records timing metrics for the operation
 ///
 /// # Arguments
 ///
 /// * `state` - Application state containing service URLs and other shared resources
-/// * `payload` - The JSON payload containing the image generation request parameters
+/// * `path` - The path of the image generations service endpoint the request is forwarded to
+/// * `request` - The body of the image request
 /// * `payload_hash` - A 32-byte hash of the original request payload
 /// * `stack_small_id` - Identifier for the current stack
 /// * `estimated_total_compute_units` - Expected computational cost of the operation
 /// * `client_encryption_metadata` - Optional encryption metadata for confidential compute
 /// * `endpoint` - The API endpoint path being accessed
 /// * `timer` - Prometheus histogram timer for measuring request duration
+/// * `model` - The model of the request
+/// * `request_id` - The request ID, forwarded to the image generations service
 ///
 /// # Returns
 ///
@@ -320,21 +585,30 @@ pub async fn confidential_image_generations_handler(
 #[allow(clippy::too_many_arguments)]
 async fn handle_image_generations_response(
     state: &AppState,
-    payload: Value,
+    path: &str,
+    request: ImagesRequest,
     payload_hash: [u8; 32],
     stack_small_id: i64,
     client_encryption_metadata: Option<EncryptionMetadata>,
     endpoint: &str,
     timer: Instant,
     model: String,
+    request_id: Option<&str>,
 ) -> Result<Json<Value>, AtomaServiceError> {
     let client = Client::new();
-    let response = client
-        .post(format!(
-            "{}{}",
-            state.image_generations_service_url, IMAGE_GENERATIONS_PATH
-        ))
-        .json(&payload)
+    let response_format = request.response_format().map(str::to_string);
+    let request_builder = client
+        .post(format!("{}{}", state.image_generations_service_url, path))
+        .headers(inference_service_headers(request_id));
+    let request_builder = match request {
+        ImagesRequest::Json(payload) => request_builder.json(&payload),
+        ImagesRequest::Multipart {
+            content_type, body, ..
+        } => request_builder
+            .header(CONTENT_TYPE, content_type)
+            .body(body),
+    };
+    let response = request_builder
         .send()
         .await
         .map_err(|e| AtomaServiceError::InternalError {
@@ -358,6 +632,7 @@ async fn handle_image_generations_response(
                 message: format!("Error reading response body: {}", e),
                 endpoint: endpoint.to_string(),
             })?;
+    convert_response_format(&mut response_body, response_format.as_deref());
 
     // Sign the response and update the stack hash
     if let Err(e) = sign_response_and_update_stack_hash(
@@ -366,6 +641,7 @@ async fn handle_image_generations_response(
         state,
         stack_small_id,
         endpoint.to_string(),
+        request_id,
     )
     .await
     {
@@ -406,38 +682,254 @@ async fn handle_image_generations_response(
     }
 }
 
+/// Converts the generated images of a response to the response format requested by the client.
+///
+/// Image generations services return the generated images either as URLs or as base64-encoded
+/// strings. Images returned as base64-encoded strings are converted to data URLs, if the client
+/// requested URLs, and images returned as data URLs are converted back to base64-encoded
+/// strings, if the client requested them. Other images are returned as is.
+fn convert_response_format(response_body: &mut Value, response_format: Option<&str>) {
+    let Some(images) = response_body.get_mut("data").and_then(Value::as_array_mut) else {
+        return;
+    };
+    for image in images.iter_mut().filter_map(Value::as_object_mut) {
+        match response_format {
+            Some(URL_RESPONSE_FORMAT) if !image.contains_key(URL_RESPONSE_FORMAT) => {
+                if let Some(Value::String(b64_json)) = image.remove(B64_JSON_RESPONSE_FORMAT) {
+                    image.insert(
+                        URL_RESPONSE_FORMAT.to_string(),
+                        Value::String(format!("{PNG_DATA_URL_PREFIX}{b64_json}")),
+                    );
+                }
+            }
+            Some(B64_JSON_RESPONSE_FORMAT) if !image.contains_key(B64_JSON_RESPONSE_FORMAT) => {
+                let b64_json = image
+                    .get(URL_RESPONSE_FORMAT)
+                    .and_then(Value::as_str)
+                    .and_then(|url| url.strip_prefix("data:"))
+                    .and_then(|data| data.split_once(";base64,"))
+                    .map(|(_, b64_json)| b64_json.to_string());
+                if let Some(b64_json) = b64_json {
+                    image.remove(URL_RESPONSE_FORMAT);
+                    image.insert(
+                        B64_JSON_RESPONSE_FORMAT.to_string(),
+                        Value::String(b64_json),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Returns the number of steps an image request was priced with, if the request does not set
+/// it explicitly (e.g., when it is resolved from the quality of the request), so that it can
+/// be forwarded to the image generations service.
+fn resolved_steps(config: &ImageGenerationsConfig, request: &Value) -> Option<u64> {
+    if request.get(STEPS_KEY).is_some_and(|steps| !steps.is_null()) {
+        return None;
+    }
+    let model = request.get(MODEL_KEY).and_then(Value::as_str)?;
+    RequestModelImageGenerations::new(request)
+        .and_then(|request| request.with_config(config, model))
+        .ok()?
+        .steps
+}
+
+/// Appends a text field to a multipart body, right before its closing delimiter.
+///
+/// Returns `None` if the boundary of the body cannot be found.
+fn append_multipart_text_field(
+    content_type: &str,
+    body: &Bytes,
+    name: &str,
+    value: &str,
+) -> Option<Bytes> {
+    let boundary = content_type
+        .split(';')
+        .find_map(|param| param.trim().strip_prefix("boundary="))?
+        .trim_matches('"');
+    let closing_delimiter = format!("--{boundary}--");
+    let position = body
+        .windows(closing_delimiter.len())
+        .rposition(|window| window == closing_delimiter.as_bytes())?;
+    let field = format!(
+        "--{boundary}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+    );
+    let mut new_body = Vec::with_capacity(body.len() + field.len());
+    new_body.extend_from_slice(&body[..position]);
+    new_body.extend_from_slice(field.as_bytes());
+    new_body.extend_from_slice(&body[position..]);
+    Some(Bytes::from(new_body))
+}
+
+/// Parses an image size in the "WIDTHxHEIGHT" format (e.g., "1024x1024").
+fn parse_size(size: &str) -> Option<(u64, u64)> {
+    let (width, height) = size.split_once('x')?;
+    let width = width.parse::<u64>().ok()?;
+    let height = height.parse::<u64>().ok()?;
+    (width > 0 && height > 0).then_some((width, height))
+}
+
 /// A model representing the parameters for an image generation request.
 ///
 /// This struct encapsulates the required parameters for generating images through
-/// the API endpoint.
+/// the API endpoint. The same parameters are used for image edits and variations requests.
 pub struct RequestModelImageGenerations {
     /// The number of sampling generation to be performed for this request
     n: u64,
     /// The desired dimensions of the generated images in the format "WIDTHxHEIGHT"
     /// (e.g., "1024x1024")
     size: String,
+    /// The width of the generated images, in pixels
+    width: u64,
+    /// The height of the generated images, in pixels
+    height: u64,
+    /// The quality of the generated images, if any (e.g., "standard" or "hd")
+    quality: Option<String>,
+    /// The number of diffusion steps, as requested, or as resolved from the quality of the
+    /// request by [`Self::with_config`]
+    steps: Option<u64>,
+    /// The number of steps the compute units of the request are relative to, set by
+    /// [`Self::with_config`]
+    default_steps: Option<u64>,
+}
+
+impl RequestModelImageGenerations {
+    /// Validates the request against the image generations configuration of the node, and
+    /// resolves its number of steps.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `AtomaServiceError::InvalidParameter` if:
+    /// - The size is not allowed for the model, or exceeds the maximum number of pixels
+    /// - The number of steps is zero or exceeds the maximum number of steps
+    /// - The quality is unknown
+    pub fn with_config(
+        mut self,
+        config: &ImageGenerationsConfig,
+        model: &str,
+    ) -> Result<Self, AtomaServiceError> {
+        let invalid_parameter = |param: &str, message: String| {
+            Err(AtomaServiceError::InvalidParameter {
+                param: param.to_string(),
+                message,
+                endpoint: IMAGE_GENERATIONS_PATH.to_string(),
+            })
+        };
+        if let Some(allowed_sizes) = config.allowed_sizes.get(model) {
+            if !allowed_sizes.iter().any(|size| *size == self.size) {
+                return invalid_parameter(
+                    SIZE_KEY,
+                    format!(
+                        "Size {} is not supported by model {model}, supported sizes: {allowed_sizes:?}",
+                        self.size
+                    ),
+                );
+            }
+        }
+        if self.width.saturating_mul(self.height) > config.max_pixels {
+            return invalid_parameter(
+                SIZE_KEY,
+                format!(
+                    "Size {} exceeds the maximum number of pixels per image: {}",
+                    self.size, config.max_pixels
+                ),
+            );
+        }
+        let steps = match (self.steps, self.quality.as_deref()) {
+            (Some(steps), _) => {
+                if steps == 0 || steps > config.max_steps {
+                    return invalid_parameter(
+                        STEPS_KEY,
+                        format!(
+                            "Steps must be between 1 and {}, but got: {steps}",
+                            config.max_steps
+                        ),
+                    );
+                }
+                steps
+            }
+            (None, Some(quality)) => match config.quality_steps.get(quality) {
+                Some(steps) => *steps,
+                None => {
+                    return invalid_parameter(
+                        QUALITY_KEY,
+                        format!(
+                            "Unsupported quality {quality}, supported qualities: {:?}",
+                            config.quality_steps.keys().collect::<Vec<_>>()
+                        ),
+                    );
+                }
+            },
+            (None, None) => config.default_steps,
+        };
+        self.steps = Some(steps);
+        self.default_steps = Some(config.default_steps.max(1));
+        Ok(self)
+    }
 }
 
 impl RequestModel for RequestModelImageGenerations {
     fn new(request: &Value) -> Result<Self, AtomaServiceError> {
+        let invalid_parameter =
+            |param: &str, message: String| AtomaServiceError::InvalidParameter {
+                param: param.to_string(),
+                message,
+                endpoint: IMAGE_GENERATIONS_PATH.to_string(),
+            };
         let n = request
             .get(N_KEY)
             .and_then(serde_json::Value::as_u64)
-            .ok_or_else(|| AtomaServiceError::InvalidBody {
-                message: "N field is required".to_string(),
-                endpoint: IMAGE_GENERATIONS_PATH.to_string(),
-            })?;
+            .ok_or_else(|| invalid_parameter(N_KEY, "N field is required".to_string()))?;
         let size = request
             .get(SIZE_KEY)
             .and_then(|s| s.as_str())
-            .ok_or_else(|| AtomaServiceError::InvalidBody {
-                message: "Size field is required".to_string(),
-                endpoint: IMAGE_GENERATIONS_PATH.to_string(),
-            })?;
+            .ok_or_else(|| invalid_parameter(SIZE_KEY, "Size field is required".to_string()))?;
+        let (width, height) = parse_size(size).ok_or_else(|| {
+            invalid_parameter(
+                SIZE_KEY,
+                format!("Invalid size format, expected two dimensional image, but got: {size}"),
+            )
+        })?;
+        let quality = match request.get(QUALITY_KEY) {
+            None | Some(Value::Null) => None,
+            Some(Value::String(quality)) => Some(quality.clone()),
+            Some(_) => {
+                return Err(invalid_parameter(
+                    QUALITY_KEY,
+                    "Quality must be a string".to_string(),
+                ))
+            }
+        };
+        let steps = match request.get(STEPS_KEY) {
+            None | Some(Value::Null) => None,
+            Some(steps) => Some(steps.as_u64().ok_or_else(|| {
+                invalid_parameter(STEPS_KEY, "Steps must be a positive integer".to_string())
+            })?),
+        };
+        match request.get(RESPONSE_FORMAT_KEY) {
+            None | Some(Value::Null) => {}
+            Some(Value::String(format))
+                if format == URL_RESPONSE_FORMAT || format == B64_JSON_RESPONSE_FORMAT => {}
+            Some(format) => {
+                return Err(invalid_parameter(
+                    RESPONSE_FORMAT_KEY,
+                    format!(
+                        "Response format must be either {URL_RESPONSE_FORMAT} or {B64_JSON_RESPONSE_FORMAT}, but got: {format}"
+                    ),
+                ))
+            }
+        }
 
         Ok(Self {
             n,
             size: size.to_string(),
+            width,
+            height,
+            quality,
+            steps,
+            default_steps: None,
         })
     }
 
@@ -445,30 +937,142 @@ impl RequestModel for RequestModelImageGenerations {
         &self,
         _tokenizer: Option<&Tokenizer>,
     ) -> Result<ComputeUnitsEstimate, AtomaServiceError> {
-        // Parse dimensions from size string (e.g., "1024x1024")
-        let dimensions: Vec<u64> = self
-            .size
-            .split('x')
-            .filter_map(|s| s.parse().ok())
-            .collect();
-
-        if dimensions.len() != 2 {
-            return Err(AtomaServiceError::InvalidBody {
-                message: format!(
-                    "Invalid size format, expected two dimensional image, but got: {}",
-                    self.size
-                ),
-                endpoint: IMAGE_GENERATIONS_PATH.to_string(),
-            });
+        // Calculate compute units based on number of images and pixel count, scaled by the
+        // number of steps of the request
+        let num_pixels = self
+            .n
+            .checked_mul(self.width)
+            .and_then(|num_pixels| num_pixels.checked_mul(self.height));
+        let compute_units = match (self.steps, self.default_steps) {
+            (Some(steps), Some(default_steps)) => num_pixels
+                .and_then(|num_pixels| num_pixels.checked_mul(steps))
+                .map(|compute_units| compute_units.div_ceil(default_steps)),
+            _ => num_pixels,
         }
+        .ok_or_else(|| AtomaServiceError::InvalidParameter {
+            param: SIZE_KEY.to_string(),
+            message: format!(
+                "Too many compute units requested, for {} images of size {}",
+                self.n, self.size
+            ),
+            endpoint: IMAGE_GENERATIONS_PATH.to_string(),
+        })?;
 
-        let width = dimensions[0];
-        let height = dimensions[1];
-
-        // Calculate compute units based on number of images and pixel count
         Ok(ComputeUnitsEstimate {
-            num_input_compute_units: self.n * width * height,
-            max_total_compute_units: self.n * width * height,
+            num_input_compute_units: compute_units,
+            max_total_compute_units: compute_units,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn estimate(
+        request: &Value,
+        config: &ImageGenerationsConfig,
+    ) -> Result<u64, AtomaServiceError> {
+        RequestModelImageGenerations::new(request)?
+            .with_config(config, "black-forest-labs/FLUX.1-schnell")?
+            .get_compute_units_estimate(None)
+            .map(|estimate| estimate.max_total_compute_units)
+    }
+
+    #[test]
+    fn test_compute_units_factor_in_steps() {
+        let config = ImageGenerationsConfig::default();
+        let request = json!({ "n": 2, "size": "4x4" });
+        assert_eq!(estimate(&request, &config).unwrap(), 32);
+
+        let request = json!({ "n": 2, "size": "4x4", "quality": "hd" });
+        assert_eq!(estimate(&request, &config).unwrap(), 64);
+
+        let request = json!({ "n": 1, "size": "4x4", "steps": 5 });
+        assert_eq!(estimate(&request, &config).unwrap(), 4);
+
+        let request = json!({ "n": 1, "size": "4x4", "steps": 0 });
+        assert!(estimate(&request, &config).is_err());
+        let request = json!({ "n": 1, "size": "4x4", "quality": "ultra" });
+        assert!(estimate(&request, &config).is_err());
+    }
+
+    #[test]
+    fn test_resolved_steps() {
+        let config = ImageGenerationsConfig::default();
+        let model = "black-forest-labs/FLUX.1-schnell";
+        let request = json!({ "model": model, "n": 1, "size": "4x4", "quality": "hd" });
+        assert_eq!(
+            resolved_steps(&config, &request),
+            config.quality_steps.get("hd").copied()
+        );
+        let request = json!({ "model": model, "n": 1, "size": "4x4" });
+        assert_eq!(
+            resolved_steps(&config, &request),
+            Some(config.default_steps)
+        );
+        // Steps set by the client are forwarded as is
+        let request = json!({ "model": model, "n": 1, "size": "4x4", "steps": 5 });
+        assert_eq!(resolved_steps(&config, &request), None);
+    }
+
+    #[test]
+    fn test_append_multipart_text_field() {
+        let body = Bytes::from(
+            "--abc\r\nContent-Disposition: form-data; name=\"prompt\"\r\n\r\nA cat\r\n--abc--\r\n",
+        );
+        let body =
+            append_multipart_text_field("multipart/form-data; boundary=abc", &body, "steps", "8")
+                .unwrap();
+        assert_eq!(
+            body,
+            "--abc\r\nContent-Disposition: form-data; name=\"prompt\"\r\n\r\nA cat\r\n--abc\r\nContent-Disposition: form-data; name=\"steps\"\r\n\r\n8\r\n--abc--\r\n"
+        );
+        assert!(append_multipart_text_field("multipart/form-data", &body, "steps", "8").is_none());
+    }
+
+    #[test]
+    fn test_size_validation() {
+        let mut config = ImageGenerationsConfig::default();
+        for size in ["100000x100000", "0x512", "512", "512x512x512", "axb"] {
+            let request = json!({ "n": 1, "size": size });
+            assert!(
+                estimate(&request, &config).is_err(),
+                "{size} should be rejected"
+            );
+        }
+
+        config.allowed_sizes.insert(
+            "black-forest-labs/FLUX.1-schnell".to_string(),
+            vec!["1024x1024".to_string()],
+        );
+        let request = json!({ "n": 1, "size": "1024x1024" });
+        assert_eq!(estimate(&request, &config).unwrap(), 1024 * 1024);
+        let request = json!({ "n": 1, "size": "512x512" });
+        assert!(estimate(&request, &config).is_err());
+    }
+
+    #[test]
+    fn test_response_format() {
+        let request = json!({ "n": 1, "size": "4x4", "response_format": "jpeg" });
+        assert!(RequestModelImageGenerations::new(&request).is_err());
+
+        let mut response = json!({ "data": [{ "b64_json": "aGVsbG8=" }] });
+        convert_response_format(&mut response, Some(URL_RESPONSE_FORMAT));
+        assert_eq!(
+            response,
+            json!({ "data": [{ "url": "data:image/png;base64,aGVsbG8=" }] })
+        );
+        convert_response_format(&mut response, Some(B64_JSON_RESPONSE_FORMAT));
+        assert_eq!(response, json!({ "data": [{ "b64_json": "aGVsbG8=" }] }));
+
+        let mut response = json!({ "data": [{ "url": "https://images.example/1.png" }] });
+        convert_response_format(&mut response, Some(B64_JSON_RESPONSE_FORMAT));
+        assert_eq!(
+            response,
+            json!({ "data": [{ "url": "https://images.example/1.png" }] })
+        );
+    }
+}
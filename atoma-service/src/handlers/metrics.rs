@@ -324,6 +324,26 @@ pub static TOTAL_FAILED_CHAT_REQUESTS: Lazy<Counter<u64>> = Lazy::new(|| {
         .build()
 });
 
+/// Counter metric that tracks the number of chat completions whose output failed validation
+/// against the JSON schema of a strict `response_format`.
+///
+/// # Metric Details
+/// - Name: `atoma_chat_completions_structured_output_validation_failures`
+/// - Type: Counter
+/// - Labels: `model`
+/// - Unit: requests (count)
+pub static CHAT_COMPLETIONS_STRUCTURED_OUTPUT_VALIDATION_FAILURES: Lazy<Counter<u64>> = Lazy::new(
+    || {
+        GLOBAL_METER
+            .u64_counter("atoma_chat_completions_structured_output_validation_failures")
+            .with_description(
+                "Total number of chat completions whose output does not match the requested JSON schema",
+            )
+            .with_unit("requests")
+            .build()
+    },
+);
+
 /// Counter metric that tracks the total number of confidential chat requests.
 ///
 /// # Metric Details
@@ -1,16 +1,18 @@
+pub mod batches;
 pub mod chat_completions;
 pub mod embeddings;
 pub mod image_generations;
 pub mod metrics;
 pub mod request_model;
 pub mod stop_streamer;
+pub mod structured_output;
+pub mod websocket;
 
 use atoma_confidential::types::{
     ConfidentialComputeEncryptionRequest, ConfidentialComputeEncryptionResponse,
 };
-use atoma_utils::hashing::blake2b_hash;
+use atoma_utils::{encryption::encrypt_plaintext_with_secret, hashing::blake2b_hash};
 use base64::{engine::general_purpose::STANDARD, Engine};
-use dashmap::DashMap;
 use flume::Sender;
 use hyper::StatusCode;
 use image_generations::CONFIDENTIAL_IMAGE_GENERATIONS_PATH;
@@ -19,7 +21,9 @@ use tracing::{info, instrument};
 
 use crate::{
     error::AtomaServiceError,
+    filters::ResponseFilterContext,
     middleware::EncryptionMetadata,
+    replication::ConcurrentRequests,
     server::{utils, AppState},
 };
 use atoma_state::types::AtomaAtomaStateManagerEvent;
@@ -42,7 +46,7 @@ const SIGNATURE_KEY: &str = "signature";
 /// Key for the usage in the response body
 pub const USAGE_KEY: &str = "usage";
 
-/// Updates response signature and stack hash state
+/// Applies the operator response filters, then updates response signature and stack hash state
 ///
 /// # Arguments
 ///
@@ -50,6 +54,7 @@ pub const USAGE_KEY: &str = "usage";
 /// * `payload_hash` - Hash of the original request payload
 /// * `state` - Application state containing keystore and state manager
 /// * `stack_small_id` - Identifier for the current stack
+/// * `request_id` - ID of the request, recorded on the state manager event
 ///
 /// # Returns
 ///
@@ -60,13 +65,23 @@ pub const USAGE_KEY: &str = "usage";
     fields(event = "sign-response-and-update-stack-hash",),
     err
 )]
-async fn sign_response_and_update_stack_hash(
+pub(crate) async fn sign_response_and_update_stack_hash(
     response_body: &mut Value,
     payload_hash: [u8; 32],
     state: &AppState,
     stack_small_id: i64,
     endpoint: String,
+    request_id: Option<&str>,
 ) -> Result<(), AtomaServiceError> {
+    // Apply the response filters, before the response body gets signed
+    state.filters.filter_response(
+        &ResponseFilterContext {
+            stack_small_id,
+            endpoint: &endpoint,
+        },
+        response_body,
+    )?;
+
     // Sign the response body byte content and add the base64 encoded signature to the response body
     let (response_hash, signature) =
         utils::sign_response_body(response_body, &state.keystore, state.address_index).map_err(
@@ -91,6 +106,7 @@ async fn sign_response_and_update_stack_hash(
         .send(AtomaAtomaStateManagerEvent::UpdateStackTotalHash {
             stack_small_id,
             total_hash: total_hash_bytes,
+            request_id: request_id.map(str::to_string),
         })
         .map_err(|e| AtomaServiceError::InternalError {
             message: format!("Error updating stack total hash: {}", e),
@@ -148,6 +164,7 @@ pub async fn handle_confidential_compute_encryption_response(
     if let Some(EncryptionMetadata {
         client_x25519_public_key,
         salt,
+        response_secret,
     }) = client_encryption_metadata
     {
         info!(
@@ -173,7 +190,6 @@ pub async fn handle_confidential_compute_encryption_response(
                 .map(|obj| obj.remove(RESPONSE_HASH_KEY));
         }
 
-        let (sender, receiver) = tokio::sync::oneshot::channel();
         let usage =
             if endpoint == CONFIDENTIAL_IMAGE_GENERATIONS_PATH {
                 None
@@ -185,26 +201,41 @@ pub async fn handle_confidential_compute_encryption_response(
                     }
                 })?)
             };
-        state
-            .encryption_sender
-            .send((
-                ConfidentialComputeEncryptionRequest {
-                    plaintext: response_body.to_string().as_bytes().to_vec(),
-                    salt,
-                    client_x25519_public_key,
-                },
-                sender,
-            ))
-            .map_err(|e| AtomaServiceError::InternalError {
-                message: format!("Error sending encryption request: {e}"),
-                endpoint: endpoint.clone(),
-            })?;
-        let result = receiver
-            .await
-            .map_err(|e| AtomaServiceError::InternalError {
-                message: format!("Error receiving encryption response: {e}"),
-                endpoint: endpoint.clone(),
-            })?;
+        let plaintext = response_body.to_string().as_bytes().to_vec();
+        let result = if let Some(response_secret) = response_secret {
+            // NOTE: HPKE responses are encrypted with the secret exported from the request
+            // HPKE context, which does not require the node's X25519 secret key
+            encrypt_plaintext_with_secret(&plaintext, &response_secret, &salt, None, &[])
+                .map(
+                    |(ciphertext, nonce)| ConfidentialComputeEncryptionResponse {
+                        ciphertext,
+                        nonce,
+                    },
+                )
+                .map_err(|e| anyhow::anyhow!(e))
+        } else {
+            let (sender, receiver) = tokio::sync::oneshot::channel();
+            state
+                .encryption_sender
+                .send((
+                    ConfidentialComputeEncryptionRequest {
+                        plaintext,
+                        salt,
+                        client_x25519_public_key,
+                    },
+                    sender,
+                ))
+                .map_err(|e| AtomaServiceError::InternalError {
+                    message: format!("Error sending encryption request: {e}"),
+                    endpoint: endpoint.clone(),
+                })?;
+            receiver
+                .await
+                .map_err(|e| AtomaServiceError::InternalError {
+                    message: format!("Error receiving encryption response: {e}"),
+                    endpoint: endpoint.clone(),
+                })?
+        };
         match result {
             Ok(ConfidentialComputeEncryptionResponse { ciphertext, nonce }) => {
                 let nonce = STANDARD.encode(nonce);
@@ -247,7 +278,13 @@ pub async fn handle_confidential_compute_encryption_response(
 /// * `state` - Application state containing the state manager channel
 /// * `stack_small_id` - Unique identifier for the stack being updated
 /// * `estimated_total_compute_units` - The estimated number of compute units that would have been used
+/// * `total_compute_units` - The number of compute units actually used (0 when the request failed)
+/// * `num_requests` - The number of requests served, counted in the usage of the stack (0 when the
+///   compute units locked for failed requests are released, the number of completed lines for
+///   batches)
 /// * `endpoint` - The API endpoint path where the request was received
+/// * `request_id` - ID of the request, recorded on the state manager event (if the update is
+///   not specific to a single request, e.g. for batches)
 ///
 /// # Returns
 ///
@@ -268,6 +305,7 @@ pub async fn handle_confidential_compute_encryption_response(
 /// - total_compute_units (always 0 for error cases)
 /// - payload_hash
 /// - endpoint
+/// - request_id
 ///
 /// # Example
 ///
@@ -280,6 +318,8 @@ pub async fn handle_confidential_compute_encryption_response(
 ///         state,
 ///         stack_id,
 ///         100, // estimated units
+///         0,   // used units
+///         0,   // served requests
 ///         "/v1/chat/completions"
 ///     ).await?;
 ///     Ok(())
@@ -293,7 +333,8 @@ pub async fn handle_confidential_compute_encryption_response(
         estimated_total_compute_units,
         total_compute_units,
         payload_hash,
-        endpoint
+        endpoint,
+        request_id
     ),
     err
 )]
@@ -302,15 +343,19 @@ pub fn update_stack_num_compute_units(
     stack_small_id: i64,
     estimated_total_compute_units: i64,
     total_compute_units: i64,
+    num_requests: i64,
     endpoint: &str,
     concurrent_requests: u64,
+    request_id: Option<&str>,
 ) -> Result<(), AtomaServiceError> {
     state_manager_sender
         .send(AtomaAtomaStateManagerEvent::UpdateStackNumComputeUnits {
             stack_small_id,
             total_compute_units,
             estimated_total_compute_units,
+            num_requests,
             concurrent_requests,
+            request_id: request_id.map(str::to_string),
         })
         .map_err(|e| AtomaServiceError::InternalError {
             message: format!("Error sending update stack num compute units event: {e}"),
@@ -318,82 +363,26 @@ pub fn update_stack_num_compute_units(
         })
 }
 
-/// Atomically decrements the concurrent request count for a specific stack ID in a `DashMap`.
-///
-/// If the count reaches zero after decrementing, the entry for the stack ID is removed from the map.
-/// This function uses the `DashMap::entry` API to ensure atomicity of the check-decrement-update/remove
-/// operation for a given key.
+/// Decrements the concurrent request count for a specific stack ID.
 ///
 /// # Arguments
 ///
-/// * `concurrent_requests_per_stack` - A reference to the `DashMap` storing `stack_small_id` -> `count`.
+/// * `concurrent_requests_per_stack` - The number of requests in flight for each stack.
 /// * `stack_small_id` - The identifier of the stack whose concurrent request count should be decremented.
 /// * `endpoint` - A string representing the API endpoint, used for logging context.
 ///
 /// # Returns
-/// Returns the new concurrent request count after decrement
-///
-/// Returns the concurrent request count for the `stack_small_id` *after* the decrement operation.
-/// If the entry did not exist, or if the count was already zero, it returns 0. If the count reached zero
-/// due to this decrement, it returns 0 after removing the entry.
 ///
-/// # Concurrency
-///
-/// The `DashMap::entry` method locks the relevant map shard, ensuring that the read, potential decrement,
-/// update, and potential removal operations occur atomically for the given `stack_small_id`.
-///
-/// # Logging
-///
-/// - Logs an error if an attempt is made to decrement the count for a `stack_small_id` that is not
-///   present in the map (implying a count of 0).
-/// - Logs an error if an attempt is made to decrement a count that is already 0.
-/// - Logs informational message when a count reaches 0 and the corresponding entry is removed.
-#[instrument(level = "info", skip_all, fields(stack_small_id, endpoint))]
+/// Returns the concurrent request count for the `stack_small_id` *after* the decrement operation,
+/// across all the replicas of the service if the state is shared (see [`ConcurrentRequests`]).
+/// If the stack had no request in flight on this replica, the count is left unchanged and an
+/// error is logged.
 pub fn handle_concurrent_requests_count_decrement(
-    concurrent_requests_per_stack: &DashMap<i64, u64>,
+    concurrent_requests_per_stack: &ConcurrentRequests,
     stack_small_id: i64,
     endpoint: &str,
 ) -> u64 {
-    let entry = concurrent_requests_per_stack.entry(stack_small_id);
-    match entry {
-        dashmap::mapref::entry::Entry::Occupied(mut occupied_entry) => {
-            let current_count = *occupied_entry.get();
-            if current_count == 0 {
-                tracing::error!(
-                    target = "atoma-service",
-                    level = "error",
-                    endpoint = endpoint,
-                );
-                occupied_entry.remove();
-                0
-            } else {
-                let new_count = current_count.saturating_sub(1);
-                occupied_entry.insert(new_count);
-                if new_count == 0 {
-                    tracing::info!(
-                        target = "atoma-service",
-                        level = "info",
-                        endpoint = endpoint,
-                        stack_small_id,
-                        "Concurrent requests count reached 0 for stack, removing entry and updating stack num compute units.",
-                    );
-                    occupied_entry.remove();
-                }
-                new_count
-            }
-        }
-        dashmap::mapref::entry::Entry::Vacant(_) => {
-            // Entry doesn't exist. This implies a count of 0.
-            tracing::error!(
-                target = "atoma-service",
-                level = "error",
-                endpoint = endpoint,
-                stack_small_id,
-                "Attempted to decrement concurrent requests for non-existent stack entry (implies count is 0).",
-            );
-            0
-        }
-    }
+    concurrent_requests_per_stack.decrement(stack_small_id, endpoint)
 }
 
 /// Handles the status code returned by the inference service.
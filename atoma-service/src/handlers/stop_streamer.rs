@@ -40,7 +40,7 @@ pub async fn stop_streamer_handler(
 
     app_state
         .client_dropped_streamer_connections
-        .insert(request_id.to_string());
+        .stop(request_id);
 
     Ok(Json("OK".to_string()))
 }
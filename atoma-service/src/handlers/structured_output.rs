@@ -0,0 +1,313 @@
+use std::fmt;
+
+use jsonschema::Validator;
+use opentelemetry::KeyValue;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::warn;
+
+use crate::error::AtomaServiceError;
+
+use super::{
+    chat_completions::openai_api::response_format::{ResponseFormat, ResponseFormatType},
+    metrics::CHAT_COMPLETIONS_STRUCTURED_OUTPUT_VALIDATION_FAILURES,
+};
+
+/// The key for the response format in the request body
+const RESPONSE_FORMAT_KEY: &str = "response_format";
+
+/// The key for the choices in the response body
+const CHOICES_KEY: &str = "choices";
+
+/// The key for the choice index in the response body
+const INDEX_KEY: &str = "index";
+
+/// The key for the choice message in the response body
+const MESSAGE_KEY: &str = "message";
+
+/// The key for the message content in the response body
+const CONTENT_KEY: &str = "content";
+
+/// The key for the structured output validation result, added to the response body
+pub const STRUCTURED_OUTPUT_VALIDATION_KEY: &str = "structured_output_validation";
+
+/// Validates chat completion outputs against the JSON schema of a strict `response_format`.
+///
+/// Inference services are expected to constrain the generated output to the requested
+/// schema, but nothing guarantees they do. When the request sets `strict`, the node checks
+/// the generated content of every choice against the schema and reports the result in the
+/// response, under the [`STRUCTURED_OUTPUT_VALIDATION_KEY`] key, before signing it.
+pub struct StructuredOutputValidator {
+    /// The name of the requested response format
+    name: String,
+    /// The compiled JSON schema of the requested response format
+    validator: Validator,
+}
+
+impl fmt::Debug for StructuredOutputValidator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StructuredOutputValidator")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The result of validating a chat completion output against the requested JSON schema
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct StructuredOutputValidation {
+    /// Whether the content of every choice matches the requested JSON schema
+    pub valid: bool,
+    /// The validation errors, for each choice whose content does not match the schema
+    pub errors: Vec<StructuredOutputValidationError>,
+}
+
+/// A validation error of the content of a chat completion choice
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct StructuredOutputValidationError {
+    /// The index of the choice
+    pub choice_index: u64,
+    /// JSON pointer to the invalid part of the content (empty for the content itself)
+    pub instance_path: String,
+    /// Description of the validation error
+    pub message: String,
+}
+
+impl StructuredOutputValidator {
+    /// Builds a validator from a chat completions request body.
+    ///
+    /// # Returns
+    ///
+    /// A validator if the request has a `json_schema` response format with `strict` set,
+    /// `None` otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AtomaServiceError::InvalidParameter` if the response format is strict but its
+    /// JSON schema is missing or is not a valid JSON schema.
+    pub fn from_request(body: &Value, endpoint: &str) -> Result<Option<Self>, AtomaServiceError> {
+        let Some(response_format) = body.get(RESPONSE_FORMAT_KEY) else {
+            return Ok(None);
+        };
+        // NOTE: Response formats that cannot be parsed are forwarded as is to the inference
+        // service, which is responsible for rejecting them.
+        let Ok(ResponseFormat {
+            format_type: ResponseFormatType::JsonSchema,
+            json_schema: Some(json_schema),
+        }) = serde_json::from_value::<ResponseFormat>(response_format.clone())
+        else {
+            return Ok(None);
+        };
+        if json_schema.strict != Some(true) {
+            return Ok(None);
+        }
+        let schema =
+            json_schema
+                .json_schema
+                .ok_or_else(|| AtomaServiceError::InvalidParameter {
+                    param: RESPONSE_FORMAT_KEY.to_string(),
+                    message: format!(
+                        "Strict response format {} has no JSON schema",
+                        json_schema.name
+                    ),
+                    endpoint: endpoint.to_string(),
+                })?;
+        let validator = jsonschema::validator_for(&schema).map_err(|e| {
+            AtomaServiceError::InvalidParameter {
+                param: RESPONSE_FORMAT_KEY.to_string(),
+                message: format!(
+                    "Invalid JSON schema for response format {}: {e}",
+                    json_schema.name
+                ),
+                endpoint: endpoint.to_string(),
+            }
+        })?;
+        Ok(Some(Self {
+            name: json_schema.name,
+            validator,
+        }))
+    }
+
+    /// Validates the generated content of each choice against the JSON schema.
+    ///
+    /// # Arguments
+    ///
+    /// * `contents` - The index and generated content of each choice, `None` if the choice
+    ///   has no content
+    pub fn validate<'a>(
+        &self,
+        contents: impl IntoIterator<Item = (u64, Option<&'a str>)>,
+    ) -> StructuredOutputValidation {
+        let mut errors = Vec::new();
+        for (choice_index, content) in contents {
+            let Some(content) = content else {
+                errors.push(StructuredOutputValidationError {
+                    choice_index,
+                    instance_path: String::new(),
+                    message: "Choice has no content".to_string(),
+                });
+                continue;
+            };
+            let instance = match serde_json::from_str::<Value>(content) {
+                Ok(instance) => instance,
+                Err(e) => {
+                    errors.push(StructuredOutputValidationError {
+                        choice_index,
+                        instance_path: String::new(),
+                        message: format!("Content is not valid JSON: {e}"),
+                    });
+                    continue;
+                }
+            };
+            errors.extend(self.validator.iter_errors(&instance).map(|error| {
+                StructuredOutputValidationError {
+                    choice_index,
+                    instance_path: error.instance_path.to_string(),
+                    message: error.to_string(),
+                }
+            }));
+        }
+        StructuredOutputValidation {
+            valid: errors.is_empty(),
+            errors,
+        }
+    }
+
+    /// Validates the choices of a non-streaming chat completion response, and adds the
+    /// validation result to the response body.
+    pub fn annotate_response(&self, response_body: &mut Value, model: &str) {
+        let validation = {
+            let contents = response_body
+                .get(CHOICES_KEY)
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .enumerate()
+                .map(|(position, choice)| {
+                    (
+                        choice
+                            .get(INDEX_KEY)
+                            .and_then(Value::as_u64)
+                            .unwrap_or(position as u64),
+                        choice
+                            .get(MESSAGE_KEY)
+                            .and_then(|message| message.get(CONTENT_KEY))
+                            .and_then(Value::as_str),
+                    )
+                });
+            self.validate(contents)
+        };
+        self.annotate(response_body, &validation, model);
+    }
+
+    /// Adds a validation result to a response body (or to the final chunk of a streamed
+    /// response), and records validation failures in metrics.
+    pub fn annotate(
+        &self,
+        response_body: &mut Value,
+        validation: &StructuredOutputValidation,
+        model: &str,
+    ) {
+        if !validation.valid {
+            warn!(
+                target = "atoma-service",
+                level = "warn",
+                response_format = self.name,
+                "Chat completion output does not match the requested JSON schema: {:?}",
+                validation.errors
+            );
+            CHAT_COMPLETIONS_STRUCTURED_OUTPUT_VALIDATION_FAILURES
+                .add(1, &[KeyValue::new("model", model.to_owned())]);
+        }
+        if let (Some(body), Ok(validation)) = (
+            response_body.as_object_mut(),
+            serde_json::to_value(validation),
+        ) {
+            body.insert(STRUCTURED_OUTPUT_VALIDATION_KEY.to_string(), validation);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn request(strict: Option<bool>) -> Value {
+        json!({
+            "model": "model",
+            "messages": [{"role": "user", "content": "Hello"}],
+            "response_format": {
+                "type": "json_schema",
+                "json_schema": {
+                    "name": "person",
+                    "schema": {
+                        "type": "object",
+                        "properties": {"name": {"type": "string"}, "age": {"type": "integer"}},
+                        "required": ["name", "age"],
+                    },
+                    "strict": strict,
+                },
+            },
+        })
+    }
+
+    #[test]
+    fn test_from_request() {
+        assert!(
+            StructuredOutputValidator::from_request(&request(Some(true)), "")
+                .unwrap()
+                .is_some()
+        );
+        assert!(
+            StructuredOutputValidator::from_request(&request(Some(false)), "")
+                .unwrap()
+                .is_none()
+        );
+        assert!(StructuredOutputValidator::from_request(&request(None), "")
+            .unwrap()
+            .is_none());
+        let json_object = json!({"model": "model", "response_format": {"type": "json_object"}});
+        assert!(StructuredOutputValidator::from_request(&json_object, "")
+            .unwrap()
+            .is_none());
+
+        let mut invalid_schema = request(Some(true));
+        invalid_schema["response_format"]["json_schema"]["schema"] = json!({"type": 42});
+        assert!(matches!(
+            StructuredOutputValidator::from_request(&invalid_schema, ""),
+            Err(AtomaServiceError::InvalidParameter { param, .. }) if param == RESPONSE_FORMAT_KEY
+        ));
+    }
+
+    #[test]
+    fn test_annotate_response() {
+        let validator = StructuredOutputValidator::from_request(&request(Some(true)), "")
+            .unwrap()
+            .unwrap();
+        let mut response = json!({
+            "choices": [
+                {"index": 0, "message": {"role": "assistant", "content": r#"{"name": "Alice", "age": 30}"#}},
+                {"index": 1, "message": {"role": "assistant", "content": r#"{"name": "Bob", "age": "thirty"}"#}},
+                {"index": 2, "message": {"role": "assistant", "content": "not json"}},
+            ],
+        });
+        validator.annotate_response(&mut response, "model");
+        let validation = &response[STRUCTURED_OUTPUT_VALIDATION_KEY];
+        assert_eq!(validation["valid"], false);
+        let errors = validation["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0]["choice_index"], 1);
+        assert_eq!(errors[0]["instance_path"], "/age");
+        assert_eq!(errors[1]["choice_index"], 2);
+
+        let mut response = json!({
+            "choices": [{"index": 0, "message": {"content": r#"{"name": "Alice", "age": 30}"#}}],
+        });
+        validator.annotate_response(&mut response, "model");
+        assert_eq!(
+            response[STRUCTURED_OUTPUT_VALIDATION_KEY],
+            json!({"valid": true, "errors": []})
+        );
+    }
+}
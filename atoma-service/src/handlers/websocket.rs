@@ -0,0 +1,587 @@
+//! WebSocket transport for streaming chat completions.
+//!
+//! Some clients (e.g. browser-based agents behind proxies buffering Server-Sent Events) cannot
+//! consume streamed chat completions over SSE. The WebSocket endpoints mirror
+//! `/v1/chat/completions` and `/v1/confidential/chat/completions`:
+//!
+//! 1. The client opens a WebSocket connection, and sends the request as the first (text)
+//!    message, together with the headers it would send to the HTTP endpoint (browsers cannot
+//!    set custom headers on WebSocket connections):
+//!    `{"headers": {"X-Signature": "...", "X-Stack-Small-Id": "..."}, "body": {...}}`
+//!    Connections on which no request is received in time are closed with a policy violation.
+//! 2. The request goes through the same middleware as the HTTP endpoint (signature or
+//!    confidential request verification, stack permissions and request filters).
+//! 3. Each chunk is sent as a text message, signed (and encrypted, for confidential requests)
+//!    exactly as the corresponding SSE event. The connection is closed once the stream ends.
+//!
+//! While the response is streamed, the client can send a `{"type": "cancel"}` message to stop
+//! the generation, which is accounted for as with `/v1/stop-streamer`: the next chunk is the
+//! last one, and includes the usage of the request.
+
+use std::{
+    collections::HashMap,
+    pin::pin,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use atoma_utils::constants::REQUEST_ID;
+use axum::{
+    body::Body,
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    http::{
+        header::{CONNECTION, CONTENT_TYPE, UPGRADE},
+        HeaderName, HeaderValue, Request,
+    },
+    middleware::from_fn,
+    response::Response,
+    routing::post,
+    Extension, Json, Router,
+};
+use futures::StreamExt;
+use hyper::{HeaderMap, StatusCode};
+use opentelemetry::KeyValue;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+use tower::ServiceExt;
+use tracing::{debug, error, info, instrument};
+
+use crate::{
+    error::AtomaServiceError,
+    handlers::{
+        chat_completions::{
+            start_streaming_response, utils::get_streaming_encryption_metadata,
+            CHAT_COMPLETIONS_PATH, CONFIDENTIAL_CHAT_COMPLETIONS_PATH,
+        },
+        handle_concurrent_requests_count_decrement,
+        metrics::{TOTAL_FAILED_CHAT_REQUESTS, TOTAL_FAILED_REQUESTS},
+        update_stack_num_compute_units,
+    },
+    middleware::{request_id_middleware, RequestMetadata},
+    resumable_streams::LAST_EVENT_ID,
+    server::{with_confidential_inference_middleware, with_inference_middleware, AppState},
+    streamer::Streamer,
+    trace_context::generate_request_id,
+};
+
+/// The path for WebSocket chat completions requests
+pub const WEBSOCKET_CHAT_COMPLETIONS_PATH: &str = "/v1/ws/chat/completions";
+
+/// The path for confidential WebSocket chat completions requests
+pub const CONFIDENTIAL_WEBSOCKET_CHAT_COMPLETIONS_PATH: &str =
+    "/v1/ws/confidential/chat/completions";
+
+/// The key for the stream flag in the request body
+const STREAM_KEY: &str = "stream";
+
+/// The key for the model in the request body
+const MODEL_KEY: &str = "model";
+
+/// Prefix of the headers that are specific to the WebSocket handshake
+const SEC_WEBSOCKET_HEADER_PREFIX: &str = "sec-websocket-";
+
+/// Maximum duration to wait for the request message, after the connection is opened
+const FIRST_MESSAGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The first message of a WebSocket chat completions connection
+#[derive(Debug, Deserialize)]
+struct WebSocketChatCompletionsRequest {
+    /// The headers of the request, as sent to the HTTP endpoint (added to the headers of the
+    /// WebSocket handshake request)
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    /// The body of the request, as sent to the HTTP endpoint
+    body: Value,
+}
+
+/// Messages sent by the client while the response is streamed
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum WebSocketClientMessage {
+    /// Stops the generation, the next chunk being the last one
+    Cancel,
+}
+
+/// Hands the [`Streamer`] of a request over from the HTTP middleware stack to the WebSocket
+/// connection, through the request extensions.
+#[derive(Clone)]
+struct StreamerSender(Arc<Mutex<Option<oneshot::Sender<Streamer>>>>);
+
+impl StreamerSender {
+    fn new(sender: oneshot::Sender<Streamer>) -> Self {
+        Self(Arc::new(Mutex::new(Some(sender))))
+    }
+
+    /// Sends the streamer to the WebSocket connection.
+    ///
+    /// If the connection is gone, the streamer is dropped, which settles the compute units of
+    /// the request as for a client dropping an SSE connection.
+    fn send(&self, streamer: Streamer) {
+        let sender = self
+            .0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .take();
+        if let Some(sender) = sender {
+            let _ = sender.send(streamer);
+        }
+    }
+}
+
+/// Chat completions over WebSocket
+///
+/// Upgrades the connection to a WebSocket, on which chat completion chunks are streamed, as
+/// for `/v1/chat/completions` requests with `stream` set.
+#[instrument(level = "info", skip_all, fields(path = WEBSOCKET_CHAT_COMPLETIONS_PATH))]
+pub async fn websocket_chat_completions_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    websocket: WebSocketUpgrade,
+) -> Response {
+    // NOTE: The connection is counted as in-flight while it streams, as the HTTP response of the
+    // upgrade is sent before the chat completion starts
+    let in_flight = state.drain.track();
+    websocket.on_upgrade(move |socket| async move {
+        handle_socket(socket, state, headers, CHAT_COMPLETIONS_PATH).await;
+        drop(in_flight);
+    })
+}
+
+/// Confidential chat completions over WebSocket
+///
+/// Upgrades the connection to a WebSocket, on which encrypted chat completion chunks are
+/// streamed, as for `/v1/confidential/chat/completions` requests with `stream` set.
+#[instrument(
+    level = "info",
+    skip_all,
+    fields(path = CONFIDENTIAL_WEBSOCKET_CHAT_COMPLETIONS_PATH)
+)]
+pub async fn confidential_websocket_chat_completions_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    websocket: WebSocketUpgrade,
+) -> Response {
+    let in_flight = state.drain.track();
+    websocket.on_upgrade(move |socket| async move {
+        handle_socket(socket, state, headers, CONFIDENTIAL_CHAT_COMPLETIONS_PATH).await;
+        drop(in_flight);
+    })
+}
+
+/// Serves a chat completions request over a WebSocket connection.
+///
+/// # Arguments
+///
+/// * `socket` - The WebSocket connection
+/// * `state` - The shared application state
+/// * `handshake_headers` - The headers of the WebSocket handshake request
+/// * `path` - The path of the HTTP endpoint the WebSocket endpoint mirrors
+async fn handle_socket(
+    mut socket: WebSocket,
+    state: AppState,
+    handshake_headers: HeaderMap,
+    path: &'static str,
+) {
+    let Ok(message) = tokio::time::timeout(FIRST_MESSAGE_TIMEOUT, socket.recv()).await else {
+        // NOTE: Idle connections would otherwise hold a task (and a file descriptor) forever
+        debug!(
+            target = "atoma-service",
+            level = "debug",
+            "No WebSocket chat completions request received within {FIRST_MESSAGE_TIMEOUT:?}"
+        );
+        let _ = socket
+            .send(Message::Close(Some(close_frame(
+                StatusCode::REQUEST_TIMEOUT,
+            ))))
+            .await;
+        return;
+    };
+    let request = match message {
+        Some(Ok(Message::Text(text))) => {
+            serde_json::from_str::<WebSocketChatCompletionsRequest>(text.as_str()).map_err(|e| {
+                AtomaServiceError::InvalidBody {
+                    message: format!("Invalid WebSocket chat completions request: {e}"),
+                    endpoint: path.to_string(),
+                }
+            })
+        }
+        Some(Ok(_)) => Err(AtomaServiceError::InvalidBody {
+            message: "Expected a text message with the chat completions request".to_string(),
+            endpoint: path.to_string(),
+        }),
+        Some(Err(_)) | None => return,
+    };
+    let (mut request, request_id) =
+        match request.and_then(|request| build_request(path, &handshake_headers, request)) {
+            Ok(request) => request,
+            Err(e) => {
+                close_with_error(socket, &e).await;
+                return;
+            }
+        };
+
+    let (streamer_sender, streamer_receiver) = oneshot::channel();
+    request
+        .extensions_mut()
+        .insert(StreamerSender::new(streamer_sender));
+    let response = match streaming_router(state.clone()).oneshot(request).await {
+        Ok(response) => response,
+        Err(infallible) => match infallible {},
+    };
+    let Ok(streamer) = streamer_receiver.await else {
+        // NOTE: The request was rejected by the middleware or the handler, whose error
+        // response is forwarded to the client
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap_or_default();
+        let _ = socket
+            .send(Message::Text(
+                String::from_utf8_lossy(&body).into_owned().into(),
+            ))
+            .await;
+        let _ = socket.send(Message::Close(Some(close_frame(status)))).await;
+        return;
+    };
+
+    stream_chunks(socket, &state, streamer, &request_id, path).await;
+}
+
+/// Streams the chunks of a response to the client, while handling the client messages.
+async fn stream_chunks(
+    mut socket: WebSocket,
+    state: &AppState,
+    streamer: Streamer,
+    request_id: &str,
+    path: &str,
+) {
+    let mut chunks = pin!(streamer.into_chunks());
+    loop {
+        tokio::select! {
+            chunk = chunks.next() => match chunk {
+                Some(Ok(chunk)) => {
+                    if socket.send(Message::Text(chunk.to_string().into())).await.is_err() {
+                        // NOTE: Dropping the streamer settles the compute units of the request
+                        break;
+                    }
+                }
+                Some(Err(e)) => {
+                    let error = AtomaServiceError::InternalError {
+                        message: format!("Error streaming chat completions response: {e}"),
+                        endpoint: path.to_string(),
+                    };
+                    close_with_error(socket, &error).await;
+                    return;
+                }
+                None => {
+                    let _ = socket.send(Message::Close(Some(close_frame(StatusCode::OK)))).await;
+                    return;
+                }
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    match serde_json::from_str::<WebSocketClientMessage>(text.as_str()) {
+                        Ok(WebSocketClientMessage::Cancel) => {
+                            info!(
+                                target = "atoma-service",
+                                level = "info",
+                                request_id,
+                                "Client cancelled WebSocket chat completions stream"
+                            );
+                            state.client_dropped_streamer_connections.stop(request_id);
+                        }
+                        Err(e) => debug!(
+                            target = "atoma-service",
+                            level = "debug",
+                            request_id,
+                            "Ignoring invalid WebSocket client message: {e}"
+                        ),
+                    }
+                }
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                // NOTE: Pings are answered automatically
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+/// Builds the HTTP request mirrored by a WebSocket chat completions request.
+///
+/// # Returns
+///
+/// The request, and its request ID (generated if the client did not provide one, so that the
+/// client can cancel the stream).
+///
+/// # Errors
+///
+/// Returns `AtomaServiceError::InvalidHeader` if a header of the request is invalid.
+fn build_request(
+    path: &str,
+    handshake_headers: &HeaderMap,
+    request: WebSocketChatCompletionsRequest,
+) -> Result<(Request<Body>, String), AtomaServiceError> {
+    let mut headers = handshake_headers
+        .iter()
+        .filter(|(name, _)| {
+            *name != CONNECTION
+                && *name != UPGRADE
+                && !name.as_str().starts_with(SEC_WEBSOCKET_HEADER_PREFIX)
+        })
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect::<HeaderMap>();
+    for (name, value) in request.headers {
+        let name = HeaderName::from_str(&name).map_err(|e| AtomaServiceError::InvalidHeader {
+            message: format!("Invalid header name {name}: {e}"),
+            endpoint: path.to_string(),
+        })?;
+        let value =
+            HeaderValue::from_str(&value).map_err(|e| AtomaServiceError::InvalidHeader {
+                message: format!("Invalid value for header {name}: {e}"),
+                endpoint: path.to_string(),
+            })?;
+        headers.insert(name, value);
+    }
+    // NOTE: Only Server-Sent Events streams can be resumed
+    headers.remove(LAST_EVENT_ID);
+    let request_id = match headers.get(REQUEST_ID) {
+        Some(request_id) => request_id
+            .to_str()
+            .map_err(|_| AtomaServiceError::InvalidHeader {
+                message: "Request ID header is invalid, cannot be converted to string".to_string(),
+                endpoint: path.to_string(),
+            })?
+            .to_string(),
+        None => {
+            let request_id = generate_request_id();
+            headers.insert(
+                REQUEST_ID,
+                HeaderValue::from_str(&request_id).expect("Hex string is a valid header value"),
+            );
+            request_id
+        }
+    };
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    let mut http_request = Request::post(path)
+        .body(Body::from(request.body.to_string()))
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Failed to build request: {e}"),
+            endpoint: path.to_string(),
+        })?;
+    *http_request.headers_mut() = headers;
+    Ok((http_request, request_id))
+}
+
+/// The router serving WebSocket requests, once received, through the middleware of the HTTP
+/// endpoints they mirror.
+fn streaming_router(state: AppState) -> Router {
+    let regular_routes = Router::new().route(CHAT_COMPLETIONS_PATH, post(start_streaming_handler));
+    let confidential_routes = Router::new().route(
+        CONFIDENTIAL_CHAT_COMPLETIONS_PATH,
+        post(start_streaming_handler),
+    );
+    Router::new()
+        .merge(with_inference_middleware(regular_routes, &state))
+        .merge(with_confidential_inference_middleware(
+            confidential_routes,
+            &state,
+        ))
+        .with_state(state)
+        .layer(from_fn(request_id_middleware))
+}
+
+/// Starts streaming the response of a WebSocket chat completions request, once the request
+/// went through the middleware of the HTTP endpoint it mirrors, and hands the resulting
+/// [`Streamer`] over to the WebSocket connection.
+///
+/// # Errors
+///
+/// Returns an error if the request cannot be forwarded to the inference service, in which case
+/// the compute units locked for the request are released.
+#[instrument(
+    level = "info",
+    skip_all,
+    fields(path = request_metadata.endpoint_path),
+    err
+)]
+async fn start_streaming_handler(
+    Extension(request_metadata): Extension<RequestMetadata>,
+    Extension(streamer_sender): Extension<StreamerSender>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(mut payload): Json<Value>,
+) -> Result<StatusCode, AtomaServiceError> {
+    let RequestMetadata {
+        stack_small_id,
+        estimated_total_compute_units,
+        num_input_tokens,
+        payload_hash,
+        client_encryption_metadata,
+        structured_output_validator,
+        endpoint_path: endpoint,
+        request_id,
+        ..
+    } = request_metadata;
+    // NOTE: Responses to WebSocket requests are always streamed
+    payload[STREAM_KEY] = json!(true);
+    let model = payload
+        .get(MODEL_KEY)
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+
+    let streamer = async {
+        let streaming_encryption_metadata = get_streaming_encryption_metadata(
+            &state,
+            client_encryption_metadata,
+            payload_hash,
+            stack_small_id,
+            &endpoint,
+        )
+        .await?;
+        start_streaming_response(
+            &state,
+            payload,
+            stack_small_id,
+            num_input_tokens,
+            estimated_total_compute_units,
+            payload_hash,
+            streaming_encryption_metadata,
+            structured_output_validator,
+            endpoint.clone(),
+            &headers,
+        )
+        .await
+    }
+    .await;
+
+    match streamer {
+        Ok(streamer) => {
+            streamer_sender.send(streamer);
+            Ok(StatusCode::OK)
+        }
+        Err(e) => {
+            TOTAL_FAILED_CHAT_REQUESTS.add(1, &[KeyValue::new("model", model.clone())]);
+            TOTAL_FAILED_REQUESTS.add(1, &[KeyValue::new("model", model)]);
+            // NOTE: The service failed to generate a response, so the compute units locked for
+            // the request are released, and the concurrent requests count is decremented.
+            let concurrent_requests = handle_concurrent_requests_count_decrement(
+                &state.concurrent_requests_per_stack,
+                stack_small_id,
+                "websocket/start_streaming_handler",
+            );
+            update_stack_num_compute_units(
+                &state.state_manager_sender,
+                stack_small_id,
+                estimated_total_compute_units,
+                0,
+                0,
+                &endpoint,
+                concurrent_requests,
+                request_id.as_deref(),
+            )?;
+            Err(e)
+        }
+    }
+}
+
+/// Sends an error to the client, and closes the connection.
+async fn close_with_error(mut socket: WebSocket, error: &AtomaServiceError) {
+    error!(
+        target = "atoma-service",
+        level = "error",
+        "WebSocket chat completions request failed: {error}"
+    );
+    if let Ok(error_response) = serde_json::to_string(&error.error_response()) {
+        let _ = socket.send(Message::Text(error_response.into())).await;
+    }
+    let _ = socket
+        .send(Message::Close(Some(close_frame(error.status_code()))))
+        .await;
+}
+
+/// Returns the frame closing a WebSocket connection, for the status of a request.
+fn close_frame(status: StatusCode) -> CloseFrame {
+    let code = if status.is_success() {
+        close_code::NORMAL
+    } else if status.is_client_error() {
+        close_code::POLICY
+    } else {
+        close_code::ERROR
+    };
+    CloseFrame {
+        code,
+        reason: status.canonical_reason().unwrap_or_default().into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_request() {
+        let mut handshake_headers = HeaderMap::new();
+        handshake_headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+        handshake_headers.insert(
+            "Sec-WebSocket-Key",
+            HeaderValue::from_static("dGhlIHNhbXBsZSBub25jZQ=="),
+        );
+        handshake_headers.insert("X-Stack-Small-Id", HeaderValue::from_static("1"));
+        let request = serde_json::from_value::<WebSocketChatCompletionsRequest>(json!({
+            "headers": {"X-Signature": "signature", "X-Stack-Small-Id": "2"},
+            "body": {"model": "model", "messages": [{"role": "user", "content": "Hello"}]},
+        }))
+        .unwrap();
+
+        let (request, request_id) =
+            build_request(CHAT_COMPLETIONS_PATH, &handshake_headers, request).unwrap();
+        assert_eq!(request.uri(), CHAT_COMPLETIONS_PATH);
+        let headers = request.headers();
+        assert!(headers.get(UPGRADE).is_none());
+        assert!(headers.get("Sec-WebSocket-Key").is_none());
+        assert_eq!(headers["X-Signature"], "signature");
+        // Headers of the request take precedence over the handshake headers
+        assert_eq!(headers["X-Stack-Small-Id"], "2");
+        assert_eq!(headers[REQUEST_ID], request_id.as_str());
+        assert_eq!(headers[CONTENT_TYPE], "application/json");
+
+        let request = serde_json::from_value::<WebSocketChatCompletionsRequest>(json!({
+            "headers": {"X-Request-Id": "request-id", "Invalid Header": "value"},
+            "body": {},
+        }))
+        .unwrap();
+        assert!(matches!(
+            build_request(CHAT_COMPLETIONS_PATH, &HeaderMap::new(), request),
+            Err(AtomaServiceError::InvalidHeader { .. })
+        ));
+    }
+
+    #[test]
+    fn test_client_messages() {
+        assert_eq!(
+            serde_json::from_str::<WebSocketClientMessage>(r#"{"type": "cancel"}"#).unwrap(),
+            WebSocketClientMessage::Cancel
+        );
+        assert!(serde_json::from_str::<WebSocketClientMessage>(r#"{"type": "pause"}"#).is_err());
+    }
+
+    #[test]
+    fn test_close_frame() {
+        assert_eq!(close_frame(StatusCode::OK).code, close_code::NORMAL);
+        // Connections on which no request is received are closed with a policy violation
+        assert_eq!(
+            close_frame(StatusCode::REQUEST_TIMEOUT).code,
+            close_code::POLICY
+        );
+        assert_eq!(
+            close_frame(StatusCode::INTERNAL_SERVER_ERROR).code,
+            close_code::ERROR
+        );
+    }
+}
@@ -12,13 +12,23 @@
 #![allow(clippy::items_after_statements)]
 #![allow(clippy::uninlined_format_args)]
 
+pub mod batch_processor;
 pub(crate) mod components;
 pub mod config;
+pub mod drain;
 pub mod error;
+pub mod filters;
 pub(crate) mod handlers;
 pub mod middleware;
+pub mod model_sync;
+pub mod models;
+pub mod readiness;
+pub mod replication;
+pub mod resumable_streams;
 pub mod server;
 pub mod streamer;
 #[cfg(test)]
 mod tests;
+pub mod tls;
+pub mod trace_context;
 pub mod types;
@@ -1,18 +1,35 @@
-use std::str::FromStr;
+use std::{str::FromStr, sync::Arc};
 
 use crate::{
     error::AtomaServiceError,
+    filters::RequestFilterContext,
     handlers::{
-        chat_completions::CHAT_COMPLETIONS_PATH, embeddings::EMBEDDINGS_PATH,
-        image_generations::IMAGE_GENERATIONS_PATH, request_model::ComputeUnitsEstimate,
+        chat_completions::{
+            sse_response, CHAT_COMPLETIONS_PATH, CONFIDENTIAL_CHAT_COMPLETIONS_PATH,
+        },
+        embeddings::EMBEDDINGS_PATH,
+        handle_concurrent_requests_count_decrement,
+        image_generations::{IMAGE_EDITS_PATH, IMAGE_GENERATIONS_PATH, IMAGE_VARIATIONS_PATH},
+        request_model::ComputeUnitsEstimate,
+        structured_output::StructuredOutputValidator,
+        update_stack_num_compute_units,
     },
+    resumable_streams::{ResumeError, StreamOwner, LAST_EVENT_ID},
     server::AppState,
+    trace_context::{extract_trace_context, generate_request_id, MAX_REQUEST_ID_LENGTH},
     types::ConfidentialComputeRequest,
 };
-use atoma_confidential::types::{ConfidentialComputeDecryptionRequest, DH_PUBLIC_KEY_SIZE};
+use atoma_confidential::types::{
+    ConfidentialComputeDecryptionRequest, ConfidentialComputeDecryptionResponse, DH_PUBLIC_KEY_SIZE,
+};
 use atoma_state::types::{AtomaAtomaStateManagerEvent, StackAvailability};
 use atoma_utils::{
-    constants::{NONCE_SIZE, PAYLOAD_HASH_SIZE, SALT_SIZE},
+    constants::{NONCE_SIZE, PAYLOAD_HASH_SIZE, REQUEST_ID, SALT_SIZE},
+    encryption::{
+        confidential_request_associated_data, AeadAlgorithm, SymmetricSecret,
+        CONFIDENTIAL_PROTOCOL_VERSION_HPKE, CONFIDENTIAL_PROTOCOL_VERSION_V1,
+        CONFIDENTIAL_PROTOCOL_VERSION_V2,
+    },
     hashing::blake2b_hash,
     verify_signature,
 };
@@ -31,7 +48,8 @@ use sui_sdk::types::{
     digests::TransactionDigest,
 };
 use tokio::sync::oneshot;
-use tracing::instrument;
+use tracing::{info_span, instrument, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 /// Body size limit for signature verification (contains the body size of the request)
 const MAX_BODY_SIZE: usize = 1024 * 1024; // 1MB
@@ -49,6 +67,10 @@ pub struct DecryptionMetadata {
 
     /// The salt
     pub salt: [u8; SALT_SIZE],
+
+    /// The secret to encrypt the response with, exported from the request HPKE context
+    /// (only set for HPKE requests)
+    pub response_secret: Option<SymmetricSecret>,
 }
 
 /// Metadata for confidential compute encryption requests
@@ -58,6 +80,9 @@ pub struct EncryptionMetadata {
     pub client_x25519_public_key: [u8; DH_PUBLIC_KEY_SIZE],
     /// The salt
     pub salt: [u8; SALT_SIZE],
+    /// The secret to encrypt the response with, for HPKE requests. If not set, the response
+    /// is encrypted with the shared secret of the client and node X25519 keys
+    pub response_secret: Option<SymmetricSecret>,
 }
 
 /// Metadata extracted from the request
@@ -77,6 +102,12 @@ pub struct RequestMetadata {
     pub client_encryption_metadata: Option<EncryptionMetadata>,
     /// endpoint path
     pub endpoint_path: String,
+    /// Validator for the chat completion output, if the request has a strict JSON schema
+    /// response format
+    pub structured_output_validator: Option<Arc<StructuredOutputValidator>>,
+    /// The request ID, forwarded to the inference services and recorded on the state manager
+    /// events of the request
+    pub request_id: Option<String>,
 }
 
 /// The type of request
@@ -138,6 +169,7 @@ impl RequestMetadata {
     ///
     /// * `client_dh_public_key` - The client's Diffie-Hellman public key
     /// * `salt` - The salt
+    /// * `response_secret` - The secret to encrypt the response with, for HPKE requests
     ///
     /// # Returns
     /// Returns self with the updated client's encryption metadata for method chaining
@@ -147,17 +179,19 @@ impl RequestMetadata {
     /// use atoma_service::middleware::RequestMetadata;
     ///
     /// let metadata = RequestMetadata::default()
-    ///     .with_client_encryption_metadata(client_dh_public_key, salt);
+    ///     .with_client_encryption_metadata(client_dh_public_key, salt, None);
     /// ```
     #[must_use]
     pub const fn with_client_encryption_metadata(
         mut self,
         client_x25519_public_key: [u8; DH_PUBLIC_KEY_SIZE],
         salt: [u8; SALT_SIZE],
+        response_secret: Option<SymmetricSecret>,
     ) -> Self {
         self.client_encryption_metadata = Some(EncryptionMetadata {
             client_x25519_public_key,
             salt,
+            response_secret,
         });
         self
     }
@@ -180,6 +214,123 @@ impl RequestMetadata {
         self.endpoint_path = endpoint_path;
         self
     }
+
+    /// Sets the structured output validator for this metadata instance
+    ///
+    /// * `structured_output_validator` - Validator for the chat completion output, if the
+    ///   request has a strict JSON schema response format
+    ///
+    /// # Returns
+    /// Returns self with the updated structured output validator for method chaining
+    #[must_use]
+    pub fn with_structured_output_validator(
+        mut self,
+        structured_output_validator: Option<Arc<StructuredOutputValidator>>,
+    ) -> Self {
+        self.structured_output_validator = structured_output_validator;
+        self
+    }
+
+    /// Sets the request ID for this metadata instance
+    ///
+    /// * `request_id` - The ID of the request
+    ///
+    /// # Returns
+    /// Returns self with the updated request ID for method chaining
+    #[must_use]
+    pub fn with_request_id(mut self, request_id: String) -> Self {
+        self.request_id = Some(request_id);
+        self
+    }
+}
+
+/// Middleware assigning a request ID to incoming requests, and tracing them.
+///
+/// The request ID is taken from the `X-Request-Id` header, or generated if the client did not
+/// provide one. It is:
+/// - set on the request headers, and on the `RequestMetadata` extension of the request, so that
+///   handlers forward it to the inference services and record it on state manager events,
+/// - recorded on a span wrapping the processing of the request, so that it is attached to the
+///   spans of all the middleware and handlers of the request,
+/// - returned to the client in the `X-Request-Id` response header.
+///
+/// If the request has a W3C `traceparent` header, the request span continues the trace of the
+/// client.
+///
+/// # Errors
+/// Returns a `BAD_REQUEST` status code if the `X-Request-Id` header is not a valid string, or
+/// is longer than [`MAX_REQUEST_ID_LENGTH`] bytes.
+pub async fn request_id_middleware(
+    mut req: Request<Body>,
+    next: Next,
+) -> Result<Response, AtomaServiceError> {
+    let endpoint = req.uri().path().to_string();
+    let request_id = match req.headers().get(REQUEST_ID) {
+        Some(request_id) => {
+            let request_id = request_id
+                .to_str()
+                .map_err(|_| AtomaServiceError::InvalidHeader {
+                    message: "Request ID header is invalid, cannot be converted to string"
+                        .to_string(),
+                    endpoint: endpoint.clone(),
+                })?;
+            if request_id.is_empty() || request_id.len() > MAX_REQUEST_ID_LENGTH {
+                return Err(AtomaServiceError::InvalidHeader {
+                    message: format!(
+                        "Request ID header must be between 1 and {MAX_REQUEST_ID_LENGTH} bytes long"
+                    ),
+                    endpoint,
+                });
+            }
+            request_id.to_string()
+        }
+        None => generate_request_id(),
+    };
+    let request_id_header =
+        HeaderValue::from_str(&request_id).expect("Request ID is a valid header value");
+    req.headers_mut()
+        .insert(REQUEST_ID, request_id_header.clone());
+    let request_metadata = req
+        .extensions()
+        .get::<RequestMetadata>()
+        .cloned()
+        .unwrap_or_default()
+        .with_request_id(request_id.clone());
+    req.extensions_mut().insert(request_metadata);
+
+    let span = info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        endpoint = %endpoint,
+    );
+    span.set_parent(extract_trace_context(req.headers()));
+    let mut response = next.run(req).instrument(span).await;
+    response.headers_mut().insert(REQUEST_ID, request_id_header);
+    Ok(response)
+}
+
+/// Middleware rejecting new requests while the service is draining, on shutdown, and tracking
+/// the in-flight requests otherwise.
+///
+/// Requests are counted as in-flight until their response body is fully sent, so that the
+/// drain phase also waits for streamed responses.
+///
+/// # Errors
+/// Returns a `SERVICE_UNAVAILABLE` status code if the service is draining.
+pub async fn drain_middleware(
+    state: State<AppState>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, AtomaServiceError> {
+    if state.drain.is_draining() {
+        return Err(AtomaServiceError::ShuttingDown {
+            endpoint: req.uri().path().to_string(),
+        });
+    }
+    let request = state.drain.track();
+    let response = next.run(req).await;
+    Ok(response.map(|body| request.attach(body)))
 }
 
 /// Middleware for verifying the signature of incoming requests.
@@ -249,18 +400,27 @@ pub async fn signature_verification_middleware(
             message: format!("Failed to convert signature to string, with error: {e}"),
             endpoint: endpoint.clone(),
         })?;
-    let body_bytes = axum::body::to_bytes(req_body, MAX_BODY_SIZE)
-        .await
-        .map_err(|e| AtomaServiceError::InvalidBody {
-            message: format!("Failed to convert body to bytes, with error: {e}"),
-            endpoint: endpoint.clone(),
-        })?;
-    let body_json: Value =
-        serde_json::from_slice(&body_bytes).map_err(|e| AtomaServiceError::InvalidBody {
-            message: format!("Failed to parse body as JSON, with error: {e}"),
-            endpoint: endpoint.clone(),
-        })?;
-    let body_blake2b_hash = blake2b_hash(body_json.to_string().as_bytes());
+    let body_bytes = axum::body::to_bytes(
+        req_body,
+        utils::max_body_size(&endpoint, &req_parts.headers),
+    )
+    .await
+    .map_err(|e| AtomaServiceError::InvalidBody {
+        message: format!("Failed to convert body to bytes, with error: {e}"),
+        endpoint: endpoint.clone(),
+    })?;
+    // NOTE: Multipart bodies (image edits and variations) are signed as is, as they cannot be
+    // canonicalized as JSON bodies are
+    let body_blake2b_hash = if utils::is_multipart(&req_parts.headers) {
+        blake2b_hash(&body_bytes)
+    } else {
+        let body_json: Value =
+            serde_json::from_slice(&body_bytes).map_err(|e| AtomaServiceError::InvalidBody {
+                message: format!("Failed to parse body as JSON, with error: {e}"),
+                endpoint: endpoint.clone(),
+            })?;
+        blake2b_hash(body_json.to_string().as_bytes())
+    };
     let body_blake2b_hash_bytes: [u8; 32] = body_blake2b_hash
         .as_slice()
         .try_into()
@@ -283,6 +443,138 @@ pub async fn signature_verification_middleware(
     Ok(next.run(req).await)
 }
 
+/// Middleware resuming streamed chat completions, for clients reconnecting after their
+/// connection dropped.
+///
+/// Requests with the `Last-Event-ID` header resume the stream of the request with the same
+/// request ID, which must have been signed by the same client, for the same payload and stack.
+/// The events following the last event received by the client are streamed back, without
+/// locking compute units again, as the request is charged once, when its generation ends.
+///
+/// As the request ID identifies the stream to resume, other chat completions requests cannot
+/// reuse the request ID of a stream, while it can still be resumed.
+///
+/// This middleware runs after the request signature is verified (or the confidential request
+/// is decrypted), and before [`verify_stack_permissions`], so that no compute units are locked
+/// for rejected requests. Other requests, or all requests if resumable streams are disabled,
+/// are passed through.
+///
+/// # Headers
+/// The middleware expects the following headers, for requests resuming a stream:
+/// - `Last-Event-ID`: The ID of the last event received by the client.
+/// - `X-Request-Id`: The request ID of the stream.
+/// - `X-Stack-Small-Id`: The ID of the stack the request is charged to.
+///
+/// # Errors
+/// Returns a `BAD_REQUEST` status code if:
+/// - Required headers are missing or invalid.
+/// - The events following the last event received are no longer buffered.
+///
+/// Returns an `UNAUTHORIZED` status code if the request does not match the request the stream
+/// was started for.
+///
+/// Returns a `NOT_FOUND` status code if there is no stream to resume for the request ID.
+///
+/// Returns a `CONFLICT` status code if a request without the `Last-Event-ID` header reuses the
+/// request ID of a stream.
+#[instrument(
+    level = "info",
+    skip_all,
+    fields(
+        endpoint = %req.uri().path(),
+    ),
+    err
+)]
+pub async fn resume_stream_middleware(
+    state: State<AppState>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, AtomaServiceError> {
+    let endpoint = req.uri().path().to_string();
+    let is_chat_completions = matches!(
+        endpoint.as_str(),
+        CHAT_COMPLETIONS_PATH | CONFIDENTIAL_CHAT_COMPLETIONS_PATH
+    );
+    if !is_chat_completions || !state.resumable_streams.is_enabled() {
+        return Ok(next.run(req).await);
+    }
+    if !req.headers().contains_key(LAST_EVENT_ID) {
+        let request_id = req
+            .headers()
+            .get(atoma_utils::constants::REQUEST_ID)
+            .and_then(|request_id| request_id.to_str().ok());
+        if let Some(request_id) = request_id {
+            if state.resumable_streams.contains(request_id) {
+                return Err(AtomaServiceError::Conflict {
+                    message: ResumeError::DuplicateRequestId(request_id.to_string()).to_string(),
+                    endpoint,
+                });
+            }
+        }
+        return Ok(next.run(req).await);
+    }
+
+    let last_event_id = req
+        .headers()
+        .get(LAST_EVENT_ID)
+        .and_then(|last_event_id| last_event_id.to_str().ok())
+        .and_then(|last_event_id| last_event_id.parse::<u64>().ok())
+        .ok_or_else(|| AtomaServiceError::InvalidHeader {
+            message: "Last event ID is not a valid event ID".to_string(),
+            endpoint: endpoint.clone(),
+        })?;
+    let request_id = req
+        .headers()
+        .get(atoma_utils::constants::REQUEST_ID)
+        .ok_or_else(|| AtomaServiceError::MissingHeader {
+            header: atoma_utils::constants::REQUEST_ID.to_string(),
+            endpoint: endpoint.clone(),
+        })?
+        .to_str()
+        .map_err(|e| AtomaServiceError::InvalidHeader {
+            message: format!("Request ID cannot be converted to a string, with error: {e}"),
+            endpoint: endpoint.clone(),
+        })?;
+    let payload_hash = req
+        .extensions()
+        .get::<RequestMetadata>()
+        .map(|request_metadata| request_metadata.payload_hash)
+        .ok_or_else(|| AtomaServiceError::InternalError {
+            message: "Request payload hash is not set".to_string(),
+            endpoint: endpoint.clone(),
+        })?;
+    let stream_owner = StreamOwner {
+        stack_small_id: utils::get_stack_small_id(req.headers(), &endpoint)?,
+        sui_address: utils::get_sui_address(req.headers(), &endpoint)?,
+        payload_hash,
+    };
+
+    let chunks = state
+        .resumable_streams
+        .resume(request_id, &stream_owner, last_event_id)
+        .map_err(|e| match e {
+            ResumeError::StreamNotFound(_) => AtomaServiceError::NotFound {
+                message: e.to_string(),
+                endpoint: endpoint.clone(),
+            },
+            ResumeError::OwnerMismatch => AtomaServiceError::AuthError {
+                auth_error: e.to_string(),
+                endpoint: endpoint.clone(),
+            },
+            ResumeError::EventsExpired(_) | ResumeError::UnknownEvent(_) => {
+                AtomaServiceError::InvalidHeader {
+                    message: e.to_string(),
+                    endpoint: endpoint.clone(),
+                }
+            }
+            ResumeError::DuplicateRequestId(_) => AtomaServiceError::Conflict {
+                message: e.to_string(),
+                endpoint: endpoint.clone(),
+            },
+        })?;
+    Ok(sse_response(chunks))
+}
+
 /// Middleware for verifying stack permissions and compute units usage.
 ///
 /// This middleware performs several checks to ensure that the incoming request
@@ -317,6 +609,9 @@ pub async fn signature_verification_middleware(
 /// - Required headers are missing or invalid.
 /// - The request body is invalid or missing required fields.
 /// - The requested model is not supported.
+/// - The prompt tokens plus `max_tokens` exceed the maximum context length of the model, and
+///   the model's context length policy is to reject such requests (otherwise, `max_tokens` is
+///   clamped to the tokens left in the context window).
 ///
 /// Returns an `UNAUTHORIZED` status code if:
 /// - There's no available stack with sufficient compute units.
@@ -345,178 +640,98 @@ pub async fn verify_stack_permissions(
     let request_type = match req_parts.uri.path() {
         CHAT_COMPLETIONS_PATH => RequestType::ChatCompletions,
         EMBEDDINGS_PATH => RequestType::Embeddings,
-        IMAGE_GENERATIONS_PATH => RequestType::ImageGenerations,
+        IMAGE_GENERATIONS_PATH | IMAGE_EDITS_PATH | IMAGE_VARIATIONS_PATH => {
+            RequestType::ImageGenerations
+        }
         _ => RequestType::NonInference,
     };
 
-    let base64_signature = req_parts
-        .headers
-        .get(atoma_utils::constants::SIGNATURE)
-        .ok_or_else(|| AtomaServiceError::MissingHeader {
-            header: atoma_utils::constants::SIGNATURE.to_string(),
-            endpoint: endpoint.clone(),
-        })?
-        .to_str()
-        .map_err(|e| AtomaServiceError::InvalidHeader {
-            message: format!("Failed to convert signature to string, with error: {e}"),
-            endpoint: endpoint.clone(),
-        })?;
-    let signature =
-        Signature::from_str(base64_signature).map_err(|e| AtomaServiceError::InvalidHeader {
-            message: format!("Failed to parse signature, with error: {e}"),
-            endpoint: endpoint.clone(),
-        })?;
-    let public_key_bytes = signature.public_key_bytes();
-    let public_key =
-        PublicKey::try_from_bytes(signature.scheme(), public_key_bytes).map_err(|e| {
-            AtomaServiceError::InvalidHeader {
-                message: format!("Failed to extract public key from bytes, with error: {e}"),
-                endpoint: endpoint.clone(),
-            }
-        })?;
-    let sui_address = SuiAddress::from(&public_key);
-    let stack_small_id = req_parts
-        .headers
-        .get(atoma_utils::constants::STACK_SMALL_ID)
-        .ok_or_else(|| AtomaServiceError::MissingHeader {
-            header: atoma_utils::constants::STACK_SMALL_ID.to_string(),
-            endpoint: endpoint.clone(),
-        })?;
-    let stack_small_id = stack_small_id
-        .to_str()
-        .map_err(|e| AtomaServiceError::InvalidHeader {
-            message: format!("Stack small ID cannot be converted to a string, with error: {e}"),
-            endpoint: endpoint.clone(),
-        })?
-        .parse::<i64>()
-        .map_err(|e| AtomaServiceError::InvalidHeader {
-            message: format!("Stack small ID is not a valid integer, with error: {e}"),
-            endpoint: endpoint.clone(),
-        })?;
-    let body_bytes = axum::body::to_bytes(req_body, MAX_BODY_SIZE)
-        .await
-        .map_err(|e| AtomaServiceError::InvalidBody {
-            message: format!("Failed to convert body to bytes, with error: {e}"),
-            endpoint: endpoint.clone(),
-        })?;
-    let body_json: Value =
-        serde_json::from_slice(&body_bytes).map_err(|e| AtomaServiceError::InvalidBody {
-            message: format!("Failed to parse body as JSON, with error: {e}"),
-            endpoint: endpoint.clone(),
-        })?;
+    let sui_address = utils::get_sui_address(&req_parts.headers, &endpoint)?;
+    let stack_small_id = utils::get_stack_small_id(&req_parts.headers, &endpoint)?;
+    let body_bytes = axum::body::to_bytes(
+        req_body,
+        utils::max_body_size(&endpoint, &req_parts.headers),
+    )
+    .await
+    .map_err(|e| AtomaServiceError::InvalidBody {
+        message: format!("Failed to convert body to bytes, with error: {e}"),
+        endpoint: endpoint.clone(),
+    })?;
+    let mut body_json = utils::parse_body(&req_parts.headers, &body_bytes, &endpoint).await?;
     let model = body_json
         .get(MODEL)
-        .ok_or_else(|| AtomaServiceError::InvalidBody {
+        .ok_or_else(|| AtomaServiceError::InvalidParameter {
+            param: MODEL.to_string(),
             message: "Model not found in body".to_string(),
             endpoint: endpoint.clone(),
         })?
         .as_str()
-        .ok_or_else(|| AtomaServiceError::InvalidBody {
+        .ok_or_else(|| AtomaServiceError::InvalidParameter {
+            param: MODEL.to_string(),
             message: "Model is not a string".to_string(),
             endpoint: endpoint.clone(),
         })?;
-    if !state.models.contains(&model.to_string()) {
-        return Err(AtomaServiceError::InvalidBody {
-            message: format!("Model not supported, supported models: {:?}", state.models),
+    let model_registry = state.model_registry.load_full();
+    if !model_registry.contains(model) {
+        return Err(AtomaServiceError::InvalidParameter {
+            param: MODEL.to_string(),
+            message: format!(
+                "Model not supported, supported models: {:?}",
+                model_registry.models()
+            ),
             endpoint: endpoint.clone(),
         });
     }
 
+    let context_limit = model_registry.context_limit(model);
+    let mut compute_units_estimate =
+        utils::calculate_compute_units(&body_json, request_type, &state, model, &endpoint)?;
+
+    // NOTE: Requests exceeding the maximum context length of the model are rejected (or their
+    // `max_tokens` clamped) before locking compute units, as the inference service would
+    // reject them anyway.
+    let mut body_bytes = body_bytes;
+    if let (RequestType::ChatCompletions, Some(context_limit)) = (request_type, context_limit) {
+        if let Some(clamped_estimate) = utils::enforce_context_length(
+            &mut body_json,
+            &compute_units_estimate,
+            context_limit,
+            &endpoint,
+        )? {
+            compute_units_estimate = clamped_estimate;
+            body_bytes = serde_json::to_vec(&body_json)
+                .map_err(|e| AtomaServiceError::InternalError {
+                    message: format!("Failed to serialize clamped body, with error: {e}"),
+                    endpoint: endpoint.clone(),
+                })?
+                .into();
+        }
+    }
     let ComputeUnitsEstimate {
         num_input_compute_units,
         max_total_compute_units,
-    } = utils::calculate_compute_units(&body_json, request_type, &state, model, &endpoint)?;
+    } = compute_units_estimate;
+
+    // NOTE: The JSON schema of a strict response format is compiled before locking compute
+    // units, so that requests with an invalid schema are rejected upfront.
+    let structured_output_validator = if request_type == RequestType::ChatCompletions {
+        StructuredOutputValidator::from_request(&body_json, &endpoint)?.map(Arc::new)
+    } else {
+        None
+    };
 
     let max_total_compute_units = max_total_compute_units as i64;
     let num_input_compute_units = num_input_compute_units as i64;
 
-    let (result_sender, result_receiver) = oneshot::channel();
-    state
-        .state_manager_sender
-        .send(
-            AtomaAtomaStateManagerEvent::GetAvailableStackWithComputeUnits {
-                stack_small_id,
-                sui_address: sui_address.to_string(),
-                total_num_compute_units: max_total_compute_units,
-                result_sender,
-            },
-        )
-        .map_err(|err| AtomaServiceError::InternalError {
-            message: format!("Failed to get available stacks: {}", err),
-            endpoint: endpoint.clone(),
-        })?;
-    let available_stack = result_receiver
-        .await
-        .map_err(|e| AtomaServiceError::AuthError {
-            auth_error: format!(
-                "Failed to get available stack with enough compute units, with error: {e}"
-            ),
-            endpoint: endpoint.clone(),
-        })?
-        .map_err(|err| AtomaServiceError::AuthError {
-            auth_error: format!(
-                "Failed to get available stack with enough compute units, with error: {err}"
-            ),
-            endpoint: endpoint.clone(),
-        })?;
-
-    match available_stack {
-        StackAvailability::Available => {
-            // NOTE: If we are within this branch logic, it means that there is a stack with the same
-            // stack_small_id and the client has enough compute units to use it.
-        }
-        StackAvailability::DoesNotExist => {
-            // NOTE: If we are within this branch logic, it means that no available stack was found,
-            // which implies that no compute units were locked, so far. For this reason, we query the
-            // Sui blockchain to check if a new stack was created for the client, already.
-            let tx_digest_str = req_parts
-                .headers
-                .get(atoma_utils::constants::TX_DIGEST)
-                .ok_or_else(|| AtomaServiceError::InvalidHeader {
-                    message: "Stack not found, tx digest header expected but not found".to_string(),
-                    endpoint: endpoint.clone(),
-                })?
-                .to_str()
-                .map_err(|e| AtomaServiceError::InvalidHeader {
-                    message: format!("Tx digest cannot be converted to a string, with error: {e}"),
-                    endpoint: endpoint.clone(),
-                })?;
-            let tx_digest = TransactionDigest::from_str(tx_digest_str).unwrap();
-            utils::request_blockchain_for_stack(
-                &state,
-                tx_digest,
-                max_total_compute_units,
-                stack_small_id,
-                endpoint.clone(),
-            )
-            .await?;
-            // NOTE: We do not need to check that the stack small id matches the one in the request,
-            // or that the number of compute units within the stack is enough for processing the request,
-            // as the Sui subscriber service should handle this verification.
-        }
-        StackAvailability::Locked => {
-            // NOTE: If we are within this branch logic, it means that there is a stack with the same
-            // stack_small_id, but it is locked, so the user needs to buy a new stack, and we provide
-            // a specific status code to flag this scenario to the client.
-            return Err(AtomaServiceError::LockedStackError {
-                message: format!(
-                    "Stack with stack_small_id={stack_small_id} is locked, please buy a new stack."
-                ),
-                endpoint: endpoint.clone(),
-            });
-        }
-        StackAvailability::Unavailable => {
-            // NOTE: If we are within this branch logic, it means that there is a stack with the same
-            // stack_small_id, but it is unavailable, so the client either buys a new stack or awaits
-            // the stack to be available again.
-            return Err(AtomaServiceError::UnavailableStackError {
-                message: format!(
-                    "Stack with stack_small_id={stack_small_id} is unavailable, please buy a new stack or await it to be available again."
-                ),
-                endpoint: endpoint.clone(),
-            });
-        }
-    }
+    utils::lock_compute_units_for_stack(
+        &state,
+        &req_parts.headers,
+        stack_small_id,
+        &sui_address,
+        max_total_compute_units,
+        &endpoint,
+    )
+    .await?;
     let request_metadata = req_parts
         .extensions
         .get::<RequestMetadata>()
@@ -528,16 +743,105 @@ pub async fn verify_stack_permissions(
             max_total_compute_units,
         )
         .with_request_type(request_type)
-        .with_endpoint_path(req_parts.uri.path().to_string());
+        .with_endpoint_path(req_parts.uri.path().to_string())
+        .with_structured_output_validator(structured_output_validator);
     req_parts.extensions.insert(request_metadata);
     let req = Request::from_parts(req_parts, Body::from(body_bytes));
-    {
-        let mut entry = state
-            .concurrent_requests_per_stack
-            .entry(stack_small_id)
-            .or_insert(0);
-        *entry += 1;
+    state
+        .concurrent_requests_per_stack
+        .increment(stack_small_id);
+    Ok(next.run(req).await)
+}
+
+/// Middleware applying the operator request filters to inference requests.
+///
+/// This middleware runs after [`verify_stack_permissions`], so that filters can rely on the
+/// [`RequestMetadata`] it adds to the request (stack, number of input tokens, etc.), and before
+/// the request is forwarded to the inference service. Filters may rewrite the request body.
+///
+/// # Errors
+/// Returns a `FORBIDDEN` status code if a filter rejects the request. In that case, the compute
+/// units locked for the request by [`verify_stack_permissions`] are released.
+///
+/// Returns a `BAD_REQUEST` status code if the request body is not valid JSON.
+#[instrument(
+    level = "info",
+    skip_all,
+    fields(
+        endpoint = %req.uri().path(),
+    ),
+    err
+)]
+pub async fn request_filter_middleware(
+    state: State<AppState>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, AtomaServiceError> {
+    if !state.filters.has_request_filters() {
+        return Ok(next.run(req).await);
+    }
+    let (req_parts, req_body) = req.into_parts();
+    let endpoint = req_parts.uri.path().to_string();
+    let request_metadata = req_parts
+        .extensions
+        .get::<RequestMetadata>()
+        .cloned()
+        .unwrap_or_default();
+    let body_bytes = axum::body::to_bytes(
+        req_body,
+        utils::max_body_size(&endpoint, &req_parts.headers),
+    )
+    .await
+    .map_err(|e| AtomaServiceError::InvalidBody {
+        message: format!("Failed to convert body to bytes, with error: {e}"),
+        endpoint: endpoint.clone(),
+    })?;
+    let mut body_json = utils::parse_body(&req_parts.headers, &body_bytes, &endpoint).await?;
+    let sui_address = utils::get_sui_address(&req_parts.headers, &endpoint)?;
+    let model = body_json
+        .get(MODEL)
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let context = RequestFilterContext {
+        sui_address: &sui_address,
+        stack_small_id: request_metadata.stack_small_id,
+        model: &model,
+        request_type: request_metadata.request_type,
+        num_input_tokens: request_metadata.num_input_tokens,
+        endpoint: &endpoint,
+    };
+    if let Err(e) = state.filters.filter_request(&context, &mut body_json).await {
+        // NOTE: The request is not processed, so the compute units locked for it must be released
+        let concurrent_requests = handle_concurrent_requests_count_decrement(
+            &state.concurrent_requests_per_stack,
+            request_metadata.stack_small_id,
+            &endpoint,
+        );
+        update_stack_num_compute_units(
+            &state.state_manager_sender,
+            request_metadata.stack_small_id,
+            request_metadata.estimated_total_compute_units,
+            0,
+            0,
+            &endpoint,
+            concurrent_requests,
+            request_metadata.request_id.as_deref(),
+        )?;
+        return Err(e);
     }
+    // NOTE: Filters can only rewrite JSON bodies, multipart bodies are forwarded as is
+    let body_bytes = if utils::is_multipart(&req_parts.headers) {
+        body_bytes
+    } else {
+        serde_json::to_vec(&body_json)
+            .map_err(|e| AtomaServiceError::InternalError {
+                message: format!("Failed to serialize filtered body, with error: {e}"),
+                endpoint: endpoint.clone(),
+            })?
+            .into()
+    };
+    let req = Request::from_parts(req_parts, Body::from(body_bytes));
     Ok(next.run(req).await)
 }
 
@@ -643,6 +947,7 @@ pub async fn confidential_compute_middleware(
             plaintext,
             client_dh_public_key: client_dh_public_key_bytes,
             salt: salt_bytes,
+            response_secret,
         }) => {
             utils::check_plaintext_body_hash(plaintext_body_hash_bytes, &plaintext, &endpoint)?;
             let body = Body::from(plaintext);
@@ -653,7 +958,11 @@ pub async fn confidential_compute_middleware(
                 .unwrap_or_default();
             req_parts.extensions.insert(
                 request_metadata
-                    .with_client_encryption_metadata(client_dh_public_key_bytes, salt_bytes)
+                    .with_client_encryption_metadata(
+                        client_dh_public_key_bytes,
+                        salt_bytes,
+                        response_secret,
+                    )
                     .with_payload_hash(plaintext_body_hash_bytes),
             );
             let stack_small_id = confidential_compute_request.stack_small_id;
@@ -679,19 +988,35 @@ pub async fn confidential_compute_middleware(
 }
 
 pub mod utils {
+    use axum::{
+        body::{Body, Bytes},
+        extract::{FromRequest, Multipart, Request},
+        http::header::CONTENT_TYPE,
+    };
     use hyper::HeaderMap;
 
-    use crate::handlers::{
-        chat_completions::RequestModelChatCompletions,
-        embeddings::RequestModelEmbeddings,
-        image_generations::RequestModelImageGenerations,
-        request_model::{ComputeUnitsEstimate, RequestModel},
+    use crate::{
+        config::ContextLengthPolicy,
+        handlers::{
+            chat_completions::RequestModelChatCompletions,
+            embeddings::RequestModelEmbeddings,
+            image_generations::{
+                RequestModelImageGenerations, IMAGE_EDITS_PATH, IMAGE_VARIATIONS_PATH,
+                MAX_IMAGE_INPUT_SIZE,
+            },
+            request_model::{ComputeUnitsEstimate, RequestModel},
+        },
+        models::{ContextLengthError, ContextLimit},
     };
 
     use super::{
-        blake2b_hash, instrument, oneshot, verify_signature, AppState, AtomaServiceError,
-        ConfidentialComputeDecryptionRequest, ConfidentialComputeRequest, DecryptionMetadata,
-        Engine, RequestType, TransactionDigest, Value, DH_PUBLIC_KEY_SIZE, NONCE_SIZE,
+        blake2b_hash, confidential_request_associated_data, instrument, oneshot, verify_signature,
+        AeadAlgorithm, AppState, AtomaAtomaStateManagerEvent, AtomaServiceError,
+        ConfidentialComputeDecryptionRequest, ConfidentialComputeDecryptionResponse,
+        ConfidentialComputeRequest, DecryptionMetadata, Engine, FromStr, PublicKey, RequestType,
+        Signature, StackAvailability, SuiAddress, SuiSignature, TransactionDigest, Value,
+        CONFIDENTIAL_PROTOCOL_VERSION_HPKE, CONFIDENTIAL_PROTOCOL_VERSION_V1,
+        CONFIDENTIAL_PROTOCOL_VERSION_V2, DH_PUBLIC_KEY_SIZE, MAX_BODY_SIZE, MODEL, NONCE_SIZE,
         PAYLOAD_HASH_SIZE, SALT_SIZE, STANDARD,
     };
 
@@ -707,6 +1032,304 @@ pub mod utils {
     /// The key for the messages in the request body
     const MESSAGES: &str = "messages";
 
+    /// Returns whether the request has a multipart body (image edits and variations requests).
+    pub fn is_multipart(headers: &HeaderMap) -> bool {
+        headers
+            .get(CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .is_some_and(|content_type| content_type.starts_with("multipart/form-data"))
+    }
+
+    /// Returns the maximum size of the body of a request, in bytes.
+    ///
+    /// Multipart bodies of image edits and variations requests carry input images, so they are
+    /// allowed to be larger than JSON bodies. The larger limit only applies to these endpoints,
+    /// as the content type is set by the client.
+    pub fn max_body_size(endpoint: &str, headers: &HeaderMap) -> usize {
+        if matches!(endpoint, IMAGE_EDITS_PATH | IMAGE_VARIATIONS_PATH) && is_multipart(headers) {
+            MAX_IMAGE_INPUT_SIZE
+        } else {
+            MAX_BODY_SIZE
+        }
+    }
+
+    /// Parses the body of an inference request, as JSON.
+    ///
+    /// Multipart bodies (image edits and variations requests) are parsed as a JSON object of
+    /// their text fields (see [`parse_multipart_fields`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns `AtomaServiceError::InvalidBody` if the body cannot be parsed.
+    pub async fn parse_body(
+        headers: &HeaderMap,
+        body_bytes: &Bytes,
+        endpoint: &str,
+    ) -> Result<Value, AtomaServiceError> {
+        if is_multipart(headers) {
+            return parse_multipart_fields(headers, body_bytes.clone(), endpoint).await;
+        }
+        serde_json::from_slice(body_bytes).map_err(|e| AtomaServiceError::InvalidBody {
+            message: format!("Failed to parse body as JSON, with error: {e}"),
+            endpoint: endpoint.to_string(),
+        })
+    }
+
+    /// Parses the text fields of a multipart body as a JSON object, with integer fields (such
+    /// as `n` or `steps`) as JSON numbers. File fields (such as `image` or `mask`) are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AtomaServiceError::InvalidBody` if the body is not a valid multipart form.
+    pub async fn parse_multipart_fields(
+        headers: &HeaderMap,
+        body_bytes: Bytes,
+        endpoint: &str,
+    ) -> Result<Value, AtomaServiceError> {
+        let invalid_body = |e: &dyn std::fmt::Display| AtomaServiceError::InvalidBody {
+            message: format!("Failed to parse multipart body, with error: {e}"),
+            endpoint: endpoint.to_string(),
+        };
+        let mut request = Request::new(Body::from(body_bytes));
+        if let Some(content_type) = headers.get(CONTENT_TYPE) {
+            request
+                .headers_mut()
+                .insert(CONTENT_TYPE, content_type.clone());
+        }
+        let mut multipart = Multipart::from_request(request, &())
+            .await
+            .map_err(|e| invalid_body(&e))?;
+        let mut fields = serde_json::Map::new();
+        while let Some(field) = multipart.next_field().await.map_err(|e| invalid_body(&e))? {
+            let Some(name) = field.name().map(str::to_string) else {
+                continue;
+            };
+            if field.file_name().is_some() {
+                field.bytes().await.map_err(|e| invalid_body(&e))?;
+                continue;
+            }
+            let text = field.text().await.map_err(|e| invalid_body(&e))?;
+            let value = text
+                .parse::<u64>()
+                .map_or_else(|_| Value::String(text), Value::from);
+            fields.insert(name, value);
+        }
+        Ok(Value::Object(fields))
+    }
+
+    /// Extracts the Sui address of the client from the `X-Signature` request header.
+    ///
+    /// The signature header contains both the signature and the public key of the signer,
+    /// from which the Sui address is derived.
+    ///
+    /// # Arguments
+    ///
+    /// * `headers` - The request headers
+    /// * `endpoint` - The endpoint the request was made to (used for error context)
+    ///
+    /// # Errors
+    ///
+    /// Returns `AtomaServiceError::MissingHeader` if the signature header is missing, or
+    /// `AtomaServiceError::InvalidHeader` if the signature or its public key cannot be parsed.
+    pub fn get_sui_address(
+        headers: &HeaderMap,
+        endpoint: &str,
+    ) -> Result<SuiAddress, AtomaServiceError> {
+        let base64_signature = headers
+            .get(atoma_utils::constants::SIGNATURE)
+            .ok_or_else(|| AtomaServiceError::MissingHeader {
+                header: atoma_utils::constants::SIGNATURE.to_string(),
+                endpoint: endpoint.to_string(),
+            })?
+            .to_str()
+            .map_err(|e| AtomaServiceError::InvalidHeader {
+                message: format!("Failed to convert signature to string, with error: {e}"),
+                endpoint: endpoint.to_string(),
+            })?;
+        let signature = Signature::from_str(base64_signature).map_err(|e| {
+            AtomaServiceError::InvalidHeader {
+                message: format!("Failed to parse signature, with error: {e}"),
+                endpoint: endpoint.to_string(),
+            }
+        })?;
+        let public_key_bytes = signature.public_key_bytes();
+        let public_key =
+            PublicKey::try_from_bytes(signature.scheme(), public_key_bytes).map_err(|e| {
+                AtomaServiceError::InvalidHeader {
+                    message: format!("Failed to extract public key from bytes, with error: {e}"),
+                    endpoint: endpoint.to_string(),
+                }
+            })?;
+        Ok(SuiAddress::from(&public_key))
+    }
+
+    /// Extracts the stack small ID from the `X-Stack-Small-Id` request header.
+    ///
+    /// # Arguments
+    ///
+    /// * `headers` - The request headers
+    /// * `endpoint` - The endpoint the request was made to (used for error context)
+    ///
+    /// # Errors
+    ///
+    /// Returns `AtomaServiceError::MissingHeader` if the header is missing, or
+    /// `AtomaServiceError::InvalidHeader` if it is not a valid integer.
+    pub fn get_stack_small_id(
+        headers: &HeaderMap,
+        endpoint: &str,
+    ) -> Result<i64, AtomaServiceError> {
+        headers
+            .get(atoma_utils::constants::STACK_SMALL_ID)
+            .ok_or_else(|| AtomaServiceError::MissingHeader {
+                header: atoma_utils::constants::STACK_SMALL_ID.to_string(),
+                endpoint: endpoint.to_string(),
+            })?
+            .to_str()
+            .map_err(|e| AtomaServiceError::InvalidHeader {
+                message: format!("Stack small ID cannot be converted to a string, with error: {e}"),
+                endpoint: endpoint.to_string(),
+            })?
+            .parse::<i64>()
+            .map_err(|e| AtomaServiceError::InvalidHeader {
+                message: format!("Stack small ID is not a valid integer, with error: {e}"),
+                endpoint: endpoint.to_string(),
+            })
+    }
+
+    /// Locks compute units on the client's stack, for processing a request.
+    ///
+    /// The compute units are locked atomically by the state manager, if the stack exists, belongs
+    /// to the client and has enough compute units left. If the node is not yet aware of the stack,
+    /// the `X-Tx-Digest` header is used to query the Sui blockchain for a newly created stack.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - Application state containing the state manager and stack retrieval channels
+    /// * `headers` - The request headers
+    /// * `stack_small_id` - Identifier of the stack to lock the compute units on
+    /// * `sui_address` - The Sui address of the client, which must own the stack
+    /// * `num_compute_units` - The number of compute units to lock
+    /// * `endpoint` - The endpoint the request was made to (used for error context)
+    ///
+    /// # Errors
+    ///
+    /// Returns:
+    /// - `AtomaServiceError::AuthError` if no stack with enough compute units is available
+    /// - `AtomaServiceError::InvalidHeader` if the stack is unknown and no valid tx digest is provided
+    /// - `AtomaServiceError::LockedStackError` if the stack is locked for claim
+    /// - `AtomaServiceError::UnavailableStackError` if the stack is currently unavailable
+    /// - `AtomaServiceError::InternalError` if communicating with the state manager fails
+    #[instrument(
+        level = "trace",
+        skip_all,
+        fields(stack_small_id, num_compute_units),
+        err
+    )]
+    pub async fn lock_compute_units_for_stack(
+        state: &AppState,
+        headers: &HeaderMap,
+        stack_small_id: i64,
+        sui_address: &SuiAddress,
+        num_compute_units: i64,
+        endpoint: &str,
+    ) -> Result<(), AtomaServiceError> {
+        let (result_sender, result_receiver) = oneshot::channel();
+        state
+            .state_manager_sender
+            .send(
+                AtomaAtomaStateManagerEvent::GetAvailableStackWithComputeUnits {
+                    stack_small_id,
+                    sui_address: sui_address.to_string(),
+                    total_num_compute_units: num_compute_units,
+                    result_sender,
+                },
+            )
+            .map_err(|err| AtomaServiceError::InternalError {
+                message: format!("Failed to get available stacks: {}", err),
+                endpoint: endpoint.to_string(),
+            })?;
+        let available_stack = result_receiver
+            .await
+            .map_err(|e| AtomaServiceError::AuthError {
+                auth_error: format!(
+                    "Failed to get available stack with enough compute units, with error: {e}"
+                ),
+                endpoint: endpoint.to_string(),
+            })?
+            .map_err(|err| AtomaServiceError::AuthError {
+                auth_error: format!(
+                    "Failed to get available stack with enough compute units, with error: {err}"
+                ),
+                endpoint: endpoint.to_string(),
+            })?;
+
+        match available_stack {
+            StackAvailability::Available => {
+                // NOTE: If we are within this branch logic, it means that there is a stack with the same
+                // stack_small_id and the client has enough compute units to use it.
+            }
+            StackAvailability::DoesNotExist => {
+                // NOTE: If we are within this branch logic, it means that no available stack was found,
+                // which implies that no compute units were locked, so far. For this reason, we query the
+                // Sui blockchain to check if a new stack was created for the client, already.
+                let tx_digest_str = headers
+                    .get(atoma_utils::constants::TX_DIGEST)
+                    .ok_or_else(|| AtomaServiceError::InvalidHeader {
+                        message: "Stack not found, tx digest header expected but not found"
+                            .to_string(),
+                        endpoint: endpoint.to_string(),
+                    })?
+                    .to_str()
+                    .map_err(|e| AtomaServiceError::InvalidHeader {
+                        message: format!(
+                            "Tx digest cannot be converted to a string, with error: {e}"
+                        ),
+                        endpoint: endpoint.to_string(),
+                    })?;
+                let tx_digest = TransactionDigest::from_str(tx_digest_str).map_err(|e| {
+                    AtomaServiceError::InvalidHeader {
+                        message: format!("Tx digest is not a valid digest, with error: {e}"),
+                        endpoint: endpoint.to_string(),
+                    }
+                })?;
+                request_blockchain_for_stack(
+                    state,
+                    tx_digest,
+                    num_compute_units,
+                    stack_small_id,
+                    endpoint.to_string(),
+                )
+                .await?;
+                // NOTE: We do not need to check that the stack small id matches the one in the request,
+                // or that the number of compute units within the stack is enough for processing the request,
+                // as the Sui subscriber service should handle this verification.
+            }
+            StackAvailability::Locked => {
+                // NOTE: If we are within this branch logic, it means that there is a stack with the same
+                // stack_small_id, but it is locked, so the user needs to buy a new stack, and we provide
+                // a specific status code to flag this scenario to the client.
+                return Err(AtomaServiceError::LockedStackError {
+                    message: format!(
+                        "Stack with stack_small_id={stack_small_id} is locked, please buy a new stack."
+                    ),
+                    endpoint: endpoint.to_string(),
+                });
+            }
+            StackAvailability::Unavailable => {
+                // NOTE: If we are within this branch logic, it means that there is a stack with the same
+                // stack_small_id, but it is unavailable, so the client either buys a new stack or awaits
+                // the stack to be available again.
+                return Err(AtomaServiceError::UnavailableStackError {
+                    message: format!(
+                        "Stack with stack_small_id={stack_small_id} is unavailable, please buy a new stack or await it to be available again."
+                    ),
+                    endpoint: endpoint.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
     /// Requests and verifies stack information from the blockchain for a given transaction.
     ///
     /// This function communicates with a blockchain service to verify the existence and validity
@@ -726,7 +1349,8 @@ pub mod utils {
     /// * `Ok(())` - If the stack exists and has sufficient compute units
     /// * `Err(AtomaServiceError)` - If verification fails, with variants:
     ///   - `InternalError` - Channel communication failures
-    ///   - `AuthError` - Insufficient compute units or invalid stack
+    ///   - `UnavailableStackError` - Insufficient compute units
+    ///   - `AuthError` - No stack found for the transaction
     ///
     /// # Channel Communication
     ///
@@ -790,15 +1414,41 @@ pub mod utils {
                 message: "Failed to receive compute units".to_string(),
                 endpoint: endpoint.clone(),
             })?;
-        if let (Some(_), Some(_)) = result {
-            Ok(())
-        } else {
-            Err(AtomaServiceError::AuthError {
-                auth_error: format!(
+        check_stack_query_result(result, tx_digest, endpoint)
+    }
+
+    /// Checks the result of a blockchain query for the stack created by a transaction.
+    ///
+    /// # Arguments
+    ///
+    /// * `stack_query_result` - The small ID of the stack created by the transaction, if any, and
+    ///   its compute units, if enough for the request
+    /// * `tx_digest` - Digest of the transaction that created the stack
+    /// * `endpoint` - API endpoint path (used for error context)
+    ///
+    /// # Errors
+    ///
+    /// - `UnavailableStackError` if the stack has not enough compute units for the request
+    /// - `AuthError` if no stack was found for the transaction
+    pub fn check_stack_query_result(
+        stack_query_result: (Option<i64>, Option<i64>),
+        tx_digest: TransactionDigest,
+        endpoint: String,
+    ) -> Result<(), AtomaServiceError> {
+        match stack_query_result {
+            (Some(_), Some(_)) => Ok(()),
+            // NOTE: The stack exists, but it has not enough compute units for the request, which
+            // is reported like any other exhausted stack.
+            (Some(_), None) => Err(AtomaServiceError::UnavailableStackError {
+                message: format!(
                     "Not enough compute units found for transaction with digest {tx_digest}"
                 ),
                 endpoint,
-            })
+            }),
+            _ => Err(AtomaServiceError::AuthError {
+                auth_error: format!("No stack found for transaction with digest {tx_digest}"),
+                endpoint,
+            }),
         }
     }
 
@@ -824,7 +1474,7 @@ pub mod utils {
     /// The calculation varies by request type:
     /// - ChatCompletions: Based on input tokens + max output tokens
     /// - Embeddings: Based on input text length
-    /// - ImageGenerations: Based on image dimensions and quantity
+    /// - ImageGenerations: Based on image dimensions, quantity and number of steps
     /// - NonInference: Returns 0 (no compute units required)
     ///
     /// This function delegates to specific calculators based on the request type:
@@ -841,32 +1491,31 @@ pub mod utils {
         match request_type {
             RequestType::ChatCompletions => {
                 let request_model = RequestModelChatCompletions::new(body_json)?;
-                let tokenizer_index =
-                    state
-                        .models
-                        .iter()
-                        .position(|m| m == model)
-                        .ok_or_else(|| AtomaServiceError::InvalidBody {
-                            message: "Model not supported".to_string(),
-                            endpoint: endpoint.to_string(),
-                        })?;
-                request_model.get_compute_units_estimate(Some(&state.tokenizers[tokenizer_index]))
+                let model_registry = state.model_registry.load();
+                let tokenizer = model_registry.tokenizer(model).ok_or_else(|| {
+                    AtomaServiceError::InvalidParameter {
+                        param: MODEL.to_string(),
+                        message: "Model not supported".to_string(),
+                        endpoint: endpoint.to_string(),
+                    }
+                })?;
+                request_model.get_compute_units_estimate(Some(tokenizer))
             }
             RequestType::Embeddings => {
                 let request_model = RequestModelEmbeddings::new(body_json)?;
-                let tokenizer_index =
-                    state
-                        .models
-                        .iter()
-                        .position(|m| m == model)
-                        .ok_or_else(|| AtomaServiceError::InvalidBody {
-                            message: "Model not supported".to_string(),
-                            endpoint: endpoint.to_string(),
-                        })?;
-                request_model.get_compute_units_estimate(Some(&state.tokenizers[tokenizer_index]))
+                let model_registry = state.model_registry.load();
+                let tokenizer = model_registry.tokenizer(model).ok_or_else(|| {
+                    AtomaServiceError::InvalidParameter {
+                        param: MODEL.to_string(),
+                        message: "Model not supported".to_string(),
+                        endpoint: endpoint.to_string(),
+                    }
+                })?;
+                request_model.get_compute_units_estimate(Some(tokenizer))
             }
             RequestType::ImageGenerations => {
-                let request_model = RequestModelImageGenerations::new(body_json)?;
+                let request_model = RequestModelImageGenerations::new(body_json)?
+                    .with_config(&state.image_generations, model)?;
                 request_model.get_compute_units_estimate(None)
             }
             RequestType::NonInference => Ok(ComputeUnitsEstimate {
@@ -876,6 +1525,77 @@ pub mod utils {
         }
     }
 
+    /// Enforces the maximum context length of the model on a chat completions request.
+    ///
+    /// # Arguments
+    /// * `body_json` - The parsed JSON body of the request, whose `max_tokens` (or
+    ///   `max_completion_tokens`) is clamped if the policy of the model is to clamp requests
+    /// * `compute_units_estimate` - The compute units estimate of the request
+    /// * `context_limit` - The maximum context length of the model, and its policy
+    /// * `endpoint` - The endpoint that the request was made to
+    ///
+    /// # Returns
+    /// * `Ok(None)` - If the request fits in the context window of the model
+    /// * `Ok(Some(estimate))` - If `max_tokens` was clamped, with the updated compute units estimate
+    ///
+    /// Requests that do not set `max_tokens` are always clamped, as their number of completion
+    /// tokens is a default, not a client's choice.
+    ///
+    /// # Errors
+    /// Returns `InvalidParameter` if the prompt does not fit in the context window, or if the
+    /// request does not fit in it and the policy of the model is to reject requests.
+    pub fn enforce_context_length(
+        body_json: &mut Value,
+        compute_units_estimate: &ComputeUnitsEstimate,
+        mut context_limit: ContextLimit,
+        endpoint: &str,
+    ) -> Result<Option<ComputeUnitsEstimate>, AtomaServiceError> {
+        if body_json.get(MAX_COMPLETION_TOKENS).is_none() && body_json.get(MAX_TOKENS).is_none() {
+            context_limit.policy = ContextLengthPolicy::Clamp;
+        }
+        let num_prompt_tokens = compute_units_estimate.num_input_compute_units;
+        let max_completion_tokens = compute_units_estimate
+            .max_total_compute_units
+            .saturating_sub(num_prompt_tokens);
+        let allowed_completion_tokens = context_limit
+            .max_completion_tokens(num_prompt_tokens, max_completion_tokens)
+            .map_err(|e| AtomaServiceError::InvalidParameter {
+                param: match e {
+                    ContextLengthError::PromptTooLong { .. } => MESSAGES.to_string(),
+                    ContextLengthError::ContextLengthExceeded { .. } => MAX_TOKENS.to_string(),
+                },
+                message: e.to_string(),
+                endpoint: endpoint.to_string(),
+            })?;
+        if allowed_completion_tokens == max_completion_tokens {
+            return Ok(None);
+        }
+        tracing::info!(
+            target = "atoma-service",
+            level = "info",
+            num_prompt_tokens,
+            max_completion_tokens,
+            allowed_completion_tokens,
+            "Clamping max tokens of request to the maximum context length of the model"
+        );
+        if let Some(body) = body_json.as_object_mut() {
+            let keys = [MAX_COMPLETION_TOKENS, MAX_TOKENS]
+                .into_iter()
+                .filter(|key| body.contains_key(*key))
+                .collect::<Vec<_>>();
+            if keys.is_empty() {
+                body.insert(MAX_TOKENS.to_string(), allowed_completion_tokens.into());
+            }
+            for key in keys {
+                body.insert(key.to_string(), allowed_completion_tokens.into());
+            }
+        }
+        Ok(Some(ComputeUnitsEstimate {
+            num_input_compute_units: num_prompt_tokens,
+            max_total_compute_units: num_prompt_tokens + allowed_completion_tokens,
+        }))
+    }
+
     /// Calculates the total number of compute units required for a chat completion request.
     ///
     /// This function analyzes the request body to determine the total computational cost by:
@@ -922,23 +1642,26 @@ pub mod utils {
         model: &str,
         endpoint: String,
     ) -> Result<i64, AtomaServiceError> {
-        let tokenizer_index = state
-            .models
-            .iter()
-            .position(|m| m == model)
-            .ok_or_else(|| AtomaServiceError::InvalidBody {
-                message: "Model not supported".to_string(),
-                endpoint: endpoint.clone(),
-            })?;
+        let model_registry = state.model_registry.load();
+        let tokenizer =
+            model_registry
+                .tokenizer(model)
+                .ok_or_else(|| AtomaServiceError::InvalidParameter {
+                    param: MODEL.to_string(),
+                    message: "Model not supported".to_string(),
+                    endpoint: endpoint.clone(),
+                })?;
 
         let messages = body_json
             .get(MESSAGES)
-            .ok_or_else(|| AtomaServiceError::InvalidBody {
+            .ok_or_else(|| AtomaServiceError::InvalidParameter {
+                param: MESSAGES.to_string(),
                 message: "Messages not found in body".to_string(),
                 endpoint: endpoint.clone(),
             })?
             .as_array()
-            .ok_or_else(|| AtomaServiceError::InvalidBody {
+            .ok_or_else(|| AtomaServiceError::InvalidParameter {
+                param: MESSAGES.to_string(),
                 message: "Messages is not an array".to_string(),
                 endpoint: endpoint.clone(),
             })?;
@@ -957,7 +1680,7 @@ pub mod utils {
                     message: "Message content is not a string".to_string(),
                     endpoint: endpoint.clone(),
                 })?;
-            let num_tokens = state.tokenizers[tokenizer_index]
+            let num_tokens = tokenizer
                 .encode(content_str, true)
                 .map_err(|_| AtomaServiceError::InvalidBody {
                     message: "Failed to encode message content".to_string(),
@@ -1099,21 +1822,27 @@ pub mod utils {
                 endpoint: endpoint.to_string(),
             }
         })?;
-        let nonce_bytes = STANDARD
-            .decode(&confidential_compute_request.nonce)
-            .map_err(|e| AtomaServiceError::InvalidHeader {
-                message: format!("Nonce cannot be converted to a string, with error: {e}"),
+        let hpke_aead = get_request_hpke_aead(confidential_compute_request, endpoint)?;
+        // NOTE: HPKE envelopes derive their nonces from the HPKE key schedule
+        let nonce_bytes: [u8; NONCE_SIZE] = if hpke_aead.is_some() {
+            [0u8; NONCE_SIZE]
+        } else {
+            let nonce_bytes = STANDARD
+                .decode(&confidential_compute_request.nonce)
+                .map_err(|e| AtomaServiceError::InvalidHeader {
+                    message: format!("Nonce cannot be converted to a string, with error: {e}"),
+                    endpoint: endpoint.to_string(),
+                })?;
+            nonce_bytes.try_into().map_err(|e| {
+            AtomaServiceError::InvalidHeader {
+                message: format!(
+                    "Failed to convert nonce bytes to {NONCE_SIZE}-byte array, incorrect length, with error: {:?}",
+                    e
+                ),
                 endpoint: endpoint.to_string(),
-            })?;
-        let nonce_bytes: [u8; NONCE_SIZE] = nonce_bytes.try_into().map_err(|e| {
-        AtomaServiceError::InvalidHeader {
-            message: format!(
-                "Failed to convert nonce bytes to {NONCE_SIZE}-byte array, incorrect length, with error: {:?}",
-                e
-            ),
-            endpoint: endpoint.to_string(),
-        }
-    })?;
+            }
+        })?
+        };
         let client_dh_public_key_bytes: [u8; DH_PUBLIC_KEY_SIZE] = STANDARD
         .decode(&confidential_compute_request.client_dh_public_key)
         .map_err(|e| {
@@ -1158,12 +1887,15 @@ pub mod utils {
                 ),
                 endpoint: endpoint.to_string(),
             })?;
+        let associated_data = get_request_associated_data(confidential_compute_request, endpoint)?;
         let confidential_compute_decryption_request = ConfidentialComputeDecryptionRequest {
             ciphertext: ciphertext_bytes,
             nonce: nonce_bytes,
             salt: salt_bytes,
             client_dh_public_key: client_dh_public_key_bytes,
             node_dh_public_key: node_dh_public_key_bytes,
+            associated_data,
+            hpke_aead,
         };
         let (result_sender, result_receiver) = oneshot::channel();
         state
@@ -1181,19 +1913,108 @@ pub mod utils {
                 ),
                 endpoint: endpoint.to_string(),
             })?;
-        let plaintext = result
-            .map_err(|e| AtomaServiceError::InvalidBody {
-                message: format!("Failed to decrypt confidential compute request, with error: {e}"),
-                endpoint: endpoint.to_string(),
-            })?
-            .plaintext;
+        let ConfidentialComputeDecryptionResponse {
+            plaintext,
+            response_secret,
+        } = result.map_err(|e| AtomaServiceError::InvalidBody {
+            message: format!("Failed to decrypt confidential compute request, with error: {e}"),
+            endpoint: endpoint.to_string(),
+        })?;
         Ok(DecryptionMetadata {
             plaintext,
             client_dh_public_key: client_dh_public_key_bytes,
             salt: salt_bytes,
+            response_secret,
         })
     }
 
+    /// Returns the HPKE AEAD algorithm of the request, if the request is an HPKE envelope.
+    ///
+    /// # Arguments
+    /// * `confidential_compute_request` - The confidential compute request
+    /// * `endpoint` - The API endpoint path being accessed (used for error context)
+    ///
+    /// # Returns
+    /// * `Ok(Some(AeadAlgorithm))` - For HPKE requests, the AEAD algorithm of the request `suite`
+    /// * `Ok(None)` - For requests of the previous protocol versions
+    ///
+    /// # Errors
+    /// Returns `AtomaServiceError::InvalidBody` if:
+    /// - The request is an HPKE request without a supported `suite`
+    /// - The request sets a `suite` without being an HPKE request
+    #[instrument(level = "trace", skip_all, err)]
+    pub fn get_request_hpke_aead(
+        confidential_compute_request: &ConfidentialComputeRequest,
+        endpoint: &str,
+    ) -> Result<Option<AeadAlgorithm>, AtomaServiceError> {
+        let is_hpke_request =
+            confidential_compute_request.version == Some(CONFIDENTIAL_PROTOCOL_VERSION_HPKE);
+        match (is_hpke_request, confidential_compute_request.suite) {
+            (true, Some(suite)) => AeadAlgorithm::from_hpke_id(suite)
+                .map(Some)
+                .ok_or_else(|| AtomaServiceError::InvalidBody {
+                    message: format!(
+                        "Unsupported HPKE AEAD suite: {suite:#06x}, supported suites: [{:#06x}, {:#06x}]",
+                        AeadAlgorithm::Aes256Gcm.hpke_id(),
+                        AeadAlgorithm::ChaCha20Poly1305.hpke_id()
+                    ),
+                    endpoint: endpoint.to_string(),
+                }),
+            (true, None) => Err(AtomaServiceError::InvalidBody {
+                message: "Missing HPKE AEAD suite".to_string(),
+                endpoint: endpoint.to_string(),
+            }),
+            (false, Some(_)) => Err(AtomaServiceError::InvalidBody {
+                message: format!(
+                    "The AEAD suite can only be set for confidential compute protocol version {CONFIDENTIAL_PROTOCOL_VERSION_HPKE}"
+                ),
+                endpoint: endpoint.to_string(),
+            }),
+            (false, None) => Ok(None),
+        }
+    }
+
+    /// Computes the associated data that the request ciphertext must be authenticated against,
+    /// according to the request confidential compute protocol version.
+    ///
+    /// # Arguments
+    /// * `confidential_compute_request` - The confidential compute request
+    /// * `endpoint` - The API endpoint path being accessed (used for error context)
+    ///
+    /// # Returns
+    /// * `Ok(Vec<u8>)` - Empty for version 1 requests (no associated data), or the
+    ///   serialized cleartext envelope for version 2 and HPKE requests
+    ///
+    /// # Errors
+    /// Returns `AtomaServiceError::InvalidBody` if the protocol version is not supported
+    #[instrument(level = "trace", skip_all, err)]
+    pub fn get_request_associated_data(
+        confidential_compute_request: &ConfidentialComputeRequest,
+        endpoint: &str,
+    ) -> Result<Vec<u8>, AtomaServiceError> {
+        match confidential_compute_request
+            .version
+            .unwrap_or(CONFIDENTIAL_PROTOCOL_VERSION_V1)
+        {
+            CONFIDENTIAL_PROTOCOL_VERSION_V1 => Ok(Vec::new()),
+            version @ (CONFIDENTIAL_PROTOCOL_VERSION_V2 | CONFIDENTIAL_PROTOCOL_VERSION_HPKE) => {
+                Ok(confidential_request_associated_data(
+                    version,
+                    confidential_compute_request.stack_small_id,
+                    &confidential_compute_request.model_name,
+                    confidential_compute_request.stream,
+                    confidential_compute_request.num_compute_units,
+                ))
+            }
+            version => Err(AtomaServiceError::InvalidBody {
+                message: format!(
+                    "Unsupported confidential compute protocol version: {version}, supported versions: [{CONFIDENTIAL_PROTOCOL_VERSION_V1}, {CONFIDENTIAL_PROTOCOL_VERSION_V2}, {CONFIDENTIAL_PROTOCOL_VERSION_HPKE}]"
+                ),
+                endpoint: endpoint.to_string(),
+            }),
+        }
+    }
+
     /// Checks if the computed plaintext body hash matches the expected plaintext body hash.
     ///
     /// This function performs a hash comparison between the computed plaintext body hash and the expected plaintext body hash.
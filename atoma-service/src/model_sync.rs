@@ -0,0 +1,366 @@
+//! Synchronization of the models served by the node with its on-chain subscriptions.
+//!
+//! The state manager tracks the models the node is subscribed to on-chain, together with
+//! their tasks. The model synchronizer periodically reconciles the node's [`ModelRegistry`]
+//! with these subscriptions:
+//! - When the node subscribes to a model it does not serve yet, the synchronizer checks
+//!   whether one of the configured chat completions services serves the model and, if so,
+//!   fetches the model's tokenizer and maximum context length, and starts accepting requests for it.
+//! - When all the tasks of a served model are deprecated or removed, the node stops accepting
+//!   new requests for the model (and accepts them again if it subscribes to a new task).
+//!
+//! [`ModelRegistry`]: crate::models::ModelRegistry
+
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use atoma_state::types::{AtomaAtomaStateManagerEvent, ModelTasks};
+use futures::future::join_all;
+use reqwest::Client;
+use serde_json::Value;
+use tokenizers::Tokenizer;
+use tokio::sync::watch::Receiver;
+use tracing::{error, info, instrument, warn};
+
+use crate::{
+    config::ModelSyncConfig,
+    error::AtomaServiceError,
+    handlers::batches::query_state_manager,
+    models::{fetch_max_context_lengths, initialize_tokenizers},
+    server::AppState,
+};
+
+/// The endpoint reported in errors of the model synchronizer
+const MODEL_SYNC_ENDPOINT: &str = "model_sync";
+
+/// The path of the OpenAI-compatible endpoint listing the models served by an inference service
+const MODELS_PATH: &str = "/v1/models";
+
+/// Runs the model synchronizer until a shutdown signal is received.
+///
+/// # Arguments
+///
+/// * `state` - The shared application state
+/// * `config` - The model synchronization configuration
+/// * `hf_token` - The HuggingFace token used to fetch the tokenizers of onboarded models
+/// * `node_small_ids` - The small IDs of the nodes operated by the current node, if known
+/// * `shutdown_receiver` - Receives the shutdown signal of the node
+pub async fn run_model_sync(
+    state: AppState,
+    config: ModelSyncConfig,
+    hf_token: String,
+    node_small_ids: Option<Vec<i64>>,
+    mut shutdown_receiver: Receiver<bool>,
+) {
+    if !config.enabled {
+        info!(
+            target = "atoma-service",
+            level = "info",
+            "Model synchronization is disabled"
+        );
+        return;
+    }
+    info!(
+        target = "atoma-service",
+        level = "info",
+        "Starting model synchronizer"
+    );
+    let poll_interval = Duration::from_millis(config.poll_interval_ms);
+    // NOTE: Models that no configured chat completions service serves are tracked, so that
+    // they are only reported once
+    let mut unserved_models = HashSet::new();
+    loop {
+        if *shutdown_receiver.borrow() {
+            break;
+        }
+        if let Err(e) = sync_models(
+            &state,
+            &config,
+            &hf_token,
+            node_small_ids.clone(),
+            &mut unserved_models,
+        )
+        .await
+        {
+            error!(
+                target = "atoma-service",
+                level = "error",
+                "Failed to synchronize models with on-chain subscriptions: {e}"
+            );
+        }
+        tokio::select! {
+            () = tokio::time::sleep(poll_interval) => {}
+            _ = shutdown_receiver.changed() => {}
+        }
+    }
+    info!(
+        target = "atoma-service",
+        level = "info",
+        "Model synchronizer shut down"
+    );
+}
+
+/// Reconciles the model registry with the models the node is subscribed to on-chain.
+///
+/// Models the node serves but is not subscribed to on-chain (e.g. models configured before
+/// the node subscribed to them) are left untouched.
+async fn sync_models(
+    state: &AppState,
+    config: &ModelSyncConfig,
+    hf_token: &str,
+    node_small_ids: Option<Vec<i64>>,
+    unserved_models: &mut HashSet<String>,
+) -> Result<(), AtomaServiceError> {
+    let models = query_state_manager(state, MODEL_SYNC_ENDPOINT, |result_sender| {
+        AtomaAtomaStateManagerEvent::GetNodeModelTasks {
+            node_small_ids,
+            result_sender,
+        }
+    })
+    .await?;
+    for model in &models {
+        let ModelTasks {
+            model_name,
+            active_task_small_ids,
+            inactive_task_small_ids,
+        } = model;
+        let model_registry = state.model_registry.load_full();
+        if !model.is_active() {
+            if model_registry.contains(model_name) {
+                info!(
+                    target = "atoma-service",
+                    level = "info",
+                    model = model_name,
+                    inactive_task_small_ids = ?inactive_task_small_ids,
+                    "All tasks of the model are deprecated or removed, no longer accepting requests for it"
+                );
+                state
+                    .model_registry
+                    .rcu(|registry| registry.with_model_enabled(model_name, false));
+            }
+            continue;
+        }
+        if model_registry.contains(model_name) {
+            continue;
+        }
+        if model_registry.is_registered(model_name) {
+            info!(
+                target = "atoma-service",
+                level = "info",
+                model = model_name,
+                active_task_small_ids = ?active_task_small_ids,
+                "Model has active tasks again, accepting requests for it"
+            );
+            state
+                .model_registry
+                .rcu(|registry| registry.with_model_enabled(model_name, true));
+            continue;
+        }
+        match onboard_model(state, config, hf_token, model_name).await {
+            Ok(true) => {
+                unserved_models.remove(model_name);
+            }
+            Ok(false) => {
+                if unserved_models.insert(model_name.clone()) {
+                    warn!(
+                        target = "atoma-service",
+                        level = "warn",
+                        model = model_name,
+                        "Node is subscribed to the model, but no configured chat completions service serves it"
+                    );
+                }
+            }
+            Err(e) => {
+                error!(
+                    target = "atoma-service",
+                    level = "error",
+                    model = model_name,
+                    "Failed to onboard model: {e}"
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Onboards a model the node is subscribed to, if one of the configured chat completions
+/// services serves it.
+///
+/// # Returns
+///
+/// Whether the model was onboarded.
+///
+/// # Errors
+///
+/// Returns an error if the tokenizer of the model cannot be fetched.
+#[instrument(level = "info", skip(state, config, hf_token))]
+async fn onboard_model(
+    state: &AppState,
+    config: &ModelSyncConfig,
+    hf_token: &str,
+    model: &str,
+) -> Result<bool, AtomaServiceError> {
+    let services = state.model_registry.load().chat_completions_services();
+    let serving_services = find_services_serving_model(
+        &services,
+        model,
+        Duration::from_millis(config.request_timeout_ms),
+    )
+    .await;
+    if serving_services.is_empty() {
+        return Ok(false);
+    }
+    // NOTE: The HuggingFace API used to fetch the tokenizer and the model configuration is
+    // blocking, so it must not run on the async runtime's worker threads
+    let (model_name, revision, hf_token) = (
+        model.to_string(),
+        config.revision.clone(),
+        hf_token.to_string(),
+    );
+    let (tokenizer, max_context_length) = tokio::task::spawn_blocking(move || {
+        futures::executor::block_on(fetch_model_files(&model_name, &revision, hf_token))
+    })
+    .await
+    .map_err(|e| AtomaServiceError::InternalError {
+        message: format!("Failed to join model files fetching task: {e}"),
+        endpoint: MODEL_SYNC_ENDPOINT.to_string(),
+    })??;
+    info!(
+        target = "atoma-service",
+        level = "info",
+        model = model,
+        services = ?serving_services,
+        max_context_length = ?max_context_length,
+        "Onboarded model, accepting requests for it"
+    );
+    state.model_registry.rcu(|registry| {
+        registry.with_model(
+            model,
+            tokenizer.clone(),
+            max_context_length,
+            serving_services.clone(),
+        )
+    });
+    Ok(true)
+}
+
+/// Fetches the tokenizer and the maximum context length of a model from HuggingFace.
+///
+/// # Errors
+///
+/// Returns an error if the tokenizer of the model cannot be fetched.
+async fn fetch_model_files(
+    model: &str,
+    revision: &str,
+    hf_token: String,
+) -> Result<(Arc<Tokenizer>, Option<u64>), AtomaServiceError> {
+    let models = [model.to_string()];
+    let revisions = [revision.to_string()];
+    let tokenizer = initialize_tokenizers(&models, &revisions, hf_token.clone())
+        .await
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Failed to fetch tokenizer: {e}"),
+            endpoint: MODEL_SYNC_ENDPOINT.to_string(),
+        })?
+        .pop()
+        .ok_or_else(|| AtomaServiceError::InternalError {
+            message: "No tokenizer fetched".to_string(),
+            endpoint: MODEL_SYNC_ENDPOINT.to_string(),
+        })?;
+    let max_context_length = fetch_max_context_lengths(&models, &revisions, hf_token)
+        .await
+        .map_err(|e| AtomaServiceError::InternalError {
+            message: format!("Failed to fetch maximum context length: {e}"),
+            endpoint: MODEL_SYNC_ENDPOINT.to_string(),
+        })?
+        .remove(model);
+    Ok((tokenizer, max_context_length))
+}
+
+/// Returns the chat completions services (URL and job name) serving a model, according to
+/// the models they list on their OpenAI-compatible `/v1/models` endpoint.
+///
+/// Services that cannot be reached are ignored.
+async fn find_services_serving_model(
+    services: &[(String, String)],
+    model: &str,
+    timeout: Duration,
+) -> Vec<(String, String)> {
+    let client = Client::new();
+    let served_models = join_all(services.iter().map(|(url, _)| {
+        let client = client.clone();
+        async move {
+            let response = client
+                .get(format!("{url}{MODELS_PATH}"))
+                .timeout(timeout)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status);
+            match response {
+                Ok(response) => response.json::<Value>().await.map(|body| {
+                    served_model_ids(&body)
+                        .into_iter()
+                        .map(str::to_string)
+                        .collect::<Vec<_>>()
+                }),
+                Err(e) => Err(e),
+            }
+        }
+    }))
+    .await;
+    services
+        .iter()
+        .zip(served_models)
+        .filter_map(|(service, served_models)| match served_models {
+            Ok(served_models) => served_models
+                .iter()
+                .any(|m| m.eq_ignore_ascii_case(model))
+                .then(|| service.clone()),
+            Err(e) => {
+                warn!(
+                    target = "atoma-service",
+                    level = "warn",
+                    url = service.0,
+                    "Failed to list the models served by chat completions service: {e}"
+                );
+                None
+            }
+        })
+        .collect()
+}
+
+/// Extracts the model IDs from the response body of an OpenAI-compatible `/v1/models` endpoint.
+fn served_model_ids(body: &Value) -> Vec<&str> {
+    body.get("data")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|model| model.get("id").and_then(Value::as_str))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_served_model_ids() {
+        let body = json!({
+            "object": "list",
+            "data": [
+                {"id": "meta-llama/Llama-3.3-70B-Instruct", "object": "model"},
+                {"id": "Qwen/Qwen2.5-7B-Instruct", "object": "model"},
+                {"object": "model"},
+            ],
+        });
+        assert_eq!(
+            served_model_ids(&body),
+            [
+                "meta-llama/Llama-3.3-70B-Instruct",
+                "Qwen/Qwen2.5-7B-Instruct"
+            ]
+        );
+        assert!(served_model_ids(&json!({"error": "not found"})).is_empty());
+    }
+}
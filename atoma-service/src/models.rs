@@ -0,0 +1,821 @@
+//! The models served by the node.
+//!
+//! The list of models, their tokenizers, maximum context lengths and the chat completions
+//! services serving them are grouped in a [`ModelRegistry`], which the node can atomically replace at runtime (e.g.
+//! when the operator adds a model or a vLLM replica), without dropping in-flight requests.
+//! Requests keep using the registry that was current when they were received.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use futures::future::{join_all, try_join_all};
+use hf_hub::{api::sync::ApiBuilder, Repo, RepoType};
+use serde_json::Value;
+use thiserror::Error;
+use tokenizers::Tokenizer;
+use tracing::{instrument, warn};
+
+use crate::config::{ContextLengthConfig, ContextLengthPolicy};
+
+/// Keys of the HuggingFace model configuration holding the maximum context length of the
+/// model, by order of precedence
+const MAX_CONTEXT_LENGTH_KEYS: [&str; 2] = ["max_model_len", "max_position_embeddings"];
+
+/// Key of the HuggingFace configuration of multimodal models, holding the configuration of
+/// their language model
+const TEXT_CONFIG_KEY: &str = "text_config";
+
+/// The models served by the node, together with their tokenizers and the chat completions
+/// services available for each of them.
+#[derive(Clone)]
+pub struct ModelRegistry {
+    /// List of available AI models.
+    models: Vec<String>,
+
+    /// Tokenizers of the available models, in the same order as `models`.
+    tokenizers: Vec<Arc<Tokenizer>>,
+
+    /// Mapping between model names and the URLs of the chat completions
+    /// services available to the current node, together with the job names of
+    /// the vLLM instances running on each service.
+    ///
+    /// NOTE: Model names are lowercased, as the configuration keys are.
+    chat_completions_service_urls: HashMap<String, Vec<(String, String)>>,
+
+    /// Models for which new requests are not accepted, because all their tasks were
+    /// deprecated or removed on-chain.
+    disabled_models: HashSet<String>,
+
+    /// Models added at runtime (see [`ModelRegistry::with_model`]), as opposed to the models
+    /// listed in the configuration.
+    onboarded_models: HashSet<String>,
+
+    /// Maximum context length of the models, as read from their HuggingFace configuration.
+    ///
+    /// NOTE: Model names are lowercased.
+    max_context_lengths: HashMap<String, u64>,
+
+    /// Overrides of the maximum context lengths, and policies applied to requests exceeding them.
+    ///
+    /// NOTE: Model names are lowercased.
+    context_length_config: ContextLengthConfig,
+}
+
+/// The maximum context length of a model, and the policy applied to requests exceeding it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContextLimit {
+    /// The maximum number of tokens (prompt and completion) the model can process.
+    pub max_context_length: u64,
+    /// The policy applied to requests exceeding the maximum context length.
+    pub policy: ContextLengthPolicy,
+}
+
+impl ContextLimit {
+    /// Returns the maximum number of completion tokens a request can be forwarded with.
+    ///
+    /// # Arguments
+    ///
+    /// * `num_prompt_tokens` - The (estimated) number of tokens of the prompt
+    /// * `max_completion_tokens` - The maximum number of completion tokens requested
+    ///
+    /// # Returns
+    ///
+    /// `max_completion_tokens` if the request fits in the context window, or the number of
+    /// tokens left in the context window if it does not and the policy is to clamp requests.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the prompt alone does not leave room for any completion token, or if
+    /// the request does not fit in the context window and the policy is to reject requests.
+    pub const fn max_completion_tokens(
+        &self,
+        num_prompt_tokens: u64,
+        max_completion_tokens: u64,
+    ) -> Result<u64, ContextLengthError> {
+        if num_prompt_tokens >= self.max_context_length {
+            return Err(ContextLengthError::PromptTooLong {
+                num_prompt_tokens,
+                max_context_length: self.max_context_length,
+            });
+        }
+        let available_tokens = self.max_context_length - num_prompt_tokens;
+        if max_completion_tokens <= available_tokens {
+            return Ok(max_completion_tokens);
+        }
+        match self.policy {
+            ContextLengthPolicy::Clamp => Ok(available_tokens),
+            ContextLengthPolicy::Reject => Err(ContextLengthError::ContextLengthExceeded {
+                num_prompt_tokens,
+                max_completion_tokens,
+                max_context_length: self.max_context_length,
+            }),
+        }
+    }
+}
+
+impl ModelRegistry {
+    /// Creates a new model registry, after validating it.
+    ///
+    /// # Arguments
+    ///
+    /// * `models` - The names of the models served by the node
+    /// * `tokenizers` - The tokenizer of each model, in the same order as `models`
+    /// * `chat_completions_service_urls` - The chat completions services (URL and job name)
+    ///   available for each model
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The number of tokenizers does not match the number of models
+    /// - A model is listed more than once
+    /// - Chat completions services are configured for a model that is not listed, or a model
+    ///   is configured with an empty list of chat completions services
+    pub fn new(
+        models: Vec<String>,
+        tokenizers: Vec<Arc<Tokenizer>>,
+        chat_completions_service_urls: HashMap<String, Vec<(String, String)>>,
+    ) -> Result<Self, ModelRegistryError> {
+        if models.len() != tokenizers.len() {
+            return Err(ModelRegistryError::TokenizersMismatch {
+                num_models: models.len(),
+                num_tokenizers: tokenizers.len(),
+            });
+        }
+        for (index, model) in models.iter().enumerate() {
+            if models[..index].contains(model) {
+                return Err(ModelRegistryError::DuplicateModel(model.clone()));
+            }
+        }
+        let chat_completions_service_urls = chat_completions_service_urls
+            .into_iter()
+            .map(|(model, urls)| (model.to_lowercase(), urls))
+            .collect::<HashMap<_, _>>();
+        for (model, urls) in &chat_completions_service_urls {
+            if !models.iter().any(|m| m.to_lowercase() == *model) {
+                return Err(ModelRegistryError::UnknownModel(model.clone()));
+            }
+            if urls.is_empty() {
+                return Err(ModelRegistryError::NoServiceUrls(model.clone()));
+            }
+        }
+        Ok(Self {
+            models,
+            tokenizers,
+            chat_completions_service_urls,
+            disabled_models: HashSet::new(),
+            onboarded_models: HashSet::new(),
+            max_context_lengths: HashMap::new(),
+            context_length_config: ContextLengthConfig::default(),
+        })
+    }
+
+    /// Sets the maximum context lengths of the models, as read from their HuggingFace
+    /// configuration (see [`fetch_max_context_lengths`]).
+    #[must_use]
+    pub fn with_max_context_lengths(mut self, max_context_lengths: HashMap<String, u64>) -> Self {
+        self.max_context_lengths = max_context_lengths
+            .into_iter()
+            .map(|(model, max_context_length)| (model.to_lowercase(), max_context_length))
+            .collect();
+        self
+    }
+
+    /// Sets the overrides of the maximum context lengths, and the policies applied to requests
+    /// exceeding them.
+    #[must_use]
+    pub fn with_context_length_config(
+        mut self,
+        context_length_config: ContextLengthConfig,
+    ) -> Self {
+        let ContextLengthConfig {
+            policy,
+            max_context_lengths,
+            policies,
+        } = context_length_config;
+        self.context_length_config = ContextLengthConfig {
+            policy,
+            max_context_lengths: max_context_lengths
+                .into_iter()
+                .map(|(model, max_context_length)| (model.to_lowercase(), max_context_length))
+                .collect(),
+            policies: policies
+                .into_iter()
+                .map(|(model, policy)| (model.to_lowercase(), policy))
+                .collect(),
+        };
+        self
+    }
+
+    /// Returns the names of the models served by the node (excluding disabled models).
+    pub fn models(&self) -> Vec<&str> {
+        self.models
+            .iter()
+            .filter(|m| !self.disabled_models.contains(*m))
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Returns whether the model is served by the node, that is, whether new requests for the
+    /// model are accepted.
+    pub fn contains(&self, model: &str) -> bool {
+        self.is_registered(model) && !self.disabled_models.contains(model)
+    }
+
+    /// Returns whether the model is in the registry, even if it is disabled.
+    pub fn is_registered(&self, model: &str) -> bool {
+        self.models.iter().any(|m| m == model)
+    }
+
+    /// Returns the tokenizer of a model, if the model is served by the node.
+    pub fn tokenizer(&self, model: &str) -> Option<&Arc<Tokenizer>> {
+        self.models
+            .iter()
+            .position(|m| m == model)
+            .map(|index| &self.tokenizers[index])
+    }
+
+    /// Returns the chat completions services (URL and job name) available for a model,
+    /// if any (model names are matched case-insensitively).
+    pub fn chat_completions_service_urls(&self, model: &str) -> Option<&[(String, String)]> {
+        self.chat_completions_service_urls
+            .get(&model.to_lowercase())
+            .map(Vec::as_slice)
+    }
+
+    /// Returns the maximum context length of a model, and the policy applied to requests
+    /// exceeding it (model names are matched case-insensitively).
+    ///
+    /// Returns `None` if the maximum context length of the model is unknown, in which case
+    /// requests are forwarded as is to the inference service.
+    pub fn context_limit(&self, model: &str) -> Option<ContextLimit> {
+        let model = model.to_lowercase();
+        let max_context_length = self
+            .context_length_config
+            .max_context_lengths
+            .get(&model)
+            .or_else(|| self.max_context_lengths.get(&model))
+            .copied()?;
+        let policy = self
+            .context_length_config
+            .policies
+            .get(&model)
+            .copied()
+            .unwrap_or(self.context_length_config.policy);
+        Some(ContextLimit {
+            max_context_length,
+            policy,
+        })
+    }
+
+    /// Returns all the chat completions services (URL and job name) of the node, across
+    /// models, without duplicates.
+    pub fn chat_completions_services(&self) -> Vec<(String, String)> {
+        let mut services = self
+            .chat_completions_service_urls
+            .values()
+            .flatten()
+            .cloned()
+            .collect::<Vec<_>>();
+        services.sort();
+        services.dedup();
+        services
+    }
+
+    /// Returns a copy of the registry, with a new model added (or, if the model is already
+    /// registered, with its tokenizer, maximum context length and chat completions services
+    /// replaced, and enabled).
+    ///
+    /// The model is tracked as onboarded at runtime, so that it is kept when the registry is
+    /// reloaded from the configuration (see [`ModelRegistry::with_runtime_changes_from`]).
+    #[must_use]
+    pub fn with_model(
+        &self,
+        model: &str,
+        tokenizer: Arc<Tokenizer>,
+        max_context_length: Option<u64>,
+        chat_completions_service_urls: Vec<(String, String)>,
+    ) -> Self {
+        let mut registry = self.clone();
+        match registry.models.iter().position(|m| m == model) {
+            Some(index) => registry.tokenizers[index] = tokenizer,
+            None => {
+                registry.models.push(model.to_string());
+                registry.tokenizers.push(tokenizer);
+            }
+        }
+        match max_context_length {
+            Some(max_context_length) => registry
+                .max_context_lengths
+                .insert(model.to_lowercase(), max_context_length),
+            None => registry.max_context_lengths.remove(&model.to_lowercase()),
+        };
+        registry
+            .chat_completions_service_urls
+            .insert(model.to_lowercase(), chat_completions_service_urls);
+        registry.disabled_models.remove(model);
+        registry.onboarded_models.insert(model.to_string());
+        registry
+    }
+
+    /// Returns a copy of the registry, with a registered model enabled or disabled.
+    ///
+    /// Disabled models stay in the registry, together with their tokenizer and chat
+    /// completions services, but new requests for them are rejected.
+    #[must_use]
+    pub fn with_model_enabled(&self, model: &str, enabled: bool) -> Self {
+        let mut registry = self.clone();
+        if enabled {
+            registry.disabled_models.remove(model);
+        } else if registry.is_registered(model) {
+            registry.disabled_models.insert(model.to_string());
+        }
+        registry
+    }
+
+    /// Returns a copy of the registry (typically, just reloaded from the configuration), with
+    /// the runtime changes of a previous registry applied:
+    /// - Models onboarded at runtime are kept, unless the registry already lists them.
+    /// - Models disabled at runtime stay disabled.
+    #[must_use]
+    pub fn with_runtime_changes_from(&self, previous: &Self) -> Self {
+        let mut registry = self.clone();
+        for model in &previous.onboarded_models {
+            if registry.is_registered(model) {
+                continue;
+            }
+            let Some(tokenizer) = previous.tokenizer(model) else {
+                continue;
+            };
+            registry = registry.with_model(
+                model,
+                tokenizer.clone(),
+                previous
+                    .max_context_lengths
+                    .get(&model.to_lowercase())
+                    .copied(),
+                previous
+                    .chat_completions_service_urls(model)
+                    .map(<[_]>::to_vec)
+                    .unwrap_or_default(),
+            );
+        }
+        for model in &previous.disabled_models {
+            registry = registry.with_model_enabled(model, false);
+        }
+        registry
+    }
+}
+
+/// Initializes tokenizers for multiple models by fetching their configurations from HuggingFace.
+///
+/// This function concurrently fetches tokenizer configurations for multiple models from HuggingFace's
+/// repository and initializes them. Each tokenizer is wrapped in an Arc for safe sharing across threads.
+///
+/// # Arguments
+///
+/// * `models` - A slice of model names/paths on HuggingFace (e.g., ["facebook/opt-125m"])
+/// * `revisions` - A slice of revision/branch names corresponding to each model (e.g., ["main"])
+/// * `hf_token` - The HuggingFace token used to fetch the tokenizers
+///
+/// # Returns
+///
+/// Returns a `Result` containing a vector of Arc-wrapped tokenizers on success, or an error if:
+/// - Failed to fetch tokenizer configuration from HuggingFace
+/// - Failed to parse the tokenizer JSON
+/// - Any other network or parsing errors occur
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use anyhow::Result;
+///
+/// #[tokio::main]
+/// async fn example() -> Result<()> {
+///     let models = vec!["facebook/opt-125m".to_string()];
+///     let revisions = vec!["main".to_string()];
+///
+///     let tokenizers = initialize_tokenizers(&models, &revisions, hf_token).await?;
+///     Ok(())
+/// }
+/// ```
+#[instrument(level = "info", skip(models, revisions, hf_token))]
+pub async fn initialize_tokenizers(
+    models: &[String],
+    revisions: &[String],
+    hf_token: String,
+) -> Result<Vec<Arc<Tokenizer>>> {
+    let api = ApiBuilder::new()
+        .with_progress(true)
+        .with_token(Some(hf_token))
+        .build()?;
+    let fetch_futures: Vec<_> = models
+        .iter()
+        .zip(revisions.iter())
+        .map(|(model, revision)| {
+            let api = api.clone();
+            async move {
+                let repo = api.repo(Repo::with_revision(
+                    model.clone(),
+                    RepoType::Model,
+                    revision.clone(),
+                ));
+
+                let tokenizer_filename = repo.get("tokenizer.json").map_err(|e| {
+                    anyhow::anyhow!(
+                        "Failed to get tokenizer.json for model {model}, with error: {e}"
+                    )
+                })?;
+
+                Tokenizer::from_file(tokenizer_filename)
+                    .map_err(|e| {
+                        anyhow::anyhow!(format!(
+                            "Failed to parse tokenizer for model {}, with error: {}",
+                            model, e
+                        ))
+                    })
+                    .map(Arc::new)
+            }
+        })
+        .collect();
+
+    try_join_all(fetch_futures).await
+}
+
+/// Fetches the maximum context length of multiple models from their HuggingFace configuration
+/// (`config.json`, next to the tokenizer).
+///
+/// The maximum context length is read from the `max_model_len` or `max_position_embeddings`
+/// keys (of the language model configuration, for multimodal models). Models whose
+/// configuration cannot be fetched or does not specify a maximum context length are omitted,
+/// as requests for them can still be served.
+///
+/// # Arguments
+///
+/// * `models` - A slice of model names/paths on HuggingFace (e.g., ["facebook/opt-125m"])
+/// * `revisions` - A slice of revision/branch names corresponding to each model (e.g., ["main"])
+/// * `hf_token` - The HuggingFace token used to fetch the configurations
+///
+/// # Returns
+///
+/// A map from model names to their maximum context length.
+///
+/// # Errors
+///
+/// Returns an error if the HuggingFace API client cannot be built.
+#[instrument(level = "info", skip(models, revisions, hf_token))]
+pub async fn fetch_max_context_lengths(
+    models: &[String],
+    revisions: &[String],
+    hf_token: String,
+) -> Result<HashMap<String, u64>> {
+    let api = ApiBuilder::new()
+        .with_progress(false)
+        .with_token(Some(hf_token))
+        .build()?;
+    let fetch_futures = models
+        .iter()
+        .zip(revisions.iter())
+        .map(|(model, revision)| {
+            let api = api.clone();
+            async move {
+                let repo = api.repo(Repo::with_revision(
+                    model.clone(),
+                    RepoType::Model,
+                    revision.clone(),
+                ));
+                let max_context_length = repo
+                    .get("config.json")
+                    .map_err(|e| format!("Failed to get config.json: {e}"))
+                    .and_then(|path| {
+                        std::fs::read(path).map_err(|e| format!("Failed to read config.json: {e}"))
+                    })
+                    .and_then(|bytes| {
+                        serde_json::from_slice::<Value>(&bytes)
+                            .map_err(|e| format!("Failed to parse config.json: {e}"))
+                    })
+                    .and_then(|config| {
+                        max_context_length(&config)
+                            .ok_or_else(|| "No maximum context length in config.json".to_string())
+                    });
+                match max_context_length {
+                    Ok(max_context_length) => Some((model.clone(), max_context_length)),
+                    Err(e) => {
+                        warn!(
+                            target = "atoma-service",
+                            level = "warn",
+                            model = model,
+                            "Maximum context length of the model is unknown, it is not enforced: {e}"
+                        );
+                        None
+                    }
+                }
+            }
+        });
+    Ok(join_all(fetch_futures)
+        .await
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
+/// Reads the maximum context length from a HuggingFace model configuration.
+fn max_context_length(config: &Value) -> Option<u64> {
+    [Some(config), config.get(TEXT_CONFIG_KEY)]
+        .into_iter()
+        .flatten()
+        .find_map(|config| {
+            MAX_CONTEXT_LENGTH_KEYS
+                .iter()
+                .find_map(|key| config.get(key).and_then(Value::as_u64))
+        })
+}
+
+/// Errors returned when a request does not fit in the context window of a model
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ContextLengthError {
+    #[error("The prompt ({num_prompt_tokens} tokens) exceeds the maximum context length of the model ({max_context_length} tokens)")]
+    PromptTooLong {
+        num_prompt_tokens: u64,
+        max_context_length: u64,
+    },
+    #[error("The prompt ({num_prompt_tokens} tokens) plus the maximum number of completion tokens ({max_completion_tokens}) exceeds the maximum context length of the model ({max_context_length} tokens)")]
+    ContextLengthExceeded {
+        num_prompt_tokens: u64,
+        max_completion_tokens: u64,
+        max_context_length: u64,
+    },
+}
+
+/// Errors returned when validating a [`ModelRegistry`]
+#[derive(Debug, Error)]
+pub enum ModelRegistryError {
+    #[error(
+        "Expected one tokenizer per model, got {num_tokenizers} tokenizers for {num_models} models"
+    )]
+    TokenizersMismatch {
+        num_models: usize,
+        num_tokenizers: usize,
+    },
+    #[error("Model {0} is listed more than once")]
+    DuplicateModel(String),
+    #[error("Chat completions services are configured for model {0}, which is not listed")]
+    UnknownModel(String),
+    #[error("No chat completions service is configured for model {0}")]
+    NoServiceUrls(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokenizers::models::bpe::BPE;
+
+    use super::*;
+
+    fn tokenizers(count: usize) -> Vec<Arc<Tokenizer>> {
+        (0..count)
+            .map(|_| Arc::new(Tokenizer::new(BPE::default())))
+            .collect()
+    }
+
+    fn service_urls(model: &str) -> HashMap<String, Vec<(String, String)>> {
+        HashMap::from([(
+            model.to_lowercase(),
+            vec![("http://localhost:8000".to_string(), "vllm".to_string())],
+        )])
+    }
+
+    #[test]
+    fn test_model_registry() {
+        let registry = ModelRegistry::new(
+            vec!["Model-A".to_string(), "model-b".to_string()],
+            tokenizers(2),
+            service_urls("Model-A"),
+        )
+        .unwrap();
+        assert_eq!(registry.models(), ["Model-A", "model-b"]);
+        assert!(registry.contains("model-b"));
+        assert!(!registry.contains("model-c"));
+        assert!(registry.tokenizer("model-b").is_some());
+        assert!(registry.tokenizer("model-c").is_none());
+        assert_eq!(
+            registry
+                .chat_completions_service_urls("Model-A")
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(registry.chat_completions_service_urls("model-b").is_none());
+    }
+
+    #[test]
+    fn test_model_registry_updates() {
+        let registry = ModelRegistry::new(
+            vec!["model-a".to_string()],
+            tokenizers(1),
+            service_urls("model-a"),
+        )
+        .unwrap();
+
+        let registry = registry.with_model(
+            "Model-B",
+            tokenizers(1).remove(0),
+            Some(4096),
+            vec![("http://localhost:8001".to_string(), "vllm-b".to_string())],
+        );
+        assert_eq!(registry.models(), ["model-a", "Model-B"]);
+        assert!(registry.tokenizer("Model-B").is_some());
+        assert_eq!(
+            registry.chat_completions_service_urls("Model-B").unwrap(),
+            [("http://localhost:8001".to_string(), "vllm-b".to_string())]
+        );
+        assert_eq!(registry.chat_completions_services().len(), 2);
+        assert_eq!(
+            registry
+                .context_limit("model-b")
+                .map(|l| l.max_context_length),
+            Some(4096)
+        );
+
+        let registry = registry.with_model_enabled("model-a", false);
+        assert!(!registry.contains("model-a"));
+        assert!(registry.is_registered("model-a"));
+        assert_eq!(registry.models(), ["Model-B"]);
+
+        let registry = registry.with_model_enabled("model-a", true);
+        assert!(registry.contains("model-a"));
+
+        // Unknown models cannot be disabled
+        let registry = registry.with_model_enabled("model-c", false);
+        assert!(!registry.is_registered("model-c"));
+    }
+
+    #[test]
+    fn test_model_registry_runtime_changes() {
+        let previous = ModelRegistry::new(
+            vec!["model-a".to_string(), "model-b".to_string()],
+            tokenizers(2),
+            service_urls("model-a"),
+        )
+        .unwrap()
+        .with_model(
+            "model-c",
+            tokenizers(1).remove(0),
+            Some(4096),
+            vec![("http://localhost:8001".to_string(), "vllm-c".to_string())],
+        )
+        .with_model_enabled("model-b", false);
+
+        // The configuration no longer lists model-b, and lists a new model-d
+        let reloaded = ModelRegistry::new(
+            vec!["model-a".to_string(), "model-d".to_string()],
+            tokenizers(2),
+            service_urls("model-a"),
+        )
+        .unwrap()
+        .with_runtime_changes_from(&previous);
+        assert_eq!(reloaded.models(), ["model-a", "model-d", "model-c"]);
+        assert!(!reloaded.is_registered("model-b"));
+        assert_eq!(
+            reloaded.chat_completions_service_urls("model-c").unwrap(),
+            [("http://localhost:8001".to_string(), "vllm-c".to_string())]
+        );
+        assert_eq!(
+            reloaded
+                .context_limit("model-c")
+                .map(|l| l.max_context_length),
+            Some(4096)
+        );
+
+        // Models disabled at runtime stay disabled, including onboarded ones
+        let previous = reloaded.with_model_enabled("model-c", false);
+        let reloaded = ModelRegistry::new(
+            vec!["model-a".to_string(), "model-b".to_string()],
+            tokenizers(2),
+            service_urls("model-a"),
+        )
+        .unwrap()
+        .with_runtime_changes_from(&previous);
+        assert!(reloaded.is_registered("model-c"));
+        assert!(!reloaded.contains("model-c"));
+        assert_eq!(reloaded.models(), ["model-a", "model-b"]);
+    }
+
+    #[test]
+    fn test_context_limit() {
+        let registry = ModelRegistry::new(
+            vec![
+                "Model-A".to_string(),
+                "model-b".to_string(),
+                "model-c".to_string(),
+            ],
+            tokenizers(3),
+            service_urls("Model-A"),
+        )
+        .unwrap()
+        .with_max_context_lengths(HashMap::from([
+            ("Model-A".to_string(), 8192),
+            ("model-b".to_string(), 4096),
+        ]))
+        .with_context_length_config(ContextLengthConfig {
+            policy: ContextLengthPolicy::Reject,
+            max_context_lengths: HashMap::from([("model-b".to_string(), 2048)]),
+            policies: HashMap::from([("model-a".to_string(), ContextLengthPolicy::Clamp)]),
+        });
+        assert_eq!(
+            registry.context_limit("Model-A"),
+            Some(ContextLimit {
+                max_context_length: 8192,
+                policy: ContextLengthPolicy::Clamp,
+            })
+        );
+        assert_eq!(
+            registry.context_limit("model-b"),
+            Some(ContextLimit {
+                max_context_length: 2048,
+                policy: ContextLengthPolicy::Reject,
+            })
+        );
+        assert_eq!(registry.context_limit("model-c"), None);
+    }
+
+    #[test]
+    fn test_context_limit_max_completion_tokens() {
+        let reject = ContextLimit {
+            max_context_length: 100,
+            policy: ContextLengthPolicy::Reject,
+        };
+        let clamp = ContextLimit {
+            policy: ContextLengthPolicy::Clamp,
+            ..reject
+        };
+        assert_eq!(reject.max_completion_tokens(60, 40), Ok(40));
+        assert_eq!(clamp.max_completion_tokens(60, 40), Ok(40));
+        assert_eq!(
+            reject.max_completion_tokens(60, 41),
+            Err(ContextLengthError::ContextLengthExceeded {
+                num_prompt_tokens: 60,
+                max_completion_tokens: 41,
+                max_context_length: 100,
+            })
+        );
+        assert_eq!(clamp.max_completion_tokens(60, 41), Ok(40));
+        assert!(matches!(
+            clamp.max_completion_tokens(100, 1),
+            Err(ContextLengthError::PromptTooLong { .. })
+        ));
+    }
+
+    #[test]
+    fn test_max_context_length() {
+        assert_eq!(
+            max_context_length(&json!({"max_position_embeddings": 131_072})),
+            Some(131_072)
+        );
+        assert_eq!(
+            max_context_length(
+                &json!({"max_position_embeddings": 131_072, "max_model_len": 32_768})
+            ),
+            Some(32_768)
+        );
+        assert_eq!(
+            max_context_length(&json!({"text_config": {"max_position_embeddings": 8192}})),
+            Some(8192)
+        );
+        assert_eq!(max_context_length(&json!({"vocab_size": 32_000})), None);
+    }
+
+    #[test]
+    fn test_model_registry_validation() {
+        assert!(matches!(
+            ModelRegistry::new(vec!["model-a".to_string()], tokenizers(2), HashMap::new()),
+            Err(ModelRegistryError::TokenizersMismatch { .. })
+        ));
+        assert!(matches!(
+            ModelRegistry::new(
+                vec!["model-a".to_string(), "model-a".to_string()],
+                tokenizers(2),
+                HashMap::new()
+            ),
+            Err(ModelRegistryError::DuplicateModel(_))
+        ));
+        assert!(matches!(
+            ModelRegistry::new(
+                vec!["model-a".to_string()],
+                tokenizers(1),
+                service_urls("model-b")
+            ),
+            Err(ModelRegistryError::UnknownModel(_))
+        ));
+        assert!(matches!(
+            ModelRegistry::new(
+                vec!["model-a".to_string()],
+                tokenizers(1),
+                HashMap::from([("model-a".to_string(), vec![])])
+            ),
+            Err(ModelRegistryError::NoServiceUrls(_))
+        ));
+    }
+}
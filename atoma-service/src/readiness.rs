@@ -0,0 +1,470 @@
+//! Readiness checks of the node, served on the `/ready` endpoint.
+//!
+//! Unlike `/health`, which only reports that the service is running, `/ready` checks the
+//! dependencies the node needs to serve requests: the Postgres database, the Sui RPC node and
+//! events subscriber, the inference services of each model, confidential compute and the P2P
+//! network. Load balancers can then stop routing requests to a node that cannot serve them.
+
+use std::{collections::BTreeMap, future::Future, sync::Arc, time::Duration};
+
+use atoma_confidential::types::ConfidentialComputeStatus;
+use atoma_state::AtomaState;
+use atoma_sui::{client::Client, subscriber::SubscriberStatus};
+use futures::future::join_all;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::sync::{watch, RwLock};
+
+use crate::{config::ReadinessConfig, models::ModelRegistry};
+
+/// The name of the database check
+pub const DATABASE_CHECK: &str = "database";
+/// The name of the Sui RPC node check
+pub const SUI_RPC_CHECK: &str = "sui_rpc";
+/// The name of the Sui events subscriber check
+pub const SUI_SUBSCRIBER_CHECK: &str = "sui_subscriber";
+/// The name of the inference services check
+pub const BACKENDS_CHECK: &str = "backends";
+/// The name of the confidential compute check
+pub const CONFIDENTIAL_COMPUTE_CHECK: &str = "confidential_compute";
+/// The name of the P2P network check
+pub const P2P_CHECK: &str = "p2p";
+
+/// The path of the health endpoint of the inference services
+const BACKEND_HEALTH_PATH: &str = "/health";
+
+/// The result of a single readiness check.
+#[derive(Clone, Debug, Serialize)]
+pub struct CheckResult {
+    /// Whether the checked dependency is ready
+    pub ready: bool,
+
+    /// Why the checked dependency is not ready
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// Details about the checked dependency (e.g. the latest Sui checkpoint)
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub details: Value,
+}
+
+impl CheckResult {
+    fn ready(details: Value) -> Self {
+        Self {
+            ready: true,
+            error: None,
+            details,
+        }
+    }
+
+    fn not_ready(error: impl Into<String>, details: Value) -> Self {
+        Self {
+            ready: false,
+            error: Some(error.into()),
+            details,
+        }
+    }
+}
+
+/// The results of the readiness checks of the node.
+#[derive(Clone, Debug, Serialize)]
+pub struct ReadinessReport {
+    /// Whether all the checks passed
+    pub ready: bool,
+
+    /// The result of each check, by name
+    pub checks: BTreeMap<&'static str, CheckResult>,
+}
+
+/// The dependencies of the node checked for readiness.
+///
+/// Dependencies that are not set are not checked.
+#[derive(Clone, Default)]
+pub struct Readiness {
+    /// The readiness configuration
+    config: ReadinessConfig,
+
+    /// The HTTP client used to probe the inference services
+    http_client: reqwest::Client,
+
+    /// The state of the node, for the database check
+    atoma_state: Option<AtomaState>,
+
+    /// The Sui client, for the Sui RPC node check
+    sui_client: Option<Arc<RwLock<Client>>>,
+
+    /// The synchronization status of the Sui events subscriber
+    subscriber_status: Option<watch::Receiver<SubscriberStatus>>,
+
+    /// The status of the confidential compute service
+    confidential_compute_status: Option<watch::Receiver<ConfidentialComputeStatus>>,
+
+    /// The number of peers the node is connected to
+    p2p_num_peers: Option<watch::Receiver<usize>>,
+}
+
+impl Readiness {
+    /// Constructor
+    #[must_use]
+    pub fn new(config: ReadinessConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Checks the connectivity to the database.
+    #[must_use]
+    pub fn with_database(mut self, atoma_state: AtomaState) -> Self {
+        self.atoma_state = Some(atoma_state);
+        self
+    }
+
+    /// Checks the reachability of the Sui RPC node.
+    #[must_use]
+    pub fn with_sui_client(mut self, sui_client: Arc<RwLock<Client>>) -> Self {
+        self.sui_client = Some(sui_client);
+        self
+    }
+
+    /// Checks the lag of the Sui events subscriber.
+    #[must_use]
+    pub fn with_subscriber_status(
+        mut self,
+        subscriber_status: watch::Receiver<SubscriberStatus>,
+    ) -> Self {
+        self.subscriber_status = Some(subscriber_status);
+        self
+    }
+
+    /// Reports the confidential compute status (and requires confidential compute to be
+    /// supported, if configured so).
+    #[must_use]
+    pub fn with_confidential_compute_status(
+        mut self,
+        confidential_compute_status: watch::Receiver<ConfidentialComputeStatus>,
+    ) -> Self {
+        self.confidential_compute_status = Some(confidential_compute_status);
+        self
+    }
+
+    /// Checks the number of peers the node is connected to.
+    #[must_use]
+    pub fn with_p2p_num_peers(mut self, p2p_num_peers: watch::Receiver<usize>) -> Self {
+        self.p2p_num_peers = Some(p2p_num_peers);
+        self
+    }
+
+    /// Runs all the readiness checks concurrently, each for at most the configured timeout.
+    ///
+    /// The inference services of the models in `model_registry`, as well as the embeddings and
+    /// image generations services (if their URL is not empty), are always checked.
+    pub async fn check(
+        &self,
+        model_registry: &ModelRegistry,
+        embeddings_service_url: &str,
+        image_generations_service_url: &str,
+    ) -> ReadinessReport {
+        let timeout = Duration::from_millis(self.config.check_timeout_ms);
+        let (database, sui_rpc, backends) = tokio::join!(
+            optional_check(self.atoma_state.as_ref(), timeout, check_database),
+            optional_check(self.sui_client.as_ref(), timeout, check_sui_rpc),
+            with_timeout(
+                timeout,
+                self.check_backends(
+                    model_registry,
+                    embeddings_service_url,
+                    image_generations_service_url,
+                    timeout,
+                ),
+            ),
+        );
+
+        let mut checks = BTreeMap::new();
+        checks.insert(BACKENDS_CHECK, backends);
+        if let Some(database) = database {
+            checks.insert(DATABASE_CHECK, database);
+        }
+        if let Some(sui_rpc) = sui_rpc {
+            checks.insert(SUI_RPC_CHECK, sui_rpc);
+        }
+        if let Some(subscriber_status) = &self.subscriber_status {
+            checks.insert(
+                SUI_SUBSCRIBER_CHECK,
+                check_subscriber(
+                    &subscriber_status.borrow(),
+                    self.config.max_subscriber_lag_ms,
+                ),
+            );
+        }
+        if let Some(confidential_compute_status) = &self.confidential_compute_status {
+            checks.insert(
+                CONFIDENTIAL_COMPUTE_CHECK,
+                check_confidential_compute(
+                    &confidential_compute_status.borrow(),
+                    self.config.require_confidential_compute,
+                ),
+            );
+        }
+        if let Some(p2p_num_peers) = &self.p2p_num_peers {
+            checks.insert(
+                P2P_CHECK,
+                check_p2p(*p2p_num_peers.borrow(), self.config.min_peers),
+            );
+        }
+
+        ReadinessReport {
+            ready: checks.values().all(|check| check.ready),
+            checks,
+        }
+    }
+
+    /// Probes the health endpoint of the inference services.
+    ///
+    /// A model is ready if at least one of its chat completions services is; models without
+    /// chat completions services are served by the embeddings or image generations services,
+    /// which must be ready themselves.
+    async fn check_backends(
+        &self,
+        model_registry: &ModelRegistry,
+        embeddings_service_url: &str,
+        image_generations_service_url: &str,
+        timeout: Duration,
+    ) -> CheckResult {
+        let mut urls = model_registry
+            .chat_completions_services()
+            .into_iter()
+            .map(|(url, _)| url)
+            .collect::<Vec<_>>();
+        urls.extend(
+            [embeddings_service_url, image_generations_service_url]
+                .into_iter()
+                .filter(|url| !url.is_empty())
+                .map(str::to_string),
+        );
+        urls.sort();
+        urls.dedup();
+        let reachable = join_all(urls.iter().map(|url| self.probe(url, timeout)))
+            .await
+            .into_iter()
+            .zip(urls.iter())
+            .map(|(ready, url)| (url.as_str(), ready))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut not_ready = Vec::new();
+        let mut models = serde_json::Map::new();
+        for model in model_registry.models() {
+            let Some(services) = model_registry.chat_completions_service_urls(model) else {
+                continue;
+            };
+            let ready = services.iter().any(|(url, _)| reachable[url.as_str()]);
+            if !ready {
+                not_ready.push(model.to_string());
+            }
+            models.insert(
+                model.to_string(),
+                json!({
+                    "ready": ready,
+                    "services": services
+                        .iter()
+                        .map(|(url, _)| (url.clone(), reachable[url.as_str()]))
+                        .collect::<BTreeMap<_, _>>(),
+                }),
+            );
+        }
+        let mut details = json!({ "models": models });
+        for (name, url) in [
+            ("embeddings", embeddings_service_url),
+            ("image_generations", image_generations_service_url),
+        ] {
+            if url.is_empty() {
+                continue;
+            }
+            let ready = reachable[url];
+            if !ready {
+                not_ready.push(name.to_string());
+            }
+            details[name] = json!({ "ready": ready, "url": url });
+        }
+
+        if not_ready.is_empty() {
+            CheckResult::ready(details)
+        } else {
+            CheckResult::not_ready(
+                format!(
+                    "No inference service available for: {}",
+                    not_ready.join(", ")
+                ),
+                details,
+            )
+        }
+    }
+
+    /// Returns whether the health endpoint of an inference service answers successfully
+    async fn probe(&self, url: &str, timeout: Duration) -> bool {
+        self.http_client
+            .get(format!(
+                "{}{BACKEND_HEALTH_PATH}",
+                url.trim_end_matches('/')
+            ))
+            .timeout(timeout)
+            .send()
+            .await
+            .is_ok_and(|response| response.status().is_success())
+    }
+}
+
+/// Runs a check for at most `timeout`, failing it if the timeout elapses first
+async fn with_timeout(timeout: Duration, check: impl Future<Output = CheckResult>) -> CheckResult {
+    tokio::time::timeout(timeout, check)
+        .await
+        .unwrap_or_else(|_| {
+            CheckResult::not_ready(
+                format!("Check timed out after {}ms", timeout.as_millis()),
+                Value::Null,
+            )
+        })
+}
+
+/// Runs a check of a dependency for at most `timeout`, if the dependency is set
+async fn optional_check<'a, T, F>(
+    dependency: Option<&'a T>,
+    timeout: Duration,
+    check: impl FnOnce(&'a T) -> F,
+) -> Option<CheckResult>
+where
+    F: Future<Output = CheckResult>,
+{
+    match dependency {
+        Some(dependency) => Some(with_timeout(timeout, check(dependency)).await),
+        None => None,
+    }
+}
+
+async fn check_database(atoma_state: &AtomaState) -> CheckResult {
+    match atoma_state.ping().await {
+        Ok(()) => CheckResult::ready(Value::Null),
+        Err(e) => CheckResult::not_ready(format!("Database is unreachable: {e}"), Value::Null),
+    }
+}
+
+async fn check_sui_rpc(sui_client: &Arc<RwLock<Client>>) -> CheckResult {
+    match sui_client
+        .read()
+        .await
+        .get_latest_checkpoint_sequence_number()
+        .await
+    {
+        Ok(latest_checkpoint) => {
+            CheckResult::ready(json!({ "latest_checkpoint": latest_checkpoint }))
+        }
+        Err(e) => CheckResult::not_ready(format!("Sui RPC node is unreachable: {e}"), Value::Null),
+    }
+}
+
+fn check_subscriber(status: &SubscriberStatus, max_lag_ms: u64) -> CheckResult {
+    let lag = status.lag();
+    let details = json!({
+        "last_synced_at_ms": status.last_synced_at_ms,
+        "last_event_timestamp_ms": status.last_event_timestamp_ms,
+        "lag_ms": lag.map(|lag| lag.as_millis()),
+    });
+    match lag {
+        None => CheckResult::not_ready(
+            "The subscriber has not caught up with the Sui events yet",
+            details,
+        ),
+        Some(lag) if lag > Duration::from_millis(max_lag_ms) => CheckResult::not_ready(
+            format!(
+                "The subscriber is lagging by {}ms (maximum {max_lag_ms}ms)",
+                lag.as_millis()
+            ),
+            details,
+        ),
+        Some(_) => CheckResult::ready(details),
+    }
+}
+
+fn check_confidential_compute(status: &ConfidentialComputeStatus, required: bool) -> CheckResult {
+    let details = json!({
+        "is_cc_supported": status.is_cc_supported,
+        "key_rotation_counter": status.key_rotation_counter,
+    });
+    if required && !status.is_cc_supported {
+        return CheckResult::not_ready("Confidential compute is not supported", details);
+    }
+    CheckResult::ready(details)
+}
+
+fn check_p2p(num_peers: usize, min_peers: usize) -> CheckResult {
+    let details = json!({ "num_peers": num_peers, "min_peers": min_peers });
+    if num_peers < min_peers {
+        return CheckResult::not_ready(
+            format!("Connected to {num_peers} peers (minimum {min_peers})"),
+            details,
+        );
+    }
+    CheckResult::ready(details)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_subscriber() {
+        assert!(!check_subscriber(&SubscriberStatus::default(), 1_000).ready);
+
+        let now_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let synced = SubscriberStatus {
+            last_synced_at_ms: Some(now_ms),
+            last_event_timestamp_ms: Some(now_ms - 500),
+        };
+        assert!(check_subscriber(&synced, 60_000).ready);
+
+        let lagging = SubscriberStatus {
+            last_synced_at_ms: Some(now_ms - 120_000),
+            ..synced
+        };
+        let check = check_subscriber(&lagging, 60_000);
+        assert!(!check.ready);
+        assert!(check.error.unwrap().contains("lagging"));
+    }
+
+    #[test]
+    fn test_check_confidential_compute() {
+        let status = ConfidentialComputeStatus {
+            is_cc_supported: false,
+            key_rotation_counter: 3,
+        };
+        assert!(check_confidential_compute(&status, false).ready);
+        let check = check_confidential_compute(&status, true);
+        assert!(!check.ready);
+        assert_eq!(check.details["key_rotation_counter"], 3);
+    }
+
+    #[tokio::test]
+    async fn test_check_p2p_peers() {
+        let (num_peers_sender, num_peers) = watch::channel(0);
+        let readiness = Readiness::new(ReadinessConfig {
+            min_peers: 2,
+            ..ReadinessConfig::default()
+        })
+        .with_p2p_num_peers(num_peers);
+        let model_registry =
+            ModelRegistry::new(vec![], vec![], std::collections::HashMap::new()).unwrap();
+
+        let report = readiness.check(&model_registry, "", "").await;
+        assert!(!report.ready);
+        assert!(!report.checks[P2P_CHECK].ready);
+        assert!(report.checks[BACKENDS_CHECK].ready);
+
+        num_peers_sender.send_replace(2);
+        let report = readiness.check(&model_registry, "", "").await;
+        assert!(report.ready);
+        assert_eq!(report.checks[P2P_CHECK].details["num_peers"], 2);
+    }
+}
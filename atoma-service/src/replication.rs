@@ -0,0 +1,742 @@
+//! Sharing of the request state between replicas of the Atoma Service.
+//!
+//! Several replicas of the Atoma Service can serve requests behind a load balancer, against the
+//! same Postgres database. Two pieces of state are then shared between the replicas:
+//! - The number of requests in flight for each stack, which decides whether a stack can be
+//!   locked for claim. Each replica records its own counts in the `concurrent_requests` table,
+//!   while in-progress batches are counted under a shared owner, as a batch can be submitted to
+//!   a replica and processed by another one.
+//! - The stop signals of streams, as a client may ask a replica to stop a stream served by
+//!   another one.
+//!
+//! Changes are broadcast to all the replicas with Postgres `LISTEN`/`NOTIFY`. When the state is
+//! not shared, it is only kept in memory.
+//!
+//! Counts of requests in flight are leased: each replica refreshes its counts on a timer, and
+//! the counts that are not refreshed in time (e.g. of a replica that crashed or was scaled down,
+//! and whose identifier is not reused) are ignored, then pruned by the remaining replicas.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use atoma_state::{types::ConcurrentRequestsCount, AtomaState};
+use dashmap::{mapref::entry::Entry, DashMap, DashSet};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgNotification;
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    watch::Receiver,
+};
+use tracing::{error, info, instrument, warn};
+
+use crate::config::ReplicationConfig;
+
+/// The Postgres notification channel on which the counts of requests in flight are broadcast
+pub const CONCURRENT_REQUESTS_CHANNEL: &str = "atoma_concurrent_requests";
+
+/// The Postgres notification channel on which the stop signals of streams are broadcast
+pub const STOP_SIGNALS_CHANNEL: &str = "atoma_stop_signals";
+
+/// The owner under which the requests in flight of in-progress batches are counted
+pub const BATCHES_OWNER: &str = "batches";
+
+/// The environment variable the replica identifier defaults to
+const HOSTNAME: &str = "HOSTNAME";
+
+/// Interval between two removals of the expired stop signals received from other replicas
+const STOP_SIGNALS_PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Time to wait before listening again, after the listener failed to reconnect to the database
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Number of times the counts of requests in flight are refreshed during their lease
+const LEASE_REFRESHES: u32 = 3;
+
+/// Minimum interval between two refreshes of the counts of requests in flight
+const MIN_LEASE_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A change of the state of the replica, to share with the other replicas
+#[derive(Debug)]
+enum StateUpdate {
+    /// Change of the number of requests in flight on the replica, for a stack
+    ConcurrentRequests { stack_small_id: i64, delta: i64 },
+    /// Change of the number of in-progress batches of a stack
+    Batches { stack_small_id: i64, delta: i64 },
+    /// The number of in-progress batches of each stack, restored when the node starts
+    ResetBatches(Vec<(i64, i64)>),
+    /// The request whose stream must be stopped
+    StopSignal(String),
+}
+
+/// A stop signal, as broadcast to the other replicas
+#[derive(Debug, Serialize, Deserialize)]
+struct StopSignal {
+    /// The replica the stop signal was received by
+    replica_id: String,
+    /// The request whose stream must be stopped
+    request_id: String,
+}
+
+/// The number of requests in flight for each stack.
+///
+/// A request is in flight from the moment compute units are locked for it, until its final
+/// compute units are recorded. Each in-progress batch counts as a single request in flight.
+#[derive(Debug, Default)]
+pub struct ConcurrentRequests {
+    /// Requests in flight on this replica, by stack (including the in-progress batches, if the
+    /// state is not shared)
+    local: DashMap<i64, u64>,
+
+    /// Requests in flight on the other replicas and of the in-progress batches, by stack and
+    /// owner, if the state is shared
+    shared: DashMap<i64, HashMap<String, u64>>,
+
+    /// Sender of the changes to share with the other replicas, if the state is shared
+    updates_sender: Option<UnboundedSender<StateUpdate>>,
+}
+
+impl ConcurrentRequests {
+    /// Counts a new request in flight for the stack.
+    pub fn increment(&self, stack_small_id: i64) {
+        *self.local.entry(stack_small_id).or_insert(0) += 1;
+        self.share(StateUpdate::ConcurrentRequests {
+            stack_small_id,
+            delta: 1,
+        });
+    }
+
+    /// Counts a request of the stack as no longer in flight.
+    ///
+    /// Returns the number of requests still in flight for the stack, across replicas.
+    ///
+    /// # Logging
+    ///
+    /// Logs an error if the stack had no request in flight on this replica.
+    #[instrument(level = "info", skip(self))]
+    pub fn decrement(&self, stack_small_id: i64, endpoint: &str) -> u64 {
+        let decremented = match self.local.entry(stack_small_id) {
+            Entry::Occupied(mut occupied_entry) => {
+                let current_count = *occupied_entry.get();
+                if current_count == 0 {
+                    error!(
+                        target = "atoma-service",
+                        level = "error",
+                        endpoint = endpoint,
+                        stack_small_id,
+                        "Attempted to decrement concurrent requests count that was already 0.",
+                    );
+                    occupied_entry.remove();
+                    false
+                } else {
+                    let new_count = current_count - 1;
+                    if new_count == 0 {
+                        info!(
+                            target = "atoma-service",
+                            level = "info",
+                            endpoint = endpoint,
+                            stack_small_id,
+                            "Concurrent requests count reached 0 for stack, removing entry and updating stack num compute units.",
+                        );
+                        occupied_entry.remove();
+                    } else {
+                        occupied_entry.insert(new_count);
+                    }
+                    true
+                }
+            }
+            Entry::Vacant(_) => {
+                error!(
+                    target = "atoma-service",
+                    level = "error",
+                    endpoint = endpoint,
+                    stack_small_id,
+                    "Attempted to decrement concurrent requests for non-existent stack entry (implies count is 0).",
+                );
+                false
+            }
+        };
+        if decremented {
+            self.share(StateUpdate::ConcurrentRequests {
+                stack_small_id,
+                delta: -1,
+            });
+        }
+        self.get(stack_small_id)
+    }
+
+    /// Counts a new in-progress batch for the stack.
+    pub fn increment_batch(&self, stack_small_id: i64) {
+        if self.updates_sender.is_none() {
+            *self.local.entry(stack_small_id).or_insert(0) += 1;
+            return;
+        }
+        // NOTE: The shared count is updated right away, and overwritten by the count
+        // broadcast once the change is recorded
+        *self
+            .shared
+            .entry(stack_small_id)
+            .or_default()
+            .entry(BATCHES_OWNER.to_string())
+            .or_insert(0) += 1;
+        self.share(StateUpdate::Batches {
+            stack_small_id,
+            delta: 1,
+        });
+    }
+
+    /// Counts a batch of the stack as completed.
+    ///
+    /// Returns the number of requests still in flight for the stack, across replicas.
+    pub fn decrement_batch(&self, stack_small_id: i64, endpoint: &str) -> u64 {
+        if self.updates_sender.is_none() {
+            return self.decrement(stack_small_id, endpoint);
+        }
+        let num_batches = self
+            .shared
+            .get(&stack_small_id)
+            .and_then(|counts| counts.get(BATCHES_OWNER).copied())
+            .unwrap_or_default();
+        self.set_shared(BATCHES_OWNER, stack_small_id, num_batches.saturating_sub(1));
+        self.share(StateUpdate::Batches {
+            stack_small_id,
+            delta: -1,
+        });
+        self.get(stack_small_id)
+    }
+
+    /// Counts the in-progress batches of the stacks (a stack appears once per batch), when the
+    /// node starts.
+    pub fn restore_batches(&self, stack_small_ids: impl IntoIterator<Item = i64>) {
+        if self.updates_sender.is_none() {
+            for stack_small_id in stack_small_ids {
+                *self.local.entry(stack_small_id).or_insert(0) += 1;
+            }
+            return;
+        }
+        let mut num_batches = HashMap::<i64, u64>::new();
+        for stack_small_id in stack_small_ids {
+            *num_batches.entry(stack_small_id).or_insert(0) += 1;
+        }
+        self.shared.iter_mut().for_each(|mut counts| {
+            counts.remove(BATCHES_OWNER);
+        });
+        self.shared.retain(|_, counts| !counts.is_empty());
+        for (&stack_small_id, &count) in &num_batches {
+            self.set_shared(BATCHES_OWNER, stack_small_id, count);
+        }
+        self.share(StateUpdate::ResetBatches(
+            num_batches
+                .into_iter()
+                .map(|(stack_small_id, count)| (stack_small_id, count as i64))
+                .collect(),
+        ));
+    }
+
+    /// Returns the number of requests in flight for the stack, across replicas.
+    pub fn get(&self, stack_small_id: i64) -> u64 {
+        let local = self.local.get(&stack_small_id).map_or(0, |count| *count);
+        let shared = self
+            .shared
+            .get(&stack_small_id)
+            .map_or(0, |counts| counts.values().sum());
+        local + shared
+    }
+
+    /// Returns the number of requests in flight across all stacks and replicas.
+    pub fn total(&self) -> u64 {
+        let local = self.local.iter().map(|count| *count.value()).sum::<u64>();
+        let shared = self
+            .shared
+            .iter()
+            .map(|counts| counts.values().sum::<u64>())
+            .sum::<u64>();
+        local + shared
+    }
+
+    /// Sets the number of requests in flight for a stack, counted by another replica (or of the
+    /// in-progress batches).
+    fn set_shared(&self, owner: &str, stack_small_id: i64, count: u64) {
+        if count > 0 {
+            self.shared
+                .entry(stack_small_id)
+                .or_default()
+                .insert(owner.to_string(), count);
+            return;
+        }
+        if let Entry::Occupied(mut counts) = self.shared.entry(stack_small_id) {
+            counts.get_mut().remove(owner);
+            if counts.get().is_empty() {
+                counts.remove();
+            }
+        }
+    }
+
+    /// Sends a change to share with the other replicas, if the state is shared.
+    fn share(&self, update: StateUpdate) {
+        if let Some(updates_sender) = &self.updates_sender {
+            if updates_sender.send(update).is_err() {
+                error!(
+                    target = "atoma-service",
+                    level = "error",
+                    "Replication stopped, the change is not shared with other replicas"
+                );
+            }
+        }
+    }
+}
+
+/// The stop signals of streams, sent by clients that no longer want to receive them.
+#[derive(Debug, Default)]
+pub struct StopSignals {
+    /// The requests whose stream must be stopped
+    stopped: DashSet<String>,
+
+    /// Sender of the stop signals to share with the other replicas, if the state is shared
+    updates_sender: Option<UnboundedSender<StateUpdate>>,
+}
+
+impl StopSignals {
+    /// Signals the stream of a request to stop, on whichever replica serves it.
+    pub fn stop(&self, request_id: &str) {
+        self.stopped.insert(request_id.to_string());
+        if let Some(updates_sender) = &self.updates_sender {
+            if updates_sender
+                .send(StateUpdate::StopSignal(request_id.to_string()))
+                .is_err()
+            {
+                error!(
+                    target = "atoma-service",
+                    level = "error",
+                    request_id,
+                    "Replication stopped, the stop signal is not shared with other replicas"
+                );
+            }
+        }
+    }
+
+    /// Returns whether the stream of a request must be stopped, consuming the stop signal.
+    pub fn take(&self, request_id: &str) -> bool {
+        self.stopped.remove(request_id).is_some()
+    }
+}
+
+/// Keeps the state of a replica in sync with the other replicas sharing the same database.
+pub struct Replication {
+    /// The identifier of the replica
+    replica_id: String,
+
+    /// The state of the node, shared by the replicas
+    atoma_state: AtomaState,
+
+    /// The requests in flight of the replica
+    concurrent_requests: Arc<ConcurrentRequests>,
+
+    /// The stop signals of the replica
+    stop_signals: Arc<StopSignals>,
+
+    /// Receiver of the changes to share with the other replicas
+    updates_receiver: UnboundedReceiver<StateUpdate>,
+
+    /// Time during which stop signals received from other replicas are kept
+    stop_signal_ttl: Duration,
+
+    /// Time after which the counts of requests in flight that were not refreshed are dropped
+    concurrent_requests_lease: Duration,
+}
+
+impl Replication {
+    /// Constructor
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no replica identifier is configured, and the `HOSTNAME` environment
+    /// variable is not set.
+    pub fn new(config: &ReplicationConfig, atoma_state: AtomaState) -> anyhow::Result<Self> {
+        let replica_id = if config.replica_id.is_empty() {
+            std::env::var(HOSTNAME).map_err(|_| {
+                anyhow::anyhow!("No replica identifier configured, and {HOSTNAME} is not set")
+            })?
+        } else {
+            config.replica_id.clone()
+        };
+        let (updates_sender, updates_receiver) = mpsc::unbounded_channel();
+        Ok(Self {
+            replica_id,
+            atoma_state,
+            concurrent_requests: Arc::new(ConcurrentRequests {
+                updates_sender: Some(updates_sender.clone()),
+                ..ConcurrentRequests::default()
+            }),
+            stop_signals: Arc::new(StopSignals {
+                updates_sender: Some(updates_sender),
+                ..StopSignals::default()
+            }),
+            updates_receiver,
+            stop_signal_ttl: Duration::from_millis(config.stop_signal_ttl_ms),
+            concurrent_requests_lease: Duration::from_millis(config.concurrent_requests_lease_ms),
+        })
+    }
+
+    /// Returns the identifier of the replica
+    pub fn replica_id(&self) -> &str {
+        &self.replica_id
+    }
+
+    /// Returns the requests in flight of the replica, shared with the other replicas
+    pub fn concurrent_requests(&self) -> Arc<ConcurrentRequests> {
+        Arc::clone(&self.concurrent_requests)
+    }
+
+    /// Returns the stop signals of the replica, shared with the other replicas
+    pub fn stop_signals(&self) -> Arc<StopSignals> {
+        Arc::clone(&self.stop_signals)
+    }
+
+    /// Runs the replication until a shutdown signal is received.
+    ///
+    /// When it starts, the replication clears the requests in flight left by a previous run of
+    /// the replica (e.g. if it crashed), and loads the requests in flight of the other replicas.
+    /// While it runs, it refreshes the lease of the requests in flight of the replica, and prunes
+    /// the ones of the replicas that are gone.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be reached when the replication starts. Errors
+    /// occurring afterwards are logged, and the replication keeps running.
+    #[instrument(level = "info", skip_all, fields(replica_id = %self.replica_id))]
+    pub async fn run(mut self, mut shutdown_receiver: Receiver<bool>) -> anyhow::Result<()> {
+        self.atoma_state
+            .reset_concurrent_requests(&self.replica_id, &[], CONCURRENT_REQUESTS_CHANNEL)
+            .await?;
+        let mut listener = self
+            .atoma_state
+            .listen(&[CONCURRENT_REQUESTS_CHANNEL, STOP_SIGNALS_CHANNEL])
+            .await?;
+        self.atoma_state
+            .prune_concurrent_requests(self.concurrent_requests_lease, CONCURRENT_REQUESTS_CHANNEL)
+            .await?;
+        self.load_shared_counts().await?;
+        info!(
+            target = "atoma-service",
+            level = "info",
+            "Sharing requests in flight and stop signals with other replicas"
+        );
+
+        let mut remote_stop_signals = VecDeque::new();
+        let mut prune_interval = tokio::time::interval(STOP_SIGNALS_PRUNE_INTERVAL);
+        let mut lease_interval = tokio::time::interval(
+            (self.concurrent_requests_lease / LEASE_REFRESHES).max(MIN_LEASE_REFRESH_INTERVAL),
+        );
+        loop {
+            tokio::select! {
+                Some(update) = self.updates_receiver.recv() => {
+                    self.publish(update).await;
+                }
+                notification = listener.try_recv() => match notification {
+                    Ok(Some(notification)) => {
+                        self.handle_notification(&notification, &mut remote_stop_signals);
+                    }
+                    Ok(None) => {
+                        // NOTE: The listener reconnects on the next call, notifications sent
+                        // in the meantime are lost, so the shared counts are reloaded
+                        warn!(
+                            target = "atoma-service",
+                            level = "warn",
+                            "Lost connection to the database, reloading requests in flight of other replicas"
+                        );
+                        if let Err(e) = self.load_shared_counts().await {
+                            error!(
+                                target = "atoma-service",
+                                level = "error",
+                                "Failed to reload requests in flight of other replicas: {e}"
+                            );
+                        }
+                    }
+                    Err(e) => {
+                        error!(
+                            target = "atoma-service",
+                            level = "error",
+                            "Failed to receive notifications from other replicas: {e}"
+                        );
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                    }
+                },
+                _ = prune_interval.tick() => {
+                    self.prune_stop_signals(&mut remote_stop_signals);
+                }
+                _ = lease_interval.tick() => {
+                    self.renew_lease().await;
+                }
+                _ = shutdown_receiver.changed() => break,
+            }
+        }
+
+        // NOTE: Share the last changes, e.g. of the requests drained on shutdown
+        while let Ok(update) = self.updates_receiver.try_recv() {
+            self.publish(update).await;
+        }
+        info!(
+            target = "atoma-service",
+            level = "info",
+            "Replication shut down"
+        );
+        Ok(())
+    }
+
+    /// Records a change of the state of the replica, and broadcasts it to the other replicas.
+    async fn publish(&self, update: StateUpdate) {
+        let result = match update {
+            StateUpdate::ConcurrentRequests {
+                stack_small_id,
+                delta,
+            } => self
+                .atoma_state
+                .update_concurrent_requests(
+                    &self.replica_id,
+                    stack_small_id,
+                    delta,
+                    CONCURRENT_REQUESTS_CHANNEL,
+                )
+                .await
+                .map(|_| ()),
+            StateUpdate::Batches {
+                stack_small_id,
+                delta,
+            } => self
+                .atoma_state
+                .update_concurrent_requests(
+                    BATCHES_OWNER,
+                    stack_small_id,
+                    delta,
+                    CONCURRENT_REQUESTS_CHANNEL,
+                )
+                .await
+                .map(|_| ()),
+            StateUpdate::ResetBatches(counts) => {
+                self.atoma_state
+                    .reset_concurrent_requests(BATCHES_OWNER, &counts, CONCURRENT_REQUESTS_CHANNEL)
+                    .await
+            }
+            StateUpdate::StopSignal(request_id) => {
+                let stop_signal = StopSignal {
+                    replica_id: self.replica_id.clone(),
+                    request_id,
+                };
+                match serde_json::to_string(&stop_signal) {
+                    Ok(payload) => {
+                        self.atoma_state
+                            .notify(STOP_SIGNALS_CHANNEL, &payload)
+                            .await
+                    }
+                    Err(e) => Err(e.into()),
+                }
+            }
+        };
+        if let Err(e) = result {
+            error!(
+                target = "atoma-service",
+                level = "error",
+                "Failed to share state with other replicas: {e}"
+            );
+        }
+    }
+
+    /// Refreshes the lease of the requests in flight of the replica and of the in-progress
+    /// batches, and prunes the requests in flight whose lease expired.
+    ///
+    /// The counts of in-progress batches are shared by all the replicas, so they are kept as long
+    /// as any replica is running.
+    async fn renew_lease(&self) {
+        for owner in [self.replica_id.as_str(), BATCHES_OWNER] {
+            if let Err(e) = self.atoma_state.refresh_concurrent_requests(owner).await {
+                error!(
+                    target = "atoma-service",
+                    level = "error",
+                    owner,
+                    "Failed to refresh the lease of requests in flight: {e}"
+                );
+            }
+        }
+        match self
+            .atoma_state
+            .prune_concurrent_requests(self.concurrent_requests_lease, CONCURRENT_REQUESTS_CHANNEL)
+            .await
+        {
+            Ok(pruned) => {
+                // NOTE: Other replicas are notified of the pruned counts, as zero
+                for count in pruned {
+                    warn!(
+                        target = "atoma-service",
+                        level = "warn",
+                        owner = count.owner,
+                        stack_small_id = count.stack_small_id,
+                        num_requests = count.num_requests,
+                        "Dropped requests in flight whose lease expired"
+                    );
+                    if count.owner != self.replica_id {
+                        self.concurrent_requests
+                            .set_shared(&count.owner, count.stack_small_id, 0);
+                    }
+                }
+            }
+            Err(e) => error!(
+                target = "atoma-service",
+                level = "error",
+                "Failed to prune requests in flight whose lease expired: {e}"
+            ),
+        }
+    }
+
+    /// Applies a change broadcast by another replica.
+    fn handle_notification(
+        &self,
+        notification: &PgNotification,
+        remote_stop_signals: &mut VecDeque<(Instant, String)>,
+    ) {
+        match notification.channel() {
+            CONCURRENT_REQUESTS_CHANNEL => {
+                match serde_json::from_str::<ConcurrentRequestsCount>(notification.payload()) {
+                    // NOTE: The requests in flight on this replica are counted locally
+                    Ok(count) if count.owner == self.replica_id => {}
+                    Ok(count) => self.concurrent_requests.set_shared(
+                        &count.owner,
+                        count.stack_small_id,
+                        count.num_requests.max(0) as u64,
+                    ),
+                    Err(e) => error!(
+                        target = "atoma-service",
+                        level = "error",
+                        "Invalid concurrent requests notification: {e}"
+                    ),
+                }
+            }
+            STOP_SIGNALS_CHANNEL => {
+                match serde_json::from_str::<StopSignal>(notification.payload()) {
+                    Ok(stop_signal) if stop_signal.replica_id == self.replica_id => {}
+                    Ok(stop_signal) => {
+                        self.stop_signals
+                            .stopped
+                            .insert(stop_signal.request_id.clone());
+                        remote_stop_signals.push_back((Instant::now(), stop_signal.request_id));
+                    }
+                    Err(e) => error!(
+                        target = "atoma-service",
+                        level = "error",
+                        "Invalid stop signal notification: {e}"
+                    ),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Removes the stop signals received from other replicas that expired, for streams that
+    /// are not served by this replica (otherwise the signal is consumed by the stream).
+    fn prune_stop_signals(&self, remote_stop_signals: &mut VecDeque<(Instant, String)>) {
+        while let Some((received_at, _)) = remote_stop_signals.front() {
+            if received_at.elapsed() < self.stop_signal_ttl {
+                break;
+            }
+            if let Some((_, request_id)) = remote_stop_signals.pop_front() {
+                self.stop_signals.stopped.remove(&request_id);
+            }
+        }
+    }
+
+    /// Loads the requests in flight of the other replicas and of the in-progress batches.
+    async fn load_shared_counts(&self) -> anyhow::Result<()> {
+        let counts = self
+            .atoma_state
+            .get_concurrent_requests(self.concurrent_requests_lease)
+            .await?;
+        self.concurrent_requests.shared.clear();
+        for count in counts {
+            if count.owner != self.replica_id {
+                self.concurrent_requests.set_shared(
+                    &count.owner,
+                    count.stack_small_id,
+                    count.num_requests.max(0) as u64,
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_concurrent_requests() {
+        let concurrent_requests = ConcurrentRequests::default();
+        concurrent_requests.increment(1);
+        concurrent_requests.increment(1);
+        concurrent_requests.increment_batch(1);
+        assert_eq!(concurrent_requests.get(1), 3);
+        assert_eq!(concurrent_requests.decrement(1, "test"), 2);
+        assert_eq!(concurrent_requests.decrement_batch(1, "test"), 1);
+        assert_eq!(concurrent_requests.decrement(1, "test"), 0);
+        // Decrementing a stack without requests in flight is a no-op
+        assert_eq!(concurrent_requests.decrement(1, "test"), 0);
+        assert_eq!(concurrent_requests.total(), 0);
+    }
+
+    #[test]
+    fn test_shared_concurrent_requests() {
+        let (updates_sender, mut updates_receiver) = mpsc::unbounded_channel();
+        let concurrent_requests = ConcurrentRequests {
+            updates_sender: Some(updates_sender),
+            ..ConcurrentRequests::default()
+        };
+        concurrent_requests.set_shared("replica-2", 1, 2);
+        concurrent_requests.increment(1);
+        concurrent_requests.restore_batches([1, 1, 2]);
+        assert_eq!(concurrent_requests.get(1), 5);
+        assert_eq!(concurrent_requests.get(2), 1);
+        assert_eq!(concurrent_requests.decrement_batch(2, "test"), 0);
+        assert_eq!(concurrent_requests.decrement(1, "test"), 4);
+        assert_eq!(concurrent_requests.total(), 4);
+
+        assert!(matches!(
+            updates_receiver.try_recv(),
+            Ok(StateUpdate::ConcurrentRequests {
+                stack_small_id: 1,
+                delta: 1
+            })
+        ));
+        match updates_receiver.try_recv() {
+            Ok(StateUpdate::ResetBatches(mut counts)) => {
+                counts.sort_unstable();
+                assert_eq!(counts, vec![(1, 2), (2, 1)]);
+            }
+            update => panic!("Unexpected update: {update:?}"),
+        }
+        assert!(matches!(
+            updates_receiver.try_recv(),
+            Ok(StateUpdate::Batches {
+                stack_small_id: 2,
+                delta: -1
+            })
+        ));
+        assert!(matches!(
+            updates_receiver.try_recv(),
+            Ok(StateUpdate::ConcurrentRequests {
+                stack_small_id: 1,
+                delta: -1
+            })
+        ));
+    }
+
+    #[test]
+    fn test_stop_signals() {
+        let stop_signals = StopSignals::default();
+        stop_signals.stop("request-1");
+        assert!(!stop_signals.take("request-2"));
+        assert!(stop_signals.take("request-1"));
+        assert!(!stop_signals.take("request-1"));
+    }
+}
@@ -0,0 +1,628 @@
+//! Resumable Server-Sent Events streams.
+//!
+//! When resumable streams are enabled, the chunks of a streamed chat completion are produced by
+//! a background task, independently of the client connection, and the most recent ones are kept
+//! in a bounded buffer. Each chunk is sent as an event with a monotonically increasing ID.
+//!
+//! If the client connection drops mid-stream, the generation goes on for a grace period, during
+//! which the client can reconnect by sending the same signed request, with the same request ID,
+//! and the `Last-Event-ID` header set to the ID of the last event it received. The client then
+//! receives the events it missed, followed by the rest of the stream. Reconnecting does not lock
+//! compute units again: the request is charged once, when the generation ends.
+//!
+//! If no client reconnects within the grace period, the generation is stopped, and the stack is
+//! charged for the tokens generated so far, as for any dropped stream.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use atoma_utils::constants::PAYLOAD_HASH_SIZE;
+use axum::Error;
+use dashmap::{mapref::entry::Entry, DashMap};
+use futures::{Stream, StreamExt};
+use serde_json::Value;
+use sui_sdk::types::base_types::SuiAddress;
+use thiserror::Error;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::{config::ResumableStreamsConfig, drain::InFlightRequest};
+
+/// The header set by clients reconnecting to a stream, with the ID of the last event they
+/// received
+pub const LAST_EVENT_ID: &str = "Last-Event-ID";
+
+/// A chunk of a stream, together with its event ID
+pub type StreamChunk = (u64, Value);
+
+/// The request a stream was started for, which requests resuming the stream must match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamOwner {
+    /// The stack the request is charged to
+    pub stack_small_id: i64,
+    /// The Sui address that signed the request
+    pub sui_address: SuiAddress,
+    /// The hash of the request payload
+    pub payload_hash: [u8; PAYLOAD_HASH_SIZE],
+}
+
+/// Errors that can occur when resuming a stream
+#[derive(Debug, Error)]
+pub enum ResumeError {
+    /// There is no stream for the request ID
+    #[error("No resumable stream for request ID {0}, it may have expired")]
+    StreamNotFound(String),
+    /// The stream was started for another request
+    #[error("Request does not match the request the stream was started for")]
+    OwnerMismatch,
+    /// The events following the last event received by the client were evicted from the buffer
+    #[error("Events following event {0} are no longer buffered")]
+    EventsExpired(u64),
+    /// The last event received by the client does not exist yet
+    #[error("Event {0} has not been sent yet")]
+    UnknownEvent(u64),
+    /// A stream was already started for the request ID
+    #[error("A resumable stream already exists for request ID {0}")]
+    DuplicateRequestId(String),
+}
+
+/// The resumable streams of the node, keyed by request ID.
+pub struct ResumableStreams {
+    /// The resumable streams configuration
+    config: ResumableStreamsConfig,
+    /// The streams in progress, or ended less than a grace period ago
+    streams: DashMap<String, Arc<ResumableStream>>,
+}
+
+impl ResumableStreams {
+    /// Constructor
+    #[must_use]
+    pub fn new(config: ResumableStreamsConfig) -> Self {
+        Self {
+            config,
+            streams: DashMap::new(),
+        }
+    }
+
+    /// Whether streams can be resumed
+    #[must_use]
+    pub const fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Whether a stream is registered for the request ID (it is in progress, or ended less than
+    /// a grace period ago)
+    #[must_use]
+    pub fn contains(&self, request_id: &str) -> bool {
+        self.streams.contains_key(request_id)
+    }
+
+    /// Starts buffering the chunks of a stream in the background, and returns the chunks for
+    /// the client that sent the request.
+    ///
+    /// # Arguments
+    ///
+    /// * `request_id` - The ID of the request, used by clients to resume the stream
+    /// * `owner` - The request the stream is started for
+    /// * `chunks` - The chunks of the stream. The stream is dropped (stopping the generation)
+    ///   once it ends, or once no client has been connected for the grace period.
+    /// * `in_flight` - The request, held in-flight until the generation ends, so that the node
+    ///   waits for it when draining, even if no client is connected
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a stream is already registered for the request ID, as the request ID
+    /// identifies the stream clients resume. The chunks are then dropped, stopping the
+    /// generation.
+    pub fn start(
+        self: &Arc<Self>,
+        request_id: String,
+        owner: StreamOwner,
+        chunks: impl Stream<Item = Result<StreamChunk, Error>> + Send + 'static,
+        in_flight: InFlightRequest,
+    ) -> Result<impl Stream<Item = Result<StreamChunk, Error>> + Send + 'static, ResumeError> {
+        let stream = Arc::new(ResumableStream::new(owner));
+        match self.streams.entry(request_id.clone()) {
+            Entry::Occupied(_) => {
+                warn!(
+                    target = "atoma-service",
+                    level = "warn",
+                    request_id,
+                    "A resumable stream already exists for the request ID"
+                );
+                return Err(ResumeError::DuplicateRequestId(request_id));
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(stream.clone());
+            }
+        }
+        let subscription = stream.subscribe(0);
+        tokio::spawn(
+            self.clone()
+                .buffer_stream(request_id, stream, chunks, in_flight),
+        );
+        Ok(subscription.into_stream())
+    }
+
+    /// Resumes a stream, after the last event received by the client.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - There is no stream for the request ID (it never existed, or it ended more than a grace
+    ///   period ago)
+    /// - The stream was started for another request
+    /// - The events following the last event received are no longer buffered, or the last event
+    ///   has not been sent yet
+    pub fn resume(
+        &self,
+        request_id: &str,
+        owner: &StreamOwner,
+        last_event_id: u64,
+    ) -> Result<impl Stream<Item = Result<StreamChunk, Error>> + Send + 'static, ResumeError> {
+        let stream = self
+            .streams
+            .get(request_id)
+            .map(|stream| stream.clone())
+            .ok_or_else(|| ResumeError::StreamNotFound(request_id.to_string()))?;
+        if stream.owner != *owner {
+            return Err(ResumeError::OwnerMismatch);
+        }
+        let next_event_id = last_event_id.saturating_add(1);
+        {
+            let buffer = stream.lock();
+            if next_event_id > buffer.next_event_id {
+                return Err(ResumeError::UnknownEvent(last_event_id));
+            }
+            let first_buffered_event_id = buffer
+                .chunks
+                .front()
+                .map_or(buffer.next_event_id, |(event_id, _)| *event_id);
+            if next_event_id < first_buffered_event_id {
+                return Err(ResumeError::EventsExpired(last_event_id));
+            }
+        }
+        info!(
+            target = "atoma-service",
+            level = "info",
+            request_id,
+            last_event_id,
+            "Resuming stream"
+        );
+        Ok(stream.subscribe(next_event_id).into_stream())
+    }
+
+    /// Buffers the chunks of a stream, until the stream ends or is abandoned by its clients,
+    /// and removes the stream a grace period later.
+    async fn buffer_stream(
+        self: Arc<Self>,
+        request_id: String,
+        stream: Arc<ResumableStream>,
+        chunks: impl Stream<Item = Result<StreamChunk, Error>> + Send + 'static,
+        in_flight: InFlightRequest,
+    ) {
+        let grace_period = Duration::from_millis(self.config.grace_period_ms);
+        let mut chunks = Box::pin(chunks);
+        let end = loop {
+            tokio::select! {
+                chunk = chunks.next() => match chunk {
+                    Some(Ok(chunk)) => stream.push(chunk, self.config.buffer_size),
+                    Some(Err(e)) => break StreamEnd::Failed(e.to_string()),
+                    None => break StreamEnd::Completed,
+                },
+                () = stream.abandoned(grace_period) => {
+                    info!(
+                        target = "atoma-service",
+                        level = "info",
+                        request_id,
+                        "No client reconnected to the stream within the grace period, stopping it"
+                    );
+                    break StreamEnd::Abandoned;
+                }
+            }
+        };
+        // NOTE: Dropping the chunks stops the generation, if it is not complete, in which case
+        // the request is charged for the tokens generated so far.
+        drop(chunks);
+        drop(in_flight);
+        stream.finish(end);
+
+        // NOTE: The stream is kept for a grace period after it ends, so that clients whose
+        // connection dropped right before the end can still receive the last events.
+        stream.abandoned(grace_period).await;
+        self.streams.remove_if(&request_id, |_, registered| {
+            Arc::ptr_eq(registered, &stream)
+        });
+    }
+}
+
+/// How a stream ended
+#[derive(Clone, Debug)]
+enum StreamEnd {
+    /// All the chunks were produced
+    Completed,
+    /// The stream failed, with the given error
+    Failed(String),
+    /// No client was connected for the grace period, and the generation was stopped
+    Abandoned,
+}
+
+/// A stream, whose most recent chunks are buffered.
+struct ResumableStream {
+    /// The request the stream was started for
+    owner: StreamOwner,
+    /// The buffered chunks, and the clients of the stream
+    buffer: Mutex<StreamBuffer>,
+    /// Notified when a chunk is buffered, or when the stream ends
+    chunks_notify: Notify,
+    /// Notified when a client connects or disconnects, or when the stream is fully delivered
+    clients_notify: Notify,
+}
+
+#[derive(Default)]
+struct StreamBuffer {
+    /// The most recent chunks of the stream
+    chunks: VecDeque<StreamChunk>,
+    /// The ID of the next event of the stream
+    next_event_id: u64,
+    /// How the stream ended, if it did
+    end: Option<StreamEnd>,
+    /// The number of clients connected to the stream
+    clients: usize,
+    /// When the last client disconnected, if no client is connected
+    detached_at: Option<Instant>,
+    /// Whether a client received all the events of the stream
+    delivered: bool,
+}
+
+impl ResumableStream {
+    fn new(owner: StreamOwner) -> Self {
+        Self {
+            owner,
+            buffer: Mutex::new(StreamBuffer::default()),
+            chunks_notify: Notify::new(),
+            clients_notify: Notify::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, StreamBuffer> {
+        self.buffer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Buffers a chunk, evicting the oldest chunk if the buffer is full
+    fn push(&self, chunk: StreamChunk, buffer_size: usize) {
+        {
+            let mut buffer = self.lock();
+            buffer.next_event_id = chunk.0 + 1;
+            buffer.chunks.push_back(chunk);
+            while buffer.chunks.len() > buffer_size {
+                buffer.chunks.pop_front();
+            }
+        }
+        self.chunks_notify.notify_waiters();
+    }
+
+    fn finish(&self, end: StreamEnd) {
+        self.lock().end = Some(end);
+        self.chunks_notify.notify_waiters();
+    }
+
+    /// Connects a client to the stream, from the given event ID
+    fn subscribe(self: &Arc<Self>, next_event_id: u64) -> Subscription {
+        {
+            let mut buffer = self.lock();
+            buffer.clients += 1;
+            buffer.detached_at = None;
+        }
+        self.clients_notify.notify_waiters();
+        Subscription {
+            stream: self.clone(),
+            next_event_id,
+            done: false,
+        }
+    }
+
+    /// Resolves once no client has been connected to the stream for the grace period, or once
+    /// a client received all the events of the stream.
+    async fn abandoned(&self, grace_period: Duration) {
+        loop {
+            // NOTE: The notification future is created before the state is checked, so that
+            // no change of the clients is missed.
+            let clients_changed = self.clients_notify.notified();
+            let deadline = {
+                let buffer = self.lock();
+                if buffer.delivered {
+                    return;
+                }
+                buffer
+                    .detached_at
+                    .filter(|_| buffer.clients == 0)
+                    .map(|detached_at| detached_at + grace_period)
+            };
+            match deadline {
+                Some(deadline) if deadline <= Instant::now() => return,
+                Some(deadline) => tokio::select! {
+                    () = tokio::time::sleep_until(deadline.into()) => {}
+                    () = clients_changed => {}
+                },
+                None => clients_changed.await,
+            }
+        }
+    }
+}
+
+/// A client connection to a stream
+struct Subscription {
+    /// The stream
+    stream: Arc<ResumableStream>,
+    /// The ID of the next event to send to the client
+    next_event_id: u64,
+    /// Whether the last event was sent to the client
+    done: bool,
+}
+
+impl Subscription {
+    fn into_stream(self) -> impl Stream<Item = Result<StreamChunk, Error>> + Send + 'static {
+        futures::stream::unfold(self, |mut subscription| async move {
+            let chunk = subscription.next().await?;
+            Some((chunk, subscription))
+        })
+    }
+
+    /// Returns the next event for the client, waiting for it to be buffered if needed
+    async fn next(&mut self) -> Option<Result<StreamChunk, Error>> {
+        if self.done {
+            return None;
+        }
+        loop {
+            let chunk_buffered = self.stream.chunks_notify.notified();
+            {
+                let mut buffer = self.stream.lock();
+                if let Some(&(first_event_id, _)) = buffer.chunks.front() {
+                    if self.next_event_id < first_event_id {
+                        // NOTE: The client is too slow to keep up with the stream
+                        self.done = true;
+                        return Some(Err(Error::new(format!(
+                            "Event {} is no longer buffered",
+                            self.next_event_id
+                        ))));
+                    }
+                    let index = (self.next_event_id - first_event_id) as usize;
+                    if let Some(chunk) = buffer.chunks.get(index) {
+                        self.next_event_id += 1;
+                        return Some(Ok(chunk.clone()));
+                    }
+                }
+                match buffer.end.clone() {
+                    Some(StreamEnd::Completed) => {
+                        self.done = true;
+                        buffer.delivered = true;
+                        drop(buffer);
+                        self.stream.clients_notify.notify_waiters();
+                        return None;
+                    }
+                    Some(StreamEnd::Failed(e)) => {
+                        self.done = true;
+                        return Some(Err(Error::new(e)));
+                    }
+                    Some(StreamEnd::Abandoned) => {
+                        self.done = true;
+                        return None;
+                    }
+                    None => {}
+                }
+            }
+            chunk_buffered.await;
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        {
+            let mut buffer = self.stream.lock();
+            buffer.clients -= 1;
+            if buffer.clients == 0 {
+                buffer.detached_at = Some(Instant::now());
+            }
+        }
+        self.stream.clients_notify.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::channel::mpsc;
+    use serde_json::json;
+
+    use super::*;
+    use crate::drain::Drain;
+
+    const REQUEST_ID: &str = "request-id";
+
+    fn owner() -> StreamOwner {
+        StreamOwner {
+            stack_small_id: 1,
+            sui_address: SuiAddress::ZERO,
+            payload_hash: [1; PAYLOAD_HASH_SIZE],
+        }
+    }
+
+    fn resumable_streams(buffer_size: usize, grace_period_ms: u64) -> Arc<ResumableStreams> {
+        Arc::new(ResumableStreams::new(ResumableStreamsConfig {
+            enabled: true,
+            buffer_size,
+            grace_period_ms,
+        }))
+    }
+
+    fn in_flight() -> InFlightRequest {
+        Drain::default().track()
+    }
+
+    fn chunk(event_id: u64) -> StreamChunk {
+        (event_id, json!({ "event": event_id }))
+    }
+
+    #[tokio::test]
+    async fn test_resume_after_last_event_id() {
+        let streams = resumable_streams(16, 60_000);
+        let (sender, receiver) = mpsc::unbounded();
+        let mut client = Box::pin(
+            streams
+                .start(REQUEST_ID.to_string(), owner(), receiver, in_flight())
+                .unwrap(),
+        );
+        for event_id in 0..3 {
+            sender.unbounded_send(Ok(chunk(event_id))).unwrap();
+        }
+        assert_eq!(client.next().await.unwrap().unwrap(), chunk(0));
+        assert_eq!(client.next().await.unwrap().unwrap(), chunk(1));
+        drop(client);
+
+        // The generation goes on while the client is disconnected
+        sender.unbounded_send(Ok(chunk(3))).unwrap();
+        assert!(!sender.is_closed());
+
+        let mut client = Box::pin(streams.resume(REQUEST_ID, &owner(), 1).unwrap());
+        sender.close_channel();
+        let chunks = client
+            .by_ref()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(chunks, vec![chunk(2), chunk(3)]);
+    }
+
+    #[tokio::test]
+    async fn test_resume_errors() {
+        let streams = resumable_streams(2, 60_000);
+        let (sender, receiver) = mpsc::unbounded();
+        let _client = streams
+            .start(REQUEST_ID.to_string(), owner(), receiver, in_flight())
+            .unwrap();
+        for event_id in 0..4 {
+            sender.unbounded_send(Ok(chunk(event_id))).unwrap();
+        }
+        // NOTE: Wait for the chunks to be buffered
+        while streams.resume(REQUEST_ID, &owner(), 3).is_err() {
+            tokio::task::yield_now().await;
+        }
+
+        assert!(matches!(
+            streams.resume("other-request-id", &owner(), 2),
+            Err(ResumeError::StreamNotFound(_))
+        ));
+        let other_owner = StreamOwner {
+            payload_hash: [2; PAYLOAD_HASH_SIZE],
+            ..owner()
+        };
+        assert!(matches!(
+            streams.resume(REQUEST_ID, &other_owner, 2),
+            Err(ResumeError::OwnerMismatch)
+        ));
+        // Only events 2 and 3 are buffered
+        assert!(matches!(
+            streams.resume(REQUEST_ID, &owner(), 0),
+            Err(ResumeError::EventsExpired(0))
+        ));
+        assert!(matches!(
+            streams.resume(REQUEST_ID, &owner(), 4),
+            Err(ResumeError::UnknownEvent(4))
+        ));
+        assert!(streams.resume(REQUEST_ID, &owner(), 1).is_ok());
+        assert!(streams.resume(REQUEST_ID, &owner(), 3).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_request_id_is_rejected() {
+        let streams = resumable_streams(16, 60_000);
+        let (sender, receiver) = mpsc::unbounded();
+        let mut client = Box::pin(
+            streams
+                .start(REQUEST_ID.to_string(), owner(), receiver, in_flight())
+                .unwrap(),
+        );
+        assert!(streams.contains(REQUEST_ID));
+
+        let (other_sender, other_receiver) = mpsc::unbounded::<Result<StreamChunk, Error>>();
+        let other_owner = StreamOwner {
+            payload_hash: [2; PAYLOAD_HASH_SIZE],
+            ..owner()
+        };
+        assert!(matches!(
+            streams.start(
+                REQUEST_ID.to_string(),
+                other_owner,
+                other_receiver,
+                in_flight()
+            ),
+            Err(ResumeError::DuplicateRequestId(_))
+        ));
+        // The second generation is stopped, and the first stream can still be resumed
+        assert!(other_sender.is_closed());
+        sender.unbounded_send(Ok(chunk(0))).unwrap();
+        assert_eq!(client.next().await.unwrap().unwrap(), chunk(0));
+        assert!(streams.resume(REQUEST_ID, &owner(), 0).is_ok());
+        assert!(matches!(
+            streams.resume(REQUEST_ID, &other_owner, 0),
+            Err(ResumeError::OwnerMismatch)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_stream_is_stopped() {
+        let streams = resumable_streams(16, 50);
+        let (sender, receiver) = mpsc::unbounded();
+        let client = streams
+            .start(REQUEST_ID.to_string(), owner(), receiver, in_flight())
+            .unwrap();
+        sender.unbounded_send(Ok(chunk(0))).unwrap();
+        drop(client);
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        // The chunks stream was dropped, stopping the generation, and the stream was removed
+        assert!(sender.is_closed());
+        assert!(matches!(
+            streams.resume(REQUEST_ID, &owner(), 0),
+            Err(ResumeError::StreamNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_generation_is_in_flight_until_it_ends() {
+        let streams = resumable_streams(16, 60_000);
+        let drain = Drain::default();
+        let (sender, receiver) = mpsc::unbounded();
+        let client = streams
+            .start(REQUEST_ID.to_string(), owner(), receiver, drain.track())
+            .unwrap();
+        drop(client);
+
+        // The generation goes on while no client is connected, and is waited for when draining
+        drain.start();
+        assert!(!drain.wait_idle(Duration::from_millis(10)).await);
+        sender.close_channel();
+        assert!(drain.wait_idle(Duration::from_secs(5)).await);
+    }
+
+    #[tokio::test]
+    async fn test_failed_stream() {
+        let streams = resumable_streams(16, 60_000);
+        let (sender, receiver) = mpsc::unbounded();
+        let client = streams
+            .start(REQUEST_ID.to_string(), owner(), receiver, in_flight())
+            .unwrap();
+        sender.unbounded_send(Ok(chunk(0))).unwrap();
+        sender
+            .unbounded_send(Err(Error::new("Inference service failed")))
+            .unwrap();
+        let chunks = client.collect::<Vec<_>>().await;
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].as_ref().unwrap(), &chunk(0));
+        assert!(chunks[1].is_err());
+    }
+}
@@ -1,5 +1,6 @@
-use std::{collections::HashMap, sync::Arc};
+use std::sync::Arc;
 
+use arc_swap::ArcSwap;
 use atoma_confidential::types::{
     ConfidentialComputeDecryptionRequest, ConfidentialComputeDecryptionResponse,
     ConfidentialComputeEncryptionRequest, ConfidentialComputeEncryptionResponse,
@@ -8,20 +9,20 @@ use atoma_confidential::types::{
 use atoma_state::types::AtomaAtomaStateManagerEvent;
 use axum::{
     body::Body,
+    extract::{DefaultBodyLimit, Query, State},
     middleware::{from_fn, from_fn_with_state},
     response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
-use dashmap::{DashMap, DashSet};
 use flume::Sender as FlumeSender;
 use hyper::StatusCode;
 use prometheus::Encoder;
 use reqwest::Method;
+use serde::Deserialize;
 use serde_json::{json, Value};
 use sui_keys::keystore::FileBasedKeystore;
 use sui_sdk::types::digests::TransactionDigest;
-use tokenizers::Tokenizer;
 use tokio::{
     net::TcpListener,
     sync::{
@@ -37,7 +38,14 @@ use utoipa::OpenApi;
 
 use crate::{
     components::openapi::openapi_routes,
+    config::{ImageGenerationsConfig, TlsConfig},
+    drain::Drain,
+    filters::FilterPipeline,
     handlers::{
+        batches::{
+            create_batch_handler, get_batch_handler, get_batch_output_handler, BATCHES_PATH,
+            BATCH_OUTPUT_PATH, BATCH_PATH, MAX_BATCH_INPUT_SIZE,
+        },
         chat_completions::{
             chat_completions_handler, confidential_chat_completions_handler, CHAT_COMPLETIONS_PATH,
             CONFIDENTIAL_CHAT_COMPLETIONS_PATH,
@@ -47,20 +55,34 @@ use crate::{
             EMBEDDINGS_PATH,
         },
         image_generations::{
-            confidential_image_generations_handler, image_generations_handler,
-            CONFIDENTIAL_IMAGE_GENERATIONS_PATH, IMAGE_GENERATIONS_PATH,
+            confidential_image_generations_handler, image_edits_handler, image_generations_handler,
+            image_variations_handler, CONFIDENTIAL_IMAGE_GENERATIONS_PATH, IMAGE_EDITS_PATH,
+            IMAGE_GENERATIONS_PATH, IMAGE_VARIATIONS_PATH, MAX_IMAGE_INPUT_SIZE,
         },
         stop_streamer::stop_streamer_handler,
+        websocket::{
+            confidential_websocket_chat_completions_handler, websocket_chat_completions_handler,
+            CONFIDENTIAL_WEBSOCKET_CHAT_COMPLETIONS_PATH, WEBSOCKET_CHAT_COMPLETIONS_PATH,
+        },
     },
     middleware::{
-        confidential_compute_middleware, signature_verification_middleware,
+        confidential_compute_middleware, drain_middleware, request_filter_middleware,
+        request_id_middleware, resume_stream_middleware, signature_verification_middleware,
         verify_stack_permissions,
     },
+    models::ModelRegistry,
+    readiness::Readiness,
+    replication::{ConcurrentRequests, StopSignals},
+    resumable_streams::ResumableStreams,
+    tls::{serve_tls, ReloadableTlsConfig},
 };
 
 /// The path for the health check endpoint.
 pub const HEALTH_PATH: &str = "/health";
 
+/// The path for the readiness endpoint.
+pub const READY_PATH: &str = "/ready";
+
 /// The path for the metrics endpoint.
 pub const METRICS_PATH: &str = "/metrics";
 
@@ -112,11 +134,13 @@ pub struct AppState {
     ///
     /// This is useful to keep track of when the node should be able to claim a stack that is
     /// almost full, without compromising any possible mismatch of compute units calculations
-    /// due to concurrent accesses to a given stack (simultaneously).
-    pub concurrent_requests_per_stack: Arc<DashMap<i64, u64>>,
+    /// due to concurrent accesses to a given stack (simultaneously). The counts are shared with
+    /// the other replicas of the service, if replication is enabled.
+    pub concurrent_requests_per_stack: Arc<ConcurrentRequests>,
 
-    /// Client dropped streamer connections
-    pub client_dropped_streamer_connections: Arc<DashSet<String>>,
+    /// Client dropped streamer connections, shared with the other replicas of the service if
+    /// replication is enabled
+    pub client_dropped_streamer_connections: Arc<StopSignals>,
 
     /// Channel sender for managing application events.
     ///
@@ -149,29 +173,13 @@ pub struct AppState {
     /// Channel sender for requesting compute units from the blockchain.
     pub stack_retrieve_sender: StackRetrieveSender,
 
-    /// Tokenizer used for processing text input.
-    ///
-    /// The tokenizer is responsible for breaking down text input into
-    /// manageable tokens, which are then used in various natural language
-    /// processing tasks.
-    pub tokenizers: Arc<Vec<Arc<Tokenizer>>>,
-
-    /// List of available AI models.
-    ///
-    /// This list contains the names or identifiers of AI models that
-    /// the application can use for inference tasks. It allows the
-    /// application to dynamically select and switch between different
-    /// models as needed.
-    pub models: Arc<Vec<String>>,
-
-    /// Mapping between model names and the URLs of the chat completions
-    /// services available to the current node, together with the job names of
-    /// the vLLM instances running on each service.
+    /// The models served by the node, together with their tokenizers and the URLs of the
+    /// chat completions services available for each of them.
     ///
-    /// These URLs point to the external services responsible for performing
-    /// AI model chat completions. The application forwards requests to this
-    /// service to obtain AI-generated responses.
-    pub chat_completions_service_urls: HashMap<String, Vec<(String, String)>>,
+    /// The registry can be atomically replaced at runtime, when the node configuration is
+    /// reloaded. Requests load the current registry when they are received, so in-flight
+    /// requests are not affected by a reload.
+    pub model_registry: Arc<ArcSwap<ModelRegistry>>,
 
     /// URL for the embeddings service.
     ///
@@ -198,6 +206,26 @@ pub struct AppState {
     /// signing operations, allowing the application to manage multiple
     /// addresses and keys efficiently.
     pub address_index: usize,
+
+    /// The operator request and response filters.
+    ///
+    /// Request filters are applied to inference requests before they are
+    /// forwarded to the inference services, and response filters to the
+    /// responses before they are signed.
+    pub filters: Arc<FilterPipeline>,
+
+    /// The streamed chat completions that clients can resume after their connection drops.
+    pub resumable_streams: Arc<ResumableStreams>,
+
+    /// The validation and pricing configuration of image requests (allowed sizes, maximum
+    /// number of pixels, steps).
+    pub image_generations: Arc<ImageGenerationsConfig>,
+
+    /// The in-flight requests of the service, drained when the node shuts down.
+    pub drain: Drain,
+
+    /// The dependencies of the node checked by the readiness endpoint.
+    pub readiness: Arc<Readiness>,
 }
 
 /// Creates and configures the main router for the application.
@@ -244,43 +272,117 @@ pub fn create_router(app_state: AppState) -> Router {
     let regular_routes = Router::new()
         .route(CHAT_COMPLETIONS_PATH, post(chat_completions_handler))
         .route(EMBEDDINGS_PATH, post(embeddings_handler))
-        .route(IMAGE_GENERATIONS_PATH, post(image_generations_handler));
+        .route(IMAGE_GENERATIONS_PATH, post(image_generations_handler))
+        .route(
+            IMAGE_EDITS_PATH,
+            post(image_edits_handler).layer(DefaultBodyLimit::max(MAX_IMAGE_INPUT_SIZE)),
+        )
+        .route(
+            IMAGE_VARIATIONS_PATH,
+            post(image_variations_handler).layer(DefaultBodyLimit::max(MAX_IMAGE_INPUT_SIZE)),
+        );
+
+    // NOTE: Batch requests are authenticated by their handlers, as the signature covers the
+    // whole batch input file (or the batch id) rather than a single request body.
+    let batch_routes = Router::new()
+        .route(
+            BATCHES_PATH,
+            post(create_batch_handler).layer(DefaultBodyLimit::max(MAX_BATCH_INPUT_SIZE)),
+        )
+        .route(BATCH_PATH, get(get_batch_handler))
+        .route(BATCH_OUTPUT_PATH, get(get_batch_output_handler));
 
     let public_routes = Router::new()
         .route(HEALTH_PATH, get(health))
+        .route(READY_PATH, get(ready))
         .route(STOP_STREAMER_PATH, post(stop_streamer_handler))
         .route(METRICS_PATH, get(metrics_handler));
 
-    Router::new()
-        .merge(
-            confidential_routes.layer(
-                ServiceBuilder::new()
-                    .layer(from_fn_with_state(
-                        app_state.clone(),
-                        confidential_compute_middleware,
-                    ))
-                    .layer(from_fn_with_state(
-                        app_state.clone(),
-                        verify_stack_permissions,
-                    )),
-            ),
-        )
-        .merge(
-            regular_routes.layer(
-                ServiceBuilder::new()
-                    .layer(from_fn(signature_verification_middleware))
-                    .layer(from_fn_with_state(
-                        app_state.clone(),
-                        verify_stack_permissions,
-                    )),
-            ),
+    // NOTE: WebSocket requests are sent as the first message of the connection, and are
+    // authenticated by the same middleware as the routes they mirror, once received.
+    let websocket_routes = Router::new()
+        .route(
+            WEBSOCKET_CHAT_COMPLETIONS_PATH,
+            get(websocket_chat_completions_handler),
         )
+        .route(
+            CONFIDENTIAL_WEBSOCKET_CHAT_COMPLETIONS_PATH,
+            get(confidential_websocket_chat_completions_handler),
+        );
+
+    // NOTE: New requests are rejected while the node drains the in-flight requests, on shutdown
+    let service_routes = Router::new()
+        .merge(with_confidential_inference_middleware(
+            confidential_routes,
+            &app_state,
+        ))
+        .merge(with_inference_middleware(regular_routes, &app_state))
+        .merge(websocket_routes)
+        .merge(batch_routes)
+        .layer(from_fn_with_state(app_state.clone(), drain_middleware));
+
+    Router::new()
+        .merge(service_routes)
         .merge(public_routes)
         .with_state(app_state)
         .merge(openapi_routes())
+        .layer(from_fn(request_id_middleware))
         .layer(cors)
 }
 
+/// Applies the middleware of the inference routes to a router: request signature verification,
+/// resumption of streams, stack permissions verification (and compute units locking), and
+/// request filters.
+pub(crate) fn with_inference_middleware(
+    routes: Router<AppState>,
+    app_state: &AppState,
+) -> Router<AppState> {
+    routes.layer(
+        ServiceBuilder::new()
+            .layer(from_fn(signature_verification_middleware))
+            .layer(from_fn_with_state(
+                app_state.clone(),
+                resume_stream_middleware,
+            ))
+            .layer(from_fn_with_state(
+                app_state.clone(),
+                verify_stack_permissions,
+            ))
+            .layer(from_fn_with_state(
+                app_state.clone(),
+                request_filter_middleware,
+            )),
+    )
+}
+
+/// Applies the middleware of the confidential inference routes to a router: request
+/// decryption, resumption of streams, stack permissions verification (and compute units
+/// locking), and request filters.
+pub(crate) fn with_confidential_inference_middleware(
+    routes: Router<AppState>,
+    app_state: &AppState,
+) -> Router<AppState> {
+    routes.layer(
+        ServiceBuilder::new()
+            .layer(from_fn_with_state(
+                app_state.clone(),
+                confidential_compute_middleware,
+            ))
+            .layer(from_fn_with_state(
+                app_state.clone(),
+                resume_stream_middleware,
+            ))
+            .layer(from_fn_with_state(
+                app_state.clone(),
+                verify_stack_permissions,
+            ))
+            .layer(from_fn_with_state(
+                app_state.clone(),
+                request_filter_middleware,
+            )),
+    )
+}
+
 /// Starts and runs the HTTP server with graceful shutdown handling.
 ///
 /// This function initializes and runs the main server instance with the provided configuration.
@@ -292,6 +394,8 @@ pub fn create_router(app_state: AppState) -> Router {
 /// * `app_state` - The shared application state containing database connections, tokenizers,
 ///   and other configuration.
 /// * `tcp_listener` - A configured TCP listener that specifies the address and port for the server.
+/// * `tls_config` - The TLS configuration of the server, if it terminates TLS (otherwise it is
+///   served over plain HTTP).
 /// * `shutdown_receiver` - A channel receiver used to listen for shutdown signals.
 ///
 /// # Returns
@@ -302,6 +406,7 @@ pub fn create_router(app_state: AppState) -> Router {
 ///
 /// This function will return an error if:
 /// - The server fails to start or encounters an error while running
+/// - The TLS certificate or private key cannot be loaded
 /// - The shutdown signal fails to be sent through the channel
 ///
 /// # Panics
@@ -317,14 +422,23 @@ pub fn create_router(app_state: AppState) -> Router {
 /// let listener = TcpListener::bind("127.0.0.1:3000").await?;
 /// let (shutdown_tx, shutdown_rx) = watch::channel(false);
 ///
-/// run_server(app_state, listener, shutdown_rx).await?;
+/// run_server(app_state, listener, None, shutdown_rx).await?;
 /// ```
 pub async fn run_server(
     app_state: AppState,
     tcp_listener: TcpListener,
+    tls_config: Option<TlsConfig>,
     mut shutdown_receiver: Receiver<bool>,
 ) -> anyhow::Result<()> {
     let app = create_router(app_state);
+    if let Some(tls_config) = tls_config {
+        let tls_config = ReloadableTlsConfig::new(tls_config)?;
+        tokio::join!(
+            serve_tls(tcp_listener, app, &tls_config, shutdown_receiver.clone()),
+            tls_config.clone().watch(shutdown_receiver),
+        );
+        return Ok(());
+    }
     let server =
         axum::serve(tcp_listener, app.into_make_service()).with_graceful_shutdown(async move {
             shutdown_receiver
@@ -349,8 +463,9 @@ pub(crate) struct HealthOpenApi;
 ///
 /// # Returns
 ///
-/// Returns a static string "OK" to indicate that the server is healthy and
-/// functioning properly.
+/// Returns `{"status": "ok"}` to indicate that the server is healthy and
+/// functioning properly, or a `503 Service Unavailable` with `{"status": "draining"}`
+/// once the node is shutting down, so that load balancers stop routing requests to it.
 ///
 /// # Examples
 ///
@@ -364,11 +479,85 @@ pub(crate) struct HealthOpenApi;
     path = "",
     tag = "health",
     responses(
-        (status = OK, description = "Service is healthy", body = Value)
+        (status = OK, description = "Service is healthy", body = Value),
+        (status = SERVICE_UNAVAILABLE, description = "Service is shutting down", body = Value)
     )
 )]
-async fn health() -> impl IntoResponse {
-    Json(json!({ "status": "ok" }))
+async fn health(State(state): State<AppState>) -> impl IntoResponse {
+    if state.drain.is_draining() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "draining" })),
+        );
+    }
+    (StatusCode::OK, Json(json!({ "status": "ok" })))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(ready))]
+pub(crate) struct ReadyOpenApi;
+
+/// Query parameters of the readiness endpoint.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct ReadyQuery {
+    /// Whether to include the result of each check in the response
+    #[serde(default)]
+    verbose: bool,
+}
+
+/// Readiness
+///
+/// This function checks that the node can serve requests: that the database and the Sui RPC
+/// node are reachable, that the Sui events subscriber is not lagging, that each model has an
+/// inference service available, and the confidential compute and P2P network status.
+///
+/// # Returns
+///
+/// Returns `{"status": "ready"}` if all the checks pass, otherwise a `503 Service Unavailable`
+/// with `{"status": "not_ready"}` (or `{"status": "draining"}` once the node is shutting down).
+/// With `?verbose=true`, the result of each check is included under `checks`.
+#[utoipa::path(
+    get,
+    path = "",
+    tag = "health",
+    params(
+        ("verbose" = Option<bool>, Query, description = "Whether to include the result of each check")
+    ),
+    responses(
+        (status = OK, description = "Node is ready to serve requests", body = Value),
+        (status = SERVICE_UNAVAILABLE, description = "Node is not ready, or shutting down", body = Value)
+    )
+)]
+async fn ready(
+    State(state): State<AppState>,
+    Query(query): Query<ReadyQuery>,
+) -> impl IntoResponse {
+    if state.drain.is_draining() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "draining" })),
+        );
+    }
+    let report = state
+        .readiness
+        .check(
+            &state.model_registry.load(),
+            &state.embeddings_service_url,
+            &state.image_generations_service_url,
+        )
+        .await;
+    let (status_code, status) = if report.ready {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not_ready")
+    };
+    if query.verbose {
+        return (
+            status_code,
+            Json(json!({ "status": status, "checks": report.checks })),
+        );
+    }
+    (status_code, Json(json!({ "status": status })))
 }
 
 /// OpenAPI documentation for the metrics endpoint.
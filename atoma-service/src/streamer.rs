@@ -1,4 +1,5 @@
 use std::{
+    collections::BTreeMap,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
@@ -8,22 +9,21 @@ use std::{
 use atoma_state::types::AtomaAtomaStateManagerEvent;
 use atoma_utils::{
     constants::{NONCE_SIZE, PAYLOAD_HASH_SIZE, SALT_SIZE},
-    encryption::encrypt_plaintext,
+    encryption::{encrypt_stream_chunk, SymmetricSecret, STREAM_CHUNK_VERSION},
     hashing::blake2b_hash,
 };
 use axum::body::Bytes;
 use axum::{response::sse::Event, Error};
 use base64::{engine::general_purpose::STANDARD, Engine};
-use dashmap::{DashMap, DashSet};
 use flume::Sender as FlumeSender;
 use futures::Stream;
 use opentelemetry::KeyValue;
 use serde_json::{json, Value};
 use sui_keys::keystore::FileBasedKeystore;
 use tracing::{error, info, instrument};
-use x25519_dalek::SharedSecret;
 
 use crate::{
+    filters::{FilterPipeline, ResponseFilterContext},
     handlers::{
         handle_concurrent_requests_count_decrement,
         metrics::{
@@ -31,8 +31,10 @@ use crate::{
             CHAT_COMPLETIONS_INTER_TOKEN_GENERATION_TIME, CHAT_COMPLETIONS_OUTPUT_TOKENS_METRICS,
             CHAT_COMPLETIONS_STREAMING_LATENCY_METRICS, CHAT_COMPLETIONS_TIME_TO_FIRST_TOKEN,
         },
+        structured_output::StructuredOutputValidator,
         update_stack_num_compute_units, USAGE_KEY,
     },
+    replication::{ConcurrentRequests, StopSignals},
     server::utils,
 };
 
@@ -51,6 +53,15 @@ const KEEP_ALIVE_TEXT_CHUNK: &[u8] = b"keep-alive-text\n";
 /// The choices key
 const CHOICES: &str = "choices";
 
+/// The choice index key
+const INDEX_KEY: &str = "index";
+
+/// The choice delta key
+const DELTA_KEY: &str = "delta";
+
+/// The delta content key
+const CONTENT_KEY: &str = "content";
+
 /// The ciphertext key
 const CIPHERTEXT_KEY: &str = "ciphertext";
 
@@ -72,14 +83,24 @@ const RESPONSE_HASH_KEY: &str = "response_hash";
 /// The signature key
 const SIGNATURE_KEY: &str = "signature";
 
+/// The chunk index key, for encrypted chunks
+const CHUNK_INDEX_KEY: &str = "chunk_index";
+
+/// The end-of-stream marker key, for encrypted chunks
+const IS_FINAL_KEY: &str = "is_final";
+
+/// The stream chunk format version key, for encrypted chunks
+const VERSION_KEY: &str = "version";
+
 /// Metadata required for encrypting streaming responses to clients.
 ///
 /// This structure contains the cryptographic elements needed to establish
 /// secure communication during streaming operations.
 pub struct StreamingEncryptionMetadata {
-    /// The shared secret key derived from ECDH key exchange
-    pub shared_secret: SharedSecret,
-    /// A unique nonce value to prevent replay attacks
+    /// The secret the chunk encryption key is derived from (the ECDH shared secret,
+    /// or the response secret exported from the request HPKE context)
+    pub secret: SymmetricSecret,
+    /// The stream base nonce, from which a distinct nonce is derived for each chunk
     pub nonce: [u8; NONCE_SIZE],
     /// Additional randomness used in the encryption process
     pub salt: [u8; SALT_SIZE],
@@ -88,9 +109,9 @@ pub struct StreamingEncryptionMetadata {
 /// A structure for streaming chat completion chunks.
 pub struct Streamer {
     /// The number of concurrent requests for the stack
-    concurrent_requests: Arc<DashMap<i64, u64>>,
+    concurrent_requests: Arc<ConcurrentRequests>,
     /// The client dropped streamer connections
-    client_dropped_streamer_connections: Arc<DashSet<String>>,
+    client_dropped_streamer_connections: Arc<StopSignals>,
     /// The stream of bytes from the inference service
     stream: Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>>,
     /// Current status of the stream
@@ -136,6 +157,19 @@ pub struct Streamer {
     streamer_computed_num_tokens: i64,
     /// The number of input tokens for the request
     num_input_tokens: i64,
+    /// The index of the next encrypted chunk, used to derive the chunk nonce
+    /// and authenticate the chunk position within the stream (confidential streams only)
+    encrypted_chunk_index: u64,
+    /// Validator for the streamed output, if the request has a strict JSON schema response format
+    structured_output_validator: Option<Arc<StructuredOutputValidator>>,
+    /// The operator response filters, applied to each chunk before it is signed
+    filters: Arc<FilterPipeline>,
+    /// The content streamed so far for each choice, keyed by choice index (only accumulated
+    /// when the output is validated against a JSON schema)
+    choice_contents: BTreeMap<u64, String>,
+    /// The ID of the next event sent to the client, so that clients can resume the stream
+    /// after the last event they received
+    next_event_id: u64,
 }
 
 /// Represents the various states of a streaming process
@@ -157,8 +191,8 @@ impl Streamer {
     pub fn new(
         stream: impl Stream<Item = Result<Bytes, reqwest::Error>> + Send + 'static,
         state_manager_sender: FlumeSender<AtomaAtomaStateManagerEvent>,
-        concurrent_requests: Arc<DashMap<i64, u64>>,
-        client_dropped_streamer_connections: Arc<DashSet<String>>,
+        concurrent_requests: Arc<ConcurrentRequests>,
+        client_dropped_streamer_connections: Arc<StopSignals>,
         stack_small_id: i64,
         num_input_tokens: i64,
         estimated_total_compute_units: i64,
@@ -167,6 +201,8 @@ impl Streamer {
         address_index: usize,
         model: String,
         streaming_encryption_metadata: Option<StreamingEncryptionMetadata>,
+        structured_output_validator: Option<Arc<StructuredOutputValidator>>,
+        filters: Arc<FilterPipeline>,
         endpoint: String,
         request_id: String,
         first_token_generation_timer: Instant,
@@ -193,6 +229,56 @@ impl Streamer {
             is_final_chunk_handled: false,
             streamer_computed_num_tokens: 0,
             num_input_tokens,
+            encrypted_chunk_index: 0,
+            structured_output_validator,
+            filters,
+            choice_contents: BTreeMap::new(),
+            next_event_id: 0,
+        }
+    }
+
+    /// Returns the request ID of the stream
+    #[must_use]
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    /// Accumulates the content of the choices of a streamed chunk, or, for the final
+    /// (usage) chunk, validates the accumulated content against the requested JSON schema
+    /// and adds the validation result to the chunk.
+    ///
+    /// This is a no-op if the request has no strict JSON schema response format.
+    fn handle_structured_output(&mut self, chunk: &mut Value) {
+        let Some(validator) = self.structured_output_validator.clone() else {
+            return;
+        };
+        let Some(choices) = chunk.get(CHOICES).and_then(Value::as_array) else {
+            return;
+        };
+        if choices.is_empty() {
+            if chunk.get(USAGE_KEY).is_some() {
+                let validation = validator.validate(
+                    self.choice_contents
+                        .iter()
+                        .map(|(index, content)| (*index, Some(content.as_str()))),
+                );
+                validator.annotate(chunk, &validation, &self.model);
+            }
+            return;
+        }
+        for (position, choice) in choices.iter().enumerate() {
+            let index = choice
+                .get(INDEX_KEY)
+                .and_then(Value::as_u64)
+                .unwrap_or(position as u64);
+            let content = self.choice_contents.entry(index).or_default();
+            if let Some(delta_content) = choice
+                .get(DELTA_KEY)
+                .and_then(|delta| delta.get(CONTENT_KEY))
+                .and_then(Value::as_str)
+            {
+                content.push_str(delta_content);
+            }
         }
     }
 
@@ -235,7 +321,8 @@ impl Streamer {
             endpoint = "handle_final_chunk",
             stack_small_id = self.stack_small_id,
             estimated_total_compute_units = self.estimated_total_compute_units,
-            payload_hash = hex::encode(self.payload_hash)
+            payload_hash = hex::encode(self.payload_hash),
+            request_id = self.request_id
         ),
         err
     )]
@@ -324,6 +411,7 @@ impl Streamer {
                 .send(AtomaAtomaStateManagerEvent::UpdateStackTotalHash {
                     stack_small_id: self.stack_small_id,
                     total_hash: total_hash_bytes,
+                    request_id: Some(self.request_id.clone()),
                 })
         {
             error!(
@@ -346,8 +434,10 @@ impl Streamer {
             self.stack_small_id,
             self.estimated_total_compute_units,
             total_compute_units as i64,
+            1,
             &self.endpoint,
             num_concurrent_requests,
+            Some(self.request_id.as_str()),
         ) {
             error!(
                 target = "atoma-service-streamer",
@@ -398,46 +488,52 @@ impl Streamer {
 
     /// Handles the encryption request for a chunk of streaming data.
     ///
-    /// This method initiates the encryption process for a given chunk by:
-    /// 1. Creating a oneshot channel for receiving the encryption response
-    /// 2. Sending the encryption request with the chunk data to the confidential compute service
-    /// 3. Setting up the streamer to wait for the encrypted response
+    /// Each chunk is encrypted under a nonce derived from the stream base nonce and the
+    /// chunk index, and is authenticated (as AES-GCM associated data) against the request ID,
+    /// the chunk index and the end-of-stream marker. This way, clients can detect reordered,
+    /// dropped, spliced or truncated chunks, using `atoma_utils::encryption::StreamDecryptor`.
+    /// Every encrypted chunk carries the `STREAM_CHUNK_VERSION` it was encrypted with, so that
+    /// clients can tell it apart from chunks of the previous, unversioned format.
     ///
     /// # Arguments
     ///
     /// * `chunk` - The JSON value containing the data to be encrypted
     /// * `usage` - The usage of the chunk
     /// * `streaming_encryption_metadata` - The streaming encryption metadata
+    /// * `request_id` - The request ID, bound to the chunk as associated data
+    /// * `chunk_index` - The index of the chunk within the stream
+    /// * `is_final` - Whether this is the last chunk of the stream
     ///
     /// # Returns
     ///
-    /// Returns a `Result<(), Error>` where:
-    /// * `Ok(())` - The encryption request was successfully sent
-    /// * `Err(Error)` - An error occurred while sending the encryption request
-    ///
-    /// # State Changes
-    ///
-    /// * Sets `waiting_for_encrypted_chunk` to `true`
-    /// * Updates `encryption_response_receiver` with the new receiver
-    #[instrument(level = "debug", skip_all, err)]
+    /// Returns a `Result<Value, Error>` where:
+    /// * `Ok(Value)` - The encrypted chunk, with its format version, nonce, index and end-of-stream marker
+    /// * `Err(Error)` - An error occurred while encrypting the chunk
+    #[instrument(level = "debug", skip_all, fields(chunk_index, is_final), err)]
     fn handle_encryption_request(
         chunk: &Value,
         usage: Option<&Value>,
         streaming_encryption_metadata: &StreamingEncryptionMetadata,
+        request_id: &str,
+        chunk_index: u64,
+        is_final: bool,
     ) -> Result<Value, Error> {
         let StreamingEncryptionMetadata {
-            shared_secret,
+            secret,
             nonce,
             salt,
         } = streaming_encryption_metadata;
 
         // NOTE: We remove the usage key from the chunk before encryption
         // because we need to send the usage key back to the client in the final chunk
-        let (encrypted_chunk, nonce) = encrypt_plaintext(
+        let (encrypted_chunk, nonce) = encrypt_stream_chunk(
             chunk.to_string().as_bytes(),
-            shared_secret,
+            secret,
             salt,
-            Some(*nonce),
+            nonce,
+            request_id,
+            chunk_index,
+            is_final,
         )
         .map_err(|e| {
             error!(
@@ -449,18 +545,50 @@ impl Streamer {
             Error::new(format!("Error encrypting chunk: {e}"))
         })?;
 
+        let mut encrypted_chunk = json!({
+            CIPHERTEXT_KEY: STANDARD.encode(encrypted_chunk),
+            NONCE_KEY: STANDARD.encode(nonce),
+            CHUNK_INDEX_KEY: chunk_index,
+            IS_FINAL_KEY: is_final,
+            VERSION_KEY: STREAM_CHUNK_VERSION,
+        });
         if let Some(usage) = usage {
-            Ok(json!({
-                CIPHERTEXT_KEY: STANDARD.encode(encrypted_chunk),
-                NONCE_KEY: STANDARD.encode(nonce),
-                USAGE_KEY: usage.clone(),
-            }))
-        } else {
-            Ok(json!({
-                CIPHERTEXT_KEY: STANDARD.encode(encrypted_chunk),
-                NONCE_KEY: STANDARD.encode(nonce),
-            }))
+            encrypted_chunk[USAGE_KEY] = usage.clone();
         }
+        Ok(encrypted_chunk)
+    }
+
+    /// Encrypts the next chunk of the stream, if the stream is confidential.
+    ///
+    /// Non-confidential chunks are returned as is. For confidential streams, the chunk
+    /// is encrypted at the current chunk index, which is then incremented.
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk` - The JSON value containing the data to be encrypted
+    /// * `usage` - The usage of the chunk, sent in plaintext along the encrypted chunk
+    /// * `is_final` - Whether this is the last chunk of the stream
+    fn maybe_encrypt_chunk(
+        &mut self,
+        chunk: Value,
+        usage: Option<&Value>,
+        is_final: bool,
+    ) -> Result<Value, Error> {
+        let Some(streaming_encryption_metadata) = self.streaming_encryption_metadata.as_ref()
+        else {
+            return Ok(chunk);
+        };
+        // NOTE: We only need to perform chunk encryption when sending the chunk back to the client
+        let encrypted_chunk = Self::handle_encryption_request(
+            &chunk,
+            usage,
+            streaming_encryption_metadata,
+            &self.request_id,
+            self.encrypted_chunk_index,
+            is_final,
+        )?;
+        self.encrypted_chunk_index += 1;
+        Ok(encrypted_chunk)
     }
 
     /// Processes an individual chunk from the streaming response.
@@ -485,7 +613,7 @@ impl Streamer {
     ///
     /// # Returns
     /// Returns a `Poll` containing:
-    /// * `Some(Ok(Event))` - A successfully processed chunk ready to send to the client
+    /// * `Some(Ok(Value))` - A successfully processed (signed, and possibly encrypted) chunk ready to send to the client
     /// * `Some(Err(Error))` - An error occurred during processing
     /// * `None` - Stream has completed ([DONE] received)
     /// * `Poll::Pending` - More data needed to complete chunk processing
@@ -508,10 +636,11 @@ impl Streamer {
             endpoint = self.endpoint,
             stack_small_id = self.stack_small_id,
             estimated_total_compute_units = self.estimated_total_compute_units,
-            payload_hash = hex::encode(self.payload_hash)
+            payload_hash = hex::encode(self.payload_hash),
+            request_id = self.request_id
         )
     )]
-    fn handle_streaming_chunk(&mut self, chunk: Bytes) -> Poll<Option<Result<Event, Error>>> {
+    fn handle_streaming_chunk(&mut self, chunk: Bytes) -> Poll<Option<Result<Value, Error>>> {
         if self.status != StreamStatus::Started {
             self.status = StreamStatus::Started;
         }
@@ -542,7 +671,7 @@ impl Streamer {
             return Poll::Ready(None);
         }
 
-        let chunk = match serde_json::from_str::<Value>(chunk_str) {
+        let mut chunk = match serde_json::from_str::<Value>(chunk_str) {
             Ok(chunk) => {
                 if !self.chunk_buffer.is_empty() {
                     error!(
@@ -631,6 +760,25 @@ impl Streamer {
             self.decoding_phase_timer = Some(timer);
         }
 
+        // NOTE: The structured output validation result is added to the final chunk before it gets signed
+        self.handle_structured_output(&mut chunk);
+
+        if let Err(e) = self.filters.filter_response(
+            &ResponseFilterContext {
+                stack_small_id: self.stack_small_id,
+                endpoint: &self.endpoint,
+            },
+            &mut chunk,
+        ) {
+            error!(
+                target = "atoma-service-streamer",
+                level = "error",
+                endpoint = self.endpoint,
+                "Chunk rejected by response filter: {e}"
+            );
+            return Poll::Ready(Some(Err(Error::new(e.to_string()))));
+        }
+
         let (signature, response_hash) = self.sign_chunk(&chunk)?;
 
         let Some(choices) = chunk.get(CHOICES).and_then(|choices| choices.as_array()) else {
@@ -647,21 +795,10 @@ impl Streamer {
             // Check if this is a final chunk with usage info
             if let Some(usage) = chunk.get(USAGE_KEY) {
                 self.status = StreamStatus::Completed;
-                let mut chunk = if let Some(streaming_encryption_metadata) =
-                    self.streaming_encryption_metadata.as_ref()
-                {
-                    // NOTE: We only need to perform chunk encryption when sending the chunk back to the client
-                    Self::handle_encryption_request(
-                        &chunk,
-                        Some(usage),
-                        streaming_encryption_metadata,
-                    )?
-                } else {
-                    chunk.clone()
-                };
+                let mut chunk = self.maybe_encrypt_chunk(chunk.clone(), Some(usage), true)?;
                 self.handle_final_chunk(usage, response_hash)?;
                 update_chunk(&mut chunk, &signature, response_hash);
-                Poll::Ready(Some(Ok(Event::default().json_data(&chunk)?)))
+                Poll::Ready(Some(Ok(chunk)))
             } else {
                 error!(
                     target = "atoma-service",
@@ -672,22 +809,18 @@ impl Streamer {
                 Poll::Ready(Some(Err(Error::new("Error getting usage from chunk"))))
             }
         } else {
-            let mut chunk = if let Some(streaming_encryption_metadata) =
-                self.streaming_encryption_metadata.as_ref()
-            {
-                // NOTE: We only need to perform chunk encryption when sending the chunk back to the client
-                Self::handle_encryption_request(&chunk, None, streaming_encryption_metadata)?
-            } else {
-                chunk
-            };
+            // NOTE: If the client dropped the streamer connection, this chunk is the last one
+            // of the stream, and it must be flagged as such before being encrypted.
+            let is_client_dropped_streamer_connection = self
+                .client_dropped_streamer_connections
+                .take(&self.request_id);
+            let mut chunk =
+                self.maybe_encrypt_chunk(chunk, None, is_client_dropped_streamer_connection)?;
             update_chunk(&mut chunk, &signature, response_hash);
             // NOTE: We increment the number of tokens computed so far, as we are processing a new chunk
             // which corresponds to a new generated token.
             self.streamer_computed_num_tokens += 1;
-            if let Some(_client_dropped_streamer_connection) = self
-                .client_dropped_streamer_connections
-                .remove(&self.request_id)
-            {
+            if is_client_dropped_streamer_connection {
                 info!(
                     target = "atoma-service-streamer",
                     level = "info",
@@ -719,9 +852,9 @@ impl Streamer {
                     endpoint = self.endpoint,
                     "Client dropped streamer connection, updating usage, chunk = {chunk}"
                 );
-                return Poll::Ready(Some(Ok(Event::default().json_data(&chunk)?)));
+                return Poll::Ready(Some(Ok(chunk)));
             }
-            Poll::Ready(Some(Ok(Event::default().json_data(&chunk)?)))
+            Poll::Ready(Some(Ok(chunk)))
         }
     }
 }
@@ -729,7 +862,56 @@ impl Streamer {
 impl Stream for Streamer {
     type Item = Result<Event, Error>;
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_next_event(cx).map(|event| {
+            event.map(|event| event.and_then(|(event_id, chunk)| chunk_event(event_id, &chunk)))
+        })
+    }
+}
+
+/// Creates the Server-Sent Event sending a chunk, with its event ID
+///
+/// # Errors
+///
+/// Returns an error if the chunk cannot be serialized.
+pub fn chunk_event(event_id: u64, chunk: &Value) -> Result<Event, Error> {
+    Event::default().id(event_id.to_string()).json_data(chunk)
+}
+
+impl Streamer {
+    /// Returns the stream of signed (and possibly encrypted) chunks, as JSON values.
+    ///
+    /// This is used by transports other than Server-Sent Events (e.g. WebSockets), for which
+    /// chunks are processed and accounted for exactly as for Server-Sent Events.
+    pub fn into_chunks(mut self) -> impl Stream<Item = Result<Value, Error>> + Send {
+        futures::stream::poll_fn(move |cx| self.poll_next_chunk(cx))
+    }
+
+    /// Returns the stream of signed (and possibly encrypted) chunks, together with their
+    /// monotonically increasing event IDs.
+    pub fn into_events(mut self) -> impl Stream<Item = Result<(u64, Value), Error>> + Send {
+        futures::stream::poll_fn(move |cx| self.poll_next_event(cx))
+    }
+
+    /// Polls the next chunk of the stream, and assigns it the next event ID.
+    fn poll_next_event(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<(u64, Value), Error>>> {
+        match self.poll_next_chunk(cx) {
+            Poll::Ready(Some(Ok(chunk))) => {
+                let event_id = self.next_event_id;
+                self.next_event_id += 1;
+                Poll::Ready(Some(Ok((event_id, chunk))))
+            }
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Polls the next chunk of the inference service stream, and processes it.
+    fn poll_next_chunk(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Value, Error>>> {
         if self.status == StreamStatus::Completed {
             return Poll::Ready(None);
         }
@@ -745,17 +927,17 @@ impl Stream for Streamer {
 
 impl Streamer {
     /// Handles a successful chunk from the stream
-    fn handle_poll_chunk(&mut self, chunk: Bytes) -> Poll<Option<Result<Event, Error>>> {
+    fn handle_poll_chunk(&mut self, chunk: Bytes) -> Poll<Option<Result<Value, Error>>> {
         match self.handle_streaming_chunk(chunk) {
-            Poll::Ready(Some(Ok(event))) => self.handle_successful_event(event),
+            Poll::Ready(Some(Ok(chunk))) => self.handle_successful_chunk(chunk),
             Poll::Ready(Some(Err(e))) => self.handle_streaming_error(e),
             Poll::Ready(None) => Poll::Ready(None),
             Poll::Pending => Poll::Pending,
         }
     }
 
-    /// Handles a successful event, updating timers
-    fn handle_successful_event(&mut self, event: Event) -> Poll<Option<Result<Event, Error>>> {
+    /// Handles a successfully processed chunk, updating timers
+    fn handle_successful_chunk(&mut self, chunk: Value) -> Poll<Option<Result<Value, Error>>> {
         // Observe the previous timer if it exists
         if let Some(timer) = self.inter_stream_token_latency_timer.take() {
             let elapsed = timer.elapsed();
@@ -775,25 +957,25 @@ impl Streamer {
         // Start the timer after we've processed this chunk
         self.inter_stream_token_latency_timer = Some(Instant::now());
 
-        Poll::Ready(Some(Ok(event)))
+        Poll::Ready(Some(Ok(chunk)))
     }
 
     /// Handles errors during streaming
-    fn handle_streaming_error(&mut self, e: Error) -> Poll<Option<Result<Event, Error>>> {
+    fn handle_streaming_error(&mut self, e: Error) -> Poll<Option<Result<Value, Error>>> {
         self.status = StreamStatus::Failed(e.to_string());
         self.update_stack_tokens_on_error();
         Poll::Ready(Some(Err(e)))
     }
 
     /// Handles stream poll errors
-    fn handle_poll_error(&mut self, e: &reqwest::Error) -> Poll<Option<Result<Event, Error>>> {
+    fn handle_poll_error(&mut self, e: &reqwest::Error) -> Poll<Option<Result<Value, Error>>> {
         self.status = StreamStatus::Failed(e.to_string());
         self.update_stack_tokens_on_error();
         Poll::Ready(None)
     }
 
     /// Handles stream completion
-    fn handle_poll_complete(&mut self) -> Poll<Option<Result<Event, Error>>> {
+    fn handle_poll_complete(&mut self) -> Poll<Option<Result<Value, Error>>> {
         if !self.chunk_buffer.is_empty() {
             error!(
                 target = "atoma-service-streamer",
@@ -807,13 +989,16 @@ impl Streamer {
     }
 
     /// Updates stack tokens when an error occurs
-    fn update_stack_tokens_on_error(&self) {
+    fn update_stack_tokens_on_error(&mut self) {
         // NOTE: We need to update the stack number of tokens as the service failed to generate
         // a proper response. For this reason, we set the total number of tokens to 0.
         // This will ensure that the stack number of tokens is not updated, and the stack
         // will not be penalized for the failed request.
         //
         // NOTE: We also decrement the concurrent requests count, as we are done processing the request.
+        // The stream is marked as handled, so that dropping it does not update the stack again,
+        // and count the failed request as served.
+        self.is_final_chunk_handled = true;
         let num_concurrent_requests = handle_concurrent_requests_count_decrement(
             &self.concurrent_requests,
             self.stack_small_id,
@@ -824,8 +1009,10 @@ impl Streamer {
             self.stack_small_id,
             self.estimated_total_compute_units,
             0,
+            0,
             &self.endpoint,
             num_concurrent_requests,
+            Some(self.request_id.as_str()),
         ) {
             error!(
                 target = "atoma-service-streamer",
@@ -884,7 +1071,8 @@ impl Drop for Streamer {
             endpoint = self.endpoint,
             stack_small_id = self.stack_small_id,
             estimated_total_compute_units = self.estimated_total_compute_units,
-            payload_hash = hex::encode(self.payload_hash)
+            payload_hash = hex::encode(self.payload_hash),
+            request_id = self.request_id
         )
     )]
     fn drop(&mut self) {
@@ -917,8 +1105,10 @@ impl Drop for Streamer {
             self.stack_small_id,
             self.estimated_total_compute_units,
             self.num_input_tokens + self.streamer_computed_num_tokens,
+            1,
             &self.endpoint,
             num_concurrent_requests,
+            Some(self.request_id.as_str()),
         ) {
             error!(
                 target = "atoma-service-streamer",
@@ -1,26 +1,35 @@
 mod middleware {
+    use arc_swap::ArcSwap;
     use atoma_confidential::AtomaConfidentialCompute;
     use atoma_state::{
-        types::{AtomaAtomaStateManagerEvent, Stack, Task},
+        types::{AtomaAtomaStateManagerEvent, Stack, StackStatus, Task},
         AtomaStateManager,
     };
-    use atoma_sui::{client::Client, config::Builder, events::AtomaEvent};
+    use atoma_sui::{
+        client::Client,
+        config::Builder,
+        events::{AtomaEvent, AtomaEventMetadata},
+    };
     use atoma_utils::{
         constants::{self, SALT_SIZE},
-        encryption::encrypt_plaintext,
+        encryption::{
+            confidential_request_associated_data, encrypt_plaintext,
+            encrypt_plaintext_with_associated_data, AeadAlgorithm,
+            CONFIDENTIAL_PROTOCOL_VERSION_HPKE, CONFIDENTIAL_PROTOCOL_VERSION_V2,
+        },
         hashing::blake2b_hash,
+        hpke,
         test::POSTGRES_TEST_DB_URL,
     };
     use axum::{
         body::Body, extract::Request, http::StatusCode, response::Response, routing::post, Router,
     };
     use base64::{engine::general_purpose::STANDARD, prelude::BASE64_STANDARD, Engine};
-    use dashmap::{DashMap, DashSet};
     use flume::Sender;
     use serde_json::{json, Value};
     use serial_test::serial;
     use sqlx::PgPool;
-    use std::{collections::HashMap, str::FromStr, sync::Arc};
+    use std::{collections::HashMap, str::FromStr, sync::Arc, time::Duration};
     use sui_keys::keystore::{AccountKeystore, FileBasedKeystore};
     use sui_sdk::types::{
         base_types::{ObjectID, SuiAddress},
@@ -32,15 +41,31 @@ mod middleware {
     use tower::Service;
 
     use crate::{
+        config::{
+            ContextLengthConfig, ContextLengthPolicy, ImageGenerationsConfig,
+            ResumableStreamsConfig,
+        },
+        drain::Drain,
+        filters::FilterPipeline,
         handlers::{
-            chat_completions::CHAT_COMPLETIONS_PATH, embeddings::EMBEDDINGS_PATH,
-            image_generations::IMAGE_GENERATIONS_PATH,
+            chat_completions::CHAT_COMPLETIONS_PATH,
+            embeddings::EMBEDDINGS_PATH,
+            image_generations::{
+                IMAGE_EDITS_PATH, IMAGE_GENERATIONS_PATH, IMAGE_VARIATIONS_PATH,
+                MAX_IMAGE_INPUT_SIZE,
+            },
         },
         middleware::{
-            confidential_compute_middleware, signature_verification_middleware,
+            confidential_compute_middleware, drain_middleware, request_id_middleware,
+            resume_stream_middleware, signature_verification_middleware, utils,
             verify_stack_permissions, RequestMetadata, RequestType,
         },
+        models::ModelRegistry,
+        readiness::Readiness,
+        replication::{ConcurrentRequests, StopSignals},
+        resumable_streams::{ResumableStreams, StreamOwner, LAST_EVENT_ID},
         server::AppState,
+        trace_context::MAX_REQUEST_ID_LENGTH,
     };
 
     const TEST_MESSAGE: &str = "Test message";
@@ -96,7 +121,7 @@ mod middleware {
         Tokenizer::from_str(&tokenizer_json).unwrap()
     }
 
-    async fn truncate_tables() {
+    pub(super) async fn truncate_tables() {
         let db = PgPool::connect(POSTGRES_TEST_DB_URL)
             .await
             .expect("Failed to connect to database");
@@ -107,7 +132,10 @@ mod middleware {
                 stacks,
                 stack_settlement_tickets,
                 nodes,
-                stack_attestation_disputes
+                stack_attestation_disputes,
+                stack_usage,
+                batches,
+                batch_requests
             CASCADE",
         )
         .execute(&db)
@@ -124,7 +152,7 @@ mod middleware {
         JoinHandle<()>,
         Sender<AtomaAtomaStateManagerEvent>,
         tokio::sync::watch::Sender<bool>,
-        Sender<AtomaEvent>,
+        Sender<(AtomaEvent, AtomaEventMetadata)>,
         Sender<(
             atoma_p2p::types::AtomaP2pEvent,
             std::option::Option<tokio::sync::oneshot::Sender<bool>>,
@@ -150,6 +178,7 @@ mod middleware {
             model_name: Some("meta-llama/Llama-3.1-70B-Instruct".to_string()),
             security_level: 0,
             minimum_reputation_score: Some(100),
+            removed_at_epoch: None,
             is_deprecated: false,
             valid_until_epoch: Some(1),
             deprecated_at_epoch: Some(1),
@@ -175,6 +204,11 @@ mod middleware {
             num_total_messages: 1,
             is_claimed: false,
             is_locked_for_claim: locked,
+            status: if locked {
+                StackStatus::LockedForClaim
+            } else {
+                StackStatus::Active
+            },
         };
         state_manager.state.insert_new_stack(stack).await.unwrap();
         let (shutdown_sender, shutdown_signal) = tokio::sync::watch::channel(false);
@@ -242,7 +276,7 @@ mod middleware {
     }
 
     #[allow(clippy::too_many_lines)]
-    async fn setup_app_state(
+    pub(super) async fn setup_app_state(
         available_compute_units: Option<i64>,
         locked: bool,
     ) -> (
@@ -251,7 +285,7 @@ mod middleware {
         Signature,
         tokio::sync::watch::Sender<bool>,
         JoinHandle<()>,
-        Sender<AtomaEvent>,
+        Sender<(AtomaEvent, AtomaEventMetadata)>,
         Sender<(
             atoma_p2p::types::AtomaP2pEvent,
             std::option::Option<tokio::sync::oneshot::Sender<bool>>,
@@ -322,25 +356,39 @@ mod middleware {
             .expect("Failed to remove keystore");
         (
             AppState {
-                concurrent_requests_per_stack: Arc::new(DashMap::new()),
-                client_dropped_streamer_connections: Arc::new(DashSet::new()),
-                models: Arc::new(
-                    models
-                        .into_iter()
-                        .map(std::string::ToString::to_string)
-                        .collect(),
-                ),
-                tokenizers: Arc::new(vec![Arc::new(tokenizer.clone()), Arc::new(tokenizer)]),
+                concurrent_requests_per_stack: Arc::new(ConcurrentRequests::default()),
+                client_dropped_streamer_connections: Arc::new(StopSignals::default()),
+                model_registry: Arc::new(ArcSwap::from_pointee(
+                    ModelRegistry::new(
+                        models
+                            .into_iter()
+                            .map(std::string::ToString::to_string)
+                            .collect(),
+                        vec![
+                            Arc::new(tokenizer.clone()),
+                            Arc::new(tokenizer.clone()),
+                            Arc::new(tokenizer),
+                        ],
+                        HashMap::new(),
+                    )
+                    .unwrap(),
+                )),
                 state_manager_sender,
                 decryption_sender,
                 encryption_sender,
                 compute_shared_secret_sender,
-                chat_completions_service_urls: HashMap::new(),
                 embeddings_service_url: String::new(),
                 image_generations_service_url: String::new(),
                 keystore: Arc::new(keystore),
                 address_index: 0,
                 stack_retrieve_sender,
+                filters: Arc::new(FilterPipeline::default()),
+                resumable_streams: Arc::new(ResumableStreams::new(
+                    ResumableStreamsConfig::default(),
+                )),
+                image_generations: Arc::new(ImageGenerationsConfig::default()),
+                drain: Drain::default(),
+                readiness: Arc::new(Readiness::default()),
             },
             public_key,
             signature,
@@ -375,6 +423,28 @@ mod middleware {
         assert_eq!(request_metadata.payload_hash, [3u8; 32]);
     }
 
+    #[test]
+    fn test_max_body_size() {
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert(
+            "Content-Type",
+            "multipart/form-data; boundary=abc".parse().unwrap(),
+        );
+        assert_eq!(
+            utils::max_body_size(IMAGE_EDITS_PATH, &headers),
+            MAX_IMAGE_INPUT_SIZE
+        );
+        assert_eq!(
+            utils::max_body_size(IMAGE_VARIATIONS_PATH, &headers),
+            MAX_IMAGE_INPUT_SIZE
+        );
+        // Clients cannot raise the body limit of other endpoints with a multipart content type
+        assert!(utils::max_body_size(CHAT_COMPLETIONS_PATH, &headers) < MAX_IMAGE_INPUT_SIZE);
+        assert!(utils::max_body_size(EMBEDDINGS_PATH, &headers) < MAX_IMAGE_INPUT_SIZE);
+        headers.insert("Content-Type", "application/json".parse().unwrap());
+        assert!(utils::max_body_size(IMAGE_EDITS_PATH, &headers) < MAX_IMAGE_INPUT_SIZE);
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_verify_stack_permissions() {
@@ -870,6 +940,244 @@ mod middleware {
         truncate_tables().await;
     }
 
+    /// Sets a maximum context length of 64 tokens for the chat completions model
+    pub(super) fn set_context_length(app_state: &AppState, policy: ContextLengthPolicy) {
+        app_state.model_registry.rcu(|registry| {
+            (**registry)
+                .clone()
+                .with_context_length_config(ContextLengthConfig {
+                    policy,
+                    max_context_lengths: HashMap::from([(
+                        "meta-llama/Llama-3.1-70B-Instruct".to_string(),
+                        64,
+                    )]),
+                    policies: HashMap::new(),
+                })
+        });
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_verify_stack_permissions_context_length_exceeded() {
+        let (
+            app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _p2p_event_sender,
+            _,
+        ) = setup_app_state(None, false).await;
+        set_context_length(&app_state, ContextLengthPolicy::Reject);
+
+        let body = json!({
+            "model": "meta-llama/Llama-3.1-70B-Instruct",
+            "messages": [{
+                "role": "user",
+                "content": "Hello"
+            }],
+            "max_tokens": 100,
+        });
+
+        let req = Request::builder()
+            .method("POST")
+            .uri(CHAT_COMPLETIONS_PATH)
+            .header(constants::SIGNATURE, signature.encode_base64())
+            .header(constants::STACK_SMALL_ID, "1")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        let mut app = Router::new()
+            .route(CHAT_COMPLETIONS_PATH, post(test_handler))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state.clone(),
+                verify_stack_permissions,
+            ));
+
+        let response = app.call(req).await.expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["param"], "max_tokens");
+        // No compute units are locked for rejected requests
+        assert_eq!(app_state.concurrent_requests_per_stack.get(1), 0);
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_verify_stack_permissions_context_length_clamped() {
+        let (
+            app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _p2p_event_sender,
+            _,
+        ) = setup_app_state(None, false).await;
+        set_context_length(&app_state, ContextLengthPolicy::Clamp);
+
+        let body = json!({
+            "model": "meta-llama/Llama-3.1-70B-Instruct",
+            "messages": [{
+                "role": "user",
+                "content": "Hello"
+            }],
+            "max_tokens": 100,
+        });
+
+        let req = Request::builder()
+            .method("POST")
+            .uri(CHAT_COMPLETIONS_PATH)
+            .header(constants::SIGNATURE, signature.encode_base64())
+            .header(constants::STACK_SMALL_ID, "1")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        async fn verify_clamped(req: Request<Body>) -> Result<Response<Body>, StatusCode> {
+            let metadata = req
+                .extensions()
+                .get::<RequestMetadata>()
+                .cloned()
+                .expect("Metadata should be set");
+            assert_eq!(metadata.estimated_total_compute_units, 64);
+            let body = axum::body::to_bytes(req.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(
+                body["max_tokens"].as_i64().unwrap(),
+                64 - metadata.num_input_tokens
+            );
+            Ok(Response::new(Body::empty()))
+        }
+
+        let mut app = Router::new()
+            .route(CHAT_COMPLETIONS_PATH, post(verify_clamped))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state,
+                verify_stack_permissions,
+            ));
+
+        let response = app.call(req).await.expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::OK);
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_resume_stream_middleware() {
+        let (
+            app_state,
+            public_key,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _p2p_event_sender,
+            _,
+        ) = setup_app_state(None, false).await;
+
+        let payload_hash = [1; 32];
+        let owner = StreamOwner {
+            stack_small_id: 1,
+            sui_address: SuiAddress::from(&public_key),
+            payload_hash,
+        };
+        let chunks: Vec<Result<(u64, Value), axum::Error>> = vec![
+            Ok((0, json!({"choices": [{"delta": {"content": "Hello"}}]}))),
+            Ok((1, json!({"choices": [{"delta": {"content": " world"}}]}))),
+        ];
+        let mut first_connection = Box::pin(
+            app_state
+                .resumable_streams
+                .start(
+                    "request-id".to_string(),
+                    owner,
+                    futures::stream::iter(chunks),
+                    app_state.drain.track(),
+                )
+                .unwrap(),
+        );
+        // NOTE: The client connection drops after the first event
+        futures::StreamExt::next(&mut first_connection)
+            .await
+            .unwrap()
+            .unwrap();
+        drop(first_connection);
+
+        let resume_request = |request_id: &str, stack_small_id: &str| {
+            let mut req = Request::builder()
+                .method("POST")
+                .uri(CHAT_COMPLETIONS_PATH)
+                .header(constants::SIGNATURE, signature.encode_base64())
+                .header(constants::STACK_SMALL_ID, stack_small_id)
+                .header(constants::REQUEST_ID, request_id)
+                .header(LAST_EVENT_ID, "0")
+                .body(Body::empty())
+                .unwrap();
+            req.extensions_mut()
+                .insert(RequestMetadata::default().with_payload_hash(payload_hash));
+            req
+        };
+        let mut app = Router::new()
+            .route(CHAT_COMPLETIONS_PATH, post(test_handler))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state,
+                resume_stream_middleware,
+            ));
+
+        let response = app
+            .call(resume_request("other-request-id", "1"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = app.call(resume_request("request-id", "2")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // New requests cannot reuse the request ID of the stream
+        let new_request = |request_id: &str| {
+            let mut req = resume_request(request_id, "1");
+            req.headers_mut().remove(LAST_EVENT_ID);
+            req
+        };
+        let response = app.call(new_request("request-id")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "conflict");
+        let response = app.call(new_request("other-request-id")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app.call(resume_request("request-id", "1")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        // Only the events following the last event received by the client are sent
+        assert!(!body.contains("Hello"));
+        assert!(body.contains("id: 1\n"));
+        assert!(body.contains(" world"));
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_signature_verification_success() {
@@ -1114,6 +1422,8 @@ mod middleware {
             request_type: RequestType::ChatCompletions,
             endpoint_path: "/".to_string(),
             client_encryption_metadata: None,
+            structured_output_validator: None,
+            request_id: None,
         };
 
         let mut req = Request::builder()
@@ -1161,6 +1471,107 @@ mod middleware {
         assert_eq!(response.status(), StatusCode::OK);
     }
 
+    #[tokio::test]
+    async fn test_request_id_middleware() {
+        async fn request_id_handler(req: Request<Body>) -> String {
+            let metadata = req
+                .extensions()
+                .get::<RequestMetadata>()
+                .expect("Metadata should be set");
+            let request_id = metadata
+                .request_id
+                .clone()
+                .expect("Request ID should be set");
+            assert_eq!(req.headers()[constants::REQUEST_ID], request_id.as_str());
+            request_id
+        }
+
+        let mut app = Router::new()
+            .route("/", post(request_id_handler))
+            .layer(axum::middleware::from_fn(request_id_middleware));
+
+        // The request ID of the client is kept
+        let req = Request::post("/")
+            .header(constants::REQUEST_ID, "client-request-id")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[constants::REQUEST_ID],
+            "client-request-id"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), b"client-request-id");
+
+        // A request ID is generated if the client did not provide one
+        let req = Request::post("/").body(Body::empty()).unwrap();
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let request_id = response.headers()[constants::REQUEST_ID]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(request_id.len(), 32);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), request_id.as_bytes());
+
+        // Oversized request IDs are rejected
+        let req = Request::post("/")
+            .header(constants::REQUEST_ID, "a".repeat(MAX_REQUEST_ID_LENGTH + 1))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_drain_middleware() {
+        let (
+            app_state,
+            _,
+            _,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _p2p_event_sender,
+            _,
+        ) = setup_app_state(None, false).await;
+        let drain = app_state.drain.clone();
+
+        let mut app = Router::new()
+            .route(EMBEDDINGS_PATH, post(test_handler))
+            .layer(axum::middleware::from_fn_with_state(
+                app_state,
+                drain_middleware,
+            ));
+
+        // Requests are in flight until their response body is dropped
+        let req = Request::post(EMBEDDINGS_PATH).body(Body::empty()).unwrap();
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(drain.in_flight_requests(), 1);
+        drain.start();
+        assert!(!drain.wait_idle(Duration::from_millis(10)).await);
+        drop(response);
+        assert!(drain.wait_idle(Duration::from_millis(10)).await);
+
+        // New requests are rejected while draining
+        let req = Request::post(EMBEDDINGS_PATH).body(Body::empty()).unwrap();
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(drain.in_flight_requests(), 0);
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_verify_stack_permissions_embeddings() {
@@ -1451,11 +1862,11 @@ mod middleware {
 
     #[tokio::test]
     #[serial]
-    async fn test_confidential_compute_middleware_missing_headers() {
+    async fn test_confidential_compute_encryption_decryption_with_associated_data() {
         let (
             app_state,
             _,
-            _,
+            signature,
             shutdown_sender,
             state_manager_handle,
             _,
@@ -1469,30 +1880,237 @@ mod middleware {
 
         let blake2b_hash: [u8; 32] = blake2b_hash(TEST_MESSAGE.as_bytes()).into();
         let shared_secret = client_dh_private_key.diffie_hellman(&server_dh_public_key);
-        let (encrypted_data, nonce) =
-            encrypt_plaintext(TEST_MESSAGE.as_bytes(), &shared_secret, &salt, None)
-                .expect("Failed to encrypt plaintext data");
+        let associated_data = confidential_request_associated_data(
+            CONFIDENTIAL_PROTOCOL_VERSION_V2,
+            1,
+            "test_model",
+            Some(false),
+            Some(100),
+        );
+        let (encrypted_data, nonce) = encrypt_plaintext_with_associated_data(
+            TEST_MESSAGE.as_bytes(),
+            &shared_secret,
+            &salt,
+            None,
+            &associated_data,
+        )
+        .expect("Failed to encrypt plaintext data");
         let encrypted_body_json = json!({
-                "ciphertext": STANDARD.encode(encrypted_data),
-                "salt": STANDARD.encode(salt),
-                "nonce": STANDARD.encode(nonce.as_slice()),
-                "node_dh_public_key": STANDARD.encode(server_dh_public_key.as_ref()),
-                "client_dh_public_key": STANDARD.encode(client_dh_public_key.as_ref()),
-                "plaintext_body_hash": STANDARD.encode(blake2b_hash.as_slice()),
-                "model_name": "test_model",
-                "num_compute_units": 100,
-                "stream": false,
-            "stack_small_id": 1
+            "ciphertext": STANDARD.encode(encrypted_data),
+            "salt": STANDARD.encode(salt),
+            "nonce": STANDARD.encode(nonce.as_slice()),
+            "node_dh_public_key": STANDARD.encode(server_dh_public_key.as_ref()),
+            "client_dh_public_key": STANDARD.encode(client_dh_public_key.as_ref()),
+            "plaintext_body_hash": STANDARD.encode(blake2b_hash.as_slice()),
+            "model_name": "test_model",
+            "num_compute_units": 100,
+            "stream": false,
+            "stack_small_id": 1,
+            "version": CONFIDENTIAL_PROTOCOL_VERSION_V2
         });
 
-        // Test missing signature
+        async fn verify_decrypted_body(req: Request<Body>) -> Result<Response<Body>, StatusCode> {
+            let body = axum::body::to_bytes(req.into_body(), 1024)
+                .await
+                .expect("Failed to read body");
+
+            assert_eq!(body, TEST_MESSAGE.as_bytes());
+            Ok(Response::new(Body::empty()))
+        }
+
+        let mut app = Router::new().route("/", post(verify_decrypted_body)).layer(
+            axum::middleware::from_fn_with_state(app_state, confidential_compute_middleware),
+        );
+
         let req = Request::builder()
             .method("POST")
             .uri("/")
+            .header(constants::SIGNATURE, signature.encode_base64())
             .body(Body::from(encrypted_body_json.to_string()))
-            .unwrap();
+            .expect("Failed to build request");
+        let response = app.call(req).await.expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::OK);
 
-        let mut app = Router::new().route("/", post(test_handler)).layer(
+        // Splicing the ciphertext onto a different stack, model or protocol version must fail
+        for (key, value) in [
+            ("stack_small_id", json!(2)),
+            ("model_name", json!("other_model")),
+            ("version", json!(1)),
+        ] {
+            let mut tampered_body_json = encrypted_body_json.clone();
+            tampered_body_json[key] = value;
+            let req = Request::builder()
+                .method("POST")
+                .uri("/")
+                .header(constants::SIGNATURE, signature.encode_base64())
+                .body(Body::from(tampered_body_json.to_string()))
+                .expect("Failed to build request");
+            let response = app.call(req).await.expect("Failed to get response");
+            assert_ne!(response.status(), StatusCode::OK);
+        }
+
+        // Unsupported protocol versions are rejected
+        let mut unsupported_body_json = encrypted_body_json.clone();
+        unsupported_body_json["version"] = json!(u32::MAX);
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header(constants::SIGNATURE, signature.encode_base64())
+            .body(Body::from(unsupported_body_json.to_string()))
+            .expect("Failed to build request");
+        let response = app.call(req).await.expect("Failed to get response");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_confidential_compute_hpke_decryption() {
+        let (
+            app_state,
+            _,
+            signature,
+            shutdown_sender,
+            state_manager_handle,
+            _,
+            _p2p_event_sender,
+            server_dh_public_key,
+        ) = setup_app_state(None, false).await;
+
+        async fn verify_decrypted_body(req: Request<Body>) -> Result<Response<Body>, StatusCode> {
+            let body = axum::body::to_bytes(req.into_body(), 1024)
+                .await
+                .expect("Failed to read body");
+
+            assert_eq!(body, TEST_MESSAGE.as_bytes());
+            Ok(Response::new(Body::empty()))
+        }
+
+        let mut app = Router::new().route("/", post(verify_decrypted_body)).layer(
+            axum::middleware::from_fn_with_state(app_state, confidential_compute_middleware),
+        );
+
+        let blake2b_hash: [u8; 32] = blake2b_hash(TEST_MESSAGE.as_bytes()).into();
+        let associated_data = confidential_request_associated_data(
+            CONFIDENTIAL_PROTOCOL_VERSION_HPKE,
+            1,
+            "test_model",
+            Some(false),
+            Some(100),
+        );
+        for aead in [AeadAlgorithm::Aes256Gcm, AeadAlgorithm::ChaCha20Poly1305] {
+            let (encapsulated_key, ciphertext) = hpke::seal_base(
+                &server_dh_public_key,
+                hpke::REQUEST_INFO,
+                &associated_data,
+                TEST_MESSAGE.as_bytes(),
+                aead,
+            )
+            .expect("Failed to seal plaintext data");
+            // NOTE: HPKE requests do not carry a nonce
+            let encrypted_body_json = json!({
+                "ciphertext": STANDARD.encode(ciphertext),
+                "salt": STANDARD.encode(rand::random::<[u8; SALT_SIZE]>()),
+                "node_dh_public_key": STANDARD.encode(server_dh_public_key.as_ref()),
+                "client_dh_public_key": STANDARD.encode(encapsulated_key),
+                "plaintext_body_hash": STANDARD.encode(blake2b_hash.as_slice()),
+                "model_name": "test_model",
+                "num_compute_units": 100,
+                "stream": false,
+                "stack_small_id": 1,
+                "version": CONFIDENTIAL_PROTOCOL_VERSION_HPKE,
+                "suite": aead.hpke_id()
+            });
+
+            let req = Request::builder()
+                .method("POST")
+                .uri("/")
+                .header(constants::SIGNATURE, signature.encode_base64())
+                .body(Body::from(encrypted_body_json.to_string()))
+                .expect("Failed to build request");
+            let response = app.call(req).await.expect("Failed to get response");
+            assert_eq!(response.status(), StatusCode::OK);
+
+            // A tampered envelope, a mismatching suite, or an unsupported or missing suite
+            // are rejected
+            for (key, value) in [
+                ("stack_small_id", json!(2)),
+                (
+                    "suite",
+                    json!(if aead == AeadAlgorithm::Aes256Gcm {
+                        3
+                    } else {
+                        2
+                    }),
+                ),
+                ("suite", json!(1)),
+                ("suite", Value::Null),
+            ] {
+                let mut tampered_body_json = encrypted_body_json.clone();
+                tampered_body_json[key] = value;
+                let req = Request::builder()
+                    .method("POST")
+                    .uri("/")
+                    .header(constants::SIGNATURE, signature.encode_base64())
+                    .body(Body::from(tampered_body_json.to_string()))
+                    .expect("Failed to build request");
+                let response = app.call(req).await.expect("Failed to get response");
+                assert_ne!(response.status(), StatusCode::OK);
+            }
+        }
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_confidential_compute_middleware_missing_headers() {
+        let (
+            app_state,
+            _,
+            _,
+            shutdown_sender,
+            state_manager_handle,
+            _,
+            _p2p_event_sender,
+            server_dh_public_key,
+        ) = setup_app_state(None, false).await;
+
+        let salt = rand::random::<[u8; SALT_SIZE]>();
+        let client_dh_private_key = x25519_dalek::StaticSecret::random_from_rng(rand::thread_rng());
+        let client_dh_public_key = x25519_dalek::PublicKey::from(&client_dh_private_key);
+
+        let blake2b_hash: [u8; 32] = blake2b_hash(TEST_MESSAGE.as_bytes()).into();
+        let shared_secret = client_dh_private_key.diffie_hellman(&server_dh_public_key);
+        let (encrypted_data, nonce) =
+            encrypt_plaintext(TEST_MESSAGE.as_bytes(), &shared_secret, &salt, None)
+                .expect("Failed to encrypt plaintext data");
+        let encrypted_body_json = json!({
+                "ciphertext": STANDARD.encode(encrypted_data),
+                "salt": STANDARD.encode(salt),
+                "nonce": STANDARD.encode(nonce.as_slice()),
+                "node_dh_public_key": STANDARD.encode(server_dh_public_key.as_ref()),
+                "client_dh_public_key": STANDARD.encode(client_dh_public_key.as_ref()),
+                "plaintext_body_hash": STANDARD.encode(blake2b_hash.as_slice()),
+                "model_name": "test_model",
+                "num_compute_units": 100,
+                "stream": false,
+            "stack_small_id": 1
+        });
+
+        // Test missing signature
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .body(Body::from(encrypted_body_json.to_string()))
+            .unwrap();
+
+        let mut app = Router::new().route("/", post(test_handler)).layer(
             axum::middleware::from_fn_with_state(
                 app_state.clone(),
                 confidential_compute_middleware,
@@ -1740,6 +2358,8 @@ mod middleware {
             request_type: RequestType::ChatCompletions,
             endpoint_path: "/".to_string(),
             client_encryption_metadata: None,
+            structured_output_validator: None,
+            request_id: None,
         };
 
         let mut req = Request::builder()
@@ -1809,3 +2429,684 @@ mod middleware {
         truncate_tables().await;
     }
 }
+
+mod inference {
+    use atoma_mock_inference::{config::MockInferenceConfig, MockInferenceServer};
+    use atoma_state::types::AtomaAtomaStateManagerEvent;
+    use atoma_utils::{constants, hashing::blake2b_hash, test::POSTGRES_TEST_DB_URL};
+    use axum::{body::Body, extract::Request, http::StatusCode, response::Response};
+    use serde_json::{json, Value};
+    use serial_test::serial;
+    use sqlx::PgPool;
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+    use sui_keys::keystore::AccountKeystore;
+    use sui_sdk::types::crypto::EncodeDecodeBase64;
+    use tokio::sync::watch;
+    use tower::Service;
+
+    use super::middleware::{set_context_length, setup_app_state, truncate_tables};
+    use crate::{
+        batch_processor::run_batch_processor,
+        config::{BatchProcessorConfig, ContextLengthPolicy, FiltersConfig, ReadinessConfig},
+        filters::FilterPipeline,
+        handlers::{
+            batches::{query_state_manager, BATCHES_PATH},
+            chat_completions::CHAT_COMPLETIONS_PATH,
+            embeddings::EMBEDDINGS_PATH,
+            image_generations::{IMAGE_EDITS_PATH, IMAGE_GENERATIONS_PATH},
+            structured_output::STRUCTURED_OUTPUT_VALIDATION_KEY,
+        },
+        models::ModelRegistry,
+        readiness::Readiness,
+        server::{create_router, AppState, READY_PATH},
+    };
+
+    const CHAT_MODEL: &str = "meta-llama/Llama-3.1-70B-Instruct";
+    const EMBEDDINGS_MODEL: &str = "intfloat/multilingual-e5-large-instruct";
+    const IMAGE_GENERATIONS_MODEL: &str = "black-forest-labs/FLUX.1-schnell";
+
+    /// Spawns a mock inference backend and points all the inference services of the node to it.
+    async fn spawn_mock_backend(
+        app_state: &mut AppState,
+        config: MockInferenceConfig,
+    ) -> MockInferenceServer {
+        let server = MockInferenceServer::spawn(config)
+            .await
+            .expect("Failed to spawn mock inference backend");
+        let model_registry = app_state.model_registry.load_full();
+        let models = model_registry
+            .models()
+            .into_iter()
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        let tokenizers = models
+            .iter()
+            .map(|model| model_registry.tokenizer(model).unwrap().clone())
+            .collect();
+        let chat_completions_service_urls = models
+            .iter()
+            .map(|model| (model.clone(), vec![(server.url(), "vllm".to_string())]))
+            .collect::<HashMap<_, _>>();
+        app_state.model_registry.store(Arc::new(
+            ModelRegistry::new(models, tokenizers, chat_completions_service_urls).unwrap(),
+        ));
+        app_state.embeddings_service_url = server.url();
+        app_state.image_generations_service_url = server.url();
+        server
+    }
+
+    /// Builds a request for stack 1, signed by the stack owner.
+    fn signed_request(app_state: &AppState, path: &str, body: &Value) -> Request<Body> {
+        signed_raw_request(app_state, path, "application/json", body.to_string())
+    }
+
+    /// Builds a request for stack 1 with a raw body, signed by the stack owner.
+    fn signed_raw_request(
+        app_state: &AppState,
+        path: &str,
+        content_type: &str,
+        body: String,
+    ) -> Request<Body> {
+        let hash = blake2b_hash(body.as_bytes());
+        let signature = app_state
+            .keystore
+            .sign_hashed(&app_state.keystore.addresses()[0], hash.as_slice())
+            .expect("Failed to sign message");
+        Request::builder()
+            .method("POST")
+            .uri(path)
+            .header(constants::SIGNATURE, signature.encode_base64())
+            .header(constants::STACK_SMALL_ID, "1")
+            .header(constants::REQUEST_ID, "mock-inference-request")
+            .header("Content-Type", content_type)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    async fn read_body(response: Response) -> String {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    /// Returns the usage recorded for stack 1, as `(num_requests, compute_units)` per day.
+    async fn stack_usage(app_state: &AppState) -> Vec<(i64, i64)> {
+        // NOTE: The state manager handles its events in order, so the usage updates sent before
+        // are recorded once this query returns
+        query_state_manager(app_state, "test", |result_sender| {
+            AtomaAtomaStateManagerEvent::GetStack {
+                stack_small_id: 1,
+                result_sender,
+            }
+        })
+        .await
+        .unwrap();
+        let db = PgPool::connect(POSTGRES_TEST_DB_URL).await.unwrap();
+        sqlx::query_as(
+            "SELECT num_requests, compute_units FROM stack_usage WHERE stack_small_id = 1",
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap()
+    }
+
+    fn mock_config() -> MockInferenceConfig {
+        MockInferenceConfig {
+            completion_tokens: 4,
+            embedding_dimensions: 8,
+            ..Default::default()
+        }
+    }
+
+    fn chat_completions_body(stream: bool) -> Value {
+        json!({
+            "model": CHAT_MODEL,
+            "messages": [{
+                "role": "user",
+                "content": "What is the capital of Mars?"
+            }],
+            "max_tokens": 8,
+            "stream": stream,
+        })
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_ready_with_mock_backend() {
+        let (
+            mut app_state,
+            _,
+            _,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _p2p_event_sender,
+            _,
+        ) = setup_app_state(None, false).await;
+        let _server = spawn_mock_backend(&mut app_state, mock_config()).await;
+        let (num_peers_sender, num_peers) = watch::channel(0);
+        app_state.readiness = Arc::new(
+            Readiness::new(ReadinessConfig {
+                min_peers: 1,
+                ..ReadinessConfig::default()
+            })
+            .with_p2p_num_peers(num_peers),
+        );
+        let mut app = create_router(app_state.clone());
+
+        let req = Request::get(READY_PATH).body(Body::empty()).unwrap();
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let response: Value = serde_json::from_str(&read_body(response).await).unwrap();
+        assert_eq!(response, json!({ "status": "not_ready" }));
+
+        num_peers_sender.send_replace(1);
+        let req = Request::get(format!("{READY_PATH}?verbose=true"))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response: Value = serde_json::from_str(&read_body(response).await).unwrap();
+        assert_eq!(response["status"], "ready");
+        assert_eq!(response["checks"]["p2p"]["details"]["num_peers"], 1);
+        assert_eq!(response["checks"]["backends"]["ready"], true);
+        assert_eq!(
+            response["checks"]["backends"]["details"]["models"][CHAT_MODEL]["ready"],
+            true
+        );
+
+        app_state.drain.start();
+        let req = Request::get(READY_PATH).body(Body::empty()).unwrap();
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let response: Value = serde_json::from_str(&read_body(response).await).unwrap();
+        assert_eq!(response, json!({ "status": "draining" }));
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_chat_completions_with_mock_backend() {
+        let (
+            mut app_state,
+            _,
+            _,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _p2p_event_sender,
+            _,
+        ) = setup_app_state(None, false).await;
+        let _server = spawn_mock_backend(&mut app_state, mock_config()).await;
+        let mut app = create_router(app_state.clone());
+
+        let req = signed_request(
+            &app_state,
+            CHAT_COMPLETIONS_PATH,
+            &chat_completions_body(false),
+        );
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[constants::REQUEST_ID],
+            "mock-inference-request"
+        );
+        let response: Value = serde_json::from_str(&read_body(response).await).unwrap();
+        assert_eq!(
+            response["choices"][0]["message"]["content"],
+            "Atoma is a decentralized"
+        );
+        assert_eq!(response["usage"]["completion_tokens"], 4);
+        let usage = stack_usage(&app_state).await;
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0].0, 1);
+        assert!(usage[0].1 > 0);
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_streamed_chat_completions_with_mock_backend() {
+        let (
+            mut app_state,
+            _,
+            _,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _p2p_event_sender,
+            _,
+        ) = setup_app_state(None, false).await;
+        let _server = spawn_mock_backend(&mut app_state, mock_config()).await;
+        let mut app = create_router(app_state.clone());
+
+        let req = signed_request(
+            &app_state,
+            CHAT_COMPLETIONS_PATH,
+            &chat_completions_body(true),
+        );
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = read_body(response).await;
+        let chunks = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .filter_map(|data| serde_json::from_str::<Value>(data).ok())
+            .collect::<Vec<_>>();
+        let content = chunks
+            .iter()
+            .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str())
+            .collect::<String>();
+        assert_eq!(content, "Atoma is a decentralized");
+        let usage = chunks
+            .iter()
+            .find_map(|chunk| chunk.get("usage"))
+            .expect("The usage chunk should be streamed");
+        assert_eq!(usage["completion_tokens"], 4);
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_streamed_chat_completions_structured_output_validation() {
+        let (
+            mut app_state,
+            _,
+            _,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _p2p_event_sender,
+            _,
+        ) = setup_app_state(None, false).await;
+        let _server = spawn_mock_backend(&mut app_state, mock_config()).await;
+        let mut app = create_router(app_state.clone());
+
+        let mut body = chat_completions_body(true);
+        body["response_format"] = json!({
+            "type": "json_schema",
+            "json_schema": {
+                "name": "capital",
+                "strict": true,
+                "schema": {
+                    "type": "object",
+                    "properties": {"capital": {"type": "string"}},
+                    "required": ["capital"],
+                },
+            },
+        });
+        let req = signed_request(&app_state, CHAT_COMPLETIONS_PATH, &body);
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = read_body(response).await;
+        let chunks = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .filter_map(|data| serde_json::from_str::<Value>(data).ok())
+            .collect::<Vec<_>>();
+        let (final_chunk, content_chunks) =
+            chunks.split_last().expect("The stream should not be empty");
+        // NOTE: The content is only validated once accumulated, at the end of the stream
+        assert!(content_chunks
+            .iter()
+            .all(|chunk| chunk.get(STRUCTURED_OUTPUT_VALIDATION_KEY).is_none()));
+        assert!(final_chunk.get("usage").is_some());
+        // The mock backend does not constrain its output, which is not valid JSON
+        let validation = &final_chunk[STRUCTURED_OUTPUT_VALIDATION_KEY];
+        assert_eq!(validation["valid"], false);
+        let errors = validation["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0]["choice_index"], 0);
+        assert!(errors[0]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Content is not valid JSON"));
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_chat_completions_with_failing_mock_backend() {
+        let (
+            mut app_state,
+            _,
+            _,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _p2p_event_sender,
+            _,
+        ) = setup_app_state(None, false).await;
+        let _server = spawn_mock_backend(
+            &mut app_state,
+            MockInferenceConfig {
+                failure_rate: 1.0,
+                failure_status_code: 400,
+                ..mock_config()
+            },
+        )
+        .await;
+        let mut app = create_router(app_state.clone());
+
+        for stream in [false, true] {
+            let req = signed_request(
+                &app_state,
+                CHAT_COMPLETIONS_PATH,
+                &chat_completions_body(stream),
+            );
+            let response = app.call(req).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+        // The compute units locked for the failed requests are released, and no usage recorded
+        assert!(stack_usage(&app_state).await.is_empty());
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_embeddings_and_image_generations_with_mock_backend() {
+        let (
+            mut app_state,
+            _,
+            _,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _p2p_event_sender,
+            _,
+        ) = setup_app_state(None, false).await;
+        let _server = spawn_mock_backend(&mut app_state, mock_config()).await;
+        let mut app = create_router(app_state.clone());
+
+        let body = json!({
+            "model": EMBEDDINGS_MODEL,
+            "input": "The capital of Mars",
+        });
+        let req = signed_request(&app_state, EMBEDDINGS_PATH, &body);
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response: Value = serde_json::from_str(&read_body(response).await).unwrap();
+        assert_eq!(
+            response["data"][0]["embedding"].as_array().unwrap().len(),
+            8
+        );
+
+        let body = json!({
+            "model": IMAGE_GENERATIONS_MODEL,
+            "prompt": "A beautiful sunset over mountains",
+            "size": "4x4",
+            "n": 1,
+        });
+        let req = signed_request(&app_state, IMAGE_GENERATIONS_PATH, &body);
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response: Value = serde_json::from_str(&read_body(response).await).unwrap();
+        assert!(response["data"][0]["b64_json"].is_string());
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_image_edits_with_mock_backend() {
+        let (
+            mut app_state,
+            _,
+            _,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _p2p_event_sender,
+            _,
+        ) = setup_app_state(None, false).await;
+        let _server = spawn_mock_backend(&mut app_state, mock_config()).await;
+        let mut app = create_router(app_state.clone());
+
+        const BOUNDARY: &str = "atoma-test-boundary";
+        let text_field = |name: &str, value: &str| {
+            format!(
+                "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+            )
+        };
+        let body = [
+            text_field("model", IMAGE_GENERATIONS_MODEL),
+            text_field("prompt", "Add a moon to the sky"),
+            text_field("n", "2"),
+            text_field("size", "4x4"),
+            text_field("response_format", "b64_json"),
+            format!(
+                "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"image\"; filename=\"image.png\"\r\nContent-Type: image/png\r\n\r\nPNG\r\n--{BOUNDARY}--\r\n"
+            ),
+        ]
+        .concat();
+        let req = signed_raw_request(
+            &app_state,
+            IMAGE_EDITS_PATH,
+            &format!("multipart/form-data; boundary={BOUNDARY}"),
+            body,
+        );
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response: Value = serde_json::from_str(&read_body(response).await).unwrap();
+        assert_eq!(response["data"].as_array().unwrap().len(), 2);
+        assert!(response["data"][0]["b64_json"].is_string());
+
+        // Sizes above the maximum number of pixels are rejected before reaching the backend
+        let body = json!({
+            "model": IMAGE_GENERATIONS_MODEL,
+            "prompt": "A beautiful sunset over mountains",
+            "size": "100000x100000",
+            "n": 1,
+        });
+        let req = signed_request(&app_state, IMAGE_GENERATIONS_PATH, &body);
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_batch_lines_go_through_request_filters() {
+        let (
+            mut app_state,
+            _,
+            _,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _p2p_event_sender,
+            _,
+        ) = setup_app_state(None, false).await;
+        let _server = spawn_mock_backend(&mut app_state, mock_config()).await;
+        let address = app_state.keystore.addresses()[0];
+        app_state.filters = Arc::new(
+            FilterPipeline::from_config(&FiltersConfig {
+                denied_models: HashMap::from([(address.to_string(), vec![CHAT_MODEL.to_string()])]),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let mut app = create_router(app_state.clone());
+
+        // The request is blocked interactively...
+        let req = signed_request(
+            &app_state,
+            CHAT_COMPLETIONS_PATH,
+            &chat_completions_body(false),
+        );
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // ...and so is the same request sent as a batch line
+        let batch_input = json!({
+            "custom_id": "request-1",
+            "method": "POST",
+            "url": CHAT_COMPLETIONS_PATH,
+            "body": chat_completions_body(false),
+        })
+        .to_string();
+        let req = signed_raw_request(&app_state, BATCHES_PATH, "application/jsonl", batch_input);
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response: Value = serde_json::from_str(&read_body(response).await).unwrap();
+        assert!(response["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("request-1"));
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_batch_lines_enforce_context_length() {
+        let (
+            mut app_state,
+            _,
+            _,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _p2p_event_sender,
+            _,
+        ) = setup_app_state(None, false).await;
+        let _server = spawn_mock_backend(&mut app_state, mock_config()).await;
+        set_context_length(&app_state, ContextLengthPolicy::Reject);
+        let mut app = create_router(app_state.clone());
+
+        let mut oversized_body = chat_completions_body(false);
+        oversized_body["max_tokens"] = json!(1_000);
+        let batch_input = [
+            json!({
+                "custom_id": "request-1",
+                "method": "POST",
+                "url": CHAT_COMPLETIONS_PATH,
+                "body": chat_completions_body(false),
+            }),
+            json!({
+                "custom_id": "request-2",
+                "method": "POST",
+                "url": CHAT_COMPLETIONS_PATH,
+                "body": oversized_body,
+            }),
+        ]
+        .iter()
+        .map(Value::to_string)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+        // A line exceeding the context length of the model rejects the whole batch...
+        let req = signed_raw_request(
+            &app_state,
+            BATCHES_PATH,
+            "application/jsonl",
+            batch_input.clone(),
+        );
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response: Value = serde_json::from_str(&read_body(response).await).unwrap();
+        assert_eq!(response["error"]["param"], "max_tokens");
+        assert!(response["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("request-2"));
+        assert_eq!(app_state.concurrent_requests_per_stack.get(1), 0);
+
+        // ...or is clamped, so that it does not reserve more compute units than it can use
+        set_context_length(&app_state, ContextLengthPolicy::Clamp);
+        let req = signed_raw_request(&app_state, BATCHES_PATH, "application/jsonl", batch_input);
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response: Value = serde_json::from_str(&read_body(response).await).unwrap();
+        assert_eq!(response["request_counts"]["total"], 2);
+        assert!(response["reserved_compute_units"].as_i64().unwrap() <= 2 * 64);
+
+        shutdown_sender.send(true).unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_batch_usage_counts_completed_lines() {
+        let (
+            mut app_state,
+            _,
+            _,
+            shutdown_sender,
+            state_manager_handle,
+            _event_subscriber_sender,
+            _p2p_event_sender,
+            _,
+        ) = setup_app_state(None, false).await;
+        let _server = spawn_mock_backend(&mut app_state, mock_config()).await;
+        let mut app = create_router(app_state.clone());
+
+        let batch_input = ["request-1", "request-2"]
+            .iter()
+            .map(|custom_id| {
+                json!({
+                    "custom_id": custom_id,
+                    "method": "POST",
+                    "url": CHAT_COMPLETIONS_PATH,
+                    "body": chat_completions_body(false),
+                })
+                .to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let req = signed_raw_request(&app_state, BATCHES_PATH, "application/jsonl", batch_input);
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let batch_processor_handle = tokio::spawn(run_batch_processor(
+            app_state.clone(),
+            BatchProcessorConfig {
+                max_concurrent_requests: 2,
+                max_interactive_requests: 10,
+                poll_interval_ms: 10,
+            },
+            shutdown_sender.subscribe(),
+        ));
+        // The usage of the batch is recorded once, when the batch is finalized
+        let mut usage = Vec::new();
+        for _ in 0..100 {
+            usage = stack_usage(&app_state).await;
+            if !usage.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0].0, 2);
+        assert!(usage[0].1 > 0);
+
+        shutdown_sender.send(true).unwrap();
+        batch_processor_handle.await.unwrap().unwrap();
+        state_manager_handle.await.unwrap();
+        truncate_tables().await;
+    }
+}
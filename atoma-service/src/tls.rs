@@ -0,0 +1,406 @@
+//! TLS termination for the Atoma service.
+//!
+//! Nodes must expose a public HTTPS URL. Instead of requiring operators to run a reverse proxy
+//! in front of the service, the service can terminate TLS itself, with a certificate and
+//! private key read from PEM files. The files are watched, so that renewed certificates
+//! (e.g. by an ACME client) are served to new connections without restarting the node.
+
+use std::{io, path::PathBuf, sync::Arc, time::Duration};
+
+use arc_swap::ArcSwap;
+use axum::Router;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
+use rustls::{crypto::ring::default_provider, ServerConfig};
+use thiserror::Error;
+use tokio::{net::TcpListener, sync::watch::Receiver, task::JoinSet};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info};
+
+use crate::config::TlsConfig;
+
+/// The ALPN protocol identifier of HTTP/2
+const ALPN_H2: &[u8] = b"h2";
+
+/// The ALPN protocol identifier of HTTP/1.1
+const ALPN_HTTP_1_1: &[u8] = b"http/1.1";
+
+/// Maximum duration of a TLS handshake, after which the connection is dropped
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Initial delay before accepting connections again, after a failure to accept a connection
+const MIN_ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Maximum delay before accepting connections again, after repeated failures to accept a connection
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The certificate and private key currently served, together with the contents of the files
+/// they were loaded from (used to detect changes).
+struct LoadedCertificate {
+    cert_pem: Vec<u8>,
+    key_pem: Vec<u8>,
+    server_config: Arc<ServerConfig>,
+}
+
+/// A TLS server configuration, reloaded when its certificate or private key files change.
+#[derive(Clone)]
+pub struct ReloadableTlsConfig {
+    /// The TLS configuration of the service
+    config: TlsConfig,
+    /// The certificate and private key currently served
+    loaded: Arc<ArcSwap<LoadedCertificate>>,
+}
+
+impl ReloadableTlsConfig {
+    /// Loads the certificate and private key of the TLS configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the certificate or private key files cannot be read, or if they do
+    /// not hold a valid certificate chain and matching private key.
+    pub fn new(config: TlsConfig) -> Result<Self, TlsError> {
+        let loaded = load_certificate(&config)?;
+        Ok(Self {
+            config,
+            loaded: Arc::new(ArcSwap::from_pointee(loaded)),
+        })
+    }
+
+    /// Returns the TLS server configuration to use for new connections.
+    pub fn server_config(&self) -> Arc<ServerConfig> {
+        self.loaded.load().server_config.clone()
+    }
+
+    /// Reloads the certificate and private key, if their files changed.
+    ///
+    /// # Returns
+    ///
+    /// Whether a new certificate was loaded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the files changed but cannot be loaded, in which case the current
+    /// certificate is still served.
+    pub fn reload(&self) -> Result<bool, TlsError> {
+        let cert_pem = read_file(&self.config.cert_path)?;
+        let key_pem = read_file(&self.config.key_path)?;
+        {
+            let current = self.loaded.load();
+            if current.cert_pem == cert_pem && current.key_pem == key_pem {
+                return Ok(false);
+            }
+        }
+        let server_config = build_server_config(&self.config, &cert_pem, &key_pem)?;
+        self.loaded.store(Arc::new(LoadedCertificate {
+            cert_pem,
+            key_pem,
+            server_config,
+        }));
+        Ok(true)
+    }
+
+    /// Periodically reloads the certificate and private key, until a shutdown signal is received.
+    pub async fn watch(self, mut shutdown_receiver: Receiver<bool>) {
+        let reload_interval = Duration::from_millis(self.config.reload_interval_ms);
+        loop {
+            tokio::select! {
+                () = tokio::time::sleep(reload_interval) => {}
+                _ = shutdown_receiver.changed() => break,
+            }
+            match self.reload() {
+                Ok(true) => info!(
+                    target = "atoma-service",
+                    level = "info",
+                    cert_path = self.config.cert_path,
+                    "Reloaded TLS certificate"
+                ),
+                Ok(false) => {}
+                Err(e) => error!(
+                    target = "atoma-service",
+                    level = "error",
+                    cert_path = self.config.cert_path,
+                    "Failed to reload TLS certificate, still serving the previous one: {e}"
+                ),
+            }
+        }
+    }
+}
+
+/// Serves the application over TLS, until a shutdown signal is received.
+///
+/// On shutdown, the listener stops accepting connections, and in-flight connections are
+/// gracefully closed once their current requests are served.
+pub async fn serve_tls(
+    tcp_listener: TcpListener,
+    app: Router,
+    tls_config: &ReloadableTlsConfig,
+    mut shutdown_receiver: Receiver<bool>,
+) {
+    let service = TowerToHyperService::new(app);
+    let mut connections = JoinSet::new();
+    let mut accept_backoff = MIN_ACCEPT_BACKOFF;
+    loop {
+        let (stream, peer_address) = tokio::select! {
+            accepted = tcp_listener.accept() => match accepted {
+                Ok(accepted) => {
+                    accept_backoff = MIN_ACCEPT_BACKOFF;
+                    accepted
+                }
+                Err(e) => {
+                    // NOTE: Errors accepting a connection (e.g. too many open files) only
+                    // concern that connection. They tend to persist for a while though, so
+                    // we back off before accepting again, instead of spinning on the listener.
+                    error!(
+                        target = "atoma-service",
+                        level = "error",
+                        "Failed to accept connection, retrying in {accept_backoff:?}: {e}"
+                    );
+                    tokio::time::sleep(accept_backoff).await;
+                    accept_backoff = (accept_backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                    continue;
+                }
+            },
+            Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            _ = shutdown_receiver.changed() => break,
+        };
+        let acceptor = TlsAcceptor::from(tls_config.server_config());
+        let service = service.clone();
+        let enable_http2 = tls_config.config.enable_http2;
+        let mut shutdown_receiver = shutdown_receiver.clone();
+        connections.spawn(async move {
+            let stream =
+                match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => {
+                        debug!(
+                            target = "atoma-service",
+                            level = "debug",
+                            peer_address = %peer_address,
+                            "TLS handshake failed: {e}"
+                        );
+                        return;
+                    }
+                    Err(_) => {
+                        debug!(
+                            target = "atoma-service",
+                            level = "debug",
+                            peer_address = %peer_address,
+                            "TLS handshake timed out"
+                        );
+                        return;
+                    }
+                };
+            let builder = Builder::new(TokioExecutor::new());
+            let builder = if enable_http2 {
+                builder
+            } else {
+                builder.http1_only()
+            };
+            let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+            tokio::pin!(connection);
+            let result = tokio::select! {
+                result = connection.as_mut() => result,
+                _ = shutdown_receiver.changed() => {
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            };
+            if let Err(e) = result {
+                debug!(
+                    target = "atoma-service",
+                    level = "debug",
+                    peer_address = %peer_address,
+                    "Error serving connection: {e}"
+                );
+            }
+        });
+    }
+    while connections.join_next().await.is_some() {}
+}
+
+/// Loads the certificate and private key files of a TLS configuration.
+fn load_certificate(config: &TlsConfig) -> Result<LoadedCertificate, TlsError> {
+    let cert_pem = read_file(&config.cert_path)?;
+    let key_pem = read_file(&config.key_path)?;
+    let server_config = build_server_config(config, &cert_pem, &key_pem)?;
+    Ok(LoadedCertificate {
+        cert_pem,
+        key_pem,
+        server_config,
+    })
+}
+
+/// Reads a certificate or private key file.
+fn read_file(path: &str) -> Result<Vec<u8>, TlsError> {
+    std::fs::read(path).map_err(|source| TlsError::ReadFile {
+        path: PathBuf::from(path),
+        source,
+    })
+}
+
+/// Builds a TLS server configuration from a PEM-encoded certificate chain and private key.
+fn build_server_config(
+    config: &TlsConfig,
+    cert_pem: &[u8],
+    key_pem: &[u8],
+) -> Result<Arc<ServerConfig>, TlsError> {
+    let certificates = rustls_pemfile::certs(&mut &cert_pem[..])
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|source| TlsError::InvalidPem {
+            path: PathBuf::from(&config.cert_path),
+            source,
+        })?;
+    if certificates.is_empty() {
+        return Err(TlsError::NoCertificate(PathBuf::from(&config.cert_path)));
+    }
+    let key = rustls_pemfile::private_key(&mut &key_pem[..])
+        .map_err(|source| TlsError::InvalidPem {
+            path: PathBuf::from(&config.key_path),
+            source,
+        })?
+        .ok_or_else(|| TlsError::NoPrivateKey(PathBuf::from(&config.key_path)))?;
+    let mut server_config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certificates, key)?;
+    server_config.alpn_protocols = if config.enable_http2 {
+        vec![ALPN_H2.to_vec(), ALPN_HTTP_1_1.to_vec()]
+    } else {
+        vec![ALPN_HTTP_1_1.to_vec()]
+    };
+    Ok(Arc::new(server_config))
+}
+
+/// Errors returned when loading a TLS certificate
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("Failed to read {}: {source}", path.display())]
+    ReadFile { path: PathBuf, source: io::Error },
+    #[error("Invalid PEM file {}: {source}", path.display())]
+    InvalidPem { path: PathBuf, source: io::Error },
+    #[error("No certificate found in {}", .0.display())]
+    NoCertificate(PathBuf),
+    #[error("No private key found in {}", .0.display())]
+    NoPrivateKey(PathBuf),
+    #[error("Invalid certificate or private key: {0}")]
+    InvalidCertificate(#[from] rustls::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::routing::get;
+    use rcgen::{generate_simple_self_signed, CertifiedKey};
+    use rustls::{pki_types::ServerName, ClientConfig, RootCertStore};
+    use tempfile::TempDir;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        sync::watch,
+    };
+    use tokio_rustls::{client::TlsStream, TlsConnector};
+
+    use super::*;
+
+    /// Writes a new self-signed certificate for `localhost` to the files of the TLS configuration
+    fn write_certificate(config: &TlsConfig) -> CertifiedKey {
+        let certified_key = generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::write(&config.cert_path, certified_key.cert.pem()).unwrap();
+        std::fs::write(&config.key_path, certified_key.key_pair.serialize_pem()).unwrap();
+        certified_key
+    }
+
+    fn tls_config(dir: &TempDir, enable_http2: bool) -> TlsConfig {
+        TlsConfig {
+            cert_path: dir.path().join("cert.pem").to_str().unwrap().to_string(),
+            key_path: dir.path().join("key.pem").to_str().unwrap().to_string(),
+            enable_http2,
+            ..TlsConfig::default()
+        }
+    }
+
+    async fn connect(
+        address: std::net::SocketAddr,
+        certified_key: &CertifiedKey,
+        alpn_protocols: &[&[u8]],
+    ) -> TlsStream<TcpStream> {
+        let mut roots = RootCertStore::empty();
+        roots.add(certified_key.cert.der().clone()).unwrap();
+        let mut client_config = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        client_config.alpn_protocols = alpn_protocols.iter().map(|p| p.to_vec()).collect();
+        let stream = TcpStream::connect(address).await.unwrap();
+        TlsConnector::from(Arc::new(client_config))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = tls_config(&dir, false);
+        write_certificate(&config);
+        let tls_config = ReloadableTlsConfig::new(config.clone()).unwrap();
+        let server_config = tls_config.server_config();
+        assert_eq!(server_config.alpn_protocols, [ALPN_HTTP_1_1.to_vec()]);
+
+        // Unchanged files are not reloaded
+        assert!(!tls_config.reload().unwrap());
+        assert!(Arc::ptr_eq(&server_config, &tls_config.server_config()));
+
+        write_certificate(&config);
+        assert!(tls_config.reload().unwrap());
+        let reloaded_server_config = tls_config.server_config();
+        assert!(!Arc::ptr_eq(&server_config, &reloaded_server_config));
+
+        // Invalid files are not loaded, the previous certificate is still served
+        std::fs::write(&config.key_path, "not a key").unwrap();
+        assert!(matches!(
+            tls_config.reload(),
+            Err(TlsError::NoPrivateKey(_))
+        ));
+        assert!(Arc::ptr_eq(
+            &reloaded_server_config,
+            &tls_config.server_config()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_serve_tls() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = tls_config(&dir, true);
+        let certified_key = write_certificate(&config);
+        let tls_config = ReloadableTlsConfig::new(config).unwrap();
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = tcp_listener.local_addr().unwrap();
+        let app = Router::new().route("/health", get(|| async { "OK" }));
+        let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+        let server = tokio::spawn(async move {
+            serve_tls(tcp_listener, app, &tls_config, shutdown_receiver).await;
+        });
+
+        let stream = connect(address, &certified_key, &[ALPN_H2, ALPN_HTTP_1_1]).await;
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(ALPN_H2));
+        drop(stream);
+
+        let mut stream = connect(address, &certified_key, &[ALPN_HTTP_1_1]).await;
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(ALPN_HTTP_1_1));
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("OK"));
+
+        shutdown_sender.send(true).unwrap();
+        server.await.unwrap();
+    }
+}
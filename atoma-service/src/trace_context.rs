@@ -0,0 +1,134 @@
+//! Request IDs and W3C trace context propagation.
+//!
+//! Every request handled by the service is assigned a request ID (the client provided
+//! `X-Request-Id` header, or a generated one), which is forwarded to the inference services
+//! together with a `traceparent` header, so that a single request can be followed from the
+//! client, through the Atoma Service and the state manager, up to the inference service.
+
+use atoma_utils::constants::REQUEST_ID;
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use opentelemetry::{
+    propagation::{Extractor, Injector, TextMapPropagator},
+    Context,
+};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// The W3C trace context header
+pub const TRACEPARENT: &str = "traceparent";
+
+/// Maximum length of the request IDs provided by clients
+pub const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Generates a new random request ID, hex encoded.
+#[must_use]
+pub fn generate_request_id() -> String {
+    hex::encode(rand::random::<[u8; 16]>())
+}
+
+/// Reads the trace context headers of a request
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+/// Writes the trace context headers of a request
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// Extracts the trace context of the client from the `traceparent` header of a request.
+///
+/// Returns an empty context if the request has no (valid) `traceparent` header.
+#[must_use]
+pub fn extract_trace_context(headers: &HeaderMap) -> Context {
+    TraceContextPropagator::new().extract(&HeaderExtractor(headers))
+}
+
+/// Returns the headers to send to the inference services: the request ID, if any, and the
+/// `traceparent` header of the current span, so that the spans of the inference services are
+/// part of the trace of the request.
+#[must_use]
+pub fn inference_service_headers(request_id: Option<&str>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Some(request_id) = request_id.and_then(|id| HeaderValue::from_str(id).ok()) {
+        headers.insert(REQUEST_ID, request_id);
+    }
+    TraceContextPropagator::new().inject_context(
+        &Span::current().context(),
+        &mut HeaderInjector(&mut headers),
+    );
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::{TraceContextExt, TracerProvider as _};
+    use opentelemetry_sdk::trace::TracerProvider;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    #[test]
+    fn test_generate_request_id() {
+        let request_id = generate_request_id();
+        assert_eq!(request_id.len(), 32);
+        assert!(request_id.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(request_id, generate_request_id());
+    }
+
+    #[test]
+    fn test_trace_context_propagation() {
+        let provider = TracerProvider::builder().build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("atoma-service")));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            TRACEPARENT,
+            HeaderValue::from_str(&format!("00-{TRACE_ID}-00f067aa0ba902b7-01")).unwrap(),
+        );
+        let parent = extract_trace_context(&headers);
+        assert_eq!(
+            parent.span().span_context().trace_id().to_string(),
+            TRACE_ID
+        );
+
+        let headers = tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request");
+            span.set_parent(parent);
+            span.in_scope(|| inference_service_headers(Some("request-id")))
+        });
+        assert_eq!(headers.get(REQUEST_ID).unwrap(), "request-id");
+        let traceparent = headers.get(TRACEPARENT).unwrap().to_str().unwrap();
+        // NOTE: The trace ID is kept, while the parent span is the span of the request
+        assert!(traceparent.starts_with(&format!("00-{TRACE_ID}-")));
+        assert!(!traceparent.contains("00f067aa0ba902b7"));
+    }
+
+    #[test]
+    fn test_inference_service_headers_without_trace() {
+        let headers = inference_service_headers(None);
+        assert!(headers.get(REQUEST_ID).is_none());
+        assert!(headers.get(TRACEPARENT).is_none());
+    }
+}
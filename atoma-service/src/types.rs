@@ -12,13 +12,15 @@ pub struct ConfidentialComputeRequest {
     /// Unique identifier for the small stack being used
     pub stack_small_id: u64,
 
-    /// Cryptographic nonce used for encryption (base64 encoded)
+    /// Cryptographic nonce used for encryption (base64 encoded), not used by HPKE requests
+    #[serde(default)]
     pub nonce: String,
 
     /// Salt value used in key derivation (base64 encoded)
     pub salt: String,
 
-    /// Client's public key for Diffie-Hellman key exchange (base64 encoded)
+    /// Client's public key for Diffie-Hellman key exchange (base64 encoded),
+    /// or the HPKE encapsulated key for HPKE requests
     pub client_dh_public_key: String,
 
     /// Node's public key for Diffie-Hellman key exchange (base64 encoded)
@@ -36,6 +38,22 @@ pub struct ConfidentialComputeRequest {
     /// Number of compute units to be used for the request, for image generations,
     /// as this value is known in advance (the number of pixels to generate)
     pub num_compute_units: Option<u64>,
+
+    /// Confidential compute protocol version (defaults to 1 if not specified).
+    ///
+    /// From version 2 onwards, the `ciphertext` is encrypted with the cleartext envelope
+    /// fields (`stack_small_id`, `model_name`, `stream` and `num_compute_units`) as
+    /// AES-GCM associated data, see `atoma_utils::encryption::confidential_request_associated_data`.
+    ///
+    /// Version 3 requests are HPKE (RFC 9180) base mode envelopes, see `atoma_utils::hpke`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+
+    /// HPKE AEAD identifier of the request, required for (and only allowed with) HPKE requests:
+    /// `0x0002` for AES-256-GCM, or `0x0003` for ChaCha20-Poly1305. The KEM is always
+    /// `DHKEM(X25519, HKDF-SHA256)` and the KDF is always `HKDF-SHA256`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suite: Option<u16>,
 }
 
 /// Represents a response from a confidential compute request
@@ -7,13 +7,80 @@ use std::path::Path;
 pub struct AtomaStateManagerConfig {
     /// The URL of the SQLite database.
     pub database_url: String,
+
+    /// Configuration for the retries of the events which failed to be applied.
+    ///
+    /// This is an optional section, if not provided, default values are used.
+    #[serde(default)]
+    pub dead_letter: DeadLetterConfig,
+
+    /// Configuration for the archiving of the claimed and settled stacks.
+    ///
+    /// This is an optional section, if not provided, default values are used.
+    #[serde(default)]
+    pub retention: RetentionConfig,
+}
+
+/// Configuration for the retries of the events which failed to be applied to the state.
+///
+/// Events whose handler fails are moved to a dead-letter table, instead of stopping the state
+/// manager, and are retried periodically until they are applied or run out of attempts.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DeadLetterConfig {
+    /// Interval, in milliseconds, between two retries of the dead-letter events.
+    pub retry_interval_ms: u64,
+
+    /// Maximum number of attempts to apply an event, after which it is no longer retried.
+    pub max_attempts: u64,
+}
+
+impl Default for DeadLetterConfig {
+    fn default() -> Self {
+        Self {
+            retry_interval_ms: 60_000,
+            max_attempts: 10,
+        }
+    }
+}
+
+/// Configuration for the archiving of the claimed and settled stacks.
+///
+/// When enabled, the stacks claimed and whose disputes were settled more than
+/// `retention_epochs` epochs ago are periodically moved, together with their settlement tickets
+/// and attestation disputes, to archive tables, keeping the hot tables small.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Whether the settled stacks are archived.
+    pub enabled: bool,
+
+    /// Number of epochs a stack is kept in the state after its dispute was settled.
+    pub retention_epochs: u64,
+
+    /// Interval, in milliseconds, between two archiving runs.
+    pub interval_ms: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retention_epochs: 30,
+            interval_ms: 3_600_000,
+        }
+    }
 }
 
 impl AtomaStateManagerConfig {
     /// Constructor
     #[must_use]
-    pub const fn new(database_url: String) -> Self {
-        Self { database_url }
+    pub fn new(database_url: String) -> Self {
+        Self {
+            database_url,
+            dead_letter: DeadLetterConfig::default(),
+            retention: RetentionConfig::default(),
+        }
     }
 
     /// Creates a new `AtomaStateManagerConfig` instance from a configuration file.
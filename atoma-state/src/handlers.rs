@@ -1,78 +1,86 @@
 use atoma_p2p::types::AtomaP2pEvent;
 use atoma_sui::events::{
     AtomaEvent, ClaimedStackEvent, NewStackSettlementAttestationEvent,
-    NodePublicKeyCommittmentEvent, NodeRegisteredEvent, NodeSubscribedToTaskEvent,
-    NodeSubscriptionUpdatedEvent, NodeUnsubscribedFromTaskEvent, StackAttestationDisputeEvent,
-    StackCreateAndUpdateEvent, StackCreatedEvent, StackSettlementTicketClaimedEvent,
-    StackSettlementTicketEvent, StackSmallId, StackTrySettleEvent, TaskDeprecationEvent,
-    TaskRegisteredEvent,
+    NodePublicKeyCommittmentEvent, NodeRegisteredEvent, NodeSubscribedToModelEvent,
+    NodeSubscribedToTaskEvent, NodeSubscriptionUpdatedEvent, NodeUnsubscribedFromTaskEvent,
+    StackAttestationDisputeEvent, StackCreateAndUpdateEvent, StackCreatedEvent,
+    StackSettlementTicketClaimedEvent, StackSettlementTicketEvent, StackSmallId,
+    StackTrySettleEvent, TaskDeprecationEvent, TaskRegisteredEvent, TaskRemovedEvent,
 };
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::oneshot;
-use tracing::{info, instrument};
+use tracing::{info, instrument, Span};
 
 use crate::{
     state_manager::Result,
     types::{
-        AtomaAtomaStateManagerEvent, StackSettlementTicket, UpdateStackNumComputeUnitsAndClaimFunds,
+        AtomaAtomaStateManagerEvent, StackSettlementTicket, StackStatus,
+        UpdateStackNumComputeUnitsAndClaimFunds,
     },
-    AtomaStateManager, AtomaStateManagerError,
+    AtomaState, AtomaStateManager, AtomaStateManagerError,
 };
 
 const RATIO_FOR_CLAIM_STACK_THRESHOLD: f64 = 0.95;
 
+/// Returns the current time, in milliseconds since the Unix epoch.
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| i64::try_from(d.as_millis()).unwrap_or(i64::MAX))
+}
+
 #[instrument(level = "info", skip_all)]
 pub async fn handle_atoma_event(
     event: AtomaEvent,
-    state_manager: &AtomaStateManager,
+    state: &AtomaState,
+    checkpoint_timestamp_ms: Option<i64>,
 ) -> Result<()> {
     match event {
-        AtomaEvent::TaskRegisteredEvent(event) => handle_new_task_event(state_manager, event).await,
+        AtomaEvent::TaskRegisteredEvent(event) => handle_new_task_event(state, event).await,
         AtomaEvent::TaskDeprecationEvent(event) => {
-            handle_task_deprecation_event(state_manager, event).await
+            handle_task_deprecation_event(state, event).await
         }
         AtomaEvent::NodeSubscribedToTaskEvent(event) => {
-            handle_node_task_subscription_event(state_manager, event).await
+            handle_node_task_subscription_event(state, event).await
         }
         AtomaEvent::NodeSubscriptionUpdatedEvent(event) => {
-            handle_node_task_subscription_updated_event(state_manager, event).await
+            handle_node_task_subscription_updated_event(state, event).await
         }
         AtomaEvent::NodeUnsubscribedFromTaskEvent(event) => {
-            handle_node_task_unsubscription_event(state_manager, event).await
-        }
-        AtomaEvent::StackCreatedEvent((event, _)) => {
-            handle_stack_created_event(state_manager, event).await
+            handle_node_task_unsubscription_event(state, event).await
         }
+        AtomaEvent::StackCreatedEvent((event, _)) => handle_stack_created_event(state, event).await,
         AtomaEvent::StackCreateAndUpdateEvent(event) => {
-            handle_stack_create_and_update_event(state_manager, event).await
+            handle_stack_create_and_update_event(state, event).await
         }
         AtomaEvent::StackTrySettleEvent((event, _)) => {
-            handle_stack_try_settle_event(state_manager, event).await
+            handle_stack_try_settle_event(state, event, checkpoint_timestamp_ms).await
         }
         AtomaEvent::StackSettlementTicketEvent(event) => {
-            handle_stack_settlement_ticket_event(state_manager, event).await
+            handle_stack_settlement_ticket_event(state, event, checkpoint_timestamp_ms).await
         }
         AtomaEvent::StackSettlementTicketClaimedEvent(event) => {
-            handle_stack_settlement_ticket_claimed_event(state_manager, event).await
+            handle_stack_settlement_ticket_claimed_event(state, event, checkpoint_timestamp_ms)
+                .await
         }
         AtomaEvent::StackAttestationDisputeEvent(event) => {
-            handle_stack_attestation_dispute_event(state_manager, event).await
+            handle_stack_attestation_dispute_event(state, event, checkpoint_timestamp_ms).await
         }
         AtomaEvent::NewStackSettlementAttestationEvent(event) => {
-            handle_new_stack_settlement_attestation_event(state_manager, event).await
+            handle_new_stack_settlement_attestation_event(state, event).await
         }
         AtomaEvent::PublishedEvent(event) => {
             info!("Published event: {:?}", event);
             Ok(())
         }
         AtomaEvent::ClaimedStackEvent(event) => {
-            handle_claimed_stack_event(state_manager, event).await
+            handle_claimed_stack_event(state, event, checkpoint_timestamp_ms).await
         }
         AtomaEvent::NodeRegisteredEvent((event, sender)) => {
-            handle_node_registered_event(state_manager, event, sender.to_string()).await
+            handle_node_registered_event(state, event, sender.to_string()).await
         }
         AtomaEvent::NodeSubscribedToModelEvent(event) => {
-            info!("Node subscribed to model event: {:?}", event);
-            Ok(())
+            handle_node_model_subscription_event(state, event).await
         }
         AtomaEvent::FirstSubmissionEvent(event) => {
             info!("First submission event: {:?}", event);
@@ -94,10 +102,7 @@ pub async fn handle_atoma_event(
             info!("Retry settlement event: {:?}", event);
             Ok(())
         }
-        AtomaEvent::TaskRemovedEvent(event) => {
-            info!("Task removed event: {:?}", event);
-            Ok(())
-        }
+        AtomaEvent::TaskRemovedEvent(event) => handle_task_removed_event(state, event).await,
         AtomaEvent::Text2ImagePromptEvent(event) => {
             info!("Text2Image prompt event: {:?}", event);
             Ok(())
@@ -111,19 +116,49 @@ pub async fn handle_atoma_event(
             Ok(())
         }
         AtomaEvent::NodePublicKeyCommittmentEvent(event) => {
-            handle_node_key_rotation_event(state_manager, event).await
+            handle_node_key_rotation_event(state, event).await
         }
     }
 }
 
+/// Returns the key of the object (task, node or stack) an Atoma event applies to, if any.
+///
+/// The events of an object must be applied in order, so the state manager holds back the events
+/// of an object while an earlier one of its events is in the dead-letter table.
+pub(crate) fn atoma_event_object_key(event: &AtomaEvent) -> Option<String> {
+    let task = |task_small_id: u64| Some(format!("task:{task_small_id}"));
+    let node = |node_small_id: u64| Some(format!("node:{node_small_id}"));
+    let stack = |stack_small_id: u64| Some(format!("stack:{stack_small_id}"));
+    match event {
+        AtomaEvent::TaskRegisteredEvent(event) => task(event.task_small_id.inner),
+        AtomaEvent::TaskDeprecationEvent(event) => task(event.task_small_id.inner),
+        AtomaEvent::TaskRemovedEvent(event) => task(event.task_small_id.inner),
+        AtomaEvent::NodeRegisteredEvent((event, _)) => node(event.node_small_id.inner),
+        AtomaEvent::NodeSubscribedToModelEvent(event) => node(event.node_small_id.inner),
+        AtomaEvent::NodeSubscribedToTaskEvent(event) => node(event.node_small_id.inner),
+        AtomaEvent::NodeSubscriptionUpdatedEvent(event) => node(event.node_small_id.inner),
+        AtomaEvent::NodeUnsubscribedFromTaskEvent(event) => node(event.node_small_id.inner),
+        AtomaEvent::NodePublicKeyCommittmentEvent(event) => node(event.node_id.inner),
+        AtomaEvent::StackCreatedEvent((event, _)) => stack(event.stack_small_id.inner),
+        AtomaEvent::StackCreateAndUpdateEvent(event) => stack(event.stack_small_id.inner),
+        AtomaEvent::StackTrySettleEvent((event, _)) => stack(event.stack_small_id.inner),
+        AtomaEvent::StackSettlementTicketEvent(event) => stack(event.stack_small_id.inner),
+        AtomaEvent::StackSettlementTicketClaimedEvent(event) => stack(event.stack_small_id.inner),
+        AtomaEvent::StackAttestationDisputeEvent(event) => stack(event.stack_small_id.inner),
+        AtomaEvent::NewStackSettlementAttestationEvent(event) => stack(event.stack_small_id.inner),
+        AtomaEvent::ClaimedStackEvent(event) => stack(event.stack_small_id.inner),
+        _ => None,
+    }
+}
+
 /// Handles a new task event by processing and inserting it into the database.
 ///
 /// This function takes a serialized `TaskRegisteredEvent`, deserializes it, and
-/// inserts the corresponding task into the database using the provided `AtomaStateManager`.
+/// inserts the corresponding task into the database using the provided `AtomaState`.
 ///
 /// # Arguments
 ///
-/// * `state_manager` - A reference to the `AtomaStateManager` for database operations.
+/// * `state` - A reference to the `AtomaState` for database operations.
 /// * `value` - A `serde_json::Value` containing the serialized `TaskRegisteredEvent`.
 ///
 /// # Returns
@@ -134,10 +169,10 @@ pub async fn handle_atoma_event(
 ///
 /// This function will return an error if:
 /// * The `value` cannot be deserialized into a `TaskRegisteredEvent`.
-/// * The `AtomaStateManager` fails to insert the new task into the database.
+/// * The `AtomaState` fails to insert the new task into the database.
 #[instrument(level = "info", skip_all)]
 pub(crate) async fn handle_new_task_event(
-    state_manager: &AtomaStateManager,
+    state: &AtomaState,
     event: TaskRegisteredEvent,
 ) -> Result<()> {
     info!(
@@ -146,7 +181,7 @@ pub(crate) async fn handle_new_task_event(
         "Processing new task event"
     );
     let task = event.into();
-    state_manager.state.insert_new_task(task).await?;
+    state.insert_new_task(task).await?;
     Ok(())
 }
 
@@ -157,7 +192,7 @@ pub(crate) async fn handle_new_task_event(
 ///
 /// # Arguments
 ///
-/// * `state_manager` - A reference to the `AtomaStateManager` for database operations.
+/// * `state` - A reference to the `AtomaState` for database operations.
 /// * `value` - A `serde_json::Value` containing the serialized task deprecation event data.
 ///
 /// # Returns
@@ -175,10 +210,10 @@ pub(crate) async fn handle_new_task_event(
 /// The function performs the following steps:
 /// 1. Deserializes the input `value` into a `TaskDeprecationEvent`.
 /// 2. Extracts the `task_small_id` and `epoch` from the event.
-/// 3. Calls the `deprecate_task` method on the `AtomaStateManager` to update the task's status in the database.
+/// 3. Calls the `deprecate_task` method on the `AtomaState` to update the task's status in the database.
 #[instrument(level = "info", skip_all)]
 pub(crate) async fn handle_task_deprecation_event(
-    state_manager: &AtomaStateManager,
+    state: &AtomaState,
     event: TaskDeprecationEvent,
 ) -> Result<()> {
     info!(
@@ -188,13 +223,87 @@ pub(crate) async fn handle_task_deprecation_event(
     );
     let task_small_id = event.task_small_id;
     let epoch = event.epoch;
-    state_manager
-        .state
+    state
         .deprecate_task(task_small_id.inner as i64, epoch as i64)
         .await?;
     Ok(())
 }
 
+/// Handles a task removed event.
+///
+/// This function processes a task removed event by marking the task as permanently removed
+/// (and deprecated) in the database, so that the node stops accepting new requests for the
+/// task's model, if it has no other active task.
+///
+/// # Arguments
+///
+/// * `state` - A reference to the `AtomaState` for database operations.
+/// * `event` - A `TaskRemovedEvent` containing the details of the removed task.
+///
+/// # Returns
+///
+/// * `Result<()>` - Ok(()) if the event was processed successfully, or an error if something went wrong.
+///
+/// # Errors
+///
+/// This function will return an error if the database operation to remove the task fails.
+#[instrument(level = "info", skip_all)]
+pub(crate) async fn handle_task_removed_event(
+    state: &AtomaState,
+    event: TaskRemovedEvent,
+) -> Result<()> {
+    info!(
+        target = "atoma-state-handlers",
+        event = "handle-task-removed-event",
+        "Processing task removed event"
+    );
+    state
+        .remove_task(
+            event.task_small_id.inner as i64,
+            event.removed_at_epoch as i64,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Handles a node model subscription event.
+///
+/// This function records the node's subscription to a model in the database, so that the
+/// node can start serving the model, if one of its inference services serves it.
+///
+/// # Arguments
+///
+/// * `state` - A reference to the `AtomaState` for database operations.
+/// * `event` - A `NodeSubscribedToModelEvent` containing the details of the subscription event.
+///
+/// # Returns
+///
+/// * `Result<()>` - Ok(()) if the event was processed successfully, or an error if something went wrong.
+///
+/// # Errors
+///
+/// This function will return an error if the database operation to record the subscription fails.
+#[instrument(level = "info", skip_all)]
+pub(crate) async fn handle_node_model_subscription_event(
+    state: &AtomaState,
+    event: NodeSubscribedToModelEvent,
+) -> Result<()> {
+    info!(
+        target = "atoma-state-handlers",
+        event = "handle-node-model-subscription-event",
+        model_name = event.model_name,
+        "Processing node model subscription event"
+    );
+    state
+        .subscribe_node_to_model(
+            event.node_small_id.inner as i64,
+            &event.model_name,
+            event.echelon_id.id as i64,
+        )
+        .await?;
+    Ok(())
+}
+
 /// Handles a node task subscription event.
 ///
 /// This function processes a node task subscription event by parsing the event data,
@@ -202,7 +311,7 @@ pub(crate) async fn handle_task_deprecation_event(
 ///
 /// # Arguments
 ///
-/// * `state_manager` - A reference to the `AtomaStateManager` for database operations.
+/// * `state` - A reference to the `AtomaState` for database operations.
 /// * `event` - A `NodeSubscribedToTaskEvent` containing the details of the subscription event.
 ///
 /// # Returns
@@ -219,10 +328,10 @@ pub(crate) async fn handle_task_deprecation_event(
 ///
 /// The function performs the following steps:
 /// 1. Extracts the `node_small_id`, `task_small_id`, `price_per_one_million_compute_units`, and `max_num_compute_units` from the event.
-/// 2. Calls the `subscribe_node_to_task` method on the `AtomaStateManager` to update the node's subscription in the database.
+/// 2. Calls the `subscribe_node_to_task` method on the `AtomaState` to update the node's subscription in the database.
 #[instrument(level = "info", skip_all)]
 pub(crate) async fn handle_node_task_subscription_event(
-    state_manager: &AtomaStateManager,
+    state: &AtomaState,
     event: NodeSubscribedToTaskEvent,
 ) -> Result<()> {
     info!(
@@ -234,8 +343,7 @@ pub(crate) async fn handle_node_task_subscription_event(
     let task_small_id = event.task_small_id.inner as i64;
     let price_per_one_million_compute_units = event.price_per_one_million_compute_units as i64;
     let max_num_compute_units = event.max_num_compute_units as i64;
-    state_manager
-        .state
+    state
         .subscribe_node_to_task(
             node_small_id,
             task_small_id,
@@ -253,7 +361,7 @@ pub(crate) async fn handle_node_task_subscription_event(
 ///
 /// # Arguments
 ///
-/// * `state_manager` - A reference to the `AtomaStateManager` for database operations.
+/// * `state` - A reference to the `AtomaState` for database operations.
 /// * `event` - A `NodeSubscriptionUpdatedEvent` containing the details of the subscription update.
 ///
 /// # Returns
@@ -270,10 +378,10 @@ pub(crate) async fn handle_node_task_subscription_event(
 ///
 /// The function performs the following steps:
 /// 1. Extracts the `node_small_id`, `task_small_id`, `price_per_one_million_compute_units`, and `max_num_compute_units` from the event.
-/// 2. Calls the `update_node_subscription` method on the `AtomaStateManager` to update the node's subscription in the database.
+/// 2. Calls the `update_node_subscription` method on the `AtomaState` to update the node's subscription in the database.
 #[instrument(level = "info", skip_all)]
 pub(crate) async fn handle_node_task_subscription_updated_event(
-    state_manager: &AtomaStateManager,
+    state: &AtomaState,
     event: NodeSubscriptionUpdatedEvent,
 ) -> Result<()> {
     info!(
@@ -285,8 +393,7 @@ pub(crate) async fn handle_node_task_subscription_updated_event(
     let task_small_id = event.task_small_id.inner as i64;
     let price_per_one_million_compute_units = event.price_per_one_million_compute_units as i64;
     let max_num_compute_units = event.max_num_compute_units as i64;
-    state_manager
-        .state
+    state
         .update_node_subscription(
             node_small_id,
             task_small_id,
@@ -304,7 +411,7 @@ pub(crate) async fn handle_node_task_subscription_updated_event(
 ///
 /// # Arguments
 ///
-/// * `state_manager` - A reference to the `AtomaStateManager` for database operations.
+/// * `state` - A reference to the `AtomaState` for database operations.
 /// * `event` - A `NodeUnsubscribedFromTaskEvent` containing the details of the unsubscription event.
 ///
 /// # Returns
@@ -321,10 +428,10 @@ pub(crate) async fn handle_node_task_subscription_updated_event(
 ///
 /// The function performs the following steps:
 /// 1. Extracts the `node_small_id` and `task_small_id` from the event.
-/// 2. Calls the `unsubscribe_node_from_task` method on the `AtomaStateManager` to update the node's subscription status in the database.
+/// 2. Calls the `unsubscribe_node_from_task` method on the `AtomaState` to update the node's subscription status in the database.
 #[instrument(level = "info", skip_all)]
 pub(crate) async fn handle_node_task_unsubscription_event(
-    state_manager: &AtomaStateManager,
+    state: &AtomaState,
     event: NodeUnsubscribedFromTaskEvent,
 ) -> Result<()> {
     info!(
@@ -334,8 +441,7 @@ pub(crate) async fn handle_node_task_unsubscription_event(
     );
     let node_small_id = event.node_small_id.inner as i64;
     let task_small_id = event.task_small_id.inner as i64;
-    state_manager
-        .state
+    state
         .unsubscribe_node_from_task(node_small_id, task_small_id)
         .await?;
     Ok(())
@@ -349,7 +455,7 @@ pub(crate) async fn handle_node_task_unsubscription_event(
 ///
 /// # Arguments
 ///
-/// * `state_manager` - A reference to the `AtomaStateManager` for database operations.
+/// * `state` - A reference to the `AtomaState` for database operations.
 /// * `event` - A `StackCreatedEvent` containing the details of the stack creation event.
 /// * `node_small_ids` - A slice of `u64` values representing the small IDs of the current nodes.
 ///
@@ -371,7 +477,7 @@ pub(crate) async fn handle_node_task_unsubscription_event(
 /// 3. If the node is valid, it converts the event into a stack object and inserts it into the database.
 #[instrument(level = "info", skip_all)]
 pub(crate) async fn handle_stack_created_event(
-    state_manager: &AtomaStateManager,
+    state: &AtomaState,
     event: StackCreatedEvent,
 ) -> Result<()> {
     info!(
@@ -387,13 +493,13 @@ pub(crate) async fn handle_stack_created_event(
         event.stack_small_id.inner
     );
     let stack = event.into();
-    state_manager.state.insert_new_stack(stack).await?;
+    state.insert_new_stack(stack).await?;
     Ok(())
 }
 
 #[instrument(level = "info", skip_all)]
 pub(crate) async fn handle_stack_create_and_update_event(
-    state_manager: &AtomaStateManager,
+    state: &AtomaState,
     event: StackCreateAndUpdateEvent,
 ) -> Result<()> {
     info!(
@@ -403,7 +509,7 @@ pub(crate) async fn handle_stack_create_and_update_event(
         event.stack_small_id.inner
     );
     let stack = event.into();
-    state_manager.state.insert_new_stack(stack).await?;
+    state.insert_new_stack(stack).await?;
     Ok(())
 }
 
@@ -414,8 +520,9 @@ pub(crate) async fn handle_stack_create_and_update_event(
 ///
 /// # Arguments
 ///
-/// * `state_manager` - A reference to the `AtomaStateManager` for database operations.
+/// * `state` - A reference to the `AtomaState` for database operations.
 /// * `event` - A `StackTrySettleEvent` containing the details of the stack try settle event.
+/// * `checkpoint_timestamp_ms` - The timestamp of the checkpoint of the event, if known.
 ///
 /// # Returns
 ///
@@ -431,22 +538,33 @@ pub(crate) async fn handle_stack_create_and_update_event(
 ///
 /// The function performs the following steps:
 /// 1. Converts the `StackTrySettleEvent` into a stack settlement ticket.
-/// 2. Calls the `insert_new_stack_settlement_ticket` method on the `AtomaStateManager` to insert the ticket into the database.
+/// 2. Calls the `insert_new_stack_settlement_ticket` method on the `AtomaState` to insert the ticket into the database.
+/// 3. Moves the stack to the `Settling` status (see `AtomaState::apply_chain_stack_status`).
 #[instrument(level = "info", skip_all)]
 pub(crate) async fn handle_stack_try_settle_event(
-    state_manager: &AtomaStateManager,
+    state: &AtomaState,
     event: StackTrySettleEvent,
+    checkpoint_timestamp_ms: Option<i64>,
 ) -> Result<()> {
     info!(
         target = "atoma-state-handlers",
         event = "handle-stack-try-settle-event",
         "Processing stack try settle event"
     );
+    let stack_small_id = event.stack_small_id.inner as i64;
     let stack_settlement_ticket = StackSettlementTicket::try_from(event)?;
-    state_manager
-        .state
+    state
         .insert_new_stack_settlement_ticket(stack_settlement_ticket)
         .await?;
+    state
+        .apply_chain_stack_status(
+            stack_small_id,
+            StackStatus::Settling,
+            None,
+            checkpoint_timestamp_ms,
+            "StackTrySettleEvent",
+        )
+        .await?;
     Ok(())
 }
 
@@ -457,7 +575,7 @@ pub(crate) async fn handle_stack_try_settle_event(
 ///
 /// # Arguments
 ///
-/// * `state_manager` - A reference to the `AtomaStateManager` for database operations.
+/// * `state` - A reference to the `AtomaState` for database operations.
 /// * `event` - A `NewStackSettlementAttestationEvent` containing the details of the attestation event.
 ///
 /// # Returns
@@ -474,10 +592,10 @@ pub(crate) async fn handle_stack_try_settle_event(
 ///
 /// The function performs the following steps:
 /// 1. Extracts the `stack_small_id`, `attestation_node_id`, `committed_stack_proof`, and `stack_merkle_leaf` from the event.
-/// 2. Calls the `update_stack_settlement_ticket_with_attestation_commitments` method on the `AtomaStateManager` to update the database.
+/// 2. Calls the `update_stack_settlement_ticket_with_attestation_commitments` method on the `AtomaState` to update the database.
 #[instrument(level = "info", skip_all)]
 pub(crate) async fn handle_new_stack_settlement_attestation_event(
-    state_manager: &AtomaStateManager,
+    state: &AtomaState,
     event: NewStackSettlementAttestationEvent,
 ) -> Result<()> {
     info!(
@@ -490,8 +608,7 @@ pub(crate) async fn handle_new_stack_settlement_attestation_event(
     let committed_stack_proof = event.committed_stack_proof;
     let stack_merkle_leaf = event.stack_merkle_leaf;
 
-    state_manager
-        .state
+    state
         .update_stack_settlement_ticket_with_attestation_commitments(
             stack_small_id,
             committed_stack_proof,
@@ -509,8 +626,9 @@ pub(crate) async fn handle_new_stack_settlement_attestation_event(
 ///
 /// # Arguments
 ///
-/// * `state_manager` - A reference to the `AtomaStateManager` for database operations.
+/// * `state` - A reference to the `AtomaState` for database operations.
 /// * `event` - A `StackSettlementTicketEvent` containing the details of the stack settlement ticket event.
+/// * `checkpoint_timestamp_ms` - The timestamp of the checkpoint of the event, if known.
 ///
 /// # Returns
 ///
@@ -526,11 +644,14 @@ pub(crate) async fn handle_new_stack_settlement_attestation_event(
 ///
 /// The function performs the following steps:
 /// 1. Extracts the `stack_small_id` and `dispute_settled_at_epoch` from the event.
-/// 2. Calls the `settle_stack_settlement_ticket` method on the `AtomaStateManager` to update the database.
+/// 2. Calls the `settle_stack_settlement_ticket` method on the `AtomaState` to update the database.
+/// 3. Moves the stack to the `Settled` status, at the `dispute_settled_at_epoch` epoch (see
+///    `AtomaState::apply_chain_stack_status`).
 #[instrument(level = "info", skip_all)]
 pub(crate) async fn handle_stack_settlement_ticket_event(
-    state_manager: &AtomaStateManager,
+    state: &AtomaState,
     event: StackSettlementTicketEvent,
+    checkpoint_timestamp_ms: Option<i64>,
 ) -> Result<()> {
     info!(
         target = "atoma-state-handlers",
@@ -539,10 +660,18 @@ pub(crate) async fn handle_stack_settlement_ticket_event(
     );
     let stack_small_id = event.stack_small_id.inner as i64;
     let dispute_settled_at_epoch = event.dispute_settled_at_epoch as i64;
-    state_manager
-        .state
+    state
         .settle_stack_settlement_ticket(stack_small_id, dispute_settled_at_epoch)
         .await?;
+    state
+        .apply_chain_stack_status(
+            stack_small_id,
+            StackStatus::Settled,
+            Some(dispute_settled_at_epoch),
+            checkpoint_timestamp_ms,
+            "StackSettlementTicketEvent",
+        )
+        .await?;
     Ok(())
 }
 
@@ -553,8 +682,9 @@ pub(crate) async fn handle_stack_settlement_ticket_event(
 ///
 /// # Arguments
 ///
-/// * `state_manager` - A reference to the `AtomaStateManager` for database operations.
+/// * `state` - A reference to the `AtomaState` for database operations.
 /// * `event` - A `StackSettlementTicketClaimedEvent` containing the details of the stack settlement ticket claimed event.
+/// * `checkpoint_timestamp_ms` - The timestamp of the checkpoint of the event, if known.
 ///
 /// # Returns
 ///
@@ -570,11 +700,13 @@ pub(crate) async fn handle_stack_settlement_ticket_event(
 ///
 /// The function performs the following steps:
 /// 1. Extracts the `stack_small_id` and `user_refund_amount` from the event.
-/// 2. Calls the `update_stack_settlement_ticket_with_claim` method on the `AtomaStateManager` to update the database.
+/// 2. Calls the `update_stack_settlement_ticket_with_claim` method on the `AtomaState` to update the database.
+/// 3. Moves the stack to the `Claimed` status (see `AtomaState::apply_chain_stack_status`).
 #[instrument(level = "info", skip_all)]
 pub(crate) async fn handle_stack_settlement_ticket_claimed_event(
-    state_manager: &AtomaStateManager,
+    state: &AtomaState,
     event: StackSettlementTicketClaimedEvent,
+    checkpoint_timestamp_ms: Option<i64>,
 ) -> Result<()> {
     info!(
         target = "atoma-state-handlers",
@@ -583,10 +715,18 @@ pub(crate) async fn handle_stack_settlement_ticket_claimed_event(
     );
     let stack_small_id = event.stack_small_id.inner as i64;
     let user_refund_amount = event.user_refund_amount as i64;
-    state_manager
-        .state
+    state
         .update_stack_settlement_ticket_with_claim(stack_small_id, user_refund_amount)
         .await?;
+    state
+        .apply_chain_stack_status(
+            stack_small_id,
+            StackStatus::Claimed,
+            None,
+            checkpoint_timestamp_ms,
+            "StackSettlementTicketClaimedEvent",
+        )
+        .await?;
     Ok(())
 }
 
@@ -597,8 +737,9 @@ pub(crate) async fn handle_stack_settlement_ticket_claimed_event(
 ///
 /// # Arguments
 ///
-/// * `state_manager` - A reference to the `AtomaStateManager` for database operations.
+/// * `state` - A reference to the `AtomaState` for database operations.
 /// * `event` - A `StackAttestationDisputeEvent` containing the details of the dispute event.
+/// * `checkpoint_timestamp_ms` - The timestamp of the checkpoint of the event, if known.
 ///
 /// # Returns
 ///
@@ -614,22 +755,33 @@ pub(crate) async fn handle_stack_settlement_ticket_claimed_event(
 ///
 /// The function performs the following steps:
 /// 1. Converts the `StackAttestationDisputeEvent` into a stack attestation dispute object.
-/// 2. Calls the `insert_stack_attestation_dispute` method on the `AtomaStateManager` to insert the dispute into the database.
+/// 2. Calls the `insert_stack_attestation_dispute` method on the `AtomaState` to insert the dispute into the database.
+/// 3. Moves the stack to the `InDispute` status (see `AtomaState::apply_chain_stack_status`).
 #[instrument(level = "info", skip_all)]
 pub(crate) async fn handle_stack_attestation_dispute_event(
-    state_manager: &AtomaStateManager,
+    state: &AtomaState,
     event: StackAttestationDisputeEvent,
+    checkpoint_timestamp_ms: Option<i64>,
 ) -> Result<()> {
     info!(
         target = "atoma-state-handlers",
         event = "handle-stack-attestation-dispute-event",
         "Processing stack attestation dispute event"
     );
+    let stack_small_id = event.stack_small_id.inner as i64;
     let stack_attestation_dispute = event.into();
-    state_manager
-        .state
+    state
         .insert_stack_attestation_dispute(stack_attestation_dispute)
         .await?;
+    state
+        .apply_chain_stack_status(
+            stack_small_id,
+            StackStatus::InDispute,
+            None,
+            checkpoint_timestamp_ms,
+            "StackAttestationDisputeEvent",
+        )
+        .await?;
     Ok(())
 }
 
@@ -659,13 +811,14 @@ pub(crate) async fn handle_stack_attestation_dispute_event(
 /// when processing claimed stack events:
 ///
 /// ```rust,ignore
-/// state_manager.state.update_stack_settlement_ticket_with_claim(stack_small_id, user_refund_amount).await?;
-/// state_manager.state.update_stack_is_claimed(stack_small_id).await?;
+/// state.update_stack_settlement_ticket_with_claim(stack_small_id, user_refund_amount).await?;
+/// state.update_stack_is_claimed(stack_small_id).await?;
 /// ```
 #[instrument(level = "info", skip_all)]
 pub(crate) async fn handle_claimed_stack_event(
-    state_manager: &AtomaStateManager,
+    state: &AtomaState,
     event: ClaimedStackEvent,
+    checkpoint_timestamp_ms: Option<i64>,
 ) -> Result<()> {
     info!(
         target = "atoma-state-handlers",
@@ -679,10 +832,18 @@ pub(crate) async fn handle_claimed_stack_event(
         user_refund_amount,
         ..
     } = event;
-    state_manager
-        .state
+    state
         .update_stack_is_claimed(stack_small_id as i64, user_refund_amount as i64)
         .await?;
+    state
+        .apply_chain_stack_status(
+            stack_small_id as i64,
+            StackStatus::Claimed,
+            None,
+            checkpoint_timestamp_ms,
+            "ClaimedStackEvent",
+        )
+        .await?;
     Ok(())
 }
 
@@ -714,7 +875,15 @@ pub(crate) async fn handle_claimed_stack_event(
 /// 2. For `GetAvailableStackWithComputeUnits`, it retrieves the available stack and sends the result.
 /// 3. For `UpdateStackNumComputeUnits`, it updates the number of compute units for the specified stack.
 /// 4. For `UpdateStackTotalHash`, it updates the total hash for the specified stack.
-#[instrument(level = "info", skip_all)]
+/// 5. For `GetNodeModelTasks`, it retrieves the models the node is subscribed to, with their tasks.
+///    For `GetStack`, it retrieves the stack, if the node knows about it.
+/// 6. For the batch events (`CreateBatch`, `GetBatch`, `GetBatchRequests`, `GetInProgressBatches`,
+///    `GetPendingBatchRequests` and `CompleteBatchRequest`), it performs the corresponding batch
+///    database operation and sends the result back.
+///
+/// The ID of the request an update originates from, if any, is recorded on the span of the
+/// event, so that the update can be traced back to the request.
+#[instrument(level = "info", skip_all, fields(request_id))]
 pub(crate) async fn handle_state_manager_event(
     state_manager: &AtomaStateManager,
     event: AtomaAtomaStateManagerEvent,
@@ -747,13 +916,17 @@ pub(crate) async fn handle_state_manager_event(
             stack_small_id,
             estimated_total_compute_units,
             total_compute_units,
+            num_requests,
             concurrent_requests,
+            request_id,
         } => {
+            Span::current().record("request_id", request_id.as_deref());
             handle_update_stack_num_compute_units_and_claim_funds(
                 state_manager,
                 stack_small_id,
                 estimated_total_compute_units,
                 total_compute_units,
+                num_requests,
                 concurrent_requests,
             )
             .await?;
@@ -761,12 +934,91 @@ pub(crate) async fn handle_state_manager_event(
         AtomaAtomaStateManagerEvent::UpdateStackTotalHash {
             stack_small_id,
             total_hash,
+            request_id,
         } => {
+            Span::current().record("request_id", request_id.as_deref());
             state_manager
                 .state
                 .update_stack_total_hash(stack_small_id, total_hash)
                 .await?;
         }
+        AtomaAtomaStateManagerEvent::GetStack {
+            stack_small_id,
+            result_sender,
+        } => {
+            let result = state_manager
+                .state
+                .get_stacks(&[stack_small_id])
+                .await
+                .map(|stacks| stacks.into_iter().next());
+            result_sender
+                .send(result)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::CreateBatch {
+            batch,
+            requests,
+            result_sender,
+        } => {
+            let result = state_manager.state.insert_new_batch(batch, requests).await;
+            result_sender
+                .send(result)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::GetBatch {
+            batch_id,
+            result_sender,
+        } => {
+            let result = state_manager.state.get_batch(&batch_id).await;
+            result_sender
+                .send(result)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::GetBatchRequests {
+            batch_id,
+            result_sender,
+        } => {
+            let result = state_manager.state.get_batch_requests(&batch_id).await;
+            result_sender
+                .send(result)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::GetNodeModelTasks {
+            node_small_ids,
+            result_sender,
+        } => {
+            let result = state_manager
+                .state
+                .get_node_model_tasks(node_small_ids.as_deref())
+                .await;
+            result_sender
+                .send(result)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::GetInProgressBatches { result_sender } => {
+            let result = state_manager.state.get_in_progress_batches().await;
+            result_sender
+                .send(result)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::GetPendingBatchRequests {
+            limit,
+            result_sender,
+        } => {
+            let result = state_manager.state.get_pending_batch_requests(limit).await;
+            result_sender
+                .send(result)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
+        AtomaAtomaStateManagerEvent::CompleteBatchRequest {
+            request,
+            result_sender,
+        } => {
+            let result = state_manager.state.complete_batch_request(request).await;
+            result_sender
+                .send(result)
+                .map_err(|_| AtomaStateManagerError::ChannelSendError)?;
+        }
     }
     Ok(())
 }
@@ -774,7 +1026,8 @@ pub(crate) async fn handle_state_manager_event(
 /// Handles an update to the number of compute units in a stack.
 ///
 /// This function processes an update to the number of compute units in a stack by parsing the event data
-/// and updating the corresponding stack in the database. If the ratio of compute units is greater than or equal to 95%,
+/// and updating the corresponding stack in the database. The compute units used and the requests served are also added
+/// to the daily usage of the stack, for the earnings reports, unless there are none. If the ratio of compute units is greater than or equal to 95%,
 /// it will submit a claim funds for stacks transaction.
 ///
 /// # Arguments
@@ -783,6 +1036,8 @@ pub(crate) async fn handle_state_manager_event(
 /// * `stack_small_id` - The unique identifier of the stack to be updated.
 /// * `estimated_total_compute_units` - The estimated total number of compute units in the stack.
 /// * `total_compute_units` - The total number of compute units in the stack.
+/// * `num_requests` - The number of requests served with the compute units.
+/// * `concurrent_requests` - The number of requests in flight for the stack.
 ///
 /// # Returns
 ///
@@ -809,6 +1064,7 @@ pub(crate) async fn handle_update_stack_num_compute_units_and_claim_funds(
     stack_small_id: i64,
     estimated_total_compute_units: i64,
     total_compute_units: i64,
+    num_requests: i64,
     concurrent_requests: u64,
 ) -> Result<()> {
     info!(
@@ -837,6 +1093,27 @@ pub(crate) async fn handle_update_stack_num_compute_units_and_claim_funds(
         "Stack {} has ratio {} with total compute units {} confidential state {} and is locked for claim {}",
         stack_small_id, ratio, total_compute_units, is_confidential, is_locked_for_claim
     );
+    // NOTE: Usage is only recorded for reporting, so failing to record it must not prevent
+    // the stack from being updated and its funds from being claimed
+    if num_requests > 0 || total_compute_units > 0 {
+        if let Err(e) = state_manager
+            .state
+            .record_stack_usage(stack_small_id, now_ms(), num_requests, total_compute_units)
+            .await
+        {
+            tracing::error!(
+                target = "atoma-state-handlers",
+                event = "handle-update-stack-num-compute-units-and-claim-funds",
+                "Failed to record usage of stack {} with total compute units {}, with error {}",
+                stack_small_id,
+                total_compute_units,
+                e
+            );
+        }
+    }
+    if is_confidential {
+        update_stack_lock_status(state_manager, stack_small_id, is_locked_for_claim).await?;
+    }
     if is_confidential && ratio >= RATIO_FOR_CLAIM_STACK_THRESHOLD && concurrent_requests == 0 {
         info!(
             target = "atoma-state-handlers",
@@ -868,6 +1145,32 @@ pub(crate) async fn handle_update_stack_num_compute_units_and_claim_funds(
     Ok(())
 }
 
+/// Moves a confidential stack between the `Active` and `LockedForClaim` statuses, following the
+/// lock computed when updating its number of compute units.
+///
+/// Stacks which are past these statuses (e.g. settling) are left unchanged.
+async fn update_stack_lock_status(
+    state_manager: &AtomaStateManager,
+    stack_small_id: i64,
+    is_locked_for_claim: bool,
+) -> Result<()> {
+    let to_status = match state_manager.state.get_stack_status(stack_small_id).await? {
+        Some(StackStatus::Active) if is_locked_for_claim => StackStatus::LockedForClaim,
+        Some(StackStatus::LockedForClaim) if !is_locked_for_claim => StackStatus::Active,
+        _ => return Ok(()),
+    };
+    state_manager
+        .state
+        .transition_stack_status(
+            stack_small_id,
+            to_status,
+            None,
+            Some(now_ms()),
+            "UpdateStackNumComputeUnits",
+        )
+        .await
+}
+
 /// Handles a p2p event.
 ///
 /// This function processes a p2p event by parsing the event data
@@ -914,7 +1217,7 @@ pub(crate) async fn handle_p2p_event(
 ///
 /// # Arguments
 ///
-/// * `state_manager` - A reference to the `AtomaStateManager` for database operations.
+/// * `state` - A reference to the `AtomaState` for database operations.
 /// * `event` - A `NodePublicKeyCommittmentEvent` containing the details of the key rotation event.
 ///
 /// # Returns
@@ -930,10 +1233,10 @@ pub(crate) async fn handle_p2p_event(
 ///
 /// The function performs the following steps:
 /// 1. Extracts the `epoch`, `node_id`, `new_public_key`, and `tee_remote_attestation_bytes` from the event.
-/// 2. Calls the `insert_node_public_key_rotation` method on the `AtomaStateManager` to update the node's public key in the database.
+/// 2. Calls the `insert_node_public_key_rotation` method on the `AtomaState` to update the node's public key in the database.
 #[instrument(level = "info", skip_all)]
 async fn handle_node_key_rotation_event(
-    state_manager: &AtomaStateManager,
+    state: &AtomaState,
     event: NodePublicKeyCommittmentEvent,
 ) -> Result<()> {
     info!(
@@ -949,8 +1252,7 @@ async fn handle_node_key_rotation_event(
         evidence_bytes,
         device_type,
     } = event;
-    state_manager
-        .state
+    state
         .insert_node_public_key_rotation(
             epoch,
             key_rotation_counter,
@@ -1027,7 +1329,7 @@ async fn handle_node_small_id_ownership_verification_event(
 ///
 /// # Arguments
 ///
-/// * `state_manager` - A reference to the `AtomaStateManager` that provides database operations
+/// * `state` - A reference to the `AtomaState` that provides database operations
 /// * `event` - A `NodeRegisteredEvent` containing the node's registration details:
 ///   * `node_small_id` - Compact identifier for the node
 ///   * `badge_id` - Identifier representing the node's capabilities/permissions
@@ -1046,10 +1348,10 @@ async fn handle_node_small_id_ownership_verification_event(
 /// # Example
 ///
 /// ```rust,ignore
-/// use atoma_state::AtomaStateManager;
+/// use atoma_state::AtomaState;
 /// use atoma_sui::events::NodeRegisteredEvent;
 ///
-/// async fn example(state_manager: &AtomaStateManager) {
+/// async fn example(state: &AtomaState) {
 ///     let event = NodeRegisteredEvent {
 ///         node_small_id: /* ... */,
 ///         badge_id: /* ... */
@@ -1057,7 +1359,7 @@ async fn handle_node_small_id_ownership_verification_event(
 ///     let node_sui_address = "0x123...".to_string();
 ///     
 ///     handle_node_registered_event(
-///         state_manager,
+///         state,
 ///         event,
 ///         node_sui_address
 ///     ).await.expect("Failed to handle node registration");
@@ -1073,7 +1375,7 @@ async fn handle_node_small_id_ownership_verification_event(
 /// 4. Inserts the registration record into the database via the state manager
 #[instrument(level = "info", skip_all)]
 async fn handle_node_registered_event(
-    state_manager: &AtomaStateManager,
+    state: &AtomaState,
     event: NodeRegisteredEvent,
     node_sui_address: String,
 ) -> Result<()> {
@@ -1086,8 +1388,7 @@ async fn handle_node_registered_event(
         node_small_id,
         badge_id,
     } = event;
-    state_manager
-        .state
+    state
         .insert_node_registration_event(node_small_id.inner as i64, badge_id, node_sui_address)
         .await?;
     Ok(())
@@ -7,10 +7,10 @@
 pub mod config;
 pub mod handlers;
 pub mod state_manager;
+mod storage;
 pub mod types;
 
 pub use config::AtomaStateManagerConfig;
-use sqlx::Postgres;
 pub use state_manager::{AtomaState, AtomaStateManager, AtomaStateManagerError};
 
 /// Builds a query with an IN clause and optional additional conditions
@@ -23,12 +23,17 @@ pub use state_manager::{AtomaState, AtomaStateManager, AtomaStateManagerError};
 ///
 /// # Returns
 /// A QueryBuilder configured with the IN clause and ready for additional bindings
-pub(crate) fn build_query_with_in<'a, T: sqlx::Type<Postgres> + sqlx::Encode<'a, Postgres>>(
+pub(crate) fn build_query_with_in<'a, DB, T>(
     base_query: &str,
     column: &str,
     values: &'a [T],
     additional_conditions: Option<&str>,
-) -> sqlx::QueryBuilder<'a, Postgres> {
+) -> sqlx::QueryBuilder<'a, DB>
+where
+    DB: sqlx::Database,
+    DB::Arguments<'a>: Default,
+    T: sqlx::Type<DB> + sqlx::Encode<'a, DB>,
+{
     let mut builder = sqlx::QueryBuilder::new(base_query);
 
     if values.is_empty() {
@@ -0,0 +1,36 @@
+-- Create batches table, holding asynchronous batch inference jobs submitted against a stack
+CREATE TABLE IF NOT EXISTS batches (
+    batch_id               TEXT    PRIMARY KEY,
+    stack_small_id         BIGINT  NOT NULL,
+    owner_address          TEXT    NOT NULL,
+    endpoint               TEXT    NOT NULL,
+    status                 TEXT    NOT NULL,
+    reserved_compute_units BIGINT  NOT NULL,
+    used_compute_units     BIGINT  NOT NULL DEFAULT 0,
+    total_requests         BIGINT  NOT NULL,
+    completed_requests     BIGINT  NOT NULL DEFAULT 0,
+    failed_requests        BIGINT  NOT NULL DEFAULT 0,
+    created_at             BIGINT  NOT NULL,
+    completed_at           BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS idx_batches_status_created_at
+    ON batches (status, created_at);
+
+-- Create batch_requests table, holding each line of a batch together with its result
+CREATE TABLE IF NOT EXISTS batch_requests (
+    batch_id                TEXT    NOT NULL,
+    line_index              BIGINT  NOT NULL,
+    custom_id               TEXT    NOT NULL,
+    body                    TEXT    NOT NULL,
+    estimated_compute_units BIGINT  NOT NULL,
+    used_compute_units      BIGINT  NOT NULL DEFAULT 0,
+    status                  TEXT    NOT NULL,
+    status_code             BIGINT,
+    response                TEXT,
+    error                   TEXT,
+    PRIMARY KEY (batch_id, line_index)
+);
+
+CREATE INDEX IF NOT EXISTS idx_batch_requests_status
+    ON batch_requests (status, batch_id, line_index);
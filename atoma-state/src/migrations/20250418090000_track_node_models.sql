@@ -0,0 +1,13 @@
+-- Track the models each node subscribed to on-chain (`NodeSubscribedToModelEvent`)
+CREATE TABLE IF NOT EXISTS node_model_subscriptions (
+    node_small_id BIGINT NOT NULL,
+    model_name    TEXT   NOT NULL,
+    echelon_id    BIGINT NOT NULL,
+    PRIMARY KEY (node_small_id, model_name)
+);
+
+-- Track tasks permanently removed from the network (`TaskRemovedEvent`)
+ALTER TABLE tasks ADD COLUMN IF NOT EXISTS removed_at_epoch BIGINT;
+
+CREATE INDEX IF NOT EXISTS idx_tasks_model_name
+    ON tasks (model_name);
@@ -0,0 +1,12 @@
+-- Number of requests in flight per stack, for each Atoma service replica sharing the database.
+-- In-progress batches are counted under a shared owner, as a batch can be submitted to a replica
+-- and processed by another one.
+-- Each replica refreshes `updated_at` (in seconds since the Unix epoch) of its counts on a timer,
+-- so that the counts left by a replica that disappeared expire once their lease runs out.
+CREATE TABLE IF NOT EXISTS concurrent_requests (
+    owner          TEXT   NOT NULL,
+    stack_small_id BIGINT NOT NULL,
+    num_requests   BIGINT NOT NULL,
+    updated_at     BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (owner, stack_small_id)
+);
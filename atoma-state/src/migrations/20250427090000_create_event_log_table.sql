@@ -0,0 +1,13 @@
+-- Append-only log of the events received from the Sui subscriber, recorded before being applied
+-- to the other tables, so that these can be rebuilt by replaying the log
+CREATE TABLE IF NOT EXISTS event_log (
+    id                      BIGSERIAL PRIMARY KEY,
+    tx_digest               TEXT      NOT NULL,
+    event_seq               BIGINT    NOT NULL,
+    event_name              TEXT      NOT NULL,
+    checkpoint_timestamp_ms BIGINT,
+    event                   TEXT      NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_event_log_tx_digest_event_seq
+    ON event_log (tx_digest, event_seq);
@@ -0,0 +1,27 @@
+-- Events applied to the state, so that events delivered again (e.g. after the cursor file is
+-- rolled back) are not applied twice
+CREATE TABLE IF NOT EXISTS applied_events (
+    tx_digest  TEXT   NOT NULL,
+    event_seq  BIGINT NOT NULL,
+    event_name TEXT   NOT NULL,
+    PRIMARY KEY (tx_digest, event_seq, event_name)
+);
+
+-- Events whose handler failed, retried periodically by the state manager. The later events of
+-- the object (task, node or stack) an event applies to are held back while it is dead-lettered
+CREATE TABLE IF NOT EXISTS dead_letter_events (
+    tx_digest    TEXT   NOT NULL,
+    event_seq    BIGINT NOT NULL,
+    event_name   TEXT   NOT NULL,
+    event_id     BIGINT NOT NULL,
+    object_key   TEXT,
+    error        TEXT   NOT NULL,
+    num_attempts BIGINT NOT NULL,
+    PRIMARY KEY (tx_digest, event_seq, event_name)
+);
+
+CREATE INDEX IF NOT EXISTS idx_dead_letter_events_event_id
+    ON dead_letter_events (event_id);
+
+CREATE INDEX IF NOT EXISTS idx_dead_letter_events_object_key
+    ON dead_letter_events (object_key, event_id);
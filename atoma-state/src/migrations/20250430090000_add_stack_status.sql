@@ -0,0 +1,44 @@
+-- Lifecycle status of the stacks (`Active`, `LockedForClaim`, `Settling`, `InDispute`, `Settled`
+-- or `Claimed`), whose transitions are validated by the state, backfilled from the flags of the
+-- stacks and of their settlement tickets
+ALTER TABLE stacks ADD COLUMN status TEXT NOT NULL DEFAULT 'Active';
+
+UPDATE stacks SET status = CASE
+    WHEN is_claimed OR EXISTS (
+        SELECT 1 FROM stack_settlement_tickets t
+        WHERE t.stack_small_id = stacks.stack_small_id AND t.is_claimed
+    ) THEN 'Claimed'
+    WHEN EXISTS (
+        SELECT 1 FROM stack_settlement_tickets t
+        WHERE t.stack_small_id = stacks.stack_small_id AND t.dispute_settled_at_epoch IS NOT NULL
+    ) THEN 'Settled'
+    WHEN EXISTS (
+        SELECT 1 FROM stack_attestation_disputes d
+        WHERE d.stack_small_id = stacks.stack_small_id
+    ) OR EXISTS (
+        SELECT 1 FROM stack_settlement_tickets t
+        WHERE t.stack_small_id = stacks.stack_small_id AND t.is_in_dispute
+    ) THEN 'InDispute'
+    WHEN in_settle_period THEN 'Settling'
+    WHEN is_locked_for_claim THEN 'LockedForClaim'
+    ELSE 'Active'
+END;
+
+-- Only claimed stacks are archived
+ALTER TABLE archived_stacks ADD COLUMN status TEXT NOT NULL DEFAULT 'Claimed';
+
+-- History of the status transitions of the stacks, with the event that triggered them and their
+-- time, in milliseconds since the Unix epoch: the timestamp of the checkpoint of the triggering
+-- Sui event, or the time the node applied the transition
+CREATE TABLE IF NOT EXISTS stack_status_transitions (
+    id               BIGSERIAL PRIMARY KEY,
+    stack_small_id   BIGINT  NOT NULL,
+    from_status      TEXT    NOT NULL,
+    to_status        TEXT    NOT NULL,
+    epoch            BIGINT,
+    timestamp_ms     BIGINT,
+    triggering_event TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_stack_status_transitions_stack_small_id
+    ON stack_status_transitions (stack_small_id);
@@ -0,0 +1,157 @@
+-- SQLite schema of the Atoma state, matching the one the Postgres migrations (in `../migrations`)
+-- lead to. New migrations must be added to both directories.
+
+-- Create tasks table
+CREATE TABLE IF NOT EXISTS tasks (
+    task_small_id            BIGINT  PRIMARY KEY,
+    task_id                  TEXT    UNIQUE NOT NULL,
+    role                     BIGINT  NOT NULL,
+    model_name               TEXT,
+    is_deprecated            BOOLEAN NOT NULL,
+    valid_until_epoch        BIGINT,
+    deprecated_at_epoch      BIGINT,
+    security_level           BIGINT  NOT NULL,
+    minimum_reputation_score BIGINT,
+    removed_at_epoch         BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS idx_tasks_model_name
+    ON tasks (model_name);
+
+-- Create node_subscriptions table
+CREATE TABLE IF NOT EXISTS node_subscriptions (
+    task_small_id                       BIGINT  NOT NULL,
+    node_small_id                       BIGINT  NOT NULL,
+    price_per_one_million_compute_units BIGINT  NOT NULL,
+    max_num_compute_units               BIGINT  NOT NULL,
+    valid                               BOOLEAN NOT NULL,
+    PRIMARY KEY (task_small_id, node_small_id)
+);
+
+-- Create node_model_subscriptions table
+CREATE TABLE IF NOT EXISTS node_model_subscriptions (
+    node_small_id BIGINT NOT NULL,
+    model_name    TEXT   NOT NULL,
+    echelon_id    BIGINT NOT NULL,
+    PRIMARY KEY (node_small_id, model_name)
+);
+
+-- Create nodes table
+CREATE TABLE IF NOT EXISTS nodes (
+    node_small_id    BIGINT PRIMARY KEY,
+    node_id          TEXT   NOT NULL,
+    node_sui_address TEXT   NOT NULL
+);
+
+-- Create node_public_key_rotations table
+CREATE TABLE IF NOT EXISTS node_public_key_rotations (
+    node_small_id        BIGINT  NOT NULL,
+    public_key_bytes     BLOB    UNIQUE NOT NULL,
+    evidence_data_bytes  BLOB    NOT NULL,
+    epoch                BIGINT  NOT NULL,
+    key_rotation_counter BIGINT  NOT NULL DEFAULT 0,
+    device_type          INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (node_small_id, device_type)
+);
+
+-- Create stacks table
+CREATE TABLE IF NOT EXISTS stacks (
+    stack_small_id                      BIGINT  PRIMARY KEY,
+    owner_address                       TEXT    NOT NULL,
+    stack_id                            TEXT    UNIQUE NOT NULL,
+    task_small_id                       BIGINT  NOT NULL,
+    selected_node_id                    BIGINT  NOT NULL,
+    num_compute_units                   BIGINT  NOT NULL,
+    price_per_one_million_compute_units BIGINT  NOT NULL,
+    already_computed_units              BIGINT  NOT NULL,
+    in_settle_period                    BOOLEAN NOT NULL,
+    total_hash                          BLOB    NOT NULL,
+    num_total_messages                  BIGINT  NOT NULL,
+    is_claimed                          BOOLEAN NOT NULL DEFAULT FALSE,
+    user_refund_amount                  BIGINT,
+    is_confidential                     BOOLEAN NOT NULL DEFAULT FALSE,
+    is_locked_for_claim                 BOOLEAN NOT NULL DEFAULT FALSE,
+    CONSTRAINT check_compute_units CHECK (already_computed_units <= num_compute_units)
+);
+
+CREATE INDEX IF NOT EXISTS idx_stacks_owner_address
+    ON stacks (owner_address);
+
+CREATE INDEX IF NOT EXISTS idx_stacks_availability_check
+    ON stacks (stack_small_id, owner_address, in_settle_period, is_claimed, is_locked_for_claim);
+
+-- Create stack_attestation_disputes table
+CREATE TABLE IF NOT EXISTS stack_attestation_disputes (
+    stack_small_id         BIGINT NOT NULL,
+    attestation_commitment BLOB   NOT NULL,
+    attestation_node_id    BIGINT NOT NULL,
+    original_node_id       BIGINT NOT NULL,
+    original_commitment    BLOB   NOT NULL,
+    PRIMARY KEY (stack_small_id, attestation_node_id)
+);
+
+-- Create stack_settlement_tickets table
+CREATE TABLE IF NOT EXISTS stack_settlement_tickets (
+    stack_small_id              BIGINT  NOT NULL,
+    selected_node_id            BIGINT  NOT NULL,
+    num_claimed_compute_units   BIGINT  NOT NULL,
+    requested_attestation_nodes TEXT    NOT NULL,
+    committed_stack_proofs      BLOB    NOT NULL,
+    stack_merkle_leaves         BLOB    NOT NULL,
+    dispute_settled_at_epoch    BIGINT,
+    already_attested_nodes      TEXT    NOT NULL,
+    is_in_dispute               BOOLEAN NOT NULL,
+    user_refund_amount          BIGINT  NOT NULL,
+    is_claimed                  BOOLEAN NOT NULL,
+    PRIMARY KEY (stack_small_id, selected_node_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_stack_settlement_tickets_stack_small_id
+    ON stack_settlement_tickets (stack_small_id);
+
+-- Create batches table, holding asynchronous batch inference jobs submitted against a stack
+CREATE TABLE IF NOT EXISTS batches (
+    batch_id               TEXT    PRIMARY KEY,
+    stack_small_id         BIGINT  NOT NULL,
+    owner_address          TEXT    NOT NULL,
+    endpoint               TEXT    NOT NULL,
+    status                 TEXT    NOT NULL,
+    reserved_compute_units BIGINT  NOT NULL,
+    used_compute_units     BIGINT  NOT NULL DEFAULT 0,
+    total_requests         BIGINT  NOT NULL,
+    completed_requests     BIGINT  NOT NULL DEFAULT 0,
+    failed_requests        BIGINT  NOT NULL DEFAULT 0,
+    created_at             BIGINT  NOT NULL,
+    completed_at           BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS idx_batches_status_created_at
+    ON batches (status, created_at);
+
+-- Create batch_requests table, holding each line of a batch together with its result
+CREATE TABLE IF NOT EXISTS batch_requests (
+    batch_id                TEXT    NOT NULL,
+    line_index              BIGINT  NOT NULL,
+    custom_id               TEXT    NOT NULL,
+    body                    TEXT    NOT NULL,
+    estimated_compute_units BIGINT  NOT NULL,
+    used_compute_units      BIGINT  NOT NULL DEFAULT 0,
+    status                  TEXT    NOT NULL,
+    status_code             BIGINT,
+    response                TEXT,
+    error                   TEXT,
+    PRIMARY KEY (batch_id, line_index)
+);
+
+CREATE INDEX IF NOT EXISTS idx_batch_requests_status
+    ON batch_requests (status, batch_id, line_index);
+
+-- Number of requests in flight per stack. SQLite databases are not shared between replicas, so
+-- there is a single owner, besides the shared one of in-progress batches.
+CREATE TABLE IF NOT EXISTS concurrent_requests (
+    owner          TEXT   NOT NULL,
+    stack_small_id BIGINT NOT NULL,
+    num_requests   BIGINT NOT NULL,
+    updated_at     BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (owner, stack_small_id)
+);
@@ -0,0 +1,50 @@
+-- Archive of the stacks claimed and settled for longer than the retention period, moved out of
+-- the `stacks`, `stack_settlement_tickets` and `stack_attestation_disputes` tables by the
+-- retention job of the state manager
+CREATE TABLE IF NOT EXISTS archived_stacks (
+    stack_small_id                      BIGINT  PRIMARY KEY,
+    owner_address                       TEXT    NOT NULL,
+    stack_id                            TEXT    NOT NULL,
+    task_small_id                       BIGINT  NOT NULL,
+    selected_node_id                    BIGINT  NOT NULL,
+    num_compute_units                   BIGINT  NOT NULL,
+    price_per_one_million_compute_units BIGINT  NOT NULL,
+    already_computed_units              BIGINT  NOT NULL,
+    in_settle_period                    BOOLEAN NOT NULL,
+    total_hash                          BLOB    NOT NULL,
+    num_total_messages                  BIGINT  NOT NULL,
+    is_claimed                          BOOLEAN NOT NULL,
+    user_refund_amount                  BIGINT,
+    is_confidential                     BOOLEAN NOT NULL,
+    is_locked_for_claim                 BOOLEAN NOT NULL,
+    archived_at_epoch                   BIGINT  NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_archived_stacks_selected_node_id
+    ON archived_stacks (selected_node_id);
+
+CREATE TABLE IF NOT EXISTS archived_stack_settlement_tickets (
+    stack_small_id              BIGINT  NOT NULL,
+    selected_node_id            BIGINT  NOT NULL,
+    num_claimed_compute_units   BIGINT  NOT NULL,
+    requested_attestation_nodes TEXT    NOT NULL,
+    committed_stack_proofs      BLOB    NOT NULL,
+    stack_merkle_leaves         BLOB    NOT NULL,
+    dispute_settled_at_epoch    BIGINT,
+    already_attested_nodes      TEXT    NOT NULL,
+    is_in_dispute               BOOLEAN NOT NULL,
+    user_refund_amount          BIGINT  NOT NULL,
+    is_claimed                  BOOLEAN NOT NULL,
+    archived_at_epoch           BIGINT  NOT NULL,
+    PRIMARY KEY (stack_small_id, selected_node_id)
+);
+
+CREATE TABLE IF NOT EXISTS archived_stack_attestation_disputes (
+    stack_small_id         BIGINT NOT NULL,
+    attestation_commitment BLOB   NOT NULL,
+    attestation_node_id    BIGINT NOT NULL,
+    original_node_id       BIGINT NOT NULL,
+    original_commitment    BLOB   NOT NULL,
+    archived_at_epoch      BIGINT NOT NULL,
+    PRIMARY KEY (stack_small_id, attestation_node_id)
+);
@@ -0,0 +1,8 @@
+-- Compute units used by the requests served on each stack, per day (UTC, `YYYY-MM-DD`)
+CREATE TABLE IF NOT EXISTS stack_usage (
+    stack_small_id BIGINT NOT NULL,
+    day            TEXT   NOT NULL,
+    num_requests   BIGINT NOT NULL,
+    compute_units  BIGINT NOT NULL,
+    PRIMARY KEY (stack_small_id, day)
+);
@@ -1,48 +1,61 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use crate::build_query_with_in;
-use crate::handlers::{handle_atoma_event, handle_p2p_event, handle_state_manager_event};
+use crate::config::{DeadLetterConfig, RetentionConfig};
+use crate::handlers::{
+    atoma_event_object_key, handle_atoma_event, handle_p2p_event, handle_state_manager_event,
+};
+use crate::storage::{dispatch, postgres::PostgresStorage, StateStorage, Storage};
 use crate::types::{
-    AtomaAtomaStateManagerEvent, Node, NodeSubscription, Stack, StackAttestationDispute,
-    StackAvailability, StackSettlementTicket, Task, UpdateStackNumComputeUnitsAndClaimFunds,
+    ArchivedStack, AtomaAtomaStateManagerEvent, Batch, BatchRequest, ConcurrentRequestsCount,
+    EarningsReportRow, LoggedEvent, ModelTasks, Node, NodeSubscription, Stack,
+    StackAttestationDispute, StackAvailability, StackSettlementTicket, StackStatus,
+    StackStatusTransition, Task, UpdateStackNumComputeUnitsAndClaimFunds,
 };
 
 use atoma_p2p::types::AtomaP2pEvent;
 use atoma_sui::client::Client;
-use atoma_sui::events::AtomaEvent;
+use atoma_sui::events::{AtomaEvent, AtomaEventMetadata};
 use flume::Receiver as FlumeReceiver;
+use sqlx::postgres::PgListener;
 use sqlx::PgPool;
-use sqlx::{FromRow, Row};
 use thiserror::Error;
 use tokio::sync::watch::Receiver;
 use tokio::sync::{oneshot, RwLock};
+use tokio::time::MissedTickBehavior;
 
 pub(crate) type Result<T> = std::result::Result<T, AtomaStateManagerError>;
 
-/// AtomaStateManager is a wrapper around a Postgres connection pool, responsible for managing the state of the Atoma system.
+/// The number of events read at once from the event log, when rebuilding the state.
+const EVENT_LOG_PAGE_SIZE: i64 = 1_000;
+
+/// AtomaStateManager is a wrapper around the Atoma state, responsible for managing the state of the Atoma system.
 ///
-/// It provides an interface to interact with the Postgres database, handling operations
+/// It provides an interface to interact with the database, handling operations
 /// related to tasks, node subscriptions, stacks, and various other system components.
 pub struct AtomaStateManager {
-    /// The Postgres connection pool used for database operations.
+    /// The Atoma state, used for database operations.
     pub state: AtomaState,
     /// The Sui blockchain client to interact with the Atoma smart contract
     pub client: Arc<RwLock<Client>>,
     /// Receiver channel from the SuiEventSubscriber
-    pub event_subscriber_receiver: FlumeReceiver<AtomaEvent>,
+    pub event_subscriber_receiver: FlumeReceiver<(AtomaEvent, AtomaEventMetadata)>,
     /// Atoma service receiver
     pub state_manager_receiver: FlumeReceiver<AtomaAtomaStateManagerEvent>,
     /// Atoma p2p service receiver
     pub p2p_service_receiver: FlumeReceiver<(AtomaP2pEvent, Option<oneshot::Sender<bool>>)>,
+    /// Configuration for the retries of the events which failed to be applied
+    pub dead_letter: DeadLetterConfig,
+    /// Configuration for the archiving of the claimed and settled stacks
+    pub retention: RetentionConfig,
 }
 
 impl AtomaStateManager {
     /// Constructor
     #[must_use]
-    pub const fn new(
+    pub fn new(
         db: PgPool,
         client: Arc<RwLock<Client>>,
-        event_subscriber_receiver: FlumeReceiver<AtomaEvent>,
+        event_subscriber_receiver: FlumeReceiver<(AtomaEvent, AtomaEventMetadata)>,
         state_manager_receiver: FlumeReceiver<AtomaAtomaStateManagerEvent>,
         p2p_service_receiver: FlumeReceiver<(AtomaP2pEvent, Option<oneshot::Sender<bool>>)>,
     ) -> Self {
@@ -52,16 +65,19 @@ impl AtomaStateManager {
             event_subscriber_receiver,
             state_manager_receiver,
             p2p_service_receiver,
+            dead_letter: DeadLetterConfig::default(),
+            retention: RetentionConfig::default(),
         }
     }
 
     /// Creates a new `AtomaStateManager` instance from a database URL.
     ///
-    /// This method establishes a connection to the Postgres database using the provided URL,
-    /// creates all necessary tables in the database, and returns a new `AtomaStateManager` instance.
+    /// This method establishes a connection to the database using the provided URL (Postgres,
+    /// or SQLite with the `sqlite` feature), creates all necessary tables in the database, and
+    /// returns a new `AtomaStateManager` instance.
     ///
     /// # Arguments
-    /// * `database_url` - The URL of the PostgreSQL database to connect to
+    /// * `database_url` - The URL of the database to connect to
     /// * `event_subscriber_receiver` - Channel receiver for Atoma events
     /// * `state_manager_receiver` - Channel receiver for state manager events
     ///
@@ -76,26 +92,41 @@ impl AtomaStateManager {
     pub async fn new_from_url(
         database_url: &str,
         client: Arc<RwLock<Client>>,
-        event_subscriber_receiver: FlumeReceiver<AtomaEvent>,
+        event_subscriber_receiver: FlumeReceiver<(AtomaEvent, AtomaEventMetadata)>,
         state_manager_receiver: FlumeReceiver<AtomaAtomaStateManagerEvent>,
         p2p_service_receiver: FlumeReceiver<(AtomaP2pEvent, Option<oneshot::Sender<bool>>)>,
     ) -> Result<Self> {
-        // Create connection options with create_if_missing enabled
-        let db = PgPool::connect(database_url).await?;
-        sqlx::migrate!("./src/migrations").run(&db).await?;
         Ok(Self {
-            state: AtomaState::new(db),
+            state: AtomaState::new_from_url(database_url).await?,
             client,
             event_subscriber_receiver,
             state_manager_receiver,
             p2p_service_receiver,
+            dead_letter: DeadLetterConfig::default(),
+            retention: RetentionConfig::default(),
         })
     }
 
+    /// Sets the configuration for the retries of the events which failed to be applied.
+    #[must_use]
+    pub fn with_dead_letter_config(mut self, dead_letter: DeadLetterConfig) -> Self {
+        self.dead_letter = dead_letter;
+        self
+    }
+
+    /// Sets the configuration for the archiving of the claimed and settled stacks.
+    #[must_use]
+    pub fn with_retention_config(mut self, retention: RetentionConfig) -> Self {
+        self.retention = retention;
+        self
+    }
+
     /// Runs the state manager, listening for events from the event subscriber and state manager receivers.
     ///
     /// This method continuously processes incoming events from the event subscriber and state manager receivers
     /// until a shutdown signal is received. It uses asynchronous select to handle multiple event sources concurrently.
+    /// Before returning, the events still pending in the receivers are flushed, so that no event
+    /// (e.g. the final compute units or stack hash update of a request) is lost on shutdown.
     ///
     /// # Arguments
     ///
@@ -122,17 +153,23 @@ impl AtomaStateManager {
     /// ```
     #[tracing::instrument(level = "trace", skip_all)]
     pub async fn run(self, mut shutdown_signal: Receiver<bool>) -> Result<()> {
+        let mut dead_letter_retry_interval =
+            tokio::time::interval(Duration::from_millis(self.dead_letter.retry_interval_ms));
+        dead_letter_retry_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut retention_interval =
+            tokio::time::interval(Duration::from_millis(self.retention.interval_ms));
+        retention_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
         loop {
             tokio::select! {
                 atoma_event = self.event_subscriber_receiver.recv_async() => {
                     match atoma_event {
-                        Ok(atoma_event) => {
+                        Ok((atoma_event, metadata)) => {
                             tracing::trace!(
                                 target = "atoma-state-manager",
                                 event = "event_subscriber_receiver",
                                 "Event received from event subscriber receiver"
                             );
-                            handle_atoma_event(atoma_event, &self).await?;
+                            self.log_and_handle_atoma_event(atoma_event, &metadata).await?;
                         }
                         Err(e) => {
                             tracing::error!(
@@ -198,6 +235,26 @@ impl AtomaStateManager {
                         }
                     }
                 }
+                _ = dead_letter_retry_interval.tick() => {
+                    if let Err(e) = self.retry_dead_letter_events().await {
+                        tracing::error!(
+                            target = "atoma-state-manager",
+                            event = "dead_letter_retry_error",
+                            error = %e,
+                            "Error retrying dead-letter events"
+                        );
+                    }
+                }
+                _ = retention_interval.tick(), if self.retention.enabled => {
+                    if let Err(e) = self.archive_settled_stacks().await {
+                        tracing::error!(
+                            target = "atoma-state-manager",
+                            event = "archive_settled_stacks_error",
+                            error = %e,
+                            "Error archiving settled stacks"
+                        );
+                    }
+                }
                 shutdown_signal_changed = shutdown_signal.changed() => {
                     match shutdown_signal_changed {
                         Ok(()) => {
@@ -224,15 +281,191 @@ impl AtomaStateManager {
                 }
             }
         }
+        self.flush_pending_events().await;
+        Ok(())
+    }
+
+    /// Handles the events still pending in the receivers, without waiting for new ones.
+    ///
+    /// Errors are logged rather than returned, so that a failing event does not prevent the
+    /// remaining ones from being flushed.
+    async fn flush_pending_events(&self) {
+        let mut num_flushed_events = 0;
+        while let Ok(state_manager_event) = self.state_manager_receiver.try_recv() {
+            num_flushed_events += 1;
+            if let Err(e) = handle_state_manager_event(self, state_manager_event).await {
+                tracing::error!(
+                    target = "atoma-state-manager",
+                    event = "state_manager_event_error",
+                    error = %e,
+                    "Error handling state manager event while flushing"
+                );
+            }
+        }
+        while let Ok((atoma_event, metadata)) = self.event_subscriber_receiver.try_recv() {
+            num_flushed_events += 1;
+            if let Err(e) = self
+                .log_and_handle_atoma_event(atoma_event, &metadata)
+                .await
+            {
+                tracing::error!(
+                    target = "atoma-state-manager",
+                    event = "event_subscriber_event_error",
+                    error = %e,
+                    "Error handling event subscriber event while flushing"
+                );
+            }
+        }
+        while let Ok(p2p_event) = self.p2p_service_receiver.try_recv() {
+            num_flushed_events += 1;
+            if let (p2p_event, Some(sender)) = p2p_event {
+                if let Err(e) = handle_p2p_event(self, p2p_event, sender).await {
+                    tracing::error!(
+                        target = "atoma-state-manager",
+                        event = "p2p_event_error",
+                        error = %e,
+                        "Error handling p2p event while flushing"
+                    );
+                }
+            }
+        }
+        tracing::info!(
+            target = "atoma-state-manager",
+            event = "state_manager_flushed",
+            num_flushed_events,
+            "Flushed pending events, shutting down the state manager"
+        );
+    }
+
+    /// Records an Atoma event in the event log, and then applies it to the state.
+    async fn log_and_handle_atoma_event(
+        &self,
+        atoma_event: AtomaEvent,
+        metadata: &AtomaEventMetadata,
+    ) -> Result<()> {
+        let logged_event = self.state.insert_event(&atoma_event, metadata).await?;
+        self.state
+            .apply_logged_event(&logged_event, atoma_event)
+            .await?;
         Ok(())
     }
+
+    /// Retries to apply the dead-letter events which have attempts left, in the order they were
+    /// received (see [`AtomaState::retry_dead_letter_events`]).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database queries fail to execute.
+    /// - A dead-letter event cannot be deserialized.
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub async fn retry_dead_letter_events(&self) -> Result<()> {
+        self.state
+            .retry_dead_letter_events(self.dead_letter.max_attempts as i64)
+            .await
+    }
+
+    /// Archives the stacks claimed and whose disputes were settled more than
+    /// `retention_epochs` epochs ago, according to the retention configuration.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<u64>`: The number of stacks archived.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The current epoch cannot be retrieved from the Sui RPC node.
+    /// - The database queries fail to execute.
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub async fn archive_settled_stacks(&self) -> Result<u64> {
+        let current_epoch = self.client.read().await.get_current_epoch().await?;
+        let max_settled_epoch = current_epoch.saturating_sub(self.retention.retention_epochs);
+        let num_archived_stacks = self
+            .state
+            .archive_settled_stacks(max_settled_epoch as i64, current_epoch as i64)
+            .await?;
+        if num_archived_stacks > 0 {
+            tracing::info!(
+                target = "atoma-state-manager",
+                event = "settled_stacks_archived",
+                num_archived_stacks,
+                current_epoch,
+                "Archived settled stacks"
+            );
+        }
+        Ok(num_archived_stacks)
+    }
+
+    /// Rebuilds the state from scratch, by replaying the event log with the current handlers.
+    ///
+    /// The tables derived from the events of the Atoma contract (tasks, subscriptions, nodes,
+    /// stacks, settlement tickets, attestation disputes and public key rotations) are cleared,
+    /// along with the applied and dead-letter events, and all the logged events are applied
+    /// again, in the order they were received. This allows recovering from a handler bug that
+    /// corrupted the state, once the handler is fixed. Events logged several times (when
+    /// delivered again) are only applied once.
+    ///
+    /// The state manager must not be running while the state is rebuilt. The compute units used
+    /// by the requests served since the stacks were created are not part of the event log, and
+    /// are lost.
+    ///
+    /// Events which fail to be applied are moved to the dead-letter table.
+    ///
+    /// # Returns
+    ///
+    /// The number of replayed events, and the number of events which failed to be applied.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database queries fail to execute.
+    /// - A logged event cannot be deserialized.
+    #[tracing::instrument(level = "info", skip_all)]
+    pub async fn rebuild_from_event_log(&self) -> Result<(u64, u64)> {
+        self.state.clear_event_derived_tables().await?;
+        let mut num_replayed_events = 0;
+        let mut num_failed_events = 0;
+        let mut last_event_id = 0;
+        loop {
+            let events = self
+                .state
+                .get_events(last_event_id, EVENT_LOG_PAGE_SIZE)
+                .await?;
+            let Some(last_event) = events.last() else {
+                break;
+            };
+            last_event_id = last_event.id;
+            for logged_event in events {
+                let atoma_event: AtomaEvent = serde_json::from_str(&logged_event.event)?;
+                num_replayed_events += 1;
+                if !self
+                    .state
+                    .apply_logged_event(&logged_event, atoma_event)
+                    .await?
+                {
+                    num_failed_events += 1;
+                }
+            }
+        }
+        tracing::info!(
+            target = "atoma-state-manager",
+            event = "state_rebuilt",
+            num_replayed_events,
+            num_failed_events,
+            "Rebuilt the state from the event log"
+        );
+        Ok((num_replayed_events, num_failed_events))
+    }
 }
 
-/// AtomaState is a wrapper around a Postgres connection pool, responsible for managing the state of the Atoma system.
+/// AtomaState is a wrapper around a database connection pool, responsible for managing the state of the Atoma system.
+///
+/// The state is stored in Postgres, or in SQLite with the `sqlite` feature.
 #[derive(Clone)]
 pub struct AtomaState {
-    /// The Postgres connection pool used for database operations.
-    pub db: PgPool,
+    /// The storage backend used for database operations.
+    storage: Storage,
 }
 
 impl AtomaState {
@@ -245,13 +478,19 @@ impl AtomaState {
     /// A new `AtomaState` instance
     #[must_use]
     pub const fn new(db: PgPool) -> Self {
-        Self { db }
+        Self {
+            storage: Storage::Postgres(PostgresStorage {
+                db,
+                transaction: None,
+            }),
+        }
     }
 
     /// Creates a new `AtomaState` instance from a database URL.
     ///
     /// # Arguments
-    /// * `database_url` - The URL of the PostgreSQL database to connect to
+    /// * `database_url` - The URL of the database to connect to, either a PostgreSQL one, or an
+    ///   SQLite one (e.g. `sqlite:///var/lib/atoma/atoma.db`) with the `sqlite` feature
     ///
     /// # Returns
     /// A new state instance wrapped in `Result`
@@ -260,10 +499,44 @@ impl AtomaState {
     /// Returns `AtomaStateManagerError` if:
     /// - Failed to connect to database
     /// - Failed to run database migrations
+    /// - The database URL is an SQLite one, and the `sqlite` feature is not enabled
     pub async fn new_from_url(database_url: &str) -> Result<Self> {
-        let db = PgPool::connect(database_url).await?;
-        sqlx::migrate!("./src/migrations").run(&db).await?;
-        Ok(Self { db })
+        Ok(Self {
+            storage: Storage::connect(database_url).await?,
+        })
+    }
+
+    /// Begins a database transaction, returning a copy of the state whose queries run in it.
+    ///
+    /// The changes made through the copy are only visible to the rest of the state once
+    /// committed, with [`Self::commit`], and are rolled back if the copy is dropped instead.
+    ///
+    /// # Errors
+    /// Returns `AtomaStateManagerError::DatabaseConnectionError` if the transaction cannot be
+    /// started.
+    pub async fn begin(&self) -> Result<Self> {
+        Ok(Self {
+            storage: self.storage.begin().await?,
+        })
+    }
+
+    /// Commits the transaction of a state returned by [`Self::begin`].
+    ///
+    /// # Errors
+    /// Returns `AtomaStateManagerError` if:
+    /// - Clones of the state still use the transaction
+    /// - The transaction fails to commit
+    pub async fn commit(self) -> Result<()> {
+        self.storage.commit().await
+    }
+
+    /// Checks that the database is reachable, by running a trivial query.
+    ///
+    /// # Errors
+    /// Returns `AtomaStateManagerError::DatabaseConnectionError` if the query fails.
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub async fn ping(&self) -> Result<()> {
+        dispatch!(self.storage, |storage| storage.ping().await)
     }
 
     /// Get a task by its unique identifier.
@@ -289,11 +562,9 @@ impl AtomaState {
         fields(task_small_id = %task_small_id)
     )]
     pub async fn get_task_by_small_id(&self, task_small_id: i64) -> Result<Task> {
-        let task = sqlx::query("SELECT * FROM tasks WHERE task_small_id = $1")
-            .bind(task_small_id)
-            .fetch_one(&self.db)
-            .await?;
-        Ok(Task::from_row(&task)?)
+        dispatch!(self.storage, |storage| storage
+            .get_task_by_small_id(task_small_id)
+            .await)
     }
 
     /// Retrieves all tasks from the database.
@@ -323,13 +594,7 @@ impl AtomaState {
     /// ```
     #[tracing::instrument(level = "trace", skip_all, fields(function = "get_all_tasks"))]
     pub async fn get_all_tasks(&self) -> Result<Vec<Task>> {
-        let tasks = sqlx::query("SELECT * FROM tasks")
-            .fetch_all(&self.db)
-            .await?;
-        tasks
-            .into_iter()
-            .map(|task| Task::from_row(&task).map_err(AtomaStateManagerError::from))
-            .collect()
+        dispatch!(self.storage, |storage| storage.get_all_tasks().await)
     }
 
     /// Inserts a new task into the database.
@@ -366,24 +631,7 @@ impl AtomaState {
         fields(task_id = %task.task_small_id)
     )]
     pub async fn insert_new_task(&self, task: Task) -> Result<()> {
-        sqlx::query(
-            "INSERT INTO tasks (
-                task_small_id, task_id, role, model_name, is_deprecated,
-                valid_until_epoch, security_level, minimum_reputation_score
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-            ON CONFLICT (task_small_id) DO NOTHING",
-        )
-        .bind(task.task_small_id)
-        .bind(task.task_id)
-        .bind(task.role)
-        .bind(task.model_name)
-        .bind(task.is_deprecated)
-        .bind(task.valid_until_epoch)
-        .bind(task.security_level)
-        .bind(task.minimum_reputation_score)
-        .execute(&self.db)
-        .await?;
-        Ok(())
+        dispatch!(self.storage, |storage| storage.insert_new_task(task).await)
     }
 
     /// Deprecates a task in the database based on its small ID.
@@ -420,12 +668,37 @@ impl AtomaState {
         fields(task_small_id = %task_small_id)
     )]
     pub async fn deprecate_task(&self, task_small_id: i64, epoch: i64) -> Result<()> {
-        sqlx::query("UPDATE tasks SET is_deprecated = TRUE, deprecated_at_epoch = $1 WHERE task_small_id = $2")
-            .bind(epoch)
-            .bind(task_small_id)
-            .execute(&self.db)
-            .await?;
-        Ok(())
+        dispatch!(self.storage, |storage| storage
+            .deprecate_task(task_small_id, epoch)
+            .await)
+    }
+
+    /// Marks a task as permanently removed from the network.
+    ///
+    /// This method sets the `removed_at_epoch` field of a task in the `tasks` table. A removed
+    /// task is also deprecated, if it was not already.
+    ///
+    /// # Arguments
+    ///
+    /// * `task_small_id` - The unique small identifier for the task to be removed.
+    /// * `epoch` - The epoch at which the task was removed.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the database query fails to execute.
+    #[tracing::instrument(
+        level = "trace",
+        skip_all,
+        fields(task_small_id = %task_small_id)
+    )]
+    pub async fn remove_task(&self, task_small_id: i64, epoch: i64) -> Result<()> {
+        dispatch!(self.storage, |storage| storage
+            .remove_task(task_small_id, epoch)
+            .await)
     }
 
     /// Retrieves all tasks subscribed to by a specific node.
@@ -464,18 +737,9 @@ impl AtomaState {
         fields(node_small_id = %node_small_id)
     )]
     pub async fn get_subscribed_tasks(&self, node_small_id: i64) -> Result<Vec<Task>> {
-        let tasks = sqlx::query(
-            "SELECT tasks.* FROM tasks
-            INNER JOIN node_subscriptions ON tasks.task_small_id = node_subscriptions.task_small_id
-            WHERE node_subscriptions.node_small_id = $1",
-        )
-        .bind(node_small_id)
-        .fetch_all(&self.db)
-        .await?;
-        tasks
-            .into_iter()
-            .map(|task| Task::from_row(&task).map_err(AtomaStateManagerError::from))
-            .collect()
+        dispatch!(self.storage, |storage| storage
+            .get_subscribed_tasks(node_small_id)
+            .await)
     }
 
     /// Retrieves all node subscriptions for a given set of node IDs.
@@ -518,21 +782,9 @@ impl AtomaState {
         &self,
         node_small_ids: &[i64],
     ) -> Result<Vec<NodeSubscription>> {
-        let mut query_builder = build_query_with_in(
-            "SELECT * FROM node_subscriptions",
-            "node_small_id",
-            node_small_ids,
-            None,
-        );
-
-        let subscriptions = query_builder.build().fetch_all(&self.db).await?;
-
-        subscriptions
-            .into_iter()
-            .map(|subscription| {
-                NodeSubscription::from_row(&subscription).map_err(AtomaStateManagerError::from)
-            })
-            .collect()
+        dispatch!(self.storage, |storage| storage
+            .get_all_node_subscriptions(node_small_ids)
+            .await)
     }
 
     /// Checks if a node is subscribed to a specific task.
@@ -575,15 +827,9 @@ impl AtomaState {
         node_small_id: i64,
         task_small_id: i64,
     ) -> Result<bool> {
-        let result = sqlx::query(
-            "SELECT COUNT(*) FROM node_subscriptions WHERE node_small_id = $1 AND task_small_id = $2",
-        )
-        .bind(node_small_id)
-        .bind(task_small_id)
-        .fetch_one(&self.db)
-        .await?;
-        let count: i64 = result.get(0);
-        Ok(count > 0)
+        dispatch!(self.storage, |storage| storage
+            .is_node_subscribed_to_task(node_small_id, task_small_id)
+            .await)
     }
 
     /// Subscribes a node to a task with a specified price per compute unit.
@@ -634,19 +880,79 @@ impl AtomaState {
         price_per_one_million_compute_units: i64,
         max_num_compute_units: i64,
     ) -> Result<()> {
-        sqlx::query(
-            "INSERT INTO node_subscriptions
-                (node_small_id, task_small_id, price_per_one_million_compute_units, max_num_compute_units, valid)
-                VALUES ($1, $2, $3, $4, TRUE)
-                ON CONFLICT (task_small_id, node_small_id) DO NOTHING",
-        )
-            .bind(node_small_id)
-            .bind(task_small_id)
-            .bind(price_per_one_million_compute_units)
-            .bind(max_num_compute_units)
-            .execute(&self.db)
-            .await?;
-        Ok(())
+        dispatch!(self.storage, |storage| storage
+            .subscribe_node_to_task(
+                node_small_id,
+                task_small_id,
+                price_per_one_million_compute_units,
+                max_num_compute_units
+            )
+            .await)
+    }
+
+    /// Records a node's subscription to a model.
+    ///
+    /// This method inserts a new entry into the `node_model_subscriptions` table, or updates
+    /// the echelon of an existing subscription.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_small_id` - The unique identifier of the node subscribing to the model.
+    /// * `model_name` - The name of the model the node is subscribing to.
+    /// * `echelon_id` - The echelon at which the node is subscribing to the model.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the database query fails to execute.
+    #[tracing::instrument(
+        level = "trace",
+        skip_all,
+        fields(node_small_id = %node_small_id, model_name = %model_name)
+    )]
+    pub async fn subscribe_node_to_model(
+        &self,
+        node_small_id: i64,
+        model_name: &str,
+        echelon_id: i64,
+    ) -> Result<()> {
+        dispatch!(self.storage, |storage| storage
+            .subscribe_node_to_model(node_small_id, model_name, echelon_id)
+            .await)
+    }
+
+    /// Retrieves the models the given nodes are subscribed to, together with their tasks.
+    ///
+    /// A model is returned if one of the nodes subscribed to it directly, or to one of its
+    /// tasks. The tasks of each model are split between active tasks, and tasks that were
+    /// deprecated or removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_small_ids` - The small IDs of the nodes, or `None` for all the nodes.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Vec<ModelTasks>>`: A result containing either:
+    ///   - `Ok(Vec<ModelTasks>)`: The models the nodes are subscribed to, ordered by name.
+    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    /// - There's an issue reading the database rows.
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub async fn get_node_model_tasks(
+        &self,
+        node_small_ids: Option<&[i64]>,
+    ) -> Result<Vec<ModelTasks>> {
+        dispatch!(self.storage, |storage| storage
+            .get_node_model_tasks(node_small_ids)
+            .await)
     }
 
     /// Retrieves the node subscription associated with a specific task ID.
@@ -689,11 +995,9 @@ impl AtomaState {
         &self,
         task_small_id: i64,
     ) -> Result<NodeSubscription> {
-        let subscription = sqlx::query("SELECT * FROM node_subscriptions WHERE task_small_id = $1")
-            .bind(task_small_id)
-            .fetch_one(&self.db)
-            .await?;
-        Ok(NodeSubscription::from_row(&subscription)?)
+        dispatch!(self.storage, |storage| storage
+            .get_node_subscription_by_task_small_id(task_small_id)
+            .await)
     }
 
     /// Updates an existing node subscription to a task with new price and compute unit values.
@@ -745,16 +1049,14 @@ impl AtomaState {
         price_per_one_million_compute_units: i64,
         max_num_compute_units: i64,
     ) -> Result<()> {
-        sqlx::query(
-            "UPDATE node_subscriptions SET price_per_one_million_compute_units = $1, max_num_compute_units = $2 WHERE node_small_id = $3 AND task_small_id = $4",
-        )
-            .bind(price_per_one_million_compute_units)
-            .bind(max_num_compute_units)
-            .bind(node_small_id)
-            .bind(task_small_id)
-            .execute(&self.db)
-            .await?;
-        Ok(())
+        dispatch!(self.storage, |storage| storage
+            .update_node_subscription(
+                node_small_id,
+                task_small_id,
+                price_per_one_million_compute_units,
+                max_num_compute_units
+            )
+            .await)
     }
 
     /// Inserts a new node registration event into the database.
@@ -803,15 +1105,9 @@ impl AtomaState {
         node_id: String,
         node_sui_address: String,
     ) -> Result<()> {
-        sqlx::query(
-            "INSERT INTO nodes (node_small_id, node_id, node_sui_address) VALUES ($1, $2, $3)",
-        )
-        .bind(node_small_id)
-        .bind(node_id)
-        .bind(node_sui_address)
-        .execute(&self.db)
-        .await?;
-        Ok(())
+        dispatch!(self.storage, |storage| storage
+            .insert_node_registration_event(node_small_id, node_id, node_sui_address)
+            .await)
     }
 
     /// Retrieves a node from the database by its small ID.
@@ -850,11 +1146,9 @@ impl AtomaState {
         fields(node_small_id = %node_small_id)
     )]
     pub async fn get_node_by_small_id(&self, node_small_id: i64) -> Result<Node> {
-        let node = sqlx::query("SELECT * FROM nodes WHERE node_small_id = $1")
-            .bind(node_small_id)
-            .fetch_one(&self.db)
-            .await?;
-        Ok(Node::from_row(&node)?)
+        dispatch!(self.storage, |storage| storage
+            .get_node_by_small_id(node_small_id)
+            .await)
     }
 
     /// Verifies the ownership of a node's small ID by checking the Sui address.
@@ -884,20 +1178,9 @@ impl AtomaState {
         node_small_id: u64,
         sui_address: String,
     ) -> Result<()> {
-        let exists = sqlx::query(
-            "SELECT EXISTS(SELECT 1 FROM nodes WHERE node_small_id = $1 AND node_sui_address = $2)",
-        )
-        .bind(node_small_id as i64)
-        .bind(sui_address)
-        .fetch_one(&self.db)
-        .await?
-        .get::<bool, _>(0);
-
-        if !exists {
-            return Err(AtomaStateManagerError::NodeSmallIdOwnershipVerificationFailed);
-        }
-
-        Ok(())
+        dispatch!(self.storage, |storage| storage
+            .verify_node_small_id_ownership(node_small_id, sui_address)
+            .await)
     }
 
     /// Unsubscribes a node from a task.
@@ -935,14 +1218,9 @@ impl AtomaState {
         node_small_id: i64,
         task_small_id: i64,
     ) -> Result<()> {
-        sqlx::query(
-            "DELETE FROM node_subscriptions WHERE node_small_id = $1 AND task_small_id = $2",
-        )
-        .bind(node_small_id)
-        .bind(task_small_id)
-        .execute(&self.db)
-        .await?;
-        Ok(())
+        dispatch!(self.storage, |storage| storage
+            .unsubscribe_node_from_task(node_small_id, task_small_id)
+            .await)
     }
 
     /// Inserts or updates a node's public key rotation record in the database.
@@ -990,33 +1268,16 @@ impl AtomaState {
         evidence_data_bytes: Vec<u8>,
         device_type: u16,
     ) -> Result<()> {
-        sqlx::query(
-            "INSERT INTO node_public_key_rotations (
-                epoch, 
-                key_rotation_counter, 
-                node_small_id, 
-                public_key_bytes, 
+        dispatch!(self.storage, |storage| storage
+            .insert_node_public_key_rotation(
+                epoch,
+                key_rotation_counter,
+                node_small_id,
+                public_key_bytes,
                 evidence_data_bytes,
                 device_type
-            ) 
-            VALUES ($1, $2, $3, $4, $5, $6)
-            ON CONFLICT (node_small_id, device_type)
-            DO UPDATE SET
-                epoch = EXCLUDED.epoch,
-                key_rotation_counter = EXCLUDED.key_rotation_counter,
-                public_key_bytes = EXCLUDED.public_key_bytes,
-                evidence_data_bytes = EXCLUDED.evidence_data_bytes
-            ",
-        )
-        .bind(epoch as i64)
-        .bind(key_rotation_counter as i64)
-        .bind(node_small_id as i64)
-        .bind(public_key_bytes)
-        .bind(evidence_data_bytes)
-        .bind(i32::from(device_type))
-        .execute(&self.db)
-        .await?;
-        Ok(())
+            )
+            .await)
     }
 
     /// Retrieves the stack associated with a specific stack ID.
@@ -1054,11 +1315,9 @@ impl AtomaState {
         fields(stack_small_id = %stack_small_id)
     )]
     pub async fn get_stack(&self, stack_small_id: i64) -> Result<Stack> {
-        let stack = sqlx::query("SELECT * FROM stacks WHERE stack_small_id = $1")
-            .bind(stack_small_id)
-            .fetch_one(&self.db)
-            .await?;
-        Ok(Stack::from_row(&stack)?)
+        dispatch!(self.storage, |storage| storage
+            .get_stack(stack_small_id)
+            .await)
     }
 
     /// Retrieves multiple stacks from the database by their small IDs.
@@ -1098,17 +1357,9 @@ impl AtomaState {
         fields(stack_small_ids = ?stack_small_ids)
     )]
     pub async fn get_stacks(&self, stack_small_ids: &[i64]) -> Result<Vec<Stack>> {
-        let mut query_builder = build_query_with_in(
-            "SELECT * FROM stacks",
-            "stack_small_id",
-            stack_small_ids,
-            None,
-        );
-        let stacks = query_builder.build().fetch_all(&self.db).await?;
-        stacks
-            .into_iter()
-            .map(|stack| Stack::from_row(&stack).map_err(AtomaStateManagerError::from))
-            .collect()
+        dispatch!(self.storage, |storage| storage
+            .get_stacks(stack_small_ids)
+            .await)
     }
 
     /// Retrieves all stacks associated with the given node IDs.
@@ -1148,19 +1399,9 @@ impl AtomaState {
         fields(node_small_ids = ?node_small_ids)
     )]
     pub async fn get_stacks_by_node_small_ids(&self, node_small_ids: &[i64]) -> Result<Vec<Stack>> {
-        let mut query_builder = build_query_with_in(
-            "SELECT * FROM stacks",
-            "selected_node_id",
-            node_small_ids,
-            None,
-        );
-
-        let stacks = query_builder.build().fetch_all(&self.db).await?;
-
-        stacks
-            .into_iter()
-            .map(|stack| Stack::from_row(&stack).map_err(AtomaStateManagerError::from))
-            .collect()
+        dispatch!(self.storage, |storage| storage
+            .get_stacks_by_node_small_ids(node_small_ids)
+            .await)
     }
 
     /// Retrieves all stacks associated with a specific node ID.
@@ -1199,14 +1440,9 @@ impl AtomaState {
         fields(node_small_id = %node_small_id)
     )]
     pub async fn get_stack_by_id(&self, node_small_id: i64) -> Result<Vec<Stack>> {
-        let stacks = sqlx::query("SELECT * FROM stacks WHERE selected_node_id = $1")
-            .bind(node_small_id)
-            .fetch_all(&self.db)
-            .await?;
-        stacks
-            .into_iter()
-            .map(|stack| Stack::from_row(&stack).map_err(AtomaStateManagerError::from))
-            .collect()
+        dispatch!(self.storage, |storage| storage
+            .get_stack_by_id(node_small_id)
+            .await)
     }
 
     /// Retrieves stacks that are almost filled beyond a specified fraction threshold.
@@ -1256,21 +1492,9 @@ impl AtomaState {
         node_small_ids: &[i64],
         fraction: f64,
     ) -> Result<Vec<Stack>> {
-        Ok(sqlx::query_as::<_, Stack>(
-            r"
-            SELECT *
-            FROM stacks
-            WHERE selected_node_id = ANY($1)
-            AND CASE
-                WHEN num_compute_units = 0 THEN true
-                ELSE (already_computed_units::float / num_compute_units::float) > $2
-            END
-            ",
-        )
-        .bind(node_small_ids)
-        .bind(fraction)
-        .fetch_all(&self.db)
-        .await?)
+        dispatch!(self.storage, |storage| storage
+            .get_almost_filled_stacks(node_small_ids, fraction)
+            .await)
     }
 
     /// Attempts to atomically reserve compute units from a stack and returns its availability status.
@@ -1320,85 +1544,9 @@ impl AtomaState {
         sui_address: &str,
         num_compute_units: i64,
     ) -> Result<StackAvailability> {
-        const AVAILABLE: &str = "Available";
-        const DOES_NOT_EXIST: &str = "DoesNotExist";
-        const UNAVAILABLE: &str = "Unavailable";
-        const LOCKED: &str = "Locked";
-        const STATUS_COLUMN: &str = "status";
-
-        // NOTE: It is safe to format! in here, as any injection is only through the above
-        // constants, which are not interpolated.
-        let query = format!(
-            "
-            WITH attempt_update AS (
-                UPDATE stacks
-                SET already_computed_units = already_computed_units + $1
-                WHERE stack_small_id = $2
-                  AND owner_address = $3
-                  AND num_compute_units - already_computed_units >= $1
-                  AND in_settle_period = false
-                  AND is_claimed = false
-                  AND is_locked_for_claim = false
-                RETURNING 1
-            ),
-            stack_status AS (
-                SELECT
-                    CASE
-                        -- Check if update succeeded
-                        WHEN EXISTS (SELECT 1 FROM attempt_update) THEN '{AVAILABLE}'
-                        -- If update failed, determine the reason
-                        ELSE (
-                            SELECT
-                                CASE
-                                    -- 2. Check other locking conditions
-                                    WHEN s.owner_address != $3 -- Check owner mismatch first
-                                    THEN '{DOES_NOT_EXIST}'
-                                    -- 3. Check other locking conditions (only if owner matches)
-                                    WHEN s.in_settle_period = true
-                                      OR s.is_claimed = true
-                                      OR s.is_locked_for_claim = true
-                                    THEN '{LOCKED}'
-                                    -- 4. Check insufficient compute units (only remaining reason if not locked otherwise)
-                                    WHEN s.num_compute_units - s.already_computed_units < $1 THEN '{UNAVAILABLE}'
-                                    -- 5. Fallback: Should technically not be reached if logic is sound
-                                    ELSE '{LOCKED}'
-                                END
-                            FROM stacks s
-                            WHERE s.stack_small_id = $2
-                            -- Handle case where stack doesn't exist for the ELSE branch
-                            UNION ALL
-                            SELECT '{DOES_NOT_EXIST}'
-                            WHERE NOT EXISTS (SELECT 1 FROM stacks WHERE stack_small_id = $2)
-                            LIMIT 1 -- Ensure only one row is returned
-                        )
-                    END as status
-            )
-            SELECT status::text FROM stack_status;
-            ",
-        );
-
-        let result = sqlx::query(&query)
-            .bind(num_compute_units)
-            .bind(stack_small_id)
-            .bind(sui_address)
-            .fetch_one(&self.db)
-            .await?;
-
-        Ok(match result.get::<&str, _>(STATUS_COLUMN) {
-            AVAILABLE => StackAvailability::Available,
-            DOES_NOT_EXIST => StackAvailability::DoesNotExist,
-            UNAVAILABLE => StackAvailability::Unavailable,
-            LOCKED => StackAvailability::Locked,
-            status => {
-                tracing::error!(
-                    "Invalid stack availability status: {}, this should never happen",
-                    result.get::<&str, _>(STATUS_COLUMN)
-                );
-                return Err(AtomaStateManagerError::InvalidSqlQuery(format!(
-                    "Invalid stack availability status: {status}, this should never happen"
-                )));
-            }
-        })
+        dispatch!(self.storage, |storage| storage
+            .get_available_stack_with_compute_units(stack_small_id, sui_address, num_compute_units)
+            .await)
     }
 
     /// Inserts a new stack into the database if it doesn't already exist.
@@ -1444,36 +1592,9 @@ impl AtomaState {
             price = %stack.price_per_one_million_compute_units)
     )]
     pub async fn insert_new_stack(&self, stack: Stack) -> Result<()> {
-        sqlx::query(
-            "INSERT INTO stacks
-                (owner_address, stack_small_id, stack_id, task_small_id, selected_node_id, 
-                num_compute_units, price_per_one_million_compute_units, already_computed_units, 
-                in_settle_period, total_hash, num_total_messages, is_confidential,
-                is_claimed, is_locked_for_claim)
-            SELECT 
-                $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11,
-                (SELECT security_level = 1 FROM tasks WHERE task_small_id = $4),
-                $12, $13
-            ON CONFLICT (stack_small_id) DO UPDATE
-            SET already_computed_units = stacks.already_computed_units + $8
-            WHERE stacks.stack_small_id = $2;",
-        )
-        .bind(stack.owner_address)
-        .bind(stack.stack_small_id)
-        .bind(stack.stack_id)
-        .bind(stack.task_small_id)
-        .bind(stack.selected_node_id)
-        .bind(stack.num_compute_units)
-        .bind(stack.price_per_one_million_compute_units)
-        .bind(stack.already_computed_units)
-        .bind(stack.in_settle_period)
-        .bind(stack.total_hash)
-        .bind(stack.num_total_messages)
-        .bind(stack.is_claimed)
-        .bind(stack.is_locked_for_claim)
-        .execute(&self.db)
-        .await?;
-        Ok(())
+        dispatch!(self.storage, |storage| storage
+            .insert_new_stack(stack)
+            .await)
     }
 
     /// Updates the number of compute units already computed for a stack.
@@ -1515,12 +1636,9 @@ impl AtomaState {
         stack_small_id: i64,
         already_computed_units: i64,
     ) -> Result<()> {
-        sqlx::query("UPDATE stacks SET already_computed_units = $1 WHERE stack_small_id = $2")
-            .bind(already_computed_units)
-            .bind(stack_small_id)
-            .execute(&self.db)
-            .await?;
-        Ok(())
+        dispatch!(self.storage, |storage| storage
+            .update_computed_units_for_stack(stack_small_id, already_computed_units)
+            .await)
     }
 
     /// Updates the number of compute units already computed for a stack.
@@ -1565,43 +1683,15 @@ impl AtomaState {
         ratio_for_claim_stacks: f64,
         concurrent_requests: i64,
     ) -> Result<UpdateStackNumComputeUnitsAndClaimFunds> {
-        let result = sqlx::query(
-            "WITH updated AS (
-                SELECT
-                    CAST(already_computed_units - ($2 - $3) AS FLOAT) / CAST(num_compute_units AS FLOAT) as new_ratio,
-                    is_confidential
-                FROM stacks
-                WHERE stack_small_id = $1
+        dispatch!(self.storage, |storage| storage
+            .update_stack_num_compute_units(
+                stack_small_id,
+                estimated_total_compute_units,
+                total_compute_units,
+                ratio_for_claim_stacks,
+                concurrent_requests
             )
-            UPDATE stacks s
-            SET
-                already_computed_units = already_computed_units - ($2 - $3),
-                is_locked_for_claim = (
-                    SELECT (u.is_confidential = true AND u.new_ratio > $4 AND $5 = 0)
-                    FROM updated u
-                )
-            WHERE s.stack_small_id = $1
-            RETURNING
-                CAST(s.already_computed_units AS FLOAT) / CAST(s.num_compute_units AS FLOAT) as ratio,
-                s.already_computed_units as stack_computed_units,
-                (s.is_confidential = true) as is_confidential,
-                s.is_locked_for_claim as is_locked_for_claim",
-        )
-        .bind(stack_small_id)
-        .bind(estimated_total_compute_units)
-        .bind(total_compute_units)
-        .bind(ratio_for_claim_stacks)
-        .bind(concurrent_requests)
-        .fetch_optional(&self.db)
-        .await?;
-
-        result.map_or_else(
-            || Err(AtomaStateManagerError::StackNotFound),
-            |row| {
-                UpdateStackNumComputeUnitsAndClaimFunds::from_row(&row)
-                    .map_err(AtomaStateManagerError::from)
-            },
-        )
+            .await)
     }
 
     /// Retrieves the stack settlement ticket for a given stack.
@@ -1644,12 +1734,9 @@ impl AtomaState {
         &self,
         stack_small_id: i64,
     ) -> Result<StackSettlementTicket> {
-        let stack_settlement_ticket =
-            sqlx::query("SELECT * FROM stack_settlement_tickets WHERE stack_small_id = $1")
-                .bind(stack_small_id)
-                .fetch_one(&self.db)
-                .await?;
-        Ok(StackSettlementTicket::from_row(&stack_settlement_ticket)?)
+        dispatch!(self.storage, |storage| storage
+            .get_stack_settlement_ticket(stack_small_id)
+            .await)
     }
 
     /// Retrieves multiple stack settlement tickets from the database.
@@ -1688,19 +1775,9 @@ impl AtomaState {
         &self,
         stack_small_ids: &[i64],
     ) -> Result<Vec<StackSettlementTicket>> {
-        let mut query_builder = build_query_with_in(
-            "SELECT * FROM stack_settlement_tickets",
-            "stack_small_id",
-            stack_small_ids,
-            None,
-        );
-
-        let stack_settlement_tickets = query_builder.build().fetch_all(&self.db).await?;
-
-        stack_settlement_tickets
-            .into_iter()
-            .map(|row| StackSettlementTicket::from_row(&row).map_err(AtomaStateManagerError::from))
-            .collect()
+        dispatch!(self.storage, |storage| storage
+            .get_stack_settlement_tickets(stack_small_ids)
+            .await)
     }
 
     /// Inserts a new stack settlement ticket into the database.
@@ -1742,45 +1819,9 @@ impl AtomaState {
         &self,
         stack_settlement_ticket: StackSettlementTicket,
     ) -> Result<()> {
-        let mut tx = self.db.begin().await?;
-        sqlx::query(
-            "INSERT INTO stack_settlement_tickets
-                (
-                    stack_small_id,
-                    selected_node_id,
-                    num_claimed_compute_units,
-                    requested_attestation_nodes,
-                    committed_stack_proofs,
-                    stack_merkle_leaves,
-                    dispute_settled_at_epoch,
-                    already_attested_nodes,
-                    is_in_dispute,
-                    user_refund_amount,
-                    is_claimed)
-                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
-                ON CONFLICT (stack_small_id, selected_node_id) DO NOTHING",
-        )
-        .bind(stack_settlement_ticket.stack_small_id)
-        .bind(stack_settlement_ticket.selected_node_id)
-        .bind(stack_settlement_ticket.num_claimed_compute_units)
-        .bind(stack_settlement_ticket.requested_attestation_nodes)
-        .bind(stack_settlement_ticket.committed_stack_proofs)
-        .bind(stack_settlement_ticket.stack_merkle_leaves)
-        .bind(stack_settlement_ticket.dispute_settled_at_epoch)
-        .bind(stack_settlement_ticket.already_attested_nodes)
-        .bind(stack_settlement_ticket.is_in_dispute)
-        .bind(stack_settlement_ticket.user_refund_amount)
-        .bind(stack_settlement_ticket.is_claimed)
-        .execute(&mut *tx)
-        .await?;
-
-        // Also update the stack to set in_settle_period to true
-        sqlx::query("UPDATE stacks SET in_settle_period = true WHERE stack_small_id = $1")
-            .bind(stack_settlement_ticket.stack_small_id)
-            .execute(&mut *tx)
-            .await?;
-        tx.commit().await?;
-        Ok(())
+        dispatch!(self.storage, |storage| storage
+            .insert_new_stack_settlement_ticket(stack_settlement_ticket)
+            .await)
     }
 
     /// Updates the total hash and increments the total number of messages for a stack.
@@ -1825,23 +1866,9 @@ impl AtomaState {
         stack_small_id: i64,
         new_hash: [u8; 32],
     ) -> Result<()> {
-        let rows_affected = sqlx::query(
-            "UPDATE stacks
-            SET total_hash = total_hash || $1,
-                num_total_messages = num_total_messages + 1
-            WHERE stack_small_id = $2",
-        )
-        .bind(&new_hash[..])
-        .bind(stack_small_id)
-        .execute(&self.db)
-        .await?
-        .rows_affected();
-
-        if rows_affected == 0 {
-            return Err(AtomaStateManagerError::StackNotFound);
-        }
-
-        Ok(())
+        dispatch!(self.storage, |storage| storage
+            .update_stack_total_hash(stack_small_id, new_hash)
+            .await)
     }
 
     /// Retrieves the total hash for a specific stack.
@@ -1878,13 +1905,9 @@ impl AtomaState {
         fields(stack_small_id = %stack_small_id)
     )]
     pub async fn get_stack_total_hash(&self, stack_small_id: i64) -> Result<Vec<u8>> {
-        let total_hash = sqlx::query_scalar::<_, Vec<u8>>(
-            "SELECT total_hash FROM stacks WHERE stack_small_id = $1",
-        )
-        .bind(stack_small_id)
-        .fetch_one(&self.db)
-        .await?;
-        Ok(total_hash)
+        dispatch!(self.storage, |storage| storage
+            .get_stack_total_hash(stack_small_id)
+            .await)
     }
 
     /// Retrieves the total hashes for multiple stacks in a single query.
@@ -1926,20 +1949,9 @@ impl AtomaState {
         fields(stack_small_ids = ?stack_small_ids)
     )]
     pub async fn get_all_total_hashes(&self, stack_small_ids: &[i64]) -> Result<Vec<Vec<u8>>> {
-        let mut query_builder = build_query_with_in(
-            "SELECT total_hash FROM stacks",
-            "stack_small_id",
-            stack_small_ids,
-            None,
-        );
-
-        Ok(query_builder
-            .build()
-            .fetch_all(&self.db)
-            .await?
-            .iter()
-            .map(|row| row.get("total_hash"))
-            .collect())
+        dispatch!(self.storage, |storage| storage
+            .get_all_total_hashes(stack_small_ids)
+            .await)
     }
 
     /// Updates a stack settlement ticket with attestation commitments.
@@ -2000,68 +2012,14 @@ impl AtomaState {
         stack_merkle_leaf: Vec<u8>,
         attestation_node_id: i64,
     ) -> Result<()> {
-        let mut tx = self.db.begin().await?;
-
-        // First query remains the same - get existing data
-        let row = sqlx::query(
-            "SELECT committed_stack_proofs, stack_merkle_leaves, requested_attestation_nodes, already_attested_nodes
-             FROM stack_settlement_tickets
-             WHERE stack_small_id = $1",
-        )
-        .bind(stack_small_id)
-        .fetch_one(&mut *tx)
-        .await?;
-
-        let mut committed_stack_proofs: Vec<u8> = row.get("committed_stack_proofs");
-        let mut current_merkle_leaves: Vec<u8> = row.get("stack_merkle_leaves");
-        let requested_nodes: String = row.get("requested_attestation_nodes");
-        let requested_nodes: Vec<i64> = serde_json::from_str(&requested_nodes)?;
-
-        // Get current attested nodes and handle null case
-        let already_attested: Option<String> = row.get("already_attested_nodes");
-        let mut attested_nodes: Vec<i64> = already_attested
-            .map(|nodes| serde_json::from_str(&nodes).unwrap_or_default())
-            .unwrap_or_default();
-
-        // Find the index and update binary data as before
-        let index = requested_nodes
-            .iter()
-            .position(|&id| id == attestation_node_id)
-            .ok_or_else(|| AtomaStateManagerError::AttestationNodeNotFound(attestation_node_id))?;
-
-        let start = (index + 1) * 32;
-        let end = start + 32;
-        if end > current_merkle_leaves.len() {
-            return Err(AtomaStateManagerError::InvalidMerkleLeafLength);
-        }
-        if end > committed_stack_proofs.len() {
-            return Err(AtomaStateManagerError::InvalidCommittedStackProofLength);
-        }
-
-        current_merkle_leaves[start..end].copy_from_slice(&stack_merkle_leaf[..32]);
-        committed_stack_proofs[start..end].copy_from_slice(&committed_stack_proof[..32]);
-
-        // Update attested nodes list
-        attested_nodes.push(attestation_node_id);
-        let attested_nodes_json = serde_json::to_string(&attested_nodes)?;
-
-        // Simplified update query
-        sqlx::query(
-            "UPDATE stack_settlement_tickets
-            SET committed_stack_proofs = $1,
-                stack_merkle_leaves = $2,
-                already_attested_nodes = $3
-            WHERE stack_small_id = $4",
-        )
-        .bind(committed_stack_proofs)
-        .bind(current_merkle_leaves)
-        .bind(attested_nodes_json)
-        .bind(stack_small_id)
-        .execute(&mut *tx)
-        .await?;
-
-        tx.commit().await?;
-        Ok(())
+        dispatch!(self.storage, |storage| storage
+            .update_stack_settlement_ticket_with_attestation_commitments(
+                stack_small_id,
+                committed_stack_proof,
+                stack_merkle_leaf,
+                attestation_node_id
+            )
+            .await)
     }
 
     /// Settles a stack settlement ticket by updating the dispute settled at epoch.
@@ -2106,12 +2064,9 @@ impl AtomaState {
         stack_small_id: i64,
         dispute_settled_at_epoch: i64,
     ) -> Result<()> {
-        sqlx::query("UPDATE stack_settlement_tickets SET dispute_settled_at_epoch = $1 WHERE stack_small_id = $2")
-            .bind(dispute_settled_at_epoch)
-            .bind(stack_small_id)
-            .execute(&self.db)
-            .await?;
-        Ok(())
+        dispatch!(self.storage, |storage| storage
+            .settle_stack_settlement_ticket(stack_small_id, dispute_settled_at_epoch)
+            .await)
     }
 
     /// Updates a stack settlement ticket to mark it as claimed and set the user refund amount.
@@ -2157,18 +2112,9 @@ impl AtomaState {
         stack_small_id: i64,
         user_refund_amount: i64,
     ) -> Result<()> {
-        sqlx::query(
-            "UPDATE stack_settlement_tickets
-                SET user_refund_amount = $1,
-                    is_claimed = true
-                WHERE stack_small_id = $2",
-        )
-        .bind(user_refund_amount)
-        .bind(stack_small_id)
-        .execute(&self.db)
-        .await?;
-
-        Ok(())
+        dispatch!(self.storage, |storage| storage
+            .update_stack_settlement_ticket_with_claim(stack_small_id, user_refund_amount)
+            .await)
     }
 
     /// Updates a stack's claim status to mark it as claimed.
@@ -2199,12 +2145,9 @@ impl AtomaState {
         stack_small_id: i64,
         user_refund_amount: i64,
     ) -> Result<()> {
-        sqlx::query("UPDATE stacks SET is_claimed = TRUE, user_refund_amount = $1 WHERE stack_small_id = $2")
-            .bind(user_refund_amount)
-            .bind(stack_small_id)
-            .execute(&self.db)
-            .await?;
-        Ok(())
+        dispatch!(self.storage, |storage| storage
+            .update_stack_is_claimed(stack_small_id, user_refund_amount)
+            .await)
     }
 
     /// Retrieves all stacks that have been claimed for the specified node IDs.
@@ -2247,19 +2190,9 @@ impl AtomaState {
         &self,
         node_small_ids: &[i64],
     ) -> Result<Vec<StackSettlementTicket>> {
-        let mut query_builder = build_query_with_in(
-            "SELECT * FROM stack_settlement_tickets",
-            "selected_node_id",
-            node_small_ids,
-            Some("is_claimed = true"),
-        );
-
-        let stacks = query_builder.build().fetch_all(&self.db).await?;
-
-        stacks
-            .into_iter()
-            .map(|row| StackSettlementTicket::from_row(&row).map_err(AtomaStateManagerError::from))
-            .collect()
+        dispatch!(self.storage, |storage| storage
+            .get_claimed_stacks(node_small_ids)
+            .await)
     }
 
     /// Retrieves all stack attestation disputes for a given stack and attestation node.
@@ -2306,21 +2239,9 @@ impl AtomaState {
         stack_small_id: i64,
         attestation_node_id: i64,
     ) -> Result<Vec<StackAttestationDispute>> {
-        let disputes = sqlx::query(
-            "SELECT * FROM stack_attestation_disputes
-                WHERE stack_small_id = $1 AND attestation_node_id = $2",
-        )
-        .bind(stack_small_id)
-        .bind(attestation_node_id)
-        .fetch_all(&self.db)
-        .await?;
-
-        disputes
-            .into_iter()
-            .map(|row| {
-                StackAttestationDispute::from_row(&row).map_err(AtomaStateManagerError::from)
-            })
-            .collect()
+        dispatch!(self.storage, |storage| storage
+            .get_stack_attestation_disputes(stack_small_id, attestation_node_id)
+            .await)
     }
 
     /// Retrieves all attestation disputes filed against the specified nodes.
@@ -2364,21 +2285,9 @@ impl AtomaState {
         &self,
         node_small_ids: &[i64],
     ) -> Result<Vec<StackAttestationDispute>> {
-        let mut query_builder = build_query_with_in(
-            "SELECT * FROM stack_attestation_disputes",
-            "original_node_id",
-            node_small_ids,
-            None,
-        );
-
-        let disputes = query_builder.build().fetch_all(&self.db).await?;
-
-        disputes
-            .into_iter()
-            .map(|row| {
-                StackAttestationDispute::from_row(&row).map_err(AtomaStateManagerError::from)
-            })
-            .collect()
+        dispatch!(self.storage, |storage| storage
+            .get_against_attestation_disputes(node_small_ids)
+            .await)
     }
 
     /// Retrieves all attestation disputes where the specified nodes are the attestation providers.
@@ -2421,21 +2330,9 @@ impl AtomaState {
         &self,
         node_small_ids: &[i64],
     ) -> Result<Vec<StackAttestationDispute>> {
-        let mut query_builder = build_query_with_in(
-            "SELECT * FROM stack_attestation_disputes",
-            "attestation_node_id",
-            node_small_ids,
-            None,
-        );
-
-        let disputes = query_builder.build().fetch_all(&self.db).await?;
-
-        disputes
-            .into_iter()
-            .map(|row| {
-                StackAttestationDispute::from_row(&row).map_err(AtomaStateManagerError::from)
-            })
-            .collect()
+        dispatch!(self.storage, |storage| storage
+            .get_own_attestation_disputes(node_small_ids)
+            .await)
     }
 
     /// Inserts a new stack attestation dispute into the database.
@@ -2476,136 +2373,1259 @@ impl AtomaState {
         &self,
         stack_attestation_dispute: StackAttestationDispute,
     ) -> Result<()> {
-        sqlx::query(
-            "INSERT INTO stack_attestation_disputes
-                (stack_small_id, attestation_commitment, attestation_node_id, original_node_id, original_commitment)
-                VALUES ($1, $2, $3, $4, $5)
-                ON CONFLICT (stack_small_id, attestation_node_id) DO NOTHING",
-        )
-            .bind(stack_attestation_dispute.stack_small_id)
-            .bind(stack_attestation_dispute.attestation_commitment)
-            .bind(stack_attestation_dispute.attestation_node_id)
-            .bind(stack_attestation_dispute.original_node_id)
-            .bind(stack_attestation_dispute.original_commitment)
-            .execute(&self.db)
-            .await?;
-
-        Ok(())
+        dispatch!(self.storage, |storage| storage
+            .insert_stack_attestation_dispute(stack_attestation_dispute)
+            .await)
     }
-}
-
-#[derive(Error, Debug)]
-pub enum AtomaStateManagerError {
-    #[error("Failed to connect to the database: {0}")]
-    DatabaseConnectionError(#[from] sqlx::Error),
-    #[error("Stack not found")]
-    StackNotFound,
-    #[error("Attestation node not found: {0}")]
-    AttestationNodeNotFound(i64),
-    #[error("Invalid Merkle leaf length")]
-    InvalidMerkleLeafLength,
-    #[error("Invalid committed stack proof length")]
-    InvalidCommittedStackProofLength,
-    #[error("Failed to parse JSON: {0}")]
-    JsonParseError(#[from] serde_json::Error),
-    #[error("Failed to retrieve existing total hash for stack: `{0}`")]
-    FailedToRetrieveExistingTotalHash(i64),
-    #[error("Failed to send result to channel")]
-    ChannelSendError,
-    #[error("Failed to create database directory")]
-    FailedToCreateDatabaseDirectory(#[from] std::io::Error),
-    #[error("Failed to migrate database")]
-    FailedToMigrateDatabase(#[from] sqlx::migrate::MigrateError),
-    #[error("Node small ID ownership verification failed")]
-    NodeSmallIdOwnershipVerificationFailed,
-    #[error("Sui client error: {0}")]
-    SuiClientError(#[from] atoma_sui::client::AtomaSuiClientError),
-    #[error("Invalid SQL query: {0}")]
-    InvalidSqlQuery(String),
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use atoma_utils::test::POSTGRES_TEST_DB_URL;
 
-    async fn setup_test_db() -> AtomaState {
-        AtomaState::new_from_url(POSTGRES_TEST_DB_URL)
-            .await
-            .unwrap()
-    }
-
-    async fn truncate_tables(db: &sqlx::PgPool) {
-        // List all your tables here
-        sqlx::query(
-            "TRUNCATE TABLE
-                tasks,
-                node_subscriptions,
-                stacks,
-                stack_settlement_tickets,
-                stack_attestation_disputes,
-                node_public_key_rotations
-            CASCADE",
-        )
-        .execute(db)
-        .await
-        .expect("Failed to truncate tables");
+    /// Inserts a new batch, together with all of its requests, into the database.
+    ///
+    /// The batch and its requests are inserted within a single transaction, so either the
+    /// whole batch is stored or none of it is. All the requests are inserted as pending.
+    ///
+    /// # Arguments
+    ///
+    /// * `batch` - The `Batch` to insert.
+    /// * `requests` - The requests (one per line of the batch input file) of the batch.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<()>`: A result indicating success (Ok(())) or failure (Err(AtomaStateManagerError)).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database transaction fails to execute.
+    /// - There's a constraint violation (e.g., duplicate batch id).
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::{AtomaStateManager, Batch, BatchRequest};
+    ///
+    /// async fn add_batch(state_manager: &AtomaStateManager, batch: Batch, requests: Vec<BatchRequest>) -> Result<(), AtomaStateManagerError> {
+    ///     state_manager.insert_new_batch(batch, requests).await
+    /// }
+    /// ```
+    #[tracing::instrument(
+        level = "trace",
+        skip_all,
+        fields(batch_id = %batch.batch_id, stack_small_id = %batch.stack_small_id, num_requests = %requests.len())
+    )]
+    pub async fn insert_new_batch(&self, batch: Batch, requests: Vec<BatchRequest>) -> Result<()> {
+        dispatch!(self.storage, |storage| storage
+            .insert_new_batch(batch, requests)
+            .await)
     }
 
-    #[tokio::test]
-    #[serial_test::serial]
-    async fn test_insert_and_get_task() {
-        let state_manager = setup_test_db().await;
+    /// Retrieves a batch by its identifier.
+    ///
+    /// # Arguments
+    ///
+    /// * `batch_id` - The unique identifier of the batch to retrieve.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Batch>`: A result containing either:
+    ///   - `Ok(Batch)`: The batch with the given identifier.
+    ///   - `Err(AtomaStateManagerError)`: An error if the batch does not exist or the query fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    /// - No batch is found for the given `batch_id`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::{AtomaStateManager, Batch};
+    ///
+    /// async fn get_batch(state_manager: &AtomaStateManager, batch_id: &str) -> Result<Batch, AtomaStateManagerError> {
+    ///     state_manager.get_batch(batch_id).await
+    /// }
+    /// ```
+    #[tracing::instrument(level = "trace", skip_all, fields(batch_id = %batch_id))]
+    pub async fn get_batch(&self, batch_id: &str) -> Result<Batch> {
+        dispatch!(self.storage, |storage| storage.get_batch(batch_id).await)
+    }
 
-        let task = Task {
-            task_small_id: 1,
-            task_id: "task1".to_string(),
-            role: 1,
-            model_name: Some("model1".to_string()),
-            is_deprecated: false,
-            valid_until_epoch: Some(100),
-            deprecated_at_epoch: None,
-            security_level: 1,
-            minimum_reputation_score: Some(50),
-        };
+    /// Retrieves all the requests of a batch, ordered by their line index.
+    ///
+    /// # Arguments
+    ///
+    /// * `batch_id` - The unique identifier of the batch whose requests are to be retrieved.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Vec<BatchRequest>>`: A result containing either:
+    ///   - `Ok(Vec<BatchRequest>)`: The requests of the batch, ordered by line index.
+    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    /// - There's an issue converting the database rows into `BatchRequest` objects.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::{AtomaStateManager, BatchRequest};
+    ///
+    /// async fn get_requests(state_manager: &AtomaStateManager, batch_id: &str) -> Result<Vec<BatchRequest>, AtomaStateManagerError> {
+    ///     state_manager.get_batch_requests(batch_id).await
+    /// }
+    /// ```
+    #[tracing::instrument(level = "trace", skip_all, fields(batch_id = %batch_id))]
+    pub async fn get_batch_requests(&self, batch_id: &str) -> Result<Vec<BatchRequest>> {
+        dispatch!(self.storage, |storage| storage
+            .get_batch_requests(batch_id)
+            .await)
+    }
 
-        state_manager.insert_new_task(task.clone()).await.unwrap();
-        let retrieved_task = state_manager.get_task_by_small_id(1).await.unwrap();
-        assert_eq!(task, retrieved_task);
+    /// Retrieves all the batches that are still in progress, oldest first.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Vec<Batch>>`: A result containing either:
+    ///   - `Ok(Vec<Batch>)`: The batches whose requests are still being processed.
+    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    /// - There's an issue converting the database rows into `Batch` objects.
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub async fn get_in_progress_batches(&self) -> Result<Vec<Batch>> {
+        dispatch!(self.storage, |storage| storage
+            .get_in_progress_batches()
+            .await)
+    }
 
-        truncate_tables(&state_manager.db).await;
+    /// Retrieves the oldest pending batch requests, across all in progress batches.
+    ///
+    /// Requests are returned in submission order, that is, ordered by the creation time of
+    /// their batch and then by their line index, so that older batches are processed first.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The maximum number of requests to return.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Vec<BatchRequest>>`: A result containing either:
+    ///   - `Ok(Vec<BatchRequest>)`: Up to `limit` pending requests.
+    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    /// - There's an issue converting the database rows into `BatchRequest` objects.
+    #[tracing::instrument(level = "trace", skip_all, fields(limit = %limit))]
+    pub async fn get_pending_batch_requests(&self, limit: i64) -> Result<Vec<BatchRequest>> {
+        dispatch!(self.storage, |storage| storage
+            .get_pending_batch_requests(limit)
+            .await)
     }
 
-    #[tokio::test]
-    #[serial_test::serial]
-    async fn test_deprecate_task() {
-        let state_manager = setup_test_db().await;
+    /// Stores the result of a processed batch request and updates the batch counters.
+    ///
+    /// The request is only updated if it is still pending, which makes the operation idempotent.
+    /// Once every request of the batch has been processed, the batch is marked as completed and
+    /// returned, so that the caller can release the compute units that were reserved for the
+    /// batch but not used.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The processed request, with its `status`, `status_code`, `response`, `error`
+    ///   and `used_compute_units` fields set.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Option<Batch>>`: A result containing either:
+    ///   - `Ok(Some(Batch))`: The batch, if this request was the last one to be processed.
+    ///   - `Ok(None)`: If the batch still has pending requests (or the request was already processed).
+    ///   - `Err(AtomaStateManagerError)`: An error if the database transaction fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database transaction fails to execute.
+    /// - There's an issue converting the database row into a `Batch` object.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use atoma_node::atoma_state::{AtomaStateManager, Batch, BatchRequest};
+    ///
+    /// async fn complete(state_manager: &AtomaStateManager, request: BatchRequest) -> Result<Option<Batch>, AtomaStateManagerError> {
+    ///     state_manager.complete_batch_request(request).await
+    /// }
+    /// ```
+    #[tracing::instrument(
+        level = "trace",
+        skip_all,
+        fields(batch_id = %request.batch_id, line_index = %request.line_index, status = %request.status)
+    )]
+    pub async fn complete_batch_request(&self, request: BatchRequest) -> Result<Option<Batch>> {
+        dispatch!(self.storage, |storage| storage
+            .complete_batch_request(request)
+            .await)
+    }
 
-        let task = Task {
-            task_small_id: 1,
-            task_id: "task1".to_string(),
-            role: 1,
-            model_name: Some("model1".to_string()),
-            is_deprecated: false,
-            valid_until_epoch: Some(100),
-            deprecated_at_epoch: None,
-            security_level: 1,
-            minimum_reputation_score: Some(50),
-        };
+    /// Adds `delta` to the number of requests in flight for a stack, as counted by `owner`, and
+    /// notifies the new count on the `channel` Postgres notification channel.
+    ///
+    /// Counts never go below zero. Notifications are only sent by Postgres, as SQLite databases
+    /// are not shared between replicas.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<ConcurrentRequestsCount>`: A result containing either:
+    ///   - `Ok(ConcurrentRequestsCount)`: The new count.
+    ///   - `Err(AtomaStateManagerError)`: An error if the database query fails.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    /// - The notification payload cannot be serialized.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn update_concurrent_requests(
+        &self,
+        owner: &str,
+        stack_small_id: i64,
+        delta: i64,
+        channel: &str,
+    ) -> Result<ConcurrentRequestsCount> {
+        dispatch!(self.storage, |storage| storage
+            .update_concurrent_requests(owner, stack_small_id, delta, channel)
+            .await)
+    }
 
-        state_manager.insert_new_task(task).await.unwrap();
-        state_manager.deprecate_task(1, 100).await.unwrap();
+    /// Replaces all the counts of requests in flight of `owner` by `counts` (pairs of stack small
+    /// id and number of requests), and notifies the changes on the `channel` Postgres
+    /// notification channel (counts that are removed are notified as zero). As for
+    /// [`Self::update_concurrent_requests`], notifications are only sent by Postgres.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database queries fail to execute.
+    /// - A notification payload cannot be serialized.
+    #[tracing::instrument(level = "trace", skip(self, counts))]
+    pub async fn reset_concurrent_requests(
+        &self,
+        owner: &str,
+        counts: &[(i64, i64)],
+        channel: &str,
+    ) -> Result<()> {
+        dispatch!(self.storage, |storage| storage
+            .reset_concurrent_requests(owner, counts, channel)
+            .await)
+    }
 
-        let deprecated_task = state_manager.get_task_by_small_id(1).await.unwrap();
-        assert!(deprecated_task.is_deprecated);
+    /// Appends an Atoma event to the event log.
+    ///
+    /// Events received from the Sui subscriber are recorded before being applied to the state, so
+    /// that the state can be rebuilt by replaying the log (see
+    /// [`AtomaStateManager::rebuild_from_event_log`]).
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The Atoma event to record.
+    /// * `metadata` - Identifies the Sui event the Atoma event was parsed from.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<LoggedEvent>`: The event, as recorded in the log.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The event cannot be serialized.
+    /// - The database query fails to execute.
+    #[tracing::instrument(
+        level = "trace",
+        skip_all,
+        fields(event_name = %event.name(), tx_digest = %metadata.tx_digest, event_seq = %metadata.event_seq)
+    )]
+    pub async fn insert_event(
+        &self,
+        event: &AtomaEvent,
+        metadata: &AtomaEventMetadata,
+    ) -> Result<LoggedEvent> {
+        let serialized_event = serde_json::to_string(event)?;
+        let event_seq = metadata.event_seq as i64;
+        let checkpoint_timestamp_ms = metadata.checkpoint_timestamp_ms.map(|ts| ts as i64);
+        let id = dispatch!(self.storage, |storage| storage
+            .insert_event(
+                &metadata.tx_digest,
+                event_seq,
+                event.name(),
+                checkpoint_timestamp_ms,
+                &serialized_event,
+            )
+            .await)?;
+        Ok(LoggedEvent {
+            id,
+            tx_digest: metadata.tx_digest.clone(),
+            event_seq,
+            event_name: event.name().to_string(),
+            checkpoint_timestamp_ms,
+            event: serialized_event,
+        })
+    }
 
-        truncate_tables(&state_manager.db).await;
+    /// Checks whether a logged event was already applied to the state.
+    ///
+    /// Events are identified by their transaction digest, sequence number and name, so that an
+    /// event delivered again is recognized, although it is logged again.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the database query fails to execute.
+    #[tracing::instrument(level = "trace", skip_all, fields(event_id = %event.id))]
+    pub async fn is_event_applied(&self, event: &LoggedEvent) -> Result<bool> {
+        dispatch!(self.storage, |storage| storage
+            .is_event_applied(event)
+            .await)
     }
 
-    #[tokio::test]
-    #[serial_test::serial]
-    async fn test_get_subscribed_tasks() {
-        let state_manager = setup_test_db().await;
+    /// Records that a logged event was applied to the state.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the database query fails to execute.
+    #[tracing::instrument(level = "trace", skip_all, fields(event_id = %event.id))]
+    pub async fn insert_applied_event(&self, event: &LoggedEvent) -> Result<()> {
+        dispatch!(self.storage, |storage| storage
+            .insert_applied_event(event)
+            .await)
+    }
+
+    /// Moves a logged event, which failed to be applied, to the dead-letter table.
+    ///
+    /// If the event is already in the table, its error is updated and its number of attempts
+    /// is increased.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The event which failed to be applied.
+    /// * `object_key` - The key of the object (task, node or stack) the event applies to, if any,
+    ///   whose later events are held back while the event is dead-lettered.
+    /// * `error` - The error returned by the handler of the event.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the database query fails to execute.
+    #[tracing::instrument(level = "trace", skip_all, fields(event_id = %event.id))]
+    pub async fn insert_dead_letter_event(
+        &self,
+        event: &LoggedEvent,
+        object_key: Option<&str>,
+        error: &str,
+    ) -> Result<()> {
+        dispatch!(self.storage, |storage| storage
+            .insert_dead_letter_event(event, object_key, error)
+            .await)
+    }
+
+    /// Checks whether an event of an object, logged before the event at position `event_id`, is
+    /// in the dead-letter table, in which case the event must be held back.
+    ///
+    /// # Arguments
+    ///
+    /// * `object_key` - The key of the object (task, node or stack) the event applies to.
+    /// * `event_id` - The position of the event in the event log.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the database query fails to execute.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn has_earlier_dead_letter_event(
+        &self,
+        object_key: &str,
+        event_id: i64,
+    ) -> Result<bool> {
+        dispatch!(self.storage, |storage| storage
+            .has_earlier_dead_letter_event(object_key, event_id)
+            .await)
+    }
+
+    /// Retrieves the dead-letter events with less than `max_attempts` attempts, in the order they
+    /// were received.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    /// - There's an issue converting the database rows into `LoggedEvent` objects.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn get_dead_letter_events(
+        &self,
+        max_attempts: i64,
+        limit: i64,
+    ) -> Result<Vec<LoggedEvent>> {
+        dispatch!(self.storage, |storage| storage
+            .get_dead_letter_events(max_attempts, limit)
+            .await)
+    }
+
+    /// Applies a logged Atoma event to the state, unless it was already applied.
+    ///
+    /// Events delivered again (e.g. after the cursor file of the Sui subscriber is rolled back)
+    /// are skipped, so that their handlers do not fail on duplicate keys. The check, the changes
+    /// of the handler and the record of the event as applied run in a single transaction, so
+    /// that an event is never applied twice, nor partially.
+    ///
+    /// If the handler of the event fails, its changes are rolled back and the event is moved to
+    /// the dead-letter table, to be retried later, rather than stopping the state manager. The
+    /// events of the same object (task, node or stack) which follow it are moved to the
+    /// dead-letter table too, without being applied, so that the events of an object are always
+    /// applied in order (e.g. a claim is never applied before the settlement ticket of its
+    /// stack).
+    ///
+    /// # Returns
+    ///
+    /// Whether the event is applied, either now or previously.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the database queries recording the event fail to
+    /// execute.
+    pub(crate) async fn apply_logged_event(
+        &self,
+        logged_event: &LoggedEvent,
+        atoma_event: AtomaEvent,
+    ) -> Result<bool> {
+        let object_key = atoma_event_object_key(&atoma_event);
+        let state = self.begin().await?;
+        if state.is_event_applied(logged_event).await? {
+            tracing::debug!(
+                target = "atoma-state-manager",
+                event = "event_already_applied",
+                event_name = logged_event.event_name,
+                tx_digest = logged_event.tx_digest,
+                event_seq = logged_event.event_seq,
+                "Event already applied, skipping it"
+            );
+            return Ok(true);
+        }
+        if let Some(object_key) = &object_key {
+            if state
+                .has_earlier_dead_letter_event(object_key, logged_event.id)
+                .await?
+            {
+                tracing::warn!(
+                    target = "atoma-state-manager",
+                    event = "event_held_back",
+                    event_id = logged_event.id,
+                    event_name = logged_event.event_name,
+                    tx_digest = logged_event.tx_digest,
+                    event_seq = logged_event.event_seq,
+                    object_key,
+                    "An earlier event of the same object is dead-lettered, holding back the event"
+                );
+                state
+                    .insert_dead_letter_event(
+                        logged_event,
+                        Some(object_key),
+                        &format!("Held back behind an earlier dead-letter event of `{object_key}`"),
+                    )
+                    .await?;
+                state.commit().await?;
+                return Ok(false);
+            }
+        }
+        match handle_atoma_event(atoma_event, &state, logged_event.checkpoint_timestamp_ms).await {
+            Ok(()) => {
+                state.insert_applied_event(logged_event).await?;
+                state.commit().await?;
+                Ok(true)
+            }
+            Err(e) => {
+                // NOTE: Dropping the transaction rolls back the changes of the handler, and
+                // releases its connection before the event is dead-lettered.
+                drop(state);
+                tracing::error!(
+                    target = "atoma-state-manager",
+                    event = "event_dead_lettered",
+                    event_id = logged_event.id,
+                    event_name = logged_event.event_name,
+                    tx_digest = logged_event.tx_digest,
+                    event_seq = logged_event.event_seq,
+                    error = %e,
+                    "Error applying event, moving it to the dead-letter table"
+                );
+                self.insert_dead_letter_event(logged_event, object_key.as_deref(), &e.to_string())
+                    .await?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Retries to apply the dead-letter events with less than `max_attempts` attempts, in the
+    /// order they were received.
+    ///
+    /// The events applied successfully (or applied in the meantime, if delivered again) are
+    /// removed from the dead-letter table, while the number of attempts of the others is
+    /// increased. The events held back behind an earlier dead-letter event of the same object
+    /// are skipped, without using their attempts, until the earlier event is applied.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database queries fail to execute.
+    /// - A dead-letter event cannot be deserialized.
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub(crate) async fn retry_dead_letter_events(&self, max_attempts: i64) -> Result<()> {
+        let events = self
+            .get_dead_letter_events(max_attempts, EVENT_LOG_PAGE_SIZE)
+            .await?;
+        for logged_event in events {
+            let atoma_event: AtomaEvent = serde_json::from_str(&logged_event.event)?;
+            if let Some(object_key) = atoma_event_object_key(&atoma_event) {
+                if self
+                    .has_earlier_dead_letter_event(&object_key, logged_event.id)
+                    .await?
+                {
+                    continue;
+                }
+            }
+            if self.apply_logged_event(&logged_event, atoma_event).await? {
+                tracing::info!(
+                    target = "atoma-state-manager",
+                    event = "dead_letter_event_applied",
+                    event_id = logged_event.id,
+                    event_name = logged_event.event_name,
+                    tx_digest = logged_event.tx_digest,
+                    "Dead-letter event applied"
+                );
+                self.delete_dead_letter_event(&logged_event).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes an event from the dead-letter table, once applied.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the database query fails to execute.
+    #[tracing::instrument(level = "trace", skip_all, fields(event_id = %event.id))]
+    pub async fn delete_dead_letter_event(&self, event: &LoggedEvent) -> Result<()> {
+        dispatch!(self.storage, |storage| storage
+            .delete_dead_letter_event(event)
+            .await)
+    }
+
+    /// Retrieves the events of the event log, in the order they were recorded.
+    ///
+    /// # Arguments
+    ///
+    /// * `after_id` - Only the events recorded after the event with this position are returned
+    ///   (`0` to start from the beginning of the log).
+    /// * `limit` - The maximum number of events to return.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    /// - There's an issue converting the database rows into `LoggedEvent` objects.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn get_events(&self, after_id: i64, limit: i64) -> Result<Vec<LoggedEvent>> {
+        dispatch!(self.storage, |storage| storage
+            .get_events(after_id, limit)
+            .await)
+    }
+
+    /// Clears the tables derived from the events of the Atoma contract, before rebuilding them
+    /// from the event log.
+    ///
+    /// The applied and dead-letter events are cleared as well, while the event log, the batches
+    /// and the requests in flight are kept.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the database queries fail to execute.
+    #[tracing::instrument(level = "info", skip_all)]
+    pub async fn clear_event_derived_tables(&self) -> Result<()> {
+        dispatch!(self.storage, |storage| storage
+            .clear_event_derived_tables()
+            .await)
+    }
+
+    /// Moves the claimed stacks, whose disputes were settled at or before `max_settled_epoch`,
+    /// to the archive tables, together with their settlement tickets and attestation disputes.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_settled_epoch` - The latest epoch at which the dispute of an archived stack was
+    ///   settled.
+    /// * `epoch` - The current epoch, recorded as the archival epoch of the stacks.
+    ///
+    /// # Returns
+    ///
+    /// - `Result<u64>`: The number of stacks archived.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the database queries fail to execute, in which case
+    /// no stack is archived.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn archive_settled_stacks(&self, max_settled_epoch: i64, epoch: i64) -> Result<u64> {
+        dispatch!(self.storage, |storage| storage
+            .archive_settled_stacks(max_settled_epoch, epoch)
+            .await)
+    }
+
+    /// Retrieves the archived stacks of the given nodes, with their settlement tickets and
+    /// attestation disputes.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_small_ids` - The small IDs of the nodes the stacks were selected for.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database queries fail to execute.
+    /// - There's an issue converting the database rows into `ArchivedStack` objects.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn get_archived_stacks(&self, node_small_ids: &[i64]) -> Result<Vec<ArchivedStack>> {
+        let mut archived_stacks = dispatch!(self.storage, |storage| storage
+            .get_archived_stacks(node_small_ids)
+            .await)?;
+        if archived_stacks.is_empty() {
+            return Ok(archived_stacks);
+        }
+        let stack_small_ids = archived_stacks
+            .iter()
+            .map(|archived_stack| archived_stack.stack.stack_small_id)
+            .collect::<Vec<_>>();
+        let settlement_tickets = dispatch!(self.storage, |storage| storage
+            .get_archived_stack_settlement_tickets(&stack_small_ids)
+            .await)?;
+        let attestation_disputes = dispatch!(self.storage, |storage| storage
+            .get_archived_stack_attestation_disputes(&stack_small_ids)
+            .await)?;
+        for archived_stack in &mut archived_stacks {
+            let stack_small_id = archived_stack.stack.stack_small_id;
+            archived_stack.settlement_tickets = settlement_tickets
+                .iter()
+                .filter(|ticket| ticket.stack_small_id == stack_small_id)
+                .cloned()
+                .collect();
+            archived_stack.attestation_disputes = attestation_disputes
+                .iter()
+                .filter(|dispute| dispute.stack_small_id == stack_small_id)
+                .cloned()
+                .collect();
+        }
+        Ok(archived_stacks)
+    }
+
+    /// Retrieves the lifecycle status of a stack, or `None` if the stack is not in the state.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the database query fails to execute, or if the
+    /// stored status is invalid.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn get_stack_status(&self, stack_small_id: i64) -> Result<Option<StackStatus>> {
+        dispatch!(self.storage, |storage| storage
+            .get_stack_status(stack_small_id)
+            .await)
+    }
+
+    /// Moves a stack to a new lifecycle status, recording the transition in the history of the
+    /// stack.
+    ///
+    /// This is the single place where the transitions of the stacks are validated: moving a
+    /// stack to the status it already has is a no-op, while a transition not allowed by
+    /// `StackStatus::can_transition_to` is rejected. Stacks not in the state are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `stack_small_id` - The small ID of the stack.
+    /// * `to_status` - The new status of the stack.
+    /// * `epoch` - The epoch at which the transition happened, if known.
+    /// * `timestamp_ms` - The time at which the transition happened, in milliseconds since the
+    ///   Unix epoch, if known.
+    /// * `triggering_event` - The name of the event which triggered the transition.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The transition is not allowed from the current status of the stack, in which case the
+    ///   status is left unchanged.
+    /// - The database queries fail to execute.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn transition_stack_status(
+        &self,
+        stack_small_id: i64,
+        to_status: StackStatus,
+        epoch: Option<i64>,
+        timestamp_ms: Option<i64>,
+        triggering_event: &str,
+    ) -> Result<()> {
+        // NOTE: The status is only updated if it did not change since it was validated,
+        // otherwise the transition is validated again against the new status.
+        loop {
+            let Some(from_status) = self.get_stack_status(stack_small_id).await? else {
+                return Ok(());
+            };
+            if from_status == to_status {
+                return Ok(());
+            }
+            if !from_status.can_transition_to(to_status) {
+                return Err(AtomaStateManagerError::InvalidStackStatusTransition {
+                    stack_small_id,
+                    from: from_status,
+                    to: to_status,
+                });
+            }
+            let updated = dispatch!(self.storage, |storage| storage
+                .update_stack_status(
+                    stack_small_id,
+                    from_status,
+                    to_status,
+                    epoch,
+                    timestamp_ms,
+                    triggering_event
+                )
+                .await)?;
+            if updated {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Moves a stack to the lifecycle status reported by an event of the Atoma contract.
+    ///
+    /// The chain is the source of truth, so unlike [`Self::transition_stack_status`], a
+    /// transition not allowed from the current status of the stack is not an error: events can
+    /// be missed or arrive out of order (e.g. a dispute on a stack whose try settle event was
+    /// missed). If the reported status is further in the lifecycle of the stack, the stack is
+    /// forced to it, and the transition is recorded in the history of the stack. Otherwise, the
+    /// event is late (e.g. a settlement ticket event after the stack was claimed), and the status
+    /// is left unchanged. Stacks not in the state are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `stack_small_id` - The small ID of the stack.
+    /// * `to_status` - The status reported by the event.
+    /// * `epoch` - The epoch at which the transition happened, if known.
+    /// * `timestamp_ms` - The time at which the transition happened, in milliseconds since the
+    ///   Unix epoch, if known.
+    /// * `triggering_event` - The name of the event which reported the status.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the database queries fail to execute.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn apply_chain_stack_status(
+        &self,
+        stack_small_id: i64,
+        to_status: StackStatus,
+        epoch: Option<i64>,
+        timestamp_ms: Option<i64>,
+        triggering_event: &str,
+    ) -> Result<()> {
+        loop {
+            let Some(from_status) = self.get_stack_status(stack_small_id).await? else {
+                return Ok(());
+            };
+            if from_status == to_status {
+                return Ok(());
+            }
+            if !from_status.can_transition_to(to_status) {
+                if from_status.stage() >= to_status.stage() {
+                    tracing::warn!(
+                        target = "atoma-state-manager",
+                        event = "late_stack_status_transition",
+                        stack_small_id,
+                        from = %from_status,
+                        to = %to_status,
+                        triggering_event,
+                        "Ignoring stack status reported by a late event"
+                    );
+                    return Ok(());
+                }
+                tracing::warn!(
+                    target = "atoma-state-manager",
+                    event = "forced_stack_status_transition",
+                    stack_small_id,
+                    from = %from_status,
+                    to = %to_status,
+                    triggering_event,
+                    "Forcing stack status reported by an out of order event"
+                );
+            }
+            let updated = dispatch!(self.storage, |storage| storage
+                .update_stack_status(
+                    stack_small_id,
+                    from_status,
+                    to_status,
+                    epoch,
+                    timestamp_ms,
+                    triggering_event
+                )
+                .await)?;
+            if updated {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Retrieves the history of the lifecycle status transitions of a stack, in the order they
+    /// happened.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    /// - There's an issue converting the database rows into `StackStatusTransition` objects.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn get_stack_status_transitions(
+        &self,
+        stack_small_id: i64,
+    ) -> Result<Vec<StackStatusTransition>> {
+        dispatch!(self.storage, |storage| storage
+            .get_stack_status_transitions(stack_small_id)
+            .await)
+    }
+
+    /// Records the usage of a stack by requests served by the node, aggregated per day.
+    ///
+    /// # Arguments
+    ///
+    /// * `stack_small_id` - The small ID of the stack.
+    /// * `timestamp_ms` - The time at which the requests were served, in milliseconds since the
+    ///   Unix epoch.
+    /// * `num_requests` - The number of requests served (e.g. the completed lines of a batch).
+    /// * `compute_units` - The number of compute units consumed by the requests.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the database query fails to execute.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn record_stack_usage(
+        &self,
+        stack_small_id: i64,
+        timestamp_ms: i64,
+        num_requests: i64,
+        compute_units: i64,
+    ) -> Result<()> {
+        dispatch!(self.storage, |storage| storage
+            .record_stack_usage(stack_small_id, timestamp_ms, num_requests, compute_units)
+            .await)
+    }
+
+    /// Retrieves the earnings report of the given nodes, per day, task and stack owner.
+    ///
+    /// The report includes both the live and the archived stacks.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_small_ids` - The small IDs of the nodes the stacks were selected for.
+    /// * `from_day` - The first day of the report (UTC, `YYYY-MM-DD`), inclusive.
+    /// * `to_day` - The last day of the report (UTC, `YYYY-MM-DD`), inclusive.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    /// - There's an issue converting the database rows into `EarningsReportRow` objects.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn get_earnings_report(
+        &self,
+        node_small_ids: &[i64],
+        from_day: Option<&str>,
+        to_day: Option<&str>,
+    ) -> Result<Vec<EarningsReportRow>> {
+        dispatch!(self.storage, |storage| storage
+            .get_earnings_report(node_small_ids, from_day, to_day)
+            .await)
+    }
+
+    /// Retrieves the counts of requests in flight of all the owners, whose lease has not expired.
+    ///
+    /// # Arguments
+    ///
+    /// * `lease` - Time after which the counts that were not refreshed are ignored.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database query fails to execute.
+    /// - There's an issue converting the database rows into `ConcurrentRequestsCount` objects.
+    #[tracing::instrument(level = "trace", skip_all)]
+    pub async fn get_concurrent_requests(
+        &self,
+        lease: Duration,
+    ) -> Result<Vec<ConcurrentRequestsCount>> {
+        dispatch!(self.storage, |storage| storage
+            .get_concurrent_requests(lease)
+            .await)
+    }
+
+    /// Renews the lease of all the counts of requests in flight of `owner`.
+    ///
+    /// Owners must refresh their counts more often than the lease expires, otherwise they are
+    /// considered gone, and their counts are pruned (see [`Self::prune_concurrent_requests`]).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the database query fails to execute.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn refresh_concurrent_requests(&self, owner: &str) -> Result<()> {
+        dispatch!(self.storage, |storage| storage
+            .refresh_concurrent_requests(owner)
+            .await)
+    }
+
+    /// Removes the counts of requests in flight whose lease expired, e.g. left by a replica that
+    /// crashed or was scaled down, and notifies them as zero on the `channel` Postgres
+    /// notification channel. As for [`Self::update_concurrent_requests`], notifications are only
+    /// sent by Postgres.
+    ///
+    /// # Returns
+    ///
+    /// The counts that were removed.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if:
+    /// - The database queries fail to execute.
+    /// - A notification payload cannot be serialized.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn prune_concurrent_requests(
+        &self,
+        lease: Duration,
+        channel: &str,
+    ) -> Result<Vec<ConcurrentRequestsCount>> {
+        dispatch!(self.storage, |storage| storage
+            .prune_concurrent_requests(lease, channel)
+            .await)
+    }
+
+    /// Sends a notification with `payload` on the `channel` Postgres notification channel.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AtomaStateManagerError::DatabaseConnectionError` if the query fails, or
+    /// `AtomaStateManagerError::UnsupportedStorageBackend` if the state is not stored in Postgres.
+    #[tracing::instrument(level = "trace", skip(self, payload))]
+    pub async fn notify(&self, channel: &str, payload: &str) -> Result<()> {
+        self.storage
+            .postgres("Notifications")?
+            .notify(channel, payload)
+            .await
+    }
+
+    /// Returns a listener receiving the notifications sent on the given Postgres notification
+    /// channels.
+    ///
+    /// The listener reconnects to the database if its connection is lost, notifications sent
+    /// in the meantime are lost.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AtomaStateManagerError::DatabaseConnectionError` if the listener cannot connect
+    /// to the database, or listen on the channels, or
+    /// `AtomaStateManagerError::UnsupportedStorageBackend` if the state is not stored in Postgres.
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn listen(&self, channels: &[&str]) -> Result<PgListener> {
+        self.storage
+            .postgres("Listening to notifications")?
+            .listen(channels)
+            .await
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum AtomaStateManagerError {
+    #[error("Failed to connect to the database: {0}")]
+    DatabaseConnectionError(#[from] sqlx::Error),
+    #[error("Stack not found")]
+    StackNotFound,
+    #[error("Attestation node not found: {0}")]
+    AttestationNodeNotFound(i64),
+    #[error("Invalid Merkle leaf length")]
+    InvalidMerkleLeafLength,
+    #[error("Invalid committed stack proof length")]
+    InvalidCommittedStackProofLength,
+    #[error("Failed to parse JSON: {0}")]
+    JsonParseError(#[from] serde_json::Error),
+    #[error("Failed to retrieve existing total hash for stack: `{0}`")]
+    FailedToRetrieveExistingTotalHash(i64),
+    #[error("Failed to send result to channel")]
+    ChannelSendError,
+    #[error("Failed to create database directory")]
+    FailedToCreateDatabaseDirectory(#[from] std::io::Error),
+    #[error("Failed to migrate database")]
+    FailedToMigrateDatabase(#[from] sqlx::migrate::MigrateError),
+    #[error("Node small ID ownership verification failed")]
+    NodeSmallIdOwnershipVerificationFailed,
+    #[error("Sui client error: {0}")]
+    SuiClientError(#[from] atoma_sui::client::AtomaSuiClientError),
+    #[error("Invalid SQL query: {0}")]
+    InvalidSqlQuery(String),
+    #[error("Batch not found: `{0}`")]
+    BatchNotFound(String),
+    #[error("Unsupported storage backend: {0}")]
+    UnsupportedStorageBackend(String),
+    #[error("Transaction still in use, it cannot be committed")]
+    TransactionInUse,
+    #[error("Invalid stack status: `{0}`")]
+    InvalidStackStatus(String),
+    #[error("Invalid status transition of stack `{stack_small_id}`, from `{from}` to `{to}`")]
+    InvalidStackStatusTransition {
+        stack_small_id: i64,
+        from: StackStatus,
+        to: StackStatus,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        BATCH_REQUEST_STATUS_COMPLETED, BATCH_REQUEST_STATUS_PENDING, BATCH_STATUS_COMPLETED,
+        BATCH_STATUS_IN_PROGRESS,
+    };
+    #[cfg(not(feature = "sqlite"))]
+    use atoma_utils::test::POSTGRES_TEST_DB_URL as TEST_DB_URL;
+    #[cfg(feature = "sqlite")]
+    use atoma_utils::test::SQLITE_TEST_DB_URL as TEST_DB_URL;
+
+    /// Tables of the Atoma state, cleared after each test. Children tables come first, so that
+    /// they can be cleared in order on SQLite.
+    const TABLES: [&str; 19] = [
+        "tasks",
+        "nodes",
+        "node_subscriptions",
+        "node_model_subscriptions",
+        "stacks",
+        "stack_settlement_tickets",
+        "stack_attestation_disputes",
+        "node_public_key_rotations",
+        "archived_stack_attestation_disputes",
+        "archived_stack_settlement_tickets",
+        "archived_stacks",
+        "stack_status_transitions",
+        "stack_usage",
+        "batch_requests",
+        "batches",
+        "concurrent_requests",
+        "event_log",
+        "applied_events",
+        "dead_letter_events",
+    ];
+
+    /// A row of the `node_public_key_rotations` table.
+    #[derive(sqlx::FromRow)]
+    struct NodePublicKeyRotationRow {
+        epoch: i64,
+        key_rotation_counter: i64,
+        node_small_id: i64,
+        public_key_bytes: Vec<u8>,
+        evidence_data_bytes: Vec<u8>,
+        device_type: i32,
+    }
+
+    async fn setup_test_db() -> AtomaState {
+        AtomaState::new_from_url(TEST_DB_URL).await.unwrap()
+    }
+
+    async fn truncate_tables(state: &AtomaState) {
+        match &state.storage {
+            Storage::Postgres(storage) => {
+                sqlx::query(&format!("TRUNCATE TABLE {} CASCADE", TABLES.join(", ")))
+                    .execute(&storage.db)
+                    .await
+                    .expect("Failed to truncate tables");
+            }
+            #[cfg(feature = "sqlite")]
+            Storage::Sqlite(storage) => {
+                for table in TABLES {
+                    sqlx::query(&format!("DELETE FROM {table}"))
+                        .execute(&storage.db)
+                        .await
+                        .expect("Failed to truncate tables");
+                }
+            }
+        }
+    }
+
+    async fn get_node_public_key_rotation(
+        state: &AtomaState,
+        node_small_id: u64,
+    ) -> Result<NodePublicKeyRotationRow> {
+        let query = "SELECT * FROM node_public_key_rotations WHERE node_small_id = $1";
+        let row = dispatch!(state.storage, |storage| sqlx::query_as(query)
+            .bind(node_small_id as i64)
+            .fetch_one(&storage.db)
+            .await?);
+        Ok(row)
+    }
+
+    async fn count_node_public_key_rotations(
+        state: &AtomaState,
+        node_small_id: Option<u64>,
+    ) -> Result<i64> {
+        let query = "SELECT COUNT(*) FROM node_public_key_rotations \
+            WHERE $1 IS NULL OR node_small_id = $1";
+        let count = dispatch!(state.storage, |storage| sqlx::query_scalar(query)
+            .bind(node_small_id.map(|id| id as i64))
+            .fetch_one(&storage.db)
+            .await?);
+        Ok(count)
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_insert_and_get_task() {
+        let state_manager = setup_test_db().await;
+
+        let task = Task {
+            task_small_id: 1,
+            task_id: "task1".to_string(),
+            role: 1,
+            model_name: Some("model1".to_string()),
+            is_deprecated: false,
+            valid_until_epoch: Some(100),
+            deprecated_at_epoch: None,
+            security_level: 1,
+            minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
+        };
+
+        state_manager.insert_new_task(task.clone()).await.unwrap();
+        let retrieved_task = state_manager.get_task_by_small_id(1).await.unwrap();
+        assert_eq!(task, retrieved_task);
+
+        truncate_tables(&state_manager).await;
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_deprecate_task() {
+        let state_manager = setup_test_db().await;
+
+        let task = Task {
+            task_small_id: 1,
+            task_id: "task1".to_string(),
+            role: 1,
+            model_name: Some("model1".to_string()),
+            is_deprecated: false,
+            valid_until_epoch: Some(100),
+            deprecated_at_epoch: None,
+            security_level: 1,
+            minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
+        };
+
+        state_manager.insert_new_task(task).await.unwrap();
+        state_manager.deprecate_task(1, 100).await.unwrap();
+
+        let deprecated_task = state_manager.get_task_by_small_id(1).await.unwrap();
+        assert!(deprecated_task.is_deprecated);
+
+        truncate_tables(&state_manager).await;
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_get_node_model_tasks() {
+        let state_manager = setup_test_db().await;
+
+        for (task_small_id, model_name) in [(1, "model1"), (2, "model1"), (3, "model2")] {
+            state_manager
+                .insert_new_task(Task {
+                    task_small_id,
+                    task_id: format!("task{task_small_id}"),
+                    role: 1,
+                    model_name: Some(model_name.to_string()),
+                    is_deprecated: false,
+                    valid_until_epoch: None,
+                    deprecated_at_epoch: None,
+                    security_level: 1,
+                    minimum_reputation_score: None,
+                    removed_at_epoch: None,
+                })
+                .await
+                .unwrap();
+            state_manager
+                .subscribe_node_to_task(1, task_small_id, 100, 1000)
+                .await
+                .unwrap();
+        }
+        // Subscriptions of other nodes are ignored
+        state_manager
+            .subscribe_node_to_model(2, "model4", 1)
+            .await
+            .unwrap();
+        state_manager
+            .subscribe_node_to_model(1, "model3", 1)
+            .await
+            .unwrap();
+        state_manager.deprecate_task(1, 10).await.unwrap();
+        state_manager.remove_task(3, 20).await.unwrap();
+
+        let removed_task = state_manager.get_task_by_small_id(3).await.unwrap();
+        assert!(removed_task.is_deprecated);
+        assert_eq!(removed_task.deprecated_at_epoch, Some(20));
+        assert_eq!(removed_task.removed_at_epoch, Some(20));
+
+        let models = state_manager
+            .get_node_model_tasks(Some(&[1]))
+            .await
+            .unwrap();
+        assert_eq!(
+            models,
+            vec![
+                ModelTasks {
+                    model_name: "model1".to_string(),
+                    active_task_small_ids: vec![2],
+                    inactive_task_small_ids: vec![1],
+                },
+                ModelTasks {
+                    model_name: "model2".to_string(),
+                    active_task_small_ids: vec![],
+                    inactive_task_small_ids: vec![3],
+                },
+                ModelTasks {
+                    model_name: "model3".to_string(),
+                    active_task_small_ids: vec![],
+                    inactive_task_small_ids: vec![],
+                },
+            ]
+        );
+        assert!(models[0].is_active());
+        assert!(!models[1].is_active());
+        assert!(models[2].is_active());
+        assert_eq!(
+            state_manager
+                .get_node_model_tasks(None)
+                .await
+                .unwrap()
+                .len(),
+            4
+        );
+
+        truncate_tables(&state_manager).await;
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_get_subscribed_tasks() {
+        let state_manager = setup_test_db().await;
 
         // Insert two tasks
         let task1 = Task {
@@ -2618,6 +3638,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         let task2 = Task {
             task_small_id: 2,
@@ -2629,6 +3650,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task1.clone()).await.unwrap();
         state_manager.insert_new_task(task2).await.unwrap();
@@ -2643,7 +3665,7 @@ mod tests {
         assert_eq!(subscribed_tasks.len(), 1);
         assert_eq!(subscribed_tasks[0], task1);
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -2674,7 +3696,7 @@ mod tests {
         assert_eq!(node.node_id, node_id);
         assert_eq!(node.node_sui_address, node_sui_address);
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -2690,15 +3712,14 @@ mod tests {
         ];
 
         for (node_small_id, node_sui_address) in nodes.clone() {
-            sqlx::query(
-                "INSERT INTO nodes (node_small_id, node_id, node_sui_address) VALUES ($1, $2, $3)",
-            )
-            .bind(node_small_id)
-            .bind(format!("node{node_small_id}"))
-            .bind(node_sui_address.clone())
-            .execute(&state_manager.db)
-            .await
-            .unwrap();
+            state_manager
+                .insert_node_registration_event(
+                    node_small_id,
+                    format!("node{node_small_id}"),
+                    node_sui_address,
+                )
+                .await
+                .unwrap();
         }
 
         // Test 1: Verify correct ownership
@@ -2827,7 +3848,7 @@ mod tests {
             "Should return NodeSmallIdOwnershipVerificationFailed error"
         );
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -2846,6 +3867,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
 
@@ -2877,7 +3899,7 @@ mod tests {
         assert_eq!(subscription.price_per_one_million_compute_units, 200);
         assert_eq!(subscription.max_num_compute_units, 2000);
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -2896,6 +3918,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
         state_manager
@@ -2918,6 +3941,7 @@ mod tests {
             num_total_messages: 0,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         };
         state_manager.insert_new_stack(stack.clone()).await.unwrap();
 
@@ -2925,7 +3949,7 @@ mod tests {
         let retrieved_stack = state_manager.get_stack(1).await.unwrap();
         assert_eq!(stack, retrieved_stack);
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -2944,6 +3968,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
         state_manager
@@ -2966,6 +3991,7 @@ mod tests {
             num_total_messages: 0,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         };
         state_manager.insert_new_stack(initial_stack).await.unwrap();
 
@@ -3012,7 +4038,7 @@ mod tests {
         let limit_stack = state_manager.get_stack(1).await.unwrap();
         assert_eq!(limit_stack.already_computed_units, 100);
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -3031,6 +4057,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
         state_manager
@@ -3053,6 +4080,7 @@ mod tests {
             num_total_messages: 0,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         };
 
         // Test case 1: First insertion should succeed
@@ -3070,7 +4098,7 @@ mod tests {
         assert_eq!(all_stacks.len(), 1);
         assert_eq!(all_stacks[0], stack);
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -3089,6 +4117,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
         state_manager
@@ -3109,6 +4138,7 @@ mod tests {
             num_total_messages: 0,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         };
         state_manager.insert_new_stack(stack).await.unwrap();
 
@@ -3123,7 +4153,7 @@ mod tests {
         let updated_stack = state_manager.get_stack(1).await.unwrap();
         assert_eq!(updated_stack.already_computed_units, 15);
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -3142,6 +4172,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
         state_manager
@@ -3162,6 +4193,7 @@ mod tests {
             num_total_messages: 0,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         };
         state_manager.insert_new_stack(stack).await.unwrap();
 
@@ -3188,7 +4220,7 @@ mod tests {
         let retrieved_ticket = state_manager.get_stack_settlement_ticket(1).await.unwrap();
         assert_eq!(ticket, retrieved_ticket);
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -3207,6 +4239,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
         state_manager
@@ -3227,6 +4260,7 @@ mod tests {
             num_total_messages: 0,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         };
         state_manager.insert_new_stack(stack).await.unwrap();
         let initial_ticket = StackSettlementTicket {
@@ -3287,7 +4321,7 @@ mod tests {
         assert_eq!(claimed_ticket.user_refund_amount, user_refund_amount);
         assert!(claimed_ticket.is_claimed);
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -3306,6 +4340,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
         state_manager
@@ -3326,6 +4361,7 @@ mod tests {
             num_total_messages: 0,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         };
         state_manager.insert_new_stack(stack).await.unwrap();
         let ticket = StackSettlementTicket {
@@ -3404,14 +4440,14 @@ mod tests {
             .unwrap();
         assert_eq!(disputes.len(), 1);
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
     #[serial_test::serial]
     async fn test_get_available_stack_with_compute_units() {
         let state_manager = setup_test_db().await;
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
 
         // Setup: Insert a task and subscribe a node
         let task = Task {
@@ -3424,6 +4460,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
         state_manager
@@ -3446,6 +4483,7 @@ mod tests {
             num_total_messages: 0,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         };
         state_manager
             .insert_new_stack(stack1.clone())
@@ -3630,7 +4668,7 @@ mod tests {
         let updated_stack = state_manager.get_stack(7).await.unwrap();
         assert_eq!(updated_stack.already_computed_units, 0); // Unchanged
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -3649,6 +4687,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
         state_manager
@@ -3671,6 +4710,7 @@ mod tests {
             num_total_messages: 0,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         };
         state_manager.insert_new_stack(stack).await.unwrap();
 
@@ -3705,7 +4745,7 @@ mod tests {
         let result = state_manager.update_stack_total_hash(999, new_hash).await;
         assert!(matches!(result, Err(AtomaStateManagerError::StackNotFound)));
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -3724,6 +4764,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
 
@@ -3804,7 +4845,7 @@ mod tests {
         assert_eq!(subscription.max_num_compute_units, 1000);
         assert!(subscription.valid);
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -3823,6 +4864,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
 
@@ -3857,6 +4899,7 @@ mod tests {
                 num_total_messages: 1,
                 is_claimed: false,
                 is_locked_for_claim: false,
+                status: StackStatus::Active,
             },
             Stack {
                 owner_address: "owner2".to_string(),
@@ -3872,6 +4915,7 @@ mod tests {
                 num_total_messages: 2,
                 is_claimed: false,
                 is_locked_for_claim: false,
+                status: StackStatus::Active,
             },
             Stack {
                 owner_address: "owner3".to_string(),
@@ -3887,6 +4931,7 @@ mod tests {
                 num_total_messages: 3,
                 is_claimed: false,
                 is_locked_for_claim: false,
+                status: StackStatus::Active,
             },
         ];
 
@@ -4007,7 +5052,7 @@ mod tests {
             "Should find stack not in settle period"
         );
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -4026,6 +5071,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
 
@@ -4050,6 +5096,7 @@ mod tests {
             num_total_messages: 0,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         };
 
         let stack2 = Stack {
@@ -4066,6 +5113,7 @@ mod tests {
             num_total_messages: 0,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         };
 
         // Insert test stacks
@@ -4088,7 +5136,7 @@ mod tests {
         let empty_stacks = state_manager.get_stack_by_id(999).await.unwrap();
         assert!(empty_stacks.is_empty());
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -4107,6 +5155,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
 
@@ -4135,6 +5184,7 @@ mod tests {
             num_total_messages: 0,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         };
 
         let stack2 = Stack {
@@ -4151,6 +5201,7 @@ mod tests {
             num_total_messages: 0,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         };
 
         state_manager
@@ -4171,7 +5222,7 @@ mod tests {
         assert_eq!(node2_stacks.len(), 1);
         assert_eq!(node2_stacks[0], stack2);
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -4190,6 +5241,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
 
@@ -4224,6 +5276,7 @@ mod tests {
                 num_total_messages: 0,
                 is_claimed: false,
                 is_locked_for_claim: false,
+                status: StackStatus::Active,
             },
             // Stack 50% filled for node 1
             Stack {
@@ -4240,6 +5293,7 @@ mod tests {
                 num_total_messages: 0,
                 is_claimed: false,
                 is_locked_for_claim: false,
+                status: StackStatus::Active,
             },
             // Stack 95% filled for node 2
             Stack {
@@ -4256,6 +5310,7 @@ mod tests {
                 num_total_messages: 0,
                 is_claimed: false,
                 is_locked_for_claim: false,
+                status: StackStatus::Active,
             },
             // Stack 100% filled for node 3
             Stack {
@@ -4272,6 +5327,7 @@ mod tests {
                 num_total_messages: 0,
                 is_claimed: false,
                 is_locked_for_claim: false,
+                status: StackStatus::Active,
             },
         ];
 
@@ -4340,7 +5396,7 @@ mod tests {
             .iter()
             .all(|s| { (s.already_computed_units as f64 / s.num_compute_units as f64) > 0.8 }));
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -4359,6 +5415,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
         state_manager
@@ -4381,6 +5438,7 @@ mod tests {
             num_total_messages: 0,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         };
         state_manager.insert_new_stack(zero_stack).await.unwrap();
 
@@ -4399,6 +5457,7 @@ mod tests {
             num_total_messages: 0,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         };
         state_manager.insert_new_stack(large_stack).await.unwrap();
 
@@ -4426,7 +5485,7 @@ mod tests {
             );
         }
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -4445,6 +5504,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
         state_manager
@@ -4465,6 +5525,7 @@ mod tests {
             num_total_messages: 0,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         };
         state_manager.insert_new_stack(stack).await.unwrap();
 
@@ -4510,7 +5571,7 @@ mod tests {
             .unwrap();
         assert!(disputes.is_empty());
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -4529,6 +5590,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
         state_manager
@@ -4549,6 +5611,7 @@ mod tests {
             num_total_messages: 0,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         };
         state_manager.insert_new_stack(stack).await.unwrap();
 
@@ -4620,7 +5683,7 @@ mod tests {
             .unwrap();
         assert!(disputes.is_empty());
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -4639,6 +5702,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
         state_manager
@@ -4666,6 +5730,7 @@ mod tests {
                 num_total_messages: 0,
                 is_claimed: false,
                 is_locked_for_claim: false,
+                status: StackStatus::Active,
             },
             Stack {
                 owner_address: "owner2".to_string(),
@@ -4681,6 +5746,7 @@ mod tests {
                 num_total_messages: 0,
                 is_claimed: false,
                 is_locked_for_claim: false,
+                status: StackStatus::Active,
             },
             Stack {
                 owner_address: "owner3".to_string(),
@@ -4696,6 +5762,7 @@ mod tests {
                 num_total_messages: 0,
                 is_claimed: false,
                 is_locked_for_claim: false,
+                status: StackStatus::Active,
             },
         ];
 
@@ -4774,14 +5841,14 @@ mod tests {
         let claimed_stacks_none = state_manager.get_claimed_stacks(&[99]).await.unwrap();
         assert!(claimed_stacks_none.is_empty());
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
     #[serial_test::serial]
     async fn test_get_all_total_hashes() {
         let state_manager = setup_test_db().await;
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
         // Setup: Create a Task
         let task = Task {
             task_small_id: 1,
@@ -4793,6 +5860,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
 
@@ -4819,6 +5887,7 @@ mod tests {
             in_settle_period: false,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
             total_hash: hash1.clone(),
             num_total_messages: 0,
         };
@@ -4844,6 +5913,7 @@ mod tests {
             in_settle_period: false,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
             total_hash: hash2.clone(),
             num_total_messages: 0,
         };
@@ -4860,6 +5930,7 @@ mod tests {
             in_settle_period: false,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
             total_hash: hash3.clone(),
             num_total_messages: 0,
         };
@@ -4907,7 +5978,7 @@ mod tests {
         assert_eq!(result.len(), 1, "Duplicate IDs should return single result");
         assert_eq!(result[0], hash1, "Hash should match for duplicate IDs");
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -4926,6 +5997,7 @@ mod tests {
             deprecated_at_epoch: None,
             security_level: 1,
             minimum_reputation_score: Some(50),
+            removed_at_epoch: None,
         };
         state_manager.insert_new_task(task).await.unwrap();
 
@@ -4953,6 +6025,7 @@ mod tests {
                 num_total_messages: 0,
                 is_claimed: false,
                 is_locked_for_claim: false,
+                status: StackStatus::Active,
             },
             Stack {
                 owner_address: "owner2".to_string(),
@@ -4968,6 +6041,7 @@ mod tests {
                 num_total_messages: 0,
                 is_claimed: false,
                 is_locked_for_claim: false,
+                status: StackStatus::Active,
             },
             Stack {
                 owner_address: "owner3".to_string(),
@@ -4983,6 +6057,7 @@ mod tests {
                 num_total_messages: 0,
                 is_claimed: false,
                 is_locked_for_claim: false,
+                status: StackStatus::Active,
             },
         ];
 
@@ -5125,7 +6200,7 @@ mod tests {
             "Should contain all tickets"
         );
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
     }
 
     #[tokio::test]
@@ -5152,23 +6227,14 @@ mod tests {
             .await?;
 
         // Verify the insertion
-        let row = sqlx::query("SELECT * FROM node_public_key_rotations WHERE node_small_id = $1")
-            .bind(node_small_id as i64)
-            .fetch_one(&state_manager.db)
-            .await?;
+        let row = get_node_public_key_rotation(&state_manager, node_small_id).await?;
 
-        assert_eq!(row.get::<i64, _>("epoch"), epoch as i64);
-        assert_eq!(
-            row.get::<i64, _>("key_rotation_counter"),
-            key_rotation_counter as i64
-        );
-        assert_eq!(row.get::<i64, _>("node_small_id"), node_small_id as i64);
-        assert_eq!(row.get::<Vec<u8>, _>("public_key_bytes"), public_key_bytes);
-        assert_eq!(
-            row.get::<Vec<u8>, _>("evidence_data_bytes"),
-            tee_remote_attestation_bytes
-        );
-        assert_eq!(row.get::<i32, _>("device_type"), 0);
+        assert_eq!(row.epoch, epoch as i64);
+        assert_eq!(row.key_rotation_counter, key_rotation_counter as i64);
+        assert_eq!(row.node_small_id, node_small_id as i64);
+        assert_eq!(row.public_key_bytes, public_key_bytes);
+        assert_eq!(row.evidence_data_bytes, tee_remote_attestation_bytes);
+        assert_eq!(row.device_type, 0);
 
         // Test update with new values
         let new_epoch = 999u64;
@@ -5186,42 +6252,27 @@ mod tests {
             )
             .await?;
 
-        // Verify the update
-        let updated_row =
-            sqlx::query("SELECT * FROM node_public_key_rotations WHERE node_small_id = $1")
-                .bind(node_small_id as i64)
-                .fetch_one(&state_manager.db)
-                .await?;
-
-        assert_eq!(updated_row.get::<i64, _>("epoch"), new_epoch as i64);
-        assert_eq!(
-            updated_row.get::<i64, _>("key_rotation_counter"),
-            new_key_rotation_counter as i64
-        );
-        assert_eq!(
-            updated_row.get::<i64, _>("node_small_id"),
-            node_small_id as i64
-        );
+        // Verify the update
+        let updated_row = get_node_public_key_rotation(&state_manager, node_small_id).await?;
+
+        assert_eq!(updated_row.epoch, new_epoch as i64);
         assert_eq!(
-            updated_row.get::<Vec<u8>, _>("public_key_bytes"),
-            new_public_key_bytes
+            updated_row.key_rotation_counter,
+            new_key_rotation_counter as i64
         );
+        assert_eq!(updated_row.node_small_id, node_small_id as i64);
+        assert_eq!(updated_row.public_key_bytes, new_public_key_bytes);
         assert_eq!(
-            updated_row.get::<Vec<u8>, _>("evidence_data_bytes"),
+            updated_row.evidence_data_bytes,
             new_tee_remote_attestation_bytes
         );
 
         // Verify only one row exists
-        let count = sqlx::query(
-            "SELECT COUNT(*) as count FROM node_public_key_rotations WHERE node_small_id = $1",
-        )
-        .bind(node_small_id as i64)
-        .fetch_one(&state_manager.db)
-        .await?;
+        let count = count_node_public_key_rotations(&state_manager, Some(node_small_id)).await?;
 
-        assert_eq!(count.get::<i64, _>("count"), 1);
+        assert_eq!(count, 1);
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
         Ok(())
     }
 
@@ -5229,7 +6280,7 @@ mod tests {
     #[serial_test::serial]
     async fn test_insert_node_public_key_rotation_multiple_nodes() -> Result<()> {
         let state_manager = setup_test_db().await;
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
 
         // Test data for multiple nodes
         let nodes = [
@@ -5254,29 +6305,20 @@ mod tests {
 
         // Verify all insertions
         for (node_id, pub_key, tee_bytes) in &nodes {
-            let row =
-                sqlx::query("SELECT * FROM node_public_key_rotations WHERE node_small_id = $1")
-                    .bind(*node_id as i64)
-                    .fetch_one(&state_manager.db)
-                    .await?;
+            let row = get_node_public_key_rotation(&state_manager, *node_id).await?;
 
-            assert_eq!(row.get::<i64, _>("node_small_id"), *node_id as i64);
-            assert_eq!(row.get::<Vec<u8>, _>("public_key_bytes"), *pub_key);
-            assert_eq!(row.get::<Vec<u8>, _>("evidence_data_bytes"), *tee_bytes);
-            assert_eq!(
-                row.get::<i32, _>("device_type"),
-                i32::try_from(*node_id).unwrap()
-            );
+            assert_eq!(row.node_small_id, *node_id as i64);
+            assert_eq!(row.public_key_bytes, *pub_key);
+            assert_eq!(row.evidence_data_bytes, *tee_bytes);
+            assert_eq!(row.device_type, i32::try_from(*node_id).unwrap());
         }
 
         // Verify total count
-        let count = sqlx::query("SELECT COUNT(*) as count FROM node_public_key_rotations")
-            .fetch_one(&state_manager.db)
-            .await?;
+        let count = count_node_public_key_rotations(&state_manager, None).await?;
 
-        assert_eq!(count.get::<i64, _>("count"), 3);
+        assert_eq!(count, 3);
 
-        truncate_tables(&state_manager.db).await;
+        truncate_tables(&state_manager).await;
         Ok(())
     }
 
@@ -5300,25 +6342,297 @@ mod tests {
             )
             .await?;
 
-        let row = sqlx::query("SELECT * FROM node_public_key_rotations WHERE node_small_id = $1")
-            .bind(node_small_id as i64)
-            .fetch_one(&state_manager.db)
-            .await?;
+        let row = get_node_public_key_rotation(&state_manager, node_small_id).await?;
+
+        assert_eq!(row.public_key_bytes, empty_bytes);
+        assert_eq!(row.evidence_data_bytes, empty_bytes);
+
+        truncate_tables(&state_manager).await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    #[allow(clippy::cast_possible_truncation)]
+    async fn test_update_stack_num_compute_units() -> Result<()> {
+        let state = setup_test_db().await;
+        truncate_tables(&state).await;
+        // Create a test task with known values
+        state
+            .insert_new_task(Task {
+                task_small_id: 1,
+                task_id: "0x1".to_string(),
+                role: 1,
+                model_name: None,
+                is_deprecated: false,
+                valid_until_epoch: None,
+                deprecated_at_epoch: None,
+                security_level: 1,
+                minimum_reputation_score: None,
+                removed_at_epoch: None,
+            })
+            .await?;
+        // Create a test stack with known values
+        let stack = Stack {
+            stack_small_id: 1,
+            task_small_id: 1,
+            num_compute_units: 1000,
+            already_computed_units: 500,
+            owner_address: "0x1".to_string(),
+            stack_id: "0x1".to_string(),
+            selected_node_id: 1,
+            price_per_one_million_compute_units: 1000,
+            in_settle_period: false,
+            total_hash: vec![0; 32],
+            num_total_messages: 0,
+            is_claimed: false,
+            is_locked_for_claim: false,
+            status: StackStatus::Active,
+        };
+        state.insert_new_stack(stack).await?;
+
+        // Test case 1: Normal update - estimated is higher than actual
+        let UpdateStackNumComputeUnitsAndClaimFunds {
+            ratio,
+            stack_computed_units,
+            is_confidential,
+            ..
+        } = state
+            .update_stack_num_compute_units(1, 200, 100, 0.95, 0)
+            .await?;
+        assert_eq!((ratio * 100.0) as i64, 40); // (500 - (200 - 100)) / 1000 = 400 / 1000 = 0.4
+        assert_eq!(stack_computed_units, 400);
+        assert!(is_confidential);
+
+        // Test case 2: Normal update - estimated is lower than actual
+        let UpdateStackNumComputeUnitsAndClaimFunds {
+            ratio,
+            stack_computed_units,
+            is_confidential,
+            ..
+        } = state
+            .update_stack_num_compute_units(1, 400, 200, 0.95, 0)
+            .await?;
+        assert_eq!((ratio * 100.0) as i64, 20); // (400 - (400 - 200)) / 1000 = 200 / 1000 = 0.2
+        assert_eq!(stack_computed_units, 200);
+        assert!(is_confidential);
+        // Test case 3: Stack not found
+        let err = state
+            .update_stack_num_compute_units(999, 0, 0, 0.95, 0)
+            .await;
+        assert!(matches!(err, Err(AtomaStateManagerError::StackNotFound)));
+
+        // Test case 4: Edge case - estimated equals num_compute_units
+        let UpdateStackNumComputeUnitsAndClaimFunds {
+            ratio,
+            stack_computed_units,
+            is_confidential,
+            ..
+        } = state
+            .update_stack_num_compute_units(1, 200, 200, 0.95, 0)
+            .await?;
+        assert_eq!((ratio * 100.0) as i64, 20); // No change: 200 / 1000 = 0.2
+        assert_eq!(stack_computed_units, 200);
+        assert!(is_confidential);
+
+        state
+            .insert_new_stack(Stack {
+                stack_small_id: 2,
+                task_small_id: 1,
+                num_compute_units: 1000,
+                already_computed_units: 960,
+                owner_address: "0x1".to_string(),
+                stack_id: "0x2".to_string(),
+                selected_node_id: 1,
+                price_per_one_million_compute_units: 1000,
+                in_settle_period: false,
+                total_hash: vec![0; 32],
+                num_total_messages: 0,
+                is_claimed: false,
+                is_locked_for_claim: false,
+                status: StackStatus::Active,
+            })
+            .await?;
+        // Test case 6: Edge case - estimated equals num_compute_units, but with concurrent requests
+        let UpdateStackNumComputeUnitsAndClaimFunds {
+            ratio,
+            stack_computed_units,
+            is_confidential,
+            ..
+        } = state
+            .update_stack_num_compute_units(2, 50, 50, 0.95, 1)
+            .await?;
+        assert_eq!((ratio * 100.0) as i64, 96); // No change: 960 / 1000 = 0.96
+        assert_eq!(stack_computed_units, 960);
+        assert!(is_confidential);
+        let stack = state.get_stack(2).await?;
+        assert!(!stack.is_locked_for_claim);
+
+        // Test case 7: Edge case - estimated equals num_compute_units, but with no concurrent requests
+        let UpdateStackNumComputeUnitsAndClaimFunds {
+            ratio,
+            stack_computed_units,
+            is_confidential,
+            ..
+        } = state
+            .update_stack_num_compute_units(2, 0, 0, 0.95, 0)
+            .await?;
+        assert_eq!((ratio * 100.0) as i64, 96); // No change: 960 / 1000 = 0.96
+        assert_eq!(stack_computed_units, 960);
+        assert!(is_confidential);
+        let stack = state.get_stack(2).await?;
+        assert!(stack.is_locked_for_claim);
+
+        // Clean up
+        truncate_tables(&state).await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    #[allow(clippy::cast_possible_truncation)]
+    async fn test_update_stack_num_compute_units_with_large_numbers() -> Result<()> {
+        let state = setup_test_db().await;
+        truncate_tables(&state).await;
+        // Create a test task with known values
+        state
+            .insert_new_task(Task {
+                task_small_id: 1,
+                task_id: "0x1".to_string(),
+                role: 1,
+                model_name: None,
+                is_deprecated: false,
+                valid_until_epoch: None,
+                deprecated_at_epoch: None,
+                security_level: 1,
+                minimum_reputation_score: None,
+                removed_at_epoch: None,
+            })
+            .await?;
+        // Create a test stack with known values
+        let stack = Stack {
+            stack_small_id: 1,
+            task_small_id: 1,
+            num_compute_units: 1000,
+            already_computed_units: 1000,
+            owner_address: "0x1".to_string(),
+            stack_id: "0x1".to_string(),
+            selected_node_id: 1,
+            price_per_one_million_compute_units: 1000,
+            in_settle_period: false,
+            total_hash: vec![0; 32],
+            num_total_messages: 0,
+            is_claimed: false,
+            is_locked_for_claim: false,
+            status: StackStatus::Active,
+        };
+        state.insert_new_stack(stack).await?;
+
+        // Test case 1: Starting from fully computed
+        let UpdateStackNumComputeUnitsAndClaimFunds {
+            ratio,
+            stack_computed_units,
+            is_confidential,
+            ..
+        } = state
+            .update_stack_num_compute_units(1, 1200, 1000, 0.95, 0)
+            .await?;
+        assert_eq!((ratio * 100.0) as i64, 80); // (1000 - (1200 - 1000)) / 1000 = 800 / 1000 = 0.8
+        assert_eq!(stack_computed_units, 800);
+        assert!(is_confidential);
+
+        // Test case 2: Large difference between estimated and actual
+        let UpdateStackNumComputeUnitsAndClaimFunds {
+            ratio,
+            stack_computed_units,
+            is_confidential,
+            ..
+        } = state
+            .update_stack_num_compute_units(1, 1800, 1000, 0.95, 0)
+            .await?;
+        assert_eq!((ratio * 100.0) as i64, 0); // (800 - (1800 - 1000)) / 1000 = 0.0
+        assert_eq!(stack_computed_units, 0);
+        assert!(is_confidential);
+
+        // Clean up
+        truncate_tables(&state).await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    #[allow(clippy::cast_possible_truncation)]
+    async fn test_update_stack_num_compute_units_concurrent() -> Result<()> {
+        let state = setup_test_db().await;
+        truncate_tables(&state).await;
+        state
+            .insert_new_task(Task {
+                task_small_id: 1,
+                task_id: "0x1".to_string(),
+                role: 1,
+                model_name: None,
+                is_deprecated: false,
+                valid_until_epoch: None,
+                deprecated_at_epoch: None,
+                security_level: 1,
+                minimum_reputation_score: None,
+                removed_at_epoch: None,
+            })
+            .await?;
+        // Create a test stack with known values
+        let stack = Stack {
+            stack_small_id: 1,
+            task_small_id: 1,
+            num_compute_units: 1000,
+            already_computed_units: 0,
+            owner_address: "0x1".to_string(),
+            stack_id: "0x1".to_string(),
+            selected_node_id: 1,
+            price_per_one_million_compute_units: 1000,
+            in_settle_period: false,
+            total_hash: vec![0; 32],
+            num_total_messages: 0,
+            is_claimed: false,
+            is_locked_for_claim: false,
+            status: StackStatus::Active,
+        };
+        state.insert_new_stack(stack).await?;
+
+        // Simulate concurrent updates
+        let futures = vec![
+            state.update_stack_num_compute_units(1, 200, 400, 0.95, 0),
+            state.update_stack_num_compute_units(1, 200, 400, 0.95, 0),
+            state.update_stack_num_compute_units(1, 200, 400, 0.95, 0),
+        ];
+
+        let results = futures::future::join_all(futures).await;
+
+        // All operations should succeed
+        assert!(results.iter().all(std::result::Result::is_ok));
 
-        assert_eq!(row.get::<Vec<u8>, _>("public_key_bytes"), empty_bytes);
-        assert_eq!(row.get::<Vec<u8>, _>("evidence_data_bytes"), empty_bytes);
+        // Final ratio should be consistent
+        let UpdateStackNumComputeUnitsAndClaimFunds {
+            ratio: final_ratio,
+            is_confidential,
+            ..
+        } = state
+            .update_stack_num_compute_units(1, 200, 400, 0.95, 0)
+            .await?;
+        assert_eq!((final_ratio * 100.0) as i64, 80);
+        assert!(is_confidential);
 
-        truncate_tables(&state_manager.db).await;
+        // Clean up
+        truncate_tables(&state).await;
         Ok(())
     }
 
     #[tokio::test]
     #[serial_test::serial]
     #[allow(clippy::cast_possible_truncation)]
-    async fn test_update_stack_num_compute_units() -> Result<()> {
+    async fn test_update_stack_num_compute_units_without_confidential_task() -> Result<()> {
         let state = setup_test_db().await;
-        truncate_tables(&state.db).await;
-        // Create a test task with known values
+        truncate_tables(&state).await;
+        // Create a non-confidential task
         state
             .insert_new_task(Task {
                 task_small_id: 1,
@@ -5328,8 +6642,9 @@ mod tests {
                 is_deprecated: false,
                 valid_until_epoch: None,
                 deprecated_at_epoch: None,
-                security_level: 1,
+                security_level: 0,
                 minimum_reputation_score: None,
+                removed_at_epoch: None,
             })
             .await?;
         // Create a test stack with known values
@@ -5337,7 +6652,7 @@ mod tests {
             stack_small_id: 1,
             task_small_id: 1,
             num_compute_units: 1000,
-            already_computed_units: 500,
+            already_computed_units: 0,
             owner_address: "0x1".to_string(),
             stack_id: "0x1".to_string(),
             selected_node_id: 1,
@@ -5347,6 +6662,7 @@ mod tests {
             num_total_messages: 0,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         };
         state.insert_new_stack(stack).await?;
 
@@ -5357,295 +6673,900 @@ mod tests {
             is_confidential,
             ..
         } = state
-            .update_stack_num_compute_units(1, 200, 100, 0.95, 0)
+            .update_stack_num_compute_units(1, 200, 400, 0.95, 0)
             .await?;
-        assert_eq!((ratio * 100.0) as i64, 40); // (500 - (200 - 100)) / 1000 = 400 / 1000 = 0.4
-        assert_eq!(stack_computed_units, 400);
-        assert!(is_confidential);
+        assert_eq!((ratio * 100.0) as i64, 20);
+        assert!(!is_confidential);
+        assert_eq!(stack_computed_units, 200);
 
-        // Test case 2: Normal update - estimated is lower than actual
+        // Test case 2: Large difference between estimated and actual
         let UpdateStackNumComputeUnitsAndClaimFunds {
             ratio,
             stack_computed_units,
             is_confidential,
             ..
         } = state
-            .update_stack_num_compute_units(1, 400, 200, 0.95, 0)
+            .update_stack_num_compute_units(1, 1200, 1000, 0.95, 0)
             .await?;
-        assert_eq!((ratio * 100.0) as i64, 20); // (400 - (400 - 200)) / 1000 = 200 / 1000 = 0.2
-        assert_eq!(stack_computed_units, 200);
-        assert!(is_confidential);
-        // Test case 3: Stack not found
-        let err = state
-            .update_stack_num_compute_units(999, 0, 0, 0.95, 0)
-            .await;
-        assert!(matches!(err, Err(AtomaStateManagerError::StackNotFound)));
+        assert_eq!((ratio * 100.0) as i64, 0);
+        assert!(!is_confidential);
+        assert_eq!(stack_computed_units, 0);
 
-        // Test case 4: Edge case - estimated equals num_compute_units
-        let UpdateStackNumComputeUnitsAndClaimFunds {
-            ratio,
-            stack_computed_units,
-            is_confidential,
-            ..
-        } = state
-            .update_stack_num_compute_units(1, 200, 200, 0.95, 0)
+        // Clean up
+        truncate_tables(&state).await;
+        Ok(())
+    }
+
+    fn test_batch(batch_id: &str, total_requests: i64) -> (Batch, Vec<BatchRequest>) {
+        let batch = Batch {
+            batch_id: batch_id.to_string(),
+            stack_small_id: 1,
+            owner_address: "0x1".to_string(),
+            endpoint: "/v1/chat/completions".to_string(),
+            status: BATCH_STATUS_IN_PROGRESS.to_string(),
+            reserved_compute_units: 100 * total_requests,
+            used_compute_units: 0,
+            total_requests,
+            completed_requests: 0,
+            failed_requests: 0,
+            created_at: 0,
+            completed_at: None,
+        };
+        let requests = (0..total_requests)
+            .map(|line_index| BatchRequest {
+                batch_id: batch_id.to_string(),
+                line_index,
+                custom_id: format!("request-{line_index}"),
+                body: r#"{"model":"model1"}"#.to_string(),
+                estimated_compute_units: 100,
+                used_compute_units: 0,
+                status: BATCH_REQUEST_STATUS_PENDING.to_string(),
+                status_code: None,
+                response: None,
+                error: None,
+            })
+            .collect();
+        (batch, requests)
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_insert_and_get_batch() -> Result<()> {
+        let state = setup_test_db().await;
+        truncate_tables(&state).await;
+
+        let (batch, requests) = test_batch("batch_1", 3);
+        state.insert_new_batch(batch.clone(), requests).await?;
+
+        let retrieved = state.get_batch("batch_1").await?;
+        assert_eq!(retrieved.stack_small_id, batch.stack_small_id);
+        assert_eq!(retrieved.status, BATCH_STATUS_IN_PROGRESS);
+        assert_eq!(retrieved.total_requests, 3);
+        assert_eq!(retrieved.reserved_compute_units, 300);
+        assert!(retrieved.completed_at.is_none());
+
+        let requests = state.get_batch_requests("batch_1").await?;
+        assert_eq!(
+            requests.iter().map(|r| r.line_index).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        assert!(requests
+            .iter()
+            .all(|r| r.status == BATCH_REQUEST_STATUS_PENDING));
+
+        assert_eq!(state.get_in_progress_batches().await?.len(), 1);
+        assert_eq!(state.get_pending_batch_requests(2).await?.len(), 2);
+        assert!(matches!(
+            state.get_batch("unknown").await,
+            Err(AtomaStateManagerError::BatchNotFound(_))
+        ));
+
+        truncate_tables(&state).await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_complete_batch_requests() -> Result<()> {
+        let state = setup_test_db().await;
+        truncate_tables(&state).await;
+
+        let (batch, requests) = test_batch("batch_1", 2);
+        state.insert_new_batch(batch, requests.clone()).await?;
+
+        let mut first = requests[0].clone();
+        first.status = BATCH_REQUEST_STATUS_COMPLETED.to_string();
+        first.status_code = Some(200);
+        first.response = Some("{}".to_string());
+        first.used_compute_units = 40;
+        assert!(state.complete_batch_request(first.clone()).await?.is_none());
+        // Completing the same request twice must not update the counters again
+        assert!(state.complete_batch_request(first).await?.is_none());
+
+        let mut second = requests[1].clone();
+        second.status = crate::types::BATCH_REQUEST_STATUS_FAILED.to_string();
+        second.status_code = Some(500);
+        second.error = Some("Internal server error".to_string());
+        let batch = state
+            .complete_batch_request(second)
+            .await?
+            .expect("Batch should be completed");
+        assert_eq!(batch.status, BATCH_STATUS_COMPLETED);
+        assert_eq!(batch.completed_requests, 1);
+        assert_eq!(batch.failed_requests, 1);
+        assert_eq!(batch.used_compute_units, 40);
+        assert!(batch.completed_at.is_some());
+
+        assert!(state.get_in_progress_batches().await?.is_empty());
+        assert!(state.get_pending_batch_requests(10).await?.is_empty());
+
+        truncate_tables(&state).await;
+        Ok(())
+    }
+
+    /// The lease of the counts of requests in flight, in the tests
+    const LEASE: Duration = Duration::from_secs(30);
+
+    // NOTE: Notifications are only sent by Postgres
+    #[cfg(not(feature = "sqlite"))]
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_concurrent_requests() -> Result<()> {
+        let state = setup_test_db().await;
+        truncate_tables(&state).await;
+        let mut listener = state.listen(&["test_concurrent_requests"]).await?;
+
+        let count = state
+            .update_concurrent_requests("replica-1", 1, 1, "test_concurrent_requests")
             .await?;
-        assert_eq!((ratio * 100.0) as i64, 20); // No change: 200 / 1000 = 0.2
-        assert_eq!(stack_computed_units, 200);
-        assert!(is_confidential);
+        assert_eq!(count.num_requests, 1);
+        let notification = listener.recv().await?;
+        assert_eq!(
+            serde_json::from_str::<ConcurrentRequestsCount>(notification.payload())?,
+            count
+        );
+        state
+            .update_concurrent_requests("replica-2", 1, 1, "test_concurrent_requests")
+            .await?;
+        // Counts never go below zero
+        let count = state
+            .update_concurrent_requests("replica-1", 1, -2, "test_concurrent_requests")
+            .await?;
+        assert_eq!(count.num_requests, 0);
+        assert_eq!(state.get_concurrent_requests(LEASE).await?.len(), 1);
 
         state
-            .insert_new_stack(Stack {
+            .reset_concurrent_requests("replica-2", &[(2, 3)], "test_concurrent_requests")
+            .await?;
+        let counts = state.get_concurrent_requests(LEASE).await?;
+        assert_eq!(
+            counts,
+            vec![ConcurrentRequestsCount {
+                owner: "replica-2".to_string(),
                 stack_small_id: 2,
-                task_small_id: 1,
-                num_compute_units: 1000,
-                already_computed_units: 960,
-                owner_address: "0x1".to_string(),
-                stack_id: "0x2".to_string(),
-                selected_node_id: 1,
-                price_per_one_million_compute_units: 1000,
-                in_settle_period: false,
-                total_hash: vec![0; 32],
-                num_total_messages: 0,
-                is_claimed: false,
-                is_locked_for_claim: false,
-            })
+                num_requests: 3,
+            }]
+        );
+
+        truncate_tables(&state).await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_concurrent_requests_lease() -> Result<()> {
+        let state = setup_test_db().await;
+        truncate_tables(&state).await;
+
+        state
+            .update_concurrent_requests("replica-1", 1, 2, "test_concurrent_requests")
             .await?;
-        // Test case 6: Edge case - estimated equals num_compute_units, but with concurrent requests
-        let UpdateStackNumComputeUnitsAndClaimFunds {
-            ratio,
-            stack_computed_units,
-            is_confidential,
-            ..
-        } = state
-            .update_stack_num_compute_units(2, 50, 50, 0.95, 1)
+        state
+            .update_concurrent_requests("replica-2", 1, 1, "test_concurrent_requests")
             .await?;
-        assert_eq!((ratio * 100.0) as i64, 96); // No change: 960 / 1000 = 0.96
-        assert_eq!(stack_computed_units, 960);
-        assert!(is_confidential);
-        let stack = state.get_stack(2).await?;
-        assert!(!stack.is_locked_for_claim);
+        // NOTE: replica-1 disappears, and stops refreshing its counts
+        dispatch!(state.storage, |storage| sqlx::query(
+            "UPDATE concurrent_requests SET updated_at = updated_at - $1 WHERE owner = $2"
+        )
+        .bind(2 * LEASE.as_secs() as i64)
+        .bind("replica-1")
+        .execute(&storage.db)
+        .await?);
+        state.refresh_concurrent_requests("replica-2").await?;
+
+        // Expired counts are ignored, even before they are pruned
+        let counts = state.get_concurrent_requests(LEASE).await?;
+        assert_eq!(
+            counts,
+            vec![ConcurrentRequestsCount {
+                owner: "replica-2".to_string(),
+                stack_small_id: 1,
+                num_requests: 1,
+            }]
+        );
 
-        // Test case 7: Edge case - estimated equals num_compute_units, but with no concurrent requests
-        let UpdateStackNumComputeUnitsAndClaimFunds {
-            ratio,
-            stack_computed_units,
-            is_confidential,
-            ..
-        } = state
-            .update_stack_num_compute_units(2, 0, 0, 0.95, 0)
+        let pruned = state
+            .prune_concurrent_requests(LEASE, "test_concurrent_requests")
             .await?;
-        assert_eq!((ratio * 100.0) as i64, 96); // No change: 960 / 1000 = 0.96
-        assert_eq!(stack_computed_units, 960);
-        assert!(is_confidential);
-        let stack = state.get_stack(2).await?;
-        assert!(stack.is_locked_for_claim);
+        assert_eq!(
+            pruned,
+            vec![ConcurrentRequestsCount {
+                owner: "replica-1".to_string(),
+                stack_small_id: 1,
+                num_requests: 2,
+            }]
+        );
+        assert!(state
+            .prune_concurrent_requests(LEASE, "test_concurrent_requests")
+            .await?
+            .is_empty());
+        assert_eq!(state.get_concurrent_requests(LEASE).await?, counts);
+
+        truncate_tables(&state).await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_event_log() -> Result<()> {
+        let state = setup_test_db().await;
+        truncate_tables(&state).await;
+
+        let task = Task {
+            task_small_id: 7,
+            task_id: "task-007".to_string(),
+            role: 1,
+            model_name: Some("model1".to_string()),
+            is_deprecated: false,
+            valid_until_epoch: None,
+            deprecated_at_epoch: None,
+            security_level: 2,
+            minimum_reputation_score: Some(80),
+            removed_at_epoch: None,
+        };
+        state.insert_new_task(task).await?;
+
+        let event = AtomaEvent::TaskRegisteredEvent(serde_json::from_value(serde_json::json!({
+            "task_id": "task-007",
+            "task_small_id": {"inner": "7"},
+            "role": {"inner": 1},
+            "model_name": "model1",
+            "security_level": {"inner": 2},
+            "minimum_reputation_score": 80
+        }))?);
+        for event_seq in 0..3 {
+            let metadata = AtomaEventMetadata {
+                tx_digest: "digest".to_string(),
+                event_seq,
+                checkpoint_timestamp_ms: Some(1_000),
+            };
+            state.insert_event(&event, &metadata).await?;
+        }
+
+        let events = state.get_events(0, 2).await?;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event_name, "TaskRegisteredEvent");
+        assert_eq!(events[0].tx_digest, "digest");
+        assert_eq!(events[0].checkpoint_timestamp_ms, Some(1_000));
+        assert_eq!(
+            events.iter().map(|e| e.event_seq).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        let logged_event: AtomaEvent = serde_json::from_str(&events[0].event)?;
+        assert!(matches!(
+            logged_event,
+            AtomaEvent::TaskRegisteredEvent(event) if event.task_small_id.inner == 7
+        ));
+        let events = state.get_events(events[1].id, 10).await?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_seq, 2);
+
+        // Only the derived tables are cleared
+        state.clear_event_derived_tables().await?;
+        assert!(state.get_all_tasks().await?.is_empty());
+        assert_eq!(state.get_events(0, 10).await?.len(), 3);
+
+        truncate_tables(&state).await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_applied_and_dead_letter_events() -> Result<()> {
+        let state = setup_test_db().await;
+        truncate_tables(&state).await;
+
+        let event = AtomaEvent::TaskDeprecationEvent(serde_json::from_value(serde_json::json!({
+            "task_id": "task-001",
+            "task_small_id": {"inner": "1"},
+            "epoch": "5"
+        }))?);
+        let metadata = AtomaEventMetadata {
+            tx_digest: "digest".to_string(),
+            event_seq: 0,
+            checkpoint_timestamp_ms: None,
+        };
+        let logged_event = state.insert_event(&event, &metadata).await?;
+        // The same event delivered again is logged again, but identified as the same event
+        let redelivered_event = state.insert_event(&event, &metadata).await?;
+        assert_ne!(logged_event.id, redelivered_event.id);
+
+        assert!(!state.is_event_applied(&logged_event).await?);
+        state
+            .insert_dead_letter_event(&logged_event, Some("task:1"), "Task not found")
+            .await?;
+        state
+            .insert_dead_letter_event(&redelivered_event, Some("task:1"), "Task not found")
+            .await?;
+        let dead_letter_events = state.get_dead_letter_events(10, 10).await?;
+        assert_eq!(dead_letter_events, vec![logged_event.clone()]);
+        // The event has run out of attempts
+        assert!(state.get_dead_letter_events(2, 10).await?.is_empty());
+
+        state.insert_applied_event(&logged_event).await?;
+        state.insert_applied_event(&logged_event).await?;
+        assert!(state.is_event_applied(&redelivered_event).await?);
+        state.delete_dead_letter_event(&logged_event).await?;
+        assert!(state.get_dead_letter_events(10, 10).await?.is_empty());
+
+        state.clear_event_derived_tables().await?;
+        assert!(!state.is_event_applied(&logged_event).await?);
+
+        truncate_tables(&state).await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_transaction() -> Result<()> {
+        let state = setup_test_db().await;
+        truncate_tables(&state).await;
+
+        let task = |task_small_id: i64| Task {
+            task_small_id,
+            task_id: format!("task-{task_small_id}"),
+            role: 1,
+            model_name: Some("model1".to_string()),
+            is_deprecated: false,
+            valid_until_epoch: None,
+            deprecated_at_epoch: None,
+            security_level: 1,
+            minimum_reputation_score: None,
+            removed_at_epoch: None,
+        };
+
+        // Dropping the transaction rolls back its changes
+        let transaction = state.begin().await?;
+        transaction.insert_new_task(task(1)).await?;
+        assert_eq!(transaction.get_all_tasks().await?.len(), 1);
+        drop(transaction);
+        assert!(state.get_all_tasks().await?.is_empty());
+
+        // The transaction cannot be committed while a clone of the state still uses it
+        let transaction = state.begin().await?;
+        transaction.insert_new_task(task(2)).await?;
+        assert!(matches!(
+            transaction.clone().commit().await,
+            Err(AtomaStateManagerError::TransactionInUse)
+        ));
+        transaction.commit().await?;
+        let tasks = state.get_all_tasks().await?;
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].task_small_id, 2);
+
+        truncate_tables(&state).await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_apply_logged_events_in_order_of_their_object() -> Result<()> {
+        let state = setup_test_db().await;
+        truncate_tables(&state).await;
+
+        let try_settle_event = |stack_small_id: u64| -> Result<AtomaEvent> {
+            Ok(AtomaEvent::StackTrySettleEvent((
+                serde_json::from_value(serde_json::json!({
+                    "stack_small_id": {"inner": stack_small_id.to_string()},
+                    "selected_node_id": {"inner": "1"},
+                    "requested_attestation_nodes": [{"inner": "2"}],
+                    "committed_stack_proof": [1, 2, 3],
+                    "stack_merkle_leaf": [4, 5, 6],
+                    "num_claimed_compute_units": "100"
+                }))?,
+                None,
+            )))
+        };
+        // The attestation of stack 1 is received before its settlement ticket, so it fails
+        let attestation_event = AtomaEvent::NewStackSettlementAttestationEvent(
+            serde_json::from_value(serde_json::json!({
+                "stack_small_id": {"inner": "1"},
+                "attestation_node_id": {"inner": "2"},
+                "committed_stack_proof": [1, 2, 3],
+                "stack_merkle_leaf": [4, 5, 6]
+            }))?,
+        );
+        let events = [
+            attestation_event,
+            try_settle_event(1)?,
+            try_settle_event(2)?,
+        ];
+        let mut logged_events = Vec::new();
+        let mut applied = Vec::new();
+        for (event_seq, event) in (0..).zip(events) {
+            let metadata = AtomaEventMetadata {
+                tx_digest: "digest".to_string(),
+                event_seq,
+                checkpoint_timestamp_ms: None,
+            };
+            let logged_event = state.insert_event(&event, &metadata).await?;
+            applied.push(state.apply_logged_event(&logged_event, event).await?);
+            logged_events.push(logged_event);
+        }
+        // The settlement ticket of stack 1 is held back behind its failed attestation, while
+        // the one of stack 2 is applied
+        assert_eq!(applied, vec![false, false, true]);
+        assert!(state.get_stack_settlement_tickets(&[1]).await?.is_empty());
+        assert_eq!(state.get_stack_settlement_tickets(&[2]).await?.len(), 1);
+        assert_eq!(
+            state.get_dead_letter_events(10, 10).await?,
+            logged_events[..2].to_vec()
+        );
+
+        // The attestation fails again, while the held back event does not use its attempts
+        state.retry_dead_letter_events(10).await?;
+        assert_eq!(
+            state.get_dead_letter_events(2, 10).await?,
+            vec![logged_events[1].clone()]
+        );
+        assert!(state.get_stack_settlement_tickets(&[1]).await?.is_empty());
+
+        // Once the attestation is discarded, the settlement ticket is applied
+        state.delete_dead_letter_event(&logged_events[0]).await?;
+        state.retry_dead_letter_events(10).await?;
+        assert!(state.get_dead_letter_events(10, 10).await?.is_empty());
+        assert_eq!(state.get_stack_settlement_tickets(&[1]).await?.len(), 1);
+        assert!(!state.is_event_applied(&logged_events[0]).await?);
+        assert!(state.is_event_applied(&logged_events[1]).await?);
+
+        truncate_tables(&state).await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_archive_settled_stacks() -> Result<()> {
+        let state = setup_test_db().await;
+        truncate_tables(&state).await;
+
+        let task = Task {
+            task_small_id: 1,
+            task_id: "task1".to_string(),
+            role: 1,
+            model_name: Some("model1".to_string()),
+            is_deprecated: false,
+            valid_until_epoch: None,
+            deprecated_at_epoch: None,
+            security_level: 1,
+            minimum_reputation_score: None,
+            removed_at_epoch: None,
+        };
+        state.insert_new_task(task).await?;
+        state.subscribe_node_to_task(1, 1, 100, 1000).await?;
+
+        for stack_small_id in 1..=3 {
+            state
+                .insert_new_stack(Stack {
+                    owner_address: "owner".to_string(),
+                    stack_small_id,
+                    stack_id: format!("stack{stack_small_id}"),
+                    task_small_id: 1,
+                    selected_node_id: 1,
+                    num_compute_units: 100,
+                    price_per_one_million_compute_units: 1000,
+                    already_computed_units: 100,
+                    in_settle_period: false,
+                    total_hash: vec![0; 32],
+                    num_total_messages: 1,
+                    is_claimed: false,
+                    is_locked_for_claim: false,
+                    status: StackStatus::Active,
+                })
+                .await?;
+        }
+        // Stack 1 is claimed and settled at epoch 5, stack 2 is settled at epoch 5 but not
+        // claimed yet, and stack 3 is claimed but settled at epoch 20
+        for (stack_small_id, dispute_settled_at_epoch, is_claimed) in
+            [(1, 5, true), (2, 5, false), (3, 20, true)]
+        {
+            state
+                .insert_new_stack_settlement_ticket(StackSettlementTicket {
+                    stack_small_id,
+                    selected_node_id: 1,
+                    num_claimed_compute_units: 100,
+                    requested_attestation_nodes: "[2]".to_string(),
+                    committed_stack_proofs: vec![1; 32],
+                    stack_merkle_leaves: vec![2; 32],
+                    dispute_settled_at_epoch: Some(dispute_settled_at_epoch),
+                    already_attested_nodes: "[2]".to_string(),
+                    is_in_dispute: false,
+                    user_refund_amount: 0,
+                    is_claimed,
+                })
+                .await?;
+        }
+        let dispute = StackAttestationDispute {
+            stack_small_id: 1,
+            attestation_commitment: vec![3; 32],
+            attestation_node_id: 2,
+            original_node_id: 1,
+            original_commitment: vec![4; 32],
+        };
+        state
+            .insert_stack_attestation_dispute(dispute.clone())
+            .await?;
+        let ticket = state.get_stack_settlement_ticket(1).await?;
+        let stack = state.get_stack(1).await?;
 
-        // Clean up
-        truncate_tables(&state.db).await;
+        assert_eq!(state.archive_settled_stacks(10, 40).await?, 1);
+        // Archiving again is a no-op
+        assert_eq!(state.archive_settled_stacks(10, 41).await?, 0);
+
+        let stacks = state.get_stacks_by_node_small_ids(&[1]).await?;
+        assert_eq!(
+            stacks.iter().map(|s| s.stack_small_id).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert!(state.get_stack_attestation_disputes(1, 2).await?.is_empty());
+
+        let archived_stacks = state.get_archived_stacks(&[1]).await?;
+        assert_eq!(
+            archived_stacks,
+            vec![ArchivedStack {
+                stack,
+                archived_at_epoch: 40,
+                settlement_tickets: vec![ticket],
+                attestation_disputes: vec![dispute],
+            }]
+        );
+        assert!(state.get_archived_stacks(&[2]).await?.is_empty());
+
+        truncate_tables(&state).await;
         Ok(())
     }
 
     #[tokio::test]
     #[serial_test::serial]
-    #[allow(clippy::cast_possible_truncation)]
-    async fn test_update_stack_num_compute_units_with_large_numbers() -> Result<()> {
+    async fn test_stack_status_transitions() -> Result<()> {
         let state = setup_test_db().await;
-        truncate_tables(&state.db).await;
-        // Create a test task with known values
+        truncate_tables(&state).await;
+
         state
-            .insert_new_task(Task {
+            .insert_new_stack(Stack {
+                owner_address: "owner".to_string(),
+                stack_small_id: 1,
+                stack_id: "stack1".to_string(),
                 task_small_id: 1,
-                task_id: "0x1".to_string(),
-                role: 1,
-                model_name: None,
-                is_deprecated: false,
-                valid_until_epoch: None,
-                deprecated_at_epoch: None,
-                security_level: 1,
-                minimum_reputation_score: None,
+                selected_node_id: 1,
+                num_compute_units: 100,
+                price_per_one_million_compute_units: 1000,
+                already_computed_units: 0,
+                in_settle_period: false,
+                total_hash: vec![],
+                num_total_messages: 0,
+                is_claimed: false,
+                is_locked_for_claim: false,
+                status: StackStatus::Active,
             })
             .await?;
-        // Create a test stack with known values
-        let stack = Stack {
-            stack_small_id: 1,
-            task_small_id: 1,
-            num_compute_units: 1000,
-            already_computed_units: 1000,
-            owner_address: "0x1".to_string(),
-            stack_id: "0x1".to_string(),
-            selected_node_id: 1,
-            price_per_one_million_compute_units: 1000,
-            in_settle_period: false,
-            total_hash: vec![0; 32],
-            num_total_messages: 0,
-            is_claimed: false,
-            is_locked_for_claim: false,
-        };
-        state.insert_new_stack(stack).await?;
+        assert_eq!(state.get_stack_status(1).await?, Some(StackStatus::Active));
+        assert_eq!(state.get_stack_status(2).await?, None);
 
-        // Test case 1: Starting from fully computed
-        let UpdateStackNumComputeUnitsAndClaimFunds {
-            ratio,
-            stack_computed_units,
-            is_confidential,
-            ..
-        } = state
-            .update_stack_num_compute_units(1, 1200, 1000, 0.95, 0)
+        state
+            .transition_stack_status(1, StackStatus::Settling, None, None, "StackTrySettleEvent")
             .await?;
-        assert_eq!((ratio * 100.0) as i64, 80); // (1000 - (1200 - 1000)) / 1000 = 800 / 1000 = 0.8
-        assert_eq!(stack_computed_units, 800);
-        assert!(is_confidential);
-
-        // Test case 2: Large difference between estimated and actual
-        let UpdateStackNumComputeUnitsAndClaimFunds {
-            ratio,
-            stack_computed_units,
-            is_confidential,
-            ..
-        } = state
-            .update_stack_num_compute_units(1, 1800, 1000, 0.95, 0)
+        // Moving a stack to its current status is a no-op
+        state
+            .transition_stack_status(1, StackStatus::Settling, None, None, "StackTrySettleEvent")
             .await?;
-        assert_eq!((ratio * 100.0) as i64, 0); // (800 - (1800 - 1000)) / 1000 = 0.0
-        assert_eq!(stack_computed_units, 0);
-        assert!(is_confidential);
+        state
+            .transition_stack_status(
+                1,
+                StackStatus::Settled,
+                Some(7),
+                None,
+                "StackSettlementTicketEvent",
+            )
+            .await?;
+        // A settled stack cannot be disputed nor go back to active
+        for to_status in [StackStatus::InDispute, StackStatus::Active] {
+            let result = state
+                .transition_stack_status(1, to_status, None, None, "StackAttestationDisputeEvent")
+                .await;
+            assert!(matches!(
+                result,
+                Err(AtomaStateManagerError::InvalidStackStatusTransition {
+                    stack_small_id: 1,
+                    from: StackStatus::Settled,
+                    ..
+                })
+            ));
+        }
+        state
+            .transition_stack_status(
+                1,
+                StackStatus::Claimed,
+                None,
+                Some(1_000),
+                "ClaimedStackEvent",
+            )
+            .await?;
+        // Stacks not in the state are ignored
+        state
+            .transition_stack_status(
+                2,
+                StackStatus::Claimed,
+                None,
+                Some(1_000),
+                "ClaimedStackEvent",
+            )
+            .await?;
+        assert_eq!(state.get_stack(1).await?.status, StackStatus::Claimed);
 
-        // Clean up
-        truncate_tables(&state.db).await;
+        let transitions = state.get_stack_status_transitions(1).await?;
+        assert_eq!(
+            transitions
+                .iter()
+                .map(|t| (
+                    t.from_status,
+                    t.to_status,
+                    t.epoch,
+                    t.triggering_event.as_str()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    StackStatus::Active,
+                    StackStatus::Settling,
+                    None,
+                    "StackTrySettleEvent"
+                ),
+                (
+                    StackStatus::Settling,
+                    StackStatus::Settled,
+                    Some(7),
+                    "StackSettlementTicketEvent"
+                ),
+                (
+                    StackStatus::Settled,
+                    StackStatus::Claimed,
+                    None,
+                    "ClaimedStackEvent"
+                ),
+            ]
+        );
+        assert!(state.get_stack_status_transitions(2).await?.is_empty());
+
+        truncate_tables(&state).await;
         Ok(())
     }
 
     #[tokio::test]
     #[serial_test::serial]
-    #[allow(clippy::cast_possible_truncation)]
-    async fn test_update_stack_num_compute_units_concurrent() -> Result<()> {
+    async fn test_chain_stack_status_out_of_order_and_late_events() -> Result<()> {
         let state = setup_test_db().await;
-        truncate_tables(&state.db).await;
+        truncate_tables(&state).await;
+
         state
-            .insert_new_task(Task {
+            .insert_new_stack(Stack {
+                owner_address: "owner".to_string(),
+                stack_small_id: 1,
+                stack_id: "stack1".to_string(),
                 task_small_id: 1,
-                task_id: "0x1".to_string(),
-                role: 1,
-                model_name: None,
-                is_deprecated: false,
-                valid_until_epoch: None,
-                deprecated_at_epoch: None,
-                security_level: 1,
-                minimum_reputation_score: None,
+                selected_node_id: 1,
+                num_compute_units: 100,
+                price_per_one_million_compute_units: 1000,
+                already_computed_units: 0,
+                in_settle_period: false,
+                total_hash: vec![],
+                num_total_messages: 0,
+                is_claimed: false,
+                is_locked_for_claim: false,
+                status: StackStatus::Active,
             })
             .await?;
-        // Create a test stack with known values
-        let stack = Stack {
+
+        // A dispute on a stack whose try settle event was missed is stored, and the stack is
+        // forced to the disputed status
+        let dispute = StackAttestationDispute {
             stack_small_id: 1,
-            task_small_id: 1,
-            num_compute_units: 1000,
-            already_computed_units: 0,
-            owner_address: "0x1".to_string(),
-            stack_id: "0x1".to_string(),
-            selected_node_id: 1,
-            price_per_one_million_compute_units: 1000,
-            in_settle_period: false,
-            total_hash: vec![0; 32],
-            num_total_messages: 0,
-            is_claimed: false,
-            is_locked_for_claim: false,
+            attestation_commitment: vec![7, 8, 9],
+            attestation_node_id: 3,
+            original_node_id: 1,
+            original_commitment: vec![1, 2, 3],
         };
-        state.insert_new_stack(stack).await?;
-
-        // Simulate concurrent updates
-        let futures = vec![
-            state.update_stack_num_compute_units(1, 200, 400, 0.95, 0),
-            state.update_stack_num_compute_units(1, 200, 400, 0.95, 0),
-            state.update_stack_num_compute_units(1, 200, 400, 0.95, 0),
-        ];
-
-        let results = futures::future::join_all(futures).await;
-
-        // All operations should succeed
-        assert!(results.iter().all(std::result::Result::is_ok));
+        state
+            .insert_stack_attestation_dispute(dispute.clone())
+            .await?;
+        state
+            .apply_chain_stack_status(
+                1,
+                StackStatus::InDispute,
+                None,
+                Some(1_000),
+                "StackAttestationDisputeEvent",
+            )
+            .await?;
+        assert_eq!(
+            state.get_stack_attestation_disputes(1, 3).await?,
+            vec![dispute]
+        );
+        assert_eq!(
+            state.get_stack_status(1).await?,
+            Some(StackStatus::InDispute)
+        );
 
-        // Final ratio should be consistent
-        let UpdateStackNumComputeUnitsAndClaimFunds {
-            ratio: final_ratio,
-            is_confidential,
-            ..
-        } = state
-            .update_stack_num_compute_units(1, 200, 400, 0.95, 0)
+        state
+            .apply_chain_stack_status(
+                1,
+                StackStatus::Claimed,
+                None,
+                Some(2_000),
+                "ClaimedStackEvent",
+            )
+            .await?;
+        // Events arriving after the stack was claimed leave its status unchanged
+        for (to_status, triggering_event) in [
+            (StackStatus::Settled, "StackSettlementTicketEvent"),
+            (StackStatus::InDispute, "StackAttestationDisputeEvent"),
+            (StackStatus::Settling, "StackTrySettleEvent"),
+        ] {
+            state
+                .apply_chain_stack_status(1, to_status, Some(7), Some(3_000), triggering_event)
+                .await?;
+            assert_eq!(state.get_stack_status(1).await?, Some(StackStatus::Claimed));
+        }
+        // Stacks not in the state are ignored
+        state
+            .apply_chain_stack_status(2, StackStatus::Claimed, None, None, "ClaimedStackEvent")
             .await?;
-        assert_eq!((final_ratio * 100.0) as i64, 80);
-        assert!(is_confidential);
 
-        // Clean up
-        truncate_tables(&state.db).await;
+        let transitions = state.get_stack_status_transitions(1).await?;
+        assert_eq!(
+            transitions
+                .iter()
+                .map(|t| (t.from_status, t.to_status, t.triggering_event.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    StackStatus::Active,
+                    StackStatus::InDispute,
+                    "StackAttestationDisputeEvent"
+                ),
+                (
+                    StackStatus::InDispute,
+                    StackStatus::Claimed,
+                    "ClaimedStackEvent"
+                ),
+            ]
+        );
+
+        truncate_tables(&state).await;
         Ok(())
     }
 
     #[tokio::test]
     #[serial_test::serial]
-    #[allow(clippy::cast_possible_truncation)]
-    async fn test_update_stack_num_compute_units_without_confidential_task() -> Result<()> {
+    async fn test_earnings_report() -> Result<()> {
+        /// 2023-11-14T22:13:20Z
+        const DAY_1_MS: i64 = 1_700_000_000_000;
+        /// 2023-11-15T22:13:20Z
+        const DAY_2_MS: i64 = DAY_1_MS + 86_400_000;
+
         let state = setup_test_db().await;
-        truncate_tables(&state.db).await;
-        // Create a non-confidential task
+        truncate_tables(&state).await;
+
         state
             .insert_new_task(Task {
                 task_small_id: 1,
-                task_id: "0x1".to_string(),
+                task_id: "task1".to_string(),
                 role: 1,
-                model_name: None,
+                model_name: Some("model1".to_string()),
                 is_deprecated: false,
                 valid_until_epoch: None,
                 deprecated_at_epoch: None,
-                security_level: 0,
+                security_level: 1,
                 minimum_reputation_score: None,
+                removed_at_epoch: None,
             })
             .await?;
-        // Create a test stack with known values
-        let stack = Stack {
-            stack_small_id: 1,
+        for (stack_small_id, selected_node_id) in [(1, 1), (2, 2)] {
+            state
+                .insert_new_stack(Stack {
+                    owner_address: format!("owner{stack_small_id}"),
+                    stack_small_id,
+                    stack_id: format!("stack{stack_small_id}"),
+                    task_small_id: 1,
+                    selected_node_id,
+                    num_compute_units: 100,
+                    price_per_one_million_compute_units: 2_000_000,
+                    already_computed_units: 50,
+                    in_settle_period: false,
+                    total_hash: vec![],
+                    num_total_messages: 0,
+                    is_claimed: false,
+                    is_locked_for_claim: false,
+                    status: StackStatus::Active,
+                })
+                .await?;
+        }
+        state.record_stack_usage(1, DAY_1_MS, 1, 10).await?;
+        state.record_stack_usage(1, DAY_1_MS + 1_000, 1, 20).await?;
+        state.record_stack_usage(1, DAY_2_MS, 1, 20).await?;
+        state.record_stack_usage(2, DAY_1_MS, 1, 5).await?;
+        for to_status in [
+            StackStatus::Settling,
+            StackStatus::Settled,
+            StackStatus::Claimed,
+        ] {
+            state
+                .transition_stack_status(1, to_status, None, Some(DAY_2_MS), "test")
+                .await?;
+        }
+
+        let row = |day: &str, owner_address: &str| EarningsReportRow {
+            day: Some(day.to_string()),
             task_small_id: 1,
-            num_compute_units: 1000,
-            already_computed_units: 0,
-            owner_address: "0x1".to_string(),
-            stack_id: "0x1".to_string(),
-            selected_node_id: 1,
-            price_per_one_million_compute_units: 1000,
-            in_settle_period: false,
-            total_hash: vec![0; 32],
-            num_total_messages: 0,
-            is_claimed: false,
-            is_locked_for_claim: false,
+            model_name: Some("model1".to_string()),
+            owner_address: owner_address.to_string(),
+            num_requests: 0,
+            compute_units_consumed: 0,
+            num_claimed_stacks: 0,
+            compute_units_claimed: 0,
+            revenue: 0,
+            refunds: 0,
+        };
+        let day_1 = EarningsReportRow {
+            num_requests: 2,
+            compute_units_consumed: 30,
+            ..row("2023-11-14", "owner1")
+        };
+        let day_2 = EarningsReportRow {
+            num_requests: 1,
+            compute_units_consumed: 20,
+            num_claimed_stacks: 1,
+            compute_units_claimed: 50,
+            revenue: 100,
+            ..row("2023-11-15", "owner1")
         };
-        state.insert_new_stack(stack).await?;
-
-        // Test case 1: Normal update - estimated is higher than actual
-        let UpdateStackNumComputeUnitsAndClaimFunds {
-            ratio,
-            stack_computed_units,
-            is_confidential,
-            ..
-        } = state
-            .update_stack_num_compute_units(1, 200, 400, 0.95, 0)
-            .await?;
-        assert_eq!((ratio * 100.0) as i64, 20);
-        assert!(!is_confidential);
-        assert_eq!(stack_computed_units, 200);
 
-        // Test case 2: Large difference between estimated and actual
-        let UpdateStackNumComputeUnitsAndClaimFunds {
-            ratio,
-            stack_computed_units,
-            is_confidential,
-            ..
-        } = state
-            .update_stack_num_compute_units(1, 1200, 1000, 0.95, 0)
-            .await?;
-        assert_eq!((ratio * 100.0) as i64, 0);
-        assert!(!is_confidential);
-        assert_eq!(stack_computed_units, 0);
+        assert_eq!(
+            state.get_earnings_report(&[1], None, None).await?,
+            vec![day_1.clone(), day_2.clone()]
+        );
+        assert_eq!(
+            state
+                .get_earnings_report(&[1], Some("2023-11-15"), None)
+                .await?,
+            vec![day_2]
+        );
+        assert_eq!(
+            state
+                .get_earnings_report(&[1], None, Some("2023-11-14"))
+                .await?,
+            vec![day_1]
+        );
+        assert_eq!(
+            state.get_earnings_report(&[2], None, None).await?,
+            vec![EarningsReportRow {
+                num_requests: 1,
+                compute_units_consumed: 5,
+                ..row("2023-11-14", "owner2")
+            }]
+        );
+        assert!(state.get_earnings_report(&[], None, None).await?.is_empty());
 
-        // Clean up
-        truncate_tables(&state.db).await;
+        truncate_tables(&state).await;
         Ok(())
     }
 }
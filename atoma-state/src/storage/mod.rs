@@ -0,0 +1,664 @@
+//! Storage backends of the Atoma state.
+//!
+//! Postgres is the default backend. SQLite can be used instead, with the `sqlite` feature, by
+//! pointing the database URL to an SQLite database (e.g. `sqlite:///var/lib/atoma/atoma.db`).
+
+pub(crate) mod postgres;
+#[cfg(feature = "sqlite")]
+pub(crate) mod sqlite;
+
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    state_manager::{AtomaStateManagerError, Result},
+    types::{
+        ArchivedStack, Batch, BatchRequest, ConcurrentRequestsCount, EarningsReportRow,
+        LoggedEvent, ModelTasks, Node, NodeSubscription, Stack, StackAttestationDispute,
+        StackAvailability, StackSettlementTicket, StackStatus, StackStatusTransition, Task,
+        UpdateStackNumComputeUnitsAndClaimFunds,
+    },
+};
+use postgres::PostgresStorage;
+#[cfg(feature = "sqlite")]
+use sqlite::SqliteStorage;
+use sqlx::{pool::PoolConnection, Database, Pool, Transaction};
+use tokio::sync::{Mutex, MutexGuard};
+
+/// The prefix of SQLite database URLs.
+const SQLITE_URL_PREFIX: &str = "sqlite:";
+
+/// The tables derived from the events of the Atoma contract, along with the events applied to
+/// them and the ones which failed to be, which are cleared before rebuilding the state from the
+/// event log. Children tables come first, so that they can be cleared in order.
+const EVENT_DERIVED_TABLES: [&str; 14] = [
+    "tasks",
+    "node_subscriptions",
+    "node_model_subscriptions",
+    "nodes",
+    "stack_attestation_disputes",
+    "stack_settlement_tickets",
+    "stacks",
+    "node_public_key_rotations",
+    "archived_stack_attestation_disputes",
+    "archived_stack_settlement_tickets",
+    "archived_stacks",
+    "stack_status_transitions",
+    "applied_events",
+    "dead_letter_events",
+];
+
+/// The columns of the `stacks` table, copied to the `archived_stacks` table.
+const STACK_COLUMNS: &str = "stack_small_id, owner_address, stack_id, task_small_id, \
+    selected_node_id, num_compute_units, price_per_one_million_compute_units, \
+    already_computed_units, in_settle_period, total_hash, num_total_messages, is_claimed, \
+    user_refund_amount, is_confidential, is_locked_for_claim, status";
+
+/// The columns of the `stack_settlement_tickets` table, copied to the
+/// `archived_stack_settlement_tickets` table.
+const STACK_SETTLEMENT_TICKET_COLUMNS: &str = "stack_small_id, selected_node_id, \
+    num_claimed_compute_units, requested_attestation_nodes, committed_stack_proofs, \
+    stack_merkle_leaves, dispute_settled_at_epoch, already_attested_nodes, is_in_dispute, \
+    user_refund_amount, is_claimed";
+
+/// The columns of the `stack_attestation_disputes` table, copied to the
+/// `archived_stack_attestation_disputes` table.
+const STACK_ATTESTATION_DISPUTE_COLUMNS: &str = "stack_small_id, attestation_commitment, \
+    attestation_node_id, original_node_id, original_commitment";
+
+/// Builds the query archiving the stacks claimed, whose disputes were settled at or before the
+/// epoch bound to `$1`, at the epoch bound to `$2`.
+fn archive_stacks_query() -> String {
+    format!(
+        "INSERT INTO archived_stacks ({STACK_COLUMNS}, archived_at_epoch)
+            SELECT {STACK_COLUMNS}, $2 FROM stacks
+            WHERE stack_small_id IN (
+                SELECT stacks.stack_small_id FROM stacks
+                JOIN stack_settlement_tickets
+                    ON stack_settlement_tickets.stack_small_id = stacks.stack_small_id
+                WHERE (stacks.is_claimed OR stack_settlement_tickets.is_claimed)
+                    AND stack_settlement_tickets.dispute_settled_at_epoch <= $1
+            )
+            ON CONFLICT (stack_small_id) DO NOTHING"
+    )
+}
+
+/// Builds the queries archiving the settlement tickets and attestation disputes of the archived
+/// stacks, at the epoch bound to `$1`.
+fn archive_stack_children_queries() -> [String; 2] {
+    [
+        format!(
+            "INSERT INTO archived_stack_settlement_tickets ({STACK_SETTLEMENT_TICKET_COLUMNS}, archived_at_epoch)
+                SELECT {STACK_SETTLEMENT_TICKET_COLUMNS}, $1 FROM stack_settlement_tickets
+                WHERE stack_small_id IN (SELECT stack_small_id FROM archived_stacks)
+                ON CONFLICT (stack_small_id, selected_node_id) DO NOTHING"
+        ),
+        format!(
+            "INSERT INTO archived_stack_attestation_disputes ({STACK_ATTESTATION_DISPUTE_COLUMNS}, archived_at_epoch)
+                SELECT {STACK_ATTESTATION_DISPUTE_COLUMNS}, $1 FROM stack_attestation_disputes
+                WHERE stack_small_id IN (SELECT stack_small_id FROM archived_stacks)
+                ON CONFLICT (stack_small_id, attestation_node_id) DO NOTHING"
+        ),
+    ]
+}
+
+/// The beginning of the earnings report query, selecting the stacks (archived or not), which are
+/// then filtered on their `selected_node_id`.
+const EARNINGS_REPORT_STACKS_QUERY: &str = "WITH report_stacks AS (
+    SELECT * FROM (
+        SELECT stack_small_id, owner_address, task_small_id, selected_node_id,
+            price_per_one_million_compute_units, already_computed_units, user_refund_amount, status
+        FROM stacks
+        UNION ALL
+        SELECT stack_small_id, owner_address, task_small_id, selected_node_id,
+            price_per_one_million_compute_units, already_computed_units, user_refund_amount, status
+        FROM archived_stacks
+    ) AS all_stacks";
+
+/// Builds the rest of the earnings report query, up to its `WHERE` clause, given the expression
+/// converting the `c.claimed_at_ms` column to a day, which depends on the backend.
+///
+/// The usage of the stacks is reported on the day the requests were served, while the compute
+/// units claimed, the revenue and the refunds of the claimed stacks are reported on the day of
+/// their claim (`NULL` if unknown, e.g. for stacks claimed before the transitions were recorded).
+fn earnings_report_rows_query(claim_day: &str) -> String {
+    format!(
+        "),
+        report_tickets AS (
+            SELECT stack_small_id, num_claimed_compute_units, user_refund_amount
+            FROM stack_settlement_tickets
+            UNION ALL
+            SELECT stack_small_id, num_claimed_compute_units, user_refund_amount
+            FROM archived_stack_settlement_tickets
+        ),
+        claims AS (
+            SELECT stack_small_id, MAX(timestamp_ms) AS claimed_at_ms
+            FROM stack_status_transitions
+            WHERE to_status = 'Claimed'
+            GROUP BY stack_small_id
+        ),
+        report_rows AS (
+            SELECT s.task_small_id, s.owner_address, u.day,
+                u.num_requests, u.compute_units AS compute_units_consumed,
+                0 AS num_claimed_stacks, 0 AS compute_units_claimed, 0 AS revenue, 0 AS refunds
+            FROM stack_usage u
+            JOIN report_stacks s ON s.stack_small_id = u.stack_small_id
+            UNION ALL
+            SELECT s.task_small_id, s.owner_address, {claim_day} AS day,
+                0, 0, 1,
+                COALESCE(t.num_claimed_compute_units, s.already_computed_units),
+                COALESCE(t.num_claimed_compute_units, s.already_computed_units)
+                    * s.price_per_one_million_compute_units / 1000000,
+                COALESCE(s.user_refund_amount, t.user_refund_amount, 0)
+            FROM report_stacks s
+            LEFT JOIN report_tickets t ON t.stack_small_id = s.stack_small_id
+            LEFT JOIN claims c ON c.stack_small_id = s.stack_small_id
+            WHERE s.status = 'Claimed'
+        )
+        SELECT r.day, r.task_small_id, tasks.model_name, r.owner_address,
+            CAST(SUM(r.num_requests) AS BIGINT) AS num_requests,
+            CAST(SUM(r.compute_units_consumed) AS BIGINT) AS compute_units_consumed,
+            CAST(SUM(r.num_claimed_stacks) AS BIGINT) AS num_claimed_stacks,
+            CAST(SUM(r.compute_units_claimed) AS BIGINT) AS compute_units_claimed,
+            CAST(SUM(r.revenue) AS BIGINT) AS revenue,
+            CAST(SUM(r.refunds) AS BIGINT) AS refunds
+        FROM report_rows r
+        LEFT JOIN tasks ON tasks.task_small_id = r.task_small_id
+        WHERE 1=1"
+    )
+}
+
+/// The end of the earnings report query, after the day filters.
+const EARNINGS_REPORT_GROUP_BY: &str = "
+    GROUP BY r.day, r.task_small_id, tasks.model_name, r.owner_address
+    ORDER BY r.day, r.task_small_id, r.owner_address";
+
+/// The queries removing the archived stacks, settlement tickets and attestation disputes from
+/// the tables of the state.
+const DELETE_ARCHIVED_STACKS_QUERIES: [&str; 3] = [
+    "DELETE FROM stack_attestation_disputes
+        WHERE stack_small_id IN (SELECT stack_small_id FROM archived_stacks)",
+    "DELETE FROM stack_settlement_tickets
+        WHERE stack_small_id IN (SELECT stack_small_id FROM archived_stacks)",
+    "DELETE FROM stacks WHERE stack_small_id IN (SELECT stack_small_id FROM archived_stacks)",
+];
+
+/// The transaction a storage backend is scoped to, shared by the clones of the backend.
+pub(crate) type SharedTransaction<DB> = Arc<Mutex<Transaction<'static, DB>>>;
+
+/// The connection the queries of a storage backend run on.
+///
+/// Each query of a backend acquires its connection right before running, and releases it once
+/// done, so that the connection of a transaction is never held across two queries.
+pub(crate) enum StorageConnection<'a, DB: Database> {
+    /// A connection acquired from the pool of the backend.
+    Pool(PoolConnection<DB>),
+    /// The connection of the transaction the backend is scoped to.
+    Transaction(MutexGuard<'a, Transaction<'static, DB>>),
+}
+
+impl<'a, DB: Database> StorageConnection<'a, DB> {
+    /// Acquires a connection from `pool`, or locks the connection of `transaction`, if any.
+    pub(crate) async fn acquire(
+        pool: &Pool<DB>,
+        transaction: Option<&'a SharedTransaction<DB>>,
+    ) -> Result<Self> {
+        Ok(match transaction {
+            Some(transaction) => Self::Transaction(transaction.lock().await),
+            None => Self::Pool(pool.acquire().await?),
+        })
+    }
+}
+
+impl<DB: Database> Deref for StorageConnection<'_, DB> {
+    type Target = DB::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Pool(connection) => connection,
+            Self::Transaction(transaction) => transaction,
+        }
+    }
+}
+
+impl<DB: Database> DerefMut for StorageConnection<'_, DB> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            Self::Pool(connection) => connection,
+            Self::Transaction(transaction) => transaction,
+        }
+    }
+}
+
+/// Begins a transaction on `pool`, to be shared by the clones of a storage backend.
+pub(crate) async fn begin_shared_transaction<DB: Database>(
+    pool: &Pool<DB>,
+) -> Result<SharedTransaction<DB>> {
+    Ok(Arc::new(Mutex::new(pool.begin().await?)))
+}
+
+/// Commits a transaction shared by the clones of a storage backend.
+///
+/// # Errors
+/// Returns `AtomaStateManagerError::TransactionInUse` if other clones of the backend still use
+/// the transaction.
+pub(crate) async fn commit_shared_transaction<DB: Database>(
+    transaction: SharedTransaction<DB>,
+) -> Result<()> {
+    let transaction = Arc::try_unwrap(transaction)
+        .map_err(|_| AtomaStateManagerError::TransactionInUse)?
+        .into_inner();
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Queries run by the Atoma state against its storage backend.
+///
+/// Each method has the semantics of the [`crate::AtomaState`] method of the same name, which
+/// documents it.
+pub(crate) trait StateStorage {
+    async fn ping(&self) -> Result<()>;
+
+    async fn get_task_by_small_id(&self, task_small_id: i64) -> Result<Task>;
+
+    async fn get_all_tasks(&self) -> Result<Vec<Task>>;
+
+    async fn insert_new_task(&self, task: Task) -> Result<()>;
+
+    async fn deprecate_task(&self, task_small_id: i64, epoch: i64) -> Result<()>;
+
+    async fn remove_task(&self, task_small_id: i64, epoch: i64) -> Result<()>;
+
+    async fn get_subscribed_tasks(&self, node_small_id: i64) -> Result<Vec<Task>>;
+
+    async fn get_all_node_subscriptions(
+        &self,
+        node_small_ids: &[i64],
+    ) -> Result<Vec<NodeSubscription>>;
+
+    async fn is_node_subscribed_to_task(
+        &self,
+        node_small_id: i64,
+        task_small_id: i64,
+    ) -> Result<bool>;
+
+    async fn subscribe_node_to_task(
+        &self,
+        node_small_id: i64,
+        task_small_id: i64,
+        price_per_one_million_compute_units: i64,
+        max_num_compute_units: i64,
+    ) -> Result<()>;
+
+    async fn subscribe_node_to_model(
+        &self,
+        node_small_id: i64,
+        model_name: &str,
+        echelon_id: i64,
+    ) -> Result<()>;
+
+    async fn get_node_model_tasks(&self, node_small_ids: Option<&[i64]>)
+        -> Result<Vec<ModelTasks>>;
+
+    async fn get_node_subscription_by_task_small_id(
+        &self,
+        task_small_id: i64,
+    ) -> Result<NodeSubscription>;
+
+    async fn update_node_subscription(
+        &self,
+        node_small_id: i64,
+        task_small_id: i64,
+        price_per_one_million_compute_units: i64,
+        max_num_compute_units: i64,
+    ) -> Result<()>;
+
+    async fn insert_node_registration_event(
+        &self,
+        node_small_id: i64,
+        node_id: String,
+        node_sui_address: String,
+    ) -> Result<()>;
+
+    async fn get_node_by_small_id(&self, node_small_id: i64) -> Result<Node>;
+
+    async fn verify_node_small_id_ownership(
+        &self,
+        node_small_id: u64,
+        sui_address: String,
+    ) -> Result<()>;
+
+    async fn unsubscribe_node_from_task(
+        &self,
+        node_small_id: i64,
+        task_small_id: i64,
+    ) -> Result<()>;
+
+    #[allow(clippy::too_many_arguments)]
+    async fn insert_node_public_key_rotation(
+        &self,
+        epoch: u64,
+        key_rotation_counter: u64,
+        node_small_id: u64,
+        public_key_bytes: Vec<u8>,
+        evidence_data_bytes: Vec<u8>,
+        device_type: u16,
+    ) -> Result<()>;
+
+    async fn get_stack(&self, stack_small_id: i64) -> Result<Stack>;
+
+    async fn get_stacks(&self, stack_small_ids: &[i64]) -> Result<Vec<Stack>>;
+
+    async fn get_stacks_by_node_small_ids(&self, node_small_ids: &[i64]) -> Result<Vec<Stack>>;
+
+    async fn get_stack_by_id(&self, node_small_id: i64) -> Result<Vec<Stack>>;
+
+    async fn get_almost_filled_stacks(
+        &self,
+        node_small_ids: &[i64],
+        fraction: f64,
+    ) -> Result<Vec<Stack>>;
+
+    async fn get_available_stack_with_compute_units(
+        &self,
+        stack_small_id: i64,
+        sui_address: &str,
+        num_compute_units: i64,
+    ) -> Result<StackAvailability>;
+
+    async fn insert_new_stack(&self, stack: Stack) -> Result<()>;
+
+    async fn update_computed_units_for_stack(
+        &self,
+        stack_small_id: i64,
+        already_computed_units: i64,
+    ) -> Result<()>;
+
+    async fn update_stack_num_compute_units(
+        &self,
+        stack_small_id: i64,
+        estimated_total_compute_units: i64,
+        total_compute_units: i64,
+        ratio_for_claim_stacks: f64,
+        concurrent_requests: i64,
+    ) -> Result<UpdateStackNumComputeUnitsAndClaimFunds>;
+
+    async fn get_stack_settlement_ticket(
+        &self,
+        stack_small_id: i64,
+    ) -> Result<StackSettlementTicket>;
+
+    async fn get_stack_settlement_tickets(
+        &self,
+        stack_small_ids: &[i64],
+    ) -> Result<Vec<StackSettlementTicket>>;
+
+    async fn insert_new_stack_settlement_ticket(
+        &self,
+        stack_settlement_ticket: StackSettlementTicket,
+    ) -> Result<()>;
+
+    async fn update_stack_total_hash(&self, stack_small_id: i64, new_hash: [u8; 32]) -> Result<()>;
+
+    async fn get_stack_total_hash(&self, stack_small_id: i64) -> Result<Vec<u8>>;
+
+    async fn get_all_total_hashes(&self, stack_small_ids: &[i64]) -> Result<Vec<Vec<u8>>>;
+
+    async fn update_stack_settlement_ticket_with_attestation_commitments(
+        &self,
+        stack_small_id: i64,
+        committed_stack_proof: Vec<u8>,
+        stack_merkle_leaf: Vec<u8>,
+        attestation_node_id: i64,
+    ) -> Result<()>;
+
+    async fn settle_stack_settlement_ticket(
+        &self,
+        stack_small_id: i64,
+        dispute_settled_at_epoch: i64,
+    ) -> Result<()>;
+
+    async fn update_stack_settlement_ticket_with_claim(
+        &self,
+        stack_small_id: i64,
+        user_refund_amount: i64,
+    ) -> Result<()>;
+
+    async fn update_stack_is_claimed(
+        &self,
+        stack_small_id: i64,
+        user_refund_amount: i64,
+    ) -> Result<()>;
+
+    async fn get_claimed_stacks(
+        &self,
+        node_small_ids: &[i64],
+    ) -> Result<Vec<StackSettlementTicket>>;
+
+    async fn get_stack_attestation_disputes(
+        &self,
+        stack_small_id: i64,
+        attestation_node_id: i64,
+    ) -> Result<Vec<StackAttestationDispute>>;
+
+    async fn get_against_attestation_disputes(
+        &self,
+        node_small_ids: &[i64],
+    ) -> Result<Vec<StackAttestationDispute>>;
+
+    async fn get_own_attestation_disputes(
+        &self,
+        node_small_ids: &[i64],
+    ) -> Result<Vec<StackAttestationDispute>>;
+
+    async fn insert_stack_attestation_dispute(
+        &self,
+        stack_attestation_dispute: StackAttestationDispute,
+    ) -> Result<()>;
+
+    async fn insert_new_batch(&self, batch: Batch, requests: Vec<BatchRequest>) -> Result<()>;
+
+    async fn get_batch(&self, batch_id: &str) -> Result<Batch>;
+
+    async fn get_batch_requests(&self, batch_id: &str) -> Result<Vec<BatchRequest>>;
+
+    async fn get_in_progress_batches(&self) -> Result<Vec<Batch>>;
+
+    async fn get_pending_batch_requests(&self, limit: i64) -> Result<Vec<BatchRequest>>;
+
+    async fn complete_batch_request(&self, request: BatchRequest) -> Result<Option<Batch>>;
+
+    async fn update_concurrent_requests(
+        &self,
+        owner: &str,
+        stack_small_id: i64,
+        delta: i64,
+        channel: &str,
+    ) -> Result<ConcurrentRequestsCount>;
+
+    async fn reset_concurrent_requests(
+        &self,
+        owner: &str,
+        counts: &[(i64, i64)],
+        channel: &str,
+    ) -> Result<()>;
+
+    async fn refresh_concurrent_requests(&self, owner: &str) -> Result<()>;
+
+    async fn prune_concurrent_requests(
+        &self,
+        lease: Duration,
+        channel: &str,
+    ) -> Result<Vec<ConcurrentRequestsCount>>;
+
+    async fn get_concurrent_requests(
+        &self,
+        lease: Duration,
+    ) -> Result<Vec<ConcurrentRequestsCount>>;
+
+    async fn insert_event(
+        &self,
+        tx_digest: &str,
+        event_seq: i64,
+        event_name: &str,
+        checkpoint_timestamp_ms: Option<i64>,
+        event: &str,
+    ) -> Result<i64>;
+
+    async fn get_events(&self, after_id: i64, limit: i64) -> Result<Vec<LoggedEvent>>;
+
+    async fn clear_event_derived_tables(&self) -> Result<()>;
+
+    async fn is_event_applied(&self, event: &LoggedEvent) -> Result<bool>;
+
+    async fn insert_applied_event(&self, event: &LoggedEvent) -> Result<()>;
+
+    async fn insert_dead_letter_event(
+        &self,
+        event: &LoggedEvent,
+        object_key: Option<&str>,
+        error: &str,
+    ) -> Result<()>;
+
+    async fn has_earlier_dead_letter_event(&self, object_key: &str, event_id: i64) -> Result<bool>;
+
+    async fn get_dead_letter_events(
+        &self,
+        max_attempts: i64,
+        limit: i64,
+    ) -> Result<Vec<LoggedEvent>>;
+
+    async fn delete_dead_letter_event(&self, event: &LoggedEvent) -> Result<()>;
+
+    async fn archive_settled_stacks(&self, max_settled_epoch: i64, epoch: i64) -> Result<u64>;
+
+    async fn get_archived_stacks(&self, node_small_ids: &[i64]) -> Result<Vec<ArchivedStack>>;
+
+    async fn get_archived_stack_settlement_tickets(
+        &self,
+        stack_small_ids: &[i64],
+    ) -> Result<Vec<StackSettlementTicket>>;
+
+    async fn get_archived_stack_attestation_disputes(
+        &self,
+        stack_small_ids: &[i64],
+    ) -> Result<Vec<StackAttestationDispute>>;
+
+    async fn get_stack_status(&self, stack_small_id: i64) -> Result<Option<StackStatus>>;
+
+    async fn update_stack_status(
+        &self,
+        stack_small_id: i64,
+        from_status: StackStatus,
+        to_status: StackStatus,
+        epoch: Option<i64>,
+        timestamp_ms: Option<i64>,
+        triggering_event: &str,
+    ) -> Result<bool>;
+
+    async fn get_stack_status_transitions(
+        &self,
+        stack_small_id: i64,
+    ) -> Result<Vec<StackStatusTransition>>;
+
+    async fn record_stack_usage(
+        &self,
+        stack_small_id: i64,
+        timestamp_ms: i64,
+        num_requests: i64,
+        compute_units: i64,
+    ) -> Result<()>;
+
+    async fn get_earnings_report(
+        &self,
+        node_small_ids: &[i64],
+        from_day: Option<&str>,
+        to_day: Option<&str>,
+    ) -> Result<Vec<EarningsReportRow>>;
+}
+
+/// The storage backend of an Atoma state, selected from its database URL.
+#[derive(Clone)]
+pub(crate) enum Storage {
+    Postgres(PostgresStorage),
+    #[cfg(feature = "sqlite")]
+    Sqlite(SqliteStorage),
+}
+
+impl Storage {
+    /// Connects to the database at `database_url`, and runs the migrations of its backend.
+    ///
+    /// # Errors
+    /// Returns `AtomaStateManagerError` if:
+    /// - Failed to connect to database
+    /// - Failed to run database migrations
+    /// - The database URL is an SQLite one, and the `sqlite` feature is not enabled
+    pub(crate) async fn connect(database_url: &str) -> Result<Self> {
+        if database_url.starts_with(SQLITE_URL_PREFIX) {
+            #[cfg(feature = "sqlite")]
+            return Ok(Self::Sqlite(SqliteStorage::connect(database_url).await?));
+            #[cfg(not(feature = "sqlite"))]
+            return Err(AtomaStateManagerError::UnsupportedStorageBackend(
+                "SQLite support requires the `sqlite` feature".to_string(),
+            ));
+        }
+        Ok(Self::Postgres(
+            PostgresStorage::connect(database_url).await?,
+        ))
+    }
+
+    /// Begins a transaction, returning a copy of the backend whose queries run in it.
+    ///
+    /// The queries of the copy are only visible to the rest of the backend once committed, with
+    /// [`Self::commit`], and are rolled back if the copy is dropped instead.
+    pub(crate) async fn begin(&self) -> Result<Self> {
+        Ok(match self {
+            Self::Postgres(storage) => Self::Postgres(storage.begin().await?),
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => Self::Sqlite(storage.begin().await?),
+        })
+    }
+
+    /// Commits the transaction of a backend returned by [`Self::begin`].
+    ///
+    /// # Errors
+    /// Returns `AtomaStateManagerError::TransactionInUse` if clones of the backend still use the
+    /// transaction.
+    pub(crate) async fn commit(self) -> Result<()> {
+        match self {
+            Self::Postgres(storage) => storage.commit().await,
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(storage) => storage.commit().await,
+        }
+    }
+
+    /// Returns the Postgres backend, for the operations only Postgres supports.
+    ///
+    /// # Errors
+    /// Returns `AtomaStateManagerError::UnsupportedStorageBackend` for other backends.
+    pub(crate) fn postgres(&self, operation: &str) -> Result<&PostgresStorage> {
+        match self {
+            Self::Postgres(storage) => Ok(storage),
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(_) => Err(AtomaStateManagerError::UnsupportedStorageBackend(format!(
+                "{operation} requires Postgres"
+            ))),
+        }
+    }
+}
+
+/// Runs `$call` against the backend of `$storage`, bound to `$backend`.
+macro_rules! dispatch {
+    ($storage:expr, |$backend:ident| $call:expr) => {
+        match &$storage {
+            $crate::storage::Storage::Postgres($backend) => $call,
+            #[cfg(feature = "sqlite")]
+            $crate::storage::Storage::Sqlite($backend) => $call,
+        }
+    };
+}
+
+pub(crate) use dispatch;
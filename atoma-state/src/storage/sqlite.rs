@@ -0,0 +1,1623 @@
+use std::{collections::BTreeMap, str::FromStr, time::Duration};
+
+use crate::{
+    build_query_with_in,
+    state_manager::{AtomaStateManagerError, Result},
+    types::{
+        ArchivedStack, Batch, BatchRequest, ConcurrentRequestsCount, EarningsReportRow,
+        LoggedEvent, ModelTasks, Node, NodeSubscription, Stack, StackAttestationDispute,
+        StackAvailability, StackSettlementTicket, StackStatus, StackStatusTransition, Task,
+        UpdateStackNumComputeUnitsAndClaimFunds, BATCH_REQUEST_STATUS_COMPLETED,
+        BATCH_REQUEST_STATUS_PENDING, BATCH_STATUS_COMPLETED, BATCH_STATUS_IN_PROGRESS,
+    },
+};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions},
+    Connection, FromRow, Row, Sqlite, SqlitePool,
+};
+
+use super::{
+    archive_stack_children_queries, archive_stacks_query, begin_shared_transaction,
+    commit_shared_transaction, earnings_report_rows_query, SharedTransaction, StateStorage,
+    StorageConnection, DELETE_ARCHIVED_STACKS_QUERIES, EARNINGS_REPORT_GROUP_BY,
+    EARNINGS_REPORT_STACKS_QUERY, EVENT_DERIVED_TABLES,
+};
+
+/// Builds the expression converting a timestamp in milliseconds since the Unix epoch to its day
+/// (UTC), formatted as `YYYY-MM-DD`.
+fn day_of(timestamp_ms: &str) -> String {
+    format!("date({timestamp_ms} / 1000, 'unixepoch')")
+}
+
+/// SQLite storage backend, for nodes that do not want to run Postgres.
+///
+/// The database is accessed through a single connection, which serializes the queries (and
+/// transactions) of the node, as SQLite only supports one writer at a time. For the same
+/// reason, the state cannot be shared with other service replicas.
+#[derive(Clone)]
+pub(crate) struct SqliteStorage {
+    /// The SQLite connection pool used for database operations.
+    pub(crate) db: SqlitePool,
+    /// The transaction the queries run in, if any, instead of connections of the pool.
+    pub(crate) transaction: Option<SharedTransaction<Sqlite>>,
+}
+
+impl SqliteStorage {
+    /// Connects to the SQLite database at `database_url`, creating it if missing, and runs its
+    /// migrations.
+    pub(crate) async fn connect(database_url: &str) -> Result<Self> {
+        let options = SqliteConnectOptions::from_str(database_url)?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal);
+        // NOTE: The connection is never closed, so that in-memory databases (e.g. `sqlite::memory:`)
+        // live as long as the pool.
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(options)
+            .await?;
+        sqlx::migrate!("./src/sqlite_migrations").run(&db).await?;
+        Ok(Self {
+            db,
+            transaction: None,
+        })
+    }
+
+    /// Returns the connection to run a query on: the one of the transaction of the backend, if
+    /// any, or one acquired from the pool.
+    async fn connection(&self) -> Result<StorageConnection<'_, Sqlite>> {
+        StorageConnection::acquire(&self.db, self.transaction.as_ref()).await
+    }
+
+    /// Begins a transaction, returning a copy of the backend whose queries run in it.
+    pub(crate) async fn begin(&self) -> Result<Self> {
+        Ok(Self {
+            db: self.db.clone(),
+            transaction: Some(begin_shared_transaction(&mut *self.connection().await?).await?),
+        })
+    }
+
+    /// Commits the transaction of the backend, if any.
+    pub(crate) async fn commit(self) -> Result<()> {
+        match self.transaction {
+            Some(transaction) => commit_shared_transaction(transaction).await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl StateStorage for SqliteStorage {
+    async fn ping(&self) -> Result<()> {
+        sqlx::query("SELECT 1")
+            .execute(&mut *self.connection().await?)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_task_by_small_id(&self, task_small_id: i64) -> Result<Task> {
+        let task = sqlx::query("SELECT * FROM tasks WHERE task_small_id = $1")
+            .bind(task_small_id)
+            .fetch_one(&mut *self.connection().await?)
+            .await?;
+        Ok(Task::from_row(&task)?)
+    }
+
+    async fn get_all_tasks(&self) -> Result<Vec<Task>> {
+        let tasks = sqlx::query("SELECT * FROM tasks")
+            .fetch_all(&mut *self.connection().await?)
+            .await?;
+        tasks
+            .into_iter()
+            .map(|task| Task::from_row(&task).map_err(AtomaStateManagerError::from))
+            .collect()
+    }
+
+    async fn insert_new_task(&self, task: Task) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO tasks (
+                task_small_id, task_id, role, model_name, is_deprecated,
+                valid_until_epoch, security_level, minimum_reputation_score
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (task_small_id) DO NOTHING",
+        )
+        .bind(task.task_small_id)
+        .bind(task.task_id)
+        .bind(task.role)
+        .bind(task.model_name)
+        .bind(task.is_deprecated)
+        .bind(task.valid_until_epoch)
+        .bind(task.security_level)
+        .bind(task.minimum_reputation_score)
+        .execute(&mut *self.connection().await?)
+        .await?;
+        Ok(())
+    }
+
+    async fn deprecate_task(&self, task_small_id: i64, epoch: i64) -> Result<()> {
+        sqlx::query("UPDATE tasks SET is_deprecated = TRUE, deprecated_at_epoch = $1 WHERE task_small_id = $2")
+            .bind(epoch)
+            .bind(task_small_id)
+            .execute(&mut *self.connection().await?)
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_task(&self, task_small_id: i64, epoch: i64) -> Result<()> {
+        sqlx::query(
+            "UPDATE tasks SET
+                is_deprecated = TRUE,
+                deprecated_at_epoch = COALESCE(deprecated_at_epoch, $1),
+                removed_at_epoch = $1
+            WHERE task_small_id = $2",
+        )
+        .bind(epoch)
+        .bind(task_small_id)
+        .execute(&mut *self.connection().await?)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_subscribed_tasks(&self, node_small_id: i64) -> Result<Vec<Task>> {
+        let tasks = sqlx::query(
+            "SELECT tasks.* FROM tasks
+            INNER JOIN node_subscriptions ON tasks.task_small_id = node_subscriptions.task_small_id
+            WHERE node_subscriptions.node_small_id = $1",
+        )
+        .bind(node_small_id)
+        .fetch_all(&mut *self.connection().await?)
+        .await?;
+        tasks
+            .into_iter()
+            .map(|task| Task::from_row(&task).map_err(AtomaStateManagerError::from))
+            .collect()
+    }
+
+    async fn get_all_node_subscriptions(
+        &self,
+        node_small_ids: &[i64],
+    ) -> Result<Vec<NodeSubscription>> {
+        let mut query_builder = build_query_with_in(
+            "SELECT * FROM node_subscriptions",
+            "node_small_id",
+            node_small_ids,
+            None,
+        );
+
+        let subscriptions = query_builder
+            .build()
+            .fetch_all(&mut *self.connection().await?)
+            .await?;
+
+        subscriptions
+            .into_iter()
+            .map(|subscription| {
+                NodeSubscription::from_row(&subscription).map_err(AtomaStateManagerError::from)
+            })
+            .collect()
+    }
+
+    async fn is_node_subscribed_to_task(
+        &self,
+        node_small_id: i64,
+        task_small_id: i64,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "SELECT COUNT(*) FROM node_subscriptions WHERE node_small_id = $1 AND task_small_id = $2",
+        )
+        .bind(node_small_id)
+        .bind(task_small_id)
+        .fetch_one(&mut *self.connection().await?)
+        .await?;
+        let count: i64 = result.get(0);
+        Ok(count > 0)
+    }
+
+    async fn subscribe_node_to_task(
+        &self,
+        node_small_id: i64,
+        task_small_id: i64,
+        price_per_one_million_compute_units: i64,
+        max_num_compute_units: i64,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO node_subscriptions
+                (node_small_id, task_small_id, price_per_one_million_compute_units, max_num_compute_units, valid)
+                VALUES ($1, $2, $3, $4, TRUE)
+                ON CONFLICT (task_small_id, node_small_id) DO NOTHING",
+        )
+            .bind(node_small_id)
+            .bind(task_small_id)
+            .bind(price_per_one_million_compute_units)
+            .bind(max_num_compute_units)
+            .execute(&mut *self.connection().await?)
+            .await?;
+        Ok(())
+    }
+
+    async fn subscribe_node_to_model(
+        &self,
+        node_small_id: i64,
+        model_name: &str,
+        echelon_id: i64,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO node_model_subscriptions (node_small_id, model_name, echelon_id)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (node_small_id, model_name) DO UPDATE SET echelon_id = EXCLUDED.echelon_id",
+        )
+        .bind(node_small_id)
+        .bind(model_name)
+        .bind(echelon_id)
+        .execute(&mut *self.connection().await?)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_node_model_tasks(
+        &self,
+        node_small_ids: Option<&[i64]>,
+    ) -> Result<Vec<ModelTasks>> {
+        let mut models = BTreeMap::<String, ModelTasks>::new();
+        // NOTE: SQLite has no arrays, so the node small ids are bound as a JSON array.
+        let node_small_ids = node_small_ids.map(serde_json::to_string).transpose()?;
+        let model_subscriptions = sqlx::query(
+            "SELECT DISTINCT model_name FROM node_model_subscriptions
+                WHERE $1 IS NULL OR node_small_id IN (SELECT value FROM json_each($1))",
+        )
+        .bind(&node_small_ids)
+        .fetch_all(&mut *self.connection().await?)
+        .await?;
+        for row in model_subscriptions {
+            let model_name: String = row.try_get("model_name")?;
+            models.entry(model_name.clone()).or_insert(ModelTasks {
+                model_name,
+                ..Default::default()
+            });
+        }
+        let task_subscriptions = sqlx::query(
+            "SELECT DISTINCT tasks.model_name, tasks.task_small_id,
+                (node_subscriptions.valid AND NOT tasks.is_deprecated AND tasks.removed_at_epoch IS NULL) AS active
+            FROM tasks
+            INNER JOIN node_subscriptions ON tasks.task_small_id = node_subscriptions.task_small_id
+            WHERE tasks.model_name IS NOT NULL
+                AND ($1 IS NULL OR node_subscriptions.node_small_id IN (SELECT value FROM json_each($1)))
+            ORDER BY tasks.task_small_id",
+        )
+        .bind(&node_small_ids)
+        .fetch_all(&mut *self.connection().await?)
+        .await?;
+        for row in task_subscriptions {
+            let model_name: String = row.try_get("model_name")?;
+            let task_small_id: i64 = row.try_get("task_small_id")?;
+            let active: bool = row.try_get("active")?;
+            let model = models.entry(model_name.clone()).or_insert(ModelTasks {
+                model_name,
+                ..Default::default()
+            });
+            if active {
+                model.active_task_small_ids.push(task_small_id);
+            } else {
+                model.inactive_task_small_ids.push(task_small_id);
+            }
+        }
+        Ok(models.into_values().collect())
+    }
+
+    async fn get_node_subscription_by_task_small_id(
+        &self,
+        task_small_id: i64,
+    ) -> Result<NodeSubscription> {
+        let subscription = sqlx::query("SELECT * FROM node_subscriptions WHERE task_small_id = $1")
+            .bind(task_small_id)
+            .fetch_one(&mut *self.connection().await?)
+            .await?;
+        Ok(NodeSubscription::from_row(&subscription)?)
+    }
+
+    async fn update_node_subscription(
+        &self,
+        node_small_id: i64,
+        task_small_id: i64,
+        price_per_one_million_compute_units: i64,
+        max_num_compute_units: i64,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE node_subscriptions SET price_per_one_million_compute_units = $1, max_num_compute_units = $2 WHERE node_small_id = $3 AND task_small_id = $4",
+        )
+            .bind(price_per_one_million_compute_units)
+            .bind(max_num_compute_units)
+            .bind(node_small_id)
+            .bind(task_small_id)
+            .execute(&mut *self.connection().await?)
+            .await?;
+        Ok(())
+    }
+
+    async fn insert_node_registration_event(
+        &self,
+        node_small_id: i64,
+        node_id: String,
+        node_sui_address: String,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO nodes (node_small_id, node_id, node_sui_address) VALUES ($1, $2, $3)",
+        )
+        .bind(node_small_id)
+        .bind(node_id)
+        .bind(node_sui_address)
+        .execute(&mut *self.connection().await?)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_node_by_small_id(&self, node_small_id: i64) -> Result<Node> {
+        let node = sqlx::query("SELECT * FROM nodes WHERE node_small_id = $1")
+            .bind(node_small_id)
+            .fetch_one(&mut *self.connection().await?)
+            .await?;
+        Ok(Node::from_row(&node)?)
+    }
+
+    async fn verify_node_small_id_ownership(
+        &self,
+        node_small_id: u64,
+        sui_address: String,
+    ) -> Result<()> {
+        let exists = sqlx::query(
+            "SELECT EXISTS(SELECT 1 FROM nodes WHERE node_small_id = $1 AND node_sui_address = $2)",
+        )
+        .bind(node_small_id as i64)
+        .bind(sui_address)
+        .fetch_one(&mut *self.connection().await?)
+        .await?
+        .get::<bool, _>(0);
+
+        if !exists {
+            return Err(AtomaStateManagerError::NodeSmallIdOwnershipVerificationFailed);
+        }
+
+        Ok(())
+    }
+
+    async fn unsubscribe_node_from_task(
+        &self,
+        node_small_id: i64,
+        task_small_id: i64,
+    ) -> Result<()> {
+        sqlx::query(
+            "DELETE FROM node_subscriptions WHERE node_small_id = $1 AND task_small_id = $2",
+        )
+        .bind(node_small_id)
+        .bind(task_small_id)
+        .execute(&mut *self.connection().await?)
+        .await?;
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn insert_node_public_key_rotation(
+        &self,
+        epoch: u64,
+        key_rotation_counter: u64,
+        node_small_id: u64,
+        public_key_bytes: Vec<u8>,
+        evidence_data_bytes: Vec<u8>,
+        device_type: u16,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO node_public_key_rotations (
+                epoch, 
+                key_rotation_counter, 
+                node_small_id, 
+                public_key_bytes, 
+                evidence_data_bytes,
+                device_type
+            ) 
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (node_small_id, device_type)
+            DO UPDATE SET
+                epoch = EXCLUDED.epoch,
+                key_rotation_counter = EXCLUDED.key_rotation_counter,
+                public_key_bytes = EXCLUDED.public_key_bytes,
+                evidence_data_bytes = EXCLUDED.evidence_data_bytes
+            ",
+        )
+        .bind(epoch as i64)
+        .bind(key_rotation_counter as i64)
+        .bind(node_small_id as i64)
+        .bind(public_key_bytes)
+        .bind(evidence_data_bytes)
+        .bind(i32::from(device_type))
+        .execute(&mut *self.connection().await?)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_stack(&self, stack_small_id: i64) -> Result<Stack> {
+        let stack = sqlx::query("SELECT * FROM stacks WHERE stack_small_id = $1")
+            .bind(stack_small_id)
+            .fetch_one(&mut *self.connection().await?)
+            .await?;
+        Ok(Stack::from_row(&stack)?)
+    }
+
+    async fn get_stacks(&self, stack_small_ids: &[i64]) -> Result<Vec<Stack>> {
+        let mut query_builder = build_query_with_in(
+            "SELECT * FROM stacks",
+            "stack_small_id",
+            stack_small_ids,
+            None,
+        );
+        let stacks = query_builder
+            .build()
+            .fetch_all(&mut *self.connection().await?)
+            .await?;
+        stacks
+            .into_iter()
+            .map(|stack| Stack::from_row(&stack).map_err(AtomaStateManagerError::from))
+            .collect()
+    }
+
+    async fn get_stacks_by_node_small_ids(&self, node_small_ids: &[i64]) -> Result<Vec<Stack>> {
+        let mut query_builder = build_query_with_in(
+            "SELECT * FROM stacks",
+            "selected_node_id",
+            node_small_ids,
+            None,
+        );
+
+        let stacks = query_builder
+            .build()
+            .fetch_all(&mut *self.connection().await?)
+            .await?;
+
+        stacks
+            .into_iter()
+            .map(|stack| Stack::from_row(&stack).map_err(AtomaStateManagerError::from))
+            .collect()
+    }
+
+    async fn get_stack_by_id(&self, node_small_id: i64) -> Result<Vec<Stack>> {
+        let stacks = sqlx::query("SELECT * FROM stacks WHERE selected_node_id = $1")
+            .bind(node_small_id)
+            .fetch_all(&mut *self.connection().await?)
+            .await?;
+        stacks
+            .into_iter()
+            .map(|stack| Stack::from_row(&stack).map_err(AtomaStateManagerError::from))
+            .collect()
+    }
+
+    async fn get_almost_filled_stacks(
+        &self,
+        node_small_ids: &[i64],
+        fraction: f64,
+    ) -> Result<Vec<Stack>> {
+        Ok(sqlx::query_as::<_, Stack>(
+            r"
+            SELECT *
+            FROM stacks
+            WHERE selected_node_id IN (SELECT value FROM json_each($1))
+            AND CASE
+                WHEN num_compute_units = 0 THEN true
+                ELSE (CAST(already_computed_units AS REAL) / CAST(num_compute_units AS REAL)) > $2
+            END
+            ",
+        )
+        .bind(serde_json::to_string(node_small_ids)?)
+        .bind(fraction)
+        .fetch_all(&mut *self.connection().await?)
+        .await?)
+    }
+
+    async fn get_available_stack_with_compute_units(
+        &self,
+        stack_small_id: i64,
+        sui_address: &str,
+        num_compute_units: i64,
+    ) -> Result<StackAvailability> {
+        // NOTE: SQLite does not support data-modifying statements in `WITH` clauses, so the
+        // reason why the compute units could not be locked is determined after the update.
+        let updated = sqlx::query(
+            "UPDATE stacks
+            SET already_computed_units = already_computed_units + $1
+            WHERE stack_small_id = $2
+              AND owner_address = $3
+              AND num_compute_units - already_computed_units >= $1
+              AND in_settle_period = false
+              AND is_claimed = false
+              AND is_locked_for_claim = false",
+        )
+        .bind(num_compute_units)
+        .bind(stack_small_id)
+        .bind(sui_address)
+        .execute(&mut *self.connection().await?)
+        .await?;
+        if updated.rows_affected() > 0 {
+            return Ok(StackAvailability::Available);
+        }
+
+        let stack = sqlx::query_as::<_, Stack>("SELECT * FROM stacks WHERE stack_small_id = $1")
+            .bind(stack_small_id)
+            .fetch_optional(&mut *self.connection().await?)
+            .await?;
+        Ok(match stack {
+            Some(stack) if stack.owner_address == sui_address => {
+                if stack.in_settle_period || stack.is_claimed || stack.is_locked_for_claim {
+                    StackAvailability::Locked
+                } else if stack.num_compute_units - stack.already_computed_units < num_compute_units
+                {
+                    StackAvailability::Unavailable
+                } else {
+                    StackAvailability::Locked
+                }
+            }
+            _ => StackAvailability::DoesNotExist,
+        })
+    }
+
+    async fn insert_new_stack(&self, stack: Stack) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO stacks
+                (owner_address, stack_small_id, stack_id, task_small_id, selected_node_id, 
+                num_compute_units, price_per_one_million_compute_units, already_computed_units, 
+                in_settle_period, total_hash, num_total_messages, is_confidential,
+                is_claimed, is_locked_for_claim, status)
+            SELECT 
+                $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11,
+                (SELECT security_level = 1 FROM tasks WHERE task_small_id = $4),
+                $12, $13, $14
+            -- NOTE: SQLite requires a WHERE clause to parse an upsert from a SELECT
+            WHERE true
+            ON CONFLICT (stack_small_id) DO UPDATE
+            SET already_computed_units = stacks.already_computed_units + $8
+            WHERE stacks.stack_small_id = $2;",
+        )
+        .bind(stack.owner_address)
+        .bind(stack.stack_small_id)
+        .bind(stack.stack_id)
+        .bind(stack.task_small_id)
+        .bind(stack.selected_node_id)
+        .bind(stack.num_compute_units)
+        .bind(stack.price_per_one_million_compute_units)
+        .bind(stack.already_computed_units)
+        .bind(stack.in_settle_period)
+        .bind(stack.total_hash)
+        .bind(stack.num_total_messages)
+        .bind(stack.is_claimed)
+        .bind(stack.is_locked_for_claim)
+        .bind(stack.status.as_str())
+        .execute(&mut *self.connection().await?)
+        .await?;
+        Ok(())
+    }
+
+    async fn update_computed_units_for_stack(
+        &self,
+        stack_small_id: i64,
+        already_computed_units: i64,
+    ) -> Result<()> {
+        sqlx::query("UPDATE stacks SET already_computed_units = $1 WHERE stack_small_id = $2")
+            .bind(already_computed_units)
+            .bind(stack_small_id)
+            .execute(&mut *self.connection().await?)
+            .await?;
+        Ok(())
+    }
+
+    async fn update_stack_num_compute_units(
+        &self,
+        stack_small_id: i64,
+        estimated_total_compute_units: i64,
+        total_compute_units: i64,
+        ratio_for_claim_stacks: f64,
+        concurrent_requests: i64,
+    ) -> Result<UpdateStackNumComputeUnitsAndClaimFunds> {
+        // NOTE: As in Postgres, the expressions of the SET clause are evaluated against the row
+        // before the update, so the new ratio is computed from the updated compute units.
+        let result = sqlx::query(
+            "UPDATE stacks
+            SET
+                already_computed_units = already_computed_units - ($2 - $3),
+                is_locked_for_claim = (
+                    is_confidential = true
+                    AND CAST(already_computed_units - ($2 - $3) AS REAL) / CAST(num_compute_units AS REAL) > $4
+                    AND $5 = 0
+                )
+            WHERE stack_small_id = $1
+            RETURNING
+                CAST(already_computed_units AS REAL) / CAST(num_compute_units AS REAL) as ratio,
+                already_computed_units as stack_computed_units,
+                (is_confidential = true) as is_confidential,
+                is_locked_for_claim",
+        )
+        .bind(stack_small_id)
+        .bind(estimated_total_compute_units)
+        .bind(total_compute_units)
+        .bind(ratio_for_claim_stacks)
+        .bind(concurrent_requests)
+        .fetch_optional(&mut *self.connection().await?)
+        .await?;
+
+        result.map_or_else(
+            || Err(AtomaStateManagerError::StackNotFound),
+            |row| {
+                UpdateStackNumComputeUnitsAndClaimFunds::from_row(&row)
+                    .map_err(AtomaStateManagerError::from)
+            },
+        )
+    }
+
+    async fn get_stack_settlement_ticket(
+        &self,
+        stack_small_id: i64,
+    ) -> Result<StackSettlementTicket> {
+        let stack_settlement_ticket =
+            sqlx::query("SELECT * FROM stack_settlement_tickets WHERE stack_small_id = $1")
+                .bind(stack_small_id)
+                .fetch_one(&mut *self.connection().await?)
+                .await?;
+        Ok(StackSettlementTicket::from_row(&stack_settlement_ticket)?)
+    }
+
+    async fn get_stack_settlement_tickets(
+        &self,
+        stack_small_ids: &[i64],
+    ) -> Result<Vec<StackSettlementTicket>> {
+        let mut query_builder = build_query_with_in(
+            "SELECT * FROM stack_settlement_tickets",
+            "stack_small_id",
+            stack_small_ids,
+            None,
+        );
+
+        let stack_settlement_tickets = query_builder
+            .build()
+            .fetch_all(&mut *self.connection().await?)
+            .await?;
+
+        stack_settlement_tickets
+            .into_iter()
+            .map(|row| StackSettlementTicket::from_row(&row).map_err(AtomaStateManagerError::from))
+            .collect()
+    }
+
+    async fn insert_new_stack_settlement_ticket(
+        &self,
+        stack_settlement_ticket: StackSettlementTicket,
+    ) -> Result<()> {
+        let mut connection = self.connection().await?;
+        let mut tx = connection.begin().await?;
+        sqlx::query(
+            "INSERT INTO stack_settlement_tickets
+                (
+                    stack_small_id,
+                    selected_node_id,
+                    num_claimed_compute_units,
+                    requested_attestation_nodes,
+                    committed_stack_proofs,
+                    stack_merkle_leaves,
+                    dispute_settled_at_epoch,
+                    already_attested_nodes,
+                    is_in_dispute,
+                    user_refund_amount,
+                    is_claimed)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                ON CONFLICT (stack_small_id, selected_node_id) DO NOTHING",
+        )
+        .bind(stack_settlement_ticket.stack_small_id)
+        .bind(stack_settlement_ticket.selected_node_id)
+        .bind(stack_settlement_ticket.num_claimed_compute_units)
+        .bind(stack_settlement_ticket.requested_attestation_nodes)
+        .bind(stack_settlement_ticket.committed_stack_proofs)
+        .bind(stack_settlement_ticket.stack_merkle_leaves)
+        .bind(stack_settlement_ticket.dispute_settled_at_epoch)
+        .bind(stack_settlement_ticket.already_attested_nodes)
+        .bind(stack_settlement_ticket.is_in_dispute)
+        .bind(stack_settlement_ticket.user_refund_amount)
+        .bind(stack_settlement_ticket.is_claimed)
+        .execute(&mut *tx)
+        .await?;
+
+        // Also update the stack to set in_settle_period to true
+        sqlx::query("UPDATE stacks SET in_settle_period = true WHERE stack_small_id = $1")
+            .bind(stack_settlement_ticket.stack_small_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn update_stack_total_hash(&self, stack_small_id: i64, new_hash: [u8; 32]) -> Result<()> {
+        // NOTE: SQLite concatenates blobs as text, so the new hash is appended to the total hash
+        // by the node instead.
+        let mut connection = self.connection().await?;
+        let mut tx = connection.begin().await?;
+        let mut total_hash = sqlx::query_scalar::<_, Vec<u8>>(
+            "SELECT total_hash FROM stacks WHERE stack_small_id = $1",
+        )
+        .bind(stack_small_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(AtomaStateManagerError::StackNotFound)?;
+        total_hash.extend_from_slice(&new_hash);
+        sqlx::query(
+            "UPDATE stacks
+            SET total_hash = $1,
+                num_total_messages = num_total_messages + 1
+            WHERE stack_small_id = $2",
+        )
+        .bind(total_hash)
+        .bind(stack_small_id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_stack_total_hash(&self, stack_small_id: i64) -> Result<Vec<u8>> {
+        let total_hash = sqlx::query_scalar::<_, Vec<u8>>(
+            "SELECT total_hash FROM stacks WHERE stack_small_id = $1",
+        )
+        .bind(stack_small_id)
+        .fetch_one(&mut *self.connection().await?)
+        .await?;
+        Ok(total_hash)
+    }
+
+    async fn get_all_total_hashes(&self, stack_small_ids: &[i64]) -> Result<Vec<Vec<u8>>> {
+        let mut query_builder = build_query_with_in(
+            "SELECT total_hash FROM stacks",
+            "stack_small_id",
+            stack_small_ids,
+            None,
+        );
+
+        Ok(query_builder
+            .build()
+            .fetch_all(&mut *self.connection().await?)
+            .await?
+            .iter()
+            .map(|row| row.get("total_hash"))
+            .collect())
+    }
+
+    async fn update_stack_settlement_ticket_with_attestation_commitments(
+        &self,
+        stack_small_id: i64,
+        committed_stack_proof: Vec<u8>,
+        stack_merkle_leaf: Vec<u8>,
+        attestation_node_id: i64,
+    ) -> Result<()> {
+        let mut connection = self.connection().await?;
+        let mut tx = connection.begin().await?;
+
+        // First query remains the same - get existing data
+        let row = sqlx::query(
+            "SELECT committed_stack_proofs, stack_merkle_leaves, requested_attestation_nodes, already_attested_nodes
+             FROM stack_settlement_tickets
+             WHERE stack_small_id = $1",
+        )
+        .bind(stack_small_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let mut committed_stack_proofs: Vec<u8> = row.get("committed_stack_proofs");
+        let mut current_merkle_leaves: Vec<u8> = row.get("stack_merkle_leaves");
+        let requested_nodes: String = row.get("requested_attestation_nodes");
+        let requested_nodes: Vec<i64> = serde_json::from_str(&requested_nodes)?;
+
+        // Get current attested nodes and handle null case
+        let already_attested: Option<String> = row.get("already_attested_nodes");
+        let mut attested_nodes: Vec<i64> = already_attested
+            .map(|nodes| serde_json::from_str(&nodes).unwrap_or_default())
+            .unwrap_or_default();
+
+        // Find the index and update binary data as before
+        let index = requested_nodes
+            .iter()
+            .position(|&id| id == attestation_node_id)
+            .ok_or_else(|| AtomaStateManagerError::AttestationNodeNotFound(attestation_node_id))?;
+
+        let start = (index + 1) * 32;
+        let end = start + 32;
+        if end > current_merkle_leaves.len() {
+            return Err(AtomaStateManagerError::InvalidMerkleLeafLength);
+        }
+        if end > committed_stack_proofs.len() {
+            return Err(AtomaStateManagerError::InvalidCommittedStackProofLength);
+        }
+
+        current_merkle_leaves[start..end].copy_from_slice(&stack_merkle_leaf[..32]);
+        committed_stack_proofs[start..end].copy_from_slice(&committed_stack_proof[..32]);
+
+        // Update attested nodes list
+        attested_nodes.push(attestation_node_id);
+        let attested_nodes_json = serde_json::to_string(&attested_nodes)?;
+
+        // Simplified update query
+        sqlx::query(
+            "UPDATE stack_settlement_tickets
+            SET committed_stack_proofs = $1,
+                stack_merkle_leaves = $2,
+                already_attested_nodes = $3
+            WHERE stack_small_id = $4",
+        )
+        .bind(committed_stack_proofs)
+        .bind(current_merkle_leaves)
+        .bind(attested_nodes_json)
+        .bind(stack_small_id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn settle_stack_settlement_ticket(
+        &self,
+        stack_small_id: i64,
+        dispute_settled_at_epoch: i64,
+    ) -> Result<()> {
+        sqlx::query("UPDATE stack_settlement_tickets SET dispute_settled_at_epoch = $1 WHERE stack_small_id = $2")
+            .bind(dispute_settled_at_epoch)
+            .bind(stack_small_id)
+            .execute(&mut *self.connection().await?)
+            .await?;
+        Ok(())
+    }
+
+    async fn update_stack_settlement_ticket_with_claim(
+        &self,
+        stack_small_id: i64,
+        user_refund_amount: i64,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE stack_settlement_tickets
+                SET user_refund_amount = $1,
+                    is_claimed = true
+                WHERE stack_small_id = $2",
+        )
+        .bind(user_refund_amount)
+        .bind(stack_small_id)
+        .execute(&mut *self.connection().await?)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn update_stack_is_claimed(
+        &self,
+        stack_small_id: i64,
+        user_refund_amount: i64,
+    ) -> Result<()> {
+        sqlx::query("UPDATE stacks SET is_claimed = TRUE, user_refund_amount = $1 WHERE stack_small_id = $2")
+            .bind(user_refund_amount)
+            .bind(stack_small_id)
+            .execute(&mut *self.connection().await?)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_claimed_stacks(
+        &self,
+        node_small_ids: &[i64],
+    ) -> Result<Vec<StackSettlementTicket>> {
+        let mut query_builder = build_query_with_in(
+            "SELECT * FROM stack_settlement_tickets",
+            "selected_node_id",
+            node_small_ids,
+            Some("is_claimed = true"),
+        );
+
+        let stacks = query_builder
+            .build()
+            .fetch_all(&mut *self.connection().await?)
+            .await?;
+
+        stacks
+            .into_iter()
+            .map(|row| StackSettlementTicket::from_row(&row).map_err(AtomaStateManagerError::from))
+            .collect()
+    }
+
+    async fn get_stack_attestation_disputes(
+        &self,
+        stack_small_id: i64,
+        attestation_node_id: i64,
+    ) -> Result<Vec<StackAttestationDispute>> {
+        let disputes = sqlx::query(
+            "SELECT * FROM stack_attestation_disputes
+                WHERE stack_small_id = $1 AND attestation_node_id = $2",
+        )
+        .bind(stack_small_id)
+        .bind(attestation_node_id)
+        .fetch_all(&mut *self.connection().await?)
+        .await?;
+
+        disputes
+            .into_iter()
+            .map(|row| {
+                StackAttestationDispute::from_row(&row).map_err(AtomaStateManagerError::from)
+            })
+            .collect()
+    }
+
+    async fn get_against_attestation_disputes(
+        &self,
+        node_small_ids: &[i64],
+    ) -> Result<Vec<StackAttestationDispute>> {
+        let mut query_builder = build_query_with_in(
+            "SELECT * FROM stack_attestation_disputes",
+            "original_node_id",
+            node_small_ids,
+            None,
+        );
+
+        let disputes = query_builder
+            .build()
+            .fetch_all(&mut *self.connection().await?)
+            .await?;
+
+        disputes
+            .into_iter()
+            .map(|row| {
+                StackAttestationDispute::from_row(&row).map_err(AtomaStateManagerError::from)
+            })
+            .collect()
+    }
+
+    async fn get_own_attestation_disputes(
+        &self,
+        node_small_ids: &[i64],
+    ) -> Result<Vec<StackAttestationDispute>> {
+        let mut query_builder = build_query_with_in(
+            "SELECT * FROM stack_attestation_disputes",
+            "attestation_node_id",
+            node_small_ids,
+            None,
+        );
+
+        let disputes = query_builder
+            .build()
+            .fetch_all(&mut *self.connection().await?)
+            .await?;
+
+        disputes
+            .into_iter()
+            .map(|row| {
+                StackAttestationDispute::from_row(&row).map_err(AtomaStateManagerError::from)
+            })
+            .collect()
+    }
+
+    async fn insert_stack_attestation_dispute(
+        &self,
+        stack_attestation_dispute: StackAttestationDispute,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO stack_attestation_disputes
+                (stack_small_id, attestation_commitment, attestation_node_id, original_node_id, original_commitment)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (stack_small_id, attestation_node_id) DO NOTHING",
+        )
+            .bind(stack_attestation_dispute.stack_small_id)
+            .bind(stack_attestation_dispute.attestation_commitment)
+            .bind(stack_attestation_dispute.attestation_node_id)
+            .bind(stack_attestation_dispute.original_node_id)
+            .bind(stack_attestation_dispute.original_commitment)
+            .execute(&mut *self.connection().await?)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn insert_new_batch(&self, batch: Batch, requests: Vec<BatchRequest>) -> Result<()> {
+        let mut connection = self.connection().await?;
+        let mut tx = connection.begin().await?;
+        sqlx::query(
+            "INSERT INTO batches
+                (batch_id, stack_small_id, owner_address, endpoint, status, reserved_compute_units,
+                 used_compute_units, total_requests, completed_requests, failed_requests, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, 0, $7, 0, 0, $8)",
+        )
+        .bind(&batch.batch_id)
+        .bind(batch.stack_small_id)
+        .bind(&batch.owner_address)
+        .bind(&batch.endpoint)
+        .bind(BATCH_STATUS_IN_PROGRESS)
+        .bind(batch.reserved_compute_units)
+        .bind(batch.total_requests)
+        .bind(batch.created_at)
+        .execute(&mut *tx)
+        .await?;
+        for request in requests {
+            sqlx::query(
+                "INSERT INTO batch_requests
+                    (batch_id, line_index, custom_id, body, estimated_compute_units, used_compute_units, status)
+                    VALUES ($1, $2, $3, $4, $5, 0, $6)",
+            )
+            .bind(&batch.batch_id)
+            .bind(request.line_index)
+            .bind(request.custom_id)
+            .bind(request.body)
+            .bind(request.estimated_compute_units)
+            .bind(BATCH_REQUEST_STATUS_PENDING)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_batch(&self, batch_id: &str) -> Result<Batch> {
+        let batch = sqlx::query("SELECT * FROM batches WHERE batch_id = $1")
+            .bind(batch_id)
+            .fetch_optional(&mut *self.connection().await?)
+            .await?;
+        batch.map_or_else(
+            || Err(AtomaStateManagerError::BatchNotFound(batch_id.to_string())),
+            |row| Batch::from_row(&row).map_err(AtomaStateManagerError::from),
+        )
+    }
+
+    async fn get_batch_requests(&self, batch_id: &str) -> Result<Vec<BatchRequest>> {
+        let requests =
+            sqlx::query("SELECT * FROM batch_requests WHERE batch_id = $1 ORDER BY line_index")
+                .bind(batch_id)
+                .fetch_all(&mut *self.connection().await?)
+                .await?;
+        requests
+            .into_iter()
+            .map(|row| BatchRequest::from_row(&row).map_err(AtomaStateManagerError::from))
+            .collect()
+    }
+
+    async fn get_in_progress_batches(&self) -> Result<Vec<Batch>> {
+        let batches =
+            sqlx::query("SELECT * FROM batches WHERE status = $1 ORDER BY created_at, batch_id")
+                .bind(BATCH_STATUS_IN_PROGRESS)
+                .fetch_all(&mut *self.connection().await?)
+                .await?;
+        batches
+            .into_iter()
+            .map(|row| Batch::from_row(&row).map_err(AtomaStateManagerError::from))
+            .collect()
+    }
+
+    async fn get_pending_batch_requests(&self, limit: i64) -> Result<Vec<BatchRequest>> {
+        let requests = sqlx::query(
+            "SELECT r.* FROM batch_requests r
+                JOIN batches b ON b.batch_id = r.batch_id
+                WHERE r.status = $1 AND b.status = $2
+                ORDER BY b.created_at, r.batch_id, r.line_index
+                LIMIT $3",
+        )
+        .bind(BATCH_REQUEST_STATUS_PENDING)
+        .bind(BATCH_STATUS_IN_PROGRESS)
+        .bind(limit)
+        .fetch_all(&mut *self.connection().await?)
+        .await?;
+        requests
+            .into_iter()
+            .map(|row| BatchRequest::from_row(&row).map_err(AtomaStateManagerError::from))
+            .collect()
+    }
+
+    async fn complete_batch_request(&self, request: BatchRequest) -> Result<Option<Batch>> {
+        let mut connection = self.connection().await?;
+        let mut tx = connection.begin().await?;
+        let updated = sqlx::query(
+            "UPDATE batch_requests
+                SET status = $3, status_code = $4, response = $5, error = $6, used_compute_units = $7
+                WHERE batch_id = $1 AND line_index = $2 AND status = $8",
+        )
+        .bind(&request.batch_id)
+        .bind(request.line_index)
+        .bind(&request.status)
+        .bind(request.status_code)
+        .bind(&request.response)
+        .bind(&request.error)
+        .bind(request.used_compute_units)
+        .bind(BATCH_REQUEST_STATUS_PENDING)
+        .execute(&mut *tx)
+        .await?;
+        if updated.rows_affected() == 0 {
+            tx.commit().await?;
+            return Ok(None);
+        }
+        let is_completed = request.status == BATCH_REQUEST_STATUS_COMPLETED;
+        let batch = sqlx::query(
+            "UPDATE batches
+                SET used_compute_units = used_compute_units + $2,
+                    completed_requests = completed_requests + CASE WHEN $3 THEN 1 ELSE 0 END,
+                    failed_requests = failed_requests + CASE WHEN $3 THEN 0 ELSE 1 END
+                WHERE batch_id = $1
+                RETURNING *",
+        )
+        .bind(&request.batch_id)
+        .bind(request.used_compute_units)
+        .bind(is_completed)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AtomaStateManagerError::BatchNotFound(request.batch_id.clone()))?;
+        let batch = Batch::from_row(&batch)?;
+        if batch.completed_requests + batch.failed_requests < batch.total_requests {
+            tx.commit().await?;
+            return Ok(None);
+        }
+        let batch = sqlx::query(
+            "UPDATE batches
+                SET status = $2, completed_at = CAST(strftime('%s', 'now') AS INTEGER)
+                WHERE batch_id = $1
+                RETURNING *",
+        )
+        .bind(&request.batch_id)
+        .bind(BATCH_STATUS_COMPLETED)
+        .fetch_one(&mut *tx)
+        .await?;
+        let batch = Batch::from_row(&batch)?;
+        tx.commit().await?;
+        Ok(Some(batch))
+    }
+
+    async fn update_concurrent_requests(
+        &self,
+        owner: &str,
+        stack_small_id: i64,
+        delta: i64,
+        _channel: &str,
+    ) -> Result<ConcurrentRequestsCount> {
+        // NOTE: SQLite databases are not shared between replicas, so there is no one to notify.
+        let count = sqlx::query(
+            "INSERT INTO concurrent_requests (owner, stack_small_id, num_requests, updated_at)
+                VALUES ($1, $2, MAX($3, 0), CAST(strftime('%s', 'now') AS INTEGER))
+                ON CONFLICT (owner, stack_small_id) DO UPDATE
+                SET num_requests = MAX(concurrent_requests.num_requests + $3, 0),
+                    updated_at = CAST(strftime('%s', 'now') AS INTEGER)
+                RETURNING *",
+        )
+        .bind(owner)
+        .bind(stack_small_id)
+        .bind(delta)
+        .fetch_one(&mut *self.connection().await?)
+        .await?;
+        Ok(ConcurrentRequestsCount::from_row(&count)?)
+    }
+
+    async fn reset_concurrent_requests(
+        &self,
+        owner: &str,
+        counts: &[(i64, i64)],
+        _channel: &str,
+    ) -> Result<()> {
+        let mut connection = self.connection().await?;
+        let mut tx = connection.begin().await?;
+        sqlx::query("DELETE FROM concurrent_requests WHERE owner = $1")
+            .bind(owner)
+            .execute(&mut *tx)
+            .await?;
+        for &(stack_small_id, num_requests) in counts {
+            sqlx::query(
+                "INSERT INTO concurrent_requests (owner, stack_small_id, num_requests, updated_at)
+                    VALUES ($1, $2, $3, CAST(strftime('%s', 'now') AS INTEGER))",
+            )
+            .bind(owner)
+            .bind(stack_small_id)
+            .bind(num_requests)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn refresh_concurrent_requests(&self, owner: &str) -> Result<()> {
+        sqlx::query(
+            "UPDATE concurrent_requests SET updated_at = CAST(strftime('%s', 'now') AS INTEGER)
+                WHERE owner = $1",
+        )
+        .bind(owner)
+        .execute(&mut *self.connection().await?)
+        .await?;
+        Ok(())
+    }
+
+    async fn prune_concurrent_requests(
+        &self,
+        lease: Duration,
+        _channel: &str,
+    ) -> Result<Vec<ConcurrentRequestsCount>> {
+        let removed = sqlx::query(
+            "DELETE FROM concurrent_requests
+                WHERE updated_at < CAST(strftime('%s', 'now') AS INTEGER) - $1
+                RETURNING *",
+        )
+        .bind(lease.as_secs() as i64)
+        .fetch_all(&mut *self.connection().await?)
+        .await?;
+        removed
+            .into_iter()
+            .map(|row| {
+                ConcurrentRequestsCount::from_row(&row).map_err(AtomaStateManagerError::from)
+            })
+            .collect()
+    }
+
+    async fn get_concurrent_requests(
+        &self,
+        lease: Duration,
+    ) -> Result<Vec<ConcurrentRequestsCount>> {
+        let counts = sqlx::query(
+            "SELECT * FROM concurrent_requests
+                WHERE num_requests > 0
+                AND updated_at >= CAST(strftime('%s', 'now') AS INTEGER) - $1",
+        )
+        .bind(lease.as_secs() as i64)
+        .fetch_all(&mut *self.connection().await?)
+        .await?;
+        counts
+            .into_iter()
+            .map(|row| {
+                ConcurrentRequestsCount::from_row(&row).map_err(AtomaStateManagerError::from)
+            })
+            .collect()
+    }
+
+    async fn insert_event(
+        &self,
+        tx_digest: &str,
+        event_seq: i64,
+        event_name: &str,
+        checkpoint_timestamp_ms: Option<i64>,
+        event: &str,
+    ) -> Result<i64> {
+        let id = sqlx::query_scalar(
+            "INSERT INTO event_log (tx_digest, event_seq, event_name, checkpoint_timestamp_ms, event)
+                VALUES ($1, $2, $3, $4, $5)
+                RETURNING id",
+        )
+        .bind(tx_digest)
+        .bind(event_seq)
+        .bind(event_name)
+        .bind(checkpoint_timestamp_ms)
+        .bind(event)
+        .fetch_one(&mut *self.connection().await?)
+        .await?;
+        Ok(id)
+    }
+
+    async fn get_events(&self, after_id: i64, limit: i64) -> Result<Vec<LoggedEvent>> {
+        let events = sqlx::query_as("SELECT * FROM event_log WHERE id > $1 ORDER BY id LIMIT $2")
+            .bind(after_id)
+            .bind(limit)
+            .fetch_all(&mut *self.connection().await?)
+            .await?;
+        Ok(events)
+    }
+
+    async fn clear_event_derived_tables(&self) -> Result<()> {
+        let mut connection = self.connection().await?;
+        let mut tx = connection.begin().await?;
+        for table in EVENT_DERIVED_TABLES {
+            sqlx::query(&format!("DELETE FROM {table}"))
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn is_event_applied(&self, event: &LoggedEvent) -> Result<bool> {
+        let is_applied = sqlx::query_scalar(
+            "SELECT EXISTS(
+                SELECT 1 FROM applied_events
+                WHERE tx_digest = $1 AND event_seq = $2 AND event_name = $3
+            )",
+        )
+        .bind(&event.tx_digest)
+        .bind(event.event_seq)
+        .bind(&event.event_name)
+        .fetch_one(&mut *self.connection().await?)
+        .await?;
+        Ok(is_applied)
+    }
+
+    async fn insert_applied_event(&self, event: &LoggedEvent) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO applied_events (tx_digest, event_seq, event_name)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (tx_digest, event_seq, event_name) DO NOTHING",
+        )
+        .bind(&event.tx_digest)
+        .bind(event.event_seq)
+        .bind(&event.event_name)
+        .execute(&mut *self.connection().await?)
+        .await?;
+        Ok(())
+    }
+
+    async fn insert_dead_letter_event(
+        &self,
+        event: &LoggedEvent,
+        object_key: Option<&str>,
+        error: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO dead_letter_events (tx_digest, event_seq, event_name, event_id, object_key, error, num_attempts)
+                VALUES ($1, $2, $3, $4, $5, $6, 1)
+                ON CONFLICT (tx_digest, event_seq, event_name) DO UPDATE SET
+                    error = EXCLUDED.error,
+                    num_attempts = dead_letter_events.num_attempts + 1",
+        )
+        .bind(&event.tx_digest)
+        .bind(event.event_seq)
+        .bind(&event.event_name)
+        .bind(event.id)
+        .bind(object_key)
+        .bind(error)
+        .execute(&mut *self.connection().await?)
+        .await?;
+        Ok(())
+    }
+
+    async fn has_earlier_dead_letter_event(&self, object_key: &str, event_id: i64) -> Result<bool> {
+        let exists = sqlx::query_scalar(
+            "SELECT EXISTS(
+                SELECT 1 FROM dead_letter_events
+                WHERE object_key = $1 AND event_id < $2
+            )",
+        )
+        .bind(object_key)
+        .bind(event_id)
+        .fetch_one(&mut *self.connection().await?)
+        .await?;
+        Ok(exists)
+    }
+
+    async fn get_dead_letter_events(
+        &self,
+        max_attempts: i64,
+        limit: i64,
+    ) -> Result<Vec<LoggedEvent>> {
+        let events = sqlx::query_as(
+            "SELECT event_log.* FROM dead_letter_events
+                JOIN event_log ON event_log.id = dead_letter_events.event_id
+                WHERE dead_letter_events.num_attempts < $1
+                ORDER BY dead_letter_events.event_id
+                LIMIT $2",
+        )
+        .bind(max_attempts)
+        .bind(limit)
+        .fetch_all(&mut *self.connection().await?)
+        .await?;
+        Ok(events)
+    }
+
+    async fn delete_dead_letter_event(&self, event: &LoggedEvent) -> Result<()> {
+        sqlx::query(
+            "DELETE FROM dead_letter_events
+                WHERE tx_digest = $1 AND event_seq = $2 AND event_name = $3",
+        )
+        .bind(&event.tx_digest)
+        .bind(event.event_seq)
+        .bind(&event.event_name)
+        .execute(&mut *self.connection().await?)
+        .await?;
+        Ok(())
+    }
+
+    async fn archive_settled_stacks(&self, max_settled_epoch: i64, epoch: i64) -> Result<u64> {
+        let mut connection = self.connection().await?;
+        let mut tx = connection.begin().await?;
+        let num_archived_stacks = sqlx::query(&archive_stacks_query())
+            .bind(max_settled_epoch)
+            .bind(epoch)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        for query in archive_stack_children_queries() {
+            sqlx::query(&query).bind(epoch).execute(&mut *tx).await?;
+        }
+        for query in DELETE_ARCHIVED_STACKS_QUERIES {
+            sqlx::query(query).execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(num_archived_stacks)
+    }
+
+    async fn get_archived_stacks(&self, node_small_ids: &[i64]) -> Result<Vec<ArchivedStack>> {
+        let mut query_builder = build_query_with_in(
+            "SELECT * FROM archived_stacks",
+            "selected_node_id",
+            node_small_ids,
+            None,
+        );
+        query_builder.push(" ORDER BY stack_small_id");
+
+        let stacks = query_builder
+            .build()
+            .fetch_all(&mut *self.connection().await?)
+            .await?;
+
+        stacks
+            .into_iter()
+            .map(|row| {
+                Ok(ArchivedStack {
+                    stack: Stack::from_row(&row)?,
+                    archived_at_epoch: row.try_get("archived_at_epoch")?,
+                    settlement_tickets: vec![],
+                    attestation_disputes: vec![],
+                })
+            })
+            .collect()
+    }
+
+    async fn get_archived_stack_settlement_tickets(
+        &self,
+        stack_small_ids: &[i64],
+    ) -> Result<Vec<StackSettlementTicket>> {
+        let mut query_builder = build_query_with_in(
+            "SELECT * FROM archived_stack_settlement_tickets",
+            "stack_small_id",
+            stack_small_ids,
+            None,
+        );
+
+        let tickets = query_builder
+            .build()
+            .fetch_all(&mut *self.connection().await?)
+            .await?;
+
+        tickets
+            .into_iter()
+            .map(|row| StackSettlementTicket::from_row(&row).map_err(AtomaStateManagerError::from))
+            .collect()
+    }
+
+    async fn get_archived_stack_attestation_disputes(
+        &self,
+        stack_small_ids: &[i64],
+    ) -> Result<Vec<StackAttestationDispute>> {
+        let mut query_builder = build_query_with_in(
+            "SELECT * FROM archived_stack_attestation_disputes",
+            "stack_small_id",
+            stack_small_ids,
+            None,
+        );
+
+        let disputes = query_builder
+            .build()
+            .fetch_all(&mut *self.connection().await?)
+            .await?;
+
+        disputes
+            .into_iter()
+            .map(|row| {
+                StackAttestationDispute::from_row(&row).map_err(AtomaStateManagerError::from)
+            })
+            .collect()
+    }
+
+    async fn get_stack_status(&self, stack_small_id: i64) -> Result<Option<StackStatus>> {
+        let status: Option<String> =
+            sqlx::query_scalar("SELECT status FROM stacks WHERE stack_small_id = $1")
+                .bind(stack_small_id)
+                .fetch_optional(&mut *self.connection().await?)
+                .await?;
+        status.map(StackStatus::try_from).transpose()
+    }
+
+    async fn update_stack_status(
+        &self,
+        stack_small_id: i64,
+        from_status: StackStatus,
+        to_status: StackStatus,
+        epoch: Option<i64>,
+        timestamp_ms: Option<i64>,
+        triggering_event: &str,
+    ) -> Result<bool> {
+        let mut connection = self.connection().await?;
+        let mut tx = connection.begin().await?;
+        let updated =
+            sqlx::query("UPDATE stacks SET status = $1 WHERE stack_small_id = $2 AND status = $3")
+                .bind(to_status.as_str())
+                .bind(stack_small_id)
+                .bind(from_status.as_str())
+                .execute(&mut *tx)
+                .await?
+                .rows_affected()
+                > 0;
+        if updated {
+            sqlx::query(
+                "INSERT INTO stack_status_transitions
+                    (stack_small_id, from_status, to_status, epoch, timestamp_ms, triggering_event)
+                    VALUES ($1, $2, $3, $4, $5, $6)",
+            )
+            .bind(stack_small_id)
+            .bind(from_status.as_str())
+            .bind(to_status.as_str())
+            .bind(epoch)
+            .bind(timestamp_ms)
+            .bind(triggering_event)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(updated)
+    }
+
+    async fn get_stack_status_transitions(
+        &self,
+        stack_small_id: i64,
+    ) -> Result<Vec<StackStatusTransition>> {
+        let transitions = sqlx::query(
+            "SELECT * FROM stack_status_transitions WHERE stack_small_id = $1 ORDER BY id",
+        )
+        .bind(stack_small_id)
+        .fetch_all(&mut *self.connection().await?)
+        .await?;
+
+        transitions
+            .into_iter()
+            .map(|row| StackStatusTransition::from_row(&row).map_err(AtomaStateManagerError::from))
+            .collect()
+    }
+
+    async fn record_stack_usage(
+        &self,
+        stack_small_id: i64,
+        timestamp_ms: i64,
+        num_requests: i64,
+        compute_units: i64,
+    ) -> Result<()> {
+        let day = day_of("$2");
+        sqlx::query(&format!(
+            "INSERT INTO stack_usage (stack_small_id, day, num_requests, compute_units)
+                VALUES ($1, {day}, $3, $4)
+                ON CONFLICT (stack_small_id, day) DO UPDATE
+                SET num_requests = stack_usage.num_requests + EXCLUDED.num_requests,
+                    compute_units = stack_usage.compute_units + EXCLUDED.compute_units"
+        ))
+        .bind(stack_small_id)
+        .bind(timestamp_ms)
+        .bind(num_requests)
+        .bind(compute_units)
+        .execute(&mut *self.connection().await?)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_earnings_report(
+        &self,
+        node_small_ids: &[i64],
+        from_day: Option<&str>,
+        to_day: Option<&str>,
+    ) -> Result<Vec<EarningsReportRow>> {
+        let mut query_builder = build_query_with_in(
+            EARNINGS_REPORT_STACKS_QUERY,
+            "selected_node_id",
+            node_small_ids,
+            None,
+        );
+        query_builder.push(earnings_report_rows_query(&day_of("c.claimed_at_ms")));
+        if let Some(from_day) = from_day {
+            query_builder.push(" AND r.day >= ").push_bind(from_day);
+        }
+        if let Some(to_day) = to_day {
+            query_builder.push(" AND r.day <= ").push_bind(to_day);
+        }
+        query_builder.push(EARNINGS_REPORT_GROUP_BY);
+
+        let rows = query_builder
+            .build()
+            .fetch_all(&mut *self.connection().await?)
+            .await?;
+
+        rows.into_iter()
+            .map(|row| EarningsReportRow::from_row(&row).map_err(AtomaStateManagerError::from))
+            .collect()
+    }
+}
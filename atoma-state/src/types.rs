@@ -30,6 +30,8 @@ pub struct Task {
     pub security_level: i64,
     /// Optional minimum reputation score required for the task
     pub minimum_reputation_score: Option<i64>,
+    /// Optional epoch timestamp when the task was permanently removed
+    pub removed_at_epoch: Option<i64>,
 }
 
 impl From<TaskRegisteredEvent> for Task {
@@ -44,6 +46,7 @@ impl From<TaskRegisteredEvent> for Task {
             deprecated_at_epoch: None,
             security_level: i64::from(event.security_level.inner),
             minimum_reputation_score: event.minimum_reputation_score.map(i64::from),
+            removed_at_epoch: None,
         }
     }
 }
@@ -78,6 +81,9 @@ pub struct Stack {
     pub is_claimed: bool,
     /// Indicates whether the stack is locked for claim (for testing purposes)
     pub is_locked_for_claim: bool,
+    /// Lifecycle status of the stack
+    #[sqlx(try_from = "String")]
+    pub status: StackStatus,
 }
 
 impl From<StackCreatedEvent> for Stack {
@@ -96,6 +102,7 @@ impl From<StackCreatedEvent> for Stack {
             num_total_messages: 0,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         }
     }
 }
@@ -116,10 +123,154 @@ impl From<StackCreateAndUpdateEvent> for Stack {
             num_total_messages: 1,
             is_claimed: false,
             is_locked_for_claim: false,
+            status: StackStatus::Active,
         }
     }
 }
 
+/// Represents the lifecycle status of a stack
+///
+/// A stack is created `Active`, and can be locked for claim once (almost) filled. It then enters
+/// the settlement period, possibly goes through an attestation dispute, and is finally claimed,
+/// which ends its lifecycle. Transitions are validated by `AtomaState::transition_stack_status`,
+/// while the transitions reported by the Atoma contract are applied with
+/// `AtomaState::apply_chain_stack_status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum StackStatus {
+    /// The stack accepts new requests
+    Active,
+    /// The stack is locked, as its funds are about to be claimed
+    LockedForClaim,
+    /// A settlement ticket was submitted for the stack, which is in its settlement period
+    Settling,
+    /// The settlement of the stack is disputed by an attestation node
+    InDispute,
+    /// The settlement period of the stack ended, and its funds can be claimed
+    Settled,
+    /// The funds of the stack were claimed
+    Claimed,
+}
+
+impl StackStatus {
+    /// Returns the name of the status, as stored in the database
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Active => "Active",
+            Self::LockedForClaim => "LockedForClaim",
+            Self::Settling => "Settling",
+            Self::InDispute => "InDispute",
+            Self::Settled => "Settled",
+            Self::Claimed => "Claimed",
+        }
+    }
+
+    /// Checks whether a stack can move from this status to `next`
+    #[must_use]
+    pub const fn can_transition_to(self, next: Self) -> bool {
+        matches!(
+            (self, next),
+            (
+                Self::Active,
+                Self::LockedForClaim | Self::Settling | Self::Claimed
+            ) | (
+                Self::LockedForClaim,
+                Self::Active | Self::Settling | Self::Claimed
+            ) | (
+                Self::Settling,
+                Self::InDispute | Self::Settled | Self::Claimed
+            ) | (Self::InDispute, Self::Settled | Self::Claimed)
+                | (Self::Settled, Self::Claimed)
+        )
+    }
+
+    /// Returns the stage of the lifecycle the status belongs to. Stages only move forward,
+    /// except between `Active` and `LockedForClaim`, which share the first stage.
+    #[must_use]
+    pub const fn stage(self) -> u8 {
+        match self {
+            Self::Active | Self::LockedForClaim => 0,
+            Self::Settling => 1,
+            Self::InDispute => 2,
+            Self::Settled => 3,
+            Self::Claimed => 4,
+        }
+    }
+}
+
+impl std::fmt::Display for StackStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<String> for StackStatus {
+    type Error = AtomaStateManagerError;
+
+    fn try_from(status: String) -> Result<Self, Self::Error> {
+        match status.as_str() {
+            "Active" => Ok(Self::Active),
+            "LockedForClaim" => Ok(Self::LockedForClaim),
+            "Settling" => Ok(Self::Settling),
+            "InDispute" => Ok(Self::InDispute),
+            "Settled" => Ok(Self::Settled),
+            "Claimed" => Ok(Self::Claimed),
+            _ => Err(AtomaStateManagerError::InvalidStackStatus(status)),
+        }
+    }
+}
+
+/// Represents a transition of the lifecycle status of a stack
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct StackStatusTransition {
+    /// Position of the transition in the history of all the transitions
+    pub id: i64,
+    /// Unique small integer identifier for the stack
+    pub stack_small_id: i64,
+    /// Status of the stack before the transition
+    #[sqlx(try_from = "String")]
+    pub from_status: StackStatus,
+    /// Status of the stack after the transition
+    #[sqlx(try_from = "String")]
+    pub to_status: StackStatus,
+    /// Epoch at which the transition happened, when known from the triggering event
+    pub epoch: Option<i64>,
+    /// Time of the transition, in milliseconds since the Unix epoch: the timestamp of the
+    /// checkpoint of the triggering Sui event, or the time the node applied the transition
+    pub timestamp_ms: Option<i64>,
+    /// Name of the event which triggered the transition
+    pub triggering_event: String,
+}
+
+/// Represents a row of an earnings report: the usage and the earnings of a node for a task and a
+/// stack owner over one day
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct EarningsReportRow {
+    /// Day of the row (UTC), formatted as `YYYY-MM-DD`. The usage is counted on the day the
+    /// requests were served, the earnings on the day the stacks were claimed. `None` for the
+    /// claimed stacks whose claim time is unknown
+    pub day: Option<String>,
+    /// Unique small integer identifier for the task of the stacks
+    pub task_small_id: i64,
+    /// Name of the model of the task, if any
+    pub model_name: Option<String>,
+    /// Address of the owner of the stacks
+    pub owner_address: String,
+    /// Number of requests served
+    pub num_requests: i64,
+    /// Number of compute units consumed by the requests served
+    pub compute_units_consumed: i64,
+    /// Number of stacks claimed
+    pub num_claimed_stacks: i64,
+    /// Number of compute units claimed for the claimed stacks
+    pub compute_units_claimed: i64,
+    /// Revenue of the claimed stacks, that is the claimed compute units times the price per one
+    /// million compute units of the stacks, divided by one million
+    pub revenue: i64,
+    /// Amount refunded to the owner of the claimed stacks
+    pub refunds: i64,
+}
+
 /// Represents the availability of a stack
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum StackAvailability {
@@ -224,6 +375,20 @@ impl From<StackAttestationDisputeEvent> for StackAttestationDispute {
     }
 }
 
+/// Represents a stack moved to the archive tables, once claimed and settled for longer than the
+/// retention period, together with its settlement tickets and attestation disputes
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ArchivedStack {
+    /// The archived stack
+    pub stack: Stack,
+    /// Epoch at which the stack was archived
+    pub archived_at_epoch: i64,
+    /// Settlement tickets of the stack
+    pub settlement_tickets: Vec<StackSettlementTicket>,
+    /// Attestation disputes of the stack
+    pub attestation_disputes: Vec<StackAttestationDispute>,
+}
+
 /// Represents a node subscription to a task
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct NodeSubscription {
@@ -259,8 +424,13 @@ pub enum AtomaAtomaStateManagerEvent {
         estimated_total_compute_units: i64,
         /// Total number of compute units in the stack
         total_compute_units: i64,
+        /// Number of requests served with the compute units, counted in the usage of the stack
+        /// (0 when the compute units locked for failed requests are released)
+        num_requests: i64,
         /// Number of concurrent requests for the stack
         concurrent_requests: u64,
+        /// ID of the request the update originates from, if any, for tracing
+        request_id: Option<String>,
     },
     /// Represents an update to the total hash of a stack
     UpdateStackTotalHash {
@@ -268,6 +438,8 @@ pub enum AtomaAtomaStateManagerEvent {
         stack_small_id: i64,
         /// Total hash of the stack
         total_hash: [u8; 32],
+        /// ID of the request the update originates from, if any, for tracing
+        request_id: Option<String>,
     },
     /// Gets an available stack with enough compute units for a given stack and public key
     GetAvailableStackWithComputeUnits {
@@ -280,6 +452,156 @@ pub enum AtomaAtomaStateManagerEvent {
         /// Oneshot channel to send the result back to the sender channel
         result_sender: oneshot::Sender<Result<StackAvailability, AtomaStateManagerError>>,
     },
+    /// Gets a stack by its small id, if the node knows about it
+    GetStack {
+        /// Unique small integer identifier for the stack
+        stack_small_id: i64,
+        /// Oneshot channel to send the result back to the sender channel
+        result_sender: oneshot::Sender<Result<Option<Stack>, AtomaStateManagerError>>,
+    },
+    /// Creates a new batch, together with all of its requests
+    CreateBatch {
+        /// The batch to create
+        batch: Batch,
+        /// The requests of the batch
+        requests: Vec<BatchRequest>,
+        /// Oneshot channel to send the result back to the sender channel
+        result_sender: oneshot::Sender<Result<(), AtomaStateManagerError>>,
+    },
+    /// Gets a batch by its identifier
+    GetBatch {
+        /// Unique identifier for the batch
+        batch_id: String,
+        /// Oneshot channel to send the result back to the sender channel
+        result_sender: oneshot::Sender<Result<Batch, AtomaStateManagerError>>,
+    },
+    /// Gets all the requests of a batch, ordered by their line index
+    GetBatchRequests {
+        /// Unique identifier for the batch
+        batch_id: String,
+        /// Oneshot channel to send the result back to the sender channel
+        result_sender: oneshot::Sender<Result<Vec<BatchRequest>, AtomaStateManagerError>>,
+    },
+    /// Gets the models the node is subscribed to, together with their tasks
+    GetNodeModelTasks {
+        /// Small IDs of the nodes operated by the current node (all nodes if `None`)
+        node_small_ids: Option<Vec<i64>>,
+        /// Oneshot channel to send the result back to the sender channel
+        result_sender: oneshot::Sender<Result<Vec<ModelTasks>, AtomaStateManagerError>>,
+    },
+    /// Gets all the batches that are still in progress
+    GetInProgressBatches {
+        /// Oneshot channel to send the result back to the sender channel
+        result_sender: oneshot::Sender<Result<Vec<Batch>, AtomaStateManagerError>>,
+    },
+    /// Gets the oldest pending batch requests, up to a limit
+    GetPendingBatchRequests {
+        /// Maximum number of requests to return
+        limit: i64,
+        /// Oneshot channel to send the result back to the sender channel
+        result_sender: oneshot::Sender<Result<Vec<BatchRequest>, AtomaStateManagerError>>,
+    },
+    /// Stores the result of a processed batch request
+    CompleteBatchRequest {
+        /// The processed request, with its status, response and used compute units set
+        request: BatchRequest,
+        /// Oneshot channel to send the batch back to the sender channel, if this request
+        /// was the last one of the batch to be processed
+        result_sender: oneshot::Sender<Result<Option<Batch>, AtomaStateManagerError>>,
+    },
+}
+
+/// A model the node is subscribed to on-chain, either directly or through one of the model's
+/// tasks, together with the tasks of the model the node is subscribed to
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ModelTasks {
+    /// Name of the model
+    pub model_name: String,
+    /// Tasks of the model the node is subscribed to, that are neither deprecated nor removed
+    pub active_task_small_ids: Vec<i64>,
+    /// Tasks of the model the node is subscribed to, that were deprecated or removed
+    pub inactive_task_small_ids: Vec<i64>,
+}
+
+impl ModelTasks {
+    /// Returns whether the node should accept new requests for the model.
+    ///
+    /// A model is active if at least one of its tasks is active, or if the node is subscribed
+    /// to the model but not (yet) to any of its tasks. Once all the tasks of a model are
+    /// deprecated or removed, the model is no longer active.
+    #[must_use]
+    pub fn is_active(&self) -> bool {
+        !self.active_task_small_ids.is_empty() || self.inactive_task_small_ids.is_empty()
+    }
+}
+
+/// Status of a batch whose requests are still being processed
+pub const BATCH_STATUS_IN_PROGRESS: &str = "in_progress";
+
+/// Status of a batch whose requests have all been processed
+pub const BATCH_STATUS_COMPLETED: &str = "completed";
+
+/// Status of a batch request that has not been processed yet
+pub const BATCH_REQUEST_STATUS_PENDING: &str = "pending";
+
+/// Status of a batch request that has been processed successfully
+pub const BATCH_REQUEST_STATUS_COMPLETED: &str = "completed";
+
+/// Status of a batch request whose processing failed
+pub const BATCH_REQUEST_STATUS_FAILED: &str = "failed";
+
+/// Represents an asynchronous batch of inference requests, submitted against a stack
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct Batch {
+    /// Unique identifier for the batch
+    pub batch_id: String,
+    /// Unique small integer identifier of the stack the batch is paid with
+    pub stack_small_id: i64,
+    /// Sui address of the user that submitted the batch
+    pub owner_address: String,
+    /// The endpoint every request of the batch targets (e.g. `/v1/chat/completions`)
+    pub endpoint: String,
+    /// Status of the batch, either `in_progress` or `completed`
+    pub status: String,
+    /// Number of compute units reserved on the stack for the whole batch
+    pub reserved_compute_units: i64,
+    /// Number of compute units actually used by the processed requests
+    pub used_compute_units: i64,
+    /// Total number of requests in the batch
+    pub total_requests: i64,
+    /// Number of requests that were processed successfully
+    pub completed_requests: i64,
+    /// Number of requests whose processing failed
+    pub failed_requests: i64,
+    /// Unix timestamp (in seconds) at which the batch was created
+    pub created_at: i64,
+    /// Unix timestamp (in seconds) at which the last request of the batch was processed
+    pub completed_at: Option<i64>,
+}
+
+/// Represents a single request (one line of the input file) of a batch
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct BatchRequest {
+    /// Identifier of the batch the request belongs to
+    pub batch_id: String,
+    /// Zero-based index of the request within the batch input file
+    pub line_index: i64,
+    /// Identifier provided by the user to match requests with their results
+    pub custom_id: String,
+    /// JSON encoded body of the request
+    pub body: String,
+    /// Number of compute units reserved for the request
+    pub estimated_compute_units: i64,
+    /// Number of compute units actually used by the request
+    pub used_compute_units: i64,
+    /// Status of the request, one of `pending`, `completed` or `failed`
+    pub status: String,
+    /// HTTP status code of the request processing, once processed
+    pub status_code: Option<i64>,
+    /// JSON encoded (signed) response body, if the request was processed successfully
+    pub response: Option<String>,
+    /// Error message, if the request processing failed
+    pub error: Option<String>,
 }
 
 /// Represents the result of updating the number of compute units in a stack and claiming funds
@@ -297,3 +619,32 @@ pub struct UpdateStackNumComputeUnitsAndClaimFunds {
     /// Indicates whether the stack is locked for claim
     pub is_locked_for_claim: bool,
 }
+
+/// Represents the number of requests in flight for a stack, as counted by one owner (an Atoma
+/// service replica, or the in-progress batches, which are shared by all the replicas)
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct ConcurrentRequestsCount {
+    /// Owner of the count, that is, the identifier of the replica serving the requests
+    pub owner: String,
+    /// Unique small integer identifier for the stack
+    pub stack_small_id: i64,
+    /// Number of requests in flight for the stack
+    pub num_requests: i64,
+}
+
+/// Represents an Atoma event recorded in the event log, before being applied to the state
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct LoggedEvent {
+    /// Position of the event in the log
+    pub id: i64,
+    /// Digest of the transaction that emitted the event
+    pub tx_digest: String,
+    /// Sequence number of the event within its transaction
+    pub event_seq: i64,
+    /// Name of the event (e.g. `StackCreatedEvent`)
+    pub event_name: String,
+    /// Timestamp of the checkpoint including the transaction, in milliseconds
+    pub checkpoint_timestamp_ms: Option<i64>,
+    /// The event, serialized as JSON
+    pub event: String,
+}
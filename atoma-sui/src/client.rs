@@ -1396,6 +1396,41 @@ impl Client {
         }
         Ok(None)
     }
+
+    /// Returns the sequence number of the latest checkpoint known to the Sui RPC node, which
+    /// also checks that the RPC node is reachable.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Sui RPC node cannot be reached.
+    #[instrument(
+        level = "trace",
+        skip_all,
+        err,
+        fields(endpoint = "get_latest_checkpoint_sequence_number")
+    )]
+    pub async fn get_latest_checkpoint_sequence_number(&self) -> Result<u64> {
+        let client = self.wallet_ctx.get_client().await?;
+        Ok(client
+            .read_api()
+            .get_latest_checkpoint_sequence_number()
+            .await?)
+    }
+
+    /// Returns the current epoch of the Sui network, as reported by the latest Sui system state.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Sui RPC node cannot be reached.
+    #[instrument(level = "trace", skip_all, err, fields(endpoint = "get_current_epoch"))]
+    pub async fn get_current_epoch(&self) -> Result<u64> {
+        let client = self.wallet_ctx.get_client().await?;
+        Ok(client
+            .governance_api()
+            .get_latest_sui_system_state()
+            .await?
+            .epoch)
+    }
 }
 
 #[derive(Debug, Error)]
@@ -194,6 +194,52 @@ pub enum AtomaEvent {
     ClaimedStackEvent(ClaimedStackEvent),
 }
 
+impl AtomaEvent {
+    /// Returns the name of the event.
+    #[must_use]
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::PublishedEvent(_) => "PublishedEvent",
+            Self::NodeRegisteredEvent(_) => "NodeRegisteredEvent",
+            Self::NodeSubscribedToModelEvent(_) => "NodeSubscribedToModelEvent",
+            Self::NodeSubscribedToTaskEvent(_) => "NodeSubscribedToTaskEvent",
+            Self::NodeSubscriptionUpdatedEvent(_) => "NodeSubscriptionUpdatedEvent",
+            Self::NodeUnsubscribedFromTaskEvent(_) => "NodeUnsubscribedFromTaskEvent",
+            Self::TaskRegisteredEvent(_) => "TaskRegisteredEvent",
+            Self::TaskDeprecationEvent(_) => "TaskDeprecationEvent",
+            Self::TaskRemovedEvent(_) => "TaskRemovedEvent",
+            Self::StackCreatedEvent(_) => "StackCreatedEvent",
+            Self::StackCreateAndUpdateEvent(_) => "StackCreateAndUpdateEvent",
+            Self::StackTrySettleEvent(_) => "StackTrySettleEvent",
+            Self::NewStackSettlementAttestationEvent(_) => "NewStackSettlementAttestationEvent",
+            Self::StackSettlementTicketEvent(_) => "StackSettlementTicketEvent",
+            Self::StackSettlementTicketClaimedEvent(_) => "StackSettlementTicketClaimedEvent",
+            Self::StackAttestationDisputeEvent(_) => "StackAttestationDisputeEvent",
+            Self::FirstSubmissionEvent(_) => "FirstSubmissionEvent",
+            Self::DisputeEvent(_) => "DisputeEvent",
+            Self::NewlySampledNodesEvent(_) => "NewlySampledNodesEvent",
+            Self::SettledEvent(_) => "SettledEvent",
+            Self::RetrySettlementEvent(_) => "RetrySettlementEvent",
+            Self::Text2ImagePromptEvent(_) => "Text2ImagePromptEvent",
+            Self::Text2TextPromptEvent(_) => "Text2TextPromptEvent",
+            Self::NewKeyRotationEvent(_) => "NewKeyRotationEvent",
+            Self::NodePublicKeyCommittmentEvent(_) => "NodePublicKeyCommittmentEvent",
+            Self::ClaimedStackEvent(_) => "ClaimedStackEvent",
+        }
+    }
+}
+
+/// Identifies the Sui event an [`AtomaEvent`] was parsed from.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AtomaEventMetadata {
+    /// The digest of the transaction that emitted the event.
+    pub tx_digest: String,
+    /// The sequence number of the event within its transaction.
+    pub event_seq: u64,
+    /// The timestamp of the checkpoint including the transaction, in milliseconds.
+    pub checkpoint_timestamp_ms: Option<u64>,
+}
+
 /// Deserializes a string representation of a number into a numeric type that implements `FromStr`.
 ///
 /// This function is primarily used as a custom deserializer for serde to handle cases where
@@ -229,6 +275,9 @@ pub enum AtomaEvent {
 /// // This will correctly deserialize: {"value": "12345"}
 /// ```
 ///
+/// Numbers are accepted as well, so that events serialized back to JSON (e.g. in the event log
+/// of the state manager) can be deserialized again.
+///
 /// # Note
 ///
 /// Despite the function name containing "u64", it can actually deserialize into any
@@ -237,11 +286,20 @@ pub enum AtomaEvent {
 fn deserialize_string_to_u64<'de, D, T>(deserializer: D) -> std::result::Result<T, D::Error>
 where
     D: serde::de::Deserializer<'de>,
-    T: FromStr,
+    T: FromStr + Deserialize<'de>,
     T::Err: std::fmt::Display,
 {
-    let s = String::deserialize(deserializer)?;
-    s.parse::<T>().map_err(serde::de::Error::custom)
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrNumber<T> {
+        String(String),
+        Number(T),
+    }
+
+    match StringOrNumber::<T>::deserialize(deserializer)? {
+        StringOrNumber::String(s) => s.parse::<T>().map_err(serde::de::Error::custom),
+        StringOrNumber::Number(n) => Ok(n),
+    }
 }
 
 /// Represents an event that is emitted when the Atoma contract is first published.
@@ -1194,6 +1252,32 @@ mod tests {
         assert_eq!(event.price_per_one_million_compute_units, 1000);
     }
 
+    #[test]
+    fn test_atoma_event_serialization_roundtrip() {
+        let json = json!({
+            "owner": "0x123",
+            "stack_id": "stack-001",
+            "stack_small_id": {"inner": "10"},
+            "task_small_id": {"inner": "3"},
+            "selected_node_id": {"inner": "11"},
+            "num_compute_units": "5",
+            "price_per_one_million_compute_units": "1000"
+        });
+        let event = AtomaEvent::StackCreatedEvent((serde_json::from_value(json).unwrap(), Some(7)));
+        assert_eq!(event.name(), "StackCreatedEvent");
+
+        let serialized = serde_json::to_string(&event).unwrap();
+        let AtomaEvent::StackCreatedEvent((event, timestamp_ms)) =
+            serde_json::from_str(&serialized).unwrap()
+        else {
+            panic!("Unexpected event variant");
+        };
+        assert_eq!(event.stack_small_id.inner, 10);
+        assert_eq!(event.num_compute_units, 5);
+        assert_eq!(event.price_per_one_million_compute_units, 1000);
+        assert_eq!(timestamp_ms, Some(7));
+    }
+
     #[test]
     fn test_stack_try_settle_event_deserialization() {
         let json = json!({
@@ -1,13 +1,17 @@
 use crate::{
     config::Config as SuiConfig,
     events::{
-        AtomaEvent, AtomaEventIdentifier, StackCreateAndUpdateEvent, StackCreatedEvent,
-        SuiEventParseError,
+        AtomaEvent, AtomaEventIdentifier, AtomaEventMetadata, StackCreateAndUpdateEvent,
+        StackCreatedEvent, SuiEventParseError,
     },
 };
 use flume::Sender;
 use serde_json::Value;
-use std::{path::Path, str::FromStr, time::Duration};
+use std::{
+    path::Path,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use sui_sdk::{
     rpc_types::{EventFilter, EventPage, SuiTransactionBlockResponseOptions},
     types::{base_types::SuiAddress, digests::TransactionDigest, event::EventID, Identifier},
@@ -17,7 +21,7 @@ use thiserror::Error;
 use tokio::sync::{
     mpsc::{self, UnboundedSender},
     oneshot,
-    watch::Receiver,
+    watch::{self, Receiver},
 };
 use tracing::{error, info, instrument, trace};
 
@@ -34,17 +38,48 @@ type ComputeUnits = i64;
 /// Represents the small identifier for a stack, stored as a 64-bit unsigned integer.
 type StackSmallId = i64;
 
-/// Represents the result of a blockchain query for stack information.
+/// Represents the result of a blockchain query for stack information: the small ID of the stack
+/// created by the transaction, if any, and its compute units, if enough for the request.
 type StackQueryResult = (Option<StackSmallId>, Option<ComputeUnits>);
 
 /// Represents a receiver for stack retrieval requests.
-pub(crate) type StackRetrieveReceiver = mpsc::UnboundedReceiver<(
+pub type StackRetrieveReceiver = mpsc::UnboundedReceiver<(
     TransactionDigest,
     ComputeUnits,
     StackSmallId,
     oneshot::Sender<StackQueryResult>,
 )>;
 
+/// Synchronization status of the subscriber with the Atoma events emitted on Sui.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SubscriberStatus {
+    /// Time (in milliseconds since the Unix epoch) at which the subscriber last read all the
+    /// available events, if it did since it started.
+    pub last_synced_at_ms: Option<u64>,
+
+    /// Timestamp (in milliseconds since the Unix epoch) of the last event read by the subscriber.
+    pub last_event_timestamp_ms: Option<u64>,
+}
+
+impl SubscriberStatus {
+    /// Returns the lag of the subscriber, that is, the time elapsed since it last read all the
+    /// available events, or `None` if it never did since it started.
+    #[must_use]
+    pub fn lag(&self) -> Option<Duration> {
+        self.last_synced_at_ms.map(|last_synced_at_ms| {
+            Duration::from_millis(now_ms().saturating_sub(last_synced_at_ms))
+        })
+    }
+}
+
+/// Returns the current time, in milliseconds since the Unix epoch.
+#[allow(clippy::cast_possible_truncation)]
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
+
 /// A subscriber for Sui blockchain events.
 ///
 /// This struct provides functionality to subscribe to and process events
@@ -60,7 +95,7 @@ pub struct Subscriber {
     filter: EventFilter,
 
     /// Sender to stream each received event to the `AtomaStateManager` running task.
-    state_manager_sender: Sender<AtomaEvent>,
+    state_manager_sender: Sender<(AtomaEvent, AtomaEventMetadata)>,
 
     /// Sender to stream confidential compute requests to the `AtomaTDX` running task.
     confidential_compute_service_sender: UnboundedSender<AtomaEvent>,
@@ -73,6 +108,9 @@ pub struct Subscriber {
 
     /// The shutdown signal.
     shutdown_signal: Receiver<bool>,
+
+    /// Sender publishing the synchronization status of the subscriber.
+    status_sender: watch::Sender<SubscriberStatus>,
 }
 
 impl Subscriber {
@@ -85,7 +123,7 @@ impl Subscriber {
     pub fn new(
         config: SuiConfig,
         is_proxy: bool,
-        state_manager_sender: Sender<AtomaEvent>,
+        state_manager_sender: Sender<(AtomaEvent, AtomaEventMetadata)>,
         stack_retrieve_receiver: StackRetrieveReceiver,
         confidential_compute_service_sender: UnboundedSender<AtomaEvent>,
         shutdown_signal: Receiver<bool>,
@@ -102,9 +140,17 @@ impl Subscriber {
             confidential_compute_service_sender,
             stack_retrieve_receiver,
             shutdown_signal,
+            status_sender: watch::Sender::new(SubscriberStatus::default()),
         }
     }
 
+    /// Returns a receiver for the synchronization status of the subscriber, updated as it
+    /// reads events.
+    #[must_use]
+    pub fn status(&self) -> Receiver<SubscriberStatus> {
+        self.status_sender.subscribe()
+    }
+
     /// Creates a new `Subscriber` instance from a configuration file.
     ///
     /// This method reads the configuration from the specified file path and initializes
@@ -126,7 +172,7 @@ impl Subscriber {
     pub fn new_from_config<P: AsRef<Path>>(
         config_path: P,
         is_proxy: bool,
-        state_manager_sender: Sender<AtomaEvent>,
+        state_manager_sender: Sender<(AtomaEvent, AtomaEventMetadata)>,
         stack_retrieve_receiver: StackRetrieveReceiver,
         confidential_compute_service_sender: UnboundedSender<AtomaEvent>,
         shutdown_signal: Receiver<bool>,
@@ -224,65 +270,18 @@ impl Subscriber {
         loop {
             tokio::select! {
                 Some((tx_digest, estimated_compute_units, selected_stack_small_id, result_sender)) = self.stack_retrieve_receiver.recv() => {
-                    let tx_events = client
-                        .read_api()
-                        .get_transaction_with_options(
-                            tx_digest,
-                            SuiTransactionBlockResponseOptions {
-                                show_events: true, ..Default::default()
-                            }
-                        )
-                        .await?
-                        .events;
-                    let mut compute_units = None;
-                    let mut stack_small_id = None;
-                    if let Some(tx_events) = tx_events {
-                        for event in &tx_events.data {
-                            let event_identifier = AtomaEventIdentifier::from_str(event.type_.name.as_str())?;
-                            if event_identifier == AtomaEventIdentifier::StackCreatedEvent {
-                                // NOTE: In this case, the transaction contains a stack creation event,
-                                // which means that whoever made a request to the service has already paid
-                                // to buy new compute units.
-                                // We need to count the compute units used by the transaction.
-                                let event: StackCreatedEvent = serde_json::from_value(event.parsed_json.clone())?;
-
-                                // Move the cast to a separate statement with the attribute
-                                #[allow(clippy::cast_sign_loss, clippy::cast_possible_wrap)]
-                                let selected_stack_small_id_u64 = selected_stack_small_id as u64;
-                                if event.stack_small_id.inner != selected_stack_small_id_u64 {
-                                    continue;
-                                }
-
-                                // Move the cast to a separate statement with the attribute
-                                #[allow(clippy::cast_possible_wrap)]
-                                let event_compute_units = event.num_compute_units as i64;
-                                if estimated_compute_units > event_compute_units {
-                                    break;
-                                }
-
-                                let event: StackCreateAndUpdateEvent = (event, estimated_compute_units).into();
-
-                                // Move the casts to separate statements with attributes
-                                #[allow(clippy::cast_possible_wrap)]
-                                let compute_units_val = event.num_compute_units as i64;
-                                compute_units = Some(compute_units_val);
-
-                                #[allow(clippy::cast_possible_wrap)]
-                                let stack_small_id_val = event.stack_small_id.inner as i64;
-                                stack_small_id = Some(stack_small_id_val);
-
-                                self.state_manager_sender
-                                    .send(AtomaEvent::StackCreateAndUpdateEvent(event))
-                                    .map_err(Box::new)?;
-                                // We found the stack creation event, so we can break out of the loop
-                                break;
-                            }
-                        }
-                    }
+                    let stack_query_result = retrieve_stack(
+                        &client,
+                        &self.state_manager_sender,
+                        tx_digest,
+                        estimated_compute_units,
+                        selected_stack_small_id,
+                    )
+                    .await?;
                     // Send the compute units to the Atoma service, so it can be used to validate the
                     // request.
                     result_sender
-                        .send((stack_small_id, compute_units))
+                        .send(stack_query_result)
                         .map_err(|_| SuiEventSubscriberError::SendComputeUnitsError)?;
                 }
                 page = client.event_api().query_events(self.filter.clone(), cursor, limit, false) => {
@@ -304,6 +303,11 @@ impl Subscriber {
                     cursor = next_cursor;
 
                     for sui_event in data {
+                        if let Some(timestamp_ms) = sui_event.timestamp_ms {
+                            self.status_sender.send_modify(|status| {
+                                status.last_event_timestamp_ms = Some(timestamp_ms);
+                            });
+                        }
                         let event_name = sui_event.type_.name;
                         trace!(
                             target = "atoma-sui-subscriber",
@@ -314,6 +318,11 @@ impl Subscriber {
                         match AtomaEventIdentifier::from_str(event_name.as_str()) {
                             Ok(atoma_event_id) => {
                                 let sender = sui_event.sender;
+                                let metadata = AtomaEventMetadata {
+                                    tx_digest: sui_event.id.tx_digest.to_string(),
+                                    event_seq: sui_event.id.event_seq,
+                                    checkpoint_timestamp_ms: sui_event.timestamp_ms,
+                                };
                                 let atoma_event = match parse_event(&atoma_event_id, sui_event.parsed_json, sender, sui_event.timestamp_ms).await {
                                     Ok(atoma_event) => atoma_event,
                                     Err(e) => {
@@ -331,7 +340,7 @@ impl Subscriber {
                                     self.config.node_small_ids().as_ref(),
                                     self.config.task_small_ids().as_ref(),
                                 ) {
-                                    self.handle_atoma_event(atoma_event_id, atoma_event).await?;
+                                    self.handle_atoma_event(atoma_event_id, atoma_event, metadata).await?;
                                 } else {
                                     continue;
                                 }
@@ -350,6 +359,9 @@ impl Subscriber {
                     if !has_next_page {
                         // Update the cursor file with the current cursor
                         write_cursor_to_toml_file(cursor, &self.config.cursor_path())?;
+                        self.status_sender.send_modify(|status| {
+                            status.last_synced_at_ms = Some(now_ms());
+                        });
                         // No new events to read, so let's wait for a while
                         trace!(
                             target = "atoma-sui-subscriber",
@@ -402,6 +414,8 @@ impl Subscriber {
     ///
     /// * `atoma_event_id` - The identifier specifying the type of Atoma event
     /// * `atoma_event` - The actual event data to be processed
+    /// * `metadata` - Identifies the Sui event the Atoma event was parsed from, so that the state
+    ///   manager can record it in its event log
     ///
     /// # Returns
     ///
@@ -421,7 +435,7 @@ impl Subscriber {
     /// # async fn example(subscriber: &SuiEventSubscriber) -> Result<(), Box<dyn std::error::Error>> {
     /// let event_id = AtomaEventIdentifier::TaskRegisteredEvent;
     /// let event = AtomaEvent::TaskRegisteredEvent(/* ... */);
-    /// subscriber.handle_atoma_event(event_id, event).await?;
+    /// subscriber.handle_atoma_event(event_id, event, metadata).await?;
     /// # Ok(())
     /// # }
     /// ```
@@ -430,6 +444,7 @@ impl Subscriber {
         &self,
         atoma_event_id: AtomaEventIdentifier,
         atoma_event: AtomaEvent,
+        metadata: AtomaEventMetadata,
     ) -> Result<()> {
         if atoma_event_id == AtomaEventIdentifier::NewKeyRotationEvent && !self.is_proxy {
             // NOTE: If the subscriber is a node, we send the event to the confidential compute service
@@ -447,19 +462,153 @@ impl Subscriber {
                     SuiEventSubscriberError::SendComputeUnitsError
                 })?;
         } else {
-            self.state_manager_sender.send(atoma_event).map_err(|e| {
-                error!(
-                    target = "atoma-sui-subscriber",
-                    event = "subscriber-send-event-error",
-                    "Failed to send event: {e}"
-                );
-                Box::new(e)
-            })?;
+            self.state_manager_sender
+                .send((atoma_event, metadata))
+                .map_err(|e| {
+                    error!(
+                        target = "atoma-sui-subscriber",
+                        event = "subscriber-send-event-error",
+                        "Failed to send event: {e}"
+                    );
+                    Box::new(e)
+                })?;
         }
         Ok(())
     }
 }
 
+/// Retrieves the stack created by a transaction for the selected stack and, if it has enough
+/// compute units for the request, forwards its creation to the state manager.
+///
+/// Returns the small id of the stack, if found, and its number of compute units, if enough for
+/// the request.
+async fn retrieve_stack(
+    client: &SuiClient,
+    state_manager_sender: &Sender<(AtomaEvent, AtomaEventMetadata)>,
+    tx_digest: TransactionDigest,
+    estimated_compute_units: ComputeUnits,
+    selected_stack_small_id: StackSmallId,
+) -> Result<StackQueryResult> {
+    let tx_events = client
+        .read_api()
+        .get_transaction_with_options(
+            tx_digest,
+            SuiTransactionBlockResponseOptions {
+                show_events: true,
+                ..Default::default()
+            },
+        )
+        .await?
+        .events;
+    let mut compute_units = None;
+    let mut stack_small_id = None;
+    if let Some(tx_events) = tx_events {
+        for event in &tx_events.data {
+            let event_identifier = AtomaEventIdentifier::from_str(event.type_.name.as_str())?;
+            if event_identifier == AtomaEventIdentifier::StackCreatedEvent {
+                // NOTE: In this case, the transaction contains a stack creation event,
+                // which means that whoever made a request to the service has already paid
+                // to buy new compute units.
+                // We need to count the compute units used by the transaction.
+                let metadata = AtomaEventMetadata {
+                    tx_digest: tx_digest.to_string(),
+                    event_seq: event.id.event_seq,
+                    checkpoint_timestamp_ms: event.timestamp_ms,
+                };
+                let event: StackCreatedEvent = serde_json::from_value(event.parsed_json.clone())?;
+
+                // Move the cast to a separate statement with the attribute
+                #[allow(clippy::cast_sign_loss, clippy::cast_possible_wrap)]
+                let selected_stack_small_id_u64 = selected_stack_small_id as u64;
+                if event.stack_small_id.inner != selected_stack_small_id_u64 {
+                    continue;
+                }
+
+                // Move the cast to a separate statement with the attribute
+                #[allow(clippy::cast_possible_wrap)]
+                let event_compute_units = event.num_compute_units as i64;
+                if estimated_compute_units > event_compute_units {
+                    // NOTE: The stack exists, but it cannot serve the request, so we report it
+                    // without compute units.
+                    stack_small_id = Some(selected_stack_small_id);
+                    break;
+                }
+
+                let event: StackCreateAndUpdateEvent = (event, estimated_compute_units).into();
+
+                // Move the casts to separate statements with attributes
+                #[allow(clippy::cast_possible_wrap)]
+                let compute_units_val = event.num_compute_units as i64;
+                compute_units = Some(compute_units_val);
+
+                #[allow(clippy::cast_possible_wrap)]
+                let stack_small_id_val = event.stack_small_id.inner as i64;
+                stack_small_id = Some(stack_small_id_val);
+
+                state_manager_sender
+                    .send((AtomaEvent::StackCreateAndUpdateEvent(event), metadata))
+                    .map_err(Box::new)?;
+                // We found the stack creation event, so we can break out of the loop
+                break;
+            }
+        }
+    }
+    Ok((stack_small_id, compute_units))
+}
+
+/// Answers the stack retrieval requests of an Atoma service running separately from the
+/// subscriber (e.g. a replica of the service), until a shutdown signal is received.
+///
+/// As with the subscriber, the stacks created by the requested transactions are forwarded to
+/// the state manager. Errors are logged, and the stack is then reported as not found.
+///
+/// # Errors
+///
+/// Returns an error if the Sui client cannot be built.
+#[instrument(level = "info", skip_all)]
+pub async fn run_stack_retriever(
+    config: &SuiConfig,
+    state_manager_sender: Sender<(AtomaEvent, AtomaEventMetadata)>,
+    mut stack_retrieve_receiver: StackRetrieveReceiver,
+    mut shutdown_signal: Receiver<bool>,
+) -> Result<()> {
+    let client = Subscriber::build_client(config).await?;
+    loop {
+        tokio::select! {
+            request = stack_retrieve_receiver.recv() => {
+                let Some((tx_digest, estimated_compute_units, selected_stack_small_id, result_sender)) = request else {
+                    break;
+                };
+                let stack_query_result = retrieve_stack(
+                    &client,
+                    &state_manager_sender,
+                    tx_digest,
+                    estimated_compute_units,
+                    selected_stack_small_id,
+                )
+                .await
+                .unwrap_or_else(|e| {
+                    error!(
+                        target = "atoma-sui-subscriber",
+                        event = "stack-retrieval-error",
+                        "Failed to retrieve stack from transaction {tx_digest}: {e}"
+                    );
+                    (None, None)
+                });
+                result_sender
+                    .send(stack_query_result)
+                    .map_err(|_| SuiEventSubscriberError::SendComputeUnitsError)?;
+            }
+            shutdown_signal_changed = shutdown_signal.changed() => {
+                if shutdown_signal_changed.is_err() || *shutdown_signal.borrow() {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Reads an event cursor from a TOML file.
 ///
 /// This function attempts to read and parse an event cursor from the specified file path.
@@ -758,7 +907,7 @@ pub enum SuiEventSubscriberError {
     #[error("Failed to deserialize event: {0}")]
     DeserializeError(#[from] serde_json::Error),
     #[error("Failed to send event to state manager: {0}")]
-    SendEventError(#[from] Box<flume::SendError<AtomaEvent>>),
+    SendEventError(#[from] Box<flume::SendError<(AtomaEvent, AtomaEventMetadata)>>),
     #[error("Failed to send compute units to state manager")]
     SendComputeUnitsError,
     #[error("Failed to read/write cursor to file: {0}")]
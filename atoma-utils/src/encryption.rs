@@ -1,12 +1,168 @@
-use aes_gcm::{aead::Aead, Aes256Gcm, Error as AesError, KeyInit};
+use aes_gcm::{
+    aead::{Aead, Payload},
+    Aes256Gcm, Error as AesError, KeyInit,
+};
+use chacha20poly1305::ChaCha20Poly1305;
 use hkdf::Hkdf;
 use sha2::Sha256;
 use x25519_dalek::SharedSecret;
 
 pub const NONCE_BYTE_SIZE: usize = 12;
 
+/// Size of the secret from which symmetric keys are derived, in bytes
+pub const SECRET_BYTE_SIZE: usize = 32;
+
+/// Confidential compute protocol version in which the cleartext request envelope
+/// is not authenticated (the ciphertext is encrypted without associated data)
+pub const CONFIDENTIAL_PROTOCOL_VERSION_V1: u32 = 1;
+
+/// Confidential compute protocol version in which the cleartext request envelope
+/// fields are authenticated as AES-GCM associated data
+pub const CONFIDENTIAL_PROTOCOL_VERSION_V2: u32 = 2;
+
+/// Confidential compute protocol version in which the request is an HPKE (RFC 9180)
+/// base mode envelope, see [`crate::hpke`]
+pub const CONFIDENTIAL_PROTOCOL_VERSION_HPKE: u32 = 3;
+
+/// Version of the encrypted stream chunk format, sent along every encrypted chunk.
+///
+/// Chunks of this version are encrypted under a per-chunk nonce and authenticated against
+/// the request ID, the chunk index and the end-of-stream marker. Chunks sent without a
+/// version predate this format and cannot be decrypted with [`StreamDecryptor`].
+pub const STREAM_CHUNK_VERSION: u32 = 1;
+
+/// Domain separation tag prepended to the associated data of every encrypted stream chunk
+const STREAM_CHUNK_AAD_TAG: &[u8] = b"atoma-stream-chunk-v1";
+
+/// Domain separation tag prepended to the associated data of confidential request envelopes
+const REQUEST_ENVELOPE_AAD_TAG: &[u8] = b"atoma-confidential-request";
+
 type Result<T> = std::result::Result<T, Error>;
 
+/// AEAD algorithms supported for confidential payloads
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AeadAlgorithm {
+    /// AES-256-GCM, used by every protocol version prior to HPKE
+    #[default]
+    Aes256Gcm,
+    /// ChaCha20-Poly1305
+    ChaCha20Poly1305,
+}
+
+impl AeadAlgorithm {
+    /// Returns the HPKE AEAD identifier of the algorithm (RFC 9180, section 7.3)
+    #[must_use]
+    pub const fn hpke_id(self) -> u16 {
+        match self {
+            Self::Aes256Gcm => 0x0002,
+            Self::ChaCha20Poly1305 => 0x0003,
+        }
+    }
+
+    /// Returns the algorithm for the given HPKE AEAD identifier, if supported
+    #[must_use]
+    pub const fn from_hpke_id(id: u16) -> Option<Self> {
+        match id {
+            0x0002 => Some(Self::Aes256Gcm),
+            0x0003 => Some(Self::ChaCha20Poly1305),
+            _ => None,
+        }
+    }
+}
+
+/// Secret from which the symmetric key of a confidential payload is derived,
+/// together with the AEAD algorithm the key is used with.
+///
+/// For the X25519 based protocol versions, this is the Diffie-Hellman shared secret
+/// used with AES-256-GCM. For HPKE requests, this is the response secret exported
+/// from the request HPKE context, see [`crate::hpke::export_response_secret`].
+#[derive(Clone)]
+pub struct SymmetricSecret {
+    /// The secret bytes
+    bytes: [u8; SECRET_BYTE_SIZE],
+    /// The AEAD algorithm keys derived from the secret are used with
+    aead: AeadAlgorithm,
+}
+
+impl SymmetricSecret {
+    /// Constructor
+    ///
+    /// # Arguments
+    /// * `bytes` - The secret bytes
+    /// * `aead` - The AEAD algorithm keys derived from the secret are used with
+    #[must_use]
+    pub const fn new(bytes: [u8; SECRET_BYTE_SIZE], aead: AeadAlgorithm) -> Self {
+        Self { bytes, aead }
+    }
+
+    /// Returns the AEAD algorithm keys derived from the secret are used with
+    #[must_use]
+    pub const fn aead(&self) -> AeadAlgorithm {
+        self.aead
+    }
+}
+
+impl From<&SharedSecret> for SymmetricSecret {
+    fn from(shared_secret: &SharedSecret) -> Self {
+        Self::new(shared_secret.to_bytes(), AeadAlgorithm::Aes256Gcm)
+    }
+}
+
+impl std::fmt::Debug for SymmetricSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SymmetricSecret")
+            .field("bytes", &"<redacted>")
+            .field("aead", &self.aead)
+            .finish()
+    }
+}
+
+/// An AEAD cipher instance for one of the supported [`AeadAlgorithm`]s
+pub(crate) enum Cipher {
+    Aes256Gcm(Box<Aes256Gcm>),
+    ChaCha20Poly1305(ChaCha20Poly1305),
+}
+
+impl Cipher {
+    /// Instantiates the cipher for `aead` with the given symmetric key
+    pub(crate) fn new(key: &[u8; SECRET_BYTE_SIZE], aead: AeadAlgorithm) -> Self {
+        match aead {
+            AeadAlgorithm::Aes256Gcm => Self::Aes256Gcm(Box::new(Aes256Gcm::new(key.into()))),
+            AeadAlgorithm::ChaCha20Poly1305 => {
+                Self::ChaCha20Poly1305(ChaCha20Poly1305::new(key.into()))
+            }
+        }
+    }
+
+    /// Encrypts and authenticates `msg`, along the associated data `aad`
+    pub(crate) fn encrypt(
+        &self,
+        nonce: &[u8; NONCE_BYTE_SIZE],
+        msg: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>> {
+        let payload = Payload { msg, aad };
+        match self {
+            Self::Aes256Gcm(cipher) => cipher.encrypt(nonce.into(), payload),
+            Self::ChaCha20Poly1305(cipher) => cipher.encrypt(nonce.into(), payload),
+        }
+        .map_err(Error::EncryptionFailed)
+    }
+
+    /// Decrypts `msg` and verifies its authenticity, along the associated data `aad`
+    pub(crate) fn decrypt(&self, nonce: &[u8], msg: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        if nonce.len() != NONCE_BYTE_SIZE {
+            return Err(Error::DecryptionFailed(AesError));
+        }
+        let payload = Payload { msg, aad };
+        match self {
+            Self::Aes256Gcm(cipher) => cipher.decrypt(nonce.into(), payload),
+            Self::ChaCha20Poly1305(cipher) => cipher.decrypt(nonce.into(), payload),
+        }
+        .map_err(Error::DecryptionFailed)
+    }
+}
+
 /// Decrypts a ciphertext using the provided shared secret and nonce.
 ///
 /// # Arguments
@@ -28,15 +184,72 @@ pub fn decrypt_ciphertext(
     salt: &[u8],
     nonce: &[u8],
 ) -> Result<Vec<u8>> {
-    let hkdf = Hkdf::<Sha256>::new(Some(salt), shared_secret.as_bytes());
-    let mut symmetric_key = [0u8; 32];
-    hkdf.expand(b"", &mut symmetric_key)
-        .map_err(Error::KeyExpansionFailed)?;
+    decrypt_ciphertext_with_associated_data(shared_secret, ciphertext, salt, nonce, &[])
+}
 
-    let cipher = Aes256Gcm::new(&symmetric_key.into());
-    cipher
-        .decrypt(nonce.into(), ciphertext)
-        .map_err(Error::DecryptionFailed)
+/// Decrypts a ciphertext using the provided shared secret and nonce, verifying
+/// that it was encrypted together with the given associated data.
+///
+/// Decrypting with empty associated data is equivalent to [`decrypt_ciphertext`].
+///
+/// # Arguments
+/// * `shared_secret` - The shared secret key for decryption
+/// * `ciphertext` - The encrypted data to decrypt
+/// * `salt` - Salt used in key derivation
+/// * `nonce` - Nonce used in encryption
+/// * `associated_data` - Data authenticated, but not encrypted, along the ciphertext
+///
+/// # Returns
+/// The decrypted plaintext as a byte vector
+///
+/// # Errors
+/// Returns an error if:
+/// - Key derivation fails
+/// - Decryption fails due to invalid data or parameters
+/// - The associated data does not match the one used for encryption
+pub fn decrypt_ciphertext_with_associated_data(
+    shared_secret: &SharedSecret,
+    ciphertext: &[u8],
+    salt: &[u8],
+    nonce: &[u8],
+    associated_data: &[u8],
+) -> Result<Vec<u8>> {
+    decrypt_ciphertext_with_secret(
+        &SymmetricSecret::from(shared_secret),
+        ciphertext,
+        salt,
+        nonce,
+        associated_data,
+    )
+}
+
+/// Decrypts a ciphertext using a key derived from the provided secret, with the
+/// secret's AEAD algorithm, verifying that it was encrypted together with the given
+/// associated data.
+///
+/// # Arguments
+/// * `secret` - The secret the decryption key is derived from
+/// * `ciphertext` - The encrypted data to decrypt
+/// * `salt` - Salt used in key derivation
+/// * `nonce` - Nonce used in encryption
+/// * `associated_data` - Data authenticated, but not encrypted, along the ciphertext
+///
+/// # Returns
+/// The decrypted plaintext as a byte vector
+///
+/// # Errors
+/// Returns an error if:
+/// - Key derivation fails
+/// - Decryption fails due to invalid data or parameters
+/// - The associated data does not match the one used for encryption
+pub fn decrypt_ciphertext_with_secret(
+    secret: &SymmetricSecret,
+    ciphertext: &[u8],
+    salt: &[u8],
+    nonce: &[u8],
+    associated_data: &[u8],
+) -> Result<Vec<u8>> {
+    derive_cipher(secret, salt)?.decrypt(nonce, ciphertext, associated_data)
 }
 
 /// Encrypts plaintext using the provided shared secret.
@@ -60,20 +273,371 @@ pub fn encrypt_plaintext(
     salt: &[u8],
     nonce: Option<[u8; NONCE_BYTE_SIZE]>,
 ) -> Result<(Vec<u8>, [u8; NONCE_BYTE_SIZE])> {
-    let hkdf = Hkdf::<Sha256>::new(Some(salt), shared_secret.as_bytes());
-    let mut symmetric_key = [0u8; 32];
-    hkdf.expand(b"", &mut symmetric_key)
-        .map_err(Error::KeyExpansionFailed)?;
+    encrypt_plaintext_with_associated_data(plaintext, shared_secret, salt, nonce, &[])
+}
 
-    let cipher = Aes256Gcm::new(&symmetric_key.into());
+/// Encrypts plaintext using the provided shared secret, authenticating the given
+/// associated data along the ciphertext.
+///
+/// Encrypting with empty associated data is equivalent to [`encrypt_plaintext`].
+///
+/// # Arguments
+/// * `plaintext` - The data to encrypt
+/// * `shared_secret` - The shared secret key for encryption
+/// * `salt` - Salt for key derivation
+/// * `nonce` - Optional nonce (generated if None)
+/// * `associated_data` - Data authenticated, but not encrypted, along the ciphertext
+///
+/// # Returns
+/// Tuple of (encrypted data, nonce used)
+///
+/// # Errors
+/// Returns an error if:
+/// - Key derivation fails
+/// - Encryption operation fails
+pub fn encrypt_plaintext_with_associated_data(
+    plaintext: &[u8],
+    shared_secret: &SharedSecret,
+    salt: &[u8],
+    nonce: Option<[u8; NONCE_BYTE_SIZE]>,
+    associated_data: &[u8],
+) -> Result<(Vec<u8>, [u8; NONCE_BYTE_SIZE])> {
+    encrypt_plaintext_with_secret(
+        plaintext,
+        &SymmetricSecret::from(shared_secret),
+        salt,
+        nonce,
+        associated_data,
+    )
+}
+
+/// Encrypts plaintext using a key derived from the provided secret, with the
+/// secret's AEAD algorithm, authenticating the given associated data along the ciphertext.
+///
+/// # Arguments
+/// * `plaintext` - The data to encrypt
+/// * `secret` - The secret the encryption key is derived from
+/// * `salt` - Salt for key derivation
+/// * `nonce` - Optional nonce (generated if None)
+/// * `associated_data` - Data authenticated, but not encrypted, along the ciphertext
+///
+/// # Returns
+/// Tuple of (encrypted data, nonce used)
+///
+/// # Errors
+/// Returns an error if:
+/// - Key derivation fails
+/// - Encryption operation fails
+pub fn encrypt_plaintext_with_secret(
+    plaintext: &[u8],
+    secret: &SymmetricSecret,
+    salt: &[u8],
+    nonce: Option<[u8; NONCE_BYTE_SIZE]>,
+    associated_data: &[u8],
+) -> Result<(Vec<u8>, [u8; NONCE_BYTE_SIZE])> {
+    let cipher = derive_cipher(secret, salt)?;
     let nonce = nonce.unwrap_or_else(rand::random::<[u8; NONCE_BYTE_SIZE]>);
-    let ciphertext = cipher
-        .encrypt(&nonce.into(), plaintext)
-        .map_err(Error::EncryptionFailed)?;
+    let ciphertext = cipher.encrypt(&nonce, plaintext, associated_data)?;
+
+    Ok((ciphertext, nonce))
+}
+
+/// Builds the associated data that authenticates the cleartext envelope of a
+/// confidential compute request (protocol version 2 onwards).
+///
+/// Binding these fields to the ciphertext prevents a man-in-the-middle from splicing
+/// an encrypted payload onto a different stack, model or request mode.
+///
+/// # Arguments
+/// * `version` - The confidential compute protocol version of the request
+/// * `stack_small_id` - The stack small ID the request is charged to
+/// * `model_name` - The model name in the request envelope
+/// * `stream` - Whether the request is a streaming request, if specified
+/// * `num_compute_units` - The number of compute units in the request envelope, if specified
+///
+/// # Returns
+/// The associated data bytes, where strings are length-prefixed and integers are
+/// big-endian encoded, and optional fields are prefixed by a presence byte
+#[must_use]
+pub fn confidential_request_associated_data(
+    version: u32,
+    stack_small_id: u64,
+    model_name: &str,
+    stream: Option<bool>,
+    num_compute_units: Option<u64>,
+) -> Vec<u8> {
+    let model_name = model_name.as_bytes();
+    let mut associated_data = Vec::new();
+    associated_data.extend_from_slice(REQUEST_ENVELOPE_AAD_TAG);
+    associated_data.extend_from_slice(&version.to_be_bytes());
+    associated_data.extend_from_slice(&stack_small_id.to_be_bytes());
+    associated_data.extend_from_slice(&(model_name.len() as u64).to_be_bytes());
+    associated_data.extend_from_slice(model_name);
+    match stream {
+        Some(stream) => associated_data.extend_from_slice(&[1, u8::from(stream)]),
+        None => associated_data.push(0),
+    }
+    match num_compute_units {
+        Some(num_compute_units) => {
+            associated_data.push(1);
+            associated_data.extend_from_slice(&num_compute_units.to_be_bytes());
+        }
+        None => associated_data.push(0),
+    }
+    associated_data
+}
+
+/// Derives the per-chunk nonce for an encrypted stream.
+///
+/// The chunk index is encoded as a big-endian `u64` and XOR-ed into the last
+/// eight bytes of the stream base nonce, so that every chunk of a stream is
+/// encrypted under a distinct nonce without any extra randomness.
+///
+/// # Arguments
+/// * `base_nonce` - The nonce negotiated for the whole stream
+/// * `chunk_index` - The zero-based sequence number of the chunk
+///
+/// # Returns
+/// The nonce to be used for the chunk at `chunk_index`
+#[must_use]
+pub fn derive_chunk_nonce(
+    base_nonce: &[u8; NONCE_BYTE_SIZE],
+    chunk_index: u64,
+) -> [u8; NONCE_BYTE_SIZE] {
+    let mut nonce = *base_nonce;
+    let offset = NONCE_BYTE_SIZE - std::mem::size_of::<u64>();
+    for (byte, index_byte) in nonce[offset..].iter_mut().zip(chunk_index.to_be_bytes()) {
+        *byte ^= index_byte;
+    }
+    nonce
+}
+
+/// Builds the associated data that authenticates an encrypted stream chunk.
+///
+/// The associated data binds the chunk to its request, its position in the stream
+/// and whether it is the last chunk of the stream. Any reordering, dropping, splicing
+/// across requests or truncation of chunks makes the AES-GCM tag verification fail.
+///
+/// # Arguments
+/// * `request_id` - The ID of the request the stream belongs to
+/// * `chunk_index` - The zero-based sequence number of the chunk
+/// * `is_final` - Whether the chunk is the end-of-stream chunk
+///
+/// # Returns
+/// The associated data bytes, laid out as
+/// `tag || len(request_id) as u64 BE || request_id || chunk_index as u64 BE || is_final as u8`
+#[must_use]
+pub fn stream_chunk_associated_data(request_id: &str, chunk_index: u64, is_final: bool) -> Vec<u8> {
+    let request_id = request_id.as_bytes();
+    let mut associated_data = Vec::with_capacity(
+        STREAM_CHUNK_AAD_TAG.len()
+            + 2 * std::mem::size_of::<u64>()
+            + request_id.len()
+            + std::mem::size_of::<u8>(),
+    );
+    associated_data.extend_from_slice(STREAM_CHUNK_AAD_TAG);
+    associated_data.extend_from_slice(&(request_id.len() as u64).to_be_bytes());
+    associated_data.extend_from_slice(request_id);
+    associated_data.extend_from_slice(&chunk_index.to_be_bytes());
+    associated_data.push(u8::from(is_final));
+    associated_data
+}
+
+/// Encrypts a single chunk of a confidential stream.
+///
+/// The chunk nonce is derived from `base_nonce` and `chunk_index` (see [`derive_chunk_nonce`]),
+/// and the request ID, chunk index and end-of-stream marker are authenticated as
+/// associated data (see [`stream_chunk_associated_data`]).
+///
+/// # Arguments
+/// * `plaintext` - The chunk data to encrypt
+/// * `secret` - The secret the encryption key is derived from
+/// * `salt` - Salt for key derivation
+/// * `base_nonce` - The nonce negotiated for the whole stream
+/// * `request_id` - The ID of the request the stream belongs to
+/// * `chunk_index` - The zero-based sequence number of the chunk
+/// * `is_final` - Whether the chunk is the end-of-stream chunk
+///
+/// # Returns
+/// Tuple of (encrypted data, nonce used for this chunk)
+///
+/// # Errors
+/// Returns an error if:
+/// - Key derivation fails
+/// - Encryption operation fails
+pub fn encrypt_stream_chunk(
+    plaintext: &[u8],
+    secret: &SymmetricSecret,
+    salt: &[u8],
+    base_nonce: &[u8; NONCE_BYTE_SIZE],
+    request_id: &str,
+    chunk_index: u64,
+    is_final: bool,
+) -> Result<(Vec<u8>, [u8; NONCE_BYTE_SIZE])> {
+    let cipher = derive_cipher(secret, salt)?;
+    let nonce = derive_chunk_nonce(base_nonce, chunk_index);
+    let associated_data = stream_chunk_associated_data(request_id, chunk_index, is_final);
+    let ciphertext = cipher.encrypt(&nonce, plaintext, &associated_data)?;
 
     Ok((ciphertext, nonce))
 }
 
+/// Decrypts and authenticates a single chunk of a confidential stream.
+///
+/// This is the counterpart of [`encrypt_stream_chunk`]. Decryption fails if the chunk
+/// was produced for a different request, a different position in the stream, or with a
+/// different end-of-stream marker. Prefer [`StreamDecryptor`] when consuming a whole
+/// stream, as it also tracks chunk ordering and detects truncation.
+///
+/// # Arguments
+/// * `ciphertext` - The encrypted chunk
+/// * `secret` - The secret the decryption key is derived from
+/// * `salt` - Salt used in key derivation
+/// * `base_nonce` - The nonce negotiated for the whole stream
+/// * `request_id` - The ID of the request the stream belongs to
+/// * `chunk_index` - The expected zero-based sequence number of the chunk
+/// * `is_final` - Whether the chunk is expected to be the end-of-stream chunk
+///
+/// # Returns
+/// The decrypted chunk as a byte vector
+///
+/// # Errors
+/// Returns an error if:
+/// - Key derivation fails
+/// - Decryption or authentication fails
+pub fn decrypt_stream_chunk(
+    ciphertext: &[u8],
+    secret: &SymmetricSecret,
+    salt: &[u8],
+    base_nonce: &[u8; NONCE_BYTE_SIZE],
+    request_id: &str,
+    chunk_index: u64,
+    is_final: bool,
+) -> Result<Vec<u8>> {
+    let cipher = derive_cipher(secret, salt)?;
+    let nonce = derive_chunk_nonce(base_nonce, chunk_index);
+    let associated_data = stream_chunk_associated_data(request_id, chunk_index, is_final);
+    cipher.decrypt(&nonce, ciphertext, &associated_data)
+}
+
+/// Stateful decrypt-and-verify helper for confidential streams.
+///
+/// `StreamDecryptor` decrypts the chunks of a stream in the order they are received,
+/// keeping track of the expected chunk index. Because each chunk is authenticated
+/// against its index and end-of-stream marker:
+/// - Reordered or dropped chunks fail to decrypt
+/// - Chunks received after the final chunk are rejected
+/// - A stream that ends before its final chunk is detected by [`StreamDecryptor::finish`]
+pub struct StreamDecryptor<'a> {
+    /// The secret the decryption key is derived from
+    secret: &'a SymmetricSecret,
+    /// Salt used in key derivation
+    salt: &'a [u8],
+    /// The nonce negotiated for the whole stream
+    base_nonce: [u8; NONCE_BYTE_SIZE],
+    /// The ID of the request the stream belongs to
+    request_id: &'a str,
+    /// The index of the next chunk expected in the stream
+    next_chunk_index: u64,
+    /// Whether the final chunk of the stream has been decrypted
+    is_finished: bool,
+}
+
+impl<'a> StreamDecryptor<'a> {
+    /// Constructor
+    ///
+    /// # Arguments
+    /// * `secret` - The secret the decryption key is derived from
+    /// * `salt` - Salt used in key derivation
+    /// * `base_nonce` - The nonce negotiated for the whole stream
+    /// * `request_id` - The ID of the request the stream belongs to
+    #[must_use]
+    pub const fn new(
+        secret: &'a SymmetricSecret,
+        salt: &'a [u8],
+        base_nonce: [u8; NONCE_BYTE_SIZE],
+        request_id: &'a str,
+    ) -> Self {
+        Self {
+            secret,
+            salt,
+            base_nonce,
+            request_id,
+            next_chunk_index: 0,
+            is_finished: false,
+        }
+    }
+
+    /// Checks that a chunk was sent in a stream chunk format this decryptor supports.
+    ///
+    /// # Arguments
+    /// * `version` - The version sent along the chunk, if any
+    ///
+    /// # Errors
+    /// Returns `Error::UnsupportedStreamChunkVersion` if the version is missing (the chunk
+    /// predates [`STREAM_CHUNK_VERSION`]) or differs from [`STREAM_CHUNK_VERSION`]
+    pub const fn check_version(version: Option<u32>) -> Result<()> {
+        match version {
+            Some(STREAM_CHUNK_VERSION) => Ok(()),
+            version => Err(Error::UnsupportedStreamChunkVersion { version }),
+        }
+    }
+
+    /// Decrypts the next chunk of the stream.
+    ///
+    /// # Arguments
+    /// * `ciphertext` - The encrypted chunk
+    /// * `is_final` - Whether the sender flagged the chunk as the end-of-stream chunk
+    ///
+    /// # Returns
+    /// The decrypted chunk as a byte vector
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The final chunk of the stream was already received
+    /// - The chunk fails to decrypt or authenticate at the expected position
+    pub fn decrypt_next(&mut self, ciphertext: &[u8], is_final: bool) -> Result<Vec<u8>> {
+        if self.is_finished {
+            return Err(Error::ChunkAfterEndOfStream);
+        }
+        let plaintext = decrypt_stream_chunk(
+            ciphertext,
+            self.secret,
+            self.salt,
+            &self.base_nonce,
+            self.request_id,
+            self.next_chunk_index,
+            is_final,
+        )?;
+        self.next_chunk_index += 1;
+        self.is_finished = is_final;
+        Ok(plaintext)
+    }
+
+    /// Verifies that the stream was terminated by its final chunk.
+    ///
+    /// # Errors
+    /// Returns `Error::StreamTruncated` if the final chunk was never received
+    pub const fn finish(self) -> Result<()> {
+        if self.is_finished {
+            Ok(())
+        } else {
+            Err(Error::StreamTruncated {
+                num_chunks: self.next_chunk_index,
+            })
+        }
+    }
+}
+
+/// Derives the cipher from the secret and salt, using HKDF-SHA256.
+fn derive_cipher(secret: &SymmetricSecret, salt: &[u8]) -> Result<Cipher> {
+    let hkdf = Hkdf::<Sha256>::new(Some(salt), &secret.bytes);
+    let mut symmetric_key = [0u8; SECRET_BYTE_SIZE];
+    hkdf.expand(b"", &mut symmetric_key)
+        .map_err(Error::KeyExpansionFailed)?;
+    Ok(Cipher::new(&symmetric_key, secret.aead))
+}
+
 /// Errors that can occur during encryption/decryption operations
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -83,4 +647,260 @@ pub enum Error {
     EncryptionFailed(AesError),
     #[error("Failed to expand key, with error: `{0}`")]
     KeyExpansionFailed(hkdf::InvalidLength),
+    #[error("Received a chunk after the end of the stream")]
+    ChunkAfterEndOfStream,
+    #[error(
+        "Unsupported stream chunk version: {version:?}, expected version {STREAM_CHUNK_VERSION}"
+    )]
+    UnsupportedStreamChunkVersion { version: Option<u32> },
+    #[error("Stream ended after {num_chunks} chunk(s) without an end-of-stream chunk")]
+    StreamTruncated { num_chunks: u64 },
+    #[error("Key exchange produced a non-contributory shared secret")]
+    NonContributoryKeyExchange,
+    #[error("HPKE context message limit reached")]
+    MessageLimitReached,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    const REQUEST_ID: &str = "test-request-id";
+
+    const MODEL_NAME: &str = "meta-llama/Llama-3.3-70B-Instruct";
+
+    fn shared_secret() -> SharedSecret {
+        let client_secret = StaticSecret::random_from_rng(rand::thread_rng());
+        let node_secret = StaticSecret::random_from_rng(rand::thread_rng());
+        client_secret.diffie_hellman(&PublicKey::from(&node_secret))
+    }
+
+    fn encrypt_stream(
+        secret: &SymmetricSecret,
+        salt: &[u8],
+        base_nonce: &[u8; NONCE_BYTE_SIZE],
+        chunks: &[&str],
+    ) -> Vec<(Vec<u8>, bool)> {
+        chunks
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let is_final = index == chunks.len() - 1;
+                let (ciphertext, _) = encrypt_stream_chunk(
+                    chunk.as_bytes(),
+                    secret,
+                    salt,
+                    base_nonce,
+                    REQUEST_ID,
+                    index as u64,
+                    is_final,
+                )
+                .expect("Encryption should succeed");
+                (ciphertext, is_final)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_empty_associated_data_is_backwards_compatible() {
+        let shared_secret = shared_secret();
+        let salt = rand::random::<[u8; 16]>();
+        let (ciphertext, nonce) = encrypt_plaintext(b"plaintext", &shared_secret, &salt, None)
+            .expect("Encryption should succeed");
+        let plaintext = decrypt_ciphertext_with_associated_data(
+            &shared_secret,
+            &ciphertext,
+            &salt,
+            &nonce,
+            &[],
+        )
+        .expect("Decryption should succeed");
+        assert_eq!(plaintext, b"plaintext");
+    }
+
+    #[test]
+    fn test_request_envelope_associated_data_binding() {
+        let shared_secret = shared_secret();
+        let salt = rand::random::<[u8; 16]>();
+        let associated_data = confidential_request_associated_data(
+            CONFIDENTIAL_PROTOCOL_VERSION_V2,
+            1,
+            MODEL_NAME,
+            Some(false),
+            None,
+        );
+        let (ciphertext, nonce) = encrypt_plaintext_with_associated_data(
+            b"plaintext",
+            &shared_secret,
+            &salt,
+            None,
+            &associated_data,
+        )
+        .expect("Encryption should succeed");
+
+        let plaintext = decrypt_ciphertext_with_associated_data(
+            &shared_secret,
+            &ciphertext,
+            &salt,
+            &nonce,
+            &associated_data,
+        )
+        .expect("Decryption should succeed");
+        assert_eq!(plaintext, b"plaintext");
+
+        let tampered_envelopes = [
+            confidential_request_associated_data(
+                CONFIDENTIAL_PROTOCOL_VERSION_V2,
+                2,
+                MODEL_NAME,
+                Some(false),
+                None,
+            ),
+            confidential_request_associated_data(
+                CONFIDENTIAL_PROTOCOL_VERSION_V2,
+                1,
+                "other-model",
+                Some(false),
+                None,
+            ),
+            confidential_request_associated_data(
+                CONFIDENTIAL_PROTOCOL_VERSION_V2,
+                1,
+                MODEL_NAME,
+                Some(true),
+                None,
+            ),
+            confidential_request_associated_data(
+                CONFIDENTIAL_PROTOCOL_VERSION_V2,
+                1,
+                MODEL_NAME,
+                Some(false),
+                Some(100),
+            ),
+        ];
+        for tampered_associated_data in tampered_envelopes {
+            assert!(decrypt_ciphertext_with_associated_data(
+                &shared_secret,
+                &ciphertext,
+                &salt,
+                &nonce,
+                &tampered_associated_data,
+            )
+            .is_err());
+        }
+        assert!(decrypt_ciphertext(&shared_secret, &ciphertext, &salt, &nonce).is_err());
+    }
+
+    #[test]
+    fn test_derive_chunk_nonce_is_unique_per_chunk() {
+        let base_nonce = rand::random::<[u8; NONCE_BYTE_SIZE]>();
+        assert_eq!(derive_chunk_nonce(&base_nonce, 0), base_nonce);
+        let nonces = (0..1000)
+            .map(|index| derive_chunk_nonce(&base_nonce, index))
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(nonces.len(), 1000, "Chunk nonces should never repeat");
+    }
+
+    #[test]
+    fn test_stream_roundtrip() {
+        let secret = SymmetricSecret::from(&shared_secret());
+        let salt = rand::random::<[u8; 16]>();
+        let base_nonce = rand::random::<[u8; NONCE_BYTE_SIZE]>();
+        let chunks = ["first", "second", "third"];
+        let encrypted = encrypt_stream(&secret, &salt, &base_nonce, &chunks);
+
+        let mut decryptor = StreamDecryptor::new(&secret, &salt, base_nonce, REQUEST_ID);
+        for ((ciphertext, is_final), chunk) in encrypted.iter().zip(chunks) {
+            let plaintext = decryptor
+                .decrypt_next(ciphertext, *is_final)
+                .expect("Decryption should succeed");
+            assert_eq!(plaintext, chunk.as_bytes());
+        }
+        decryptor.finish().expect("Stream should be complete");
+    }
+
+    #[test]
+    fn test_stream_roundtrip_with_chacha20_poly1305() {
+        let bytes = rand::random::<[u8; SECRET_BYTE_SIZE]>();
+        let secret = SymmetricSecret::new(bytes, AeadAlgorithm::ChaCha20Poly1305);
+        let salt = rand::random::<[u8; 16]>();
+        let base_nonce = rand::random::<[u8; NONCE_BYTE_SIZE]>();
+        let encrypted = encrypt_stream(&secret, &salt, &base_nonce, &["a", "b"]);
+
+        let mut decryptor = StreamDecryptor::new(&secret, &salt, base_nonce, REQUEST_ID);
+        assert_eq!(
+            decryptor.decrypt_next(&encrypted[0].0, false).unwrap(),
+            b"a"
+        );
+        assert_eq!(decryptor.decrypt_next(&encrypted[1].0, true).unwrap(), b"b");
+        decryptor.finish().expect("Stream should be complete");
+
+        // The same secret used with a different AEAD algorithm does not authenticate
+        let aes_secret = SymmetricSecret::new(bytes, AeadAlgorithm::Aes256Gcm);
+        let mut decryptor = StreamDecryptor::new(&aes_secret, &salt, base_nonce, REQUEST_ID);
+        assert!(decryptor.decrypt_next(&encrypted[0].0, false).is_err());
+    }
+
+    #[test]
+    fn test_stream_rejects_reordered_chunks() {
+        let secret = SymmetricSecret::from(&shared_secret());
+        let salt = rand::random::<[u8; 16]>();
+        let base_nonce = rand::random::<[u8; NONCE_BYTE_SIZE]>();
+        let encrypted = encrypt_stream(&secret, &salt, &base_nonce, &["a", "b", "c"]);
+
+        let mut decryptor = StreamDecryptor::new(&secret, &salt, base_nonce, REQUEST_ID);
+        assert!(decryptor.decrypt_next(&encrypted[1].0, false).is_err());
+    }
+
+    #[test]
+    fn test_stream_detects_truncation() {
+        let secret = SymmetricSecret::from(&shared_secret());
+        let salt = rand::random::<[u8; 16]>();
+        let base_nonce = rand::random::<[u8; NONCE_BYTE_SIZE]>();
+        let encrypted = encrypt_stream(&secret, &salt, &base_nonce, &["a", "b", "c"]);
+
+        let mut decryptor = StreamDecryptor::new(&secret, &salt, base_nonce, REQUEST_ID);
+        decryptor.decrypt_next(&encrypted[0].0, false).unwrap();
+        // Pretending that an intermediate chunk is the final one fails authentication
+        assert!(decryptor.decrypt_next(&encrypted[1].0, true).is_err());
+        decryptor.decrypt_next(&encrypted[1].0, false).unwrap();
+        assert!(matches!(
+            decryptor.finish(),
+            Err(Error::StreamTruncated { num_chunks: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_stream_rejects_other_request_and_trailing_chunks() {
+        let secret = SymmetricSecret::from(&shared_secret());
+        let salt = rand::random::<[u8; 16]>();
+        let base_nonce = rand::random::<[u8; NONCE_BYTE_SIZE]>();
+        let encrypted = encrypt_stream(&secret, &salt, &base_nonce, &["a", "b"]);
+
+        let mut decryptor = StreamDecryptor::new(&secret, &salt, base_nonce, "other-request-id");
+        assert!(decryptor.decrypt_next(&encrypted[0].0, false).is_err());
+
+        let mut decryptor = StreamDecryptor::new(&secret, &salt, base_nonce, REQUEST_ID);
+        decryptor.decrypt_next(&encrypted[0].0, false).unwrap();
+        decryptor.decrypt_next(&encrypted[1].0, true).unwrap();
+        assert!(matches!(
+            decryptor.decrypt_next(&encrypted[1].0, true),
+            Err(Error::ChunkAfterEndOfStream)
+        ));
+    }
+
+    #[test]
+    fn test_stream_check_version() {
+        StreamDecryptor::check_version(Some(STREAM_CHUNK_VERSION))
+            .expect("Current stream chunk version should be supported");
+        assert!(matches!(
+            StreamDecryptor::check_version(None),
+            Err(Error::UnsupportedStreamChunkVersion { version: None })
+        ));
+        assert!(matches!(
+            StreamDecryptor::check_version(Some(STREAM_CHUNK_VERSION + 1)),
+            Err(Error::UnsupportedStreamChunkVersion { version: Some(_) })
+        ));
+    }
 }
@@ -0,0 +1,591 @@
+//! HPKE (RFC 9180) base mode, with `DHKEM(X25519, HKDF-SHA256)` and `HKDF-SHA256`,
+//! and either AES-256-GCM or ChaCha20-Poly1305 as AEAD.
+//!
+//! Confidential compute requests using [`CONFIDENTIAL_PROTOCOL_VERSION_HPKE`] are single-shot
+//! HPKE envelopes sealed to the node's X25519 public key, so that clients can rely on any
+//! standard HPKE implementation instead of the X25519 + HKDF + AES-256-GCM construction of
+//! the previous protocol versions:
+//!
+//! - The `info` parameter is [`REQUEST_INFO`]
+//! - The associated data is the request envelope associated data, see
+//!   [`crate::encryption::confidential_request_associated_data`]
+//! - Responses are encrypted with a secret exported from the request context, with
+//!   [`RESPONSE_EXPORTER_CONTEXT`] as exporter context (see [`export_response_secret`])
+//!
+//! [`CONFIDENTIAL_PROTOCOL_VERSION_HPKE`]: crate::encryption::CONFIDENTIAL_PROTOCOL_VERSION_HPKE
+
+use crate::encryption::{
+    derive_chunk_nonce, AeadAlgorithm, Cipher, Error, SymmetricSecret, NONCE_BYTE_SIZE,
+    SECRET_BYTE_SIZE,
+};
+use hkdf::{Hkdf, InvalidLength};
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+/// Size of the HPKE encapsulated key (the sender ephemeral X25519 public key), in bytes
+pub const ENCAPSULATED_KEY_SIZE: usize = 32;
+
+/// HPKE KEM identifier of `DHKEM(X25519, HKDF-SHA256)`
+pub const KEM_ID_X25519_HKDF_SHA256: u16 = 0x0020;
+
+/// HPKE KDF identifier of `HKDF-SHA256`
+pub const KDF_ID_HKDF_SHA256: u16 = 0x0001;
+
+/// HPKE `info` parameter of confidential compute requests
+pub const REQUEST_INFO: &[u8] = b"atoma-confidential-request";
+
+/// HPKE exporter context of the secret confidential compute responses are encrypted with
+pub const RESPONSE_EXPORTER_CONTEXT: &[u8] = b"atoma-confidential-response";
+
+/// HPKE base mode identifier
+const MODE_BASE: u8 = 0x00;
+
+/// Prefix of every labeled HKDF input
+const VERSION_LABEL: &[u8] = b"HPKE-v1";
+
+/// Suite identifier of the KEM, `"KEM" || I2OSP(kem_id, 2)`
+const KEM_SUITE_ID: [u8; 5] = [
+    b'K',
+    b'E',
+    b'M',
+    KEM_ID_X25519_HKDF_SHA256.to_be_bytes()[0],
+    KEM_ID_X25519_HKDF_SHA256.to_be_bytes()[1],
+];
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// An HPKE encryption context, as established by [`setup_base_sender`] or [`setup_base_receiver`].
+///
+/// A context seals (or opens) a sequence of messages, each under a distinct nonce derived
+/// from the context base nonce and the message sequence number.
+pub struct HpkeContext {
+    /// The AEAD algorithm of the context
+    aead: AeadAlgorithm,
+    /// The AEAD cipher, instantiated with the context key
+    cipher: Cipher,
+    /// The base nonce, from which the nonce of every message is derived
+    base_nonce: [u8; NONCE_BYTE_SIZE],
+    /// The exporter secret
+    exporter_secret: [u8; SECRET_BYTE_SIZE],
+    /// The sequence number of the next message
+    sequence_number: u64,
+}
+
+impl HpkeContext {
+    /// Encrypts and authenticates the next message of the context.
+    ///
+    /// # Arguments
+    /// * `associated_data` - Data authenticated, but not encrypted, along the ciphertext
+    /// * `plaintext` - The data to encrypt
+    ///
+    /// # Returns
+    /// The encrypted data
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The context message limit was reached
+    /// - Encryption operation fails
+    pub fn seal(&mut self, associated_data: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = self.next_nonce()?;
+        let ciphertext = self.cipher.encrypt(&nonce, plaintext, associated_data)?;
+        self.sequence_number += 1;
+        Ok(ciphertext)
+    }
+
+    /// Decrypts and authenticates the next message of the context.
+    ///
+    /// # Arguments
+    /// * `associated_data` - Data authenticated, but not encrypted, along the ciphertext
+    /// * `ciphertext` - The encrypted data
+    ///
+    /// # Returns
+    /// The decrypted plaintext as a byte vector
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The context message limit was reached
+    /// - Decryption or authentication fails
+    pub fn open(&mut self, associated_data: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = self.next_nonce()?;
+        let plaintext = self.cipher.decrypt(&nonce, ciphertext, associated_data)?;
+        self.sequence_number += 1;
+        Ok(plaintext)
+    }
+
+    /// Exports a secret from the context (RFC 9180, section 5.3).
+    ///
+    /// # Arguments
+    /// * `exporter_context` - The context the secret is exported for
+    /// * `secret` - The buffer the exported secret is written to
+    ///
+    /// # Errors
+    /// Returns an error if the requested secret length is too large
+    pub fn export(&self, exporter_context: &[u8], secret: &mut [u8]) -> Result<()> {
+        labeled_expand(
+            &hpke_suite_id(self.aead),
+            &self.exporter_secret,
+            b"sec",
+            exporter_context,
+            secret,
+        )
+    }
+
+    /// Returns the AEAD algorithm of the context
+    #[must_use]
+    pub const fn aead(&self) -> AeadAlgorithm {
+        self.aead
+    }
+
+    /// Computes the nonce of the next message, `base_nonce XOR I2OSP(seq, Nn)`
+    fn next_nonce(&self) -> Result<[u8; NONCE_BYTE_SIZE]> {
+        if self.sequence_number == u64::MAX {
+            return Err(Error::MessageLimitReached);
+        }
+        Ok(derive_chunk_nonce(&self.base_nonce, self.sequence_number))
+    }
+}
+
+/// Sets up an HPKE base mode sender context to the given recipient public key.
+///
+/// # Arguments
+/// * `recipient_public_key` - The recipient X25519 public key
+/// * `info` - Application-supplied information bound to the context
+/// * `aead` - The AEAD algorithm of the context
+///
+/// # Returns
+/// Tuple of (encapsulated key to be sent to the recipient, sender context)
+///
+/// # Errors
+/// Returns an error if the recipient public key is a low order point
+pub fn setup_base_sender(
+    recipient_public_key: &PublicKey,
+    info: &[u8],
+    aead: AeadAlgorithm,
+) -> Result<([u8; ENCAPSULATED_KEY_SIZE], HpkeContext)> {
+    let ephemeral_secret = EphemeralSecret::random_from_rng(rand::thread_rng());
+    let encapsulated_key = PublicKey::from(&ephemeral_secret).to_bytes();
+    let dh = ephemeral_secret.diffie_hellman(recipient_public_key);
+    if !dh.was_contributory() {
+        return Err(Error::NonContributoryKeyExchange);
+    }
+    let shared_secret = extract_and_expand(
+        dh.as_bytes(),
+        &encapsulated_key,
+        recipient_public_key.as_bytes(),
+    )?;
+    let context = key_schedule(&shared_secret, info, aead)?;
+    Ok((encapsulated_key, context))
+}
+
+/// Sets up an HPKE base mode recipient context from the sender encapsulated key.
+///
+/// # Arguments
+/// * `encapsulated_key` - The encapsulated key sent by the sender
+/// * `recipient_secret_key` - The recipient X25519 secret key
+/// * `info` - Application-supplied information bound to the context
+/// * `aead` - The AEAD algorithm of the context
+///
+/// # Returns
+/// The recipient context
+///
+/// # Errors
+/// Returns an error if the encapsulated key is a low order point
+pub fn setup_base_receiver(
+    encapsulated_key: &[u8; ENCAPSULATED_KEY_SIZE],
+    recipient_secret_key: &StaticSecret,
+    info: &[u8],
+    aead: AeadAlgorithm,
+) -> Result<HpkeContext> {
+    let dh = recipient_secret_key.diffie_hellman(&PublicKey::from(*encapsulated_key));
+    if !dh.was_contributory() {
+        return Err(Error::NonContributoryKeyExchange);
+    }
+    let recipient_public_key = PublicKey::from(recipient_secret_key);
+    let shared_secret = extract_and_expand(
+        dh.as_bytes(),
+        encapsulated_key,
+        recipient_public_key.as_bytes(),
+    )?;
+    key_schedule(&shared_secret, info, aead)
+}
+
+/// Single-shot HPKE base mode encryption to the given recipient public key.
+///
+/// # Arguments
+/// * `recipient_public_key` - The recipient X25519 public key
+/// * `info` - Application-supplied information bound to the context
+/// * `associated_data` - Data authenticated, but not encrypted, along the ciphertext
+/// * `plaintext` - The data to encrypt
+/// * `aead` - The AEAD algorithm
+///
+/// # Returns
+/// Tuple of (encapsulated key, encrypted data)
+///
+/// # Errors
+/// Returns an error if:
+/// - The recipient public key is a low order point
+/// - Encryption operation fails
+pub fn seal_base(
+    recipient_public_key: &PublicKey,
+    info: &[u8],
+    associated_data: &[u8],
+    plaintext: &[u8],
+    aead: AeadAlgorithm,
+) -> Result<([u8; ENCAPSULATED_KEY_SIZE], Vec<u8>)> {
+    let (encapsulated_key, mut context) = setup_base_sender(recipient_public_key, info, aead)?;
+    let ciphertext = context.seal(associated_data, plaintext)?;
+    Ok((encapsulated_key, ciphertext))
+}
+
+/// Single-shot HPKE base mode decryption.
+///
+/// # Arguments
+/// * `encapsulated_key` - The encapsulated key sent by the sender
+/// * `recipient_secret_key` - The recipient X25519 secret key
+/// * `info` - Application-supplied information bound to the context
+/// * `associated_data` - Data authenticated, but not encrypted, along the ciphertext
+/// * `ciphertext` - The encrypted data
+/// * `aead` - The AEAD algorithm
+///
+/// # Returns
+/// The decrypted plaintext as a byte vector
+///
+/// # Errors
+/// Returns an error if:
+/// - The encapsulated key is a low order point
+/// - Decryption or authentication fails
+pub fn open_base(
+    encapsulated_key: &[u8; ENCAPSULATED_KEY_SIZE],
+    recipient_secret_key: &StaticSecret,
+    info: &[u8],
+    associated_data: &[u8],
+    ciphertext: &[u8],
+    aead: AeadAlgorithm,
+) -> Result<Vec<u8>> {
+    setup_base_receiver(encapsulated_key, recipient_secret_key, info, aead)?
+        .open(associated_data, ciphertext)
+}
+
+/// Exports the secret confidential compute responses are encrypted with from the request
+/// HPKE context.
+///
+/// The returned secret is used exactly as the X25519 shared secret of the previous protocol
+/// versions (the response key is derived from it, and the response salt, with HKDF-SHA256),
+/// but with the AEAD algorithm of the request.
+///
+/// # Errors
+/// Returns an error if the secret export fails
+pub fn export_response_secret(context: &HpkeContext) -> Result<SymmetricSecret> {
+    let mut secret = [0u8; SECRET_BYTE_SIZE];
+    context.export(RESPONSE_EXPORTER_CONTEXT, &mut secret)?;
+    Ok(SymmetricSecret::new(secret, context.aead()))
+}
+
+/// Suite identifier of the HPKE key schedule,
+/// `"HPKE" || I2OSP(kem_id, 2) || I2OSP(kdf_id, 2) || I2OSP(aead_id, 2)`
+fn hpke_suite_id(aead: AeadAlgorithm) -> [u8; 10] {
+    let mut suite_id = [0u8; 10];
+    suite_id[..4].copy_from_slice(b"HPKE");
+    suite_id[4..6].copy_from_slice(&KEM_ID_X25519_HKDF_SHA256.to_be_bytes());
+    suite_id[6..8].copy_from_slice(&KDF_ID_HKDF_SHA256.to_be_bytes());
+    suite_id[8..].copy_from_slice(&aead.hpke_id().to_be_bytes());
+    suite_id
+}
+
+/// `ExtractAndExpand` of `DHKEM(X25519, HKDF-SHA256)`, with
+/// `kem_context = enc || pkRm`
+fn extract_and_expand(
+    dh: &[u8; 32],
+    encapsulated_key: &[u8; ENCAPSULATED_KEY_SIZE],
+    recipient_public_key: &[u8; 32],
+) -> Result<[u8; SECRET_BYTE_SIZE]> {
+    let kem_context = [encapsulated_key.as_slice(), recipient_public_key].concat();
+    let eae_prk = labeled_extract(&KEM_SUITE_ID, b"", b"eae_prk", dh);
+    let mut shared_secret = [0u8; SECRET_BYTE_SIZE];
+    labeled_expand(
+        &KEM_SUITE_ID,
+        &eae_prk,
+        b"shared_secret",
+        &kem_context,
+        &mut shared_secret,
+    )?;
+    Ok(shared_secret)
+}
+
+/// HPKE base mode key schedule (RFC 9180, section 5.1), with empty PSK and PSK ID
+fn key_schedule(
+    shared_secret: &[u8; SECRET_BYTE_SIZE],
+    info: &[u8],
+    aead: AeadAlgorithm,
+) -> Result<HpkeContext> {
+    let suite_id = hpke_suite_id(aead);
+    let psk_id_hash = labeled_extract(&suite_id, b"", b"psk_id_hash", b"");
+    let info_hash = labeled_extract(&suite_id, b"", b"info_hash", info);
+    let key_schedule_context = [[MODE_BASE].as_slice(), &psk_id_hash, &info_hash].concat();
+    let secret = labeled_extract(&suite_id, shared_secret, b"secret", b"");
+
+    let mut key = [0u8; SECRET_BYTE_SIZE];
+    labeled_expand(&suite_id, &secret, b"key", &key_schedule_context, &mut key)?;
+    let mut base_nonce = [0u8; NONCE_BYTE_SIZE];
+    labeled_expand(
+        &suite_id,
+        &secret,
+        b"base_nonce",
+        &key_schedule_context,
+        &mut base_nonce,
+    )?;
+    let mut exporter_secret = [0u8; SECRET_BYTE_SIZE];
+    labeled_expand(
+        &suite_id,
+        &secret,
+        b"exp",
+        &key_schedule_context,
+        &mut exporter_secret,
+    )?;
+
+    Ok(HpkeContext {
+        aead,
+        cipher: Cipher::new(&key, aead),
+        base_nonce,
+        exporter_secret,
+        sequence_number: 0,
+    })
+}
+
+/// `LabeledExtract(salt, label, ikm)`
+fn labeled_extract(suite_id: &[u8], salt: &[u8], label: &[u8], ikm: &[u8]) -> [u8; 32] {
+    let labeled_ikm = [VERSION_LABEL, suite_id, label, ikm].concat();
+    let (prk, _) = Hkdf::<Sha256>::extract(Some(salt), &labeled_ikm);
+    prk.into()
+}
+
+/// `LabeledExpand(prk, label, info, L)`, where `L` is the length of `output`
+fn labeled_expand(
+    suite_id: &[u8],
+    prk: &[u8; 32],
+    label: &[u8],
+    info: &[u8],
+    output: &mut [u8],
+) -> Result<()> {
+    let length = u16::try_from(output.len())
+        .map_err(|_| Error::KeyExpansionFailed(InvalidLength))?
+        .to_be_bytes();
+    let labeled_info = [length.as_slice(), VERSION_LABEL, suite_id, label, info].concat();
+    Hkdf::<Sha256>::from_prk(prk)
+        .map_err(|_| Error::KeyExpansionFailed(InvalidLength))?
+        .expand(&labeled_info, output)
+        .map_err(Error::KeyExpansionFailed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AEADS: [AeadAlgorithm; 2] = [AeadAlgorithm::Aes256Gcm, AeadAlgorithm::ChaCha20Poly1305];
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_seal_open_roundtrip() {
+        let recipient_secret_key = StaticSecret::random_from_rng(rand::thread_rng());
+        let recipient_public_key = PublicKey::from(&recipient_secret_key);
+        for aead in AEADS {
+            let (encapsulated_key, ciphertext) = seal_base(
+                &recipient_public_key,
+                REQUEST_INFO,
+                b"aad",
+                b"plaintext",
+                aead,
+            )
+            .expect("Sealing should succeed");
+            let plaintext = open_base(
+                &encapsulated_key,
+                &recipient_secret_key,
+                REQUEST_INFO,
+                b"aad",
+                &ciphertext,
+                aead,
+            )
+            .expect("Opening should succeed");
+            assert_eq!(plaintext, b"plaintext");
+
+            for (info, associated_data) in [
+                (b"other-info".as_slice(), b"aad".as_slice()),
+                (REQUEST_INFO, b"other-aad"),
+            ] {
+                assert!(open_base(
+                    &encapsulated_key,
+                    &recipient_secret_key,
+                    info,
+                    associated_data,
+                    &ciphertext,
+                    aead,
+                )
+                .is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn test_sender_and_receiver_export_the_same_response_secret() {
+        let recipient_secret_key = StaticSecret::random_from_rng(rand::thread_rng());
+        let recipient_public_key = PublicKey::from(&recipient_secret_key);
+        let (encapsulated_key, mut sender_context) = setup_base_sender(
+            &recipient_public_key,
+            REQUEST_INFO,
+            AeadAlgorithm::ChaCha20Poly1305,
+        )
+        .unwrap();
+        let mut receiver_context = setup_base_receiver(
+            &encapsulated_key,
+            &recipient_secret_key,
+            REQUEST_INFO,
+            AeadAlgorithm::ChaCha20Poly1305,
+        )
+        .unwrap();
+
+        for message in [b"first".as_slice(), b"second"] {
+            let ciphertext = sender_context.seal(b"", message).unwrap();
+            assert_eq!(receiver_context.open(b"", &ciphertext).unwrap(), message);
+        }
+
+        let salt = rand::random::<[u8; 16]>();
+        let sender_secret = export_response_secret(&sender_context).unwrap();
+        let receiver_secret = export_response_secret(&receiver_context).unwrap();
+        assert_eq!(receiver_secret.aead(), AeadAlgorithm::ChaCha20Poly1305);
+        let (ciphertext, nonce) = crate::encryption::encrypt_plaintext_with_secret(
+            b"response",
+            &receiver_secret,
+            &salt,
+            None,
+            &[],
+        )
+        .unwrap();
+        let plaintext = crate::encryption::decrypt_ciphertext_with_secret(
+            &sender_secret,
+            &ciphertext,
+            &salt,
+            &nonce,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(plaintext, b"response");
+    }
+
+    /// Envelopes sealed with the HPKE implementation of the Python `cryptography` package,
+    /// to the recipient secret key `0x01..=0x20`, with `REQUEST_INFO` and empty associated data
+    #[test]
+    fn test_opens_envelopes_from_other_implementations() {
+        const PLAINTEXT: &[u8] = br#"{"model":"test"}"#;
+        let envelopes = [
+            (
+                AeadAlgorithm::Aes256Gcm,
+                "e6f4bd63c6addef85d69cc6751de9b8e7e8dd22e060d8b19e7e5b0601cc5fe38\
+                 0f885eec9753baee2b5a19cc16b34754f8001a232d8e6bce421474128a51f150",
+            ),
+            (
+                AeadAlgorithm::ChaCha20Poly1305,
+                "21c34e4e2333e530264fbb425e342cf78c36910ad7d9fc7660b0a46fc3d50518\
+                 60530509fe094a2b1dd22d9eb47d0a7b46d828ac3d904b051ba9f633526d264e",
+            ),
+        ];
+        let recipient_secret_key = StaticSecret::from(std::array::from_fn(|i| i as u8 + 1));
+        for (aead, envelope) in envelopes {
+            let envelope = from_hex(envelope);
+            let (encapsulated_key, ciphertext) = envelope.split_at(ENCAPSULATED_KEY_SIZE);
+            let plaintext = open_base(
+                encapsulated_key.try_into().unwrap(),
+                &recipient_secret_key,
+                REQUEST_INFO,
+                &[],
+                ciphertext,
+                aead,
+            )
+            .expect("Opening should succeed");
+            assert_eq!(plaintext, PLAINTEXT);
+        }
+    }
+
+    /// Test vector of RFC 9180, appendix A.2.1: `DHKEM(X25519, HKDF-SHA256)`, `HKDF-SHA256`,
+    /// `ChaCha20Poly1305`, base mode
+    #[test]
+    fn test_rfc_9180_base_mode_test_vector() {
+        const INFO: &str = "4f6465206f6e2061204772656369616e2055726e";
+        const SK_RM: &str = "8057991eef8f1f1af18f4a9491d16a1ce333f695d4db8e38da75975c4478e0fb";
+        const PK_RM: &str = "4310ee97d88cc1f088a5576c77ab0cf5c3ac797f3d95139c6c84b5429c59662a";
+        const ENC: &str = "1afa08d3dec047a643885163f1180476fa7ddb54c6a8029ea33f95796bf2ac4a";
+        const PLAINTEXT: &[u8] = b"Beauty is truth, truth beauty";
+        let encryptions = [
+            (
+                b"Count-0".as_slice(),
+                "1c5250d8034ec2b784ba2cfd69dbdb8af406cfe3ff938e131f0def8c8b60b4db\
+                 21993c62ce81883d2dd1b51a28",
+            ),
+            (
+                b"Count-1".as_slice(),
+                "6b53c051e4199c518de79594e1c4ab18b96f081549d45ce015be002090bb119e\
+                 85285337cc95ba5f59992dc98c",
+            ),
+        ];
+        let exports = [
+            (
+                b"".as_slice(),
+                "4bbd6243b8bb54cec311fac9df81841b6fd61f56538a775e7c80a9f40160606e",
+            ),
+            (
+                b"\x00".as_slice(),
+                "8c1df14732580e5501b00f82b10a1647b40713191b7c1240ac80e2b68808ba69",
+            ),
+            (
+                b"TestContext".as_slice(),
+                "5acb09211139c43b3090489a9da433e8a30ee7188ba8b0a9a1ccf0c229283e53",
+            ),
+        ];
+
+        let recipient_secret_key =
+            StaticSecret::from(<[u8; 32]>::try_from(from_hex(SK_RM)).unwrap());
+        assert_eq!(
+            PublicKey::from(&recipient_secret_key).as_bytes().as_slice(),
+            from_hex(PK_RM)
+        );
+        let mut context = setup_base_receiver(
+            &from_hex(ENC).try_into().unwrap(),
+            &recipient_secret_key,
+            &from_hex(INFO),
+            AeadAlgorithm::ChaCha20Poly1305,
+        )
+        .unwrap();
+        for (associated_data, ciphertext) in encryptions {
+            assert_eq!(
+                context
+                    .open(associated_data, &from_hex(ciphertext))
+                    .unwrap(),
+                PLAINTEXT
+            );
+        }
+        for (exporter_context, exported_value) in exports {
+            let mut secret = [0u8; 32];
+            context.export(exporter_context, &mut secret).unwrap();
+            assert_eq!(secret.as_slice(), from_hex(exported_value));
+        }
+    }
+
+    #[test]
+    fn test_rejects_low_order_encapsulated_key() {
+        let recipient_secret_key = StaticSecret::random_from_rng(rand::thread_rng());
+        assert!(matches!(
+            setup_base_receiver(
+                &[0u8; ENCAPSULATED_KEY_SIZE],
+                &recipient_secret_key,
+                REQUEST_INFO,
+                AeadAlgorithm::Aes256Gcm,
+            ),
+            Err(Error::NonContributoryKeyExchange)
+        ));
+    }
+}